@@ -5,10 +5,14 @@
 use regex::Regex;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error};
+use std::io::{BufRead, BufReader, Error, ErrorKind};
 use std::path::Path;
 use std::str::FromStr;
 
+/// The directory AFL keeps the inputs it fuzzes in. None of them crashed the fuzzed binary, so with
+/// `--queue` a crash of a sanitizer build on one is a memory error the fuzzer could not see
+pub const QUEUE_DIR: &str = "queue";
+
 /// See AFL's documentation for an explanation of these fields
 #[derive(Debug, PartialEq)]
 pub struct AflStats {
@@ -79,6 +83,26 @@ pub fn parse_afl_fuzzer_stats(filename: &Path) -> Result<HashMap<String, String>
     Ok(kv)
 }
 
+/// Read the target command line that AFL++ saves to the `cmdline` file in its output directory.
+/// Each argument is stored on its own line and the `@@` placeholder is kept where it was given to
+/// afl-fuzz.
+pub fn parse_afl_cmdline(filename: &Path) -> Result<Vec<String>, Error> {
+    let input = File::open(filename)?;
+    let reader = BufReader::new(input);
+
+    let mut args = vec![];
+
+    for line in reader.lines() {
+        args.push(line?);
+    }
+
+    if args.is_empty() || args[0].is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "Empty AFL cmdline file"));
+    }
+
+    Ok(args)
+}
+
 #[allow(clippy::upper_case_acronyms)]
 #[doc(hidden)]
 trait KVConverter {
@@ -177,6 +201,14 @@ pub fn validate_afl_fuzzer_stats<S: std::hash::BuildHasher>(
     // TODO: notify on unrecognized stats being parsed to allow for future versions
 }
 
+/// Whether `path` is an input in an AFL queue
+pub fn is_queue_input(path: &Path) -> bool {
+    path.parent()
+        .and_then(|dir| dir.file_name())
+        .map(|name| name == QUEUE_DIR)
+        .unwrap_or(false)
+}
+
 #[cfg(test)]
 mod test {
     use std::path::PathBuf;
@@ -220,4 +252,20 @@ mod test {
         assert_eq!(s2v.target_mode, "shmem_testcase default");
         assert_eq!(s2v.execs_ps_last_min.unwrap(), 0.0);
     }
+
+    #[test]
+    fn test_afl_cmdline() {
+        let c = parse_afl_cmdline(&test_path("aflpp_cmdline.txt"));
+        assert!(c.is_ok());
+        assert_eq!(c.unwrap(), vec!["./my_exe", "--option", "two words", "@@"]);
+
+        assert!(parse_afl_cmdline(&test_path("missing_cmdline.txt")).is_err());
+    }
+
+    #[test]
+    fn test_is_queue_input() {
+        assert!(is_queue_input(Path::new("out/default/queue/id:000012,src:000003,time:1500,execs:99,op:havoc,rep:4,+cov")));
+        assert!(!is_queue_input(Path::new("out/default/crashes/id:000000,sig:11,src:000012,time:2000,execs:150,op:havoc,rep:2")));
+        assert!(!is_queue_input(Path::new("queue")));
+    }
 }
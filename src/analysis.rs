@@ -0,0 +1,221 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Analysis passes run on each crash after it has been triaged by the debugger.
+//!
+//! An [AnalysisPipeline] runs a list of [AnalysisPass]es in order, each reading and adding to an
+//! [AnalysisContext]. New analyzers can be added by implementing [AnalysisPass] without touching
+//! the core triage flow.
+//!
+//! ## Passes
+//! * [EnrichPass] (`enrich`) - Translate the raw debugger output into an [EnrichedTriageInfo],
+//!   including sanitizer report parsing. Required by all other passes and cannot be disabled.
+//! * [BucketPass] (`bucket`) - Deduplicate the crash using a [CrashBucketStrategy]. When disabled,
+//!   every crash is treated as unique.
+//! * [ClassifyPass] (`classify`) - Rate the [CrashSeverity] of the crash, with the rules of its
+//!   [SeverityModel] first. When disabled, all crashes have an unknown severity.
+use crate::bucket::{self, BucketHashAlgorithm, CrashBucketInfo, CrashBucketPolicy, CrashBucketStrategy, StackClusterer};
+use crate::debugger::gdb::GdbTriageResult;
+use crate::report::enriched::{self, EnrichedTriageInfo};
+use crate::report::severity::{CrashSeverity, SeverityModel};
+use crate::ReportOptions;
+
+/// The passes that can be disabled from the command line
+pub const OPTIONAL_PASSES: [&str; 2] = ["bucket", "classify"];
+
+/// The state of a crash as it moves through the analysis passes
+pub struct AnalysisContext<'a> {
+    /// The testcase path, for messages
+    pub testcase: &'a str,
+    pub triage: &'a GdbTriageResult,
+    pub report_options: &'a ReportOptions,
+    pub enriched: Option<EnrichedTriageInfo>,
+    pub bucket: Option<CrashBucketInfo>,
+    pub severity: Option<CrashSeverity>,
+}
+
+impl<'a> AnalysisContext<'a> {
+    /// The enriched triage information, which is required by every pass after [EnrichPass]
+    pub fn enriched(&self) -> Result<&EnrichedTriageInfo, String> {
+        self.enriched.as_ref().ok_or_else(|| "The enrich pass must run first".to_string())
+    }
+}
+
+/// A single step of crash analysis
+pub trait AnalysisPass: Send + Sync {
+    /// A short name used to refer to this pass (e.g. on the command line)
+    fn name(&self) -> &'static str;
+    fn run(&self, ctx: &mut AnalysisContext) -> Result<(), String>;
+}
+
+/// The result of running an [AnalysisPipeline] on a crash
+pub struct CrashAnalysis {
+    pub enriched: EnrichedTriageInfo,
+    pub bucket: CrashBucketInfo,
+    pub severity: CrashSeverity,
+}
+
+pub struct EnrichPass;
+
+impl AnalysisPass for EnrichPass {
+    fn name(&self) -> &'static str {
+        "enrich"
+    }
+
+    fn run(&self, ctx: &mut AnalysisContext) -> Result<(), String> {
+        ctx.enriched = Some(enriched::enrich_triage_info(ctx.report_options, ctx.triage)?);
+        Ok(())
+    }
+}
+
+pub struct BucketPass {
+    pub strategy: CrashBucketStrategy,
+    /// The command for [CrashBucketStrategy::external]
+    pub command: Option<String>,
+    /// The knobs for [CrashBucketStrategy::custom]
+    pub policy: CrashBucketPolicy,
+    /// The clusters found so far for [CrashBucketStrategy::similar]
+    pub clusterer: StackClusterer,
+    /// How strategies that hash their inputs identify buckets
+    pub hash: BucketHashAlgorithm,
+}
+
+impl BucketPass {
+    pub fn new(strategy: CrashBucketStrategy) -> Self {
+        BucketPass {
+            strategy,
+            command: None,
+            policy: CrashBucketPolicy::default(),
+            clusterer: StackClusterer::new(bucket::DEFAULT_SIMILARITY_THRESHOLD),
+            hash: BucketHashAlgorithm::md5,
+        }
+    }
+}
+
+impl AnalysisPass for BucketPass {
+    fn name(&self) -> &'static str {
+        "bucket"
+    }
+
+    fn run(&self, ctx: &mut AnalysisContext) -> Result<(), String> {
+        let einfo = ctx.enriched()?;
+
+        let bucket_info = match (&self.strategy, &self.command) {
+            (CrashBucketStrategy::none, _) => bucket::bucket_crash(self.strategy, einfo),
+            _ if einfo.hang == Some(true) => bucket::bucket_hang(self.strategy, einfo),
+            (CrashBucketStrategy::external, Some(command)) => {
+                bucket::bucket_crash_external(command, einfo).unwrap_or_else(|e| {
+                    log::warn!("{}: {}", shlex::quote(ctx.testcase), e);
+                    bucket::bucket_crash(CrashBucketStrategy::external, einfo)
+                })
+            }
+            (CrashBucketStrategy::custom, _) => bucket::bucket_crash_policy(&self.policy, einfo),
+            (CrashBucketStrategy::similar, _) => self.clusterer.bucket_crash(einfo),
+            _ => bucket::bucket_crash(self.strategy, einfo),
+        };
+
+        ctx.bucket = Some(bucket::rehash_bucket(bucket_info, self.hash, einfo));
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct ClassifyPass {
+    /// Rules overriding the built-in rating
+    pub model: SeverityModel,
+}
+
+impl AnalysisPass for ClassifyPass {
+    fn name(&self) -> &'static str {
+        "classify"
+    }
+
+    fn run(&self, ctx: &mut AnalysisContext) -> Result<(), String> {
+        ctx.severity = Some(self.model.rate(ctx.enriched()?));
+        Ok(())
+    }
+}
+
+/// An ordered list of [AnalysisPass]es
+pub struct AnalysisPipeline {
+    passes: Vec<Box<dyn AnalysisPass>>,
+}
+
+impl AnalysisPipeline {
+    /// The standard passes, in order, excluding those named in `disabled`
+    pub fn new(bucket: BucketPass, classify: ClassifyPass, disabled: &[&str]) -> Self {
+        let passes: Vec<Box<dyn AnalysisPass>> = vec![
+            Box::new(EnrichPass),
+            Box::new(bucket),
+            Box::new(classify),
+        ];
+
+        AnalysisPipeline {
+            passes: passes.into_iter().filter(|p| !disabled.contains(&p.name())).collect(),
+        }
+    }
+
+    /// Append a pass to the end of the pipeline
+    pub fn add_pass(&mut self, pass: Box<dyn AnalysisPass>) {
+        self.passes.push(pass);
+    }
+
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|p| p.name()).collect()
+    }
+
+    /// Run every pass on a triaged crash
+    pub fn run(&self, testcase: &str, triage: &GdbTriageResult, report_options: &ReportOptions) -> Result<CrashAnalysis, String> {
+        let mut ctx = AnalysisContext {
+            testcase,
+            triage,
+            report_options,
+            enriched: None,
+            bucket: None,
+            severity: None,
+        };
+
+        for pass in &self.passes {
+            pass.run(&mut ctx).map_err(|e| format!("Analysis pass {} failed: {}", pass.name(), e))?;
+        }
+
+        let enriched = ctx.enriched.ok_or_else(|| "The enrich pass is required".to_string())?;
+
+        Ok(CrashAnalysis {
+            // An empty bucket makes the crash unique
+            bucket: ctx.bucket.unwrap_or_else(|| bucket::bucket_crash(CrashBucketStrategy::none, &enriched)),
+            severity: ctx.severity.unwrap_or(CrashSeverity::unknown),
+            enriched,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::load_test_triage;
+
+    #[test]
+    fn test_analysis_pipeline() {
+        let triage: GdbTriageResult = load_test_triage();
+        let opt = ReportOptions::default();
+
+        let pipeline = AnalysisPipeline::new(BucketPass::new(CrashBucketStrategy::first_function_name), ClassifyPass::default(), &[]);
+        assert_eq!(pipeline.pass_names(), vec!["enrich", "bucket", "classify"]);
+
+        let analysis = pipeline.run("tc", &triage, &opt).unwrap();
+        assert!(!analysis.bucket.strategy_result.is_empty());
+        assert_eq!(analysis.severity, CrashSeverity::medium);
+
+        let pipeline = AnalysisPipeline::new(BucketPass::new(CrashBucketStrategy::first_function_name), ClassifyPass::default(), &OPTIONAL_PASSES);
+        assert_eq!(pipeline.pass_names(), vec!["enrich"]);
+
+        let analysis = pipeline.run("tc", &triage, &opt).unwrap();
+        assert!(analysis.bucket.strategy_result.is_empty());
+        assert_eq!(analysis.severity, CrashSeverity::unknown);
+
+        let mut pipeline = AnalysisPipeline::new(BucketPass::new(CrashBucketStrategy::none), ClassifyPass::default(), &["enrich"]);
+        pipeline.add_pass(Box::new(ClassifyPass::default()));
+        assert!(pipeline.run("tc", &triage, &opt).is_err());
+    }
+}
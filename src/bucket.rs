@@ -23,16 +23,56 @@
 //! available)
 //! * [CrashBucketStrategy::first_function_name] - The same as `function_names` but only the first
 //! frame's function name
+//! * [CrashBucketStrategy::external] - Delegate bucketing to a command ([bucket_crash_external])
+//! * [CrashBucketStrategy::custom] - Hash frames as configured by a [CrashBucketPolicy] ([bucket_crash_policy])
+//! * [CrashBucketStrategy::similar] - Cluster crashes whose backtraces are similar, but not
+//!   necessarily the same ([StackClusterer])
+//!
+//! ## Bucket identifiers
+//! Strategies that hash their inputs use a [BucketHashAlgorithm]. With `md5`, the bucket identifier
+//! is the bare MD5 hash of the concatenated inputs, as in earlier releases. The other algorithms
+//! give identifiers in the format `ALGORITHM-HASH-SLUG`, see [format_bucket_id]:
+//!
+//! * `ALGORITHM` is the name of the [BucketHashAlgorithm] (e.g. `sha256`)
+//! * `HASH` is the first 16 hex digits of the SHA-256 of the algorithm's inputs, one per line
+//! * `SLUG` is the faulting function with characters other than `[A-Za-z0-9_]` replaced, for
+//!   humans only. Hangs have a `hang_` prefix
+//!
+//! Identifiers are meant as durable keys for external systems: the identifier of a crash never
+//! changes between releases for the same algorithm and inputs. Changing how an algorithm
+//! normalizes or hashes its inputs requires a new algorithm name.
 //!
 //! Accurate crash bucketing is an active research area and is usually somewhat target specific. Many strategies are a heuristic at best.
 //! This could lead to you missing truly unique crashes (false negative) or having many duplicate
 //! crashes (false positive).
 use serde::{Deserialize, Serialize};
-use super::report::enriched::EnrichedTriageInfo;
+use super::report::enriched::{EnrichedFrameInfo, EnrichedTriageInfo};
+use super::process;
 use clap::arg_enum;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+
+/// How long an external bucketing command may run
+const EXTERNAL_BUCKET_TIMEOUT_MS: u64 = 30000;
+
+/// How many hex digits of the hash are in a bucket identifier
+const BUCKET_ID_HASH_DIGITS: usize = 16;
+/// The most characters of the faulting function in a bucket identifier
+const BUCKET_ID_SLUG_LENGTH: usize = 32;
+
+lazy_static! {
+    /// Line numbers, module offsets, addresses, and template or function arguments of a frame
+    /// signature, which the fuzzy algorithm ignores
+    static ref R_FUZZY_NOISE: Regex = Regex::new(r":\d+$|\+0x[0-9a-fA-F]+|0x[0-9a-fA-F]+|<.*>|\(.*\)").unwrap();
+    /// Frame signatures in system libraries or a sanitizer runtime, which the casr algorithm ignores
+    static ref R_CASR_IGNORED: Regex = Regex::new(
+        r"^(/usr)?/lib(32|64)?/|/compiler-rt/|/sanitizer_common/|^(lib)?(asan|msan|tsan|ubsan|lsan)_|libclang_rt\.").unwrap();
+    static ref R_SLUG_CHARS: Regex = Regex::new(r"[^A-Za-z0-9_]").unwrap();
+}
 
 /// Information on the crash bucketing strategy, inputs, and output
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CrashBucketInfo {
     /// What is the stringified output from the bucketing function
     pub strategy_result: String,
@@ -40,11 +80,69 @@ pub struct CrashBucketInfo {
     pub strategy: CrashBucketStrategy,
     /// What stringified inputs were used as input to the bucketing function
     pub inputs: Vec<String>,
+    /// How the inputs were hashed, for strategies that hash them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_algorithm: Option<BucketHashAlgorithm>,
+}
+
+/// The bucket identifier of a crash. Falls back to a unique "Crash ID" when the bucketing
+/// strategy gave no result
+pub fn bucket_id(bucket_info: &CrashBucketInfo, crash_id: usize) -> String {
+    if bucket_info.strategy_result.is_empty() {
+        format!("CID_{}", crash_id)
+    } else {
+        bucket_info.strategy_result.to_string()
+    }
+}
+
+arg_enum! {
+    /// How frames with symbols are identified by a [CrashBucketPolicy]
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    #[allow(non_camel_case_types)]
+    pub enum FrameSymbolKey {
+        // The source file and line, or the function name if there is no line information
+        file_line,
+        // Only the function name, so crashes at different lines of a function are the same
+        function,
+        // Ignore symbols and identify every frame by its address
+        none,
+    }
+}
+
+/// The knobs of the [CrashBucketStrategy::custom] strategy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrashBucketPolicy {
+    /// How many frames to hash, starting from the first interesting frame. 0 hashes every frame
+    pub frames: usize,
+    /// How to identify frames that have symbols
+    pub symbols: FrameSymbolKey,
+    /// Identify frames without symbols by their module relative address (module+offset) instead of
+    /// their absolute address. Absolute addresses change between runs when ASLR is enabled
+    pub relative_addresses: bool,
+    /// Skip frames without symbols instead of using their address
+    pub ignore_addresses: bool,
+    /// Also hash whether the fault was a read, a write, or an instruction fetch, so that a wild
+    /// write does not share a bucket with a read at the same location
+    #[serde(default)]
+    pub access: bool,
+}
+
+impl Default for CrashBucketPolicy {
+    /// The same frame signatures as [CrashBucketStrategy::afltriage]
+    fn default() -> Self {
+        CrashBucketPolicy {
+            frames: 0,
+            symbols: FrameSymbolKey::file_line,
+            relative_addresses: true,
+            ignore_addresses: false,
+            access: false,
+        }
+    }
 }
 
 arg_enum! {
     /// The built-in crash deduplication (crash bucketing) method (strategy) to use
-    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
     #[allow(non_camel_case_types)]
     pub enum CrashBucketStrategy {
         none,
@@ -54,17 +152,117 @@ arg_enum! {
         first_5_frames,
         function_names,
         first_function_name,
+        external,
+        custom,
+        similar,
         //exploitable_major,
         //user,
     }
 }
 
+arg_enum! {
+    /// How the inputs of a bucketing strategy are hashed into a bucket identifier
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    #[allow(non_camel_case_types)]
+    pub enum BucketHashAlgorithm {
+        // The MD5 of the concatenated inputs, without an algorithm or slug in the identifier
+        md5,
+        // The SHA-256 of the inputs
+        sha256,
+        // The SHA-256 of the inputs without line numbers, offsets, addresses, or template and function arguments
+        fuzzy,
+        // The SHA-256 of the inputs without system library and sanitizer runtime frames, like CASR
+        casr,
+    }
+}
+
+impl BucketHashAlgorithm {
+    /// The inputs this algorithm hashes
+    fn normalize(&self, inputs: &[String]) -> Vec<String> {
+        match self {
+            BucketHashAlgorithm::md5 | BucketHashAlgorithm::sha256 => inputs.to_vec(),
+            BucketHashAlgorithm::fuzzy => inputs.iter()
+                .map(|i| R_FUZZY_NOISE.replace_all(i, "").to_string())
+                .collect(),
+            BucketHashAlgorithm::casr => {
+                let kept: Vec<String> = inputs.iter()
+                    .filter(|i| !R_CASR_IGNORED.is_match(i))
+                    .cloned()
+                    .collect();
+
+                // A crash entirely in system code is still bucketed by it
+                if kept.is_empty() { inputs.to_vec() } else { kept }
+            }
+        }
+    }
+
+    /// Hash bucketing `inputs`
+    pub fn hash(&self, inputs: &[String]) -> String {
+        let inputs = self.normalize(inputs);
+
+        match self {
+            BucketHashAlgorithm::md5 => {
+                let mut hash = md5::Context::new();
+
+                for i in &inputs {
+                    hash.consume(i.as_bytes());
+                }
+
+                format!("{:x}", hash.compute())
+            }
+            _ => {
+                let mut digest = format!("{:x}", Sha256::digest(inputs.join("\n").as_bytes()));
+                digest.truncate(BUCKET_ID_HASH_DIGITS);
+                digest
+            }
+        }
+    }
+}
+
+/// Format the identifier of a bucket from its `inputs` as described in [the module
+/// documentation](self). `md5` identifiers are the bare hash
+pub fn format_bucket_id(algorithm: BucketHashAlgorithm, inputs: &[String], einfo: &EnrichedTriageInfo) -> String {
+    let hash = algorithm.hash(inputs);
+
+    if algorithm == BucketHashAlgorithm::md5 {
+        return hash;
+    }
+
+    let mut slug = R_SLUG_CHARS.replace_all(&einfo.faulting_function, "_").to_string();
+    slug.truncate(BUCKET_ID_SLUG_LENGTH);
+
+    if slug.is_empty() {
+        slug = "unknown".into();
+    }
+
+    if einfo.hang == Some(true) {
+        slug = format!("hang_{}", slug);
+    }
+
+    format!("{}-{}-{}", algorithm, hash, slug)
+}
+
+/// Re-hash the bucket of a crash with `algorithm`. Buckets of strategies that do not hash their
+/// inputs are returned unchanged
+pub fn rehash_bucket(mut info: CrashBucketInfo, algorithm: BucketHashAlgorithm, einfo: &EnrichedTriageInfo) -> CrashBucketInfo {
+    if info.hash_algorithm.is_none() || info.hash_algorithm == Some(algorithm) {
+        return info;
+    }
+
+    info.strategy_result = format_bucket_id(algorithm, &info.inputs, einfo);
+    info.hash_algorithm = Some(algorithm);
+    info
+}
+
 /// Using [EnrichedTriageInfo] and a [CrashBucketStrategy], determine a unique string output that
 /// attempts to captures the uniqueness of a crash.
 pub fn bucket_crash(strategy: CrashBucketStrategy, einfo: &EnrichedTriageInfo) -> CrashBucketInfo {
     let max_frames = einfo.faulting_thread.frames.len();
     let (strategy_result, inputs) = match &strategy {
-        CrashBucketStrategy::none => ("".into(), vec![]),
+        // External bucketing needs a command. See bucket_crash_external
+        // Clustering needs to know about previous crashes. See StackClusterer
+        CrashBucketStrategy::none | CrashBucketStrategy::external | CrashBucketStrategy::similar => ("".into(), vec![]),
+        CrashBucketStrategy::custom => return bucket_crash_policy(&CrashBucketPolicy::default(), einfo),
         CrashBucketStrategy::afltriage => bucket_n_frames(einfo, max_frames),
         CrashBucketStrategy::first_frame => bucket_n_frames(einfo, 1),
         CrashBucketStrategy::first_frame_raw => bucket_n_frames_raw(einfo, 1),
@@ -73,13 +271,185 @@ pub fn bucket_crash(strategy: CrashBucketStrategy, einfo: &EnrichedTriageInfo) -
         CrashBucketStrategy::first_5_frames => bucket_n_frames(einfo, 5),
     };
 
+    let hash_algorithm = Some(BucketHashAlgorithm::md5).filter(|_| !strategy_result.is_empty());
+
     CrashBucketInfo {
         strategy_result,
         strategy,
         inputs,
+        hash_algorithm,
     }
 }
 
+/// Bucket a hang by the function names from where the target is stuck. Unlike a crash, the exact
+/// address a hang was stopped at is random, so only function names are stable between runs. The
+/// bucket identifier is prefixed with `hang_` to keep hangs apart from crashes
+pub fn bucket_hang(strategy: CrashBucketStrategy, einfo: &EnrichedTriageInfo) -> CrashBucketInfo {
+    let (hash, inputs) = bucket_n_function_names(einfo, einfo.faulting_thread.frames.len());
+
+    CrashBucketInfo {
+        strategy_result: format!("hang_{}", hash),
+        strategy,
+        inputs,
+        hash_algorithm: Some(BucketHashAlgorithm::md5),
+    }
+}
+
+/// Bucket a crash by delegating the decision to an external `command`, which is run using `sh -c`.
+///
+/// The command receives a JSON object on STDIN with the crash `summary`, `faulting_function`,
+/// `signal_name`, and the normalized stack (`inputs`, the same frame signatures used by the
+/// `afltriage` strategy). It must print the bucket identifier on the first line of STDOUT. To use a
+/// deduplication service over HTTP, wrap a client, for example:
+/// `curl -s --data-binary @- https://dedup.example/bucket`
+pub fn bucket_crash_external(command: &str, einfo: &EnrichedTriageInfo) -> Result<CrashBucketInfo, String> {
+    let inputs = get_frame_signatures(einfo);
+
+    let request = serde_json::json!({
+        "summary": einfo.summary,
+        "faulting_function": einfo.faulting_function,
+        "signal_name": einfo.stop_info.signal_name,
+        "inputs": inputs,
+    });
+
+    let output = process::execute_capture_output_timeout(
+        "sh", &["-c", command], EXTERNAL_BUCKET_TIMEOUT_MS, Some(request.to_string().into_bytes()))
+        .map_err(|e| format!("Failed to execute bucket command: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("Bucket command failed ({}): {}", output.status, output.stderr.trim()));
+    }
+
+    let strategy_result = output.stdout.lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .ok_or_else(|| "Bucket command did not print a bucket".to_string())?;
+
+    Ok(CrashBucketInfo {
+        strategy_result: strategy_result.to_string(),
+        strategy: CrashBucketStrategy::external,
+        inputs,
+        hash_algorithm: None,
+    })
+}
+
+/// Bucket a crash by hashing the frame signatures selected by a [CrashBucketPolicy]
+pub fn bucket_crash_policy(policy: &CrashBucketPolicy, einfo: &EnrichedTriageInfo) -> CrashBucketInfo {
+    let mut hash = md5::Context::new();
+    let mut inputs = get_policy_frame_signatures(policy, einfo);
+
+    if policy.frames > 0 {
+        inputs.truncate(policy.frames);
+    }
+
+    if let Some(access) = einfo.stop_info.fault_access.filter(|_| policy.access) {
+        inputs.push(format!("access:{}", access.as_str()));
+    }
+
+    for i in &inputs {
+        hash.consume(i.as_bytes());
+    }
+
+    CrashBucketInfo {
+        strategy_result: format!("{:x}", hash.compute()),
+        strategy: CrashBucketStrategy::custom,
+        inputs,
+        hash_algorithm: Some(BucketHashAlgorithm::md5),
+    }
+}
+
+/// The default [StackClusterer] similarity threshold
+pub const DEFAULT_SIMILARITY_THRESHOLD: f64 = 0.8;
+
+/// Clusters crashes by the similarity of their backtraces for [CrashBucketStrategy::similar].
+///
+/// Similarity is one minus the edit distance between two lists of frame signatures (the same ones
+/// used by the `afltriage` strategy), divided by the length of the longer list. A crash joins the
+/// most similar existing cluster if the similarity to that cluster's first backtrace is at least
+/// the threshold, so crashes that differ by a noise frame (e.g. a different allocator path) land
+/// together. Otherwise it starts a new cluster. As the first crash of a cluster is its
+/// representative, assignments can depend on the order crashes are triaged in.
+pub struct StackClusterer {
+    threshold: f64,
+    /// The cluster identifier and representative frames of each cluster
+    clusters: Mutex<Vec<(String, Vec<String>)>>,
+}
+
+impl StackClusterer {
+    /// Create a clusterer with a similarity `threshold` between 0 (everything is similar) and 1
+    /// (only exact matches)
+    pub fn new(threshold: f64) -> Self {
+        StackClusterer {
+            threshold,
+            clusters: Mutex::new(vec![]),
+        }
+    }
+
+    /// Assign a crash to a cluster
+    pub fn bucket_crash(&self, einfo: &EnrichedTriageInfo) -> CrashBucketInfo {
+        let inputs = get_frame_signatures(einfo);
+        let mut clusters = self.clusters.lock().unwrap();
+
+        let best = clusters.iter()
+            .map(|(id, frames)| (id, frame_similarity(frames, &inputs)))
+            .filter(|(_, similarity)| *similarity >= self.threshold)
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+        let strategy_result = match best {
+            Some((id, _)) => id.to_string(),
+            None => {
+                let mut hash = md5::Context::new();
+
+                for i in &inputs {
+                    hash.consume(i.as_bytes());
+                }
+
+                let id = format!("{:x}", hash.compute());
+                clusters.push((id.to_string(), inputs.clone()));
+                id
+            }
+        };
+
+        CrashBucketInfo {
+            strategy_result,
+            strategy: CrashBucketStrategy::similar,
+            inputs,
+            // Clusters are identified by their first crash, so re-hashing would split them
+            hash_algorithm: None,
+        }
+    }
+}
+
+/// The similarity of two frame lists from 0 to 1 using their edit distance
+fn frame_similarity(a: &[String], b: &[String]) -> f64 {
+    let longest = std::cmp::max(a.len(), b.len());
+
+    if longest == 0 {
+        return 1.0;
+    }
+
+    1.0 - (frame_edit_distance(a, b) as f64 / longest as f64)
+}
+
+/// The Levenshtein distance between two frame lists, where each frame is a single symbol
+fn frame_edit_distance(a: &[String], b: &[String]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+
+    for (i, fa) in a.iter().enumerate() {
+        cur[0] = i + 1;
+
+        for (j, fb) in b.iter().enumerate() {
+            let substitution = prev[j] + if fa == fb { 0 } else { 1 };
+            cur[j + 1] = std::cmp::min(substitution, std::cmp::min(prev[j + 1], cur[j]) + 1);
+        }
+
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    prev[b.len()]
+}
+
 /// Bucket the first guessed `n` frames
 fn bucket_n_frames(einfo: &EnrichedTriageInfo, n: usize) -> (String, Vec<String>) {
     let mut hash = md5::Context::new();
@@ -135,12 +505,33 @@ fn bucket_n_frames_raw(einfo: &EnrichedTriageInfo, n: usize) -> (String, Vec<Str
     (format!("{:x}", hash.compute()), inputs)
 }
 
+/// The frames to bucket by: from the guessed faulting frame up to the last trustworthy one (see
+/// [crate::report::unwind])
+fn bucketed_frames(einfo: &EnrichedTriageInfo) -> &[EnrichedFrameInfo] {
+    let frames = &einfo.faulting_thread.frames;
+    let end = match &einfo.backtrace_quality {
+        Some(quality) => quality.trusted_frames.max(einfo.faulting_frame_idx + 1).min(frames.len()),
+        None => frames.len(),
+    };
+
+    &frames[einfo.faulting_frame_idx.min(end)..end]
+}
+
 /// Get frame signatures (file:line, module+offset, or address) starting from the guessed faulting
 /// frame, or if not available, the first true frame
 fn get_frame_signatures(einfo: &EnrichedTriageInfo) -> Vec<String> {
+    // Failed assertions are identified by the assertion instead of the path to it
+    if let Some(assertion) = &einfo.assertion {
+        return vec![assertion.signature()];
+    }
+
+    if let Some(inputs) = go_frame_signatures(einfo) {
+        return inputs;
+    }
+
     let mut inputs = vec![];
 
-    for fr in &einfo.faulting_thread.frames[einfo.faulting_frame_idx..] {
+    for fr in bucketed_frames(einfo) {
         let file_sym = match &fr.symbol {
             Some(symbol) => symbol.format_file(),
             None => "".to_string(),
@@ -157,6 +548,8 @@ fn get_frame_signatures(einfo: &EnrichedTriageInfo) -> Vec<String> {
         }
     }
 
+    insert_race_signature(einfo, &mut inputs);
+
     // I'm sure there is a better way of handling this situation
     if inputs.is_empty() {
         if einfo.faulting_thread.frames.is_empty() {
@@ -169,6 +562,72 @@ fn get_frame_signatures(einfo: &EnrichedTriageInfo) -> Vec<String> {
     inputs
 }
 
+/// Get frame signatures starting from the guessed faulting frame as configured by `policy`
+fn get_policy_frame_signatures(policy: &CrashBucketPolicy, einfo: &EnrichedTriageInfo) -> Vec<String> {
+    if let Some(assertion) = &einfo.assertion {
+        return vec![assertion.signature()];
+    }
+
+    if let Some(inputs) = go_frame_signatures(einfo) {
+        return inputs;
+    }
+
+    let mut inputs = vec![];
+
+    for fr in bucketed_frames(einfo) {
+        let symbol = match (&fr.symbol, policy.symbols) {
+            (_, FrameSymbolKey::none) | (None, _) => None,
+            (Some(symbol), FrameSymbolKey::file_line) => {
+                let file_sym = symbol.format_file();
+
+                if file_sym.contains(':') {
+                    Some(file_sym)
+                } else {
+                    symbol.function_name.clone()
+                }
+            }
+            (Some(symbol), FrameSymbolKey::function) => symbol.function_name.clone(),
+        };
+
+        if let Some(symbol) = symbol.filter(|s| !s.is_empty()) {
+            inputs.push(symbol);
+        } else if policy.ignore_addresses {
+            continue;
+        } else if !policy.relative_addresses {
+            inputs.push(fr.address.f.to_string());
+        } else if fr.module != "[stack]" && fr.module != "[heap]" && fr.module != "??" {
+            inputs.push(fr.module_address.to_string());
+        }
+    }
+
+    insert_race_signature(einfo, &mut inputs);
+
+    if inputs.is_empty() {
+        inputs.push("UNKNOWN".to_string());
+    }
+
+    inputs
+}
+
+/// The frames of the goroutine that failed, as GDB's backtrace of a Go program is of the runtime
+fn go_frame_signatures(einfo: &EnrichedTriageInfo) -> Option<Vec<String>> {
+    einfo.go_fatal_error.as_ref()
+        .map(|e| e.frame_signatures())
+        .filter(|inputs| !inputs.is_empty())
+}
+
+/// A data race is between two accesses, so the location of the other access is hashed after the
+/// faulting frame. Otherwise races of one write with different reads would share a bucket
+fn insert_race_signature(einfo: &EnrichedTriageInfo, inputs: &mut Vec<String>) {
+    let other_access = einfo.sanitizer_reports.iter().flatten()
+        .find(|r| r.stop_reason == "data-race")
+        .and_then(|r| r.related_locations.first());
+
+    if let Some(location) = other_access {
+        inputs.insert(inputs.len().min(1), format!("race:{}", location));
+    }
+}
+
 /// Get the "raw" frame signatures, which is just their address in string form
 fn get_raw_frame_signatures(einfo: &EnrichedTriageInfo) -> Vec<String> {
     let mut inputs = vec![];
@@ -183,3 +642,181 @@ fn get_raw_frame_signatures(einfo: &EnrichedTriageInfo) -> Vec<String> {
 
     inputs
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::load_test_report;
+
+    #[test]
+    fn test_bucket_external() {
+        let einfo: EnrichedTriageInfo = load_test_report();
+
+        let info = bucket_crash_external("grep -q '\"test.c:20\"' && echo '\n  my_bucket  '", &einfo).unwrap();
+        assert_eq!(info.strategy_result, "my_bucket");
+        assert_eq!(info.strategy, CrashBucketStrategy::external);
+        assert_eq!(info.inputs, get_frame_signatures(&einfo));
+
+        assert!(bucket_crash_external("cat > /dev/null; exit 1", &einfo).is_err());
+        assert!(bucket_crash_external("cat > /dev/null", &einfo).is_err());
+    }
+
+    #[test]
+    fn test_frame_similarity() {
+        let frames = |s: &str| -> Vec<String> { s.split(' ').map(str::to_string).collect() };
+
+        assert_eq!(frame_edit_distance(&frames("a b c d"), &frames("a b c d")), 0);
+        assert_eq!(frame_edit_distance(&frames("a x b c d"), &frames("a b c d")), 1);
+        assert_eq!(frame_edit_distance(&frames("a b"), &frames("c d e")), 3);
+        assert_eq!(frame_edit_distance(&[], &frames("a b")), 2);
+
+        assert!((frame_similarity(&frames("malloc a b c d"), &frames("calloc a b c d")) - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_stack_clusterer() {
+        let einfo: EnrichedTriageInfo = load_test_report();
+        // The golden report only has two interesting frames
+        let clusterer = StackClusterer::new(0.5);
+
+        let first = clusterer.bucket_crash(&einfo);
+        assert_eq!(first.strategy, CrashBucketStrategy::similar);
+        assert_eq!(clusterer.bucket_crash(&einfo).strategy_result, first.strategy_result);
+
+        // The same crash reached without one of its frames
+        let mut noisy: EnrichedTriageInfo = load_test_report();
+        noisy.faulting_thread.frames.remove(noisy.faulting_frame_idx + 1);
+        assert_eq!(clusterer.bucket_crash(&noisy).strategy_result, first.strategy_result);
+
+        let strict = StackClusterer::new(1.0);
+        let first = strict.bucket_crash(&einfo);
+        assert_ne!(strict.bucket_crash(&noisy).strategy_result, first.strategy_result);
+    }
+
+    #[test]
+    fn test_bucket_hang() {
+        let einfo: EnrichedTriageInfo = load_test_report();
+        let info = bucket_hang(CrashBucketStrategy::afltriage, &einfo);
+
+        assert!(info.strategy_result.starts_with("hang_"));
+        assert_eq!(info.inputs.first().map(String::as_str), Some("bad_read"));
+        assert_eq!(info.strategy_result, bucket_hang(CrashBucketStrategy::afltriage, &einfo).strategy_result);
+        assert_ne!(info.strategy_result, bucket_crash(CrashBucketStrategy::function_names, &einfo).strategy_result);
+    }
+
+    #[test]
+    fn test_bucket_policy() {
+        let einfo: EnrichedTriageInfo = load_test_report();
+
+        let default = bucket_crash_policy(&CrashBucketPolicy::default(), &einfo);
+        assert_eq!(default.strategy, CrashBucketStrategy::custom);
+        assert_eq!(default.inputs, get_frame_signatures(&einfo));
+
+        let policy = CrashBucketPolicy {
+            frames: 1,
+            symbols: FrameSymbolKey::function,
+            ..Default::default()
+        };
+        let info = bucket_crash_policy(&policy, &einfo);
+        assert_eq!(info.inputs, vec![einfo.faulting_function.to_string()]);
+
+        let policy = CrashBucketPolicy {
+            symbols: FrameSymbolKey::none,
+            ignore_addresses: true,
+            ..Default::default()
+        };
+        assert_eq!(bucket_crash_policy(&policy, &einfo).inputs, vec!["UNKNOWN"]);
+
+        let policy = CrashBucketPolicy {
+            frames: 1,
+            symbols: FrameSymbolKey::function,
+            access: true,
+            ..Default::default()
+        };
+        assert_eq!(bucket_crash_policy(&policy, &einfo).inputs, vec![einfo.faulting_function.to_string(), "access:read".into()]);
+    }
+
+    #[test]
+    fn test_bucket_hash_algorithm() {
+        let einfo: EnrichedTriageInfo = load_test_report();
+        let info = bucket_crash(CrashBucketStrategy::afltriage, &einfo);
+
+        // md5 identifiers must never change
+        assert_eq!(info.hash_algorithm, Some(BucketHashAlgorithm::md5));
+        assert_eq!(rehash_bucket(info.clone(), BucketHashAlgorithm::md5, &einfo), info);
+        assert_eq!(BucketHashAlgorithm::md5.hash(&info.inputs), info.strategy_result);
+
+        let sha = rehash_bucket(info.clone(), BucketHashAlgorithm::sha256, &einfo);
+        assert_eq!(sha.strategy_result, "sha256-ef0b896c3e5a1745-bad_read");
+        assert_eq!(sha.hash_algorithm, Some(BucketHashAlgorithm::sha256));
+        assert_eq!(sha.inputs, info.inputs);
+
+        let frames = |s: &str| -> Vec<String> { s.split(' ').map(str::to_string).collect() };
+        assert_eq!(BucketHashAlgorithm::fuzzy.normalize(&frames("test.c:20 /tmp/test+0x1c foo<int>(char*)")),
+            frames("test.c /tmp/test foo"));
+        assert_eq!(BucketHashAlgorithm::casr.normalize(
+            &frames("/lib/x86_64-linux-gnu/libc.so.6+0x1c asan_report.cpp:12 parse.c:7 test.c:20")),
+            frames("parse.c:7 test.c:20"));
+        assert_eq!(BucketHashAlgorithm::casr.normalize(&frames("/usr/lib/libc.so+0x1")), frames("/usr/lib/libc.so+0x1"));
+
+        let external = bucket_crash(CrashBucketStrategy::none, &einfo);
+        assert_eq!(rehash_bucket(external.clone(), BucketHashAlgorithm::sha256, &einfo), external);
+
+        let mut hang: EnrichedTriageInfo = load_test_report();
+        hang.hang = Some(true);
+        let info = rehash_bucket(bucket_hang(CrashBucketStrategy::afltriage, &hang), BucketHashAlgorithm::fuzzy, &hang);
+        assert!(info.strategy_result.starts_with("fuzzy-"));
+        assert!(info.strategy_result.ends_with("-hang_bad_read"));
+    }
+
+    #[test]
+    fn test_bucket_data_race() {
+        let einfo: EnrichedTriageInfo = load_test_report();
+        let mut race: EnrichedTriageInfo = load_test_report();
+        race.sanitizer_reports = crate::report::sanitizer::sanitizer_report_extract(
+            "WARNING: ThreadSanitizer: data race (pid=1)\n  Write of size 4 at 0x1 by thread T1:\n    \
+            #0 bad_read test.c:20:5 (test+0x1)\n\n  Previous read of size 4 at 0x1 by main thread:\n    \
+            #0 main test.c:27:3 (test+0x2)\n").map(|r| vec![r]);
+
+        let inputs = get_frame_signatures(&race);
+        assert_eq!(inputs[0], get_frame_signatures(&einfo)[0]);
+        assert_eq!(inputs[1], "race:test.c:27");
+        assert_ne!(bucket_crash(CrashBucketStrategy::afltriage, &race).strategy_result,
+            bucket_crash(CrashBucketStrategy::afltriage, &einfo).strategy_result);
+    }
+
+    #[test]
+    fn test_bucket_assertion() {
+        let mut einfo: EnrichedTriageInfo = load_test_report();
+        let mut other_path: EnrichedTriageInfo = load_test_report();
+        other_path.faulting_frame_idx += 1;
+
+        for e in [&mut einfo, &mut other_path] {
+            e.assertion = crate::report::assertion::assertion_extract(
+                "test: test.c:20: bad_read: Assertion `idx < 10' failed.\n");
+        }
+
+        assert_eq!(get_frame_signatures(&einfo), vec!["assert:test.c:20:idx < 10"]);
+        assert_eq!(bucket_crash(CrashBucketStrategy::afltriage, &einfo).strategy_result,
+            bucket_crash(CrashBucketStrategy::afltriage, &other_path).strategy_result);
+        assert_eq!(bucket_crash_policy(&CrashBucketPolicy::default(), &einfo).inputs, vec!["assert:test.c:20:idx < 10"]);
+    }
+
+    #[test]
+    fn test_bucket_unreliable_backtrace() {
+        let einfo: EnrichedTriageInfo = load_test_report();
+        let mut smashed: EnrichedTriageInfo = load_test_report();
+        smashed.backtrace_quality = Some(crate::report::unwind::BacktraceQuality {
+            trusted_frames: einfo.faulting_frame_idx + 2,
+            problems: vec!["frame has a PC that is unmapped".into()],
+        });
+
+        let inputs = get_frame_signatures(&smashed);
+        assert_eq!(inputs, get_frame_signatures(&einfo)[..2].to_vec());
+        assert_eq!(bucket_crash_policy(&CrashBucketPolicy::default(), &smashed).inputs, inputs);
+
+        // The faulting frame is always used
+        smashed.backtrace_quality.as_mut().unwrap().trusted_frames = 0;
+        assert_eq!(get_frame_signatures(&smashed), get_frame_signatures(&einfo)[..1].to_vec());
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! AFLTriage configuration files
+//!
+//! Every command line option can also be set in a TOML configuration file. By default
+//! `afltriage.toml` is read from the current directory, if it exists, or a file can be given with
+//! `--config`. Options given on the command line always take precedence over the configuration
+//! file.
+//!
+//! Keys are named after the long command line options, using underscores instead of dashes.
+//! Flags are booleans, options taking multiple values are arrays, and environment variables for
+//! the target are a table. For example:
+//!
+//! ```toml
+//! input = ["fuzzing_directory"]
+//! output = "reports"
+//! command = ["./target_binary", "--option-one", "@@"]
+//! gdb_path = "/opt/gdb/bin/gdb"
+//! timeout = 30000
+//! jobs = 8
+//! report_formats = ["text", "json"]
+//! bucket_strategy = "first_5_frames"
+//! child_output = true
+//!
+//! [env]
+//! ASAN_OPTIONS = "abort_on_error=1:symbolize=1"
+//! ```
+use std::path::Path;
+use toml::value::{Table, Value};
+
+/// The configuration file used when `--config` is not given
+pub const DEFAULT_CONFIG_FILE: &str = "afltriage.toml";
+
+/// Command line arguments derived from a configuration file
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigArgs {
+    /// Options, in command line form
+    pub options: Vec<String>,
+    /// The target command, if any
+    pub command: Vec<String>,
+}
+
+/// Read and parse a TOML configuration file
+pub fn load_config(path: &Path) -> Result<Table, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+    data.parse::<Value>()
+        .map_err(|e| e.to_string())
+        .and_then(|v| match v {
+            Value::Table(t) => Ok(t),
+            _ => Err("Expected a table".to_string()),
+        })
+}
+
+/// Convert the entries of a configuration file into command line arguments.
+///
+/// Keys for which `given_on_cmdline` returns true are skipped, so that the command line overrides
+/// the configuration file.
+pub fn config_to_args<F: Fn(&str) -> bool>(config: &Table, given_on_cmdline: F) -> Result<ConfigArgs, String> {
+    let mut args = ConfigArgs::default();
+
+    for (key, value) in config {
+        if given_on_cmdline(key) {
+            continue;
+        }
+
+        if key == "config" {
+            return Err("A configuration file cannot include another".to_string());
+        }
+
+        if key == "command" {
+            args.command = match value {
+                Value::String(s) => shlex::split(s)
+                    .ok_or_else(|| format!("Invalid quoting in {}", key))?,
+                Value::Array(a) => a.iter()
+                    .map(|v| scalar_to_string(key, v))
+                    .collect::<Result<Vec<String>, String>>()?,
+                _ => return Err(format!("{} must be a string or an array of strings", key)),
+            };
+
+            continue;
+        }
+
+        let flag = match key.as_str() {
+            "input" => "-i".to_string(),
+            "output" => "-o".to_string(),
+            _ => format!("--{}", key.replace('_', "-")),
+        };
+
+        match value {
+            Value::Boolean(true) => args.options.push(flag),
+            Value::Boolean(false) => (),
+            Value::Array(a) => {
+                for v in a {
+                    args.options.push(flag.to_string());
+                    args.options.push(scalar_to_string(key, v)?);
+                }
+            }
+            Value::Table(t) => {
+                for (name, v) in t {
+                    args.options.push(flag.to_string());
+                    args.options.push(format!("{}={}", name, scalar_to_string(key, v)?));
+                }
+            }
+            v => {
+                args.options.push(flag);
+                args.options.push(scalar_to_string(key, v)?);
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+fn scalar_to_string(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.to_string()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(format!("Unsupported value for {}", key)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_to_args() {
+        let config: Table = r#"
+            input = ["a", "b"]
+            output = "reports"
+            command = ["./target", "two words", "@@"]
+            timeout = 100
+            child_output = true
+            stdin = false
+            report_formats = ["text", "json"]
+
+            [env]
+            A = "1"
+        "#.parse::<Value>().unwrap().as_table().unwrap().clone();
+
+        let args = config_to_args(&config, |_| false).unwrap();
+
+        // TOML tables are sorted by key
+        assert_eq!(args.options, vec![
+            "--child-output", "--env", "A=1", "-i", "a", "-i", "b", "-o", "reports",
+            "--report-formats", "text", "--report-formats", "json", "--timeout", "100",
+        ]);
+        assert_eq!(args.command, vec!["./target", "two words", "@@"]);
+
+        let args = config_to_args(&config, |k| k == "timeout" || k == "command").unwrap();
+        assert!(!args.options.contains(&"--timeout".to_string()));
+        assert!(args.command.is_empty());
+
+        let config: Table = "command = './target --opt \"two words\" @@'".parse::<Value>().unwrap()
+            .as_table().unwrap().clone();
+        assert_eq!(config_to_args(&config, |_| false).unwrap().command,
+            vec!["./target", "--opt", "two words", "@@"]);
+
+        let config: Table = "config = 'other.toml'".parse::<Value>().unwrap().as_table().unwrap().clone();
+        assert!(config_to_args(&config, |_| false).is_err());
+    }
+}
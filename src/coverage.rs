@@ -0,0 +1,200 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Code coverage of crashing testcases.
+//!
+//! With `--coverage`, each crashing testcase is run once more outside of the debugger with the
+//! sanitizer option `coverage=1`. When the target exits or dies of a sanitizer error, the sanitizer
+//! runtime writes the coverage points (edges or basic blocks) that were hit to a
+//! `MODULE.PID.sancov` file per module. The points of the target binary are symbolized to the
+//! functions they are in and recorded as [Coverage] in JSON reports, so the code the crashes of
+//! each bucket exercise can be compared later.
+//!
+//! The target must be built with a sanitizer and `-fsanitize-coverage=trace-pc-guard`. gcov
+//! coverage is not supported, as gcov only writes it when the target exits normally.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::debugger::gdb::GdbTriageOptions;
+use crate::platform::symbolizer::ExternalSymbolizer;
+use crate::process::{self, ChildResult};
+
+/// The magic numbers of `.sancov` files with 64-bit and 32-bit offsets
+const SANCOV_MAGIC_64: u64 = 0xC0BF_FFFF_FFFF_FF64;
+const SANCOV_MAGIC_32: u64 = 0xC0BF_FFFF_FFFF_FF32;
+
+/// The code a testcase covered in the target binary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Coverage {
+    /// How many coverage points of the target binary were hit
+    pub covered_points: usize,
+    /// The functions of the target binary with a hit coverage point, sorted
+    pub covered_functions: Vec<String>,
+}
+
+impl Coverage {
+    pub fn summary(&self) -> String {
+        format!("{} function(s), {} coverage point(s)", self.covered_functions.len(), self.covered_points)
+    }
+}
+
+/// Parse a `.sancov` file: a magic number for the width of the offsets, then the offsets of the
+/// coverage points that were hit, relative to where the module is loaded
+pub fn parse_sancov(data: &[u8]) -> Result<Vec<u64>, String> {
+    if data.len() < 8 {
+        return Err("Truncated .sancov file".into());
+    }
+
+    let mut magic = [0u8; 8];
+    magic.copy_from_slice(&data[..8]);
+
+    let (width, big_endian) = match (u64::from_le_bytes(magic), u64::from_be_bytes(magic)) {
+        (SANCOV_MAGIC_64, _) => (8, false),
+        (SANCOV_MAGIC_32, _) => (4, false),
+        (_, SANCOV_MAGIC_64) => (8, true),
+        (_, SANCOV_MAGIC_32) => (4, true),
+        _ => return Err("Not a .sancov file".into()),
+    };
+
+    Ok(data[8..].chunks_exact(width)
+        .map(|offset| {
+            let mut bytes = [0u8; 8];
+
+            if big_endian {
+                bytes[8 - width..].copy_from_slice(offset);
+                u64::from_be_bytes(bytes)
+            } else {
+                bytes[..width].copy_from_slice(offset);
+                u64::from_le_bytes(bytes)
+            }
+        })
+        .collect())
+}
+
+/// Read the coverage points of the module named `module_name` from the `.sancov` files in `dir`.
+/// Each process the target started writes its own files
+pub fn read_module_coverage(dir: &Path, module_name: &str) -> Result<BTreeSet<u64>, String> {
+    let mut points = BTreeSet::new();
+    let prefix = format!("{}.", module_name);
+
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read the coverage directory: {}", e))?;
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // MODULE.PID.sancov
+        let is_module_file = name.strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".sancov"))
+            .map(|pid| pid.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or(false);
+
+        if !is_module_file {
+            continue;
+        }
+
+        let data = std::fs::read(entry.path())
+            .map_err(|e| format!("Failed to read {}: {}", name, e))?;
+        points.extend(parse_sancov(&data).map_err(|e| format!("{}: {}", name, e))?);
+    }
+
+    Ok(points)
+}
+
+/// The `ASAN_OPTIONS` to collect coverage with: those of the target with coverage written to `dir`
+fn coverage_asan_options(options: &GdbTriageOptions, dir: &Path) -> String {
+    let coverage_options = format!("coverage=1:coverage_dir={}", dir.display());
+
+    // Later options take precedence
+    match options.target_var("ASAN_OPTIONS").unwrap_or_default().as_str() {
+        "" => coverage_options,
+        target_options => format!("{}:{}", target_options, coverage_options),
+    }
+}
+
+/// Run the target outside of the debugger with coverage written to `dir`. `prog_args` is the
+/// target command with the testcase already substituted
+pub fn run_coverage(options: &GdbTriageOptions, prog_args: &[String], input: Option<Vec<u8>>, timeout_ms: u64,
+    dir: &Path) -> std::io::Result<ChildResult> {
+    let mut args: Vec<String> = vec![];
+
+    if options.clear_env {
+        args.push("-i".into());
+    }
+
+    args.extend(options.target_env.iter().map(|(k, v)| format!("{}={}", k, v)));
+    args.push(format!("ASAN_OPTIONS={}", coverage_asan_options(options, dir)));
+    args.extend(prog_args.iter().cloned());
+
+    let (program, args) = match &options.sandbox {
+        Some(sandbox) => sandbox.wrap("env", &args,
+            &prog_args.iter().map(Path::new).collect::<Vec<&Path>>()),
+        None => ("env".to_string(), args),
+    };
+
+    process::execute_capture_output_timeout(&program, &args, timeout_ms, input)
+}
+
+/// The [Coverage] of the target `binary` from the `.sancov` files written to `dir`
+pub fn collect_coverage(dir: &Path, binary: &Path, symbolizer: &ExternalSymbolizer) -> Result<Coverage, String> {
+    let module_name = binary.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let points: Vec<u64> = read_module_coverage(dir, &module_name)?.into_iter().collect();
+
+    if points.is_empty() {
+        return Err(format!("No coverage was written for {}. Is it built with -fsanitize-coverage=trace-pc-guard?", module_name));
+    }
+
+    let functions: BTreeSet<String> = symbolizer.function_names(binary, &points)
+        .map_err(|e| format!("Failed to symbolize coverage with {}: {}", symbolizer.path.display(), e))?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    Ok(Coverage {
+        covered_points: points.len(),
+        covered_functions: functions.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sancov() {
+        let mut data = SANCOV_MAGIC_64.to_le_bytes().to_vec();
+        data.extend(0x1234u64.to_le_bytes());
+        data.extend(0x5678u64.to_le_bytes());
+        assert_eq!(parse_sancov(&data), Ok(vec![0x1234, 0x5678]));
+
+        let mut data32 = SANCOV_MAGIC_32.to_be_bytes().to_vec();
+        data32.extend(0x1234u32.to_be_bytes());
+        assert_eq!(parse_sancov(&data32), Ok(vec![0x1234]));
+
+        assert!(parse_sancov(b"\x7fELF").is_err());
+        assert!(parse_sancov(&[0u8; 16]).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut other = SANCOV_MAGIC_64.to_le_bytes().to_vec();
+        other.extend(0x1234u64.to_le_bytes());
+        other.extend(0x9abcu64.to_le_bytes());
+
+        std::fs::write(dir.path().join("target.100.sancov"), &data).unwrap();
+        std::fs::write(dir.path().join("target.101.sancov"), &other).unwrap();
+        std::fs::write(dir.path().join("libc.so.6.100.sancov"), &other).unwrap();
+        std::fs::write(dir.path().join("target_asan.100.sancov"), &other).unwrap();
+
+        let points = read_module_coverage(dir.path(), "target").unwrap();
+        assert_eq!(points.into_iter().collect::<Vec<u64>>(), vec![0x1234, 0x5678, 0x9abc]);
+
+        let options = GdbTriageOptions {
+            target_env: vec![("ASAN_OPTIONS".into(), "abort_on_error=1".into())],
+            ..Default::default()
+        };
+        assert_eq!(coverage_asan_options(&options, Path::new("/tmp/cov")), "abort_on_error=1:coverage=1:coverage_dir=/tmp/cov");
+    }
+}
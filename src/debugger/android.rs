@@ -0,0 +1,321 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Triage on an Android device over adb.
+//!
+//! With [crate::debugger::gdb::GdbTriageOptions::android], the target command is run on the
+//! device, so its paths are the device's. Each testcase is pushed to [DEVICE_DIR] and `@@` is
+//! replaced by its path on the device. The target is started under the device's `gdbserver` (or
+//! `lldb-server` in its `gdbserver` mode) through `adb shell`, and GDB connects to it through a
+//! port forwarded by adb. GDB must support the device's architecture, like `gdb-multiarch`.
+//!
+//! Binaries on the device are usually stripped. GDB loads their symbols from the symbol
+//! directories: the first is the sysroot, where binaries are found by their path on the device, as
+//! in the `symbols` directory of an AOSP build, and all of them are searched for libraries by
+//! name, as in the `obj/local/ABI` directory of an NDK build. Without symbol directories, GDB
+//! reads the binaries from the device. The target's output is relayed by `adb shell`, and is what
+//! sanitizer reports are extracted from.
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The directory on the device testcases are pushed to
+pub const DEVICE_DIR: &str = "/data/local/tmp/afltriage";
+
+/// Where the NDK's instructions have gdbserver pushed to
+pub const DEFAULT_GDBSERVER: &str = "/data/local/tmp/gdbserver";
+
+/// The port gdbserver listens on, on the device and forwarded to the host
+pub const DEFAULT_PORT: u16 = 5039;
+
+/// How long gdbserver may take to start the target
+const GDBSERVER_START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long gdbserver may take to exit once GDB is done with the target
+const GDBSERVER_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Messages gdbserver and lldb-server print about the target, which are not the target's output
+const GDBSERVER_MESSAGES: [&str; 8] = [
+    "Listening on port ", "Remote debugging from host ", "Child exited with status ",
+    "Child terminated with signal ", "Detaching from process ", "Killing process", "Launched '",
+    "lldb-server exiting",
+];
+
+/// An Android device to run the target on
+#[derive(Debug, Clone)]
+pub struct AndroidDevice {
+    /// The adb executable
+    pub adb: PathBuf,
+    /// The serial number of the device, if there is more than one
+    pub serial: Option<String>,
+    /// The path of gdbserver or lldb-server on the device
+    pub gdbserver: String,
+    /// The port gdbserver listens on
+    pub port: u16,
+    /// Directories of unstripped binaries and libraries of the device
+    pub symbol_dirs: Vec<PathBuf>,
+}
+
+/// A gdbserver running the target on the device
+pub struct GdbServer {
+    child: Child,
+    stdout: Arc<Mutex<Vec<u8>>>,
+    stderr: Arc<Mutex<Vec<u8>>>,
+    readers: Vec<std::thread::JoinHandle<()>>,
+}
+
+impl AndroidDevice {
+    /// The device with `serial`, or the only one, with gdbserver at its default path
+    pub fn new(adb: PathBuf, serial: Option<String>) -> AndroidDevice {
+        AndroidDevice {
+            adb,
+            serial,
+            gdbserver: DEFAULT_GDBSERVER.to_string(),
+            port: DEFAULT_PORT,
+            symbol_dirs: vec![],
+        }
+    }
+
+    fn adb_command(&self) -> Command {
+        let mut command = Command::new(&self.adb);
+
+        if let Some(serial) = &self.serial {
+            command.args(["-s", serial]);
+        }
+
+        command
+    }
+
+    /// Run adb with `args` to completion
+    fn adb(&self, args: &[&str]) -> std::io::Result<()> {
+        let output = self.adb_command().args(args).stdin(Stdio::null()).output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(std::io::Error::other(format!("adb {} failed: {}", args.join(" "), stderr.trim())));
+        }
+
+        Ok(())
+    }
+
+    /// Create the testcase directory on the device and forward the gdbserver port to it
+    pub fn prepare(&self) -> std::io::Result<()> {
+        self.adb(&["shell", &format!("mkdir -p {}", DEVICE_DIR)])?;
+        self.adb(&["forward", &format!("tcp:{}", self.port), &format!("tcp:{}", self.port)])
+    }
+
+    /// The path of `testcase` once pushed to the device
+    pub fn device_path(testcase: &str) -> String {
+        let name = Path::new(testcase).file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        format!("{}/{}", DEVICE_DIR, name)
+    }
+
+    /// Push `testcase` to the device, returning its path there
+    pub fn push_testcase(&self, testcase: &str) -> std::io::Result<String> {
+        let device_path = AndroidDevice::device_path(testcase);
+        self.adb(&["push", testcase, &device_path])?;
+        Ok(device_path)
+    }
+
+    /// The shell command that starts `prog_args` under gdbserver on the device, with `env` and the
+    /// file at `input_file` on its stdin
+    pub fn gdbserver_command(&self, prog_args: &[String], input_file: Option<&str>, env: &[(String, String)]) -> String {
+        let mut command: Vec<String> = vec![];
+
+        if !env.is_empty() {
+            command.push("env".into());
+            command.extend(env.iter().map(|(key, value)| format!("{}={}", key, value)));
+        }
+
+        command.push(self.gdbserver.clone());
+
+        if self.gdbserver.ends_with("lldb-server") {
+            command.extend(["gdbserver".into(), format!(":{}", self.port), "--".into()]);
+        } else {
+            command.push(format!(":{}", self.port));
+        }
+
+        command.extend(prog_args.iter().cloned());
+
+        let mut command = crate::util::sh_join(&command);
+
+        if let Some(file) = input_file {
+            command = format!("{} < {}", command, crate::util::sh_quote(file));
+        }
+
+        command
+    }
+
+    /// Start gdbserver running `prog_args` on the device, once it is ready for GDB to connect
+    pub fn start_gdbserver(&self, prog_args: &[String], input_file: Option<&str>, env: &[(String, String)]) -> std::io::Result<GdbServer> {
+        let command = self.gdbserver_command(prog_args, input_file, env);
+
+        let mut child = self.adb_command()
+            .args(["shell", &command])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let stdout = Arc::new(Mutex::new(vec![]));
+        let stderr = Arc::new(Mutex::new(vec![]));
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        // gdbserver prints that it listens on stderr, and lldb-server that it launched the target on stdout
+        let mut readers = vec![];
+
+        if let Some(stream) = child.stdout.take() {
+            readers.push(spawn_reader(stream, stdout.clone(), ready_tx.clone()));
+        }
+
+        if let Some(stream) = child.stderr.take() {
+            readers.push(spawn_reader(stream, stderr.clone(), ready_tx));
+        }
+
+        let server = GdbServer { child, stdout, stderr, readers };
+
+        if ready_rx.recv_timeout(GDBSERVER_START_TIMEOUT).is_err() {
+            let (_, stderr) = server.finish(self);
+            return Err(std::io::Error::other(format!("{} did not start: {}", self.gdbserver, stderr.trim())));
+        }
+
+        Ok(server)
+    }
+
+    /// Kill gdbserver on the device, in case GDB left it running
+    fn kill_gdbserver(&self) {
+        let name = Path::new(&self.gdbserver).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+        if let Err(e) = self.adb(&["shell", &format!("pkill -x {}", shlex::quote(&name))]) {
+            log::debug!("Could not kill {} on the device: {}", name, e);
+        }
+    }
+
+    /// GDB commands that find the device's binaries in the symbol directories
+    pub fn gdb_setup_commands(&self) -> Vec<String> {
+        let dirs: Vec<String> = self.symbol_dirs.iter().map(|d| d.to_string_lossy().to_string()).collect();
+
+        match dirs.first() {
+            Some(sysroot) => vec![
+                format!("set sysroot {}", sysroot),
+                format!("set solib-search-path {}", dirs.join(":")),
+            ],
+            None => vec![],
+        }
+    }
+
+    /// The unstripped copy of the device binary at `device_path` in the symbol directories, by its
+    /// path or else by its name
+    pub fn local_binary(&self, device_path: &str) -> Option<PathBuf> {
+        let relative = device_path.trim_start_matches('/');
+        let name = Path::new(device_path).file_name()?;
+
+        self.symbol_dirs.iter().map(|dir| dir.join(relative))
+            .chain(self.symbol_dirs.iter().map(|dir| dir.join(name)))
+            .find(|path| path.is_file())
+    }
+
+    /// The GDB command that connects to gdbserver
+    pub fn connect_command(&self) -> String {
+        format!("target remote localhost:{}", self.port)
+    }
+}
+
+impl GdbServer {
+    /// Wait for gdbserver to exit, killing it if it does not, and return the target's stdout and
+    /// stderr
+    pub fn finish(mut self, device: &AndroidDevice) -> (String, String) {
+        let deadline = std::time::Instant::now() + GDBSERVER_EXIT_TIMEOUT;
+
+        while let Ok(None) = self.child.try_wait() {
+            if std::time::Instant::now() >= deadline {
+                device.kill_gdbserver();
+                let _ = self.child.kill();
+                let _ = self.child.wait();
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        for reader in self.readers.drain(..) {
+            let _ = reader.join();
+        }
+
+        let stdout = String::from_utf8_lossy(&self.stdout.lock().unwrap()).to_string();
+        let stderr = String::from_utf8_lossy(&self.stderr.lock().unwrap()).to_string();
+
+        (strip_gdbserver_messages(&stdout), strip_gdbserver_messages(&stderr))
+    }
+}
+
+/// Collect the output of gdbserver from `stream` into `buffer`, telling `ready` once it is ready
+/// for GDB to connect
+fn spawn_reader(stream: impl Read + Send + 'static, buffer: Arc<Mutex<Vec<u8>>>, ready: mpsc::Sender<()>)
+    -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        let mut line = vec![];
+
+        while let Ok(n) = reader.read_until(b'\n', &mut line) {
+            if n == 0 {
+                break;
+            }
+
+            let text = String::from_utf8_lossy(&line);
+            if text.starts_with("Listening on port ") || text.starts_with("Launched '") {
+                let _ = ready.send(());
+            }
+
+            buffer.lock().unwrap().extend_from_slice(&line);
+            line.clear();
+        }
+    })
+}
+
+/// Remove the lines gdbserver printed from the target's `output`
+fn strip_gdbserver_messages(output: &str) -> String {
+    output.split_inclusive('\n')
+        .filter(|line| !GDBSERVER_MESSAGES.iter().any(|m| line.starts_with(m)))
+        .filter(|line| !(line.starts_with("Process ") && line.contains(" created; pid = ")))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_android_device() {
+        let mut device = AndroidDevice::new(PathBuf::from("adb"), Some("emulator-5554".into()));
+        let prog_args = vec!["/data/local/tmp/fuzzer".to_string(), AndroidDevice::device_path("/out/crashes/id:000000,sig:11")];
+
+        assert_eq!(prog_args[1], "/data/local/tmp/afltriage/id:000000,sig:11");
+        assert_eq!(device.gdbserver_command(&prog_args, None, &[]),
+            "/data/local/tmp/gdbserver :5039 /data/local/tmp/fuzzer /data/local/tmp/afltriage/id:000000,sig:11");
+
+        device.gdbserver = "/data/local/tmp/lldb-server".into();
+        let env = vec![("HWASAN_OPTIONS".to_string(), "abort_on_error=1".to_string())];
+        assert_eq!(device.gdbserver_command(&prog_args[..1], Some("/data/local/tmp/afltriage/input"), &env),
+            "env HWASAN_OPTIONS=abort_on_error=1 /data/local/tmp/lldb-server gdbserver :5039 -- /data/local/tmp/fuzzer \
+            < /data/local/tmp/afltriage/input");
+
+        let symbols = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(symbols.path().join("system/lib64")).unwrap();
+        std::fs::write(symbols.path().join("system/lib64/libfoo.so"), "").unwrap();
+        std::fs::write(symbols.path().join("fuzzer"), "").unwrap();
+        device.symbol_dirs = vec![symbols.path().to_path_buf()];
+
+        assert_eq!(device.local_binary("/system/lib64/libfoo.so"), Some(symbols.path().join("system/lib64/libfoo.so")));
+        assert_eq!(device.local_binary("/data/local/tmp/fuzzer"), Some(symbols.path().join("fuzzer")));
+        assert_eq!(device.local_binary("/data/local/tmp/other"), None);
+
+        let output = "Process /data/local/tmp/fuzzer created; pid = 1234\nListening on port 5039\n\
+            Remote debugging from host 127.0.0.1, port 40000\n==1234==ERROR: HWAddressSanitizer: tag-mismatch\n\
+            Child terminated with signal = 0x6 (SIGABRT)\n";
+        assert_eq!(strip_gdbserver_messages(output), "==1234==ERROR: HWAddressSanitizer: tag-mismatch\n");
+    }
+}
@@ -0,0 +1,448 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Triage of Windows targets under CDB instead of GDB.
+//!
+//! WinAFL crashes are Windows exceptions, which GDB cannot debug. With [GdbTriageOptions::cdb],
+//! every testcase is run under CDB, the console debugger of the Debugging Tools for Windows. CDB
+//! only stops on the second chance of an exception, after the target's own handlers declined it,
+//! and then runs [cdb_commands] to print the exception record, registers, stack, and modules
+//! between markers. This output is parsed into the same [GdbTriageResult] GDB gives, so bucketing
+//! and reports work the same.
+//!
+//! Exceptions are reported as the closest Linux signal (see [exception_signal]), e.g. an access
+//! violation as a SIGSEGV at the address it accessed, so they are classified like crashes on
+//! Linux. CDB reads its commands from stdin, so testcases can only be given to the target as a
+//! file.
+use crate::debugger::gdb::*;
+use crate::process::{self, OutputLimit, ProcessStdin};
+use regex::Regex;
+use std::path::Path;
+use std::rc::Rc;
+
+const EXCEPTION_MARKER: &str = "----AFLTRIAGE-EXCEPTION----";
+const REGISTERS_MARKER: &str = "----AFLTRIAGE-REGISTERS----";
+const STACK_MARKER: &str = "----AFLTRIAGE-STACK----";
+const MODULES_MARKER: &str = "----AFLTRIAGE-MODULES----";
+const END_MARKER: &str = "----AFLTRIAGE-END----";
+
+/// The commands CDB runs: break only on the second chance of the exceptions it stops on by
+/// default, run the target, and print what triage needs after the exception that stopped it. The
+/// most frames are the same as GDBTriage
+fn cdb_commands() -> String {
+    format!("sxd av; sxd sov; sxd dz; sxd c000008e; sxd ii; sxd gp; sxd *; g; \
+        .echo {}; .exr -1; .echo {}; r; .echo {}; kn 100; .echo {}; lm f; .echo {}; q",
+        EXCEPTION_MARKER, REGISTERS_MARKER, STACK_MARKER, MODULES_MARKER, END_MARKER)
+}
+
+lazy_static! {
+    static ref R_EXCEPTION_ADDRESS: Regex = Regex::new(r"(?m)^\s*ExceptionAddress: ([0-9a-f`]+)").unwrap();
+    static ref R_EXCEPTION_CODE: Regex = Regex::new(r"(?m)^\s*ExceptionCode: ([0-9a-f]+)").unwrap();
+    static ref R_EXCEPTION_PARAMETER: Regex = Regex::new(r"(?m)^\s*Parameter\[([0-9]+)\]: ([0-9a-f`]+)").unwrap();
+    static ref R_REGISTER: Regex = Regex::new(r"\b([a-z][a-z0-9]*)=([0-9a-f]{8,16})\b").unwrap();
+    /// `kn` frames: the frame number, child stack pointer, return address, and call site. Inlined
+    /// frames have no stack pointer or return address
+    static ref R_FRAME: Regex = Regex::new(
+        r"^([0-9a-f]{2,}) (?:\(Inline Function\)|[0-9a-f`]+) ([0-9a-f`-]+)\s+(.+)$").unwrap();
+    static ref R_CALL_SITE: Regex = Regex::new(
+        r"^(?P<module>[^!\s]+)!(?P<func>.+?)(\+0x[0-9a-f]+)?( \[(?P<file>.+) @ (?P<line>[0-9]+)\])?$").unwrap();
+    /// `lm f` modules: the start and end address, module name, and image path
+    static ref R_MODULE: Regex = Regex::new(r"^([0-9a-f`]+) ([0-9a-f`]+)\s+(\S+)\s+(.+)$").unwrap();
+    /// The process and thread an event happened in, like `(1f2c.2d48): Access violation - code c0000005`
+    static ref R_EVENT: Regex = Regex::new(r"^\(([0-9a-f]+)\.([0-9a-f]+)\): ").unwrap();
+    static ref R_PROMPT: Regex = Regex::new(r"^[0-9]+:[0-9]+(:[a-z0-9]+)?> ").unwrap();
+}
+
+/// A Windows exception record, from `.exr`
+#[derive(Debug, PartialEq)]
+struct CdbException {
+    address: u64,
+    code: u32,
+    parameters: Vec<u64>,
+}
+
+/// A frame of a `kn` stack
+#[derive(Debug, Default, PartialEq)]
+struct CdbFrame {
+    /// The return address into the caller, if the frame was not inlined
+    return_address: Option<u64>,
+    function: Option<String>,
+    file: Option<String>,
+    line: Option<i64>,
+}
+
+/// A module from `lm f`
+#[derive(Debug, PartialEq)]
+struct CdbModule {
+    start: u64,
+    end: u64,
+    path: String,
+}
+
+/// What CDB printed after the target stopped
+#[derive(Debug, Default)]
+struct CdbOutput {
+    tid: i32,
+    exception: Option<CdbException>,
+    registers: Vec<(String, u64)>,
+    stack: Vec<CdbFrame>,
+    modules: Vec<CdbModule>,
+    /// The target's output, without CDB's
+    stdout: String,
+}
+
+/// Parse a CDB address, which may be split by a backtick like `00007ff6`a1b21234`
+fn parse_address(address: &str) -> Option<u64> {
+    u64::from_str_radix(&address.replace('`', ""), 16).ok()
+}
+
+/// The lines of `output` between `start` and the next marker
+fn section<'a>(output: &'a str, start: &str) -> Vec<&'a str> {
+    output.lines()
+        .map(|l| l.trim_end_matches('\r'))
+        .skip_while(|l| *l != start)
+        .skip(1)
+        .take_while(|l| !l.starts_with("----AFLTRIAGE-"))
+        .collect()
+}
+
+fn parse_exception(lines: &[&str]) -> Option<CdbException> {
+    let text = lines.join("\n");
+    let code = R_EXCEPTION_CODE.captures(&text)?;
+
+    Some(CdbException {
+        address: R_EXCEPTION_ADDRESS.captures(&text).and_then(|c| parse_address(&c[1]))?,
+        code: u32::from_str_radix(&code[1], 16).ok()?,
+        parameters: R_EXCEPTION_PARAMETER.captures_iter(&text)
+            .filter_map(|c| parse_address(&c[2]))
+            .collect(),
+    })
+}
+
+fn parse_frame(line: &str) -> Option<CdbFrame> {
+    let frame = R_FRAME.captures(line.trim_start())?;
+    let call_site = R_CALL_SITE.captures(&frame[3]);
+
+    Some(CdbFrame {
+        return_address: parse_address(&frame[2]),
+        function: call_site.as_ref().map(|c| c["func"].to_string()),
+        file: call_site.as_ref().and_then(|c| c.name("file")).map(|f| f.as_str().to_string()),
+        line: call_site.as_ref().and_then(|c| c.name("line")).and_then(|l| l.as_str().parse().ok()),
+    })
+}
+
+fn parse_module(line: &str) -> Option<CdbModule> {
+    let module = R_MODULE.captures(line)?;
+
+    Some(CdbModule {
+        start: parse_address(&module[1])?,
+        end: parse_address(&module[2])?,
+        path: module[4].trim().to_string(),
+    })
+}
+
+/// The target's output is what CDB printed after running the commands and before the exception
+/// that stopped the target, except for the modules it loaded and the exceptions it handled
+fn target_output(output: &str) -> String {
+    let lines: Vec<&str> = output.lines()
+        .take_while(|l| l.trim_end_matches('\r') != EXCEPTION_MARKER)
+        .skip_while(|l| !R_PROMPT.is_match(l))
+        .skip(1)
+        .collect();
+
+    // The exception is followed by the faulting instruction
+    let end = match output.contains(EXCEPTION_MARKER) {
+        true => lines.iter().rposition(|l| R_EVENT.is_match(l)).unwrap_or(lines.len()),
+        false => lines.len(),
+    };
+
+    lines[..end].iter()
+        .filter(|l| !l.starts_with("ModLoad: ") && !R_EVENT.is_match(l))
+        .map(|l| format!("{}\n", l.trim_end_matches('\r')))
+        .collect()
+}
+
+/// Parse what CDB printed for [cdb_commands]
+fn parse_cdb_output(output: &str) -> CdbOutput {
+    let tid = output.lines()
+        .take_while(|l| l.trim_end_matches('\r') != EXCEPTION_MARKER)
+        .filter_map(|l| R_EVENT.captures(l))
+        .last()
+        .and_then(|c| i32::from_str_radix(&c[2], 16).ok())
+        .unwrap_or(0);
+
+    CdbOutput {
+        tid,
+        exception: parse_exception(&section(output, EXCEPTION_MARKER)),
+        registers: section(output, REGISTERS_MARKER).iter()
+            .flat_map(|l| R_REGISTER.captures_iter(l))
+            .filter_map(|c| Some((c[1].to_string(), u64::from_str_radix(&c[2], 16).ok()?)))
+            .collect(),
+        stack: section(output, STACK_MARKER).iter().filter_map(|l| parse_frame(l)).collect(),
+        modules: section(output, MODULES_MARKER).iter().filter_map(|l| parse_module(l)).collect(),
+        stdout: target_output(output),
+    }
+}
+
+/// The Linux signal closest to a Windows exception code, its number, and whether it comes from the
+/// processor, as for a signal sent by the kernel
+fn exception_signal(code: u32) -> (&'static str, i32, bool) {
+    match code {
+        // Access violation, stack overflow
+        0xc000_0005 | 0xc000_00fd => ("SIGSEGV", 11, true),
+        // In-page I/O error
+        0xc000_0006 => ("SIGBUS", 7, true),
+        // Illegal and privileged instruction
+        0xc000_001d | 0xc000_0096 => ("SIGILL", 4, true),
+        // Integer and floating point errors
+        0xc000_008c..=0xc000_0095 => ("SIGFPE", 8, true),
+        // Breakpoint, including in a 32-bit target under WOW64
+        0x8000_0003 | 0x4000_001f => ("SIGTRAP", 5, true),
+        // Any other unhandled exception terminates the process, like a fail fast (c0000409), heap
+        // corruption (c0000374), or an uncaught C++ exception (e06d7363)
+        _ => ("SIGABRT", 6, false),
+    }
+}
+
+/// The address size and GDB architecture name of the target, from its registers
+fn architecture(registers: &[(String, u64)]) -> (usize, &'static str) {
+    let has = |name: &str| registers.iter().any(|(r, _)| r == name);
+
+    if has("rip") {
+        (64, "i386:x86-64")
+    } else if has("eip") {
+        (32, "i386")
+    } else if has("x0") {
+        (64, "aarch64")
+    } else {
+        (std::mem::size_of::<usize>() * 8, std::env::consts::ARCH)
+    }
+}
+
+fn frame_info(address: u64, frame: &CdbFrame, modules: &[CdbModule]) -> GdbFrameInfo {
+    let symbol = frame.function.as_ref().map(|function| Rc::new(GdbSymbol {
+        function_name: Some(function.to_string()),
+        file: frame.file.clone(),
+        line: frame.line,
+        ..Default::default()
+    }));
+
+    match modules.iter().find(|m| address >= m.start && address < m.end) {
+        Some(module) => GdbFrameInfo {
+            address,
+            relative_address: address - module.start,
+            module: module.path.to_string(),
+            module_address: format!("{}+0x{:x}", module.path, address - module.start),
+            symbol,
+        },
+        None => GdbFrameInfo {
+            address,
+            relative_address: address,
+            module: "??".into(),
+            module_address: format!("0x{:x}", address),
+            symbol,
+        },
+    }
+}
+
+/// Convert CDB's output to a triage result. A run without an exception did not crash
+fn cdb_triage_result(cdb: CdbOutput, stderr: String) -> GdbTriageResult {
+    let child = GdbChildOutput { stdout: cdb.stdout, stderr, ..Default::default() };
+
+    let exception = match cdb.exception {
+        Some(exception) => exception,
+        None => return GdbTriageResult {
+            response: GdbJsonResult {
+                result: GdbResultCode::ERROR_TARGET_NOT_RUNNING,
+                context: None,
+                forks: None,
+                exit_code: None,
+                exit_signal: None,
+                oracle_backtrace: None,
+                warnings: vec![],
+            },
+            child,
+        },
+    };
+
+    let (signal_name, signal_number, from_processor) = exception_signal(exception.code);
+    // Access violations and in-page errors have the address they accessed as their second
+    // parameter, and stack overflows the one they touched
+    let faulting_address = match exception.code {
+        0xc000_0005 | 0xc000_0006 | 0xc000_00fd => exception.parameters.get(1).copied(),
+        _ if from_processor => Some(exception.address),
+        _ => None,
+    };
+    let (address_bits, architecture) = architecture(&cdb.registers);
+
+    // Frames only show their return address, which is where their caller is
+    let mut address = exception.address;
+    let mut backtrace = vec![];
+
+    for frame in &cdb.stack {
+        backtrace.push(frame_info(address, frame, &cdb.modules));
+
+        if let Some(return_address) = frame.return_address {
+            address = return_address;
+        }
+    }
+
+    let registers = cdb.registers.iter()
+        .map(|(name, value)| Rc::new(GdbRegister {
+            name: name.to_string(),
+            value: *value,
+            pretty_value: format!("0x{:x}", value),
+            r#type: "int".into(),
+            size: (address_bits / 8) as u64,
+        }))
+        .collect();
+
+    GdbTriageResult {
+        response: GdbJsonResult {
+            result: GdbResultCode::SUCCESS,
+            context: Some(GdbContextInfo {
+                stop_info: GdbStopInfo {
+                    signal_name: signal_name.into(),
+                    signal_number,
+                    // SEGV_MAPERR, ILL_ILLOPC, FPE_INTDIV, and TRAP_BRKPT are all 1, and SI_USER
+                    // is 0
+                    signal_code: from_processor as i32,
+                    faulting_address,
+                },
+                arch_info: GdbArchInfo {
+                    address_bits,
+                    architecture: architecture.into(),
+                },
+                primary_thread: GdbThread {
+                    tid: cdb.tid,
+                    name: None,
+                    backtrace,
+                    current_instruction: None,
+                    registers: Some(registers),
+                    unwind_error: None,
+                },
+                other_threads: None,
+                memory_map: Some(cdb.modules.iter()
+                    .map(|m| GdbMemoryMapping {
+                        start: m.start,
+                        end: m.end,
+                        offset: 0,
+                        permissions: None,
+                        name: m.path.to_string(),
+                    })
+                    .collect()),
+                modules: Some(cdb.modules.iter()
+                    .map(|m| GdbModule {
+                        path: m.path.to_string(),
+                        load_address: Some(m.start),
+                        end_address: Some(m.end),
+                        build_id: None,
+                    })
+                    .collect()),
+                extra: None,
+                forked_child: None,
+                hang: None,
+                exception: None,
+                fault_handler: None,
+            }),
+            forks: None,
+            exit_code: None,
+            exit_signal: None,
+            oracle_backtrace: None,
+            warnings: vec![],
+        },
+        child,
+    }
+}
+
+/// Run Windows programs under CDB until they raise an exception they do not handle
+pub struct CdbTriager<'a> {
+    cdb: &'a Path,
+    options: &'a GdbTriageOptions,
+}
+
+impl<'a> CdbTriager<'a> {
+    pub fn new(cdb: &'a Path, options: &'a GdbTriageOptions) -> CdbTriager<'a> {
+        CdbTriager { cdb, options }
+    }
+
+    /// Run a program under CDB until it stops on an exception or exits. The testcase must be in
+    /// `prog_args`
+    pub fn triage_program(&self, prog_args: &[String], timeout_ms: u64) -> Result<GdbTriageResult, GdbTriageError> {
+        let mut args: Vec<String> = vec![
+            // Skip the initial and final breakpoints, and show source lines in frames
+            "-g".into(), "-G".into(), "-lines".into(),
+            "-c".into(), cdb_commands(),
+        ];
+        args.extend(prog_args.iter().cloned());
+
+        let limit = self.options.max_child_output.map(OutputLimit::new);
+
+        let output = process::execute_capture_limited_output_timeout(&self.cdb.to_string_lossy(), &args,
+            timeout_ms, ProcessStdin::Null, limit.as_ref())
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::TimedOut => GdbTriageError::new_brief(GdbTriageErrorKind::Timeout, e.to_string()),
+                _ => GdbTriageError::new_brief(GdbTriageErrorKind::Command, format!("Failed to run CDB: {}", e)),
+            })?;
+
+        let cdb = parse_cdb_output(&output.stdout);
+
+        if cdb.exception.is_none() && output.stdout.lines().any(|l| l.trim_end_matches('\r') == EXCEPTION_MARKER) {
+            return Err(GdbTriageError::new_detailed(GdbTriageErrorKind::Command,
+                "CDB stopped without an exception record", vec![output.stdout]));
+        }
+
+        Ok(cdb_triage_result(cdb, output.stderr))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_cdb_output() {
+        let mut path = PathBuf::from(file!());
+        path.pop();
+        path.push("res");
+        path.push("test_cdb");
+        path.push("access_violation.txt");
+
+        let output = std::fs::read_to_string(path).unwrap();
+        let cdb = parse_cdb_output(&output);
+
+        assert_eq!(cdb.tid, 0x2d48);
+        assert_eq!(cdb.exception, Some(CdbException {
+            address: 0x7ff6_a1b2_1234,
+            code: 0xc000_0005,
+            parameters: vec![0, 0x10],
+        }));
+        assert!(cdb.registers.contains(&("rip".into(), 0x7ff6_a1b2_1234)));
+        assert_eq!(cdb.stack.len(), 5);
+        assert_eq!(cdb.stack[1], CdbFrame {
+            return_address: Some(0x7ff6_a1b2_1320),
+            function: Some("main".into()),
+            file: Some("C:\\fuzz\\src\\main.c".into()),
+            line: Some(30),
+        });
+        assert_eq!(cdb.modules[0], CdbModule { start: 0x7ff6_a1b2_0000, end: 0x7ff6_a1b4_5000, path: "C:\\fuzz\\target.exe".into() });
+        assert_eq!(cdb.stdout, "parsing header\n");
+
+        let triage = cdb_triage_result(cdb, String::new());
+        let ctx = triage.response.context.as_ref().unwrap();
+        assert_eq!((ctx.stop_info.signal_name.as_str(), ctx.stop_info.faulting_address), ("SIGSEGV", Some(0x10)));
+        assert_eq!(ctx.arch_info.architecture, "i386:x86-64");
+        assert_eq!(ctx.primary_thread.backtrace[0].module_address, "C:\\fuzz\\target.exe+0x1234");
+        assert_eq!(ctx.primary_thread.backtrace[1].address, 0x7ff6_a1b2_12a9);
+        assert_eq!(ctx.primary_thread.backtrace[3].module, "C:\\Windows\\System32\\KERNEL32.DLL");
+
+        let opt = crate::report::ReportOptions { child_output_lines: 0, ..Default::default() };
+        let einfo = crate::report::enriched::enrich_triage_info(&opt, &triage).unwrap();
+        assert!(einfo.summary.contains("parse_header"));
+
+        let clean = cdb_triage_result(parse_cdb_output("0:000> sxd av; g\nparsing header\n"), String::new());
+        assert!(matches!(clean.response.result, GdbResultCode::ERROR_TARGET_NOT_RUNNING));
+        assert_eq!(clean.child.stdout, "parsing header\n");
+    }
+}
@@ -31,20 +31,39 @@
 //! create a dedicated PTY for GDB.
 use serde::{Deserialize, Serialize};
 use std::io::{ErrorKind, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::os::unix::io::AsRawFd;
 use std::os::unix::process::ExitStatusExt;
 
-use crate::util::shell_join;
-use crate::process;
+use crate::util::{decode_output, sh_join, sh_quote, shell_join, TARGET_SHELL};
+use crate::process::{self, OutputLimit, ProcessStdin};
 use crate::platform::linux::signal_to_string;
+use crate::input::ChildStdin;
+use crate::sandbox::{Sandbox, SandboxLevel};
+use crate::debugger::session::GdbSession;
+use crate::debugger::valgrind;
+use crate::debugger::cdb::CdbTriager;
+use crate::debugger::lldb::LldbTriager;
+use crate::debugger::remote::{RemoteProtocolErrors, RemoteTarget};
+use crate::debugger::android::AndroidDevice;
+use crate::platform::symbolizer::ExternalSymbolizer;
+use crate::sourcemap::SourcePathMap;
+use std::cell::RefCell;
 
 #[doc(hidden)]
 /// The built-in GDBTriage python script
 const INTERNAL_TRIAGE_SCRIPT: &[u8] = include_bytes!("./res/GDBTriage.py");
+const FORK_TRACKING_SCRIPT: &[u8] = include_bytes!("./res/fork_tracking.gdb");
+const ORACLE_BREAKPOINT_SCRIPT: &[u8] = include_bytes!("./res/oracle_breakpoint.py");
+const HANG_WATCHDOG_SCRIPT: &[u8] = include_bytes!("./res/hang_watchdog.py");
+const REMOTE_FAULT_SCRIPT: &[u8] = include_bytes!("./res/remote_fault.py");
+
+/// How long GDB may take to collect the triage information of a hang after the target was stopped
+const HANG_TRIAGE_GRACE_MS: u64 = 30000;
 
 /// Symbol information for frame
-#[derive(Debug, Serialize, PartialEq, Deserialize)]
+#[derive(Debug, Default, Serialize, PartialEq, Deserialize)]
 pub struct GdbSymbol {
     /// The demangled function name
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -188,6 +207,9 @@ pub struct GdbFrameInfo {
 pub struct GdbThread {
     /// The thread's OS ID
     pub tid: i32,
+    /// The thread's name, if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     /// Zero or more stack frames
     pub backtrace: Vec<GdbFrameInfo>,
     /// The current instruction where the thread stopped
@@ -196,6 +218,9 @@ pub struct GdbThread {
     /// The thread's register set. Registers in the GDB defined order
     #[serde(skip_serializing_if = "Option::is_none")]
     pub registers: Option<Vec<Rc<GdbRegister>>>,
+    /// Why GDB stopped unwinding the backtrace early, if the stack looked corrupt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unwind_error: Option<String>,
 }
 
 /// A target register
@@ -237,6 +262,40 @@ pub struct GdbArchInfo {
     pub architecture: String,
 }
 
+/// A memory mapping of the target process
+///
+/// Taken from Linux /proc/PID/mappings
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GdbMemoryMapping {
+    /// The start address of the mapping
+    pub start: u64,
+    /// The end address of the mapping (exclusive)
+    pub end: u64,
+    /// The offset into the backing file
+    pub offset: u64,
+    /// The mapping's permissions (e.g. r-xp). Only available on newer GDB versions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<String>,
+    /// The backing file or a pseudo-path like \[heap\] or \[stack\]. Empty for anonymous mappings
+    pub name: String,
+}
+
+/// A module (the executable or a shared library) loaded in the target process
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GdbModule {
+    /// The module's file path
+    pub path: String,
+    /// The lowest address the module is mapped at, if it is in the memory map
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_address: Option<u64>,
+    /// The end of the module's highest mapping (exclusive)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_address: Option<u64>,
+    /// The module's GNU build ID in hex, if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_id: Option<String>,
+}
+
 /// The stop context information from GDBTriage
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GdbContextInfo {
@@ -244,11 +303,56 @@ pub struct GdbContextInfo {
     pub stop_info: GdbStopInfo,
     /// Architecture information
     pub arch_info: GdbArchInfo,
-    /// The primary (or faulting) thread that caused a process stop
+    /// The primary (or faulting) thread that caused a process stop. When several threads stop with
+    /// a crash signal at once, a thread that faulted is preferred over one that was sent the signal
     pub primary_thread: GdbThread,
-    /// Other process threads (not currently supported)
+    /// Other process threads, with fewer frames. Not collected with [TriageDetail::Brief]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub other_threads: Option<Vec<GdbThread>>,
+    /// The process memory mappings at the time of the stop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_map: Option<Vec<GdbMemoryMapping>>,
+    /// The modules loaded at the time of the stop. Not collected with [TriageDetail::Brief]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modules: Option<Vec<GdbModule>>,
+    /// Fields added by triage script extensions, keyed by extension name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Set when the stop happened in a forked child process followed with
+    /// [GdbTriageOptions::follow_forks]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forked_child: Option<bool>,
+    /// Set when the target ran longer than the timeout and was stopped by the hang watchdog
+    /// ([GdbTriageOptions::detect_hangs])
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hang: Option<bool>,
+    /// Set when the target was terminating due to an uncaught C++ exception
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exception: Option<GdbException>,
+    /// The fault handler that stopped a remote target ([GdbTriageOptions::remote])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fault_handler: Option<String>,
+}
+
+/// The uncaught C++ exception of a terminating target
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GdbException {
+    /// The demangled type of the exception, if it could be read from its `std::type_info`
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub exception_type: Option<String>,
+}
+
+/// A frame of the backtrace captured at the oracle breakpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GdbOracleFrame {
+    /// A target-native address
+    pub address: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<i64>,
 }
 
 /// The result code from GDBTriage
@@ -265,6 +369,36 @@ pub struct GdbJsonResult {
     pub result: GdbResultCode,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub context: Option<GdbContextInfo>,
+    /// How many times the target forked before it stopped running. Only counted when forks are not
+    /// followed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forks: Option<u64>,
+    /// The target's exit code, if it exited normally
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// The signal that terminated the target, if it was one GDB does not stop on (e.g. SIGKILL)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_signal: Option<String>,
+    /// The backtrace at the first hit of [GdbTriageOptions::oracle_breakpoint], if it was hit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oracle_backtrace: Option<Vec<GdbOracleFrame>>,
+    /// Problems that did not stop triage, e.g. a variable that could not be read, and messages GDB
+    /// printed while triaging. The rest of the result is still usable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// What GDBTriage emits instead of a [GdbJsonResult] when it fails. Its `result` is `ERROR_SCRIPT`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GdbScriptError {
+    /// The exception that stopped the triage script
+    pub error: String,
+    /// The python traceback of the exception
+    #[serde(default)]
+    pub traceback: Vec<String>,
+    /// Problems before the failure
+    #[serde(default)]
+    pub warnings: Vec<String>,
 }
 
 /// The top-level AFLTriage structure
@@ -275,10 +409,28 @@ pub struct GdbTriageResult {
 }
 
 /// The target's output strings
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct GdbChildOutput {
     pub stdout: String,
     pub stderr: String,
+    /// The exact bytes of stdout, if they are not UTF-8 and some were replaced in `stdout`.
+    /// Serialized as hex
+    #[serde(default, rename = "stdout_hex", with = "crate::util::hex_bytes", skip_serializing_if = "Option::is_none")]
+    pub stdout_raw: Option<Vec<u8>>,
+    /// The exact bytes of stderr, if they are not UTF-8 and some were replaced in `stderr`.
+    /// Serialized as hex
+    #[serde(default, rename = "stderr_hex", with = "crate::util::hex_bytes", skip_serializing_if = "Option::is_none")]
+    pub stderr_raw: Option<Vec<u8>>,
+}
+
+impl GdbChildOutput {
+    /// The target's output from its exact bytes
+    pub fn from_bytes(stdout: Vec<u8>, stderr: Vec<u8>) -> GdbChildOutput {
+        let (stdout, stdout_raw) = decode_output(stdout);
+        let (stderr, stderr_raw) = decode_output(stderr);
+
+        GdbChildOutput { stdout, stderr, stdout_raw, stderr_raw }
+    }
 }
 
 /// What type of GDBTriage error occurred
@@ -287,6 +439,31 @@ pub enum GdbTriageErrorKind {
     Command,
     Internal,
     Timeout,
+    /// The connection to a remote target failed or was lost
+    Remote,
+}
+
+/// The unparsed output of a GDB run, for debugging triage itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawGdbOutput {
+    pub program_cmdline: String,
+    pub gdb_cmdline: String,
+    /// The commands sent to a [GdbSession], if the testcase was run in one
+    pub session_commands: Option<String>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl RawGdbOutput {
+    pub fn format(&self) -> String {
+        let gdb = match &self.session_commands {
+            Some(commands) => format!("GDB SESSION CMDLINE: {}\nGDB COMMANDS:\n{}", self.gdb_cmdline, commands),
+            None => format!("GDB CMDLINE: {}", self.gdb_cmdline),
+        };
+
+        format!("--- RAW GDB BEGIN ---\nPROGRAM CMDLINE: {}\n{}\nSTDOUT:\n{}\nSTDERR:\n{}\n--- RAW GDB END ---",
+            self.program_cmdline, gdb, self.stdout, self.stderr)
+    }
 }
 
 /// Information on the GDBTriage error that occurred
@@ -365,6 +542,14 @@ struct DbgMarker {
 }
 
 impl DbgMarker {
+    /// Like [DbgMarker::extract], for output that is not UTF-8
+    fn extract_bytes<'a>(&self, data: &'a [u8]) -> Option<&'a [u8]> {
+        let find = |marker: &str| data.windows(marker.len()).position(|w| w == marker.as_bytes());
+        let start_idx = find(self.start)? + self.start.len() + 1;
+
+        data.get(start_idx..find(self.end)?)
+    }
+
     fn extract<'a>(&self, text: &'a str) -> Result<&'a str, String> {
         match text.find(&self.start) {
             Some(mut start_idx) => {
@@ -424,15 +609,168 @@ enum GdbTriageScript {
     Internal(tempfile::NamedTempFile),
 }
 
+/// Options controlling how GDB executes the target
+#[derive(Debug, Default, Clone)]
+pub struct GdbTriageOptions {
+    /// Environment variables to set for the target
+    pub target_env: Vec<(String, String)>,
+    /// Start the target with an empty environment. Variables in `target_env` are still set
+    pub clear_env: bool,
+    /// Python scripts loaded after the built-in triage script. These can register extensions to
+    /// add fields to the triage JSON (see `register_extension` in `res/GDBTriage.py`)
+    pub extra_scripts: Vec<PathBuf>,
+    /// Follow the child of a fork (and keep the parent) so targets that daemonize can be triaged.
+    /// Otherwise forks are only counted
+    pub follow_forks: bool,
+    /// A breakpoint location (e.g. a function) where a backtrace is captured without stopping the
+    /// target. Used to bucket oracle findings
+    pub oracle_breakpoint: Option<String>,
+    /// Stop a target that runs past the timeout and triage where it is stuck, instead of killing
+    /// GDB. The stop is reported as a hang (see [GdbContextInfo::hang])
+    pub detect_hangs: bool,
+    /// Run the target with address space layout randomization. GDB disables it by default so
+    /// addresses are the same between runs, which makes address-based bucketing reliable
+    pub aslr: bool,
+    /// Run GDB and the target isolated from the system (see [Sandbox])
+    pub sandbox: Option<Sandbox>,
+    /// The target's stdin when the testcase is not given on stdin
+    pub child_stdin: ChildStdin,
+    /// Keep a GDB process per thread and run every testcase in it, instead of starting GDB for
+    /// each testcase (see [GdbSession]). Ignored if other options need a fresh GDB for each run
+    pub reuse_gdb: bool,
+    /// Let GDB cache the symbol indexes it builds in this directory, which speeds up loading large
+    /// targets. Each thread claims its own `worker-N` subdirectory (see [claim_index_cache_dir]).
+    /// Disabled if `None`
+    pub index_cache: Option<PathBuf>,
+    /// Let GDB download missing debug info from the debuginfod servers in `DEBUGINFOD_URLS`
+    pub debuginfod: bool,
+    /// Name the frames GDB could not with this symbolizer after triage
+    pub external_symbolizer: Option<ExternalSymbolizer>,
+    /// Run testcases under Valgrind Memcheck at this path instead of GDB (see [valgrind])
+    pub valgrind: Option<PathBuf>,
+    /// Run Windows testcases under CDB at this path instead of GDB (see [crate::debugger::cdb])
+    pub cdb: Option<PathBuf>,
+    /// Run macOS testcases under LLDB at this path instead of GDB (see [crate::debugger::lldb])
+    pub lldb: Option<PathBuf>,
+    /// Directories LLDB searches for dSYM bundles, besides the one next to each program
+    pub dsym_dirs: Vec<PathBuf>,
+    /// Directories GDB searches for separate debug files, by build ID or by path, before its
+    /// default one
+    pub debug_file_dirs: Vec<PathBuf>,
+    /// The most bytes of the target's stdout and stderr kept for each run. The beginning and, most
+    /// of all, the end are kept. Unlimited if `None`
+    pub max_child_output: Option<usize>,
+    /// Only collect the backtrace of the faulting thread, and not of the other threads (see
+    /// [GdbContextInfo::other_threads]). For targets with many threads, walking all of them is slow
+    /// and makes large reports
+    pub crashing_thread_only: bool,
+    /// Run testcases on bare-metal firmware behind a remote GDB server, like OpenOCD, instead of
+    /// starting the target (see [crate::debugger::remote])
+    pub remote: Option<RemoteTarget>,
+    /// Run the target on an Android device under gdbserver, started over adb (see
+    /// [crate::debugger::android])
+    pub android: Option<AndroidDevice>,
+    /// Record the remote protocol exchange of each testcase run on a remote or Android target to a
+    /// new file in this directory (see [crate::debugger::remote])
+    pub remote_log_dir: Option<PathBuf>,
+    /// Where GDB reads the source files of a build done elsewhere from
+    pub source_path_map: SourcePathMap,
+    /// Format arguments and locals without GDB's pretty-printers, so C++ containers show their
+    /// internals. Faster, as no printers are loaded or run
+    pub raw_values: bool,
+}
+
+impl GdbTriageOptions {
+    /// The value of an environment variable for the target: the last one set in `target_env`, or
+    /// AFLTriage's own
+    pub fn target_var(&self, name: &str) -> Option<String> {
+        self.target_env.iter().rev()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.to_string())
+            .or_else(|| std::env::var(name).ok())
+    }
+}
+
+/// How much crash information GDBTriage collects
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriageDetail {
+    /// Everything used in reports
+    Full,
+    /// Only what bucketing needs: the stop info, memory map, and the frames of the primary thread
+    /// without their arguments, locals, or source. Registers, the faulting instruction, other
+    /// threads, and extensions are left out
+    Brief,
+}
+
+impl TriageDetail {
+    fn command(&self, crashing_thread_only: bool) -> &'static str {
+        match self {
+            TriageDetail::Full if crashing_thread_only => "gdbtriage crashing-thread",
+            TriageDetail::Full => "gdbtriage",
+            TriageDetail::Brief => "gdbtriage brief",
+        }
+    }
+}
+
+/// The debug file directory of most GDB builds, which distributions install debug packages to
+pub const DEFAULT_DEBUG_FILE_DIRECTORY: &str = "/usr/lib/debug";
+
+/// A session is restarted after this many runs, in case GDB leaks memory or state
+const SESSION_MAX_RUNS: usize = 500;
+
+thread_local! {
+    /// The GDB session of this thread, with [GdbTriageOptions::reuse_gdb]
+    static GDB_SESSION: RefCell<Option<GdbSession>> = const { RefCell::new(None) };
+    /// The base and claimed index cache directory of this thread, with the file that locks it
+    static INDEX_CACHE_DIR: RefCell<Option<(PathBuf, PathBuf, std::fs::File)>> = const { RefCell::new(None) };
+}
+
+/// How many `worker-N` index cache directories are tried before giving up
+const INDEX_CACHE_MAX_WORKERS: usize = 1024;
+
+/// Claim a `worker-N` subdirectory of `base` for one GDB at a time. GDB does not expect other
+/// processes to write to its index cache, so each worker, of this or another AFLTriage instance
+/// sharing `base`, gets the first directory that no one holds a lock on. The directory stays
+/// claimed until the returned file is closed and is reused by later runs
+fn claim_index_cache_dir(base: &Path) -> std::io::Result<(PathBuf, std::fs::File)> {
+    for n in 0..INDEX_CACHE_MAX_WORKERS {
+        let dir = base.join(format!("worker-{}", n));
+        std::fs::create_dir_all(&dir)?;
+
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join(".afltriage.lock"))?;
+
+        // flock locks belong to the open file, so threads of the same process exclude each other
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok((dir, lock));
+        }
+    }
+
+    Err(std::io::Error::other(format!("every index cache directory in {} is in use", base.display())))
+}
+
 /// Triage crashes using GDB
 pub struct GdbTriager {
     triage_script: GdbTriageScript,
+    fork_tracking_script: tempfile::NamedTempFile,
+    oracle_breakpoint_script: tempfile::NamedTempFile,
+    hang_watchdog_script: tempfile::NamedTempFile,
+    remote_fault_script: tempfile::NamedTempFile,
     pub gdb_path: String,
+    pub options: GdbTriageOptions,
 }
 
 impl GdbTriager {
-    /// Create a new [GdbTriager] using the built-in GDBTriage script
+    /// Create a new [GdbTriager] using the built-in GDBTriage script and default options
     pub fn new(gdb_path: String) -> GdbTriager {
+        GdbTriager::with_options(gdb_path, GdbTriageOptions::default())
+    }
+
+    /// Create a new [GdbTriager] using the built-in GDBTriage script
+    pub fn with_options(gdb_path: String, options: GdbTriageOptions) -> GdbTriager {
         let mut triage_script =
             GdbTriageScript::Internal(tempfile::Builder::new().suffix(".py").tempfile().unwrap());
 
@@ -442,9 +780,26 @@ impl GdbTriager {
             panic!("Unsupported script path");
         }
 
+        let mut fork_tracking_script = tempfile::Builder::new().suffix(".gdb").tempfile().unwrap();
+        fork_tracking_script.write_all(FORK_TRACKING_SCRIPT).unwrap();
+
+        let mut oracle_breakpoint_script = tempfile::Builder::new().suffix(".py").tempfile().unwrap();
+        oracle_breakpoint_script.write_all(ORACLE_BREAKPOINT_SCRIPT).unwrap();
+
+        let mut hang_watchdog_script = tempfile::Builder::new().suffix(".py").tempfile().unwrap();
+        hang_watchdog_script.write_all(HANG_WATCHDOG_SCRIPT).unwrap();
+
+        let mut remote_fault_script = tempfile::Builder::new().suffix(".py").tempfile().unwrap();
+        remote_fault_script.write_all(REMOTE_FAULT_SCRIPT).unwrap();
+
         GdbTriager {
             triage_script,
+            fork_tracking_script,
+            oracle_breakpoint_script,
+            hang_watchdog_script,
+            remote_fault_script,
             gdb_path,
+            options,
         }
     }
 
@@ -491,6 +846,383 @@ impl GdbTriager {
         true
     }
 
+    /// Confirm that the selected GDB was built with debuginfod support
+    pub fn has_debuginfod(&self) -> bool {
+        let gdb_args = vec!["--nx", "--batch", "-ex", "set debuginfod enabled on"];
+
+        match process::execute_capture_output(&self.gdb_path, &gdb_args) {
+            Ok(o) if o.status.success() && o.stderr.trim().is_empty() => true,
+            Ok(o) => {
+                log::error!("GDB does not support debuginfod: {}", o.stderr.trim());
+                false
+            }
+            Err(e) => {
+                log::error!("Failed to execute specified GDB '{}': {}", &self.gdb_path, e);
+                false
+            }
+        }
+    }
+
+    /// Download the debug info of the target and the libraries it loads from debuginfod, showing
+    /// GDB's download progress. The debuginfod client caches downloads, so triage runs find them
+    /// without downloading again. The target is stopped once its libraries are mapped, before any
+    /// of its code runs, which is why this is not sandboxed even though downloading needs the
+    /// network. Returns whether GDB succeeded
+    pub fn prefetch_debug_info(&self, program: &str) -> std::io::Result<bool> {
+        #[rustfmt::rustfmt_skip]
+        let gdb_args = [
+            vec_of_strings!("--nx", "--batch"),
+            self.debuginfod_args(),
+            self.setup_args(),
+            vec_of_strings!(
+                "-ex", "starti < /dev/null",
+                // The first event maps the libraries and the second is when they are consistent
+                "-ex", "set stop-on-solib-events 1",
+                "-ex", "continue",
+                "-ex", "continue",
+                "-ex", "kill",
+                "--args", program
+            ),
+        ].concat();
+
+        let status = std::process::Command::new(&self.gdb_path)
+            .args(&gdb_args)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .status()?;
+
+        Ok(status.success())
+    }
+
+    fn debuginfod_args(&self) -> Vec<String> {
+        if self.options.debuginfod {
+            vec_of_strings!("-iex", "set debuginfod enabled on")
+        } else {
+            vec![]
+        }
+    }
+
+    fn debug_file_args(&self) -> Vec<String> {
+        if self.options.debug_file_dirs.is_empty() {
+            return vec![];
+        }
+
+        // Setting the directories replaces the default, which has the debug files of system packages
+        let dirs: Vec<String> = self.options.debug_file_dirs.iter()
+            .map(|dir| dir.to_string_lossy().to_string())
+            .chain(std::iter::once(DEFAULT_DEBUG_FILE_DIRECTORY.to_string()))
+            .collect();
+
+        vec_of_strings!("-iex", format!("set debug-file-directory {}", dirs.join(":")))
+    }
+
+    fn source_path_args(&self) -> Vec<String> {
+        self.options.source_path_map.gdb_commands().into_iter()
+            .flat_map(|command| vec_of_strings!("-iex", command))
+            .collect()
+    }
+
+    fn pretty_printer_args(&self) -> Vec<String> {
+        // Read by GDBTriage when it formats values
+        if self.options.raw_values {
+            vec_of_strings!("-iex", "set $_afltriage_raw_values = 1")
+        } else {
+            vec![]
+        }
+    }
+
+    /// A new file in [GdbTriageOptions::remote_log_dir] for the remote protocol transcript of a
+    /// testcase, named after it, if transcripts are recorded
+    fn remote_transcript_path(&self, prog_args: &[String], input_file: Option<&str>) -> std::io::Result<Option<PathBuf>> {
+        let dir = match &self.options.remote_log_dir {
+            Some(dir) if self.options.remote.is_some() || self.options.android.is_some() => dir,
+            _ => return Ok(None),
+        };
+
+        let testcase = input_file.or_else(|| prog_args.last().map(String::as_str)).unwrap_or_default();
+        let name: String = Path::new(testcase).file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+            .collect();
+
+        let file = tempfile::Builder::new()
+            .prefix(&format!("{}.", name))
+            .suffix(".remote.log")
+            .tempfile_in(dir)?;
+
+        Ok(Some(file.keep().map_err(|e| e.error)?.1))
+    }
+
+    fn triage_script_path(&self) -> &Path {
+        if let GdbTriageScript::Internal(tf) = &self.triage_script {
+            tf.path()
+        } else {
+            panic!("Unsupported triage script path")
+        }
+    }
+
+    /// Whether testcases can be triaged in a reused [GdbSession]. Following forks, oracle
+    /// breakpoints, hang detection, and remote and Android targets keep state in GDB between runs. An inherited
+    /// stdin would be the session's commands and a fully sandboxed GDB cannot see new testcases in
+    /// `/tmp`
+    pub fn supports_sessions(&self) -> bool {
+        !self.options.follow_forks &&
+            self.options.remote.is_none() &&
+            self.options.android.is_none() &&
+            self.options.oracle_breakpoint.is_none() &&
+            !self.options.detect_hangs &&
+            self.options.child_stdin != ChildStdin::inherit &&
+            self.options.sandbox.as_ref().map(|s| s.level != SandboxLevel::full).unwrap_or(true)
+    }
+
+    /// Arguments that enable the index cache in this thread's directory, if any
+    fn index_cache_args(&self) -> Vec<String> {
+        let base = match &self.options.index_cache {
+            Some(base) => base,
+            None => return vec![],
+        };
+
+        let dir = INDEX_CACHE_DIR.with(|cell| {
+            let mut claimed = cell.borrow_mut();
+
+            if let Some((claimed_base, dir, _)) = claimed.as_ref() {
+                if claimed_base == base {
+                    return Some(dir.clone());
+                }
+            }
+
+            match claim_index_cache_dir(base) {
+                Ok((dir, lock)) => {
+                    *claimed = Some((base.clone(), dir.clone(), lock));
+                    Some(dir)
+                }
+                Err(e) => {
+                    log::warn!("Not using the GDB index cache: {}", e);
+                    None
+                }
+            }
+        });
+
+        match dir {
+            // The directory must be set before the cache is enabled, or GDB uses its default one
+            Some(dir) => vec_of_strings!(
+                "-iex", format!("set index-cache directory {}", dir.to_string_lossy()),
+                "-iex", "set index-cache on"
+            ),
+            None => vec![],
+        }
+    }
+
+    /// The command line of a [GdbSession] for the target `program`
+    fn session_command_line(&self, program: &str) -> (String, Vec<String>) {
+        let gdb_extra_script_args: Vec<String> = self.options.extra_scripts.iter()
+            .flat_map(|script| vec_of_strings!("-x", script.to_string_lossy()))
+            .collect();
+
+        #[rustfmt::rustfmt_skip]
+        let gdb_args = [
+            vec_of_strings!(
+                "--nx", "--quiet",
+                "-iex", "set print inferior-events off",
+                "-iex", "set python print-stack full",
+                // Commands come from a pipe, so there is nobody to answer
+                "-iex", "set pagination off",
+                "-iex", "set confirm off",
+                "-iex", "set width 0"
+            ),
+            self.index_cache_args(),
+            self.debuginfod_args(),
+            self.debug_file_args(),
+            self.source_path_args(),
+            self.pretty_printer_args(),
+            self.setup_args(),
+            vec_of_strings!("-x", self.triage_script_path().to_string_lossy()),
+            gdb_extra_script_args,
+            vec_of_strings!("--args", program),
+        ].concat();
+
+        self.gdb_command_line(&gdb_args, &[Path::new(program)])
+    }
+
+    /// The command line running GDB with `gdb_args`, in the sandbox if there is one. GDB runs the
+    /// target with `$SHELL`, so it is set to [TARGET_SHELL] for the arguments quoted by [sh_quote]
+    fn gdb_command_line(&self, gdb_args: &[String], exposed: &[&Path]) -> (String, Vec<String>) {
+        let mut args = vec![format!("SHELL={}", TARGET_SHELL), self.gdb_path.clone()];
+        args.extend(gdb_args.iter().cloned());
+
+        match &self.options.sandbox {
+            Some(sandbox) => sandbox.wrap("env", &args, exposed),
+            None => ("env".to_string(), args),
+        }
+    }
+
+    /// Triage a target program in this thread's [GdbSession], starting one if needed. Returns
+    /// `None` if the session failed for a reason other than the target, such as GDB exiting
+    fn triage_program_in_session(
+        &self,
+        prog_args: &[String],
+        input_file: Option<&str>,
+        raw_output: &mut Option<RawGdbOutput>,
+        timeout_ms: u64,
+        detail: TriageDetail,
+    ) -> Option<Result<GdbTriageResult, GdbTriageError>> {
+        let (program, args) = self.session_command_line(&prog_args[0]);
+        let command_line = [std::slice::from_ref(&program), &args[..]].concat();
+
+        #[rustfmt::rustfmt_skip]
+        let commands = vec_of_strings!(
+            MARKER_CHILD_OUTPUT.gdb_start,
+            "set logging file /dev/null",
+            "set logging redirect on",
+            "set logging on",
+            self.run_command(prog_args, input_file),
+            "set logging redirect off",
+            "set logging off",
+            MARKER_CHILD_OUTPUT.gdb_end,
+            MARKER_BACKTRACE.gdb_start,
+            detail.command(self.options.crashing_thread_only),
+            MARKER_BACKTRACE.gdb_end,
+            "kill",
+            "gdbtriage-reset"
+        );
+
+        GDB_SESSION.with(|cell| {
+            let mut session = cell.borrow_mut();
+
+            let reusable = session.as_ref()
+                .map(|s| s.command_line == command_line && s.runs < SESSION_MAX_RUNS)
+                .unwrap_or(false);
+
+            if !reusable {
+                // Drop the previous session first so two GDBs never run per thread
+                *session = None;
+
+                match GdbSession::spawn(&program, &args) {
+                    Ok(s) => *session = Some(s),
+                    Err(e) => {
+                        log::warn!("Failed to start a GDB session: {}", e);
+                        return None;
+                    }
+                }
+            }
+
+            let output = session.as_mut().unwrap().execute(&commands, timeout_ms, self.output_limit().as_ref());
+
+            let ((decoded_stdout, raw_stdout), (decoded_stderr, raw_stderr)) = match output {
+                Ok((stdout, stderr)) => (decode_output(stdout), decode_output(stderr)),
+                Err(e) => {
+                    *session = None;
+
+                    return if e.kind() == ErrorKind::TimedOut {
+                        Some(Err(GdbTriageError::new(
+                            GdbTriageErrorKind::Timeout,
+                            "Timed out when triaging",
+                            e.to_string(),
+                        )))
+                    } else {
+                        log::warn!("GDB session failed, restarting it: {}", e);
+                        None
+                    };
+                }
+            };
+
+            let result = parse_triage_output(&decoded_stdout, &decoded_stderr,
+                raw_stdout.as_deref(), raw_stderr.as_deref());
+
+            *raw_output = Some(RawGdbOutput {
+                program_cmdline: shell_join(prog_args),
+                gdb_cmdline: shell_join(&command_line),
+                session_commands: Some(commands.join("\n")),
+                stdout: decoded_stdout,
+                stderr: decoded_stderr,
+            });
+
+            Some(result)
+        })
+    }
+
+    /// How much of GDB's output is kept for each run: only the target's output is limited
+    fn output_limit(&self) -> Option<OutputLimit> {
+        self.options.max_child_output
+            .map(|max| OutputLimit::new(max).within(MARKER_CHILD_OUTPUT.start, MARKER_CHILD_OUTPUT.end))
+    }
+
+    /// The GDB command that runs the target
+    fn run_command(&self, prog_args: &[String], input_file: Option<&str>) -> String {
+        match input_file {
+            // GDB overwrites args in the format (damn you)
+            // Using this version of run uses the shell to run the command.
+            // Not ideal, but since we don't have a clean TTY for the target, this will have to do.
+            // Arguments are quoted for the shell, as GDB passes them to it as they are
+            Some(file) => format!("run {} < {}",
+                    sh_join(&prog_args[1..]),
+                    sh_quote(file)
+                ),
+            // Explicit arguments are needed for the same reason
+            None => match self.options.child_stdin {
+                ChildStdin::null => format!("run {} < /dev/null", sh_join(&prog_args[1..])),
+                ChildStdin::closed => format!("run {} <&-", sh_join(&prog_args[1..])),
+                ChildStdin::inherit => String::from("run"),
+            },
+        }
+    }
+
+    /// Settings applied before the target is run
+    fn setup_args(&self) -> Vec<String> {
+        // Bare-metal targets have no environment or processes
+        if self.options.remote.is_some() {
+            return vec_of_strings!("-x", self.remote_fault_script.path().to_string_lossy());
+        }
+
+        // The environment is set for gdbserver on the device
+        if let Some(device) = &self.options.android {
+            return device.gdb_setup_commands().into_iter()
+                .flat_map(|command| vec_of_strings!("-ex", command))
+                .collect();
+        }
+
+        let mut gdb_setup_args: Vec<String> = vec![];
+
+        // Explicit, in case a GDB init file changed the default
+        gdb_setup_args.extend(vec_of_strings!("-ex",
+            if self.options.aslr { "set disable-randomization off" } else { "set disable-randomization on" }));
+
+        // The target sees the SHELL of AFLTriage, not the one GDB is given to start it with
+        gdb_setup_args.extend(match std::env::var("SHELL") {
+            Ok(shell) => vec_of_strings!("-ex", format!("set environment SHELL={}", shell)),
+            Err(_) => vec_of_strings!("-ex", "unset environment SHELL"),
+        });
+
+        if self.options.clear_env {
+            gdb_setup_args.extend(vec_of_strings!("-ex", "unset environment"));
+        }
+
+        for (key, value) in &self.options.target_env {
+            gdb_setup_args.extend(vec_of_strings!("-ex", format!("set environment {}={}", key, value)));
+        }
+
+        if self.options.follow_forks {
+            gdb_setup_args.extend(vec_of_strings!(
+                "-ex", "set follow-fork-mode child",
+                "-ex", "set detach-on-fork off"
+            ));
+        } else {
+            gdb_setup_args.extend(vec_of_strings!("-x", self.fork_tracking_script.path().to_string_lossy()));
+        }
+
+        if let Some(location) = &self.options.oracle_breakpoint {
+            // A JSON string is also a valid python string literal
+            gdb_setup_args.extend(vec_of_strings!(
+                "-x", self.oracle_breakpoint_script.path().to_string_lossy(),
+                "-ex", format!("python afltriage_oracle_breakpoint({})", serde_json::to_string(location).unwrap())
+            ));
+        }
+
+        gdb_setup_args
+    }
+
     /// Execute a target program under GDB and execute GDBTriage to collect crash information, if
     /// any.
     ///
@@ -502,56 +1234,210 @@ impl GdbTriager {
         show_raw_output: bool,
         timeout_ms: u64,
     ) -> Result<GdbTriageResult, GdbTriageError> {
-        let triage_script_path = if let GdbTriageScript::Internal(tf) = &self.triage_script {
-            tf.path()
-        } else {
-            panic!("Unsupported triage script path")
+        self.triage_program_with_detail(prog_args, input_file, show_raw_output, timeout_ms, TriageDetail::Full)
+    }
+
+    /// Like [GdbTriager::triage_program], collecting the crash information of `detail`
+    pub fn triage_program_with_detail(
+        &self,
+        prog_args: &[String],
+        input_file: Option<&str>,
+        show_raw_output: bool,
+        timeout_ms: u64,
+        detail: TriageDetail,
+    ) -> Result<GdbTriageResult, GdbTriageError> {
+        let (result, raw_output) = self.triage_program_with_raw_output(prog_args, input_file, timeout_ms, detail);
+
+        if let (true, Some(raw_output)) = (show_raw_output, raw_output) {
+            println!("{}", raw_output.format());
+        }
+
+        result
+    }
+
+    /// Like [GdbTriager::triage_program_with_detail], also returning the raw output of GDB if it
+    /// ran to completion
+    pub fn triage_program_with_raw_output(
+        &self,
+        prog_args: &[String],
+        input_file: Option<&str>,
+        timeout_ms: u64,
+        detail: TriageDetail,
+    ) -> (Result<GdbTriageResult, GdbTriageError>, Option<RawGdbOutput>) {
+        if let Some(valgrind_path) = &self.options.valgrind {
+            return (valgrind::triage_program(valgrind_path, &self.options, prog_args, input_file, timeout_ms), None);
+        }
+
+        if let Some(cdb_path) = &self.options.cdb {
+            return (CdbTriager::new(cdb_path, &self.options).triage_program(prog_args, timeout_ms), None);
+        }
+
+        if let Some(lldb_path) = &self.options.lldb {
+            return (LldbTriager::new(lldb_path, &self.options).triage_program(prog_args, input_file, timeout_ms), None);
+        }
+
+        let mut result = None;
+        let mut raw_output = None;
+
+        if self.options.reuse_gdb && self.supports_sessions() {
+            result = self.triage_program_in_session(prog_args, input_file, &mut raw_output, timeout_ms, detail);
+        }
+
+        // Fall back to a fresh GDB if the session could not run the testcase
+        let mut result = match result {
+            Some(result) => result,
+            None => self.triage_program_batch(prog_args, input_file, &mut raw_output, timeout_ms, detail),
         };
 
-        let gdb_run_command = match input_file {
-            // GDB overwrites args in the format (damn you)
-            // Using this version of run uses the shell to run the command.
-            // Not ideal, but since we don't have a clean TTY for the target, this will have to do
-            Some(file) => format!("run {} < {}",
-                    shell_join(&prog_args[1..]),
-                    shlex::quote(file)
-                ),
-            None => String::from("run"),
+        if let (Ok(triage), Some(symbolizer)) = (result.as_mut(), &self.options.external_symbolizer) {
+            let symbolized = symbolizer.symbolize(triage);
+
+            if symbolized > 0 {
+                log::debug!("Symbolized {} frame(s) with {}", symbolized, symbolizer.path.display());
+            }
+        }
+
+        (result, raw_output)
+    }
+
+    /// Triage a target program in a new GDB process
+    fn triage_program_batch(
+        &self,
+        prog_args: &[String],
+        input_file: Option<&str>,
+        raw_output: &mut Option<RawGdbOutput>,
+        timeout_ms: u64,
+        detail: TriageDetail,
+    ) -> Result<GdbTriageResult, GdbTriageError> {
+        let triage_script_path = self.triage_script_path();
+        let gdb_run_args: Vec<String> = match &self.options.remote {
+            // The testcase is given by the target command's arguments after the firmware
+            Some(remote) => remote.run_commands(&prog_args[1..].join(" ")).into_iter()
+                .flat_map(|command| vec_of_strings!("-ex", command))
+                .collect(),
+            None => match &self.options.android {
+                Some(device) => vec_of_strings!("-ex", device.connect_command(), "-ex", "continue"),
+                None => vec_of_strings!("-ex", self.run_command(prog_args, input_file)),
+            },
+        };
+        let mut gdb_setup_args = self.setup_args();
+
+        let transcript = match self.remote_transcript_path(prog_args, input_file) {
+            Ok(transcript) => transcript,
+            Err(e) => return Err(GdbTriageError::new(
+                GdbTriageErrorKind::Command,
+                "Failed to create the remote protocol transcript",
+                e.to_string(),
+            )),
+        };
+
+        // Before GDB connects
+        if let Some(path) = &transcript {
+            gdb_setup_args.extend(vec_of_strings!("-ex", format!("set remotelogfile {}", path.display())));
+        }
+
+        // GDB itself still needs a timeout in case triage gets stuck after the watchdog fired
+        let gdb_timeout_ms = if self.options.detect_hangs {
+            gdb_setup_args.extend(vec_of_strings!(
+                "-x", self.hang_watchdog_script.path().to_string_lossy(),
+                "-ex", format!("python afltriage_hang_watchdog({})", timeout_ms)
+            ));
+
+            timeout_ms + HANG_TRIAGE_GRACE_MS
+        } else {
+            timeout_ms
         };
 
         // TODO: memory limit?
         #[rustfmt::rustfmt_skip]
-        let gdb_args = vec_of_strings!(
+        let gdb_args_head = vec_of_strings!(
             "--nx", "--batch",
-            // FIXME: index cache is a bit unreliable on earlier GDB versions
-            //"-iex", "set index-cache on",
-            //"-iex", "set index-cache directory gdb_cache",
-
             // Make special effort to get target output WITHOUT any GDB logging
             "-iex", "set print inferior-events off",
             // Get detailed python errors
-            "-iex", "set python print-stack full",
+            "-iex", "set python print-stack full"
+        );
+
+        #[rustfmt::rustfmt_skip]
+        let gdb_args_tail = [vec_of_strings!(
             // Markers will not print if logging is to /dev/null
             "-ex", MARKER_CHILD_OUTPUT.gdb_start,
             "-ex", "set logging file /dev/null",
             "-ex", "set logging redirect on",
-            "-ex", "set logging on",
-            "-ex", gdb_run_command,
+            "-ex", "set logging on"
+        ), gdb_run_args, vec_of_strings!(
             "-ex", "set logging redirect off",
             "-ex", "set logging off",
             "-ex", MARKER_CHILD_OUTPUT.gdb_end,
             "-ex", MARKER_BACKTRACE.gdb_start,
-            "-x", triage_script_path.to_str().unwrap(),
-            "-ex", "gdbtriage",
-            "-ex", MARKER_BACKTRACE.gdb_end,
-            "--args"
+            "-x", triage_script_path.to_str().unwrap()
+        )].concat();
+
+        let gdb_extra_script_args: Vec<String> = self.options.extra_scripts.iter()
+            .flat_map(|script| vec_of_strings!("-x", script.to_string_lossy()))
+            .collect();
+
+        #[rustfmt::rustfmt_skip]
+        let gdb_args_end = vec_of_strings!(
+            "-ex", detail.command(self.options.crashing_thread_only),
+            "-ex", MARKER_BACKTRACE.gdb_end
         );
 
-        let gdb_cmdline = &[&gdb_args[..], prog_args].concat();
+        // The target command of an Android device has the device's paths. GDB is given the
+        // unstripped copy of the target, if there is one
+        let gdb_program_args = match &self.options.android {
+            Some(device) => device.local_binary(&prog_args[0]).iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+            None => [&vec_of_strings!("--args")[..], prog_args].concat(),
+        };
+
+        let gdb_symbol_args = [self.index_cache_args(), self.debuginfod_args(), self.debug_file_args(),
+            self.source_path_args(), self.pretty_printer_args()].concat();
+
+        let gdb_cmdline = &[&gdb_args_head[..], &gdb_symbol_args[..], &gdb_setup_args[..], &gdb_args_tail[..],
+            &gdb_extra_script_args[..], &gdb_args_end[..], &gdb_program_args[..]].concat();
+
+        let mut exposed: Vec<&Path> = vec![
+            triage_script_path,
+            self.fork_tracking_script.path(),
+            self.oracle_breakpoint_script.path(),
+            self.hang_watchdog_script.path(),
+            self.remote_fault_script.path(),
+        ];
+        exposed.extend(self.options.extra_scripts.iter().map(PathBuf::as_path));
+        exposed.extend(self.options.debug_file_dirs.iter().map(PathBuf::as_path));
+        exposed.extend(input_file.map(Path::new));
+        exposed.extend(prog_args.iter().map(Path::new));
+
+        let (program, program_args) = self.gdb_command_line(gdb_cmdline, &exposed);
 
         // Never write to stdin for GDB as it can pass testcases to the target using "run < FILE"
+        let stdin = match self.options.child_stdin {
+            ChildStdin::inherit if input_file.is_none() => ProcessStdin::Inherit,
+            _ => ProcessStdin::Null,
+        };
+
+        let gdbserver = match &self.options.android {
+            Some(device) => match device.start_gdbserver(prog_args, input_file, &self.options.target_env) {
+                Ok(server) => Some((device, server)),
+                Err(e) => return Err(GdbTriageError::new(
+                    GdbTriageErrorKind::Command,
+                    "Failed to start gdbserver on the device",
+                    e.to_string(),
+                )),
+            },
+            None => None,
+        };
+
+        let output = process::execute_capture_limited_output_timeout(&program, &program_args, gdb_timeout_ms,
+            stdin, self.output_limit().as_ref());
+
+        // The target's output is relayed by adb, not GDB
+        let device_output = gdbserver.map(|(device, server)| server.finish(device));
+
         let output =
-            match process::execute_capture_output_timeout(&self.gdb_path, gdb_cmdline, timeout_ms, None) {
+            match output {
                 Ok(o) => o,
                 Err(e) => {
                     return if e.kind() == ErrorKind::TimedOut {
@@ -570,16 +1456,32 @@ impl GdbTriager {
                 }
             };
 
+        *raw_output = Some(RawGdbOutput {
+            program_cmdline: shell_join(prog_args),
+            gdb_cmdline: shell_join(&[std::slice::from_ref(&program), &program_args[..]].concat()),
+            session_commands: None,
+            stdout: output.stdout.to_string(),
+            stderr: output.stderr.to_string(),
+        });
+
         let decoded_stdout = &output.stdout;
         let decoded_stderr = &output.stderr;
 
-        if show_raw_output {
-            let gdb_cmd_fmt = shell_join(
-                &[std::slice::from_ref(&self.gdb_path), gdb_cmdline]
-                    .concat()
-            );
-            println!("--- RAW GDB BEGIN ---\nPROGRAM CMDLINE: {}\nGDB CMDLINE: {}\nSTDOUT:\n{}\nSTDERR:\n{}\n--- RAW GDB END ---",
-                shell_join(&prog_args[..]), gdb_cmd_fmt, decoded_stdout, decoded_stderr);
+        let remote_errors = if self.options.remote.is_some() || self.options.android.is_some() {
+            RemoteProtocolErrors::scan(decoded_stderr)
+        } else {
+            RemoteProtocolErrors::default()
+        };
+        let transcript_note = transcript.as_ref()
+            .map(|path| format!("Remote protocol transcript: {}", path.display()));
+
+        // Without a connection, the target did not run, or stopped in an unknown state
+        if !remote_errors.connection.is_empty() {
+            return Err(GdbTriageError::new_detailed(
+                GdbTriageErrorKind::Remote,
+                "Lost the connection to the remote target",
+                remote_errors.connection.into_iter().chain(transcript_note).collect(),
+            ));
         }
 
         if let Some(exit_code) = output.status.code() {
@@ -601,71 +1503,222 @@ impl GdbTriager {
             ));
         }
 
-        let child_output_stdout = match MARKER_CHILD_OUTPUT.extract(decoded_stdout) {
-            Ok(output) => output.to_string(),
-            Err(e) => {
-                return Err(GdbTriageError::new(
-                    GdbTriageErrorKind::Command,
-                    "Could not extract child STDOUT",
-                    e,
-                ))
-            }
-        };
+        let mut result = parse_triage_output(decoded_stdout, decoded_stderr, output.stdout_raw.as_deref(), output.stderr_raw.as_deref());
 
-        let child_output_stderr = match MARKER_CHILD_OUTPUT.extract(decoded_stderr) {
-            Ok(output) => output.to_string(),
-            Err(e) => {
-                return Err(GdbTriageError::new(
-                    GdbTriageErrorKind::Command,
-                    "Could not extract child STDERR",
-                    e,
-                ))
+        if let Ok(triage) = &mut result {
+            if !remote_errors.packets.is_empty() {
+                triage.response.warnings.extend(remote_errors.packets.iter().map(|e| format!("Remote protocol error: {}", e)));
+                triage.response.warnings.extend(transcript_note);
             }
-        };
+        }
 
-        let backtrace_output = match MARKER_BACKTRACE.extract(decoded_stdout) {
-            Ok(output) => output,
-            Err(e) => {
-                return Err(GdbTriageError::new(
-                    GdbTriageErrorKind::Command,
-                    "Failed to get triage JSON from GDB",
-                    e,
-                ))
-            }
-        };
+        match device_output {
+            Some((stdout, stderr)) => result.map(|triage| GdbTriageResult {
+                child: GdbChildOutput { stdout, stderr, stdout_raw: None, stderr_raw: None },
+                ..triage
+            }),
+            None => result,
+        }
+    }
+}
 
-        let backtrace_messages = match MARKER_BACKTRACE.extract(decoded_stderr) {
-            Ok(output) => output,
-            Err(e) => {
-                return Err(GdbTriageError::new(
-                    GdbTriageErrorKind::Command,
-                    "Failed to get triage errors from GDB",
-                    e,
-                ))
-            }
-        };
+/// Extract the target's output and the triage JSON from the output of GDB. The exact bytes of the
+/// output, `raw_stdout` and `raw_stderr`, are only given if they are not UTF-8
+fn parse_triage_output(
+    decoded_stdout: &str,
+    decoded_stderr: &str,
+    raw_stdout: Option<&[u8]>,
+    raw_stderr: Option<&[u8]>,
+) -> Result<GdbTriageResult, GdbTriageError> {
+    let child_output_stdout = match MARKER_CHILD_OUTPUT.extract(decoded_stdout) {
+        Ok(output) => output.to_string(),
+        Err(e) => {
+            return Err(GdbTriageError::new(
+                GdbTriageErrorKind::Command,
+                "Could not extract child STDOUT",
+                e,
+            ))
+        }
+    };
 
-        if backtrace_output.is_empty() && !backtrace_messages.is_empty() {
-            return Err(GdbTriageError::new_detailed(
+    let child_output_stderr = match MARKER_CHILD_OUTPUT.extract(decoded_stderr) {
+        Ok(output) => output.to_string(),
+        Err(e) => {
+            return Err(GdbTriageError::new(
                 GdbTriageErrorKind::Command,
-                "Triage script emitted errors",
-                backtrace_messages.lines().map(str::to_string).collect(),
-            ));
+                "Could not extract child STDERR",
+                e,
+            ))
         }
+    };
 
-        match serde_json::from_str(backtrace_output) {
-            Ok(json) => Ok(GdbTriageResult {
-                response: json,
-                child: GdbChildOutput {
-                    stdout: child_output_stdout,
-                    stderr: child_output_stderr,
-                },
-            }),
-            Err(e) => Err(GdbTriageError::new(
+    let backtrace_output = match MARKER_BACKTRACE.extract(decoded_stdout) {
+        Ok(output) => output,
+        Err(e) => {
+            return Err(GdbTriageError::new(
                 GdbTriageErrorKind::Command,
-                "Failed to parse triage JSON from GDB",
-                e.to_string(),
-            )),
+                "Failed to get triage JSON from GDB",
+                e,
+            ))
+        }
+    };
+
+    let backtrace_messages = match MARKER_BACKTRACE.extract(decoded_stderr) {
+        Ok(output) => output,
+        Err(e) => {
+            return Err(GdbTriageError::new(
+                GdbTriageErrorKind::Command,
+                "Failed to get triage errors from GDB",
+                e,
+            ))
         }
+    };
+
+    if backtrace_output.is_empty() && !backtrace_messages.is_empty() {
+        return Err(GdbTriageError::new_detailed(
+            GdbTriageErrorKind::Command,
+            "Triage script emitted errors",
+            backtrace_messages.lines().map(str::to_string).collect(),
+        ));
+    }
+
+    let json: serde_json::Value = match serde_json::from_str(backtrace_output) {
+        Ok(json) => json,
+        Err(e) => return Err(GdbTriageError::new(
+            GdbTriageErrorKind::Command,
+            "Failed to parse triage JSON from GDB",
+            e.to_string(),
+        )),
+    };
+
+    if json["result"] == "ERROR_SCRIPT" {
+        return Err(match serde_json::from_value::<GdbScriptError>(json) {
+            Ok(error) => GdbTriageError::new_detailed(
+                GdbTriageErrorKind::Command,
+                format!("Triage script failed: {}", error.error),
+                error.traceback.into_iter().chain(error.warnings).collect(),
+            ),
+            Err(e) => GdbTriageError::new(
+                GdbTriageErrorKind::Command,
+                "Failed to parse triage script error from GDB",
+                e.to_string(),
+            ),
+        });
+    }
+
+    match serde_json::from_value::<GdbJsonResult>(json) {
+        Ok(mut response) => Ok(GdbTriageResult {
+            response: {
+                // Anything GDB printed does not stop triage once there is a result
+                response.warnings.extend(backtrace_messages.lines()
+                    .map(str::trim).filter(|l| !l.is_empty()).map(str::to_string));
+                response
+            },
+            child: GdbChildOutput {
+                stdout: child_output_stdout,
+                stderr: child_output_stderr,
+                // GDB's output may not be UTF-8 while the target's is
+                stdout_raw: raw_stdout.and_then(|raw| MARKER_CHILD_OUTPUT.extract_bytes(raw))
+                    .filter(|raw| std::str::from_utf8(raw).is_err()).map(<[u8]>::to_vec),
+                stderr_raw: raw_stderr.and_then(|raw| MARKER_CHILD_OUTPUT.extract_bytes(raw))
+                    .filter(|raw| std::str::from_utf8(raw).is_err()).map(<[u8]>::to_vec),
+            },
+        }),
+        Err(e) => Err(GdbTriageError::new(
+            GdbTriageErrorKind::Command,
+            "Failed to parse triage JSON from GDB",
+            e.to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_claim_index_cache_dir() {
+        let base = tempfile::tempdir().unwrap();
+
+        let (first, first_lock) = claim_index_cache_dir(base.path()).unwrap();
+        let (second, _second_lock) = claim_index_cache_dir(base.path()).unwrap();
+        assert_eq!(first, base.path().join("worker-0"));
+        assert_eq!(second, base.path().join("worker-1"));
+
+        // A released directory is reused
+        drop(first_lock);
+        assert_eq!(claim_index_cache_dir(base.path()).unwrap().0, first);
+    }
+
+    #[test]
+    fn test_parse_binary_child_output() {
+        let stdout = [
+            b"----AFLTRIAGE_CHILD_OUTPUT_START----\nbinary \xff\x00 output\n----AFLTRIAGE_CHILD_OUTPUT_END----\n".as_ref(),
+            b"----AFLTRIAGE_BACKTRACE_START----\n{\"result\": \"ERROR_TARGET_NOT_RUNNING\", \"exit_code\": 0}\n".as_ref(),
+            b"----AFLTRIAGE_BACKTRACE_END----\n".as_ref(),
+        ].concat();
+        let stderr = b"----AFLTRIAGE_CHILD_OUTPUT_START----\ntext\n----AFLTRIAGE_CHILD_OUTPUT_END----\n\
+            ----AFLTRIAGE_BACKTRACE_START----\n----AFLTRIAGE_BACKTRACE_END----\n".to_vec();
+
+        let (decoded_stdout, raw_stdout) = decode_output(stdout);
+        let (decoded_stderr, raw_stderr) = decode_output(stderr);
+        let result = parse_triage_output(&decoded_stdout, &decoded_stderr, raw_stdout.as_deref(), raw_stderr.as_deref()).unwrap();
+
+        assert_eq!(result.child.stdout, "binary \u{FFFD}\u{0} output\n");
+        assert_eq!(result.child.stdout_raw.as_deref(), Some(b"binary \xff\x00 output\n".as_ref()));
+        assert_eq!((result.child.stderr.as_str(), result.child.stderr_raw.as_ref()), ("text\n", None));
+
+        let json = serde_json::to_value(&result.child).unwrap();
+        assert_eq!(json["stdout_hex"], "62696e61727920ff00206f75747075740a");
+        assert!(json.get("stderr_hex").is_none());
+    }
+    #[test]
+    fn test_parse_triage_warnings() {
+        let output = |json: &str, messages: &str| (
+            format!("----AFLTRIAGE_CHILD_OUTPUT_START----\n----AFLTRIAGE_CHILD_OUTPUT_END----\n\
+                ----AFLTRIAGE_BACKTRACE_START----\n{}----AFLTRIAGE_BACKTRACE_END----\n",
+                if json.is_empty() { "".to_string() } else { format!("{}\n", json) }),
+            format!("----AFLTRIAGE_CHILD_OUTPUT_START----\n----AFLTRIAGE_CHILD_OUTPUT_END----\n\
+                ----AFLTRIAGE_BACKTRACE_START----\n{}----AFLTRIAGE_BACKTRACE_END----\n", messages),
+        );
+
+        // A partial result is kept with its warnings and whatever GDB printed
+        let (stdout, stderr) = output(
+            r#"{"result": "ERROR_TARGET_NOT_RUNNING", "warnings": ["Could not read local buf in frame #3: Cannot access memory"]}"#,
+            "warning: Error disabling address space randomization: Operation not permitted\n");
+        let result = parse_triage_output(&stdout, &stderr, None, None).unwrap();
+        assert_eq!(result.response.warnings, vec![
+            "Could not read local buf in frame #3: Cannot access memory",
+            "warning: Error disabling address space randomization: Operation not permitted",
+        ]);
+
+        let (stdout, stderr) = output(
+            r#"{"result": "ERROR_SCRIPT", "error": "KeyError: 'rip'", "traceback": ["Traceback (most recent call last):"], "warnings": []}"#, "");
+        let error = parse_triage_output(&stdout, &stderr, None, None).unwrap_err();
+        assert_eq!(error.error, "Triage script failed: KeyError: 'rip'");
+        assert_eq!(error.details, vec!["Traceback (most recent call last):"]);
+
+        let (stdout, stderr) = output("", "Python Exception <class 'gdb.error'>: No symbol table is loaded.\n");
+        assert_eq!(parse_triage_output(&stdout, &stderr, None, None).unwrap_err().error, "Triage script emitted errors");
+    }
+
+    #[test]
+    fn test_memory_mapping_pattern() {
+        // The pattern GDBTriage parses `info proc mappings` lines with, which are stripped first
+        let script = String::from_utf8_lossy(INTERNAL_TRIAGE_SCRIPT);
+        let pattern = script.lines()
+            .find_map(|line| line.strip_prefix("r_MAPPINGS = re.compile(r\"")?.strip_suffix("\")"))
+            .unwrap();
+        let mappings = regex::Regex::new(pattern).unwrap();
+        let parse = |line: &str| mappings.captures(line)
+            .map(|c| (c.get(5).map(|m| m.as_str().to_string()), c[6].trim().to_string()))
+            .unwrap();
+
+        assert_eq!(parse("0x555555554000     0x555555555000     0x1000        0x0  r--p   /tmp/test"),
+            (Some("r--p".into()), "/tmp/test".into()));
+        // GDB 12 and above list unnamed mappings with only their permissions
+        assert_eq!(parse("0x7ffff7d8e000     0x7ffff7d9b000     0xd000        0x0  rw-p"), (Some("rw-p".into()), "".into()));
+        // Older GDB has no permissions column
+        assert_eq!(parse("0x555555554000     0x555555555000     0x1000        0x0 /tmp/test"), (None, "/tmp/test".into()));
     }
 }
@@ -0,0 +1,412 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Triage of macOS targets under LLDB instead of GDB.
+//!
+//! GDB cannot debug programs on current versions of macOS, so with [GdbTriageOptions::lldb] every
+//! testcase is run under LLDB in batch mode. The target's output is redirected to files, and only
+//! if the target crashes, LLDB runs [LldbTriager::crash_commands] to print the stopped thread, its
+//! stack, registers, and loaded images between markers. This output is parsed into the same
+//! [GdbTriageResult] GDB gives, with the Mach exception that stopped the target converted to a
+//! signal (see [crate::platform::macos]).
+//!
+//! LLDB finds the dSYM bundle next to a program or with Spotlight. Bundles kept elsewhere are found
+//! in [GdbTriageOptions::dsym_dirs]. The UUID of each image is used as its build ID.
+use crate::debugger::gdb::*;
+use crate::input::ChildStdin;
+use crate::platform::macos::mach_exception_stop_info;
+use crate::process::{self, LimitedOutput, OutputLimit, ProcessStdin};
+use crate::util::decode_output;
+use regex::Regex;
+use std::path::Path;
+use std::rc::Rc;
+
+const THREAD_MARKER: &str = "----AFLTRIAGE-THREAD----";
+const STACK_MARKER: &str = "----AFLTRIAGE-STACK----";
+const REGISTERS_MARKER: &str = "----AFLTRIAGE-REGISTERS----";
+const IMAGES_MARKER: &str = "----AFLTRIAGE-IMAGES----";
+const END_MARKER: &str = "----AFLTRIAGE-END----";
+
+/// Frames are printed with their fields separated, as `pc|module|function|file|line`
+const FRAME_PREFIX: &str = "AFLTRIAGE-FRAME|";
+const FRAME_FORMAT: &str = "AFLTRIAGE-FRAME|${frame.pc}|{${module.file.fullpath}}|{${function.name-without-args}}|\
+    {${line.file.fullpath}}|{${line.number}}\\n";
+
+lazy_static! {
+    static ref R_THREAD: Regex = Regex::new(r"^\s*\*? ?thread #[0-9]+: tid = (0x[0-9a-f]+|[0-9]+),.*, stop reason = (?P<reason>.+)$").unwrap();
+    static ref R_REGISTER: Regex = Regex::new(r"^\s*([a-z][a-z0-9]*) = 0x([0-9a-f]+)").unwrap();
+    /// `image list -u -h -f` images: the UUID, load address, and path
+    static ref R_IMAGE: Regex = Regex::new(
+        r"^\[\s*[0-9]+\] (?:(?P<uuid>[0-9A-F]{8}-[0-9A-F-]{27}) )?(?P<addr>0x[0-9a-f]+) (?P<path>.+?)\s*$").unwrap();
+    static ref R_EXITED: Regex = Regex::new(r"(?m)^Process [0-9]+ exited with status = (-?[0-9]+)").unwrap();
+    static ref R_LAUNCH_ERROR: Regex = Regex::new(
+        r"(?m)^error: (process launch failed|process exited with status -1|attach failed)(?P<msg>.*)$").unwrap();
+}
+
+/// A frame of an LLDB stack
+#[derive(Debug, Default, PartialEq)]
+struct LldbFrame {
+    pc: u64,
+    module: Option<String>,
+    function: Option<String>,
+    file: Option<String>,
+    line: Option<i64>,
+}
+
+/// An image loaded in the target
+#[derive(Debug, PartialEq)]
+struct LldbImage {
+    path: String,
+    load_address: u64,
+    uuid: Option<String>,
+}
+
+/// What LLDB printed after the target crashed
+#[derive(Debug, Default)]
+struct LldbOutput {
+    tid: i32,
+    stop_reason: Option<String>,
+    stack: Vec<LldbFrame>,
+    registers: Vec<(String, u64)>,
+    images: Vec<LldbImage>,
+    /// The target's exit status, if it exited without crashing
+    exit_code: Option<i32>,
+}
+
+/// The lines of `output` between `start` and the next marker
+fn section<'a>(output: &'a str, start: &str) -> Vec<&'a str> {
+    output.lines()
+        .skip_while(|l| *l != start)
+        .skip(1)
+        .take_while(|l| !l.starts_with("----AFLTRIAGE-"))
+        .collect()
+}
+
+fn parse_hex(value: &str) -> Option<u64> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+fn parse_frame(line: &str) -> Option<LldbFrame> {
+    let fields = &line[line.find(FRAME_PREFIX)? + FRAME_PREFIX.len()..];
+    let mut left = fields.splitn(3, '|');
+    let (pc, module, rest) = (left.next()?, left.next()?, left.next()?);
+    // Function names can have a '|', like C++ operators
+    let mut right = rest.rsplitn(3, '|');
+    let (line, file, function) = (right.next()?, right.next()?, right.next()?);
+    let non_empty = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+
+    Some(LldbFrame {
+        pc: parse_hex(pc)?,
+        module: non_empty(module),
+        function: non_empty(function),
+        file: non_empty(file),
+        line: line.parse().ok(),
+    })
+}
+
+/// Parse what LLDB printed for [LldbTriager::crash_commands]
+fn parse_lldb_output(output: &str) -> LldbOutput {
+    let thread = section(output, THREAD_MARKER).iter().find_map(|l| R_THREAD.captures(l));
+
+    LldbOutput {
+        tid: thread.as_ref()
+            .and_then(|t| {
+                let tid = t.get(1).unwrap().as_str();
+                match tid.strip_prefix("0x") {
+                    Some(hex) => i32::from_str_radix(hex, 16).ok(),
+                    None => tid.parse().ok(),
+                }
+            })
+            .unwrap_or(0),
+        stop_reason: thread.map(|t| t["reason"].trim().to_string()),
+        stack: section(output, STACK_MARKER).iter().filter_map(|l| parse_frame(l)).collect(),
+        registers: section(output, REGISTERS_MARKER).iter()
+            .filter_map(|l| R_REGISTER.captures(l))
+            .filter_map(|c| Some((c[1].to_string(), parse_hex(&c[2])?)))
+            .collect(),
+        images: section(output, IMAGES_MARKER).iter()
+            .filter_map(|l| R_IMAGE.captures(l))
+            .filter_map(|c| Some(LldbImage {
+                path: c["path"].to_string(),
+                load_address: parse_hex(&c["addr"])?,
+                uuid: c.name("uuid").map(|u| u.as_str().replace('-', "").to_lowercase()),
+            }))
+            .collect(),
+        exit_code: R_EXITED.captures(output).and_then(|c| c[1].parse().ok()),
+    }
+}
+
+/// The address size and GDB architecture name of the target, from its registers
+fn architecture(registers: &[(String, u64)]) -> (usize, &'static str) {
+    let has = |name: &str| registers.iter().any(|(r, _)| r == name);
+
+    if has("rip") {
+        (64, "i386:x86-64")
+    } else if has("x0") {
+        (64, "aarch64")
+    } else {
+        (std::mem::size_of::<usize>() * 8, std::env::consts::ARCH)
+    }
+}
+
+fn frame_info(frame: &LldbFrame, images: &[LldbImage]) -> GdbFrameInfo {
+    let symbol = frame.function.as_ref().map(|function| Rc::new(GdbSymbol {
+        function_name: Some(function.to_string()),
+        file: frame.file.clone(),
+        line: frame.line,
+        ..Default::default()
+    }));
+
+    let image = frame.module.as_ref()
+        .and_then(|m| images.iter().find(|i| &i.path == m))
+        .filter(|i| frame.pc >= i.load_address);
+
+    match image {
+        Some(image) => GdbFrameInfo {
+            address: frame.pc,
+            relative_address: frame.pc - image.load_address,
+            module: image.path.to_string(),
+            module_address: format!("{}+0x{:x}", image.path, frame.pc - image.load_address),
+            symbol,
+        },
+        None => GdbFrameInfo {
+            address: frame.pc,
+            relative_address: frame.pc,
+            module: frame.module.clone().unwrap_or_else(|| "??".into()),
+            module_address: format!("0x{:x}", frame.pc),
+            symbol,
+        },
+    }
+}
+
+/// Convert LLDB's output to a triage result. A run without a stop reason did not crash
+fn lldb_triage_result(lldb: LldbOutput, child: GdbChildOutput) -> GdbTriageResult {
+    let stop_info = match lldb.stop_reason.as_deref().and_then(mach_exception_stop_info) {
+        Some(stop_info) => stop_info,
+        None => return GdbTriageResult {
+            response: GdbJsonResult {
+                result: GdbResultCode::ERROR_TARGET_NOT_RUNNING,
+                context: None,
+                forks: None,
+                exit_code: lldb.exit_code,
+                exit_signal: None,
+                oracle_backtrace: None,
+                warnings: vec![],
+            },
+            child,
+        },
+    };
+
+    let (address_bits, architecture) = architecture(&lldb.registers);
+
+    let registers = lldb.registers.iter()
+        .map(|(name, value)| Rc::new(GdbRegister {
+            name: name.to_string(),
+            value: *value,
+            pretty_value: format!("0x{:x}", value),
+            r#type: "int".into(),
+            size: (address_bits / 8) as u64,
+        }))
+        .collect();
+
+    GdbTriageResult {
+        response: GdbJsonResult {
+            result: GdbResultCode::SUCCESS,
+            context: Some(GdbContextInfo {
+                stop_info,
+                arch_info: GdbArchInfo {
+                    address_bits,
+                    architecture: architecture.into(),
+                },
+                primary_thread: GdbThread {
+                    tid: lldb.tid,
+                    name: None,
+                    backtrace: lldb.stack.iter().map(|fr| frame_info(fr, &lldb.images)).collect(),
+                    current_instruction: None,
+                    registers: Some(registers),
+                    unwind_error: None,
+                },
+                other_threads: None,
+                memory_map: None,
+                modules: Some(lldb.images.iter()
+                    .map(|i| GdbModule {
+                        path: i.path.to_string(),
+                        load_address: Some(i.load_address),
+                        end_address: None,
+                        build_id: i.uuid.clone(),
+                    })
+                    .collect()),
+                extra: None,
+                forked_child: None,
+                hang: None,
+                exception: None,
+                fault_handler: None,
+            }),
+            forks: None,
+            exit_code: None,
+            exit_signal: None,
+            oracle_backtrace: None,
+            warnings: vec![],
+        },
+        child,
+    }
+}
+
+/// Quote an argument of an LLDB command
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Run macOS programs under LLDB until they crash
+pub struct LldbTriager<'a> {
+    lldb: &'a Path,
+    options: &'a GdbTriageOptions,
+}
+
+impl<'a> LldbTriager<'a> {
+    pub fn new(lldb: &'a Path, options: &'a GdbTriageOptions) -> LldbTriager<'a> {
+        LldbTriager { lldb, options }
+    }
+
+    /// The commands that configure the target and launch it, with its stdin from `stdin` and its
+    /// output to `stdout` and `stderr`. The shell is not used to start it, so DYLD_* variables are
+    /// not removed by SIP
+    fn launch_commands(&self, stdin: &str, stdout: &Path, stderr: &Path) -> Vec<String> {
+        let mut commands = vec![
+            format!("settings set target.disable-aslr {}", !self.options.aslr),
+            format!("settings set target.inherit-env {}", !self.options.clear_env),
+            format!("settings set frame-format {}", quote(FRAME_FORMAT)),
+        ];
+
+        commands.extend(self.options.target_env.iter()
+            .map(|(k, v)| format!("settings append target.env-vars {}", quote(&format!("{}={}", k, v)))));
+        commands.extend(self.options.dsym_dirs.iter()
+            .map(|dir| format!("settings append target.debug-file-search-paths {}", quote(&dir.to_string_lossy()))));
+        commands.push(format!("process launch --shell-expand-args false -i {} -o {} -e {}",
+            quote(stdin), quote(&stdout.to_string_lossy()), quote(&stderr.to_string_lossy())));
+
+        commands
+    }
+
+    /// The commands run when the target crashes. The most frames are the same as GDBTriage
+    fn crash_commands() -> Vec<String> {
+        vec![
+            format!("script print('{}')", THREAD_MARKER),
+            "thread info".into(),
+            format!("script print('{}')", STACK_MARKER),
+            "bt 100".into(),
+            format!("script print('{}')", REGISTERS_MARKER),
+            "register read".into(),
+            format!("script print('{}')", IMAGES_MARKER),
+            "image list -u -h -f".into(),
+            format!("script print('{}')", END_MARKER),
+        ]
+    }
+
+    /// Run a program under LLDB until it crashes or exits. The testcase is given on stdin if
+    /// `input_file` is set
+    pub fn triage_program(&self, prog_args: &[String], input_file: Option<&str>, timeout_ms: u64)
+        -> Result<GdbTriageResult, GdbTriageError> {
+        let temp_file = || tempfile::NamedTempFile::new()
+            .map_err(|e| GdbTriageError::new_brief(GdbTriageErrorKind::Internal,
+                format!("Failed to create the target's output file: {}", e)));
+        let (stdout_file, stderr_file) = (temp_file()?, temp_file()?);
+
+        // LLDB cannot close the target's stdin, and only inherits its own from a terminal
+        let stdin = match (input_file, self.options.child_stdin) {
+            (Some(path), _) => path,
+            (None, ChildStdin::inherit) => "/dev/stdin",
+            (None, _) => "/dev/null",
+        };
+
+        let mut args: Vec<String> = vec!["--batch".into(), "--no-lldbinit".into()];
+
+        for command in self.launch_commands(stdin, stdout_file.path(), stderr_file.path()) {
+            args.extend(vec!["-o".into(), command]);
+        }
+
+        for command in LldbTriager::crash_commands() {
+            args.extend(vec!["-k".into(), command]);
+        }
+
+        args.push("--".into());
+        args.extend(prog_args.iter().cloned());
+
+        let output = process::execute_capture_limited_output_timeout(&self.lldb.to_string_lossy(), &args,
+            timeout_ms, ProcessStdin::Null, None)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::TimedOut => GdbTriageError::new_brief(GdbTriageErrorKind::Timeout, e.to_string()),
+                _ => GdbTriageError::new_brief(GdbTriageErrorKind::Command, format!("Failed to run LLDB: {}", e)),
+            })?;
+
+        if let Some(error) = R_LAUNCH_ERROR.captures(&output.stdout).or_else(|| R_LAUNCH_ERROR.captures(&output.stderr)) {
+            return Err(GdbTriageError::new_detailed(GdbTriageErrorKind::Command,
+                format!("LLDB could not start the target ({}{})", &error[1], &error["msg"]),
+                vec![output.stdout, output.stderr]));
+        }
+
+        let limit = self.options.max_child_output.map(OutputLimit::new);
+        let read = |file: &tempfile::NamedTempFile| {
+            let mut output = LimitedOutput::new(limit.clone());
+            output.push(&std::fs::read(file.path()).unwrap_or_default());
+            output.finish()
+        };
+        let (stdout, stdout_raw) = decode_output(read(&stdout_file));
+        let (stderr, stderr_raw) = decode_output(read(&stderr_file));
+        let child = GdbChildOutput { stdout, stderr, stdout_raw, stderr_raw };
+
+        Ok(lldb_triage_result(parse_lldb_output(&output.stdout), child))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_lldb_output() {
+        let mut path = PathBuf::from(file!());
+        path.pop();
+        path.push("res");
+        path.push("test_lldb");
+        path.push("bad_access.txt");
+
+        let output = std::fs::read_to_string(path).unwrap();
+        let lldb = parse_lldb_output(&output);
+
+        assert_eq!(lldb.tid, 0x1c4c26);
+        assert_eq!(lldb.stop_reason.as_deref(), Some("EXC_BAD_ACCESS (code=1, address=0x10)"));
+        assert_eq!(lldb.stack.len(), 3);
+        assert_eq!(lldb.stack[0], LldbFrame {
+            pc: 0x100003f50,
+            module: Some("/Users/fuzz/target".into()),
+            function: Some("parse_header".into()),
+            file: Some("/Users/fuzz/src/parse.c".into()),
+            line: Some(12),
+        });
+        assert_eq!((lldb.stack[2].function.as_deref(), lldb.stack[2].file.as_deref()), (Some("start"), None));
+        assert!(lldb.registers.contains(&("pc".into(), 0x100003f50)));
+        assert_eq!(lldb.images[0], LldbImage {
+            path: "/Users/fuzz/target".into(),
+            load_address: 0x100000000,
+            uuid: Some("1a2b3c4d11112222333344445555666a".into()),
+        });
+        assert_eq!(lldb.exit_code, None);
+
+        let triage = lldb_triage_result(lldb, GdbChildOutput::default());
+        let ctx = triage.response.context.as_ref().unwrap();
+        assert_eq!((ctx.stop_info.signal_name.as_str(), ctx.stop_info.faulting_address), ("SIGSEGV", Some(0x10)));
+        assert_eq!(ctx.arch_info.architecture, "aarch64");
+        assert_eq!(ctx.primary_thread.backtrace[0].module_address, "/Users/fuzz/target+0x3f50");
+
+        let opt = crate::report::ReportOptions { child_output_lines: 0, ..Default::default() };
+        let einfo = crate::report::enriched::enrich_triage_info(&opt, &triage).unwrap();
+        assert!(einfo.summary.contains("parse_header"));
+
+        let exited = parse_lldb_output("(lldb) process launch\nProcess 4242 exited with status = 3 (0x00000003)\n");
+        let clean = lldb_triage_result(exited, GdbChildOutput::default());
+        assert!(matches!(clean.response.result, GdbResultCode::ERROR_TARGET_NOT_RUNNING));
+        assert_eq!(clean.response.exit_code, Some(3));
+    }
+}
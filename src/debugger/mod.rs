@@ -2,4 +2,10 @@
 //
 // SPDX-License-Identifier: BSD-3-Clause
 //! Debugging backend specific handling
+pub mod android;
+pub mod cdb;
 pub mod gdb;
+pub mod lldb;
+pub mod remote;
+pub mod session;
+pub mod valgrind;
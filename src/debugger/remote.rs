@@ -0,0 +1,176 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Triage of bare-metal firmware on a remote GDB server, like OpenOCD.
+//!
+//! With [crate::debugger::gdb::GdbTriageOptions::remote], the target command is the firmware's
+//! ELF, which GDB only loads symbols from. For each testcase, GDB connects to the server with
+//! `target extended-remote`, resets and halts the target, runs the user's input commands to deliver
+//! the testcase (e.g. `restore @@ binary 0x20001000` to write it to RAM), and continues the target. Firmware has no
+//! signals, so a crash is the target entering one of the fault handlers, where a breakpoint stops
+//! it. GDB unwinds through the exception frame to where the fault happened. Any other stop, like a
+//! breakpoint set by the input commands where the harness finishes a testcase, is not a crash.
+//!
+//! The server drives a single board, so testcases are triaged one at a time.
+//!
+//! Remote setups fail in ways a local target does not: the server drops the connection, or the
+//! probe loses packets. GDB's messages about them are found with [RemoteProtocolErrors], so a lost
+//! connection is a triage error instead of a run without a crash. With
+//! [crate::debugger::gdb::GdbTriageOptions::remote_log_dir], the packets exchanged for each testcase
+//! are also recorded with `set remotelogfile`. This applies to Android targets under gdbserver too.
+use crate::report::enriched::EnrichedFrameInfo;
+
+/// The fault handlers of ARM Cortex-M firmware, as named by CMSIS
+pub const DEFAULT_FAULT_HANDLERS: [&str; 5] = [
+    "HardFault_Handler", "MemManage_Handler", "BusFault_Handler", "UsageFault_Handler", "SecureFault_Handler",
+];
+
+/// The OpenOCD command that resets the target and halts it before it runs any code
+pub const DEFAULT_RESET_COMMAND: &str = "monitor reset halt";
+
+/// What GDB prints when the connection to a remote target fails or is lost
+const CONNECTION_ERRORS: [&str; 5] = [
+    "Remote connection closed", "Remote communication error", "Connection refused", "Connection timed out",
+    "Connection reset by peer",
+];
+
+/// What GDB prints when a packet of the remote protocol is lost, rejected, or malformed
+const PACKET_ERRORS: [&str; 4] = [
+    "Ignoring packet error", "Remote failure reply", "Remote replied unexpectedly", "packet reply is too long",
+];
+
+/// A remote GDB server with bare-metal firmware to run testcases on
+#[derive(Debug, Clone)]
+pub struct RemoteTarget {
+    /// The server, as `HOST:PORT`
+    pub address: String,
+    /// GDB commands that reset and halt the target before each testcase
+    pub reset_commands: Vec<String>,
+    /// GDB commands that deliver a testcase to the target. `@@` is replaced by its path
+    pub input_commands: Vec<String>,
+    /// Functions the target enters on a fault
+    pub fault_handlers: Vec<String>,
+}
+
+impl RemoteTarget {
+    /// A server at `address`, reset with OpenOCD and stopped in the CMSIS fault handlers
+    pub fn new(address: &str) -> RemoteTarget {
+        RemoteTarget {
+            address: address.to_string(),
+            reset_commands: vec![DEFAULT_RESET_COMMAND.to_string()],
+            input_commands: vec![],
+            fault_handlers: DEFAULT_FAULT_HANDLERS.iter().map(|h| h.to_string()).collect(),
+        }
+    }
+
+    /// The GDB commands that run the testcase at `testcase` on the target until it faults or stops.
+    /// `res/remote_fault.py` must be loaded first
+    pub fn run_commands(&self, testcase: &str) -> Vec<String> {
+        let mut commands = vec![format!("target extended-remote {}", self.address)];
+
+        commands.extend(self.reset_commands.iter().cloned());
+        commands.extend(self.input_commands.iter().map(|c| c.replace("@@", testcase)));
+        // A JSON list of strings is also a valid python list literal
+        commands.push(format!("python afltriage_fault_handlers({})", serde_json::to_string(&self.fault_handlers).unwrap()));
+        commands.push("continue".to_string());
+
+        commands
+    }
+}
+
+/// The problems with the remote protocol GDB reported during a run
+#[derive(Debug, Default, PartialEq)]
+pub struct RemoteProtocolErrors {
+    /// Messages about a connection that failed or was lost
+    pub connection: Vec<String>,
+    /// Messages about packets that were lost or rejected
+    pub packets: Vec<String>,
+}
+
+impl RemoteProtocolErrors {
+    /// Find the remote protocol errors in GDB's output
+    pub fn scan(gdb_output: &str) -> RemoteProtocolErrors {
+        let mut errors = RemoteProtocolErrors::default();
+
+        for line in gdb_output.lines().map(str::trim) {
+            let list = if CONNECTION_ERRORS.iter().any(|e| line.contains(e)) {
+                &mut errors.connection
+            } else if PACKET_ERRORS.iter().any(|e| line.contains(e)) {
+                &mut errors.packets
+            } else {
+                continue;
+            };
+
+            if !list.iter().any(|l| l == line) {
+                list.push(line.to_string());
+            }
+        }
+
+        errors
+    }
+}
+
+/// The frame that faulted: the one the exception interrupted, below the fault handler and the
+/// exception frame GDB shows as `<signal handler called>`
+pub fn find_fault_handler_frame(handler: &str, frames: &[EnrichedFrameInfo]) -> usize {
+    let function = |i: usize| frames.get(i)
+        .and_then(|fr| fr.symbol.as_ref())
+        .and_then(|s| s.function_name.as_deref());
+
+    let handler_idx = match (0..frames.len()).find(|i| function(*i) == Some(handler)) {
+        Some(i) => i,
+        None => return 0,
+    };
+
+    // The exception frame has no function
+    match (handler_idx + 1..frames.len()).find(|i| function(*i).is_some()) {
+        Some(i) => i,
+        None if handler_idx + 1 < frames.len() => handler_idx + 1,
+        None => handler_idx,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debugger::gdb::GdbSymbol;
+    use std::rc::Rc;
+
+    fn frame(function: Option<&str>) -> EnrichedFrameInfo {
+        let frame: EnrichedFrameInfo = serde_json::from_value(serde_json::json!({
+            "summary": "", "address": {"r": 0x8000400, "f": "0x8000400", "s": 32},
+            "relative_address": {"r": 0x400, "f": "0x400", "s": 32},
+            "module": "firmware.elf", "module_address": "firmware.elf+0x400",
+        })).unwrap();
+        let symbol = function.map(|f| Rc::new(GdbSymbol { function_name: Some(f.into()), ..Default::default() }));
+        EnrichedFrameInfo { symbol, ..frame }
+    }
+
+    #[test]
+    fn test_remote_target() {
+        let remote = RemoteTarget {
+            input_commands: vec!["restore @@ binary 0x20001000".into()],
+            fault_handlers: vec!["HardFault_Handler".into()],
+            ..RemoteTarget::new("localhost:3333")
+        };
+
+        assert_eq!(remote.run_commands("/tmp/crash-1"), vec![
+            "target extended-remote localhost:3333",
+            "monitor reset halt",
+            "restore /tmp/crash-1 binary 0x20001000",
+            "python afltriage_fault_handlers([\"HardFault_Handler\"])",
+            "continue",
+        ]);
+
+        let frames = vec![frame(Some("HardFault_Handler")), frame(None), frame(Some("parse_packet")), frame(Some("main"))];
+        assert_eq!(find_fault_handler_frame("HardFault_Handler", &frames), 2);
+        assert_eq!(find_fault_handler_frame("BusFault_Handler", &frames), 0);
+
+        let errors = RemoteProtocolErrors::scan("Ignoring packet error, continuing...\n\
+            Ignoring packet error, continuing...\nRemote failure reply: E01\nparsing header\n\
+            localhost:3333: Connection timed out.\n");
+        assert_eq!(errors.connection, vec!["localhost:3333: Connection timed out."]);
+        assert_eq!(errors.packets, vec!["Ignoring packet error, continuing...", "Remote failure reply: E01"]);
+        assert_eq!(RemoteProtocolErrors::scan("parsing header\n"), RemoteProtocolErrors::default());
+    }
+}
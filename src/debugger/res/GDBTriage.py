@@ -16,14 +16,18 @@ except ImportError:
 
 import copy
 import collections
+import glob
 import json
+import signal
 import re
+import traceback
 
 from pprint import pprint
 
 ModuleSection = collections.namedtuple("ModuleSection", ["name", "start", "end", "filename"])
 
-r_MAPPINGS = re.compile(r"(0x[a-fA-F0-9]+)\s+(0x[a-fA-F0-9]+)\s+(0x[a-fA-F0-9]+)\s+(0x[a-fA-F0-9]+)\s+(.*)")
+# Newer GDB versions include a permissions column before the objfile
+r_MAPPINGS = re.compile(r"(0x[a-fA-F0-9]+)\s+(0x[a-fA-F0-9]+)\s+(0x[a-fA-F0-9]+)\s+(0x[a-fA-F0-9]+)\s+(?:([r-][w-][x-][ps-])(?:\s+|$))?(.*)")
 # 0xf7fd6114 - 0xf7fd6138 is .note.gnu.build-id in /lib/ld-linux.so.2
 r_FILE_INFO = re.compile(r"(0x[a-fA-F0-9]+) - (0x[a-fA-F0-9]+) is ([^\s]+)( in .*)?")
 
@@ -36,8 +40,110 @@ r_REGISTER_VALUES = re.compile(r"([^\s]+)\s+(0x[a-fA-F0-9]+)\s+(.*)")
 #### OPTIONS
 # Collect backtraces from all threads
 # TODO: make these into `gdbtriage` options
-ALL_THREADS = False
+ALL_THREADS = True
 FRAME_LIMIT=100
+# Other threads are only summarized, so fewer of their frames and threads are collected
+OTHER_THREAD_FRAME_LIMIT=16
+MAX_OTHER_THREADS=64
+
+# Signals a thread stops with when it crashes, as opposed to being stopped by GDB
+CRASH_SIGNALS = ["SIGSEGV", "SIGBUS", "SIGILL", "SIGFPE", "SIGABRT", "SIGTRAP", "SIGSYS"]
+
+#### EXTENSIONS
+# Extra scripts (see --triage-script-extra) are loaded after this one and share its globals.
+# They can call register_extension(name, callback) to add custom fields to the triage JSON.
+# Each callback receives the primary gdb.InferiorThread and returns a JSON serializable value,
+# which is emitted as context["extra"][name]. Returning None omits the field.
+EXTENSIONS = collections.OrderedDict()
+
+def register_extension(name, callback):
+    EXTENSIONS[name] = callback
+
+#### PRETTY PRINTERS
+# Locals and arguments are formatted with GDB's pretty-printers, so an std::vector shows its elements
+# instead of its pointers. GDB auto-loads the printers a shared libstdc++ ships with. The ones it
+# misses, for a static libstdc++ or for libc++ (whose printers must be on PYTHONPATH), are loaded
+# the first time a value is formatted. AFLTriage sets $_afltriage_raw_values to skip them
+LIBSTDCXX_PRINTER_DIRS = ["/usr/share/gcc*/python"]
+LIBSTDCXX_PRINTER_NAME = "libstdc++-v6"
+LIBCXX_PRINTER_NAME = "libcxx_pretty_printer"
+
+pretty_printers_loaded = False
+raw_values = False
+
+def get_raw_values():
+    # $_afltriage_raw_values is void unless set by AFLTriage
+    try:
+        return int(gdb.parse_and_eval("$_afltriage_raw_values")) != 0
+    except Exception:
+        return False
+
+def has_pretty_printer(name):
+    holders = [gdb, gdb.current_progspace()] + list(gdb.objfiles())
+
+    for holder in holders:
+        for printer in getattr(holder, "pretty_printers", []):
+            if getattr(printer, "name", "") == name:
+                return True
+
+    return False
+
+def load_pretty_printers():
+    global pretty_printers_loaded
+
+    if pretty_printers_loaded:
+        return
+
+    pretty_printers_loaded = True
+
+    if not has_pretty_printer(LIBSTDCXX_PRINTER_NAME):
+        for pattern in LIBSTDCXX_PRINTER_DIRS:
+            for path in sorted(glob.glob(pattern)):
+                if path not in sys.path:
+                    sys.path.append(path)
+
+        try:
+            from libstdcxx.v6.printers import register_libstdcxx_printers
+            register_libstdcxx_printers(None)
+        except Exception:
+            pass
+
+    if not has_pretty_printer(LIBCXX_PRINTER_NAME):
+        try:
+            # Registered directly, as its loader only registers itself for objfiles loaded later
+            import gdb.printing
+            from libcxx.printers import LibcxxPrettyPrinter
+            gdb.printing.register_pretty_printer(gdb.current_progspace(), LibcxxPrettyPrinter(LIBCXX_PRINTER_NAME))
+        except Exception:
+            pass
+
+def format_value(value):
+    # Value.format_string was added in GDB 9. Older versions always use the printers
+    can_format_raw = hasattr(value, "format_string")
+
+    if raw_values and can_format_raw:
+        return value.format_string(raw=True)
+
+    load_pretty_printers()
+
+    try:
+        return xstr(value)
+    except gdb.error as e:
+        # Printers follow the pointers of a container, which a memory corruption can break
+        if not can_format_raw:
+            raise
+
+        warn("Pretty-printer failed, using the raw value: %s" % (e))
+        return value.format_string(raw=True)
+
+#### DIAGNOSTICS
+# Problems that did not stop triage, like a variable that could not be read, are emitted in the
+# response's "warnings". A partial result is still usable for a report
+WARNINGS = []
+
+def warn(message):
+    if message not in WARNINGS:
+        WARNINGS.append(message)
 
 """
 ######################
@@ -273,14 +379,33 @@ def get_code_context(location, filename):
 
     return lineno, code
 
+# Reasons GDB stops unwinding that mean the stack is likely corrupt
+CORRUPT_UNWIND_REASONS = [getattr(gdb, name) for name in [
+    "FRAME_UNWIND_INNER_ID", "FRAME_UNWIND_SAME_ID", "FRAME_UNWIND_MEMORY_ERROR"] if hasattr(gdb, name)]
+
+def get_unwind_error(frame):
+    """Why GDB could not unwind past `frame`, if it suggests a corrupt stack"""
+    try:
+        reason = frame.unwind_stop_reason()
+    except (gdb.error, RuntimeError):
+        return None
+
+    if reason in CORRUPT_UNWIND_REASONS:
+        return gdb.frame_stop_reason_string(reason)
+
+    return None
+
 def capture_backtrace(primary=True, detailed=False, frame_limit=0):
+    """Returns the backtrace and, if unwinding stopped early due to a likely corrupt stack, why"""
     backtrace = []
     cframe = gdb.newest_frame()
     frame_count = 0
+    last_frame = None
 
     while cframe and cframe.is_valid():
         frame_count += 1
         if frame_limit and frame_count > frame_limit:
+            last_frame = None
             break
 
         frame_info = {}
@@ -372,7 +497,7 @@ def capture_backtrace(primary=True, detailed=False, frame_limit=0):
 
                     sym["callsite"] += [code.rstrip()]
             except RuntimeError as e:
-                pass
+                warn("Could not read the source of frame #%d: %s" % (frame_count-1, e))
 
             for v in xlist(decorator.frame_locals()):
                 info = {}
@@ -384,9 +509,10 @@ def capture_backtrace(primary=True, detailed=False, frame_limit=0):
 
                 try:
                     value = vsym.value(cframe)
-                    info["value"] = xstr(value)
+                    info["value"] = format_value(value)
                 except Exception as e:
                     info["value"] = "<%s>" % (str(e))
+                    warn("Could not read local %s in frame #%d: %s" % (info["name"], frame_count-1, e))
 
                 sym["locals"] += [info]
 
@@ -400,9 +526,10 @@ def capture_backtrace(primary=True, detailed=False, frame_limit=0):
 
                 try:
                     value = vsym.value(cframe)
-                    info["value"] = xstr(value)
+                    info["value"] = format_value(value)
                 except Exception as e:
                     info["value"] = "<%s>" % (str(e))
+                    warn("Could not read argument %s in frame #%d: %s" % (info["name"], frame_count-1, e))
 
                 sym["args"] += [info]
 
@@ -419,9 +546,12 @@ def capture_backtrace(primary=True, detailed=False, frame_limit=0):
                 del frame_info["symbol"]["line"]
 
         backtrace += [frame_info]
+        last_frame = cframe
         cframe = cframe.older()
 
-    return backtrace
+    unwind_error = get_unwind_error(last_frame) if last_frame is not None else None
+
+    return backtrace, unwind_error
 
 def get_instruction_at(pc):
     try:
@@ -439,7 +569,58 @@ def get_instruction_at(pc):
     except gdb.MemoryError:
         return None
 
+def get_signal_name(signo):
+    try:
+        return signal.Signals(signo).name
+    except ValueError:
+        return "SIG%d" % signo
+
+def get_thread_tid(thread):
+    # The ptid is (pid, lwp, tid). Native Linux threads are LWPs
+    _, lwp, tid = thread.ptid
+    return lwp or tid or thread.num
+
+def get_thread_siginfo(thread):
+    """Switches to the thread and returns the number and code of the signal it stopped with"""
+    thread.switch()
+
+    try:
+        return int(gdb.parse_and_eval("$_siginfo.si_signo")), int(gdb.parse_and_eval("$_siginfo.si_code"))
+    except gdb.error:
+        return None
+
+def find_faulting_thread(selected):
+    """GDB selects the thread that reported the stop, but when several threads stop with a signal at
+    once it picks one of them at random. Prefer threads that faulted (a positive si_code is sent by
+    the kernel) over threads that were sent a crash signal, e.g. by abort(), and then the selected
+    thread and the lowest numbered thread, so the same crash is reported from the same thread"""
+    crash_signals = set()
+    for name in CRASH_SIGNALS:
+        try:
+            crash_signals.add(int(signal.Signals[name]))
+        except KeyError:
+            pass
+
+    candidates = []
+    for thread in xlist(gdb.selected_inferior().threads()):
+        siginfo = get_thread_siginfo(thread)
+
+        if siginfo is not None and siginfo[0] in crash_signals:
+            faulted = siginfo[1] > 0
+            candidates += [(not faulted, thread.num != selected.num, thread.num, thread)]
+
+    selected.switch()
+
+    if not candidates:
+        return selected
+
+    return sorted(candidates, key=lambda c: c[:3])[0][3]
+
 def get_stop_info():
+    # Bare-metal targets have no signals. Set by remote_fault.py when it stopped the target in a fault handler
+    if getattr(gdb, "afltriage_fault_handler", None) is not None:
+        return {"signal_name": "SIGTRAP", "signal_number": int(signal.SIGTRAP), "signal_code": 0}
+
     lines = gdb.execute("info program", to_string=True).splitlines()
     signal_name = "SIGUNKNOWN"
 
@@ -458,6 +639,11 @@ def get_stop_info():
     signo = int(gdb.parse_and_eval("$_siginfo.si_signo"))
     sicode = int(gdb.parse_and_eval("$_siginfo.si_code"))
 
+    # "info program" describes the thread that reported the stop, which is not the faulting thread
+    # if another one was chosen
+    if signal_name not in ["SIGUNKNOWN", "SIGTRAP"] and get_signal_name(signo) != signal_name:
+        signal_name = get_signal_name(signo)
+
     sinfo =  {"signal_name": signal_name, "signal_number": signo, "signal_code": sicode}
 
     # https://man7.org/linux/man-pages/man2/sigaction.2.html
@@ -466,14 +652,26 @@ def get_stop_info():
 
     return sinfo
 
-def get_thread_stop_context(primary_thread):
+def get_thread_stop_context(primary_thread, brief=False, all_threads=ALL_THREADS):
     gdb_state = {}
 
     assert primary_thread
 
     pri_thread_info = {}
-    pri_thread_info["tid"] = xint(primary_thread.num)
-    pri_thread_info["backtrace"] = capture_backtrace(primary=True, detailed=True, frame_limit=FRAME_LIMIT)
+    pri_thread_info["tid"] = get_thread_tid(primary_thread)
+
+    if primary_thread.name:
+        pri_thread_info["name"] = primary_thread.name
+
+    pri_thread_info["backtrace"], unwind_error = capture_backtrace(primary=True, detailed=not brief, frame_limit=FRAME_LIMIT)
+
+    if unwind_error:
+        pri_thread_info["unwind_error"] = unwind_error
+
+    # Only frames are needed to bucket a crash
+    if brief:
+        gdb_state["primary_thread"] = pri_thread_info
+        return gdb_state
 
     regs = get_primary_register_values()
     if regs:
@@ -489,7 +687,7 @@ def get_thread_stop_context(primary_thread):
     gdb_state["primary_thread"] = pri_thread_info
 
     # having extra thread information is optional
-    if ALL_THREADS:
+    if all_threads:
         infe = gdb.selected_inferior()
 
         threads = []
@@ -497,14 +695,24 @@ def get_thread_stop_context(primary_thread):
             if thread.num == primary_thread.num:
                 continue
 
+            if len(threads) >= MAX_OTHER_THREADS:
+                break
+
             thread.switch()
 
             thread_info = {}
-            thread_info["tid"] = xint(thread.num)
-            thread_info["backtrace"] = capture_backtrace(primary=False, detailed=False, frame_limit=FRAME_LIMIT)
+            thread_info["tid"] = get_thread_tid(thread)
+
+            if thread.name:
+                thread_info["name"] = thread.name
+
+            thread_info["backtrace"], _ = capture_backtrace(primary=False, detailed=False, frame_limit=OTHER_THREAD_FRAME_LIMIT)
 
             threads += [thread_info]
 
+        # The stop info and extensions are of the primary thread
+        primary_thread.switch()
+
         if threads:
             gdb_state["other_threads"] = threads
 
@@ -571,36 +779,94 @@ def get_module_sections():
         sections += [ModuleSection(section_name, start, end, filename)]
 
     # `info files` can be missing info when the ELF file is fully stripped
-    lines = gdb.execute("info proc mappings", to_string=True).splitlines()
+    # get every named section
+    for mapping in get_memory_mappings():
+        if mapping["start"] in start_addrs:
+            continue
+
+        if mapping["name"] == "":
+            continue
+
+        mod = ModuleSection("", mapping["start"], mapping["end"], mapping["name"])
+        sections += [mod]
+
+    sections = sorted(sections, key=lambda x: x.start)
+    sections_cache = sections
+    return sections
+
+mappings_cache = None
+
+def get_memory_mappings():
+    global mappings_cache
+
+    if mappings_cache is not None:
+        return mappings_cache
+
+    mappings = []
+
+    try:
+        lines = gdb.execute("info proc mappings", to_string=True).splitlines()
+    except gdb.error:
+        lines = []
 
     for line in lines:
         line = line.strip()
 
-        # get every named section
         match = r_MAPPINGS.search(line)
 
         if not match:
             continue
 
-        start, end, size, offset, name = match.groups()
+        start, end, size, offset, perms, name = match.groups()
 
-        start = int(start, 16)
-        end = int(end, 16)
-        size = int(size, 16)
-        offset = int(offset, 16)
+        mapping = {
+            "start": int(start, 16),
+            "end": int(end, 16),
+            "offset": int(offset, 16),
+            "name": name.strip(),
+        }
+
+        if perms:
+            mapping["permissions"] = perms
+
+        mappings += [mapping]
+
+    mappings_cache = mappings
+    return mappings
 
-        if start in start_addrs:
+def get_modules():
+    """The executable and shared libraries loaded in the target, with where they are mapped"""
+    mappings = get_memory_mappings()
+    modules = []
+
+    for objfile in xlist(gdb.objfiles()):
+        # Separate debug info files belong to the module they describe
+        if not objfile.is_valid() or getattr(objfile, "owner", None) is not None:
             continue
 
-        if name.strip() == "":
+        # Skips the vDSO, which GDB names "system-supplied DSO at 0x..."
+        path = objfile.filename
+        if not path or not os.path.isabs(path):
             continue
 
-        mod = ModuleSection("", start, end, name)
-        sections += [mod]
+        module = {"path": path}
 
-    sections = sorted(sections, key=lambda x: x.start)
-    sections_cache = sections
-    return sections
+        # Mappings name the file that was opened, which may be a symlink
+        names = [path, os.path.realpath(path)]
+        ranges = [(m["start"], m["end"]) for m in mappings if m["name"] in names]
+
+        if ranges:
+            module["load_address"] = min(r[0] for r in ranges)
+            module["end_address"] = max(r[1] for r in ranges)
+
+        # Only available on GDB 7.8 and above
+        build_id = getattr(objfile, "build_id", None)
+        if build_id:
+            module["build_id"] = build_id
+
+        modules += [module]
+
+    return modules
 
 def get_primary_module_path():
     return gdb.progspaces()[0].filename
@@ -628,11 +894,101 @@ def get_arch_info():
 
     return v
 
+def run_extensions(thread):
+    extra = collections.OrderedDict()
+
+    for name, callback in EXTENSIONS.items():
+        try:
+            value = callback(thread)
+            # Catch unserializable values here instead of failing the entire response
+            json.dumps(value)
+        except Exception as e:
+            value = {"error": "%s: %s" % (type(e).__name__, e)}
+            warn("Extension %s failed: %s: %s" % (name, type(e).__name__, e))
+
+        if value is not None:
+            extra[name] = value
+
+    return extra
+
+# std::terminate() and its libstdc++ and libc++ implementations
+TERMINATE_FUNCTIONS = ["std::terminate", "__cxxabiv1::__terminate", "std::__terminate"]
+
+def get_uncaught_exception(backtrace):
+    # Only a target on its way out of std::terminate() can have an uncaught exception
+    terminating = any(fr.get("symbol", {}).get("function_name", "").split("(")[0] in TERMINATE_FUNCTIONS
+            for fr in backtrace)
+
+    if not terminating:
+        return None
+
+    exception = {}
+
+    # __cxa_throw() begins catching the exception before calling std::terminate(), so it is the
+    # current exception. Its type_info is a vtable pointer followed by the mangled type name
+    try:
+        tinfo = gdb.parse_and_eval("(const char **)__cxa_current_exception_type()")
+
+        if int(tinfo) != 0:
+            mangled = tinfo[1].string()
+            demangled = gdb.execute("demangle _ZTS%s" % mangled, to_string=True).strip()
+            exception["type"] = demangled.replace("typeinfo name for ", "", 1)
+    except Exception as e:
+        warn("Failed to read the type of the uncaught exception: %s" % e)
+
+    return exception
+
+def get_fork_count():
+    # Set by fork_tracking.gdb, if it was loaded
+    try:
+        return int(gdb.parse_and_eval("$_afltriage_forks"))
+    except Exception:
+        return 0
+
+def get_exit_code():
+    # $_exitcode is void unless the target exited normally
+    try:
+        return int(gdb.parse_and_eval("$_exitcode"))
+    except Exception:
+        return None
+
+def get_exit_signal():
+    # $_exitsignal is void unless the target was terminated by a signal GDB does not stop on
+    try:
+        signo = int(gdb.parse_and_eval("$_exitsignal"))
+    except Exception:
+        return None
+
+    return get_signal_name(signo)
+
 class GDBTriageCommand(gdb.Command):
     def __init__(self):
         gdb.Command.__init__(self, "gdbtriage", gdb.COMMAND_OBSCURE)
 
     def invoke(self, argstr, from_tty):
+        # "gdbtriage brief" collects only what is needed for bucketing, and "gdbtriage crashing-thread"
+        # skips the other threads
+        brief = argstr.strip() == "brief"
+        all_threads = ALL_THREADS and argstr.strip() != "crashing-thread"
+
+        del WARNINGS[:]
+
+        # Failures are emitted as JSON too, so they are not mistaken for GDB's own errors
+        try:
+            response = self.triage(brief, all_threads)
+        except Exception as e:
+            response = {
+                "result": "ERROR_SCRIPT",
+                "error": "%s: %s" % (type(e).__name__, e),
+                "traceback": traceback.format_exc().splitlines(),
+            }
+
+        if WARNINGS:
+            response["warnings"] = list(WARNINGS)
+
+        print(json.dumps(response))
+
+    def triage(self, brief, all_threads):
         if not hasattr(gdb, "FrameDecorator"):
             raise ImportError("GDB 7.10 and above must be used")
 
@@ -645,24 +1001,102 @@ class GDBTriageCommand(gdb.Command):
         # XXX: only do this on i386/x86_64
         gdb.execute("set disassembly-flavor intel", to_string=True)
 
+        global raw_values
+        raw_values = get_raw_values()
+
         # XXX: undo "set"'s to restore GDB state
         primary_thread = gdb.selected_thread()
 
+        # A remote target only crashed if remote_fault.py stopped it in a fault handler
+        fault_handler = getattr(gdb, "afltriage_fault_handler", None)
+        if getattr(gdb, "afltriage_remote", False) and fault_handler is None:
+            primary_thread = None
+
         # Target or doesn't exist!
         if primary_thread is not None:
+            primary_thread = find_faulting_thread(primary_thread)
+            primary_thread.switch()
+
             # Assumes success. Failures should be handled internally. Otherwise throw
             response = { "result": "SUCCESS" }
 
-            ctx = get_thread_stop_context(primary_thread)
+            ctx = get_thread_stop_context(primary_thread, brief, all_threads)
             ctx["arch_info"] = get_arch_info()
             # we must have stop info
             # TODO: handle other platforms (non Linux) stop info
             ctx["stop_info"] = get_stop_info()
 
+            mappings = get_memory_mappings()
+            if mappings:
+                ctx["memory_map"] = mappings
+
+            if not brief:
+                exception = get_uncaught_exception(ctx["primary_thread"]["backtrace"])
+                if exception is not None:
+                    ctx["exception"] = exception
+
+                modules = get_modules()
+                if modules:
+                    ctx["modules"] = modules
+
+                extra = run_extensions(primary_thread)
+                if extra:
+                    ctx["extra"] = extra
+
+            # Followed fork children are added as new inferiors
+            if gdb.selected_inferior().num > 1:
+                ctx["forked_child"] = True
+
+            # Set by hang_watchdog.py, if it was loaded and stopped the target
+            if getattr(gdb, "afltriage_hang", False):
+                ctx["hang"] = True
+
+            if fault_handler is not None:
+                ctx["fault_handler"] = fault_handler
+
             response["context"] = ctx
         else:
             response = {"result": "ERROR_TARGET_NOT_RUNNING"}
 
-        print(json.dumps(response))
+            forks = get_fork_count()
+            if forks:
+                response["forks"] = forks
+
+            exit_code = get_exit_code()
+            if exit_code is not None:
+                response["exit_code"] = exit_code
+
+            exit_signal = get_exit_signal()
+            if exit_signal is not None:
+                response["exit_signal"] = exit_signal
+
+            # Set by oracle_breakpoint.py, if it was loaded and the breakpoint was hit
+            oracle_backtrace = getattr(gdb, "afltriage_oracle_backtrace", None)
+            if oracle_backtrace is not None:
+                response["oracle_backtrace"] = oracle_backtrace
+
+        return response
+
+class GDBTriageResetCommand(gdb.Command):
+    # Forget everything about the previous run, for GDB sessions that run the target more than once
+    def __init__(self):
+        gdb.Command.__init__(self, "gdbtriage-reset", gdb.COMMAND_OBSCURE)
+
+    def invoke(self, argstr, from_tty):
+        global sections_cache
+        global mappings_cache
+
+        sections_cache = None
+        mappings_cache = None
+
+        gdb.execute("set $_afltriage_forks = 0", to_string=True)
+
+        # Otherwise the exit code or signal of an earlier run would be reported
+        try:
+            gdb.set_convenience_variable("_exitcode", None)
+            gdb.set_convenience_variable("_exitsignal", None)
+        except AttributeError:
+            pass
 
 GDBTriageCommand()
+GDBTriageResetCommand()
@@ -0,0 +1,56 @@
+# Stop a target that runs for too long so GDBTriage.py can collect where it is stuck.
+# Loaded before the target runs. GDBTriage.py marks the triage JSON as a hang if the watchdog fired
+import os
+import signal
+import threading
+import gdb
+
+gdb.afltriage_hang = False
+
+def gdb_child_pids():
+    # GDB's children are the target (the startup shell execs it in place)
+    gdb_pid = str(os.getpid())
+    pids = []
+
+    for pid in os.listdir("/proc"):
+        if not pid.isdigit():
+            continue
+
+        try:
+            with open("/proc/%s/stat" % pid) as fp:
+                stat = fp.read()
+        except OSError:
+            continue
+
+        # The command name may contain spaces. The parent PID is the second field after it
+        fields = stat[stat.rfind(")") + 2:].split()
+        if len(fields) > 1 and fields[1] == gdb_pid:
+            pids.append(int(pid))
+
+    return pids
+
+def afltriage_hang_watchdog(timeout_ms):
+    def interrupt():
+        pids = gdb_child_pids()
+
+        if pids:
+            gdb.afltriage_hang = True
+
+        # SIGSTOP cannot be caught by the target. GDB reports it as a stop like any other signal
+        for pid in pids:
+            try:
+                os.kill(pid, signal.SIGSTOP)
+            except OSError:
+                pass
+
+    timer = threading.Timer(timeout_ms / 1000.0, interrupt)
+    timer.daemon = True
+
+    def cancel(event):
+        # Catchpoints (e.g. fork tracking) also stop the target, but continue it right away
+        if isinstance(event, gdb.SignalEvent) or isinstance(event, gdb.ExitedEvent):
+            timer.cancel()
+
+    gdb.events.stop.connect(cancel)
+    gdb.events.exited.connect(cancel)
+    timer.start()
@@ -0,0 +1,46 @@
+# Capture a backtrace at the first hit of the oracle breakpoint without stopping the target.
+# Loaded before the target runs. GDBTriage.py adds the backtrace to the triage JSON
+import gdb
+
+gdb.afltriage_oracle_backtrace = None
+
+ORACLE_FRAME_LIMIT = 64
+
+def capture_oracle_backtrace():
+    frames = []
+    frame = gdb.newest_frame()
+
+    while frame is not None and len(frames) < ORACLE_FRAME_LIMIT:
+        frame_info = {"address": int(frame.pc())}
+
+        name = frame.name()
+        if name:
+            frame_info["function_name"] = name
+
+        sal = frame.find_sal()
+        if sal.symtab and sal.line:
+            frame_info["file"] = sal.symtab.filename
+            frame_info["line"] = int(sal.line)
+
+        frames.append(frame_info)
+
+        try:
+            frame = frame.older()
+        except gdb.error:
+            break
+
+    return frames
+
+class OracleBreakpoint(gdb.Breakpoint):
+    def stop(self):
+        if gdb.afltriage_oracle_backtrace is None:
+            try:
+                gdb.afltriage_oracle_backtrace = capture_oracle_backtrace()
+            except gdb.error:
+                gdb.afltriage_oracle_backtrace = []
+
+        # Never stop. The oracle decides if the run is a finding once the target exits
+        return False
+
+def afltriage_oracle_breakpoint(location):
+    OracleBreakpoint(location, internal=True)
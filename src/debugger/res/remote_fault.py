@@ -0,0 +1,32 @@
+# Stop bare-metal firmware when it enters a fault handler, so GDBTriage.py can collect where it
+# faulted. Loaded before the target runs on a remote GDB server. GDBTriage.py reports any other stop
+# of the target as a run without a crash
+import gdb
+
+gdb.afltriage_remote = True
+gdb.afltriage_fault_handler = None
+
+class FaultHandlerBreakpoint(gdb.Breakpoint):
+    def __init__(self, handler):
+        gdb.Breakpoint.__init__(self, handler, internal=True)
+        self.handler = handler
+
+    def stop(self):
+        # Firmware often aliases the handlers to one default handler, so several can be hit at once
+        if gdb.afltriage_fault_handler is None:
+            gdb.afltriage_fault_handler = self.handler
+
+        return True
+
+def is_defined(function):
+    try:
+        gdb.parse_and_eval("&'%s'" % function)
+        return True
+    except gdb.error:
+        return False
+
+def afltriage_fault_handlers(handlers):
+    for handler in handlers:
+        # Handlers the firmware does not define are skipped
+        if is_defined(handler):
+            FaultHandlerBreakpoint(handler)
@@ -0,0 +1,149 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Long-lived GDB sessions.
+//!
+//! Starting GDB and loading the target's symbols can take far longer than running the target,
+//! especially for large binaries with a lot of debug information. A [GdbSession] keeps one GDB
+//! process running and sends it the commands to run and triage each testcase on its stdin. Every
+//! batch of commands ends with a sync marker written to stdout and stderr, which delimits the
+//! output of one run. A session that times out or exits is dropped and the next testcase starts a
+//! new one.
+use crate::process::{LimitedOutput, OutputLimit};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// A GDB process that runs commands sent to its stdin
+pub struct GdbSession {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Receiver<Vec<u8>>,
+    stderr: Receiver<Vec<u8>>,
+    /// The command line the session was started with. A session is only reused for the same one
+    pub command_line: Vec<String>,
+    /// How many batches of commands the session has executed
+    pub runs: usize,
+}
+
+/// Lines longer than this are forwarded in pieces, so that a target writing without newlines is
+/// not buffered whole
+const MAX_LINE_LENGTH: u64 = 64 * 1024;
+
+/// Forward the lines of `reader` to a channel until it closes
+fn read_lines<R: Read + Send + 'static>(reader: R) -> Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut line = vec![];
+
+        while let Ok(n) = (&mut reader).take(MAX_LINE_LENGTH).read_until(b'\n', &mut line) {
+            if n == 0 || tx.send(std::mem::take(&mut line)).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+/// Collect lines until the `sync` marker line, keeping as much as `limit` allows
+fn collect_until(lines: &Receiver<Vec<u8>>, sync: &str, deadline: Instant, limit: Option<&OutputLimit>) -> Result<Vec<u8>> {
+    let mut output = LimitedOutput::new(limit.cloned());
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        match lines.recv_timeout(remaining) {
+            Ok(line) if line.trim_ascii_end() == sync.as_bytes() => return Ok(output.finish()),
+            Ok(line) => output.push(&line),
+            Err(RecvTimeoutError::Timeout) =>
+                return Err(Error::new(ErrorKind::TimedOut, "GDB session exceeded timeout")),
+            Err(RecvTimeoutError::Disconnected) =>
+                return Err(Error::new(ErrorKind::UnexpectedEof, "GDB session exited")),
+        }
+    }
+}
+
+impl GdbSession {
+    /// Start GDB with `args`. GDB must not be given `--batch`, so that it reads commands from stdin
+    pub fn spawn(gdb_path: &str, args: &[String]) -> Result<GdbSession> {
+        // SAFETY: only pre_exec call back is unsafe
+        let mut child = unsafe {
+            Command::new(gdb_path)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .pre_exec(|| {
+                    crate::process::pre_execute();
+                    Ok(())
+                })
+                .spawn()?
+        };
+
+        Ok(GdbSession {
+            stdin: child.stdin.take().unwrap(),
+            stdout: read_lines(child.stdout.take().unwrap()),
+            stderr: read_lines(child.stderr.take().unwrap()),
+            child,
+            command_line: [&[gdb_path.to_string()], args].concat(),
+            runs: 0,
+        })
+    }
+
+    /// Execute GDB `commands` and return what GDB and the target wrote to stdout and stderr
+    /// meanwhile, keeping as much as `limit` allows. The session cannot be used again after an error
+    pub fn execute(&mut self, commands: &[String], timeout_ms: u64, limit: Option<&OutputLimit>) -> Result<(Vec<u8>, Vec<u8>)> {
+        self.runs += 1;
+
+        let sync = format!("----AFLTRIAGE_SESSION_SYNC_{}----", self.runs);
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+
+        for command in commands {
+            writeln!(self.stdin, "{}", command)?;
+        }
+
+        writeln!(self.stdin, "python [(x.write('{}\\n'),x.flush()) for x in [sys.stdout, sys.stderr]]", sync)?;
+        self.stdin.flush()?;
+
+        let stdout = collect_until(&self.stdout, &sync, deadline, limit)?;
+        let stderr = collect_until(&self.stderr, &sync, deadline, limit)?;
+
+        Ok((stdout, stderr))
+    }
+}
+
+impl Drop for GdbSession {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_sync() {
+        let (tx, rx) = mpsc::channel();
+        let deadline = Instant::now() + Duration::from_millis(1000);
+
+        for line in &["child output\n", "----SYNC----\n", "next run\n"] {
+            tx.send(line.as_bytes().to_vec()).unwrap();
+        }
+
+        assert_eq!(collect_until(&rx, "----SYNC----", deadline, None).unwrap(), b"child output\n");
+
+        let err = collect_until(&rx, "----SYNC----", Instant::now() + Duration::from_millis(10), None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        drop(tx);
+        let err = collect_until(&rx, "----SYNC----", deadline, None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,439 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Triage under Valgrind Memcheck instead of GDB.
+//!
+//! Some heap bugs, like a read of freed memory that is still mapped, never crash an uninstrumented
+//! binary and only surface under Valgrind. With [GdbTriageOptions::valgrind], every testcase is run
+//! under Memcheck until its first error, and Memcheck's XML output is parsed into the same
+//! [GdbTriageResult] GDB gives, so bucketing and reports work the same. The error is written to the
+//! child's stderr in Memcheck's text format, where it is classified like a sanitizer report (see
+//! [crate::report::sanitizer]).
+//!
+//! Valgrind does not record where modules are loaded, so frames without symbols are identified by
+//! their absolute address. Valgrind loads programs at the same addresses on every run, so these
+//! are still stable.
+use crate::debugger::gdb::*;
+use crate::platform::linux::signal_to_string;
+use crate::process::{self, OutputLimit, ProcessStdin};
+use regex::Regex;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::rc::Rc;
+
+/// The exit code Memcheck exits with after the first error
+const MEMCHECK_ERROR_EXIT_CODE: i32 = 99;
+
+lazy_static! {
+    static ref R_XML_ENTITY: Regex = Regex::new(r"&(amp|lt|gt|quot|apos);").unwrap();
+}
+
+/// A frame of a Memcheck stack
+#[derive(Debug, Default, PartialEq)]
+struct MemcheckFrame {
+    ip: u64,
+    obj: Option<String>,
+    function: Option<String>,
+    file: Option<String>,
+    line: Option<i64>,
+}
+
+/// A Memcheck error
+#[derive(Debug, PartialEq)]
+struct MemcheckError {
+    tid: i32,
+    what: String,
+    stack: Vec<MemcheckFrame>,
+    /// Descriptions of the address, like where it was freed, each with its stack
+    aux: Vec<(String, Vec<MemcheckFrame>)>,
+}
+
+/// The signal that killed the target
+#[derive(Debug)]
+struct MemcheckFatalSignal {
+    tid: i32,
+    stop_info: GdbStopInfo,
+    stack: Vec<MemcheckFrame>,
+}
+
+/// What Memcheck reported in its XML output
+#[derive(Debug, Default)]
+struct MemcheckOutput {
+    pid: i32,
+    /// The first error
+    error: Option<MemcheckError>,
+    fatal_signal: Option<MemcheckFatalSignal>,
+}
+
+fn unescape(text: &str) -> String {
+    R_XML_ENTITY.replace_all(text, |c: &regex::Captures| match &c[1] {
+        "amp" => "&",
+        "lt" => "<",
+        "gt" => ">",
+        "quot" => "\"",
+        _ => "'",
+    }).to_string()
+}
+
+/// The elements named `tag` directly or indirectly in `xml`, in order
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut found = vec![];
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&open) {
+        let inner = &rest[start + open.len()..];
+
+        match inner.find(&close) {
+            Some(end) => {
+                found.push(&inner[..end]);
+                rest = &inner[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+
+    found
+}
+
+fn element(xml: &str, tag: &str) -> Option<String> {
+    elements(xml, tag).first().map(|e| unescape(e.trim()))
+}
+
+fn parse_number<T: std::str::FromStr>(xml: &str, tag: &str) -> Option<T> {
+    element(xml, tag).and_then(|n| n.parse().ok())
+}
+
+fn parse_hex(xml: &str, tag: &str) -> Option<u64> {
+    element(xml, tag).and_then(|n| u64::from_str_radix(n.trim_start_matches("0x"), 16).ok())
+}
+
+fn parse_stack(stack: &str) -> Vec<MemcheckFrame> {
+    elements(stack, "frame").into_iter()
+        .map(|frame| MemcheckFrame {
+            ip: parse_hex(frame, "ip").unwrap_or(0),
+            obj: element(frame, "obj"),
+            function: element(frame, "fn"),
+            file: match (element(frame, "dir"), element(frame, "file")) {
+                (Some(dir), Some(file)) => Some(Path::new(&dir).join(file).to_string_lossy().to_string()),
+                (None, file) => file,
+                _ => None,
+            },
+            line: parse_number(frame, "line"),
+        })
+        .collect()
+}
+
+/// The first error and the fatal signal in Memcheck's XML output
+fn parse_memcheck_xml(xml: &str) -> MemcheckOutput {
+    let error = elements(xml, "error").first().map(|error| {
+        let stacks = elements(error, "stack");
+        // xwhat has the same text as what, and more details for leaks
+        let what = element(error, "what")
+            .or_else(|| elements(error, "xwhat").first().and_then(|x| element(x, "text")))
+            .unwrap_or_default();
+
+        let aux = elements(error, "auxwhat").into_iter()
+            .enumerate()
+            .map(|(i, aux)| (unescape(aux.trim()), stacks.get(i + 1).map(|s| parse_stack(s)).unwrap_or_default()))
+            .collect();
+
+        MemcheckError {
+            tid: parse_number(error, "tid").unwrap_or(1),
+            what,
+            stack: stacks.first().map(|s| parse_stack(s)).unwrap_or_default(),
+            aux,
+        }
+    });
+
+    let fatal_signal = elements(xml, "fatal_signal").first().map(|signal| MemcheckFatalSignal {
+        tid: parse_number(signal, "tid").unwrap_or(1),
+        stop_info: GdbStopInfo {
+            signal_name: element(signal, "signame").unwrap_or_default(),
+            signal_number: parse_number(signal, "signo").unwrap_or(0),
+            signal_code: parse_number(signal, "sicode").unwrap_or(0),
+            faulting_address: parse_hex(signal, "siaddr"),
+        },
+        stack: elements(signal, "stack").first().map(|s| parse_stack(s)).unwrap_or_default(),
+    });
+
+    MemcheckOutput {
+        pid: parse_number(xml, "pid").unwrap_or(0),
+        error,
+        fatal_signal,
+    }
+}
+
+fn format_frame_text(pid: i32, i: usize, frame: &MemcheckFrame) -> String {
+    let location = match (&frame.file, frame.line, &frame.obj) {
+        (Some(file), Some(line), _) => {
+            let file = Path::new(file).file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+            format!("({}:{})", file, line)
+        }
+        (_, _, Some(obj)) => format!("(in {})", obj),
+        _ => "".into(),
+    };
+
+    format!("=={}==    {} 0x{:X}: {} {}\n", pid, if i == 0 { "at" } else { "by" },
+        frame.ip, frame.function.as_deref().unwrap_or("???"), location)
+}
+
+/// The first error in Memcheck's text format
+fn format_error_text(pid: i32, what: &str, stack: &[MemcheckFrame], aux: &[(String, Vec<MemcheckFrame>)]) -> String {
+    let mut out = format!("=={}== {}\n", pid, what);
+
+    for (i, frame) in stack.iter().enumerate() {
+        out += &format_frame_text(pid, i, frame);
+    }
+
+    for (auxwhat, stack) in aux {
+        out += &format!("=={}==  {}\n", pid, auxwhat);
+
+        for (i, frame) in stack.iter().enumerate() {
+            out += &format_frame_text(pid, i, frame);
+        }
+    }
+
+    out + &format!("=={}== \n", pid)
+}
+
+fn frame_info(frame: &MemcheckFrame) -> GdbFrameInfo {
+    let module = frame.obj.clone().unwrap_or_else(|| "??".into());
+
+    GdbFrameInfo {
+        address: frame.ip,
+        relative_address: frame.ip,
+        module_address: format!("{}+0x{:x}", module, frame.ip),
+        module,
+        symbol: frame.function.as_ref().map(|function| Rc::new(GdbSymbol {
+            function_name: Some(function.to_string()),
+            file: frame.file.clone(),
+            line: frame.line,
+            ..Default::default()
+        })),
+    }
+}
+
+/// Convert Memcheck's output to a triage result. A run without an error or a fatal signal did not
+/// crash
+fn memcheck_triage_result(memcheck: MemcheckOutput, address_bits: usize, status: Option<ExitStatus>, child: GdbChildOutput) -> GdbTriageResult {
+    let mut child = child;
+
+    let (tid, stack, stop_info) = match (memcheck.error, memcheck.fatal_signal) {
+        (Some(error), signal) => {
+            child.stderr += &format_error_text(memcheck.pid, &error.what, &error.stack, &error.aux);
+
+            // Memcheck exits after the first error, so the target is only stopped by a signal
+            // that came before it
+            let stop_info = match signal {
+                Some(signal) => signal.stop_info,
+                None => GdbStopInfo {
+                    signal_name: "NONE".into(),
+                    signal_number: 0,
+                    signal_code: 0,
+                    faulting_address: None,
+                },
+            };
+
+            (error.tid, error.stack, stop_info)
+        }
+        (None, Some(signal)) => (signal.tid, signal.stack, signal.stop_info),
+        (None, None) => return GdbTriageResult {
+            response: GdbJsonResult {
+                result: GdbResultCode::ERROR_TARGET_NOT_RUNNING,
+                context: None,
+                forks: None,
+                exit_code: status.and_then(|s| s.code()),
+                exit_signal: status.and_then(|s| s.signal()).map(|signo| signal_to_string(signo).to_string()),
+                oracle_backtrace: None,
+                warnings: vec![],
+            },
+            child,
+        },
+    };
+
+    GdbTriageResult {
+        response: GdbJsonResult {
+            result: GdbResultCode::SUCCESS,
+            context: Some(GdbContextInfo {
+                stop_info,
+                arch_info: GdbArchInfo {
+                    address_bits,
+                    architecture: std::env::consts::ARCH.into(),
+                },
+                primary_thread: GdbThread {
+                    tid,
+                    name: None,
+                    backtrace: stack.iter().map(frame_info).collect(),
+                    current_instruction: None,
+                    registers: None,
+                    unwind_error: None,
+                },
+                other_threads: None,
+                memory_map: None,
+                modules: None,
+                extra: None,
+                forked_child: None,
+                hang: None,
+                exception: None,
+                fault_handler: None,
+            }),
+            forks: None,
+            exit_code: None,
+            exit_signal: None,
+            oracle_backtrace: None,
+            warnings: vec![],
+        },
+        child,
+    }
+}
+
+/// The address size of an ELF program, assuming the host's if it cannot be read
+fn program_address_bits(program: &str) -> usize {
+    let mut header = [0u8; 5];
+    let read = which::which(program).ok()
+        .and_then(|p| std::fs::File::open(p).ok())
+        .map(|mut f| std::io::Read::read_exact(&mut f, &mut header).is_ok())
+        .unwrap_or(false);
+
+    match header {
+        [0x7f, b'E', b'L', b'F', 1] if read => 32,
+        [0x7f, b'E', b'L', b'F', 2] if read => 64,
+        _ => std::mem::size_of::<usize>() * 8,
+    }
+}
+
+/// Run a program under Memcheck with `valgrind` until its first error. The testcase is given on
+/// stdin if `input_file` is set
+pub fn triage_program(
+    valgrind: &Path,
+    options: &GdbTriageOptions,
+    prog_args: &[String],
+    input_file: Option<&str>,
+    timeout_ms: u64,
+) -> Result<GdbTriageResult, GdbTriageError> {
+    let xml_file = tempfile::Builder::new().suffix(".xml").tempfile()
+        .map_err(|e| GdbTriageError::new_brief(GdbTriageErrorKind::Internal,
+            format!("Failed to create the Memcheck output file: {}", e)))?;
+
+    let mut args: Vec<String> = vec![];
+
+    if options.clear_env {
+        args.push("-i".into());
+    }
+
+    args.extend(options.target_env.iter().map(|(k, v)| format!("{}={}", k, v)));
+    args.extend(vec![
+        valgrind.to_string_lossy().to_string(),
+        "--tool=memcheck".into(),
+        "--xml=yes".into(),
+        format!("--xml-file={}", xml_file.path().display()),
+        "--exit-on-first-error=yes".into(),
+        format!("--error-exitcode={}", MEMCHECK_ERROR_EXIT_CODE),
+        "--child-silent-after-fork=yes".into(),
+    ]);
+    args.extend(prog_args.iter().cloned());
+
+    let input = match input_file {
+        Some(path) => Some(crate::util::read_file_to_bytes(path)
+            .map_err(|e| GdbTriageError::new_brief(GdbTriageErrorKind::Internal,
+                format!("Failed to read the testcase: {}", e)))?),
+        None => None,
+    };
+
+    let input = match input {
+        Some(data) => ProcessStdin::Data(data),
+        None => ProcessStdin::Null,
+    };
+    let limit = options.max_child_output.map(OutputLimit::new);
+
+    let output = process::execute_capture_limited_output_timeout("env", &args, timeout_ms, input, limit.as_ref())
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::TimedOut => GdbTriageError::new_brief(GdbTriageErrorKind::Timeout, e.to_string()),
+            _ => GdbTriageError::new_brief(GdbTriageErrorKind::Command, format!("Failed to run Valgrind: {}", e)),
+        })?;
+
+    let xml = std::fs::read_to_string(xml_file.path()).unwrap_or_default();
+
+    if !xml.contains("<valgrindoutput>") {
+        return Err(GdbTriageError::new_detailed(GdbTriageErrorKind::Command,
+            "Valgrind did not write its output", vec![output.stderr]));
+    }
+
+    let child = GdbChildOutput {
+        stdout: output.stdout,
+        stderr: output.stderr,
+        stdout_raw: output.stdout_raw,
+        stderr_raw: output.stderr_raw,
+    };
+
+    Ok(memcheck_triage_result(parse_memcheck_xml(&xml), program_address_bits(&prog_args[0]),
+        Some(output.status), child))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_memcheck_xml() {
+        let mut path = PathBuf::from(file!());
+        path.pop();
+        path.push("res");
+        path.push("test_valgrind");
+        path.push("memcheck_uaf.xml");
+
+        let xml = std::fs::read_to_string(path).unwrap();
+        let memcheck = parse_memcheck_xml(&xml);
+
+        assert_eq!(memcheck.pid, 24193);
+        assert!(memcheck.fatal_signal.is_none());
+
+        let error = memcheck.error.as_ref().unwrap();
+        assert_eq!((error.tid, error.what.as_str()), (1, "Invalid read of size 4"));
+        assert_eq!(error.stack[0], MemcheckFrame {
+            ip: 0x1091B4,
+            obj: Some("/tmp/test".into()),
+            function: Some("use_after_free".into()),
+            file: Some("/tmp/test.c".into()),
+            line: Some(12),
+        });
+        assert_eq!(error.aux.len(), 2);
+        assert_eq!(error.aux[0].0, "Address 0x4a4a048 is 8 bytes inside a block of size 16 free'd");
+        assert_eq!(error.aux[1].1[0].function.as_deref(), Some("malloc"));
+
+        let child = GdbChildOutput::default();
+        let triage = memcheck_triage_result(memcheck, 64, Some(ExitStatus::from_raw(MEMCHECK_ERROR_EXIT_CODE << 8)), child);
+        let ctx = triage.response.context.as_ref().unwrap();
+        assert_eq!(ctx.primary_thread.backtrace.len(), 2);
+        assert_eq!(ctx.primary_thread.backtrace[0].module_address, "/tmp/test+0x1091b4");
+        assert!(triage.child.stderr.contains("==24193==    at 0x1091B4: use_after_free (test.c:12)\n"));
+
+        let report = crate::report::sanitizer::sanitizer_report_extract(&triage.child.stderr).unwrap();
+        assert_eq!((report.stop_reason.as_str(), report.operation.as_str()), ("heap-use-after-free", "READ"));
+        assert_eq!(report.frames, vec![0x1091B4, 0x109207]);
+
+        let opt = crate::report::ReportOptions { child_output_lines: 0, ..Default::default() };
+        let einfo = crate::report::enriched::enrich_triage_info(&opt, &triage).unwrap();
+        assert!(einfo.summary.starts_with("MEMCHECK detected heap-use-after-free in use_after_free after a READ"));
+
+        let abort = "<valgrindoutput><pid>7</pid><fatal_signal><tid>2</tid><signo>6</signo>\
+            <signame>SIGABRT</signame><sicode>-6</sicode><stack><frame><ip>0x4A2D</ip>\
+            <obj>/usr/lib/libc.so.6</obj><fn>raise</fn></frame></stack></fatal_signal></valgrindoutput>";
+        let triage = memcheck_triage_result(parse_memcheck_xml(abort), 64, None,
+            GdbChildOutput::default());
+        let ctx = triage.response.context.unwrap();
+        assert_eq!((ctx.stop_info.signal_name.as_str(), ctx.primary_thread.tid), ("SIGABRT", 2));
+
+        let clean = memcheck_triage_result(parse_memcheck_xml("<valgrindoutput></valgrindoutput>"), 64,
+            Some(ExitStatus::from_raw(0)), GdbChildOutput::default());
+        assert!(matches!(clean.response.result, GdbResultCode::ERROR_TARGET_NOT_RUNNING));
+        assert_eq!((clean.response.exit_code, clean.response.exit_signal), (Some(0), None));
+
+        let killed = memcheck_triage_result(parse_memcheck_xml("<valgrindoutput></valgrindoutput>"), 64,
+            Some(ExitStatus::from_raw(libc::SIGKILL)), GdbChildOutput::default());
+        assert_eq!((killed.response.exit_code, killed.response.exit_signal.as_deref()), (None, Some("SIGKILL")));
+    }
+}
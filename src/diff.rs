@@ -0,0 +1,61 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Compare the crash buckets of two triage runs.
+//!
+//! `afltriage diff OLD NEW` prints the buckets that are new, fixed, or changed in crash count
+//! between two output directories, e.g. from triaging the same crashes before and after a patch.
+//! With `--fail-on-new`, it exits with 1 when NEW has buckets that OLD does not, so it can gate a
+//! CI job.
+use clap::{App, AppSettings, Arg, ArgMatches};
+use std::path::Path;
+
+use afltriage::report::diff::{self, ResultsDiff};
+
+pub fn build_app() -> App<'static, 'static> {
+    App::new("afltriage diff")
+        .about("Compare the crash buckets of two triage runs")
+        .usage("afltriage diff [OPTIONS] <old> <new>")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .setting(AppSettings::DeriveDisplayOrder)
+        .arg(Arg::with_name("old")
+             .required(true)
+             .help("The output directory of the earlier triage run, or its afltriage_buckets.json."))
+        .arg(Arg::with_name("new")
+             .required(true)
+             .help("The output directory of the later triage run, or its afltriage_buckets.json."))
+        .arg(Arg::with_name("json")
+             .long("--json")
+             .help("Print the differences as JSON instead of text."))
+        .arg(Arg::with_name("fail_on_new")
+             .long("--fail-on-new")
+             .help("Exit with 1 if the later run found buckets the earlier one did not."))
+}
+
+/// Run `afltriage diff`. Returns the process exit code
+pub fn diff_main(args: &ArgMatches) -> i32 {
+    let read = |name: &str| diff::read_buckets(Path::new(args.value_of(name).unwrap()));
+
+    let (old, new) = match (read("old"), read("new")) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            log::error!("{}", e);
+            log::error!("Both runs need an output directory with bucket metadata to be compared");
+            return 1;
+        }
+    };
+
+    let diff: ResultsDiff = diff::diff_results(old, new);
+
+    if args.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&diff).unwrap());
+    } else {
+        print!("{}", diff::format_text_diff(&diff));
+    }
+
+    if args.is_present("fail_on_new") && !diff.new.is_empty() {
+        1
+    } else {
+        0
+    }
+}
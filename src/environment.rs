@@ -4,7 +4,13 @@
 //! AFLTriage environment variables
 //!
 //! * `AFLTRIAGE_GDB_PATH` - Set the path to GDB. [default: gdb]
+//!
+//! This module also handles the environment variables given to the target (see `--env` and
+//! `--env-file`).
 use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
 
 pub struct AfltriageEnv {
     pub gdb_path: String,
@@ -33,3 +39,54 @@ pub fn parse_afltriage_env() -> Option<AfltriageEnv> {
 
     Some(aenv)
 }
+
+/// Parse a `KEY=VALUE` environment variable assignment. The value may be empty or contain `=`
+pub fn parse_env_assignment(assignment: &str) -> Option<(String, String)> {
+    let (key, value) = assignment.split_at(assignment.find('=')?);
+    let key = key.trim();
+
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        return None;
+    }
+
+    Some((key.to_string(), value[1..].to_string()))
+}
+
+/// Read a file of `KEY=VALUE` lines for the target's environment. Blank lines and lines starting
+/// with `#` are ignored.
+pub fn read_env_file(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut vars = vec![];
+
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        match parse_env_assignment(trimmed) {
+            Some(var) => vars.push(var),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("line {}: expected KEY=VALUE", lineno + 1))),
+        }
+    }
+
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_env_assignment() {
+        assert_eq!(parse_env_assignment("A=B"), Some(("A".into(), "B".into())));
+        assert_eq!(parse_env_assignment("ASAN_OPTIONS=a=1:b=2"), Some(("ASAN_OPTIONS".into(), "a=1:b=2".into())));
+        assert_eq!(parse_env_assignment("EMPTY="), Some(("EMPTY".into(), "".into())));
+        assert_eq!(parse_env_assignment("NOVALUE"), None);
+        assert_eq!(parse_env_assignment("=VALUE"), None);
+        assert_eq!(parse_env_assignment("BAD KEY=VALUE"), None);
+    }
+}
@@ -0,0 +1,364 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Selecting which testcases to triage.
+//!
+//! `--filter` narrows the testcases found in the inputs down to a subset. Each filter is one of:
+//!
+//! * A glob matched against the file name, like `id:0003*`
+//! * `regex:PATTERN`, a regular expression searched for in the file name
+//! * A comparison of a field of an AFL file name, like `sig=11`, `op!=havoc`, or `time>=3600000`.
+//!   The operators are `=`, `!=`, `<`, `<=`, `>`, and `>=`. Numbers are compared as numbers, so
+//!   `id<100` matches `id:000042`. `sig` also accepts signal names, like `sig=SIGSEGV`. Files
+//!   without the field never match, except with `!=`
+//!
+//! A testcase is triaged only if it matches every filter.
+//!
+//! Testcases with the same contents as an earlier one, like the copies of a crash that the
+//! instances of an AFL sync directory share, are not triaged again (see
+//! [remove_duplicate_testcases]). Their crash is the one of the original.
+//!
+//! The testcases left are triaged in the order they were found, or in a [TestcaseOrder] given with
+//! `--order`. For a quick look at a huge corpus, `--sample N` triages N testcases picked at random
+//! instead. Random choices are made from a seed that is logged, so they can be repeated with
+//! `--seed`.
+use clap::arg_enum;
+use crate::report::provenance::parse_afl_filename;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::SystemTime;
+
+lazy_static! {
+    static ref R_FIELD_FILTER: Regex = Regex::new(r"^([A-Za-z_]+)(=|!=|<=|>=|<|>)(.+)$").unwrap();
+}
+
+/// Signal numbers by name, for the `sig` field
+const SIGNALS: [(&str, i64); 11] = [
+    ("SIGILL", libc::SIGILL as i64),
+    ("SIGTRAP", libc::SIGTRAP as i64),
+    ("SIGABRT", libc::SIGABRT as i64),
+    ("SIGBUS", libc::SIGBUS as i64),
+    ("SIGFPE", libc::SIGFPE as i64),
+    ("SIGKILL", libc::SIGKILL as i64),
+    ("SIGUSR1", libc::SIGUSR1 as i64),
+    ("SIGSEGV", libc::SIGSEGV as i64),
+    ("SIGUSR2", libc::SIGUSR2 as i64),
+    ("SIGALRM", libc::SIGALRM as i64),
+    ("SIGSYS", libc::SIGSYS as i64),
+];
+
+/// How a field filter compares a field to its value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Eq => ordering == Ordering::Equal,
+            Comparison::Ne => ordering != Ordering::Equal,
+            Comparison::Lt => ordering == Ordering::Less,
+            Comparison::Le => ordering != Ordering::Greater,
+            Comparison::Gt => ordering == Ordering::Greater,
+            Comparison::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// A filter given with `--filter`
+#[derive(Debug)]
+pub enum TestcaseFilter {
+    Glob(Regex),
+    Regex(Regex),
+    Field {
+        key: String,
+        comparison: Comparison,
+        value: String,
+    },
+}
+
+/// A number for comparing the `key` field, if `value` is one
+fn field_number(key: &str, value: &str) -> Option<i64> {
+    if key == "sig" {
+        let name = value.to_uppercase();
+        let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+
+        if let Some((_, signo)) = SIGNALS.iter().find(|(n, _)| *n == name) {
+            return Some(*signo);
+        }
+    }
+
+    value.parse().ok()
+}
+
+impl TestcaseFilter {
+    pub fn parse(spec: &str) -> Result<TestcaseFilter, String> {
+        if let Some(pattern) = spec.strip_prefix("regex:") {
+            return Regex::new(pattern)
+                .map(TestcaseFilter::Regex)
+                .map_err(|e| format!("Invalid --filter regex {}: {}", pattern, e));
+        }
+
+        if let Some(caps) = R_FIELD_FILTER.captures(spec) {
+            let comparison = match &caps[2] {
+                "=" => Comparison::Eq,
+                "!=" => Comparison::Ne,
+                "<" => Comparison::Lt,
+                "<=" => Comparison::Le,
+                ">" => Comparison::Gt,
+                _ => Comparison::Ge,
+            };
+
+            let (key, value) = (caps[1].to_string(), caps[3].to_string());
+
+            if !matches!(comparison, Comparison::Eq | Comparison::Ne) && field_number(&key, &value).is_none() {
+                return Err(format!("Invalid --filter {}: {} can only be compared with a number", spec, &caps[2]));
+            }
+
+            return Ok(TestcaseFilter::Field { key, comparison, value });
+        }
+
+        crate::util::glob_to_regex(spec)
+            .map(TestcaseFilter::Glob)
+            .map_err(|e| format!("Invalid --filter glob {}: {}", spec, e))
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+
+        match self {
+            TestcaseFilter::Glob(re) | TestcaseFilter::Regex(re) => re.is_match(&name),
+            TestcaseFilter::Field { key, comparison, value } => {
+                let fields = parse_afl_filename(&name);
+
+                let actual = match fields.get(key) {
+                    Some(actual) => actual,
+                    None => return *comparison == Comparison::Ne,
+                };
+
+                let ordering = match (field_number(key, actual), field_number(key, value)) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    _ => actual.as_str().cmp(value.as_str()),
+                };
+
+                comparison.holds(ordering)
+            }
+        }
+    }
+}
+
+arg_enum! {
+    /// The order testcases are triaged in
+    #[derive(PartialEq, Debug, Clone, Copy)]
+    #[allow(non_camel_case_types)]
+    pub enum TestcaseOrder {
+        // The order the inputs were given in, and sorted by name within directories
+        found,
+        // The most recently modified first
+        newest,
+        // The smallest first
+        smallest,
+        // Shuffled
+        random,
+    }
+}
+
+/// A small deterministic random number generator (SplitMix64), so samples can be repeated
+pub struct SampleRng(u64);
+
+impl SampleRng {
+    pub fn new(seed: u64) -> Self {
+        SampleRng(seed)
+    }
+
+    /// A seed from the current time
+    pub fn time_seed() -> u64 {
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `bound`
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    /// Shuffle the first `n` items of `items` into a uniformly random choice of them
+    fn partial_shuffle<T>(&mut self, items: &mut [T], n: usize) {
+        for i in 0..n.min(items.len()) {
+            let j = i + self.below(items.len() - i);
+            items.swap(i, j);
+        }
+    }
+}
+
+/// Keep a random `sample` of `items`, if given, and put them in `order`. `path` is the testcase
+/// file of an item
+pub fn select_testcases<T, F>(items: Vec<T>, path: F, order: TestcaseOrder, sample: Option<usize>, rng: &mut SampleRng) -> Vec<T>
+where
+    F: Fn(&T) -> &Path,
+{
+    // Remember where each item was found, to restore the order after sampling
+    let mut items: Vec<(usize, T)> = items.into_iter().enumerate().collect();
+
+    if let Some(n) = sample {
+        rng.partial_shuffle(&mut items, n);
+        items.truncate(n);
+
+        if order != TestcaseOrder::random {
+            items.sort_by_key(|(i, _)| *i);
+        }
+    }
+
+    match order {
+        TestcaseOrder::found => (),
+        TestcaseOrder::newest => {
+            // Files that cannot be read sort last
+            items.sort_by_cached_key(|(_, item)| std::cmp::Reverse(
+                std::fs::metadata(path(item)).and_then(|m| m.modified()).ok()));
+        }
+        TestcaseOrder::smallest => {
+            items.sort_by_cached_key(|(_, item)| std::fs::metadata(path(item)).map(|m| m.len()).unwrap_or(u64::MAX));
+        }
+        TestcaseOrder::random => {
+            if sample.is_none() {
+                let len = items.len();
+                rng.partial_shuffle(&mut items, len);
+            }
+        }
+    }
+
+    items.into_iter().map(|(_, item)| item).collect()
+}
+
+/// Remove the items whose testcase has the same contents as an earlier one. Returns the items left
+/// and the duplicates removed, keyed by the index of their original in the items left. Only files
+/// with the same size are read, and files that cannot be read are kept
+pub fn remove_duplicate_testcases<T, F>(items: Vec<T>, path: F) -> (Vec<T>, HashMap<usize, Vec<T>>)
+where
+    F: Fn(&T) -> &Path,
+{
+    let sizes: Vec<Option<u64>> = items.iter()
+        .map(|item| std::fs::metadata(path(item)).map(|m| m.len()).ok())
+        .collect();
+
+    let mut size_counts: HashMap<u64, usize> = HashMap::new();
+
+    for size in sizes.iter().flatten() {
+        *size_counts.entry(*size).or_insert(0) += 1;
+    }
+
+    let mut unique = vec![];
+    let mut duplicates: HashMap<usize, Vec<T>> = HashMap::new();
+    let mut originals: HashMap<(u64, String), usize> = HashMap::new();
+
+    for (item, size) in items.into_iter().zip(sizes) {
+        let key = size.filter(|size| size_counts[size] > 1)
+            .and_then(|size| std::fs::read(path(&item)).ok().map(|data| (size, format!("{:x}", Sha256::digest(data)))));
+
+        match key {
+            Some(key) => match originals.get(&key) {
+                Some(original) => duplicates.entry(*original).or_default().push(item),
+                None => {
+                    originals.insert(key, unique.len());
+                    unique.push(item);
+                }
+            },
+            None => unique.push(item),
+        }
+    }
+
+    (unique, duplicates)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_testcase_filter() {
+        let crash = Path::new("out/default/crashes/id:000003,sig:11,src:000001,time:4000,execs:99,op:havoc,rep:2");
+        let abort = Path::new("out/default/crashes/id:000042,sig:06,src:000007,time:9000,op:flip1,pos:3");
+        let plain = Path::new("corpus/crash-0a1b2c");
+
+        let matches = |spec: &str| -> Vec<bool> {
+            let filter = TestcaseFilter::parse(spec).unwrap();
+            vec![filter.matches(crash), filter.matches(abort), filter.matches(plain)]
+        };
+
+        assert_eq!(matches("id:0000*"), vec![true, true, false]);
+        assert_eq!(matches("crash-*"), vec![false, false, true]);
+        assert_eq!(matches("regex:op:(havoc|splice)"), vec![true, false, false]);
+        assert_eq!(matches("sig=11"), vec![true, false, false]);
+        assert_eq!(matches("sig=SIGSEGV"), vec![true, false, false]);
+        assert_eq!(matches("sig=abrt"), vec![false, true, false]);
+        assert_eq!(matches("op!=havoc"), vec![false, true, true]);
+        assert_eq!(matches("time>=5000"), vec![false, true, false]);
+        assert_eq!(matches("id<10"), vec![true, false, false]);
+
+        assert!(TestcaseFilter::parse("op>havoc").is_err());
+        assert!(TestcaseFilter::parse("regex:(").is_err());
+    }
+
+    #[test]
+    fn test_select_testcases() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a", "b", "c", "d"].iter().zip([30, 10, 40, 20].iter())
+            .map(|(name, size)| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, vec![0u8; *size]).unwrap();
+                path
+            })
+            .collect();
+        let names = |selected: Vec<PathBuf>| -> Vec<String> {
+            selected.iter().map(|p| p.file_name().unwrap().to_string_lossy().to_string()).collect()
+        };
+        let select = |order, sample, seed| {
+            names(select_testcases(paths.clone(), |p| p.as_path(), order, sample, &mut SampleRng::new(seed)))
+        };
+
+        assert_eq!(select(TestcaseOrder::found, None, 1), vec!["a", "b", "c", "d"]);
+        assert_eq!(select(TestcaseOrder::smallest, None, 1), vec!["b", "d", "a", "c"]);
+
+        // Samples are in the requested order and repeat with the same seed
+        let sample = select(TestcaseOrder::found, Some(2), 7);
+        assert_eq!(sample.len(), 2);
+        assert!(sample[0] < sample[1]);
+        assert_eq!(select(TestcaseOrder::found, Some(2), 7), sample);
+        assert_eq!(select(TestcaseOrder::found, Some(10), 7), vec!["a", "b", "c", "d"]);
+
+        let mut shuffled = select(TestcaseOrder::random, None, 3);
+        shuffled.sort();
+        assert_eq!(shuffled, vec!["a", "b", "c", "d"]);
+
+        std::fs::write(dir.path().join("e"), vec![0u8; 30]).unwrap();
+        std::fs::write(dir.path().join("f"), vec![1u8; 30]).unwrap();
+        std::fs::write(dir.path().join("g"), vec![0u8; 10]).unwrap();
+        let mut all = paths.clone();
+        all.extend(["e", "f", "g", "missing"].iter().map(|name| dir.path().join(name)));
+
+        let (unique, duplicates) = remove_duplicate_testcases(all, |p| p.as_path());
+        assert_eq!(names(unique), vec!["a", "b", "c", "d", "f", "missing"]);
+        assert_eq!(duplicates.len(), 2);
+        assert_eq!(names(duplicates[&0].clone()), vec!["e"]);
+        assert_eq!(names(duplicates[&1].clone()), vec!["g"]);
+    }
+}
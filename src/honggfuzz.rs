@@ -0,0 +1,104 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Honggfuzz specific handling
+//!
+//! Honggfuzz saves each unique crash to its workspace (`-W`, the current directory by default) with
+//! a name describing it, like
+//! `SIGSEGV.PC.555555555b2e.STACK.1b4e1a5c8.CODE.1.ADDR.0.INSTR.mov____(%rax),%eax.fuzz`, and
+//! describes the crashes of a run in [REPORT_FILE]. With `--tmout_sigvtalrm`, timeouts are saved as
+//! `SIGVTALRM` crashes. The corpus (`*.honggfuzz.cov`) and any other files in the workspace are not
+//! crashes.
+use regex::Regex;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::util;
+
+/// The report Honggfuzz writes to its workspace
+pub const REPORT_FILE: &str = "HONGGFUZZ.REPORT.TXT";
+
+lazy_static! {
+    static ref R_CRASH_NAME: Regex = Regex::new(
+        r"^(?P<signal>SIG[A-Z0-9]+)\.PC\.(?P<pc>[0-9a-f]+)\.STACK\.(?P<stack>[0-9a-f]+)\.CODE\.(?P<code>-?[0-9]+)\.ADDR\.(?P<addr>(?:0x)?[0-9a-f]+)\.").unwrap();
+}
+
+/// The fields of a Honggfuzz crash file name
+#[derive(Debug, Clone, PartialEq)]
+pub struct HonggfuzzCrashName {
+    pub signal: String,
+    pub pc: u64,
+    /// Honggfuzz's hash of the crashing stack, which it deduplicates crashes with
+    pub stack_hash: u64,
+    pub code: i32,
+    pub address: u64,
+}
+
+impl HonggfuzzCrashName {
+    /// Parse the name of a crash file. Other file names give None
+    pub fn parse(name: &str) -> Option<HonggfuzzCrashName> {
+        let caps = R_CRASH_NAME.captures(name)?;
+        let hex = |s: &str| u64::from_str_radix(s.trim_start_matches("0x"), 16).ok();
+
+        Some(HonggfuzzCrashName {
+            signal: caps["signal"].to_string(),
+            pc: hex(&caps["pc"])?,
+            stack_hash: hex(&caps["stack"])?,
+            code: caps["code"].parse().ok()?,
+            address: hex(&caps["addr"])?,
+        })
+    }
+
+    /// Whether the crash is a timeout, saved with `--tmout_sigvtalrm`
+    pub fn is_timeout(&self) -> bool {
+        self.signal == "SIGVTALRM"
+    }
+}
+
+/// Heuristic to see if a directory seems like a Honggfuzz workspace
+pub fn is_honggfuzz_workspace(dir: &Path) -> bool {
+    dir.join(REPORT_FILE).is_file()
+}
+
+/// Collect the crashes (and timeouts if `include_hangs`) in a Honggfuzz workspace
+pub fn collect_crashes(workspace: &Path, include_hangs: bool) -> io::Result<Vec<PathBuf>> {
+    Ok(util::list_sorted_files_at(workspace)?
+        .into_iter()
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| HonggfuzzCrashName::parse(&name.to_string_lossy()))
+                .map(|crash| include_hangs || !crash.is_timeout())
+                .unwrap_or(false)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_honggfuzz_workspace() {
+        let crash = HonggfuzzCrashName::parse("SIGSEGV.PC.555555555b2e.STACK.1b4e1a5c8.CODE.1.ADDR.0.INSTR.mov____(%rax),%eax.fuzz").unwrap();
+        assert_eq!(crash.signal, "SIGSEGV");
+        assert_eq!(crash.pc, 0x555555555b2e);
+        assert_eq!(crash.stack_hash, 0x1b4e1a5c8);
+        assert_eq!((crash.code, crash.address), (1, 0));
+        assert!(!crash.is_timeout());
+        assert_eq!(HonggfuzzCrashName::parse("SIGABRT.PC.7ffff7a42e97.STACK.18f5d0c2b8.CODE.-6.ADDR.0x0.INSTR.mov____0x108(%rsp),%rcx.fuzz").unwrap().code, -6);
+        assert_eq!(HonggfuzzCrashName::parse("0b1e5a2fa4a3b39c9b4e56ad6a1cf5c4.000002a8.honggfuzz.cov"), None);
+
+        let workspace = tempfile::tempdir().unwrap();
+        assert!(!is_honggfuzz_workspace(workspace.path()));
+
+        for name in &[REPORT_FILE, "SIGSEGV.PC.555555555b2e.STACK.1b4e1a5c8.CODE.1.ADDR.0.INSTR.mov____(%rax),%eax.fuzz",
+                "SIGVTALRM.PC.555555555b40.STACK.0.CODE.-6.ADDR.0.INSTR.jmp____0x555555555b40.fuzz", "input.cov"] {
+            std::fs::write(workspace.path().join(name), "abc").unwrap();
+        }
+
+        assert!(is_honggfuzz_workspace(workspace.path()));
+        assert_eq!(collect_crashes(workspace.path(), false).unwrap().len(), 1);
+        assert_eq!(collect_crashes(workspace.path(), true).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Commands run around each testcase.
+//!
+//! Some targets need their environment reset between testcases, like a scratch directory
+//! recreated, a service restarted, or files left behind by the last run removed. With
+//! `--before-cmd` and `--after-cmd`, a shell command is run before the first run of each testcase
+//! and after its last one (including reruns and leak checks, and the profiling run of the first
+//! testcase), with the testcase's path in [TESTCASE_VAR]. Bucket minimization runs are not wrapped. The after command runs even if the before command failed, so it can clean up
+//! after it. Commands run outside of any sandbox and, with several jobs, concurrently for different
+//! testcases.
+use crate::process;
+
+/// The environment variable with the path of the testcase
+pub const TESTCASE_VAR: &str = "AFLTRIAGE_TESTCASE";
+
+/// How long a hook command may run
+const HOOK_TIMEOUT_MS: u64 = 30000;
+
+/// The commands run around each testcase
+#[derive(Debug, Default)]
+pub struct TestcaseHooks {
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Run `command` in the shell for `testcase`. `option` names the command in errors
+fn run_hook(option: &str, command: &str, testcase: &str) -> Result<(), String> {
+    // env sets the variable without quoting the path for the shell
+    let args = [&format!("{}={}", TESTCASE_VAR, testcase), "sh", "-c", command];
+
+    let result = process::execute_capture_output_timeout("env", &args, HOOK_TIMEOUT_MS, None)
+        .map_err(|e| format!("Failed to execute {}: {}", option, e))?;
+
+    if !result.status.success() {
+        return Err(format!("{} failed ({}): {}", option, result.status, result.stderr.trim()));
+    }
+
+    Ok(())
+}
+
+impl TestcaseHooks {
+    /// Run the before command for `testcase`, if there is one
+    pub fn before(&self, testcase: &str) -> Result<(), String> {
+        match &self.before {
+            Some(command) => run_hook("--before-cmd", command, testcase),
+            None => Ok(()),
+        }
+    }
+
+    /// Run the after command for `testcase`, if there is one
+    pub fn after(&self, testcase: &str) -> Result<(), String> {
+        match &self.after {
+            Some(command) => run_hook("--after-cmd", command, testcase),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_testcase_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let testcase = "crashes/id:000000,sig:11 it's";
+
+        let hooks = TestcaseHooks {
+            before: Some(format!("echo \"before $AFLTRIAGE_TESTCASE\" >> '{}'", log.display())),
+            after: Some("echo cleanup failed >&2; exit 3".into()),
+        };
+
+        hooks.before(testcase).unwrap();
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "before crashes/id:000000,sig:11 it's\n");
+        assert_eq!(hooks.after(testcase).unwrap_err(), "--after-cmd failed (exit status: 3): cleanup failed");
+        assert_eq!(TestcaseHooks::default().before(testcase), Ok(()));
+    }
+}
@@ -0,0 +1,334 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! How testcases are given to the target.
+//!
+//! By default the target reads the testcase from a file, with its path substituted for `@@` on the
+//! command line. It can also be written to the target's stdin, or sent over a TCP or UDP socket on
+//! localhost for network harnesses. A [SocketInput] waits for the target to listen on its port,
+//! which it learns from `/proc/net` instead of probing (a probe would be the first connection the
+//! target accepts), then sends the testcase. TCP connections are drained until triage ends so a
+//! target writing a response is not killed by `SIGPIPE`.
+//!
+//! Harnesses that read a named pipe are given a [FifoInput] instead: a FIFO created for each run,
+//! whose path is substituted for `@@`. The testcase is written to it once the target opens it for
+//! reading, and the FIFO is closed after it so the target reads end of file.
+//!
+//! When the testcase is not given on stdin, [ChildStdin] decides what the target reads from it
+//! instead. Targets that read stdin can hang waiting on a terminal, so it is `/dev/null` by default.
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{Shutdown, TcpStream, UdpSocket};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How often to check if the target is listening
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// The TCP state of a listening socket in `/proc/net/tcp`
+const TCP_LISTEN: &str = "0A";
+/// The largest payload of a UDP datagram
+const MAX_UDP_PAYLOAD: usize = 65507;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SocketProtocol {
+    Tcp,
+    Udp,
+}
+
+/// A localhost port the target receives the testcase on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SocketInput {
+    pub protocol: SocketProtocol,
+    pub port: u16,
+}
+
+/// How the target is given a testcase
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputMode {
+    /// A file path substituted for `@@`
+    File,
+    Stdin,
+    Socket(SocketInput),
+    /// A named pipe substituted for `@@`
+    Fifo,
+}
+
+impl InputMode {
+    /// Parse an input mode: `file`, `stdin`, `fifo`, `tcp:PORT`, or `udp:PORT`
+    pub fn parse(spec: &str) -> std::result::Result<InputMode, String> {
+        let invalid = || format!("Invalid input mode '{}'. Expected file, stdin, fifo, tcp:PORT, or udp:PORT", spec);
+
+        match spec.split_once(':') {
+            None if spec == "file" => Ok(InputMode::File),
+            None if spec == "stdin" => Ok(InputMode::Stdin),
+            None if spec == "fifo" => Ok(InputMode::Fifo),
+            Some((protocol, port)) => {
+                let protocol = match protocol {
+                    "tcp" => SocketProtocol::Tcp,
+                    "udp" => SocketProtocol::Udp,
+                    _ => return Err(invalid()),
+                };
+                let port = port.parse().ok().filter(|&p| p != 0).ok_or_else(invalid)?;
+
+                Ok(InputMode::Socket(SocketInput { protocol, port }))
+            }
+            None => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for SocketInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.protocol {
+            SocketProtocol::Tcp => write!(f, "tcp:{}", self.port),
+            SocketProtocol::Udp => write!(f, "udp:{}", self.port),
+        }
+    }
+}
+
+/// The target's stdin when it is not used to give the target the testcase
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[allow(non_camel_case_types)]
+pub enum ChildStdin {
+    #[default]
+    null,
+    closed,
+    inherit,
+}
+
+impl ChildStdin {
+    /// The values of `--child-stdin`
+    pub fn variants() -> [&'static str; 3] {
+        ["null", "closed", "inherit"]
+    }
+}
+
+impl std::str::FromStr for ChildStdin {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<ChildStdin, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "null" => Ok(ChildStdin::null),
+            "closed" => Ok(ChildStdin::closed),
+            "inherit" => Ok(ChildStdin::inherit),
+            _ => Err(format!("valid values: {}", ChildStdin::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for ChildStdin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// The local ports of sockets in a `/proc/net/{tcp,tcp6,udp,udp6}` table. Only listening sockets
+/// are included for TCP
+fn bound_ports(table: &str, protocol: SocketProtocol) -> Vec<u16> {
+    table.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local_address = fields.get(1)?;
+            let state = fields.get(3)?;
+
+            if protocol == SocketProtocol::Tcp && *state != TCP_LISTEN {
+                return None;
+            }
+
+            u16::from_str_radix(local_address.rsplit(':').next()?, 16).ok()
+        })
+        .collect()
+}
+
+impl SocketInput {
+    /// Whether a socket is listening on the port
+    pub fn is_listening(&self) -> bool {
+        let tables: &[&str] = match self.protocol {
+            SocketProtocol::Tcp => &["/proc/net/tcp", "/proc/net/tcp6"],
+            SocketProtocol::Udp => &["/proc/net/udp", "/proc/net/udp6"],
+        };
+
+        tables.iter()
+            .filter_map(|t| std::fs::read_to_string(Path::new(t)).ok())
+            .any(|table| bound_ports(&table, self.protocol).contains(&self.port))
+    }
+
+    /// Send `data` to the target once it listens on the port. Returns early, without sending, if
+    /// `done` is set before then (e.g. the target crashed or exited first). TCP connections are
+    /// kept open and drained until `done` is set
+    pub fn deliver(&self, data: &[u8], done: &AtomicBool) -> Result<()> {
+        while !self.is_listening() {
+            if done.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        match self.protocol {
+            SocketProtocol::Tcp => {
+                let mut stream = TcpStream::connect(("localhost", self.port))?;
+                stream.write_all(data)?;
+                stream.shutdown(Shutdown::Write)?;
+                stream.set_read_timeout(Some(POLL_INTERVAL * 10))?;
+
+                let mut buf = [0; 4096];
+
+                while !done.load(Ordering::SeqCst) {
+                    match stream.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(_) => (),
+                        Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => (),
+                        Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                        Err(_) => break,
+                    }
+                }
+
+                Ok(())
+            }
+            SocketProtocol::Udp => {
+                if data.len() > MAX_UDP_PAYLOAD {
+                    return Err(Error::new(ErrorKind::InvalidInput,
+                        format!("{} bytes do not fit in a UDP datagram", data.len())));
+                }
+
+                let socket = UdpSocket::bind(("127.0.0.1", 0))?;
+                socket.send_to(data, ("127.0.0.1", self.port))?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A named pipe the target reads a testcase from. It is removed when dropped
+pub struct FifoInput {
+    _dir: tempfile::TempDir,
+    path: PathBuf,
+}
+
+impl FifoInput {
+    /// Create a FIFO in a new temporary directory
+    pub fn new() -> Result<FifoInput> {
+        let dir = tempfile::Builder::new().prefix("afltriage-fifo").tempdir()?;
+        let path = dir.path().join("input");
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+
+        // SAFETY: the path is a valid C string
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(FifoInput { _dir: dir, path })
+    }
+
+    /// The path substituted for `@@`
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `data` to the FIFO once the target opens it, then close it. Returns early, without
+    /// writing, if `done` is set before then (e.g. the target crashed or exited first)
+    pub fn deliver(&self, data: &[u8], done: &AtomicBool) -> Result<()> {
+        // Opening a FIFO for writing without blocking fails until there is a reader
+        let mut fifo = loop {
+            match OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(&self.path) {
+                Ok(fifo) => break fifo,
+                Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                    if done.load(Ordering::SeqCst) {
+                        return Ok(());
+                    }
+
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        // Block on a full pipe instead. If the target stops reading, the write fails once it exits
+        // SAFETY: only changes the flags of the open file
+        unsafe {
+            let flags = libc::fcntl(fifo.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(fifo.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK);
+        }
+
+        match fifo.write_all(data) {
+            // The target does not have to read everything
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_input_mode() {
+        assert_eq!(InputMode::parse("file").unwrap(), InputMode::File);
+        assert_eq!(InputMode::parse("stdin").unwrap(), InputMode::Stdin);
+        assert_eq!(InputMode::parse("tcp:8080").unwrap(),
+            InputMode::Socket(SocketInput { protocol: SocketProtocol::Tcp, port: 8080 }));
+        assert_eq!(InputMode::parse("udp:53").unwrap(),
+            InputMode::Socket(SocketInput { protocol: SocketProtocol::Udp, port: 53 }));
+        assert!(InputMode::parse("tcp:0").is_err());
+        assert!(InputMode::parse("tcp:http").is_err());
+        assert!(InputMode::parse("sctp:80").is_err());
+        assert!(InputMode::parse("socket").is_err());
+        assert_eq!(InputMode::parse("fifo").unwrap(), InputMode::Fifo);
+
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n\
+           0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 920 1\n\
+           1: 0100007F:A3F2 0100007F:1F90 01 00000000:00000000 00:00000000 00000000  1000        0 921 1\n";
+        assert_eq!(bound_ports(table, SocketProtocol::Tcp), vec![8080]);
+        assert_eq!(bound_ports(table, SocketProtocol::Udp), vec![8080, 0xa3f2]);
+    }
+
+    #[test]
+    fn test_tcp_delivery() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = SocketInput { protocol: SocketProtocol::Tcp, port: listener.local_addr().unwrap().port() };
+        assert!(socket.is_listening());
+
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            let sender = s.spawn(|| socket.deliver(b"crash", &done));
+
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = vec![];
+            stream.read_to_end(&mut received).unwrap();
+            assert_eq!(received, b"crash");
+
+            // The target's response is drained
+            stream.write_all(b"ok").unwrap();
+            done.store(true, Ordering::SeqCst);
+            sender.join().unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_fifo_delivery() {
+        let fifo = FifoInput::new().unwrap();
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            let sender = s.spawn(|| fifo.deliver(b"crash", &done));
+
+            let mut received = vec![];
+            std::fs::File::open(fifo.path()).unwrap().read_to_end(&mut received).unwrap();
+            assert_eq!(received, b"crash");
+            sender.join().unwrap().unwrap();
+        });
+
+        // Nothing is written if the target never opens the FIFO
+        done.store(true, Ordering::SeqCst);
+        fifo.deliver(b"crash", &done).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! File GitHub issues for the crash buckets of a previous triage run.
+//!
+//! `afltriage issues <output>` reads the crashes saved with `--save-results` and renders an issue
+//! for the first crash of every bucket that does not have one yet. With `--repo`, issues are filed
+//! with the GitHub CLI (`gh`). Otherwise they are written to the output directory as markdown,
+//! ready to paste. Either way, the buckets are recorded in the state file so that later runs skip
+//! them.
+use clap::{App, AppSettings, Arg, ArgMatches};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+
+use afltriage::{report, util};
+use afltriage::analysis::{AnalysisPipeline, BucketPass, CrashAnalysis};
+use afltriage::bucket::CrashBucketStrategy;
+use afltriage::report::issue::{self, Issue, IssueRecord, IssueState};
+
+pub fn build_app() -> App<'static, 'static> {
+    App::new("afltriage issues")
+        .about("File (or render ready to paste) a GitHub issue for each new crash bucket saved with --save-results")
+        .usage("afltriage issues [OPTIONS] <output>")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .setting(AppSettings::DeriveDisplayOrder)
+        .arg(Arg::with_name("output")
+             .required(true)
+             .help("An output directory from a triage run using --save-results."))
+        .arg(Arg::with_name("repo")
+             .long("--repo")
+             .takes_value(true)
+             .value_name("OWNER/REPO")
+             .help("File the issues in this GitHub repository using the GitHub CLI (gh). Without it, issues are written to the output directory as markdown."))
+        .arg(Arg::with_name("label")
+             .long("--label")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .help("Add a label to filed issues. Can be given more than once."))
+        .arg(Arg::with_name("state")
+             .long("--state")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("The file recording which buckets already have an issue. Share it between runs to avoid filing a bucket twice. Defaults to afltriage_issues.json in the output directory."))
+        .arg(Arg::with_name("max_issues")
+             .long("--max-issues")
+             .takes_value(true)
+             .value_name("N")
+             .help("File at most this many issues, most severe buckets first."))
+        .arg(crate::severity_rule_arg())
+}
+
+/// File an issue with `gh`. Returns its URL
+fn file_issue(repo: &str, labels: &[&str], issue: &Issue) -> Result<String, String> {
+    let mut command = Command::new("gh");
+    command.args(["issue", "create", "--repo", repo, "--title", &issue.title, "--body-file", "-"]);
+
+    for label in labels {
+        command.args(["--label", label]);
+    }
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run gh: {}", e))?;
+
+    child.stdin.take().unwrap().write_all(issue.body.as_bytes())
+        .map_err(|e| format!("Failed to write the issue to gh: {}", e))?;
+
+    let output = child.wait_with_output().map_err(|e| format!("Failed to run gh: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("gh failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Run `afltriage issues`. Returns the process exit code
+pub fn issues_main(args: &ArgMatches) -> i32 {
+    let output_dir = Path::new(args.value_of("output").unwrap());
+    let results_path = output_dir.join(report::SAVED_RESULTS_FILE);
+    let state_path = args.value_of("state")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| output_dir.join(issue::ISSUE_STATE_FILE));
+    let repo = args.value_of("repo");
+    let labels: Vec<&str> = args.values_of("label").unwrap_or_default().collect();
+    let max_issues = if args.is_present("max_issues") {
+        value_t!(args, "max_issues", usize).unwrap_or_else(|e| e.exit())
+    } else {
+        usize::MAX
+    };
+
+    if repo.is_some() && which::which("gh").is_err() {
+        log::error!("Filing issues with --repo requires the GitHub CLI (gh) in the PATH");
+        return 1;
+    }
+
+    let saved = match crate::rebucket::read_saved_results(&results_path) {
+        Ok(saved) => saved,
+        Err(e) => {
+            log::error!("Failed to read saved results from {}: {}", shlex::quote(&results_path.to_string_lossy()), e);
+            log::error!("Triage with --save-results to be able to file issues");
+            return 1;
+        }
+    };
+
+    let mut state = match IssueState::load(&state_path) {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("Failed to read the issue state {}: {}", shlex::quote(&state_path.to_string_lossy()), e);
+            return 1;
+        }
+    };
+
+    // Buckets were already assigned during triage
+    let classify_pass = match crate::classify_pass_from_args(args) {
+        Ok(pass) => pass,
+        Err(e) => {
+            log::error!("{}", e);
+            return 1;
+        }
+    };
+
+    let pipeline = AnalysisPipeline::new(BucketPass::new(CrashBucketStrategy::none), classify_pass, &["bucket"]);
+    let mut seen = HashSet::new();
+    let mut new_buckets = vec![];
+    let mut existing = 0;
+
+    for result in &saved {
+        if !seen.insert(result.bucket.as_str()) {
+            continue;
+        }
+
+        if state.contains(&result.bucket, repo.is_some()) {
+            existing += 1;
+            continue;
+        }
+
+        let CrashAnalysis { enriched, severity, .. } = match pipeline.run(&result.envelope.testcase, &result.triage, &result.envelope.report_options) {
+            Ok(analysis) => analysis,
+            Err(e) => {
+                log::error!("{}: {}", shlex::quote(&result.envelope.testcase), e);
+                return 1;
+            }
+        };
+
+        new_buckets.push((severity, result, issue::format_issue(&enriched, &result.envelope, severity), enriched));
+    }
+
+    // Most severe first, otherwise in the order they were found
+    new_buckets.sort_by_key(|b| std::cmp::Reverse(b.0));
+
+    let skipped = new_buckets.len().saturating_sub(max_issues);
+    let mut created = 0;
+    let mut failed = false;
+
+    for (_, result, issue, enriched) in new_buckets.into_iter().take(max_issues) {
+        let created_at = util::format_iso8601(SystemTime::now(), false);
+
+        let record = match repo {
+            Some(repo) => match file_issue(repo, &labels, &issue) {
+                Ok(url) => {
+                    log::info!("Filed {} for bucket {}", url, result.bucket);
+                    IssueRecord { title: issue.title, url: Some(url), file: None, created_at }
+                }
+                Err(e) => {
+                    log::error!("Failed to file an issue for bucket {}: {}", result.bucket, e);
+                    failed = true;
+                    break;
+                }
+            },
+            None => {
+                let name = format!("{}.issue.md", report::report_filename_stem(&enriched, &result.bucket));
+
+                if let Err(e) = std::fs::write(output_dir.join(&name), format!("# {}\n\n{}", issue.title, issue.body)) {
+                    log::error!("Failed to write issue {}: {}", shlex::quote(&name), e);
+                    failed = true;
+                    break;
+                }
+
+                log::info!("Rendered {} for bucket {}", shlex::quote(&name), result.bucket);
+                IssueRecord { title: issue.title, url: None, file: Some(name), created_at }
+            }
+        };
+
+        state.issues.insert(result.bucket.to_string(), record);
+        created += 1;
+    }
+
+    if let Err(e) = state.save(&state_path) {
+        log::error!("Failed to save the issue state {}: {}", shlex::quote(&state_path.to_string_lossy()), e);
+        return 1;
+    }
+
+    log::info!("{} {} new issues for {} buckets, {} already had one",
+        if repo.is_some() { "Filed" } else { "Rendered" }, created, seen.len(), existing);
+
+    if skipped > 0 {
+        log::info!("Skipped {} buckets due to --max-issues", skipped);
+    }
+
+    if failed {
+        1
+    } else {
+        0
+    }
+}
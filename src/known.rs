@@ -0,0 +1,94 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Import and export the known bucket database of `--known-buckets`.
+//!
+//! `afltriage known <database>` merges other databases or lists of bucket identifiers into a
+//! database with `--import`, and writes its bucket identifiers, one per line, with `--export`.
+//! Without either, it lists the known buckets.
+use clap::{App, AppSettings, Arg, ArgMatches};
+use std::path::Path;
+
+use afltriage::report::known::KnownBuckets;
+
+pub fn build_app() -> App<'static, 'static> {
+    App::new("afltriage known")
+        .about("Import, export, or list the buckets of a --known-buckets database")
+        .usage("afltriage known [OPTIONS] <database>")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .setting(AppSettings::DeriveDisplayOrder)
+        .arg(Arg::with_name("database")
+             .required(true)
+             .help("The known bucket database. It is created if it does not exist."))
+        .arg(Arg::with_name("import")
+             .long("--import")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("FILE")
+             .help("Merge another database, or a list of bucket identifiers one per line, into the database. Can be given more than once."))
+        .arg(Arg::with_name("export")
+             .long("--export")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Write the identifiers of the known buckets to this file, one per line. Use - for stdout."))
+}
+
+/// Run `afltriage known`. Returns the process exit code
+pub fn known_main(args: &ArgMatches) -> i32 {
+    let path = Path::new(args.value_of("database").unwrap());
+
+    let mut known = match KnownBuckets::load(path) {
+        Ok(known) => known,
+        Err(e) => {
+            log::error!("Failed to read the known buckets {}: {}", shlex::quote(&path.to_string_lossy()), e);
+            return 1;
+        }
+    };
+
+    let imports: Vec<&str> = args.values_of("import").unwrap_or_default().collect();
+
+    for import in &imports {
+        let imported = std::fs::read_to_string(import)
+            .map_err(|e| e.to_string())
+            .and_then(|data| known.import(&data));
+
+        match imported {
+            Ok(new) => log::info!("Imported {} new bucket(s) from {}", new, import),
+            Err(e) => {
+                log::error!("Failed to import {}: {}", import, e);
+                return 1;
+            }
+        }
+    }
+
+    if !imports.is_empty() {
+        if let Err(e) = known.save(path) {
+            log::error!("Failed to write the known buckets {}: {}", shlex::quote(&path.to_string_lossy()), e);
+            return 1;
+        }
+
+        log::info!("{} bucket(s) are known", known.buckets.len());
+    }
+
+    match args.value_of("export") {
+        Some("-") => print!("{}", known.export_list()),
+        Some(export) => {
+            if let Err(e) = std::fs::write(export, known.export_list()) {
+                log::error!("Failed to export the known buckets to {}: {}", export, e);
+                return 1;
+            }
+
+            log::info!("Exported {} bucket(s) to {}", known.buckets.len(), export);
+        }
+        None if imports.is_empty() => {
+            for (bucket, entry) in &known.buckets {
+                println!("{} [{}] seen in {} run(s), last {}: {}", bucket, entry.severity.as_str(), entry.runs,
+                    entry.last_seen.as_deref().unwrap_or("never"), entry.summary);
+            }
+        }
+        None => (),
+    }
+
+    0
+}
@@ -0,0 +1,240 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! LeakSanitizer findings.
+//!
+//! LeakSanitizer checks for leaks when the target exits, so leaks are only found in runs that did
+//! not crash. It stops the target with ptrace to scan its memory, which it cannot do under GDB,
+//! and AFLTriage disables it with `detect_leaks=0` by default. With `--detect-leaks`, testcases
+//! that did not crash are run once more outside of the debugger with leak detection enabled and a
+//! leak report is turned into a [LeakFinding].
+//!
+//! Findings are bucketed apart from crashes, with a `leak_` prefix, by the allocation site of the
+//! largest direct leak: the first frames of its stack after the allocator's own.
+use crate::debugger::gdb::GdbTriageOptions;
+use crate::process::{self, ChildResult};
+use crate::util::shell_join;
+use regex::Regex;
+use serde::Serialize;
+
+/// How many frames of the allocation stack, after the allocator, a leak is bucketed by
+const BUCKET_FRAMES: usize = 3;
+
+lazy_static! {
+    static ref R_LEAK_REPORT: Regex = Regex::new(r"(?m)^==[0-9]+==ERROR: LeakSanitizer: detected memory leaks").unwrap();
+    static ref R_LEAK: Regex = Regex::new(
+        r"(?m)^(?P<kind>Direct|Indirect) leak of (?P<bytes>[0-9]+) byte\(s\) in (?P<objects>[0-9]+) object\(s\) allocated from:"
+    ).unwrap();
+    static ref R_LEAK_FRAME: Regex = Regex::new(
+        r"^\s*#[0-9]+\s+0x[a-fA-F0-9]+(\s+in\s+(?P<func>.+?))?(\s+(?P<file>[^\s()]+?):(?P<line>[0-9]+)(:[0-9]+)?|\s+\((?P<module>[^)]+)\))?\s*$"
+    ).unwrap();
+    static ref R_LEAK_SUMMARY: Regex = Regex::new(r"(?m)^SUMMARY: \w+: (?P<summary>.+?)\.?$").unwrap();
+    /// Frames of the allocator and the sanitizer runtime, which every leak shares
+    static ref R_ALLOCATOR_FRAME: Regex = Regex::new(
+        r"^(__interceptor_|___interceptor_|__asan_|__lsan_|__sanitizer_|operator new)|^(malloc|calloc|realloc|reallocarray|strdup|strndup|aligned_alloc|posix_memalign|memalign|valloc|pvalloc)$"
+    ).unwrap();
+}
+
+/// A leaked allocation site reported by LeakSanitizer
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Leak {
+    /// Direct leaks are unreachable. Indirect leaks are only reachable from other leaks
+    pub direct: bool,
+    pub bytes: u64,
+    pub objects: u64,
+    /// The frames of the allocation stack, as reported
+    pub stack: Vec<String>,
+}
+
+/// A run that did not crash, but leaked memory
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LeakFinding {
+    /// LeakSanitizer's summary, e.g. "80 byte(s) leaked in 3 allocation(s)"
+    pub description: String,
+    /// The bucket identifier, from the allocation site of the first direct leak
+    pub bucket: String,
+    /// The frame signatures used for bucketing
+    pub allocation_site: Vec<String>,
+    pub leaks: Vec<Leak>,
+}
+
+impl LeakFinding {
+    pub fn summary(&self) -> String {
+        match self.allocation_site.first() {
+            Some(site) => format!("LSAN detected memory leaks: {} from {}", self.description, site),
+            None => format!("LSAN detected memory leaks: {}", self.description),
+        }
+    }
+}
+
+/// A frame signature for bucketing: file:line, a function name, or a module. Allocator frames
+/// have none
+fn leak_frame_signature(frame: &str) -> Option<String> {
+    let frame = R_LEAK_FRAME.captures(frame)?;
+    let func = frame.name("func").map(|f| f.as_str());
+
+    if func.map(|f| R_ALLOCATOR_FRAME.is_match(f)).unwrap_or(false) {
+        return None;
+    }
+
+    match (frame.name("file"), frame.name("line"), func, frame.name("module")) {
+        (Some(file), Some(line), _, _) => Some(format!("{}:{}", file.as_str(), line.as_str())),
+        (_, _, Some(func), _) => Some(func.to_string()),
+        (_, _, _, Some(module)) => Some(module.as_str().to_string()),
+        _ => None,
+    }
+}
+
+/// Parse the leaks from a LeakSanitizer report, in the order they were reported
+fn parse_leaks(report: &str) -> Vec<Leak> {
+    let headers: Vec<regex::Captures> = R_LEAK.captures_iter(report).collect();
+
+    headers.iter().enumerate().map(|(i, header)| {
+        let start = header.get(0).unwrap().end();
+        let end = headers.get(i + 1).map(|h| h.get(0).unwrap().start()).unwrap_or(report.len());
+
+        Leak {
+            direct: &header["kind"] == "Direct",
+            bytes: header["bytes"].parse().unwrap_or(0),
+            objects: header["objects"].parse().unwrap_or(0),
+            stack: report[start..end].lines()
+                .map(str::trim)
+                .take_while(|l| l.starts_with('#') || l.is_empty())
+                .filter(|l| !l.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }).collect()
+}
+
+/// Find a LeakSanitizer report in the target's stderr and turn it into a finding
+pub fn leak_finding_from_output(stderr: &str) -> Option<LeakFinding> {
+    let start = R_LEAK_REPORT.find(stderr)?.start();
+    let report = &stderr[start..];
+    let leaks = parse_leaks(report);
+
+    // LeakSanitizer reports the largest direct leaks first
+    let allocation_site: Vec<String> = leaks.iter()
+        .find(|l| l.direct)
+        .or_else(|| leaks.first())
+        .map(|l| l.stack.iter().filter_map(|f| leak_frame_signature(f)).take(BUCKET_FRAMES).collect())
+        .unwrap_or_default();
+
+    let mut hash = md5::Context::new();
+
+    for frame in &allocation_site {
+        hash.consume(frame.as_bytes());
+    }
+
+    let description = R_LEAK_SUMMARY.captures(report)
+        .map(|c| c["summary"].to_string())
+        .unwrap_or_else(|| format!("{} byte(s) leaked in {} allocation(s)",
+            leaks.iter().map(|l| l.bytes).sum::<u64>(), leaks.iter().map(|l| l.objects).sum::<u64>()));
+
+    Some(LeakFinding {
+        description,
+        bucket: format!("leak_{:x}", hash.compute()),
+        allocation_site,
+        leaks,
+    })
+}
+
+/// The `ASAN_OPTIONS` to check for leaks with: those of the target with leak detection enabled
+fn leak_check_asan_options(options: &GdbTriageOptions) -> String {
+    let target_options = options.target_var("ASAN_OPTIONS").unwrap_or_default();
+
+    // Later options take precedence
+    match target_options.as_str() {
+        "" => "detect_leaks=1".to_string(),
+        _ => format!("{}:detect_leaks=1", target_options),
+    }
+}
+
+/// Run the target outside of the debugger with leak detection enabled. `prog_args` is the target
+/// command with the testcase already substituted
+pub fn run_leak_check(options: &GdbTriageOptions, prog_args: &[String], input: Option<Vec<u8>>, timeout_ms: u64)
+    -> std::io::Result<ChildResult> {
+    let mut args: Vec<String> = vec![];
+
+    if options.clear_env {
+        args.push("-i".into());
+    }
+
+    args.extend(options.target_env.iter().map(|(k, v)| format!("{}={}", k, v)));
+    args.push(format!("ASAN_OPTIONS={}", leak_check_asan_options(options)));
+    args.extend(prog_args.iter().cloned());
+
+    let (program, args) = match &options.sandbox {
+        Some(sandbox) => sandbox.wrap("env", &args,
+            &prog_args.iter().map(std::path::Path::new).collect::<Vec<&std::path::Path>>()),
+        None => ("env".to_string(), args),
+    };
+
+    process::execute_capture_output_timeout(&program, &args, timeout_ms, input)
+}
+
+/// Render a text report of a finding
+pub fn format_text_report(finding: &LeakFinding, command_line: &[String], testcase: &str) -> String {
+    let mut report = format!("Summary: {}\n", finding.summary());
+    report += &format!("Command line: {}\n", shell_join(command_line));
+    report += &format!("Testcase: {}\n", shlex::quote(testcase));
+    report += &format!("Leak bucket: {}\n", finding.bucket);
+
+    for leak in &finding.leaks {
+        report += &format!("\n{} leak of {} byte(s) in {} object(s) allocated from:\n",
+            if leak.direct { "Direct" } else { "Indirect" }, leak.bytes, leak.objects);
+
+        for frame in &leak.stack {
+            report += &format!("  {}\n", frame);
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_leak_finding() {
+        let mut path = PathBuf::from(file!());
+        path.pop();
+        path.push("report");
+        path.push("res");
+        path.push("test_sanitizer_reports");
+        path.push("lsan_leak.txt");
+
+        let stderr = std::fs::read_to_string(path).unwrap();
+        let finding = leak_finding_from_output(&stderr).unwrap();
+
+        assert_eq!(finding.description, "80 byte(s) leaked in 3 allocation(s)");
+        assert_eq!(finding.allocation_site, vec!["/tmp/records.c:31", "/tmp/records.c:58", "/tmp/records.c:80"]);
+        assert!(finding.bucket.starts_with("leak_"));
+        assert_eq!(finding.summary(), "LSAN detected memory leaks: 80 byte(s) leaked in 3 allocation(s) from /tmp/records.c:31");
+
+        assert_eq!(finding.leaks.len(), 2);
+        assert_eq!((finding.leaks[0].direct, finding.leaks[0].bytes, finding.leaks[0].objects), (true, 64, 2));
+        assert_eq!(finding.leaks[0].stack.len(), 5);
+        assert_eq!((finding.leaks[1].direct, finding.leaks[1].bytes), (false, 16));
+        assert_eq!(leak_frame_signature(&finding.leaks[1].stack[0]), None);
+        assert_eq!(leak_frame_signature("#4 0x7f3a9c21d082  (/lib/x86_64-linux-gnu/libc.so.6+0x24082)").unwrap(),
+            "/lib/x86_64-linux-gnu/libc.so.6+0x24082");
+
+        // Another leak from the same site shares the bucket
+        let other = stderr.replace("64 byte(s) in 2", "32 byte(s) in 1").replace("80 byte(s) leaked in 3", "48 byte(s) leaked in 2");
+        assert_eq!(leak_finding_from_output(&other).unwrap().bucket, finding.bucket);
+
+        assert_eq!(leak_finding_from_output("parsed 3 records\n"), None);
+    }
+
+    #[test]
+    fn test_leak_check_asan_options() {
+        let options = GdbTriageOptions {
+            target_env: vec![("ASAN_OPTIONS".into(), "abort_on_error=1:detect_leaks=0".into())],
+            ..Default::default()
+        };
+        assert_eq!(leak_check_asan_options(&options), "abort_on_error=1:detect_leaks=0:detect_leaks=1");
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+/*!
+  AFLTriage is a tool to process a target program's crashing input files using a debugger.
+
+  When fuzzing a target program, one or more crashes may be found. It is up to a developer or
+  researcher to understand their root cause and impact in order to take the appropriate action.
+  Depending on the program and platform, a debugger may be available to inspect the memory,
+  registers, and stack trace at the time of the crash. If there are many crashes or a debugger
+  takes a long time to triage a crash, using one can be quite tedious.
+
+  AFLTriage automates the process of using a [debugger](./debugger/index.html) in parallel to collect
+  additional context for a set of crashing inputs.  With crash triage data it uses strategies to
+  deduplicate (or [bucket](./bucket/index.html)) crashes to reduce the amount of reports generated.
+  Once all target and crash context is collected, AFLTriage emits [reports](./report/index.html)
+  for an analyst to look at and take action on.
+
+  Additionally when fuzzing C and C++ code, Sanitizers such as AddressSanitizer, can be enabled to
+  detect undefined behavior at the moment of occurrence, greatly improving the likelihood of
+  successfully root causing a crash. AFLTriage captures this information, if available, and uses it
+  to augment its reporting.
+
+  Linux targets are triaged under GDB, or Valgrind Memcheck with `--valgrind`. Windows targets are
+  triaged under CDB, macOS targets under LLDB, Android targets under gdbserver on the device, and
+  bare-metal firmware through a remote GDB server.
+
+  ## Library Usage
+
+  Besides the `afltriage` command line tool, AFLTriage can be embedded into other tools (e.g.
+  custom fuzzing orchestrators) as a library. The main entry points are:
+
+  - [GdbTriager](debugger::gdb::GdbTriager) to run a target under GDB and collect a
+    [GdbTriageResult](debugger::gdb::GdbTriageResult)
+  - [AnalysisPipeline](analysis::AnalysisPipeline) to enrich, bucket, and classify a crash
+  - [render_reports](report::render_reports) to produce text or JSON reports
+
+  ```no_run
+  use afltriage::analysis::{AnalysisPipeline, BucketPass, ClassifyPass};
+  use afltriage::bucket::CrashBucketStrategy;
+  use afltriage::debugger::gdb::GdbTriager;
+  use afltriage::ReportOptions;
+
+  let gdb = GdbTriager::new("gdb".into());
+  let args = vec!["./target".to_string(), "crash_input".to_string()];
+  let triage = gdb.triage_program(&args, None, false, 60000).unwrap();
+
+  let pipeline = AnalysisPipeline::new(BucketPass::new(CrashBucketStrategy::afltriage), ClassifyPass::default(), &[]);
+  let opt = ReportOptions::default();
+  let analysis = pipeline.run("crash_input", &triage, &opt).unwrap();
+
+  println!("{} (bucket {})", analysis.enriched.summary, analysis.bucket.strategy_result);
+  ```
+
+  ## AFLTriage High Level Flow
+
+  1. Environment check
+       - Debugger check: For GDB its version and Python version are checked
+       - Target exists and is executable and the command line is okay
+       - Environment variables are processed
+  1. Input categorization
+       - For each input path categorize it into: single file, directory with files, AFL directory, or AFL sync directory
+  1. Input collection
+       - Resolve all paths to input files in a stable order
+  1. Profile
+       - See how the target operates under the debugger
+  1. Triage
+       - In parallel, for each input file, triage using a debugger, process debugger data, and bucket crashes
+  1. Report
+       - With triage information, emit reports in the formats requested (text/json/raw)
+ */
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod afl;
+pub mod analysis;
+pub mod bucket;
+pub mod coverage;
+pub mod debugger;
+pub mod environment;
+pub mod filter;
+pub mod honggfuzz;
+pub mod hooks;
+pub mod input;
+pub mod leak;
+pub mod libfuzzer;
+pub mod metrics;
+pub mod minidump;
+pub mod minimize;
+pub mod nocrash;
+pub mod notify;
+pub mod oracle;
+pub mod platform;
+pub mod preprocess;
+pub mod process;
+pub mod proto;
+pub mod report;
+pub mod sandbox;
+pub mod sourcemap;
+pub mod util;
+
+pub use report::{ReportEnvelope, ReportOptions, ReportOutputFormat};
@@ -0,0 +1,139 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! libFuzzer specific handling
+//!
+//! libFuzzer saves each crashing input as an artifact named after what went wrong and the SHA-1 of
+//! the input, like `crash-2aae6c35c94fcfb415dbe95f408b9ce91ee846ed`. The name is appended to the
+//! `-artifact_prefix` option as a string, so the prefix is either a directory (`out/`) or a
+//! directory and the start of a file name (`out/fuzz-`), and artifacts are in the current directory
+//! without one. An [ArtifactPrefix] finds the artifacts of a prefix given the same way.
+use regex::Regex;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::util;
+
+lazy_static! {
+    static ref R_ARTIFACT_NAME: Regex = Regex::new(
+        r"(?P<kind>crash|leak|oom|timeout|slow-unit)-[0-9a-f]{40}$").unwrap();
+}
+
+/// Why libFuzzer saved an artifact
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArtifactKind {
+    Crash,
+    Leak,
+    /// The input exceeded `-rss_limit_mb` or `-malloc_limit_mb`
+    OutOfMemory,
+    /// The input ran longer than `-timeout`
+    Timeout,
+    /// The input ran longer than `-report_slow_units`
+    SlowUnit,
+}
+
+impl ArtifactKind {
+    /// Whether the artifact is a hang rather than a crash
+    pub fn is_hang(&self) -> bool {
+        matches!(self, ArtifactKind::Timeout | ArtifactKind::SlowUnit)
+    }
+}
+
+/// The kind of the artifact named `name` with the file name prefix `prefix`, if it is one
+pub fn parse_artifact_name(name: &str, prefix: &str) -> Option<ArtifactKind> {
+    let caps = R_ARTIFACT_NAME.captures(name.strip_prefix(prefix)?)?;
+
+    // The prefix must be followed by the kind immediately
+    if caps.get(0).unwrap().start() != 0 {
+        return None;
+    }
+
+    Some(match &caps["kind"] {
+        "crash" => ArtifactKind::Crash,
+        "leak" => ArtifactKind::Leak,
+        "oom" => ArtifactKind::OutOfMemory,
+        "timeout" => ArtifactKind::Timeout,
+        _ => ArtifactKind::SlowUnit,
+    })
+}
+
+/// An `-artifact_prefix`: the directory libFuzzer writes artifacts to and the start of their names
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtifactPrefix {
+    pub dir: PathBuf,
+    pub file_prefix: String,
+}
+
+impl ArtifactPrefix {
+    /// Split a prefix like `out/fuzz-`. An existing directory is taken as a directory even without
+    /// a trailing slash
+    pub fn new(prefix: &Path) -> ArtifactPrefix {
+        let prefix_str = prefix.to_string_lossy();
+
+        if prefix.is_dir() || prefix_str.ends_with('/') {
+            return ArtifactPrefix { dir: prefix.to_path_buf(), file_prefix: String::new() };
+        }
+
+        let dir = match prefix.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        ArtifactPrefix {
+            dir,
+            file_prefix: prefix.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+        }
+    }
+
+    /// Collect the crashing artifacts (and timeouts and slow units if `include_hangs`) with this
+    /// prefix
+    pub fn collect_artifacts(&self, include_hangs: bool) -> io::Result<Vec<PathBuf>> {
+        Ok(util::list_sorted_files_at(&self.dir)?
+            .into_iter()
+            .filter(|path| path.is_file())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| parse_artifact_name(&name.to_string_lossy(), &self.file_prefix))
+                    .map(|kind| include_hangs || !kind.is_hang())
+                    .unwrap_or(false)
+            })
+            .collect())
+    }
+
+    /// Whether there are any artifacts, crashes or hangs, with this prefix
+    pub fn has_artifacts(&self) -> bool {
+        self.collect_artifacts(true).map(|a| !a.is_empty()).unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_libfuzzer_artifacts() {
+        let sha1 = "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed";
+        assert_eq!(parse_artifact_name(&format!("crash-{}", sha1), ""), Some(ArtifactKind::Crash));
+        assert_eq!(parse_artifact_name(&format!("fuzz-slow-unit-{}", sha1), "fuzz-"), Some(ArtifactKind::SlowUnit));
+        assert_eq!(parse_artifact_name(&format!("fuzz-oom-{}", sha1), ""), None);
+        assert_eq!(parse_artifact_name(&format!("crash-{}", sha1), "fuzz-"), None);
+        assert_eq!(parse_artifact_name("crash-1234", ""), None);
+
+        assert_eq!(ArtifactPrefix::new(Path::new("fuzz-")), ArtifactPrefix { dir: ".".into(), file_prefix: "fuzz-".into() });
+        assert_eq!(ArtifactPrefix::new(Path::new("out/")), ArtifactPrefix { dir: "out/".into(), file_prefix: "".into() });
+
+        let out = tempfile::tempdir().unwrap();
+        for name in &[format!("fuzz-crash-{}", sha1), format!("fuzz-timeout-{}", sha1), format!("leak-{}", sha1), "corpus".into()] {
+            std::fs::write(out.path().join(name), "abc").unwrap();
+        }
+
+        let prefix = ArtifactPrefix::new(&out.path().join("fuzz-"));
+        assert_eq!(prefix.dir, out.path());
+        assert_eq!(prefix.collect_artifacts(false).unwrap(), vec![out.path().join(format!("fuzz-crash-{}", sha1))]);
+        assert_eq!(prefix.collect_artifacts(true).unwrap().len(), 2);
+
+        let dir = ArtifactPrefix::new(out.path());
+        assert_eq!(dir.collect_artifacts(false).unwrap(), vec![out.path().join(format!("leak-{}", sha1))]);
+        assert!(!ArtifactPrefix::new(&out.path().join("other-")).has_artifacts());
+    }
+}
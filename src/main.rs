@@ -1,130 +1,175 @@
 // Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
 //
 // SPDX-License-Identifier: BSD-3-Clause
-/*!
-  AFLTriage is a tool to process a target program's crashing input files using a debugger.
-
-  When fuzzing a target program, one or more crashes may be found. It is up to a developer or
-  researcher to understand their root cause and impact in order to take the appropriate action.
-  Depending on the program and platform, a debugger may be available to inspect the memory,
-  registers, and stack trace at the time of the crash. If there are many crashes or a debugger
-  takes a long time to triage a crash, using one can be quite tedious.
-
-  AFLTriage automates the process of using a [debugger](./debugger/index.html) in parallel to collect
-  additional context for a set of crashing inputs.  With crash triage data it uses strategies to
-  deduplicate (or [bucket](./bucket/index.html)) crashes to reduce the amount of reports generated.
-  Once all target and crash context is collected, AFLTriage emits [reports](./report/index.html)
-  for an analyst to look at and take action on.
-
-  Additionally when fuzzing C and C++ code, Sanitizers such as AddressSanitizer, can be enabled to
-  detect undefined behavior at the moment of occurrence, greatly improving the likelihood of
-  successfully root causing a crash. AFLTriage captures this information, if available, and uses it
-  to augment its reporting.
-
-  AFLTriage currently only supports Linux based targets and GDB.
-
-  ## AFLTriage High Level Flow
-
-  1. Environment check
-       - Debugger check: For GDB its version and Python version are checked
-       - Target exists and is executable and the command line is okay
-       - Environment variables are processed
-  1. Input categorization
-       - For each input path categorize it into: single file, directory with files, AFL directory, or AFL sync directory
-  1. Input collection
-       - Resolve all paths to input files in a stable order
-  1. Profile
-       - See how the target operates under the debugger
-  1. Triage
-       - In parallel, for each input file, triage using a debugger, process debugger data, and bucket crashes
-  1. Report
-       - With triage information, emit reports in the formats requested (text/json/raw)
- */
-use clap::{arg_enum, App, AppSettings, Arg, ArgMatches};
-use indicatif::{ProgressBar, ProgressStyle};
+//! The AFLTriage command line tool. See the library documentation for an overview
+use clap::{App, AppSettings, Arg, ArgMatches};
 use is_executable::IsExecutable;
 use rayon::prelude::*;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::{Duration, Instant};
-
-#[macro_use]
-extern crate lazy_static;
+use std::time::{Duration, Instant, SystemTime};
 
 #[macro_use]
 extern crate clap;
 extern crate num_cpus;
 
-pub mod afl;
-pub mod environment;
-pub mod debugger;
-pub mod platform;
-pub mod process;
-pub mod report;
-pub mod util;
-pub mod bucket;
+pub mod config;
+pub mod diff;
+pub mod progress;
+pub mod rebucket;
+pub mod issues;
+pub mod known;
 
-use afl::AflStats;
-use debugger::gdb::*;
-use process::ChildResult;
-use bucket::{CrashBucketStrategy, CrashBucketInfo};
+// The test report loaders of the library, which are not built into it outside of its own tests
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "report/fixtures.rs"]
+mod fixtures;
+
+use afltriage::{afl, bucket, coverage, environment, honggfuzz, leak, metrics, minidump, minimize, nocrash, notify, oracle, process, report, util};
+use afltriage::metrics::TriageMetrics;
+use afltriage::hooks::TestcaseHooks;
+use afltriage::preprocess::Preprocessor;
+use afltriage::filter::{remove_duplicate_testcases, select_testcases, SampleRng, TestcaseFilter, TestcaseOrder};
+use afltriage::proto::ProtoDecoder;
+use afltriage::input::{ChildStdin, FifoInput, InputMode};
+use afltriage::sandbox::{Sandbox, SandboxLevel};
+use afltriage::sourcemap::SourcePathMap;
+use afltriage::oracle::Oracle;
+use afltriage::leak::LeakFinding;
+use afltriage::coverage::Coverage;
+use afltriage::afl::AflStats;
+use afltriage::libfuzzer::ArtifactPrefix;
+use afltriage::analysis::{self, AnalysisPipeline, BucketPass, ClassifyPass, CrashAnalysis};
+use afltriage::debugger::gdb::*;
+use afltriage::debugger::remote::RemoteTarget;
+use afltriage::debugger::android::{self, AndroidDevice};
+use afltriage::platform::{kernel, loader, macos, privileges, scheduling, symbolizer};
+use afltriage::platform::loader::EnvironmentError;
+use afltriage::process::ChildResult;
+use afltriage::bucket::{BucketHashAlgorithm, CrashBucketPolicy, CrashBucketStrategy, FrameSymbolKey, StackClusterer};
+use afltriage::report::{ReportEnvelope, ReportOptions, ReportOutputFormat, Reproducibility, SavedResult, SAVED_RESULTS_FILE};
+use afltriage::report::summary::TestcaseSummary;
+use progress::TriageProgress;
+use afltriage::report::campaign::{CampaignSummary, CrashBreakdown};
+use afltriage::report::index::BucketEntry;
+use afltriage::report::layout::OutputLayout;
+use afltriage::report::provenance::TestcaseProvenance;
+use afltriage::report::retention::{RetentionPolicy, RetentionRules};
+use afltriage::report::redact::{MachineIdentity, Redactor};
+use afltriage::report::template::TextTemplate;
+use afltriage::report::elastic::{ElasticsearchSink, TriageDocument};
+use afltriage::report::known::KnownBuckets;
+use afltriage::report::severity::{SeverityModel, SeverityRule};
 
 #[doc(hidden)]
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-// arg_enum! doesn't support docstrings...
-arg_enum! {
-
-    /// The output formats supported by AFLTriage
-    #[derive(PartialEq, Debug)]
-    // these are user controlable options so follow normal cmdline conventions
-    #[allow(non_camel_case_types)]
-    pub enum ReportOutputFormat {
-        // A simple, but opinionated text report. Don't parse this directly, choose JSON or raw instead, as the output format is unversioned
-        text,
-        // Opinionated fields derived from the raw triage output
-        json,
-        // Unfiltered JSON output from the triage script and child process
-        // Heavily dependent on the debugging backend
-        rawjson,
-    }
-}
+/// How many times candidates are triaged while minimizing a testcase without --minimize-execs
+const DEFAULT_MINIMIZE_EXECS: usize = 200;
 
-fn setup_command_line() -> ArgMatches<'static> {
-    let mut app = App::new("afltriage")
+fn build_app(required: bool) -> App<'static, 'static> {
+    App::new("afltriage")
                           .version(crate_version!())
                           .author(crate_authors!("\n"))
                           .about(crate_description!())
-                          .usage("afltriage -i <input>... -o <output> <command>...")
+                          .usage("afltriage {-i <input>... | --input-dir <dir>} -o <output> [command]...")
                           .setting(AppSettings::TrailingVarArg)
                           .setting(AppSettings::DontDelimitTrailingValues)
                           .setting(AppSettings::DontCollapseArgsInUsage)
                           .setting(AppSettings::UnifiedHelpMessage)
                           .setting(AppSettings::DeriveDisplayOrder)
+                          .after_help("Use 'afltriage rebucket --help' to re-bucket the crashes of a previous run saved with --save-results, \
+                                       and 'afltriage issues --help' to file GitHub issues for its buckets. \
+                                       Use 'afltriage known --help' to import or export a --known-buckets database, \
+                                       and 'afltriage diff --help' to compare the buckets of two runs.")
                           .arg(Arg::with_name("input")
                                .short("-i")
                                .takes_value(true)
-                               .required(true)
+                               .required_unless("input_dir")
+                               .required(required)
                                .multiple(true)
-                               .help("A list of paths to a testcase, directory of testcases, AFL directory, and/or directory of AFL directories to be triaged.")
+                               .help("A list of paths to a testcase, directory of testcases, AFL directory, directory of AFL directories, \
+                                     Honggfuzz workspace, and/or libFuzzer artifact prefix to be triaged.")
                                .long_help("A list of paths to a testcase, directory of testcases, AFL directory, \
-                                     and/or directory of AFL directories to be triaged. Note that this arg \
+                                     directory of AFL directories, Honggfuzz workspace, and/or libFuzzer artifact prefix \
+                                     (like out/ or out/fuzz- for -artifact_prefix=out/fuzz-) to be triaged. Note that this arg \
                                      takes multiple inputs in a row (e.g. -i input1 input2...) so it cannot be the last \
                                      argument passed to AFLTriage -- this is reserved for the command."))
+                          .arg(Arg::with_name("input_dir")
+                               .long("--input-dir")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("dir")
+                               .help("A directory of raw inputs to triage recursively, without assuming AFL's directory structure or filename conventions. \
+                                     Can be given multiple times."))
+                          .arg(Arg::with_name("include")
+                               .long("--include")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("glob")
+                               .help("Only triage --input-dir files whose path relative to the directory matches a glob (e.g. '**/crash-*'). Can be given multiple times."))
+                          .arg(Arg::with_name("extensions")
+                               .long("--extensions")
+                               .takes_value(true)
+                               .multiple(true)
+                               .use_delimiter(true)
+                               .value_name("ext")
+                               .help("Only triage --input-dir files with these extensions: e.g. bin,dat."))
+                          .arg(Arg::with_name("filter")
+                               .long("--filter")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("FILTER")
+                               .help("Only triage testcases whose file name matches a glob (e.g. 'id:0003*'), a regex:PATTERN, or a comparison of an AFL file name field \
+                                     (e.g. sig=11, sig=SIGSEGV, op!=havoc, time>=3600000). Can be given multiple times, and testcases must match them all."))
+                          .arg(Arg::with_name("keep_duplicates")
+                               .long("--keep-duplicates")
+                               .help("Triage testcases with the same contents as an earlier testcase instead of adding them to the bucket of the earlier one."))
+                          .arg(Arg::with_name("order")
+                               .long("--order")
+                               .takes_value(true)
+                               .possible_values(&TestcaseOrder::variants())
+                               .default_value("found")
+                               .case_insensitive(true)
+                               .help("The order to triage testcases in: as found, the most recently modified first, the smallest first, or shuffled."))
+                          .arg(Arg::with_name("sample")
+                               .long("--sample")
+                               .takes_value(true)
+                               .value_name("N")
+                               .help("Only triage N testcases picked at random, for quick results from a huge corpus."))
+                          .arg(Arg::with_name("seed")
+                               .long("--seed")
+                               .takes_value(true)
+                               .help("The seed for --sample and --order random, to pick the same testcases again. A seed is chosen and logged otherwise."))
                           .arg(Arg::with_name("output")
                                .short("-o")
                                .takes_value(true)
-                               .required(true)
+                               .required(required)
                                .help("The output directory for triage report files. Use '-' to print entire reports to console."))
                           .arg(Arg::with_name("command")
                                .multiple(true)
-                               .required(true)
-                               .help("The binary executable and args to execute. Use '@@' as a placeholder for the path to the input file or --stdin. Optionally use -- to delimit the start of the command."))
+                               .required(false)
+                               .help("The binary executable and args to execute. Use '@@' as a placeholder for the path to the input file or --stdin. Optionally use -- to delimit the start of the command. \
+                                     If omitted, the command saved by AFL++ in an AFL directory's cmdline file is used."))
+                          .arg(Arg::with_name("config")
+                               .long("--config")
+                               .takes_value(true)
+                               .help("A TOML configuration file providing default values for any of these options. \
+                                     Options given on the command line take precedence. [default: afltriage.toml, if present]"))
+                          .arg(Arg::with_name("gdb_path")
+                               .long("--gdb-path")
+                               .takes_value(true)
+                               .help("The path to GDB. Overrides AFLTRIAGE_GDB_PATH."))
                           .arg(Arg::with_name("timeout")
                                .short("-t")
                                .long("--timeout")
@@ -136,6 +181,17 @@ fn setup_command_line() -> ArgMatches<'static> {
                                .long("--jobs")
                                .takes_value(true)
                                .help("How many threads to use during triage."))
+                          .arg(Arg::with_name("cpu_set")
+                               .long("--cpu-set")
+                               .takes_value(true)
+                               .value_name("CPUS")
+                               .help("Only run AFLTriage, GDB, and the target on these cores, e.g. 0-3,8. Defaults --jobs to the number of cores given. \
+                                     Use cores no fuzzer is bound to when triaging next to a running campaign."))
+                          .arg(Arg::with_name("nice")
+                               .long("--nice")
+                               .takes_value(true)
+                               .value_name("NICENESS")
+                               .help("Run AFLTriage, GDB, and the target with this niceness, from 0 to 19, so a running fuzzing campaign keeps priority."))
                           .arg(Arg::with_name("report_formats")
                                .long("--report-formats")
                                .takes_value(true)
@@ -146,6 +202,84 @@ fn setup_command_line() -> ArgMatches<'static> {
                                .required(false)
                                .case_insensitive(true)
                                .help("The triage report output formats. Multiple values allowed: e.g. text,json."))
+                          .arg(Arg::with_name("text_template")
+                               .long("--text-template")
+                               .takes_value(true)
+                               .value_name("FILE")
+                               .help("Render text reports with this template instead of the built-in layout. Templates use a subset of the Handlebars syntax, see src/report/res/report.txt.hbs."))
+                          .arg(Arg::with_name("redact")
+                               .long("--redact")
+                               .help("Strip the home and current directories, user names, and the hostname from crash reports, so they can be shared outside of this machine. \
+                                     The target's output is kept as is unless --redact-child-output is given."))
+                          .arg(Arg::with_name("redact_pattern")
+                               .long("--redact-pattern")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("REGEX")
+                               .requires("redact")
+                               .help("Also replace text matching this regular expression in crash reports with <redacted>. Can be given multiple times."))
+                          .arg(Arg::with_name("redact_child_output")
+                               .long("--redact-child-output")
+                               .requires("redact")
+                               .help("Also redact the target's stdout and stderr in crash reports."))
+                          .arg(Arg::with_name("output_layout")
+                               .long("--output-layout")
+                               .takes_value(true)
+                               .possible_values(&OutputLayout::variants())
+                               .default_value("flat")
+                               .case_insensitive(true)
+                               .help("How reports are organized in the output directory. buckets gives each unique bucket a directory with its reports, \
+                                     a bucket.json of its metadata, and its member testcases (symlinked) in testcases/."))
+                          .arg(Arg::with_name("copy_testcases")
+                               .long("--copy-testcases")
+                               .help("Copy member testcases into bucket directories instead of symlinking them, with --output-layout buckets."))
+                          .arg(Arg::with_name("retain")
+                               .long("--retain")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("SEVERITY=ARTIFACTS")
+                               .help("Override which artifacts are kept for crashes of a severity (high, medium, low, unknown). \
+                                     ARTIFACTS is a comma separated list of report formats and child_output, or none to only list the crash in the index. \
+                                     Can be given multiple times. e.g. --retain high=text,json,child_output --retain low=none"))
+                          .arg(severity_rule_arg())
+                          .arg(Arg::with_name("webhook")
+                               .long("--webhook")
+                               .takes_value(true)
+                               .value_name("URL")
+                               .help("POST a JSON payload to this URL (e.g. a Slack incoming webhook) whenever a new unique bucket is found. Requires curl."))
+                          .arg(Arg::with_name("webhook_min_severity")
+                               .long("--webhook-min-severity")
+                               .takes_value(true)
+                               .possible_values(&["high", "medium", "low", "unknown"])
+                               .default_value("high")
+                               .help("Only notify the --webhook of new buckets at least this severe."))
+                          .arg(Arg::with_name("elasticsearch")
+                               .long("--elasticsearch")
+                               .takes_value(true)
+                               .value_name("INDEX_URL")
+                               .help("Index a document for every crashing testcase in this Elasticsearch or OpenSearch index, e.g. http://localhost:9200/afltriage. \
+                                     Credentials can be given in the URL. Requires curl."))
+                          .arg(Arg::with_name("target_name")
+                               .long("--target-name")
+                               .takes_value(true)
+                               .requires("elasticsearch")
+                               .help("The target name of --elasticsearch documents, to tell targets of a campaign apart. Defaults to the file name of the target binary."))
+                          .arg(Arg::with_name("metrics_listen")
+                               .long("--metrics-listen")
+                               .takes_value(true)
+                               .value_name("ADDRESS")
+                               .help("Serve Prometheus metrics of the run at /metrics on this address (e.g. 127.0.0.1:9464) while triaging."))
+                          .arg(Arg::with_name("known_buckets")
+                               .long("--known-buckets")
+                               .takes_value(true)
+                               .value_name("FILE")
+                               .help("Record every bucket found in this database of known buckets, shared across runs and campaigns. It is created if it does not exist."))
+                          .arg(Arg::with_name("ignore_known")
+                               .long("--ignore-known")
+                               .requires("known_buckets")
+                               .help("Do not write reports or notify for buckets already in the --known-buckets database, so only new crashes surface."))
                           .arg(Arg::with_name("bucket_strategy")
                                .long("--bucket-strategy")
                                .takes_value(true)
@@ -153,7 +287,22 @@ fn setup_command_line() -> ArgMatches<'static> {
                                .default_value("afltriage")
                                .required(false)
                                .case_insensitive(true)
-                               .help("The crash deduplication strategy to use."))
+                               .help("The crash deduplication strategy to use. The external strategy requires --bucket-command. \
+                                     The custom strategy is configured with the --bucket-frames, --bucket-symbols, --bucket-absolute-addresses, and --bucket-ignore-addresses options. \
+                                     The similar strategy clusters similar backtraces (see --bucket-similarity)."))
+                          .arg(Arg::with_name("bucket_command")
+                               .long("--bucket-command")
+                               .takes_value(true)
+                               .help("A shell command that decides the bucket of each crash for the external bucket strategy. \
+                                     It receives the crash's normalized stack as JSON on stdin and prints a bucket identifier."))
+                          .args(&bucket_policy_args())
+                          .arg(Arg::with_name("disable_passes")
+                               .long("--disable-passes")
+                               .takes_value(true)
+                               .multiple(true)
+                               .use_delimiter(true)
+                               .possible_values(&analysis::OPTIONAL_PASSES)
+                               .help("Analysis passes to skip. Without bucket every crash is unique and without classify every crash has an unknown severity."))
                           .arg(Arg::with_name("child_output")
                                .long("--child-output")
                                .help("Include child output in triage reports."))
@@ -162,10 +311,331 @@ fn setup_command_line() -> ArgMatches<'static> {
                                .default_value("25")
                                .takes_value(true)
                                .help("How many lines of program output from the target to include in reports. Use 0 to mean unlimited lines (not recommended)."))
+                          .arg(Arg::with_name("max_child_output")
+                               .long("--max-child-output")
+                               .takes_value(true)
+                               .value_name("BYTES")
+                               .default_value("4194304")
+                               .help("The most bytes of stdout and of stderr captured from the target for each testcase. The first quarter and the last bytes are kept, with a note of how much was cut in between. Use 0 for no limit."))
+                          .arg(Arg::with_name("crashing_thread_only")
+                               .long("--crashing-thread-only")
+                               .help("Only collect the backtrace of the thread that crashed. By default, the other threads are collected too and collapsed by backtrace in reports, which is slow for targets with many threads."))
+                          .arg(Arg::with_name("no_pretty_printers")
+                               .long("--no-pretty-printers")
+                               .help("Show the raw members of arguments and locals instead of formatting them with GDB's pretty-printers, \
+                                     e.g. the pointers of an std::vector instead of its elements. Faster for C++ targets with many locals."))
+                          .arg(Arg::with_name("max_json_size")
+                               .long("--max-json-size")
+                               .takes_value(true)
+                               .value_name("BYTES")
+                               .help("The largest a json or rawjson report may be. Larger reports drop other threads, the memory map, frame locals, target output, and finally outer frames until they fit. Truncated reports are marked with \"truncated\": true."))
+                          .arg(Arg::with_name("env")
+                               .long("--env")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("KEY=VAL")
+                               .help("Set an environment variable for the target. Can be given multiple times."))
+                          .arg(Arg::with_name("debug_file_dir")
+                               .long("--debug-file-dir")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("DIR")
+                               .help("A directory GDB searches for the separate debug files of stripped binaries, before /usr/lib/debug. \
+                                     Debug files are found by build ID in DIR/.build-id/xx/yyyy.debug, or by the binary's path and .gnu_debuglink. \
+                                     Can be given multiple times."))
+                          .arg(Arg::with_name("substitute_path")
+                               .long("--substitute-path")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("OLD=NEW")
+                               .help("Read source files under the directory OLD from NEW, for targets built in a container or on another machine. \
+                                     Given to GDB with 'set substitute-path' and applied where reports read source. Can be given multiple times."))
+                          .arg(Arg::with_name("env_file")
+                               .long("--env-file")
+                               .takes_value(true)
+                               .help("A file of KEY=VAL lines to set in the target's environment. Variables given with --env take precedence."))
+                          .arg(Arg::with_name("clear_env")
+                               .long("--clear-env")
+                               .help("Start the target with an empty environment, besides the variables set by AFLTriage, --env, and --env-file."))
+                          .arg(Arg::with_name("minimize")
+                               .long("--minimize")
+                               .help("After triage, minimize the first testcase of every unique bucket. Blocks of the input are removed as long as it still crashes \
+                                     in the same bucket. Minimized testcases are written next to the reports with a .min extension."))
+                          .arg(Arg::with_name("minimize_execs")
+                               .long("--minimize-execs")
+                               .takes_value(true)
+                               .value_name("N")
+                               .requires("minimize")
+                               .help("The maximum number of times to triage candidates while minimizing each bucket's testcase. Defaults to 200."))
+                          .arg(Arg::with_name("rerun")
+                               .long("--rerun")
+                               .takes_value(true)
+                               .value_name("N")
+                               .help("Triage each crashing testcase N more times and record how often it crashes in the same bucket. \
+                                     Buckets that do not always reproduce are flagged as flaky in reports and listed after reproducible ones in the index."))
+                          .arg(Arg::with_name("oracle")
+                               .long("--oracle")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("ID=KIND:ARGUMENT")
+                               .help("A test oracle that marks a run without a crash as a finding. KIND is regex (a pattern matched against the target's output), \
+                                     exit (a comma separated list of exit codes), or command (a shell command that receives the run as JSON on stdin and exits with 1 for a finding). \
+                                     Can be given multiple times. e.g. --oracle 'sql=regex:SQL error' --oracle crash_exit=exit:2,3"))
+                          .arg(Arg::with_name("oracle_breakpoint")
+                               .long("--oracle-breakpoint")
+                               .takes_value(true)
+                               .value_name("LOCATION")
+                               .help("A GDB breakpoint location (e.g. an error reporting function) where a backtrace is captured without stopping the target. \
+                                     Oracle findings are bucketed by the oracle and the caller of this location."))
+                          .arg(Arg::with_name("detect_leaks")
+                               .long("--detect-leaks")
+                               .help("Run testcases that did not crash once more outside of the debugger with LeakSanitizer enabled, and report leaks as findings. \
+                                     Leaks are bucketed by their allocation site with a leak_ prefix."))
+                          .arg(Arg::with_name("coverage")
+                               .long("--coverage")
+                               .help("Run crashing testcases once more outside of the debugger to collect their code coverage, and list the functions \
+                                     they covered in JSON reports. The target must be built with a sanitizer and -fsanitize-coverage=trace-pc-guard."))
+                          .arg(Arg::with_name("report_no_crash")
+                               .long("--report-no-crash")
+                               .help("Report testcases that did not crash as findings, bucketed by how the target ended, e.g. NO_CRASH_exit_1 or NO_CRASH_signal_SIGKILL. \
+                                     Useful to find the testcases of a corpus that no longer crash."))
+                          .arg(Arg::with_name("preprocess")
+                               .long("--preprocess")
+                               .takes_value(true)
+                               .value_name("COMMAND")
+                               .help("A shell command run for each testcase before it is triaged, such as 'zstd -d {in} -o {out}'. \
+                                     {in} is the testcase and the target is given the file written to {out}. \
+                                     Processed inputs are kept in the preprocessed directory of the output directory."))
+                          .arg(Arg::with_name("before_cmd")
+                               .long("--before-cmd")
+                               .takes_value(true)
+                               .value_name("COMMAND")
+                               .help("A shell command run before each testcase is triaged, such as 'rm -rf /tmp/scratch && mkdir /tmp/scratch'. \
+                                     The path of the testcase is in $AFLTRIAGE_TESTCASE. A failing command makes the testcase a triage error."))
+                          .arg(Arg::with_name("after_cmd")
+                               .long("--after-cmd")
+                               .takes_value(true)
+                               .value_name("COMMAND")
+                               .help("A shell command run after each testcase is triaged, even if --before-cmd failed. \
+                                     The path of the testcase is in $AFLTRIAGE_TESTCASE. A failing command is only warned about."))
+                          .arg(Arg::with_name("proto")
+                               .long("--proto")
+                               .takes_value(true)
+                               .value_name("PROTO_FILE:MESSAGE_TYPE")
+                               .help("Decode crashing testcases as serialized protobuf messages of MESSAGE_TYPE (e.g. fuzzing.Input) and include the text format in reports. \
+                                     For libprotobuf-mutator targets. Requires protoc."))
+                          .arg(Arg::with_name("no_aslr")
+                               .long("--no-aslr")
+                               .conflicts_with("aslr")
+                               .help("Run the target without address space layout randomization so addresses are the same between runs. This is the default."))
+                          .arg(Arg::with_name("aslr")
+                               .long("--aslr")
+                               .help("Run the target with address space layout randomization, like most fuzzers do. Address-based bucketing strategies become less reliable."))
+                          .arg(Arg::with_name("sandbox")
+                               .long("--sandbox")
+                               .takes_value(true)
+                               .possible_values(&SandboxLevel::variants())
+                               .default_value("none")
+                               .case_insensitive(true)
+                               .help("Run GDB and the target isolated from the system with bubblewrap (bwrap). \
+                                     net gives the target no network access. full also makes the filesystem read-only with a private /tmp."))
+                          .arg(Arg::with_name("sandbox_seccomp")
+                               .long("--sandbox-seccomp")
+                               .takes_value(true)
+                               .value_name("BPF_FILE")
+                               .help("A compiled BPF seccomp filter applied to GDB and the target in the sandbox. The filter must allow ptrace. \
+                                     Requires --sandbox net or full."))
+                          .arg(Arg::with_name("harden")
+                               .long("--harden")
+                               .help("Run with the least privileges triage needs, for untrusted testcases. Drops every capability except CAP_SYS_PTRACE \
+                                     and sets no_new_privs for AFLTriage, GDB, and the target. Refuses to run as root unless --allow-root is given."))
+                          .arg(Arg::with_name("allow_root")
+                               .long("--allow-root")
+                               .requires("harden")
+                               .help("Allow --harden to run as root. Root keeps file access to everything, even without capabilities."))
+                          .arg(Arg::with_name("hangs")
+                               .long("--hangs")
+                               .help("Triage hangs too. A target that runs longer than the timeout is stopped and triaged where it is stuck, \
+                                     then bucketed by the function names of its backtrace with a hang_ prefix. AFL hangs directories are also triaged."))
+                          .arg(Arg::with_name("queue")
+                               .long("--queue")
+                               .help("Triage the inputs in AFL queue directories too. None of them crashed the fuzzed binary, so run them under an ASAN build \
+                                     of the target to find the memory errors that did not crash it. Queue inputs without findings are counted as not crashing."))
+                          .arg(Arg::with_name("follow_forks")
+                               .long("--follow-forks")
+                               .help("Follow the child process when the target forks, so targets that daemonize (e.g. with daemon()) can be triaged. \
+                                     Without this, targets that fork and then exit cleanly are reported as not crashing."))
+                          .arg(Arg::with_name("triage_script_extra")
+                               .long("--triage-script-extra")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("FILE")
+                               .help("A GDB python script to load after the built-in triage script. It can call register_extension(name, callback) \
+                                     to add custom fields to triage reports. Can be given multiple times."))
+                          .arg(Arg::with_name("save_results")
+                               .long("--save-results")
+                               .help("Save the triage result of every crash to the output directory, allowing them to be re-bucketed later with 'afltriage rebucket' or filed as issues with 'afltriage issues'."))
+                          .arg(Arg::with_name("local_time")
+                               .long("--local-time")
+                               .help("Write timestamps in local time instead of UTC. Timestamps are always ISO-8601 and include their UTC offset."))
                           .arg(Arg::with_name("stdin")
                                .long("--stdin")
                                .takes_value(false)
-                               .help("Provide testcase input to the target via stdin instead of a file."))
+                               .help("Provide testcase input to the target via stdin instead of a file. The same as --input-mode stdin."))
+                          .arg(Arg::with_name("two_phase")
+                               .long("--two-phase")
+                               .help("Triage every testcase with only what is needed to bucket it (the signal and frames, without locals, source, or registers), \
+                                     then triage the first testcase of each bucket again in full for its reports. Much faster when most testcases are duplicates. \
+                                     Saved results and CSV/SQL summaries of other testcases have the brief triage."))
+                          .arg(Arg::with_name("reuse_gdb")
+                               .long("--reuse-gdb")
+                               .help("Keep one GDB running per thread and triage every testcase in it, instead of starting GDB for each testcase. \
+                                     Much faster for targets with large debug information. A GDB that fails is restarted. \
+                                     Not used with --follow-forks, --oracle-breakpoint, --hangs, --child-stdin inherit, or --sandbox full."))
+                          .arg(Arg::with_name("index_cache")
+                               .long("--index-cache")
+                               .takes_value(true)
+                               .value_name("DIR")
+                               .help("Let GDB cache the symbol indexes of the target and its libraries in DIR, which speeds up loading targets with large debug information. \
+                                     Each worker uses its own subdirectory, so several AFLTriage instances can share DIR."))
+                          .arg(Arg::with_name("no_index_cache")
+                               .long("--no-index-cache")
+                               .help("Do not use the GDB index cache, even if a configuration file sets --index-cache."))
+                          .arg(Arg::with_name("debuginfod")
+                               .long("--debuginfod")
+                               .help("Download missing debug info for the target and its libraries from the debuginfod servers in DEBUGINFOD_URLS. \
+                                     Debug info is downloaded, with progress, before triage starts and cached for every testcase."))
+                          .arg(Arg::with_name("debuginfod_urls")
+                               .long("--debuginfod-urls")
+                               .takes_value(true)
+                               .value_name("URLS")
+                               .help("Space separated debuginfod server URLs to use instead of DEBUGINFOD_URLS. Implies --debuginfod."))
+                          .arg(Arg::with_name("external_symbolizer")
+                               .long("--external-symbolizer")
+                               .takes_value(true)
+                               .value_name("SYMBOLIZER")
+                               .default_value("auto")
+                               .help("Name the frames GDB could not symbolize with llvm-symbolizer or addr2line. \
+                                     'auto' uses the first of them in PATH, 'off' disables it, and anything else is the symbolizer to use."))
+                          .arg(Arg::with_name("valgrind")
+                               .long("--valgrind")
+                               .help("Run each testcase under Valgrind Memcheck instead of GDB, stopping at the first memory error. \
+                                     Finds heap bugs that do not crash uninstrumented binaries, but reports have no registers or variables."))
+                          .arg(Arg::with_name("cdb")
+                               .long("--cdb")
+                               .conflicts_with_all(&["valgrind", "gdb_remote", "android"])
+                               .help("Run each testcase under CDB (from PATH) instead of GDB to triage Windows targets, like the crashes of WinAFL. \
+                                     Exceptions are reported as the closest signal, e.g. an access violation as SIGSEGV. Testcases must be given with @@."))
+                          .arg(Arg::with_name("lldb")
+                               .long("--lldb")
+                               .conflicts_with_all(&["valgrind", "cdb", "gdb_remote", "android"])
+                               .help("Run each testcase under LLDB (from PATH) instead of GDB to triage macOS targets. The default on macOS unless --gdb-path is given. \
+                                     Mach exceptions are reported as the signal they turn into, e.g. EXC_BAD_ACCESS as SIGSEGV."))
+                          .arg(Arg::with_name("dsym_dir")
+                               .long("--dsym-dir")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("DIR")
+                               .help("A directory LLDB searches for dSYM bundles, besides the one next to each binary. Can be given multiple times."))
+                          .arg(Arg::with_name("android")
+                               .long("--android")
+                               .conflicts_with_all(&["valgrind", "gdb_remote"])
+                               .help("Run the target on an Android device under gdbserver, started with adb (from PATH), and triage it remotely. \
+                                     The target command has the device's paths, and testcases are pushed to the device for @@ or --stdin. \
+                                     Testcases are triaged one at a time."))
+                          .arg(Arg::with_name("adb_serial")
+                               .long("--adb-serial")
+                               .takes_value(true)
+                               .value_name("SERIAL")
+                               .requires("android")
+                               .help("The serial number of the Android device to use, if there is more than one."))
+                          .arg(Arg::with_name("android_gdbserver")
+                               .long("--android-gdbserver")
+                               .takes_value(true)
+                               .value_name("PATH")
+                               .requires("android")
+                               .help("The path of gdbserver on the Android device. lldb-server is run in its gdbserver mode. \
+                                     Defaults to /data/local/tmp/gdbserver."))
+                          .arg(Arg::with_name("android_port")
+                               .long("--android-port")
+                               .takes_value(true)
+                               .value_name("PORT")
+                               .requires("android")
+                               .help("The port gdbserver listens on, forwarded from the host with adb. Defaults to 5039."))
+                          .arg(Arg::with_name("android_symbols")
+                               .long("--android-symbols")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("DIR")
+                               .requires("android")
+                               .help("A directory of unstripped binaries of the Android device. The first is the sysroot, like the symbols directory of an AOSP build, \
+                                     and all are searched for libraries by name, like obj/local/ABI of an NDK build. Can be given multiple times."))
+                          .arg(Arg::with_name("gdb_remote")
+                               .long("--gdb-remote")
+                               .takes_value(true)
+                               .value_name("HOST:PORT")
+                               .conflicts_with("valgrind")
+                               .help("Triage bare-metal firmware on a remote GDB server, like OpenOCD, with 'target extended-remote'. \
+                                     The target command is the firmware ELF to load symbols from, followed by @@. \
+                                     A crash is the target entering a fault handler. Testcases are triaged one at a time."))
+                          .arg(Arg::with_name("remote_reset")
+                               .long("--remote-reset")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("COMMAND")
+                               .requires("gdb_remote")
+                               .help("A GDB command that resets and halts the remote target before each testcase. \
+                                     Can be given multiple times. Defaults to 'monitor reset halt'."))
+                          .arg(Arg::with_name("remote_input")
+                               .long("--remote-input")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("COMMAND")
+                               .requires("gdb_remote")
+                               .help("A GDB command that delivers the testcase to the remote target, where @@ is replaced by its path, \
+                                     e.g. 'restore @@ binary 0x20001000'. Can be given multiple times. \
+                                     Set a breakpoint where the firmware finishes a testcase so runs without a crash stop."))
+                          .arg(Arg::with_name("remote_fault_handler")
+                               .long("--remote-fault-handler")
+                               .takes_value(true)
+                               .multiple(true)
+                               .number_of_values(1)
+                               .value_name("FUNCTION")
+                               .requires("gdb_remote")
+                               .help("A function the remote target enters on a fault. Can be given multiple times. \
+                                     Defaults to the Cortex-M fault handlers of CMSIS, like HardFault_Handler."))
+                          .arg(Arg::with_name("remote_log_dir")
+                               .long("--remote-log-dir")
+                               .takes_value(true)
+                               .value_name("DIR")
+                               .help("With --gdb-remote or --android, record the remote protocol packets GDB exchanges for each testcase to a new file in DIR, \
+                                     named after the testcase. Connection failures are reported as triage errors and packet errors as report warnings either way."))
+                          .arg(Arg::with_name("child_stdin")
+                               .long("--child-stdin")
+                               .takes_value(true)
+                               .possible_values(&ChildStdin::variants())
+                               .default_value("null")
+                               .case_insensitive(true)
+                               .help("The target's stdin when testcases are not given on stdin: /dev/null, closed, or inherited from AFLTriage. \
+                                     Targets reading an inherited terminal can hang."))
+                          .arg(Arg::with_name("input_mode")
+                               .long("--input-mode")
+                               .takes_value(true)
+                               .value_name("MODE")
+                               .conflicts_with("stdin")
+                               .help("How the target is given testcases: file (the path replaces @@), stdin, fifo, tcp:PORT, or udp:PORT. \
+                                     With fifo, a named pipe replaces @@ and the testcase is written to it once the target opens it. \
+                                     For network harnesses, the testcase is sent to the port on localhost once the target listens on it. \
+                                     Triage runs one testcase at a time as every target uses the same port."))
                           .arg(Arg::with_name("profile_only")
                                .long("--profile-only")
                                .takes_value(false)
@@ -175,60 +645,298 @@ fn setup_command_line() -> ArgMatches<'static> {
                                .takes_value(false)
                                .conflicts_with("profile_only")
                                .help("Skip target profiling before input processing."))
+                          .arg(Arg::with_name("dry_run")
+                               .long("--dry-run")
+                               .help("List the testcases that would be triaged, with the command line of each, and the debugger and target configuration, without starting GDB or writing any output."))
                           .arg(Arg::with_name("debug")
                                .long("--debug")
-                               .help("Enable low-level debugging output of triage operations."));
+                               .help("Enable low-level debugging output of triage operations."))
+                          .arg(Arg::with_name("save_raw_output")
+                               .long("--save-raw-output")
+                               .takes_value(true)
+                               .value_name("DIR")
+                               .help("Save the full output of GDB for each testcase to a file in DIR, including testcases that failed to triage. \
+                                     Files are named after the testcase."))
+}
+
+/// The options of the custom bucket strategy, shared with `afltriage rebucket`
+pub fn bucket_policy_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("bucket_frames")
+            .long("--bucket-frames")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("0")
+            .help("How many frames to hash for the custom bucket strategy, starting from the first interesting frame. Use 0 to hash every frame."),
+        Arg::with_name("bucket_symbols")
+            .long("--bucket-symbols")
+            .takes_value(true)
+            .possible_values(&FrameSymbolKey::variants())
+            .default_value("file_line")
+            .case_insensitive(true)
+            .help("How the custom bucket strategy identifies frames with symbols: by file:line, by function name only, or not at all (none)."),
+        Arg::with_name("bucket_absolute_addresses")
+            .long("--bucket-absolute-addresses")
+            .help("Hash the absolute address of frames without symbols instead of their module relative address for the custom bucket strategy."),
+        Arg::with_name("bucket_ignore_addresses")
+            .long("--bucket-ignore-addresses")
+            .help("Skip frames without symbols for the custom bucket strategy instead of hashing their address."),
+        Arg::with_name("bucket_access")
+            .long("--bucket-access")
+            .help("Also hash whether the fault was a read, a write, or an instruction fetch for the custom bucket strategy."),
+        Arg::with_name("bucket_similarity")
+            .long("--bucket-similarity")
+            .takes_value(true)
+            .value_name("THRESHOLD")
+            .default_value("0.8")
+            .help("How similar two backtraces must be for the similar bucket strategy to cluster them, from 0 to 1. \
+                  Similarity is based on the edit distance between their frames, so 1 only clusters identical backtraces."),
+        Arg::with_name("bucket_hash")
+            .long("--bucket-hash")
+            .takes_value(true)
+            .possible_values(&BucketHashAlgorithm::variants())
+            .default_value("md5")
+            .case_insensitive(true)
+            .help("How bucketing strategies hash their inputs into bucket identifiers. Other algorithms than md5 give \
+                  identifiers in the stable format ALGORITHM-HASH-SLUG, e.g. sha256-ef0b896c3e5a1745-bad_read."),
+    ]
+}
+
+/// Create the bucketing pass from the bucketing options. Returns an error message if the options
+/// are invalid
+pub fn bucket_pass_from_args(args: &ArgMatches) -> Result<BucketPass, String> {
+    let strategy = value_t!(args, "bucket_strategy", CrashBucketStrategy).map_err(|e| e.to_string())?;
+    let command = args.value_of("bucket_command");
+
+    if strategy == CrashBucketStrategy::external {
+        match command {
+            Some(command) => log::info!("Bucketing crashes using external command: {}", command),
+            None => return Err("The external bucket strategy requires --bucket-command".into()),
+        }
+    } else if command.is_some() {
+        log::warn!("Ignoring --bucket-command as the bucket strategy is not external");
+    }
+
+    let policy = CrashBucketPolicy {
+        frames: value_t!(args, "bucket_frames", usize).map_err(|e| e.to_string())?,
+        symbols: value_t!(args, "bucket_symbols", FrameSymbolKey).map_err(|e| e.to_string())?,
+        relative_addresses: !args.is_present("bucket_absolute_addresses"),
+        ignore_addresses: args.is_present("bucket_ignore_addresses"),
+        access: args.is_present("bucket_access"),
+    };
+
+    let policy_given = ["bucket_frames", "bucket_symbols", "bucket_absolute_addresses", "bucket_ignore_addresses", "bucket_access"]
+        .iter()
+        .any(|name| args.occurrences_of(name) > 0);
+
+    if strategy == CrashBucketStrategy::custom {
+        log::info!("Bucketing crashes using policy: {:?}", policy);
+    } else if policy_given {
+        log::warn!("Ignoring the --bucket-* policy options as the bucket strategy is not custom");
+    }
+
+    let similarity = value_t!(args, "bucket_similarity", f64).map_err(|e| e.to_string())?;
+
+    if !(0.0..=1.0).contains(&similarity) {
+        return Err(format!("The bucket similarity threshold must be between 0 and 1, not {}", similarity));
+    }
+
+    if strategy == CrashBucketStrategy::similar {
+        log::info!("Clustering crashes with a backtrace similarity of at least {}", similarity);
+    } else if args.occurrences_of("bucket_similarity") > 0 {
+        log::warn!("Ignoring --bucket-similarity as the bucket strategy is not similar");
+    }
+
+    let hash = value_t!(args, "bucket_hash", BucketHashAlgorithm).map_err(|e| e.to_string())?;
+
+    match strategy {
+        CrashBucketStrategy::none | CrashBucketStrategy::external | CrashBucketStrategy::similar
+            if hash != BucketHashAlgorithm::md5 =>
+            log::warn!("Ignoring --bucket-hash for crashes as the {} bucket strategy does not hash its inputs", strategy),
+        _ => log::info!("Bucketing crashes with the {} strategy and the {} hash", strategy, hash),
+    }
+
+    Ok(BucketPass {
+        strategy,
+        command: command.map(str::to_string),
+        policy,
+        clusterer: StackClusterer::new(similarity),
+        hash,
+    })
+}
+
+/// The option overriding the built-in severity rating, shared with the subcommands
+pub fn severity_rule_arg() -> Arg<'static, 'static> {
+    Arg::with_name("severity_rule")
+        .long("--severity-rule")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .value_name("CONDITIONS=SEVERITY")
+        .help("Rate crashes meeting all CONDITIONS, joined by +, with SEVERITY instead of the built-in rating. \
+              Conditions are signal:NAME, sanitizer:REASON, access:read, access:write, pc_control, region:KIND (heap, stack, module, anonymous, special, unmapped, or null), and hang. \
+              The matching rule with the most conditions wins. Can be given multiple times. e.g. --severity-rule signal:SIGABRT=medium --severity-rule region:heap+access:read=high")
+}
+
+/// Create the classification pass from the severity rules
+pub fn classify_pass_from_args(args: &ArgMatches) -> Result<ClassifyPass, String> {
+    let rules = args.values_of("severity_rule").unwrap_or_default()
+        .map(SeverityRule::parse)
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if !rules.is_empty() {
+        log::info!("Rating crash severity with {} rule(s) before the built-in rating", rules.len());
+    }
+
+    Ok(ClassifyPass { model: SeverityModel { rules } })
+}
+
+/// Parse the command line, merged with a configuration file if one was given or found. Returns the
+/// configuration file used, if any
+fn setup_command_line() -> (ArgMatches<'static>, Option<PathBuf>) {
+    let raw_args: Vec<String> = env::args().collect();
+
+    // First pass to find out which options were given on the command line
+    let cmdline = build_app(false).get_matches_from(&raw_args);
+
+    let config_path = match cmdline.value_of("config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(PathBuf::from(config::DEFAULT_CONFIG_FILE)).filter(|p| p.is_file()),
+    };
+
+    let config_path = match config_path {
+        Some(path) => path,
+        None => {
+            if raw_args.len() <= 1 {
+                build_app(true).print_help().unwrap();
+                println!();
+                std::process::exit(0);
+            }
+
+            return (build_app(true).get_matches_from(raw_args), None);
+        }
+    };
+
+    let config_args = config::load_config(&config_path)
+        .and_then(|table| config::config_to_args(&table, |name| cmdline.occurrences_of(name) > 0))
+        .unwrap_or_else(|e| {
+            clap::Error::with_description(
+                &format!("Invalid configuration file {}: {}", config_path.display(), e),
+                clap::ErrorKind::InvalidValue).exit()
+        });
+
+    // Configuration options go first so the command stays last
+    let mut args = vec![raw_args[0].to_string()];
+    args.extend(config_args.options);
+    args.extend(raw_args.into_iter().skip(1));
 
-    if env::args().len() <= 1 {
-        app.print_help().unwrap();
-        println!();
-        std::process::exit(0);
+    if !cmdline.is_present("command") && !config_args.command.is_empty() {
+        args.push("--".into());
+        args.extend(config_args.command);
     }
 
-    app.get_matches()
+    (build_app(true).get_matches_from(args), Some(config_path))
 }
 
 /// State shared between all triage threads
 struct TriageState {
     crashed: usize,
+    /// Crashes that were hangs stopped by the watchdog (see --hangs)
+    hangs: usize,
     no_crash: usize,
+    /// Testcases that did not crash, but forked before exiting
+    forked: usize,
     timedout: usize,
     errored: usize,
-    crash_signature: HashSet<String>,
+    /// Testcases the target could not start for, by the missing dependency
+    environment_errors: HashMap<EnvironmentError, usize>,
+    /// Crashes with a sanitizer report that has only raw addresses
+    unsymbolized_sanitizer_reports: usize,
+    /// Modules with unnamed frames in the backtrace of a crash, with their build IDs
+    unsymbolized_modules: BTreeMap<String, Option<String>>,
+    /// Crashes with a sanitizer report of inputs from an AFL queue (see --queue)
+    queue_sanitizer_findings: usize,
+    /// Unique crash buckets keyed by their identifier
+    buckets: HashMap<String, BucketEntry>,
     unique_errors: HashMap<GdbTriageError, usize>,
+    /// Where every crash is saved with --save-results
+    saved_results: Option<BufWriter<File>>,
+    /// Every crash, when writing run-level CSV or SQL reports
+    summaries: Vec<TestcaseSummary>,
+    /// Crash counts for the campaign summary
+    breakdown: CrashBreakdown,
+    /// Runs without a crash that an oracle marked as a finding
+    findings: usize,
+    finding_buckets: HashSet<String>,
+    /// The provenance of every crashing testcase, keyed by the testcase
+    provenance: HashMap<String, TestcaseProvenance>,
+    /// Runs without a crash that leaked memory (see --detect-leaks)
+    leaks: usize,
+    leak_buckets: HashSet<String>,
+    /// How many runs without a crash are in each bucket, by how the target ended
+    no_crash_buckets: HashMap<String, usize>,
+    /// The pre-processed input of the first testcase of each bucket, keyed by the testcase
+    preprocessed: HashMap<String, String>,
+    /// Buckets whose representative was chosen, with --two-phase
+    representatives: HashSet<String>,
+    /// Testcases in buckets whose representative has not been triaged in full yet
+    waiting_for_representative: HashMap<String, Vec<String>>,
 }
 
-/// The result of a triage operation
-enum TriageResult {
-    NoCrash(GdbChildOutput),
-    Crash(GdbTriageResult),
-    Error(GdbTriageError),
-    Timedout,
-}
+/// Add the testcases that were not triaged as duplicates of a crashing testcase to its bucket.
+/// Returns how many were added
+fn record_duplicates(state: &mut TriageState, duplicates: &HashMap<String, Vec<String>>, local_time: bool) -> usize {
+    let mut recorded = 0;
+
+    // The bucket and summary of each original, found once
+    let buckets: HashMap<String, String> = state.buckets.iter()
+        .flat_map(|(bucket, e)| e.testcases.iter().map(move |tc| (tc, bucket)))
+        .filter(|(tc, _)| duplicates.contains_key(*tc))
+        .map(|(tc, bucket)| (tc.to_string(), bucket.to_string()))
+        .collect();
+    let summaries: HashMap<String, TestcaseSummary> = state.summaries.iter()
+        .filter(|s| duplicates.contains_key(&s.testcase))
+        .map(|s| (s.testcase.to_string(), s.clone()))
+        .collect();
+
+    for (original, copies) in duplicates {
+        let entry = match buckets.get(original).and_then(|bucket| state.buckets.get_mut(bucket)) {
+            Some(entry) => entry,
+            None => continue,
+        };
 
-/// Metadata for a report that can act as a wrapper around data from a debugger
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
-pub struct ReportEnvelope {
-    command_line: Vec<String>,
-    testcase: String,
-    debugger: String,
-    //env: Vec<String>,
-    bucket: CrashBucketInfo,
-    report_options: ReportOptions,
-}
+        entry.testcases.extend(copies.iter().cloned());
+        recorded += copies.len();
+
+        for copy in copies {
+            if let Some(summary) = summaries.get(original) {
+                let summary = TestcaseSummary { testcase: copy.to_string(), ..summary.clone() };
+                state.summaries.push(summary);
+            }
+
+            if let Some(provenance) = state.provenance.get(original) {
+                let provenance = TestcaseProvenance {
+                    bucket: provenance.bucket.to_string(),
+                    triaged_at: provenance.triaged_at.clone(),
+                    reproducibility: provenance.reproducibility,
+                    ..report::provenance::read_provenance(copy, local_time)
+                };
+                state.provenance.insert(copy.to_string(), provenance);
+            }
+        }
+    }
 
-/// A fully stringified report ready to be written to an output
-struct RenderedReport {
-    data: String,
-    format: ReportOutputFormat,
-    extension: &'static str,
+    recorded
 }
 
-/// Options controlling the output of reports
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct ReportOptions {
-    pub show_child_output: bool,
-    pub child_output_lines: usize,
+/// The result of a triage operation
+enum TriageResult {
+    NoCrash(Box<GdbTriageResult>),
+    Crash(Box<GdbTriageResult>),
+    Error(GdbTriageError),
+    /// The target could not start, so the testcase was not run
+    EnvironmentError(EnvironmentError),
+    Timedout,
 }
 
 /// Data collected during the profiling of a target to triage crashes against
@@ -255,14 +963,12 @@ fn profile_target(
     binary_args: &[&str],
     testcase: &str,
     debug: bool,
-    input_stdin: bool,
+    input_mode: InputMode,
     timeout_ms: u64,
 ) -> std::io::Result<ProfileResult> {
     log::info!("Profiling target...");
 
-    let prog_args = util::expand_filepath_templates(binary_args, testcase);
-
-    let input_file = if input_stdin {
+    let input_file = if input_mode == InputMode::Stdin {
         Some(util::read_file_to_bytes(testcase)?)
     } else {
         None
@@ -270,7 +976,21 @@ fn profile_target(
 
     let start = Instant::now();
     let before_rss = util::get_peak_rss();
-    let process_result = process::execute_capture_output_timeout(&prog_args[0], &prog_args[1..], timeout_ms, input_file);
+    let process_result = with_delivered_input(input_mode, testcase, |input_path| {
+        let prog_args = util::expand_filepath_templates(binary_args, input_path);
+
+        let (program, program_args) = match &gdb.options.sandbox {
+            Some(sandbox) => sandbox.wrap(&prog_args[0], &prog_args[1..],
+                &prog_args.iter().map(Path::new).collect::<Vec<&Path>>()),
+            None => (prog_args[0].clone(), prog_args[1..].to_vec()),
+        };
+
+        match input_file {
+            None if gdb.options.child_stdin == ChildStdin::inherit =>
+                process::execute_capture_output_timeout_inherit_stdin(&program, &program_args, timeout_ms),
+            _ => process::execute_capture_output_timeout(&program, &program_args, timeout_ms, input_file),
+        }
+    });
     let process_execution_time = start.elapsed();
     let after_process_rss = util::get_peak_rss();
     let process_rss = std::cmp::max(after_process_rss - before_rss, 1); // round up to 1kb
@@ -279,7 +999,8 @@ fn profile_target(
         process_execution_time, process_rss);
 
     let start = Instant::now();
-    let triage_result = triage_test_case(gdb, binary_args, testcase, debug, input_stdin, timeout_ms);
+    let raw_output = RawOutput { show: debug, save_as: None };
+    let triage_result = triage_test_case(gdb, binary_args, testcase, &raw_output, input_mode, timeout_ms, TriageDetail::Full);
     let debugger_execution_time = start.elapsed();
     let after_debugger_rss = util::get_peak_rss();
 
@@ -306,22 +1027,152 @@ fn profile_target(
     })
 }
 
+/// Run `f`, which runs the target with the path it is given substituted for `@@`, while sending
+/// `testcase` to the target over a socket or a FIFO if that is the input mode. `f` is given the
+/// path of the FIFO, or `testcase` otherwise
+fn with_delivered_input<T>(input_mode: InputMode, testcase: &str, f: impl FnOnce(&str) -> T) -> T {
+    if !matches!(input_mode, InputMode::Socket(_) | InputMode::Fifo) {
+        return f(testcase);
+    }
+
+    let data = match util::read_file_to_bytes(testcase) {
+        Ok(data) => data,
+        Err(e) => {
+            log::warn!("Failed to read {} to send to the target: {}", shlex::quote(testcase), e);
+            return f(testcase);
+        }
+    };
+
+    let fifo = match input_mode {
+        InputMode::Fifo => match FifoInput::new() {
+            Ok(fifo) => Some(fifo),
+            Err(e) => {
+                log::warn!("Failed to create a FIFO for {}: {}", shlex::quote(testcase), e);
+                return f(testcase);
+            }
+        },
+        _ => None,
+    };
+
+    let done = AtomicBool::new(false);
+
+    std::thread::scope(|s| {
+        let sender = s.spawn(|| match (input_mode, &fifo) {
+            (InputMode::Socket(socket), _) => socket.deliver(&data, &done)
+                .map_err(|e| format!("Failed to send {} to the target on {}: {}", shlex::quote(testcase), socket, e)),
+            (_, Some(fifo)) => fifo.deliver(&data, &done)
+                .map_err(|e| format!("Failed to write {} to the FIFO: {}", shlex::quote(testcase), e)),
+            _ => Ok(()),
+        });
+
+        let result = match &fifo {
+            Some(fifo) => f(&fifo.path().to_string_lossy()),
+            None => f(testcase),
+        };
+        done.store(true, Ordering::SeqCst);
+
+        if let Ok(Err(e)) = sender.join() {
+            log::warn!("{}", e);
+        }
+
+        result
+    })
+}
+
+/// Triage a minidump instead of running the target
+fn triage_minidump_test_case(gdb: &GdbTriager, testcase: &str) -> TriageResult {
+    let triage = util::read_file_to_bytes(testcase)
+        .map_err(|e| e.to_string())
+        .and_then(|data| minidump::triage_minidump(&data));
+
+    match triage {
+        Ok(mut triage) => {
+            if let Some(symbolizer) = &gdb.options.external_symbolizer {
+                symbolizer.symbolize(&mut triage);
+            }
+
+            TriageResult::Crash(Box::new(triage))
+        }
+        Err(e) => TriageResult::Error(GdbTriageError::new_brief(
+            GdbTriageErrorKind::Internal, format!("Failed to triage minidump: {}", e))),
+    }
+}
+
+/// What is done with the raw output of GDB for a testcase
+#[derive(Default)]
+struct RawOutput {
+    /// Print it, with --debug
+    show: bool,
+    /// Save it to this file, with --save-raw-output
+    save_as: Option<PathBuf>,
+}
+
+impl RawOutput {
+    /// The file in `dir` the raw output of `testcase` is saved to. Testcases in different
+    /// directories often have the same name, so a hash of the path is added
+    fn save_path(dir: &Path, testcase: &str) -> PathBuf {
+        let name = Path::new(testcase).file_name()
+            .map(|n| util::sanitize(&n.to_string_lossy()))
+            .unwrap_or_else(|| "testcase".into());
+
+        dir.join(format!("{}_{}.txt", name, &format!("{:x}", md5::compute(testcase))[..8]))
+    }
+
+    fn write(&self, raw_gdb_output: &RawGdbOutput) {
+        if self.show {
+            println!("{}", raw_gdb_output.format());
+        }
+
+        if let Some(path) = &self.save_as {
+            if let Err(e) = std::fs::write(path, raw_gdb_output.format()) {
+                log::warn!("Failed to save the raw GDB output to {}: {}", shlex::quote(&path.to_string_lossy()), e);
+            }
+        }
+    }
+}
+
 /// Triage a single testcase using GDB and the target
 fn triage_test_case(
     gdb: &GdbTriager,
     binary_args: &[&str],
     testcase: &str,
-    debug: bool,
-    input_stdin: bool,
+    raw_output: &RawOutput,
+    input_mode: InputMode,
     timeout_ms: u64,
+    detail: TriageDetail,
 ) -> TriageResult {
-    let prog_args = util::expand_filepath_templates(binary_args, testcase);
+    if minidump::is_minidump(Path::new(testcase)) {
+        return triage_minidump_test_case(gdb, testcase);
+    }
+
+    // Android targets read testcases from the device
+    let testcase = match &gdb.options.android {
+        Some(device) => match device.push_testcase(testcase) {
+            Ok(path) => path,
+            Err(e) => return TriageResult::Error(GdbTriageError::new(
+                GdbTriageErrorKind::Command,
+                "Failed to push the testcase to the Android device",
+                e.to_string(),
+            )),
+        },
+        None => testcase.to_string(),
+    };
+    let testcase = testcase.as_str();
 
     // Whether to pass a file in via GDB stdin
-    let input_file = if input_stdin { Some(testcase) } else { None };
+    let input_file = if input_mode == InputMode::Stdin { Some(testcase) } else { None };
+
+    let (triage_result, raw_gdb_output) = with_delivered_input(input_mode, testcase, |input_path| {
+        let prog_args = util::expand_filepath_templates(binary_args, input_path);
+        gdb.triage_program_with_raw_output(&prog_args, input_file, timeout_ms, detail)
+    });
+
+    if let Some(raw_gdb_output) = raw_gdb_output {
+        raw_output.write(&raw_gdb_output);
+    }
 
     let triage_result: GdbTriageResult =
-        match gdb.triage_program(&prog_args, input_file, debug, timeout_ms) {
+        match triage_result {
             Ok(triage_result) => triage_result,
             Err(e) => {
                 if e.error_kind == GdbTriageErrorKind::Timeout {
@@ -332,52 +1183,271 @@ fn triage_test_case(
             }
         };
 
+    if let Some(e) = loader::detect_environment_error(&triage_result) {
+        return TriageResult::EnvironmentError(e);
+    }
+
     match triage_result.response.result {
-        GdbResultCode::SUCCESS => TriageResult::Crash(triage_result),
-        GdbResultCode::ERROR_TARGET_NOT_RUNNING => TriageResult::NoCrash(triage_result.child),
+        GdbResultCode::SUCCESS => TriageResult::Crash(Box::new(triage_result)),
+        GdbResultCode::ERROR_TARGET_NOT_RUNNING => TriageResult::NoCrash(Box::new(triage_result)),
     }
 }
 
-/// The input type of an input path given to AFLTriage
-enum UserInputPathType {
-    Unknown,
-    Missing,
-    Single,
-    PlainDir,
-    AflDir,
-    AflSyncDir,
-}
+/// Run a testcase that did not crash again, outside of the debugger, and check for a LeakSanitizer
+/// report
+fn check_leaks(gdb: &GdbTriager, binary_args: &[&str], testcase: &str, input_mode: InputMode, timeout_ms: u64) -> Option<LeakFinding> {
+    let input = if input_mode == InputMode::Stdin {
+        match util::read_file_to_bytes(testcase) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                log::warn!("{}: Failed to read the testcase for the leak check: {}", shlex::quote(testcase), e);
+                return None;
+            }
+        }
+    } else {
+        None
+    };
 
-/// A user input path with a type associated
-struct UserInputPath {
-    ty: UserInputPathType,
-    path: PathBuf,
-}
+    let leak_check = with_delivered_input(input_mode, testcase, |input_path| {
+        let prog_args = util::expand_filepath_templates(binary_args, input_path);
+        leak::run_leak_check(&gdb.options, &prog_args, input, timeout_ms)
+    });
 
-/// A wrapper struct for defining a single testcase
-struct Testcase {
-    path: PathBuf,
-    /// Must be safe for filesystem
-    #[allow(dead_code)]
-    unique_id: String,
+    match leak_check {
+        Ok(output) => leak::leak_finding_from_output(&output.stderr),
+        Err(e) => {
+            log::warn!("{}: Leak check failed: {}", shlex::quote(testcase), e);
+            None
+        }
+    }
 }
 
-/// Heuristic to see if a directory seems like an AFL directory
-fn has_afl_directory_signature(input: &Path) -> bool {
-    return input.join("fuzzer_stats").exists() ||
-        input.join("queue").exists() ||
-        input.join("crashes").exists();
-}
+/// Run a crashing testcase again, outside of the debugger, and collect the functions of `binary` it
+/// covered
+fn check_coverage(gdb: &GdbTriager, binary_args: &[&str], testcase: &str, input_mode: InputMode, timeout_ms: u64,
+    binary: &Path, symbolizer: &symbolizer::ExternalSymbolizer) -> Option<Coverage> {
+    let input = if input_mode == InputMode::Stdin {
+        match util::read_file_to_bytes(testcase) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                log::warn!("{}: Failed to read the testcase for coverage: {}", shlex::quote(testcase), e);
+                return None;
+            }
+        }
+    } else {
+        None
+    };
 
-/// Heuristically determine what a user's input path points to
-fn determine_input_type(input: &Path) -> UserInputPathType {
-    let metadata = match input.symlink_metadata() {
-        Ok(meta) => meta,
-        Err(_) => return UserInputPathType::Missing,
+    let dir = match tempfile::tempdir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            log::warn!("{}: Failed to create a coverage directory: {}", shlex::quote(testcase), e);
+            return None;
+        }
     };
 
-    if metadata.file_type().is_file() {
-        return UserInputPathType::Single;
+    let run = with_delivered_input(input_mode, testcase, |input_path| {
+        let prog_args = util::expand_filepath_templates(binary_args, input_path);
+        coverage::run_coverage(&gdb.options, &prog_args, input, timeout_ms, dir.path())
+    });
+
+    // The target is expected to crash, so only a failure to start it is an error
+    let result = run.map_err(|e| e.to_string())
+        .and_then(|_| coverage::collect_coverage(dir.path(), binary, symbolizer));
+
+    match result {
+        Ok(coverage) => Some(coverage),
+        Err(e) => {
+            log::warn!("{}: Coverage collection failed: {}", shlex::quote(testcase), e);
+            None
+        }
+    }
+}
+
+/// Write the reports of an oracle or leak finding to `output_dir`, named after its `bucket`, or
+/// print them if there is none
+fn write_finding_reports(
+    output_dir: &Option<PathBuf>,
+    bucket: &str,
+    rendered_reports: Vec<(&str, &str, String)>,
+    testcase: &str,
+    write_message: &dyn Fn(String, Option<&str>),
+) {
+    for (report_name, extension, data) in rendered_reports {
+        match output_dir {
+            Some(output_dir) => {
+                let report_filename = format!("afltriage_{}.{}", util::sanitize(bucket), extension);
+
+                if let Err(e) = std::fs::write(output_dir.join(&report_filename), data) {
+                    write_message(format!("Failed to write report: {}", e), Some(testcase));
+                }
+            }
+            None => write_message(format!(
+                "--- {} REPORT BEGIN ---\n{}\n--- {} REPORT END ---",
+                report_name, data, report_name,
+            ), None),
+        }
+    }
+}
+
+/// Triage a testcase and check if it crashes in the bucket `strategy_result`. Any crash counts if
+/// the bucketing strategy gave no result
+#[allow(clippy::too_many_arguments)]
+fn crashes_in_bucket(
+    gdb: &GdbTriager,
+    binary_args: &[&str],
+    testcase: &str,
+    pipeline: &AnalysisPipeline,
+    report_options: &ReportOptions,
+    input_mode: InputMode,
+    timeout_ms: u64,
+    strategy_result: &str,
+) -> bool {
+    // Only the bucket is compared
+    match triage_test_case(gdb, binary_args, testcase, &RawOutput::default(), input_mode, timeout_ms, TriageDetail::Brief) {
+        TriageResult::Crash(triage) => match pipeline.run(testcase, &triage, report_options) {
+            Ok(analysis) => analysis.bucket.strategy_result.is_empty() ||
+                analysis.bucket.strategy_result == strategy_result,
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+/// Minimize the first testcase of every bucket. A candidate reproduces if it crashes in the same
+/// bucket, or crashes at all if the bucketing strategy gave no result. Testcases that were
+/// pre-processed are minimized in their `preprocessed` form. Minimized testcases are written to
+/// `output_dir` and added to their bucket's reports
+#[allow(clippy::too_many_arguments)]
+fn minimize_buckets(
+    buckets: &mut HashMap<String, BucketEntry>,
+    preprocessed: &HashMap<String, String>,
+    gdb: &GdbTriager,
+    binary_args: &[&str],
+    pipeline: &AnalysisPipeline,
+    report_options: &ReportOptions,
+    input_mode: InputMode,
+    timeout_ms: u64,
+    max_execs: usize,
+    output_dir: &Path,
+) {
+    buckets.par_iter_mut().for_each(|(bucket, entry)| {
+        let testcase = preprocessed.get(&entry.testcases[0]).unwrap_or(&entry.testcases[0]);
+
+        let input = match util::read_file_to_bytes(testcase) {
+            Ok(input) => input,
+            Err(e) => {
+                log::warn!("Failed to read {} for minimization: {}", shlex::quote(testcase), e);
+                return;
+            }
+        };
+
+        let candidate_file = match tempfile::NamedTempFile::new() {
+            Ok(f) => f,
+            Err(e) => {
+                log::warn!("Failed to create a minimization candidate file: {}", e);
+                return;
+            }
+        };
+        let candidate_path = candidate_file.path().to_str().unwrap();
+
+        let minimized = minimize::minimize(&input, max_execs, |data| {
+            if std::fs::write(candidate_file.path(), data).is_err() {
+                return false;
+            }
+
+            crashes_in_bucket(gdb, binary_args, candidate_path, pipeline, report_options,
+                input_mode, timeout_ms, bucket)
+        });
+
+        // Next to the bucket's reports
+        let filename = entry.reports.first()
+            .map(|r| Path::new(r).with_extension("min").to_string_lossy().to_string())
+            .unwrap_or_else(|| format!("afltriage_{}.min", util::sanitize(bucket)));
+
+        if let Err(e) = std::fs::write(output_dir.join(&filename), &minimized.data) {
+            log::warn!("Failed to write minimized testcase: {}", e);
+            return;
+        }
+
+        log::info!("Minimized {} from {} to {} bytes ({} execs)",
+            shlex::quote(testcase), input.len(), minimized.data.len(), minimized.execs);
+
+        entry.reports.push(filename);
+    });
+}
+
+/// The input type of an input path given to AFLTriage
+enum UserInputPathType {
+    Unknown,
+    Missing,
+    Single,
+    PlainDir,
+    AflDir,
+    AflSyncDir,
+    /// A Honggfuzz workspace
+    HonggfuzzDir,
+    /// A libFuzzer `-artifact_prefix`: a directory or a directory and the start of file names
+    LibFuzzerArtifacts,
+    /// A directory of raw inputs given with --input-dir
+    CorpusDir,
+}
+
+/// Filters for the files of a [UserInputPathType::CorpusDir]
+struct CorpusFilter {
+    /// Globs matched against a file's path relative to the directory. Empty matches everything
+    globs: Vec<Regex>,
+    /// Allowed file extensions. Empty allows all
+    extensions: Vec<String>,
+}
+
+impl CorpusFilter {
+    fn matches(&self, relative_path: &Path) -> bool {
+        let path_str = relative_path.to_string_lossy();
+
+        let glob_match = self.globs.is_empty() ||
+            self.globs.iter().any(|re| re.is_match(&path_str));
+
+        let ext_match = self.extensions.is_empty() ||
+            relative_path.extension()
+                .map(|ext| self.extensions.iter().any(|e| ext == e.as_str()))
+                .unwrap_or(false);
+
+        glob_match && ext_match
+    }
+}
+
+/// A user input path with a type associated
+struct UserInputPath {
+    ty: UserInputPathType,
+    path: PathBuf,
+}
+
+/// A wrapper struct for defining a single testcase
+struct Testcase {
+    path: PathBuf,
+    /// Must be safe for filesystem
+    #[allow(dead_code)]
+    unique_id: String,
+}
+
+/// Heuristic to see if a directory seems like an AFL directory
+fn has_afl_directory_signature(input: &Path) -> bool {
+    return input.join("fuzzer_stats").exists() ||
+        input.join("queue").exists() ||
+        input.join("crashes").exists();
+}
+
+/// Heuristically determine what a user's input path points to
+fn determine_input_type(input: &Path) -> UserInputPathType {
+    let metadata = match input.symlink_metadata() {
+        Ok(meta) => meta,
+        Err(_) if ArtifactPrefix::new(input).has_artifacts() => return UserInputPathType::LibFuzzerArtifacts,
+        Err(_) => return UserInputPathType::Missing,
+    };
+
+    if metadata.file_type().is_file() {
+        return UserInputPathType::Single;
     }
 
     // looks like an AFL dir
@@ -392,14 +1462,73 @@ fn determine_input_type(input: &Path) -> UserInputPathType {
             }
         }
 
+        if honggfuzz::is_honggfuzz_workspace(input) {
+            return UserInputPathType::HonggfuzzDir;
+        }
+
+        if ArtifactPrefix::new(input).has_artifacts() {
+            return UserInputPathType::LibFuzzerArtifacts;
+        }
+
         return UserInputPathType::PlainDir;
     }
 
     UserInputPathType::Unknown
 }
 
+/// Find the target command line AFL++ saved for the AFL directories being triaged
+///
+/// Every AFL directory (and every instance of an AFL sync directory) is checked. The first command
+/// line found is used and any instance with a different command line is warned about.
+fn find_afl_cmdline(processed_inputs: &[UserInputPath]) -> Option<Vec<String>> {
+    let mut afl_dirs: Vec<PathBuf> = vec![];
+
+    for input in processed_inputs {
+        match input.ty {
+            UserInputPathType::AflDir => afl_dirs.push(input.path.clone()),
+            UserInputPathType::AflSyncDir => {
+                for instance in util::list_sorted_files_at(input.path.as_path()).unwrap_or(vec![]) {
+                    if has_afl_directory_signature(instance.as_path()) {
+                        afl_dirs.push(instance);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut found: Option<(PathBuf, Vec<String>)> = None;
+
+    for dir in afl_dirs {
+        let cmdline_path = dir.join("cmdline");
+
+        if let Ok(cmdline) = afl::parse_afl_cmdline(&cmdline_path) {
+            match &found {
+                Some((first_path, first_cmdline)) => {
+                    if *first_cmdline != cmdline {
+                        log::warn!("AFL cmdline {} differs from {}. Ignoring it",
+                            shlex::quote(&cmdline_path.to_string_lossy()),
+                            shlex::quote(&first_path.to_string_lossy()));
+                    }
+                }
+                None => found = Some((cmdline_path, cmdline)),
+            }
+        }
+    }
+
+    found.map(|(path, cmdline)| {
+        log::info!("Using target command line from {}", shlex::quote(&path.to_string_lossy()));
+        cmdline
+    })
+}
+
+/// Environment variables AFLTriage sets for accurate triage
+const MANAGED_ENV_VARS: [&str; 4] = ["LIBC_FATAL_STDERR_", "ASAN_OPTIONS", "ASAN_SYMBOLIZER_PATH", "GOTRACEBACK"];
+
 /// Give AFLTriage the best shot at successfully triaging a target
-fn environment_check(gdb: &GdbTriager, binary_args: &[&str]) -> bool {
+/// Check that the target and debugger can be run. GDB is only started to check its version with
+/// `check_gdb`
+fn environment_check(gdb: &GdbTriager, binary_args: &[&str], check_gdb: bool) -> bool {
     let rawexe = binary_args.get(0).unwrap();
     let exe = PathBuf::from(rawexe);
     let justfilename = exe
@@ -409,28 +1538,52 @@ fn environment_check(gdb: &GdbTriager, binary_args: &[&str]) -> bool {
         .unwrap();
 
     // A PATH resolvable name
-    if justfilename == *rawexe {
-        if which::which(rawexe).is_err() {
-            log::error!(
-                "Binary {} not found in PATH. Try using the absolute path",
-                rawexe
-            );
-            return false;
+    // The target is on the Android device
+    let binary_path = if gdb.options.android.is_some() {
+        exe.clone()
+    } else if justfilename == *rawexe {
+        match which::which(rawexe) {
+            Ok(path) => path,
+            Err(_) => {
+                log::error!(
+                    "Binary {} not found in PATH. Try using the absolute path",
+                    rawexe
+                );
+                return false;
+            }
         }
-    } else if !exe.is_executable() {
+    } else if gdb.options.remote.is_some() && !exe.is_file() {
+        log::error!("Firmware {} does not exist", rawexe);
+        return false;
+    } else if gdb.options.remote.is_none() && !exe.is_executable() {
         log::error!("Binary {} does not exist or is not executable", rawexe);
         return false;
-    }
+    } else {
+        exe.clone()
+    };
 
-    if !gdb.has_supported_gdb() {
+    if check_gdb && gdb.options.valgrind.is_none() && gdb.options.cdb.is_none() && gdb.options.lldb.is_none() &&
+        !gdb.has_supported_gdb() {
         return false;
     }
 
+    if gdb.options.lldb.is_some() {
+        if let Some(problem) = macos::debugging_problem(&binary_path) {
+            log::warn!("{}", problem);
+        }
+    }
+
     // Undocumented Glibc env var that prevents it from printing to /dev/tty, which isn't captured by GDB
     // https://stackoverflow.com/questions/32056387/catching-libc-error-messages-redirecting-from-dev-tty
     env::set_var("LIBC_FATAL_STDERR_", "1");
 
-    match env::var("ASAN_OPTIONS") {
+    // Variables given for the target override those of AFLTriage's environment
+    let target_var = |name: &str| match gdb.options.target_env.iter().rev().find(|(key, _)| key == name) {
+        Some((_, value)) => Ok(value.to_string()),
+        None => env::var(name),
+    };
+
+    match target_var("ASAN_OPTIONS") {
         Ok(val) => {
             log::warn!("Using ASAN_OPTIONS=\"{}\" that was set by the environment. This can change triage result accuracy", val);
 
@@ -446,6 +1599,12 @@ fn environment_check(gdb: &GdbTriager, binary_args: &[&str]) -> bool {
         ),
     }
 
+    // Go programs exit with status 2 on a panic or fatal error, which the debugger does not see as
+    // a crash, unless the runtime is told to abort
+    if target_var("GOTRACEBACK").is_err() {
+        env::set_var("GOTRACEBACK", "crash");
+    }
+
     match env::var("ASAN_SYMBOLIZER_PATH") {
         Ok(val) => {
             log::info!(
@@ -464,41 +1623,120 @@ fn environment_check(gdb: &GdbTriager, binary_args: &[&str]) -> bool {
         },
     }
 
+    // Unsymbolized ASAN reports bucket and summarize poorly, without anything failing
+    if let Ok(true) = symbolizer::is_asan_instrumented(&binary_path) {
+        let problem = symbolizer::asan_symbolizer_problem(
+            target_var("ASAN_OPTIONS").ok().as_deref(),
+            target_var("ASAN_SYMBOLIZER_PATH").ok().as_deref());
+
+        if let Some(problem) = problem {
+            log::warn!("********************************************************************************");
+            log::warn!("{} is instrumented with ASAN, but {}", rawexe, problem);
+            log::warn!("ASAN reports will only contain raw addresses, which makes bucketing and summaries unreliable");
+            log::warn!("Install llvm-symbolizer or set ASAN_SYMBOLIZER_PATH to llvm-symbolizer or addr2line");
+            log::warn!("********************************************************************************");
+        }
+    }
+
     true
 }
 
+/// List what a triage run would do with `--dry-run`: the debugger configuration, and each testcase
+/// with the command line it would be triaged with
+fn print_dry_run(gdb: &GdbTriager, gdb_path: &str, binary_args: &[&str], input_mode: InputMode, testcases: &[Testcase]) {
+    let debugger = match (&gdb.options.valgrind, &gdb.options.cdb, &gdb.options.lldb) {
+        (Some(valgrind), _, _) => format!("valgrind ({})", valgrind.display()),
+        (_, Some(cdb), _) => format!("cdb ({})", cdb.display()),
+        (_, _, Some(lldb)) => format!("lldb ({})", lldb.display()),
+        _ => match which::which(gdb_path) {
+            Ok(path) => format!("gdb ({}, not started)", path.display()),
+            Err(_) => format!("gdb ({}, NOT FOUND)", gdb_path),
+        },
+    };
+
+    println!("Debugger: {}", debugger);
+
+    if let Some(remote) = &gdb.options.remote {
+        println!("Remote target: {} (fault handlers: {})", remote.address, remote.fault_handlers.join(", "));
+    }
+
+    if let Some(device) = &gdb.options.android {
+        println!("Android device: {} ({} on port {}, testcases in {})", device.serial.as_deref().unwrap_or("the only one"),
+            device.gdbserver, device.port, android::DEVICE_DIR);
+    }
+
+    println!("Sandbox: {}", gdb.options.sandbox.as_ref().map(|s| s.level.to_string()).unwrap_or_else(|| "none".into()));
+    println!("ASLR: {}", if gdb.options.aslr { "enabled" } else { "disabled" });
+    println!("Input mode: {}", match input_mode {
+        InputMode::File => "file (@@)".to_string(),
+        InputMode::Stdin => "stdin".to_string(),
+        InputMode::Socket(socket) => socket.to_string(),
+        InputMode::Fifo => "fifo (a named pipe replaces @@)".to_string(),
+    });
+
+    for (key, value) in &gdb.options.target_env {
+        println!("Environment: {}={}", key, value);
+    }
+
+    println!("\n{} testcases would be triaged:", testcases.len());
+
+    for testcase in testcases {
+        let path = testcase.path.to_string_lossy();
+        let command = util::shell_join(&util::expand_filepath_templates(binary_args, &path));
+
+        match input_mode {
+            InputMode::File => println!("{}", command),
+            InputMode::Stdin => println!("{} < {}", command, shlex::quote(&path)),
+            InputMode::Socket(socket) => println!("{}  # {} sent over {}", command, shlex::quote(&path), socket),
+            InputMode::Fifo => println!("{}  # {} written to a FIFO at @@", util::shell_join(binary_args), shlex::quote(&path)),
+        }
+    }
+}
+
 /// Crashes and stats from an AFL directory
 struct AflDirInfo {
     testcases: Vec<Testcase>,
     aflstats: Option<AflStats>,
 }
 
-/// Collect the paths to crashes found by AFL and read the fuzzer_stats
-fn collect_afl_crashes_from_dir(path: &Path) -> Option<AflDirInfo> {
+/// Collect the paths to crashes (and hangs if `include_hangs`, and the queue if `include_queue`)
+/// found by AFL and read the fuzzer_stats
+fn collect_afl_crashes_from_dir(path: &Path, include_hangs: bool, include_queue: bool) -> Option<AflDirInfo> {
     let mut testcases = vec![];
     let path_str = shlex::quote(path.to_str().unwrap());
+    let mut subdirs = vec!["crashes"];
 
-    match util::list_sorted_files_at(path.join("crashes").as_path()) {
-        Ok(tcs) => {
-            for tc in tcs {
-                if tc.is_file() {
-                    // TODO: robust filter command (.*id:.*)
-                    if tc.file_name().unwrap() == "README.txt" {
-                        continue;
-                    }
+    if include_hangs {
+        subdirs.push("hangs");
+    }
+
+    if include_queue {
+        subdirs.push(afl::QUEUE_DIR);
+    }
+
+    for subdir in subdirs {
+        match util::list_sorted_files_at(path.join(subdir).as_path()) {
+            Ok(tcs) => {
+                for tc in tcs {
+                    if tc.is_file() {
+                        // TODO: robust filter command (.*id:.*)
+                        if tc.file_name().unwrap() == "README.txt" {
+                            continue;
+                        }
 
-                    testcases.push(Testcase {
-                        unique_id: "".to_string(),
-                        path: tc,
-                    });
+                        testcases.push(Testcase {
+                            unique_id: "".to_string(),
+                            path: tc,
+                        });
+                    }
                 }
             }
-        }
-        Err(e) => {
-            if e.kind() != std::io::ErrorKind::NotFound {
-                log::warn!("Failed to get AFL crashes from directory {}: {}",
-                path_str, e);
-                return None;
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("Failed to get AFL {} from directory {}: {}",
+                    subdir, path_str, e);
+                    return None;
+                }
             }
         }
     }
@@ -525,7 +1763,8 @@ fn collect_afl_crashes_from_dir(path: &Path) -> Option<AflDirInfo> {
 }
 
 /// With determined [UserInputPath]s, extract all files from the paths into [Testcase]s
-fn collect_input_testcases(processed_inputs: &mut Vec<UserInputPath>) -> Vec<Testcase> {
+fn collect_input_testcases(processed_inputs: &mut Vec<UserInputPath>, corpus_filter: &CorpusFilter, include_hangs: bool,
+    include_queue: bool) -> Vec<Testcase> {
     let mut all_testcases = Vec::new();
 
     for input in processed_inputs {
@@ -563,7 +1802,7 @@ fn collect_input_testcases(processed_inputs: &mut Vec<UserInputPath>) -> Vec<Tes
                 }
             }
             UserInputPathType::AflDir => {
-                if let Some(afldir) = collect_afl_crashes_from_dir(input.path.as_path()) {
+                if let Some(afldir) = collect_afl_crashes_from_dir(input.path.as_path(), include_hangs, include_queue) {
                     if afldir.testcases.is_empty() {
                         log::warn!("No crashes found in AFL directory {}", path_str);
                     } else {
@@ -585,7 +1824,7 @@ fn collect_input_testcases(processed_inputs: &mut Vec<UserInputPath>) -> Vec<Tes
                 for instance in util::list_sorted_files_at(input.path.as_path()).unwrap_or(vec![]) {
                     let subpath = instance.as_path();
                     if has_afl_directory_signature(subpath) {
-                        if let Some(afldir) = collect_afl_crashes_from_dir(subpath) {
+                        if let Some(afldir) = collect_afl_crashes_from_dir(subpath, include_hangs, include_queue) {
                             instances.push((instance, afldir));
                         }
                     }
@@ -609,6 +1848,51 @@ fn collect_input_testcases(processed_inputs: &mut Vec<UserInputPath>) -> Vec<Tes
                     }
                 }
             }
+            UserInputPathType::HonggfuzzDir => {
+                match honggfuzz::collect_crashes(input.path.as_path(), include_hangs) {
+                    Ok(tcs) if tcs.is_empty() => log::warn!("No crashes found in Honggfuzz workspace {}", path_str),
+                    Ok(tcs) => {
+                        log::info!("Triaging Honggfuzz workspace {} ({} files)", path_str, tcs.len());
+                        all_testcases.extend(tcs.into_iter().map(|path| Testcase { unique_id: "".to_string(), path }));
+                    }
+                    Err(e) => log::warn!("Failed to get crashes from Honggfuzz workspace {}: {}", path_str, e),
+                }
+            }
+            UserInputPathType::LibFuzzerArtifacts => {
+                match ArtifactPrefix::new(input.path.as_path()).collect_artifacts(include_hangs) {
+                    Ok(tcs) if tcs.is_empty() => log::warn!("No crashes found with libFuzzer artifact prefix {}", path_str),
+                    Ok(tcs) => {
+                        log::info!("Triaging libFuzzer artifacts {} ({} files)", path_str, tcs.len());
+                        all_testcases.extend(tcs.into_iter().map(|path| Testcase { unique_id: "".to_string(), path }));
+                    }
+                    Err(e) => log::warn!("Failed to get libFuzzer artifacts {}: {}", path_str, e),
+                }
+            }
+            UserInputPathType::CorpusDir => {
+                match util::list_sorted_files_recursive(input.path.as_path()) {
+                    Ok(tcs) => {
+                        let total = tcs.len();
+                        let mut valid = 0;
+
+                        for tc in tcs {
+                            if corpus_filter.matches(tc.strip_prefix(&input.path).unwrap_or(&tc)) {
+                                valid += 1;
+                                all_testcases.push(Testcase {
+                                    unique_id: "".to_string(),
+                                    path: tc,
+                                });
+                            }
+                        }
+
+                        if valid > 0 {
+                            log::info!("Triaging input directory {} ({} of {} files)", path_str, valid, total);
+                        } else {
+                            log::warn!("No matching files found in input directory {}", path_str);
+                        }
+                    }
+                    Err(e) => log::warn!("Failed to get files from input directory {}: {}", path_str, e),
+                }
+            }
             UserInputPathType::Unknown | UserInputPathType::Missing => log::warn!("Skipping unknown or missing path {}", path_str),
         }
     }
@@ -658,109 +1942,1011 @@ fn main() {
     std::process::exit(main_wrapper());
 }
 
-fn main_wrapper() -> i32 {
-    let args = setup_command_line();
-
+/// Reduce the privileges of AFLTriage with --harden. Returns the privileges in effect, if they
+/// could be read
+fn harden_privileges(args: &ArgMatches) -> Result<Option<privileges::Privileges>, String> {
+    let hardened = args.is_present("harden");
 
-    println!("AFLTriage v{} by Grant Hernandez\n", VERSION);
-    init_logger();
+    if hardened {
+        if unsafe { libc::geteuid() } == 0 && !args.is_present("allow_root") {
+            return Err("Refusing to triage untrusted testcases as root with --harden. Run as an unprivileged user or pass --allow-root".into());
+        }
 
-    let stop_requested = Arc::new(AtomicBool::new(false));
-    for sig in signal_hook::consts::TERM_SIGNALS {
-        // will exit on Ctrl+c the second time
-        // FIXME: child processes can still be running, becoming orphaned
-        signal_hook::flag::register_conditional_shutdown(*sig, 1, Arc::clone(&stop_requested)).unwrap();
-        // Friendly nudge to exit
-        signal_hook::flag::register(*sig, Arc::clone(&stop_requested)).unwrap();
+        privileges::harden(&[privileges::CAP_SYS_PTRACE])
+            .map_err(|e| format!("Failed to drop privileges: {}", e))?;
     }
 
-    let aenv = match environment::parse_afltriage_env() {
-        Some(e) => e,
-        None => {
-            log::error!("Failed to parse environment variables");
-            return 1;
+    match privileges::read_privileges() {
+        Ok(mut privileges) => {
+            privileges.hardened = hardened;
+            if hardened {
+                log::info!("Reduced privileges to {}", privileges.summary());
+            } else {
+                log::debug!("Privileges: {}", privileges.summary());
+            }
+            Ok(Some(privileges))
         }
-    };
+        Err(e) => {
+            log::warn!("Failed to read the privileges of AFLTriage: {}", e);
+            Ok(None)
+        }
+    }
+}
 
-    let binary_args: Vec<&str> = args.values_of("command").unwrap().collect();
-    let gdb: GdbTriager = GdbTriager::new(aenv.gdb_path.to_string());
+/// Apply --cpu-set and --nice to AFLTriage
+fn limit_scheduling(args: &ArgMatches) -> Result<(), String> {
+    if let Some(spec) = args.value_of("cpu_set") {
+        let cpus = scheduling::parse_cpu_set(spec)?;
 
-    if !environment_check(&gdb, &binary_args) {
-        return 1;
+        scheduling::set_cpu_affinity(&cpus)
+            .map_err(|e| format!("Failed to restrict AFLTriage to CPUs {}: {}", spec, e))?;
+        log::info!("Running on CPUs {} ({} core(s))", spec, cpus.len());
     }
 
-    let input_stdin = args.is_present("stdin");
-    let has_atat = binary_args.iter().any(|s| *s == "@@");
-
-    if input_stdin {
-        log::info!("Providing testcase input via stdin");
+    if args.is_present("nice") {
+        let niceness = value_t!(args, "nice", i32).unwrap_or_else(|e| e.exit());
 
-        if has_atat {
-            log::warn!("Image triage args contains @@ but you are using --stdin");
+        if !(0..=scheduling::MAX_NICENESS).contains(&niceness) {
+            return Err(format!("--nice must be from 0 to {}", scheduling::MAX_NICENESS));
         }
-    } else {
 
-        if !has_atat {
-            log::error!("Image triage args missing file placeholder: @@. If you'd like to pass input to the child via stdin, use the --stdin option.");
-            return 1;
-        }
+        scheduling::set_niceness(niceness).map_err(|e| format!("Failed to set the niceness to {}: {}", niceness, e))?;
+        log::info!("Running with niceness {}", niceness);
     }
 
-    log::info!("Image triage cmdline: {}", util::shell_join(&binary_args));
+    Ok(())
+}
 
-    let output = args.value_of("output").unwrap();
+fn main_wrapper() -> i32 {
+    // Subcommands are dispatched by hand as clap cannot mix them with a trailing target command
+    if env::args().nth(1).as_deref() == Some("rebucket") {
+        let args = rebucket::build_app().get_matches_from(env::args().skip(1));
 
-    // Output to the terminal
-    let output_dir = if output == "-" {
-        None
-    } else {
-        let d = std::path::PathBuf::from(output);
+        println!("AFLTriage v{} by Grant Hernandez\n", VERSION);
+        init_logger();
 
-        if let Err(e) = std::fs::create_dir(&d) {
-            if e.kind() != std::io::ErrorKind::AlreadyExists {
-                log::error!("Error creating output directory: {}", e);
-                return 1;
-            }
-        }
+        return rebucket::rebucket_main(&args);
+    }
 
-        Some(d)
-    };
+    if env::args().nth(1).as_deref() == Some("diff") {
+        let args = diff::build_app().get_matches_from(env::args().skip(1));
 
-    let report_output_formats: Vec<ReportOutputFormat> = values_t!(args, "report_formats", ReportOutputFormat).unwrap_or_else(|e| e.exit());
-    let report_output_formats_s = report_output_formats.iter()
-        .map(|f| f.to_string())
-        .collect::<Vec<String>>()
-        .join(", ");
+        // Keep JSON output parseable
+        if !args.is_present("json") {
+            println!("AFLTriage v{} by Grant Hernandez\n", VERSION);
+        }
+        init_logger();
 
-    if output_dir.is_some() {
-        log::info!("Will write {} reports to directory \"{}\"", report_output_formats_s, output);
-    } else {
-        log::info!("Will output {} reports to terminal", report_output_formats_s);
+        return diff::diff_main(&args);
     }
 
-    let input_paths: Vec<&str> = args.values_of("input").unwrap().collect();
-
-    let mut processed_inputs = Vec::new();
+    if env::args().nth(1).as_deref() == Some("known") {
+        let args = known::build_app().get_matches_from(env::args().skip(1));
 
-    for input in input_paths {
-        let path = PathBuf::from(input);
-        let ty = determine_input_type(&path);
+        println!("AFLTriage v{} by Grant Hernandez\n", VERSION);
+        init_logger();
 
-        processed_inputs.push(UserInputPath {
-            ty,
-            path,
-        });
+        return known::known_main(&args);
     }
 
-    let all_testcases = collect_input_testcases(&mut processed_inputs);
+    if env::args().nth(1).as_deref() == Some("issues") {
+        let args = issues::build_app().get_matches_from(env::args().skip(1));
 
-    if all_testcases.is_empty() {
-        log::error!("No testcases found!");
-        return 1;
+        println!("AFLTriage v{} by Grant Hernandez\n", VERSION);
+        init_logger();
+
+        return issues::issues_main(&args);
     }
 
-    let debug = args.is_present("debug");
-    let child_output = args.is_present("child_output");
+    let (args, config_path) = setup_command_line();
+
+
+    println!("AFLTriage v{} by Grant Hernandez\n", VERSION);
+    init_logger();
+
+    if let Some(path) = config_path {
+        log::info!("Using configuration file {}", path.display());
+    }
+
+    let stop_requested = Arc::new(AtomicBool::new(false));
+    for sig in signal_hook::consts::TERM_SIGNALS {
+        // will exit on Ctrl+c the second time
+        // FIXME: child processes can still be running, becoming orphaned
+        signal_hook::flag::register_conditional_shutdown(*sig, 1, Arc::clone(&stop_requested)).unwrap();
+        // Friendly nudge to exit
+        signal_hook::flag::register(*sig, Arc::clone(&stop_requested)).unwrap();
+    }
+
+    // Before any thread or process is started, as they inherit the privileges
+    let privileges = match harden_privileges(&args) {
+        Ok(privileges) => privileges,
+        Err(e) => {
+            log::error!("{}", e);
+            return 1;
+        }
+    };
+
+    // Likewise inherited by threads and processes
+    if let Err(e) = limit_scheduling(&args) {
+        log::error!("{}", e);
+        return 1;
+    }
+
+    let aenv = match environment::parse_afltriage_env() {
+        Some(e) => e,
+        None => {
+            log::error!("Failed to parse environment variables");
+            return 1;
+        }
+    };
+
+    let input_paths: Vec<&str> = args.values_of("input").unwrap_or_default().collect();
+
+    let mut processed_inputs = Vec::new();
+
+    for input in input_paths {
+        let path = PathBuf::from(input);
+        let ty = determine_input_type(&path);
+
+        processed_inputs.push(UserInputPath {
+            ty,
+            path,
+        });
+    }
+
+    for input in args.values_of("input_dir").unwrap_or_default() {
+        let path = PathBuf::from(input);
+        let ty = if path.is_dir() {
+            UserInputPathType::CorpusDir
+        } else {
+            UserInputPathType::Missing
+        };
+
+        processed_inputs.push(UserInputPath {
+            ty,
+            path,
+        });
+    }
+
+    let mut corpus_filter = CorpusFilter {
+        globs: vec![],
+        extensions: args.values_of("extensions").unwrap_or_default()
+            .map(|ext| ext.trim_start_matches('.').to_string())
+            .collect(),
+    };
+
+    let mut testcase_filters = vec![];
+
+    for spec in args.values_of("filter").unwrap_or_default() {
+        match TestcaseFilter::parse(spec) {
+            Ok(filter) => testcase_filters.push(filter),
+            Err(e) => {
+                log::error!("{}", e);
+                return 1;
+            }
+        }
+    }
+
+    for glob in args.values_of("include").unwrap_or_default() {
+        match util::glob_to_regex(glob) {
+            Ok(re) => corpus_filter.globs.push(re),
+            Err(e) => {
+                log::error!("Invalid --include glob {}: {}", shlex::quote(glob), e);
+                return 1;
+            }
+        }
+    }
+
+    // An explicit command always overrides the one saved by AFL++
+    let binary_args_owned: Vec<String> = match args.values_of("command") {
+        Some(command) => command.map(str::to_string).collect(),
+        None => match find_afl_cmdline(&processed_inputs) {
+            Some(cmdline) => cmdline,
+            None => {
+                log::error!("No target command line was given and no AFL cmdline file was found in the inputs");
+                return 1;
+            }
+        },
+    };
+
+    let binary_args: Vec<&str> = binary_args_owned.iter().map(String::as_str).collect();
+
+    let mut target_env = vec![];
+
+    if let Some(env_file) = args.value_of("env_file") {
+        match environment::read_env_file(Path::new(env_file)) {
+            Ok(vars) => target_env.extend(vars),
+            Err(e) => {
+                log::error!("Failed to read environment file {}: {}", shlex::quote(env_file), e);
+                return 1;
+            }
+        }
+    }
+
+    for assignment in args.values_of("env").unwrap_or_default() {
+        match environment::parse_env_assignment(assignment) {
+            Some(var) => target_env.push(var),
+            None => {
+                log::error!("Invalid --env value {}. Expected KEY=VAL", shlex::quote(assignment));
+                return 1;
+            }
+        }
+    }
+
+    let mut extra_scripts = vec![];
+
+    for script in args.values_of("triage_script_extra").unwrap_or_default() {
+        let path = PathBuf::from(script);
+
+        if !path.is_file() {
+            log::error!("Triage script {} does not exist", shlex::quote(script));
+            return 1;
+        }
+
+        log::info!("Loading extra triage script {}", shlex::quote(script));
+        extra_scripts.push(path);
+    }
+
+    let mut oracles = vec![];
+
+    for spec in args.values_of("oracle").unwrap_or_default() {
+        match Oracle::parse(spec) {
+            Ok(oracle) => oracles.push(oracle),
+            Err(e) => {
+                log::error!("{}", e);
+                return 1;
+            }
+        }
+    }
+
+    if !oracles.is_empty() {
+        log::info!("Checking runs without a crash with oracles: {}",
+            oracles.iter().map(|o| o.id.as_str()).collect::<Vec<&str>>().join(", "));
+    } else if args.is_present("oracle_breakpoint") {
+        log::warn!("--oracle-breakpoint has no effect without --oracle");
+    }
+
+    // Leak checks run the target on this machine
+    let detect_leaks = args.is_present("detect_leaks") && !args.is_present("android");
+    let report_no_crash = args.is_present("report_no_crash");
+
+    if detect_leaks {
+        log::info!("Checking runs without a crash for leaks with LeakSanitizer");
+    }
+
+    let max_child_output = match value_t!(args, "max_child_output", usize) {
+        Ok(0) => None,
+        Ok(n) if n < process::MIN_OUTPUT_LIMIT => {
+            log::error!("--max-child-output must be 0 or at least {} bytes", process::MIN_OUTPUT_LIMIT);
+            return 1;
+        }
+        Ok(n) => Some(n),
+        Err(_) => {
+            log::error!("Max child output parse error");
+            return 1;
+        }
+    };
+
+    let sandbox = match value_t!(args, "sandbox", SandboxLevel) {
+        Ok(SandboxLevel::none) => {
+            if args.is_present("sandbox_seccomp") {
+                log::error!("--sandbox-seccomp requires --sandbox net or --sandbox full");
+                return 1;
+            }
+
+            None
+        }
+        Ok(level) => {
+            if which::which("bwrap").is_err() {
+                log::error!("bwrap (bubblewrap) is required to sandbox the target, but it was not found in PATH");
+                return 1;
+            }
+
+            let seccomp_filter = args.value_of("sandbox_seccomp").map(PathBuf::from);
+
+            if let Some(filter) = &seccomp_filter {
+                if !filter.is_file() {
+                    log::error!("Seccomp filter {} does not exist", shlex::quote(&filter.to_string_lossy()));
+                    return 1;
+                }
+            }
+
+            log::info!("Sandboxing the target (level {})", level);
+            Some(Sandbox { level, seccomp_filter })
+        }
+        Err(e) => {
+            log::error!("{}", e);
+            return 1;
+        }
+    };
+
+    // GDB cannot debug programs on current versions of macOS
+    let use_lldb = args.is_present("lldb") || (cfg!(target_os = "macos") &&
+        !["gdb_path", "valgrind", "cdb", "gdb_remote", "android"].iter().any(|name| args.is_present(name)));
+
+    let android = match args.is_present("android") {
+        false => None,
+        true => match which::which("adb") {
+            Ok(adb) => {
+                let mut device = AndroidDevice::new(adb, args.value_of("adb_serial").map(str::to_string));
+
+                if let Some(gdbserver) = args.value_of("android_gdbserver") {
+                    device.gdbserver = gdbserver.to_string();
+                }
+
+                if args.is_present("android_port") {
+                    device.port = value_t!(args, "android_port", u16).unwrap_or_else(|e| e.exit());
+                }
+
+                for dir in args.values_of("android_symbols").unwrap_or_default() {
+                    if !Path::new(dir).is_dir() {
+                        log::error!("Android symbol directory {} does not exist", shlex::quote(dir));
+                        return 1;
+                    }
+
+                    device.symbol_dirs.push(PathBuf::from(dir));
+                }
+
+                Some(device)
+            }
+            Err(_) => {
+                log::error!("adb not found in PATH");
+                return 1;
+            }
+        },
+    };
+
+    let remote_log_dir = match args.value_of("remote_log_dir") {
+        Some(_) if !args.is_present("gdb_remote") && !args.is_present("android") => {
+            log::error!("--remote-log-dir needs a remote target. Use --gdb-remote or --android");
+            return 1;
+        }
+        Some(dir) => match std::fs::create_dir_all(dir) {
+            Ok(()) => Some(PathBuf::from(dir)),
+            Err(e) => {
+                log::error!("Failed to create the remote log directory {}: {}", shlex::quote(dir), e);
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let substitute_paths: Vec<&str> = args.values_of("substitute_path").map(|v| v.collect()).unwrap_or_default();
+
+    let source_path_map = match SourcePathMap::parse(&substitute_paths) {
+        Ok(map) => map,
+        Err(e) => {
+            log::error!("{}", e);
+            return 1;
+        }
+    };
+
+    let mut debug_file_dirs = vec![];
+
+    for dir in args.values_of("debug_file_dir").into_iter().flatten() {
+        let mut dir = PathBuf::from(dir);
+
+        if !dir.is_dir() {
+            log::error!("Debug file directory {} does not exist", shlex::quote(&dir.to_string_lossy()));
+            return 1;
+        }
+
+        // GDB adds .build-id itself
+        if dir.file_name().map(|name| name == ".build-id").unwrap_or(false) {
+            dir.pop();
+        }
+
+        debug_file_dirs.push(dir);
+    }
+
+    let gdb_options = GdbTriageOptions {
+        target_env,
+        clear_env: args.is_present("clear_env"),
+        extra_scripts,
+        follow_forks: args.is_present("follow_forks"),
+        oracle_breakpoint: args.value_of("oracle_breakpoint").map(str::to_string),
+        detect_hangs: args.is_present("hangs"),
+        aslr: args.is_present("aslr"),
+        sandbox,
+        child_stdin: value_t!(args, "child_stdin", ChildStdin).unwrap_or_else(|e| e.exit()),
+        reuse_gdb: args.is_present("reuse_gdb"),
+        index_cache: match args.is_present("no_index_cache") {
+            true => None,
+            false => args.value_of("index_cache").map(PathBuf::from),
+        },
+        debuginfod: args.is_present("debuginfod") || args.is_present("debuginfod_urls"),
+        external_symbolizer: match args.value_of("external_symbolizer").unwrap() {
+            "off" => None,
+            spec => match symbolizer::ExternalSymbolizer::find(spec) {
+                Some(s) => Some(s),
+                None if spec == "auto" => None,
+                None => {
+                    log::error!("External symbolizer {} not found", spec);
+                    return 1;
+                }
+            },
+        },
+        valgrind: match args.is_present("valgrind") {
+            false => None,
+            true => match which::which("valgrind") {
+                Ok(path) => Some(path),
+                Err(_) => {
+                    log::error!("Valgrind not found in PATH");
+                    return 1;
+                }
+            },
+        },
+        cdb: match args.is_present("cdb") {
+            false => None,
+            true => match which::which("cdb") {
+                Ok(path) => Some(path),
+                Err(_) => {
+                    log::error!("CDB not found in PATH");
+                    return 1;
+                }
+            },
+        },
+        lldb: match use_lldb {
+            false => None,
+            true => match which::which("lldb") {
+                Ok(path) => Some(path),
+                Err(_) => {
+                    log::error!("LLDB not found in PATH");
+                    return 1;
+                }
+            },
+        },
+        dsym_dirs: args.values_of("dsym_dir")
+            .map(|v| v.map(PathBuf::from).collect())
+            .unwrap_or_default(),
+        debug_file_dirs,
+        max_child_output,
+        crashing_thread_only: args.is_present("crashing_thread_only"),
+        raw_values: args.is_present("no_pretty_printers"),
+        remote: args.value_of("gdb_remote").map(|address| {
+            let defaults = RemoteTarget::new(address);
+
+            RemoteTarget {
+                reset_commands: args.values_of("remote_reset")
+                    .map(|v| v.map(str::to_string).collect())
+                    .unwrap_or(defaults.reset_commands),
+                input_commands: args.values_of("remote_input")
+                    .map(|v| v.map(str::to_string).collect())
+                    .unwrap_or_default(),
+                fault_handlers: args.values_of("remote_fault_handler")
+                    .map(|v| v.map(str::to_string).collect())
+                    .unwrap_or(defaults.fault_handlers),
+                address: defaults.address,
+            }
+        }),
+        android,
+        remote_log_dir,
+        source_path_map: source_path_map.clone(),
+    };
+
+    if gdb_options.valgrind.is_some() {
+        let gdb_only: Vec<&str> = ["sandbox", "follow_forks", "hangs", "oracle_breakpoint", "reuse_gdb", "debuginfod",
+            "debug_file_dir", "no_pretty_printers"].iter()
+            .filter(|name| args.is_present(name))
+            .copied()
+            .collect();
+
+        if !gdb_only.is_empty() {
+            log::warn!("Ignoring options that need GDB under --valgrind: {}", gdb_only.join(", "));
+        }
+    }
+
+    if gdb_options.lldb.is_some() {
+        let gdb_only: Vec<&str> = ["sandbox", "follow_forks", "hangs", "oracle_breakpoint", "reuse_gdb", "debuginfod",
+            "debug_file_dir", "no_pretty_printers"].iter()
+            .filter(|name| args.is_present(name))
+            .copied()
+            .collect();
+
+        if !gdb_only.is_empty() {
+            log::warn!("Ignoring options that need GDB under LLDB: {}", gdb_only.join(", "));
+        }
+    }
+
+    if gdb_options.cdb.is_some() {
+        let gdb_only: Vec<&str> = ["env", "env_file", "clear_env", "aslr", "sandbox", "follow_forks", "hangs",
+            "oracle_breakpoint", "reuse_gdb", "debuginfod", "debug_file_dir", "no_pretty_printers"].iter()
+            .filter(|name| args.is_present(name))
+            .copied()
+            .collect();
+
+        if !gdb_only.is_empty() {
+            log::warn!("Ignoring options that need GDB under --cdb: {}", gdb_only.join(", "));
+        }
+    }
+
+    // GDB runs the target with a command, which cannot span lines
+    if gdb_options.valgrind.is_none() && gdb_options.cdb.is_none() && gdb_options.lldb.is_none() &&
+        binary_args.iter().skip(1).any(|arg| arg.contains('\n')) {
+        log::error!("GDB cannot pass a target argument containing a newline. Pass it from a wrapper script instead");
+        return 1;
+    }
+
+    if let Some(remote) = &gdb_options.remote {
+        let local_only: Vec<&str> = ["env", "env_file", "clear_env", "aslr", "follow_forks", "hangs", "oracle_breakpoint", "reuse_gdb"].iter()
+            .filter(|name| args.is_present(name))
+            .copied()
+            .collect();
+
+        if !local_only.is_empty() {
+            log::warn!("Ignoring options that need a local target under --gdb-remote: {}", local_only.join(", "));
+        }
+
+        if gdb_options.sandbox.is_some() {
+            log::error!("The sandbox has no network access, so GDB cannot connect to {}", remote.address);
+            return 1;
+        }
+
+        if remote.input_commands.is_empty() {
+            log::warn!("No --remote-input commands were given, so testcases are not delivered to the remote target");
+        }
+
+        log::info!("Triaging on the remote target {}, stopping in {}", remote.address, remote.fault_handlers.join(", "));
+    }
+
+    if let Some(device) = &gdb_options.android {
+        let local_only: Vec<&str> = ["clear_env", "aslr", "follow_forks", "hangs", "oracle_breakpoint", "reuse_gdb", "detect_leaks"].iter()
+            .filter(|name| args.is_present(name))
+            .copied()
+            .collect();
+
+        if !local_only.is_empty() {
+            log::warn!("Ignoring options that need a local target under --android: {}", local_only.join(", "));
+        }
+
+        if gdb_options.sandbox.is_some() {
+            log::error!("The sandbox cannot reach adb, so the target cannot run on the Android device");
+            return 1;
+        }
+
+        log::info!("Triaging on the Android device {} with {}",
+            device.serial.as_deref().unwrap_or("(the only one)"), device.gdbserver);
+    }
+
+    if let Some(urls) = args.value_of("debuginfod_urls") {
+        // GDB inherits the environment, even when the target's is cleared
+        env::set_var("DEBUGINFOD_URLS", urls);
+    }
+
+    if gdb_options.debuginfod && env::var("DEBUGINFOD_URLS").map(|u| u.trim().is_empty()).unwrap_or(true) {
+        log::error!("--debuginfod needs the servers to use in DEBUGINFOD_URLS or --debuginfod-urls");
+        return 1;
+    }
+
+    let gdb_path = args.value_of("gdb_path").unwrap_or(&aenv.gdb_path);
+
+    let mut gdb: GdbTriager = GdbTriager::with_options(gdb_path.to_string(), gdb_options);
+
+    let dry_run = args.is_present("dry_run");
+
+    if !environment_check(&gdb, &binary_args, !dry_run) {
+        return 1;
+    }
+
+    if gdb.options.android.is_some() {
+        // The variables AFLTriage set in its own environment do not reach the device
+        let managed_env: Vec<(String, String)> = ["ASAN_OPTIONS", "GOTRACEBACK"].iter()
+            .filter(|name| !gdb.options.target_env.iter().any(|(key, _)| key == *name))
+            .filter_map(|name| env::var(name).ok().map(|value| (name.to_string(), value)))
+            .collect();
+
+        gdb.options.target_env.splice(0..0, managed_env);
+    }
+
+    if let (Some(device), false) = (&gdb.options.android, dry_run) {
+        if let Err(e) = device.prepare() {
+            log::error!("Failed to prepare the Android device: {}", e);
+            return 1;
+        }
+    }
+
+    if gdb.options.debuginfod && gdb.options.valgrind.is_none() && gdb.options.cdb.is_none() && gdb.options.lldb.is_none() && !dry_run {
+        if !gdb.has_debuginfod() {
+            return 1;
+        }
+
+        log::info!("Downloading debug info from {}", env::var("DEBUGINFOD_URLS").unwrap_or_default());
+
+        match gdb.prefetch_debug_info(binary_args[0]) {
+            Ok(true) => (),
+            Ok(false) => log::warn!("Could not download all debug info before triage. Triage will try again for each testcase"),
+            Err(e) => log::warn!("Failed to download debug info: {}", e),
+        }
+    }
+
+    if gdb.options.reuse_gdb {
+        if gdb.supports_sessions() {
+            log::info!("Reusing a GDB session per thread");
+        } else {
+            log::warn!("Starting GDB for each testcase as --reuse-gdb is not supported with the other options given");
+        }
+    }
+
+    let kernel_settings = kernel::read_kernel_settings();
+    log::info!("Kernel settings: {}", kernel_settings.summary());
+
+    for warning in kernel_settings.run_warnings(unsafe { libc::geteuid() } == 0, gdb.options.aslr) {
+        log::warn!("{}", warning);
+    }
+
+    if gdb.options.clear_env {
+        log::info!("Starting the target with an empty environment");
+
+        // The cleared environment still needs the variables AFLTriage depends on
+        let managed_env: Vec<(String, String)> = MANAGED_ENV_VARS.iter()
+            .filter(|name| !gdb.options.target_env.iter().any(|(key, _)| key == *name))
+            .filter_map(|name| env::var(name).ok().map(|value| (name.to_string(), value)))
+            .collect();
+
+        gdb.options.target_env.splice(0..0, managed_env);
+    }
+
+    for (key, value) in &gdb.options.target_env {
+        log::info!("Target environment: {}={}", key, value);
+    }
+
+    let input_mode = match args.value_of("input_mode").map(InputMode::parse) {
+        None if args.is_present("stdin") => InputMode::Stdin,
+        None => InputMode::File,
+        Some(Ok(mode)) => mode,
+        Some(Err(e)) => {
+            log::error!("{}", e);
+            return 1;
+        }
+    };
+    let has_atat = binary_args.iter().any(|s| *s == "@@");
+
+    if gdb.options.remote.is_some() && input_mode != InputMode::File {
+        log::error!("Testcases are delivered to the remote target by --remote-input commands, not over stdin, a socket, or a FIFO");
+        return 1;
+    }
+
+    if let (Some(_), InputMode::Socket(socket)) = (&gdb.options.android, input_mode) {
+        log::error!("Testcases cannot be sent over {} to the Android device", socket);
+        return 1;
+    }
+
+    if gdb.options.android.is_some() && input_mode == InputMode::Fifo {
+        log::error!("Testcases cannot be written to a FIFO on the Android device");
+        return 1;
+    }
+
+    if gdb.options.cdb.is_some() && input_mode != InputMode::File {
+        log::error!("CDB reads its commands from stdin, so testcases must be given to the target with @@");
+        return 1;
+    }
+
+    match input_mode {
+        InputMode::File => {
+            if !has_atat {
+                log::error!("Image triage args missing file placeholder: @@. If you'd like to pass input to the child via stdin, use the --stdin option.");
+                return 1;
+            }
+        }
+        InputMode::Stdin => {
+            log::info!("Providing testcase input via stdin");
+
+            if has_atat {
+                log::warn!("Image triage args contains @@ but you are using --stdin");
+            }
+        }
+        InputMode::Socket(socket) => {
+            log::info!("Providing testcase input over {}", socket);
+
+            if gdb.options.sandbox.is_some() {
+                log::error!("The sandbox has no network access, so testcases cannot be sent over {}", socket);
+                return 1;
+            }
+        }
+        InputMode::Fifo => {
+            if !has_atat {
+                log::error!("Image triage args missing file placeholder: @@. It is replaced by the path of the FIFO to read testcases from.");
+                return 1;
+            }
+
+            log::info!("Providing testcase input through a FIFO");
+        }
+    }
+
+    log::info!("Image triage cmdline: {}", util::shell_join(&binary_args));
+
+    let output = args.value_of("output").unwrap();
+
+    // Output to the terminal
+    let output_dir = if output == "-" {
+        None
+    } else {
+        let d = std::path::PathBuf::from(output);
+
+        if dry_run {
+            // Nothing is written
+        } else if let Err(e) = std::fs::create_dir(&d) {
+            if e.kind() != std::io::ErrorKind::AlreadyExists {
+                log::error!("Error creating output directory: {}", e);
+                return 1;
+            }
+        }
+
+        Some(d)
+    };
+
+    let report_output_formats: Vec<ReportOutputFormat> = values_t!(args, "report_formats", ReportOutputFormat).unwrap_or_else(|e| e.exit());
+    let report_output_formats_s = report_output_formats.iter()
+        .map(|f| f.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let output_layout = value_t!(args, "output_layout", OutputLayout).unwrap_or_else(|e| e.exit());
+
+    if output_layout == OutputLayout::flat && args.is_present("copy_testcases") {
+        log::warn!("Ignoring --copy-testcases as only --output-layout buckets has bucket directories");
+    }
+
+    let bucket_pass = match bucket_pass_from_args(&args) {
+        Ok(pass) => pass,
+        Err(e) => {
+            log::error!("{}", e);
+            return 1;
+        }
+    };
+
+    let classify_pass = match classify_pass_from_args(&args) {
+        Ok(pass) => pass,
+        Err(e) => {
+            log::error!("{}", e);
+            return 1;
+        }
+    };
+
+    let disabled_passes: Vec<&str> = args.values_of("disable_passes").unwrap_or_default().collect();
+    let pipeline = AnalysisPipeline::new(bucket_pass, classify_pass, &disabled_passes);
+
+    if !disabled_passes.is_empty() {
+        log::info!("Analysis passes: {}", pipeline.pass_names().join(", "));
+    }
+
+    if output_dir.is_some() {
+        log::info!("Will write {} reports to directory \"{}\"", report_output_formats_s, output);
+    } else {
+        log::info!("Will output {} reports to terminal", report_output_formats_s);
+    }
+
+    let mut all_testcases = collect_input_testcases(&mut processed_inputs, &corpus_filter, args.is_present("hangs"),
+        args.is_present("queue"));
+
+    // Queue inputs did not crash the fuzzed binary, so only a sanitizer build is likely to find anything
+    if args.is_present("queue") {
+        let binary = which::which(binary_args[0]).unwrap_or_else(|_| PathBuf::from(binary_args[0]));
+
+        if let Ok(false) = symbolizer::is_asan_instrumented(&binary) {
+            log::warn!("{} is not instrumented with ASAN. --queue finds memory errors that did not crash the fuzzed binary \
+                with an ASAN build of the target", shlex::quote(binary_args[0]));
+        }
+    }
+
+    if !testcase_filters.is_empty() {
+        let found = all_testcases.len();
+        all_testcases.retain(|tc| testcase_filters.iter().all(|f| f.matches(&tc.path)));
+        log::info!("Selected {} of {} testcases with --filter", all_testcases.len(), found);
+    }
+
+    // The copies of a testcase, keyed by the original
+    let mut duplicates: HashMap<String, Vec<String>> = HashMap::new();
+
+    if !args.is_present("keep_duplicates") {
+        let (unique, removed) = remove_duplicate_testcases(all_testcases, |tc| tc.path.as_path());
+        all_testcases = unique;
+
+        for (original, copies) in removed {
+            duplicates.insert(all_testcases[original].path.to_string_lossy().to_string(),
+                copies.into_iter().map(|tc| tc.path.to_string_lossy().to_string()).collect());
+        }
+
+        let removed: usize = duplicates.values().map(Vec::len).sum();
+
+        if removed > 0 {
+            log::info!("Skipping {} testcase(s) with the same contents as another testcase", removed);
+        }
+    }
+
+    let order = value_t!(args, "order", TestcaseOrder).unwrap_or_else(|e| e.exit());
+    let sample = if args.is_present("sample") {
+        Some(value_t!(args, "sample", usize).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+
+    if sample.is_some() || order == TestcaseOrder::random {
+        let seed = if args.is_present("seed") {
+            value_t!(args, "seed", u64).unwrap_or_else(|e| e.exit())
+        } else {
+            SampleRng::time_seed()
+        };
+        let found = all_testcases.len();
+
+        all_testcases = select_testcases(all_testcases, |tc| tc.path.as_path(), order, sample, &mut SampleRng::new(seed));
+
+        if sample.is_some() {
+            log::info!("Sampled {} of {} testcases (--seed {})", all_testcases.len(), found, seed);
+        } else {
+            log::info!("Shuffled the testcases (--seed {})", seed);
+        }
+    } else if order != TestcaseOrder::found {
+        all_testcases = select_testcases(all_testcases, |tc| tc.path.as_path(), order, None, &mut SampleRng::new(0));
+    }
+
+    if all_testcases.is_empty() {
+        log::error!("No testcases found!");
+        return 1;
+    }
+
+    if dry_run {
+        print_dry_run(&gdb, gdb_path, &binary_args, input_mode, &all_testcases);
+        return 0;
+    }
+
+    let raw_output_dir = args.value_of("save_raw_output").map(PathBuf::from);
+
+    if let Some(dir) = &raw_output_dir {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            log::error!("Error creating the raw output directory: {}", e);
+            return 1;
+        }
+    }
+
+    // Kept until the end of the run when there is no output directory
+    let mut preprocess_tempdir = None;
+
+    let preprocessor = match args.value_of("preprocess") {
+        Some(command) => {
+            let preprocessed_dir = match &output_dir {
+                Some(output_dir) => {
+                    let dir = output_dir.join("preprocessed");
+
+                    if let Err(e) = std::fs::create_dir_all(&dir) {
+                        log::error!("Error creating the preprocessed directory: {}", e);
+                        return 1;
+                    }
+
+                    dir
+                }
+                None => match tempfile::tempdir() {
+                    Ok(dir) => preprocess_tempdir.insert(dir).path().to_path_buf(),
+                    Err(e) => {
+                        log::error!("Error creating a directory for preprocessed testcases: {}", e);
+                        return 1;
+                    }
+                },
+            };
+
+            match Preprocessor::new(command, &preprocessed_dir) {
+                Ok(preprocessor) => {
+                    log::info!("Pre-processing testcases with: {}", command);
+                    Some(preprocessor)
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    return 1;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let proto_decoder = match args.value_of("proto").map(ProtoDecoder::parse) {
+        Some(Ok(decoder)) => {
+            if which::which("protoc").is_err() {
+                log::error!("protoc is required to decode protobuf testcases, but it was not found in PATH");
+                return 1;
+            }
+
+            if !decoder.proto_file.is_file() {
+                log::error!("Protobuf file {} does not exist", shlex::quote(&decoder.proto_file.to_string_lossy()));
+                return 1;
+            }
+
+            Some(decoder)
+        }
+        Some(Err(e)) => {
+            log::error!("{}", e);
+            return 1;
+        }
+        None => None,
+    };
+
+    let debug = args.is_present("debug");
+    let child_output = args.is_present("child_output");
+
+    let mut retention = RetentionRules::new(RetentionPolicy {
+        report_formats: report_output_formats.to_vec(),
+        child_output,
+    });
+
+    for rule in args.values_of("retain").unwrap_or_default() {
+        if let Err(e) = retention.add_rule(rule) {
+            log::error!("{}", e);
+            return 1;
+        }
+    }
+
+    let webhook = match args.value_of("webhook") {
+        Some(url) => {
+            if which::which("curl").is_err() {
+                log::error!("--webhook requires curl in the PATH");
+                return 1;
+            }
+
+            Some(notify::Webhook {
+                url: url.to_string(),
+                min_severity: args.value_of("webhook_min_severity").unwrap().parse().unwrap(),
+            })
+        }
+        None => None,
+    };
+
+    let elasticsearch = match args.value_of("elasticsearch") {
+        Some(_) if which::which("curl").is_err() => {
+            log::error!("--elasticsearch requires curl in the PATH");
+            return 1;
+        }
+        Some(url) => Some(ElasticsearchSink::new(url)),
+        None => None,
+    };
+
+    let known_buckets_path = args.value_of("known_buckets").map(PathBuf::from);
+    let ignore_known = args.is_present("ignore_known");
+
+    let mut known_buckets = match &known_buckets_path {
+        Some(path) => match KnownBuckets::load(path) {
+            Ok(known) => {
+                log::info!("{} bucket(s) are known from previous runs", known.buckets.len());
+                known
+            }
+            Err(e) => {
+                log::error!("Failed to read the known buckets {}: {}", shlex::quote(&path.to_string_lossy()), e);
+                return 1;
+            }
+        },
+        None => KnownBuckets::default(),
+    };
+    let suppressed_policy = RetentionPolicy { report_formats: vec![], child_output: false };
+
+    let target_name = match args.value_of("target_name") {
+        Some(name) => name.to_string(),
+        None => Path::new(binary_args[0]).file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| binary_args[0].to_string()),
+    };
+
+    let text_template = match args.value_of("text_template") {
+        Some(path) => match TextTemplate::load(Path::new(path)) {
+            Ok(template) => Some(template),
+            Err(e) => {
+                log::error!("Failed to load the text report template {}: {}", shlex::quote(path), e);
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let redactor = if args.is_present("redact") {
+        let patterns: Vec<&str> = args.values_of("redact_pattern").map(|v| v.collect()).unwrap_or_default();
+
+        match Redactor::new(&MachineIdentity::current(), &patterns, args.is_present("redact_child_output")) {
+            Ok(redactor) => Some(redactor),
+            Err(e) => {
+                log::error!("{}", e);
+                return 1;
+            }
+        }
+    } else {
+        None
+    };
 
     let child_output_lines = if let Ok(n) = value_t!(args, "child_output_lines", usize) {
         n
@@ -769,6 +2955,18 @@ fn main_wrapper() -> i32 {
         return 1;
     };
 
+    let max_json_size = if args.is_present("max_json_size") {
+        match value_t!(args, "max_json_size", usize) {
+            Ok(n) => Some(n),
+            Err(_) => {
+                log::error!("Max JSON size parse error");
+                return 1;
+            }
+        }
+    } else {
+        None
+    };
+
     let timeout_ms = value_t!(args, "timeout", u64).unwrap_or_else(|_| 60000);
 
     if timeout_ms < 100 {
@@ -777,11 +2975,79 @@ fn main_wrapper() -> i32 {
         log::info!("Triage timeout set to {}ms", timeout_ms);
     }
 
-    let mut max_recommended_threadcount = num_cpus::get();
+    let mut max_recommended_threadcount = num_cpus::get();
+
+    // Firmware only runs on the remote target, and Android targets on the device
+    let runs_locally = gdb.options.remote.is_none() && gdb.options.android.is_none();
+
+    if !runs_locally && !args.is_present("skip_profile") {
+        log::info!("Skipping target profiling as the target does not run on this machine");
+    }
+
+    // Coverage is collected by running the target on this machine and symbolizing its .sancov files
+    let coverage_collector = match args.is_present("coverage") {
+        false => None,
+        true if !runs_locally || gdb.options.cdb.is_some() => {
+            log::warn!("Ignoring --coverage as the target does not run on this machine");
+            None
+        }
+        true => {
+            if gdb.options.sandbox.as_ref().map(|s| s.level == SandboxLevel::full).unwrap_or(false) {
+                log::error!("--coverage cannot write coverage files to the read-only filesystem of --sandbox full");
+                return 1;
+            }
+
+            let symbolizer = match gdb.options.external_symbolizer.clone().or_else(|| symbolizer::ExternalSymbolizer::find("auto")) {
+                Some(symbolizer) => symbolizer,
+                None => {
+                    log::error!("--coverage needs llvm-symbolizer or addr2line to name the covered functions");
+                    return 1;
+                }
+            };
+
+            // Coverage files are named after the file the binary was loaded from
+            let binary = match std::fs::canonicalize(which::which(binary_args[0]).unwrap_or_else(|_| PathBuf::from(binary_args[0]))) {
+                Ok(binary) => binary,
+                Err(e) => {
+                    log::error!("Failed to find the target binary for --coverage: {}", e);
+                    return 1;
+                }
+            };
+
+            log::info!("Collecting the coverage of crashes, symbolized with {}", symbolizer.path.display());
+            Some((binary, symbolizer))
+        }
+    };
+
+    let hooks = TestcaseHooks {
+        before: args.value_of("before_cmd").map(str::to_string),
+        after: args.value_of("after_cmd").map(str::to_string),
+    };
+
+    if !args.is_present("skip_profile") && runs_locally {
+        let first_testcase_path = all_testcases[0].path.to_str().unwrap().to_string();
+
+        if let Err(e) = hooks.before(&first_testcase_path) {
+            log::error!("{}: {}", shlex::quote(&first_testcase_path), e);
+            return 1;
+        }
+
+        let first_testcase_path = match &preprocessor {
+            Some(preprocessor) => match preprocessor.process(&first_testcase_path, 0) {
+                Ok(path) => path.to_string_lossy().to_string(),
+                Err(e) => {
+                    log::error!("Failed to pre-process {}: {}", shlex::quote(&first_testcase_path), e);
+                    return 1;
+                }
+            },
+            None => first_testcase_path,
+        };
+
+        let profile_result = profile_target(&gdb, &binary_args, &first_testcase_path, debug, input_mode, timeout_ms);
 
-    if !args.is_present("skip_profile") {
-        let first_testcase_path = all_testcases[0].path.to_str().unwrap();
-        let profile_result = profile_target(&gdb, &binary_args, first_testcase_path, debug, input_stdin, timeout_ms);
+        if let Err(e) = hooks.after(all_testcases[0].path.to_str().unwrap()) {
+            log::warn!("{}: {}", shlex::quote(all_testcases[0].path.to_str().unwrap()), e);
+        }
 
         if let Ok(profile_result) = profile_result {
             if let std::io::Result::Err(e) = profile_result.process_result {
@@ -798,6 +3064,11 @@ fn main_wrapper() -> i32 {
                 TriageResult::Timedout => {
                     log::warn!("The triage process timed out during profiling! The debugger needs to load and process symbols, which increases execution time. Consider raising the timeout")
                 },
+                TriageResult::EnvironmentError(e) => {
+                    log::error!("The target could not start during profiling: {}", e);
+                    log::error!("Install the missing dependency or triage on the system the target was built for - exiting...");
+                    return 1;
+                }
                 TriageResult::Error(err) => {
                     log::error!("The triage errored during profiling (enable --debug for more information): {}", err.to_string());
                     log::error!("It's unlikely that triage will succeed - exiting...");
@@ -841,7 +3112,26 @@ fn main_wrapper() -> i32 {
     };
 
     // No point in having more threads than testcases...
-    let job_count = std::cmp::max(1, std::cmp::min(requested_job_count, all_testcases.len()));
+    let mut job_count = std::cmp::max(1, std::cmp::min(requested_job_count, all_testcases.len()));
+
+    if let InputMode::Socket(socket) = input_mode {
+        if job_count > 1 {
+            log::warn!("Triaging one testcase at a time as every target listens on {}", socket);
+            job_count = 1;
+        }
+    }
+
+    if let Some(remote) = &gdb.options.remote {
+        if job_count > 1 {
+            log::warn!("Triaging one testcase at a time as there is one remote target at {}", remote.address);
+            job_count = 1;
+        }
+    }
+
+    if gdb.options.android.is_some() && job_count > 1 {
+        log::warn!("Triaging one testcase at a time as gdbserver listens on one port of the Android device");
+        job_count = 1;
+    }
 
     //////////////////
 
@@ -853,20 +3143,44 @@ fn main_wrapper() -> i32 {
         .build_global()
         .unwrap();
 
-    let pb = ProgressBar::new((&all_testcases).len() as u64);
+    let saved_results = match (&output_dir, args.is_present("save_results")) {
+        (Some(output_dir), true) => {
+            match File::create(output_dir.join(SAVED_RESULTS_FILE)) {
+                Ok(f) => Some(BufWriter::new(f)),
+                Err(e) => {
+                    log::error!("Failed to create the saved results file: {}", e);
+                    return 1;
+                }
+            }
+        }
+        (None, true) => {
+            log::warn!("Ignoring --save-results as there is no output directory");
+            None
+        }
+        _ => None,
+    };
 
     let display_progress = util::isatty() && output_dir.is_some() && !debug;
+    let progress = TriageProgress::new(all_testcases.len() as u64, display_progress, job_count);
 
-    if display_progress {
-        pb.set_style(ProgressStyle::default_bar()
-                     .template("[+] Triaging {spinner:.green} [{pos}/{len} {elapsed_precise}] [{bar:.cyan/blue}] {wide_msg}")
-                     .progress_chars("#>-"));
-        pb.enable_steady_tick(200);
-    }
+    let metrics = match args.value_of("metrics_listen") {
+        Some(address) => {
+            let metrics = Arc::new(TriageMetrics::new(all_testcases.len() as u64));
+
+            if let Err(e) = metrics::serve_metrics(address, Arc::clone(&metrics)) {
+                log::error!("Failed to serve metrics on {}: {}", address, e);
+                return 1;
+            }
+
+            log::info!("Serving metrics at http://{}/metrics", address);
+            Some(metrics)
+        }
+        None => None,
+    };
 
     let write_message: Box<dyn Fn(String, Option<&str>) + Sync> = if display_progress {
         Box::new(|msg, _tc| {
-            pb.set_message(&msg)
+            progress.set_message(&msg)
         })
     } else {
         Box::new(|msg, tc| {
@@ -880,42 +3194,316 @@ fn main_wrapper() -> i32 {
 
     write_message(format!("Processing initial {} test cases", job_count), None);
 
+    let local_time = args.is_present("local_time");
+
+    // No default value is given to clap, as that would make it require --minimize
+    let minimize_execs = if args.is_present("minimize_execs") {
+        value_t!(args, "minimize_execs", usize).unwrap_or_else(|e| e.exit())
+    } else if args.is_present("minimize") {
+        DEFAULT_MINIMIZE_EXECS
+    } else {
+        0
+    };
+
+    let reruns = if args.is_present("rerun") {
+        match value_t!(args, "rerun", usize) {
+            Ok(n) => n,
+            Err(_) => {
+                log::error!("Rerun count parse error");
+                return 1;
+            }
+        }
+    } else {
+        0
+    };
+
+    let run_started_at = SystemTime::now();
+    let run_start = Instant::now();
+
     let state = Arc::new(Mutex::new(TriageState {
         crashed: 0,
+        hangs: 0,
         no_crash: 0,
+        forked: 0,
         errored: 0,
+        environment_errors: HashMap::new(),
+        unsymbolized_sanitizer_reports: 0,
+        queue_sanitizer_findings: 0,
+        unsymbolized_modules: BTreeMap::new(),
         timedout: 0,
-        crash_signature: HashSet::new(),
+        buckets: HashMap::new(),
         unique_errors: HashMap::new(),
+        saved_results,
+        summaries: vec![],
+        breakdown: CrashBreakdown::default(),
+        findings: 0,
+        finding_buckets: HashSet::new(),
+        provenance: HashMap::new(),
+        leaks: 0,
+        leak_buckets: HashSet::new(),
+        no_crash_buckets: HashMap::new(),
+        preprocessed: HashMap::new(),
+        representatives: HashSet::new(),
+        waiting_for_representative: HashMap::new(),
     }));
 
-    let report_options = ReportOptions {
-        child_output_lines,
-        show_child_output: child_output,
-    };
+    let two_phase = args.is_present("two_phase");
+    let first_pass_detail = if two_phase { TriageDetail::Brief } else { TriageDetail::Full };
+
+    let csv_summary = report_output_formats.contains(&ReportOutputFormat::csv);
+    let sql_results = report_output_formats.contains(&ReportOutputFormat::sql);
 
-    all_testcases.par_iter().panic_fuse().for_each(|testcase| {
+    let mut report_options = ReportOptions::default();
+    report_options.child_output_lines = child_output_lines;
+    report_options.max_json_size = max_json_size;
+    // Trimmed per crash according to its retention policy
+    report_options.show_child_output = retention.any_child_output();
+    report_options.source_path_map = source_path_map;
+
+    all_testcases.par_iter().enumerate().panic_fuse().for_each(|(testcase_id, testcase)| {
         if stop_requested.load(Ordering::Relaxed) {
-            if display_progress {
-                pb.inc(1);
-            }
+            progress.inc();
             return
         }
 
         let path = testcase.path.to_str().unwrap();
-        let result = triage_test_case(&gdb, &binary_args, path, debug, input_stdin, timeout_ms);
+        progress.start(path);
+        let triaged_at = SystemTime::now();
+        let triage_start = Instant::now();
+
+        let preprocessed = match &preprocessor {
+            Some(preprocessor) => preprocessor.process(path, testcase_id)
+                .map(|p| Some(p.to_string_lossy().to_string())),
+            None => Ok(None),
+        };
+
+        // The input given to the target
+        let input_path = match &preprocessed {
+            Ok(Some(preprocessed)) => preprocessed.as_str(),
+            _ => path,
+        };
+
+        let raw_output = RawOutput {
+            show: debug,
+            save_as: raw_output_dir.as_ref().map(|dir| RawOutput::save_path(dir, path)),
+        };
+
+        let prepared = preprocessed.as_ref().map_err(|e| e.to_string()).and_then(|_| hooks.before(path));
+
+        let result = match prepared {
+            Err(e) => TriageResult::Error(GdbTriageError::new_brief(GdbTriageErrorKind::Command, e)),
+            Ok(_) => triage_test_case(&gdb, &binary_args, input_path, &raw_output, input_mode, timeout_ms, first_pass_detail),
+        };
+
+        // Analysis can be slow (e.g. external bucketing commands) so do it before taking the lock
+        let analysis = match &result {
+            TriageResult::Crash(triage) => Some(pipeline.run(path, triage, &report_options).unwrap()),
+            _ => None,
+        };
+
+        // With --two-phase, the first crash of each bucket is triaged again in full for its reports
+        let representative = match &analysis {
+            Some(analysis) if two_phase => analysis.bucket.strategy_result.is_empty() ||
+                state.lock().unwrap().representatives.insert(analysis.bucket.strategy_result.to_string()),
+            _ => false,
+        };
+
+        let (result, analysis) = if representative {
+            let brief_bucket = &analysis.as_ref().unwrap().bucket.strategy_result;
+
+            match triage_test_case(&gdb, &binary_args, input_path, &raw_output, input_mode, timeout_ms, TriageDetail::Full) {
+                TriageResult::Crash(triage) => {
+                    let full = pipeline.run(path, &triage, &report_options).unwrap();
+
+                    // Keep the brief result if the crash changed, as testcases are already in its bucket
+                    if full.bucket.strategy_result == *brief_bucket {
+                        (TriageResult::Crash(triage), Some(full))
+                    } else {
+                        log::warn!("{} crashed differently when triaged again in full. Reporting the first triage",
+                            shlex::quote(path));
+                        (result, analysis)
+                    }
+                }
+                _ => {
+                    log::warn!("{} did not crash when triaged again in full. Reporting the first triage", shlex::quote(path));
+                    (result, analysis)
+                }
+            }
+        } else {
+            (result, analysis)
+        };
+        let triage_duration = triage_start.elapsed();
+
+        let decoded_testcase = match (&analysis, &proto_decoder) {
+            (Some(_), Some(decoder)) => decoder.decode(input_path).map_err(|e| {
+                log::warn!("{}: {}", shlex::quote(path), e);
+            }).ok(),
+            _ => None,
+        };
+
+        let reproducibility = match &analysis {
+            Some(analysis) if reruns > 0 => Some(Reproducibility {
+                reruns,
+                reproduced: (0..reruns)
+                    .filter(|_| crashes_in_bucket(&gdb, &binary_args, input_path, &pipeline, &report_options,
+                        input_mode, timeout_ms, &analysis.bucket.strategy_result))
+                    .count(),
+            }),
+            _ => None,
+        };
+
+        let finding = match &result {
+            TriageResult::NoCrash(triage) if !oracles.is_empty() => oracle::check_oracles(&oracles, path, triage),
+            _ => None,
+        };
+
+        // Hashing the testcase can be slow for large inputs
+        let provenance = analysis.as_ref().map(|_| report::provenance::read_provenance(path, local_time));
+
+        let leak = match &result {
+            TriageResult::NoCrash(_) if detect_leaks && finding.is_none() =>
+                check_leaks(&gdb, &binary_args, input_path, input_mode, timeout_ms),
+            _ => None,
+        };
+
+        let coverage = match (&analysis, &coverage_collector) {
+            (Some(_), Some((binary, symbolizer))) =>
+                check_coverage(&gdb, &binary_args, input_path, input_mode, timeout_ms, binary, symbolizer),
+            _ => None,
+        };
+
+        if let Err(e) = hooks.after(path) {
+            log::warn!("{}: {}", shlex::quote(path), e);
+        }
 
         // Do not reorder. Avoid long computations with this lock held
+        let outcome = match &result {
+            TriageResult::Crash(_) => "crash",
+            TriageResult::NoCrash(_) => "no_crash",
+            TriageResult::Timedout => "timeout",
+            TriageResult::Error(_) => "error",
+            TriageResult::EnvironmentError(_) => "environment_error",
+        };
+
         let mut state = state.lock().unwrap();
+        let mut notification = None;
+        let mut document = None;
 
         // TODO: display child-output even without a crash to help debug triage errors
 
         match result {
-            TriageResult::NoCrash(_child) => {
+            TriageResult::NoCrash(triage) => {
                 state.no_crash += 1;
 
-                if !display_progress {
-                    write_message("No crash".into(), Some(path));
+                let forks = triage.response.forks.unwrap_or(0);
+
+                if forks > 0 {
+                    state.forked += 1;
+                }
+
+                if let Some(finding) = finding {
+                    state.findings += 1;
+
+                    if !state.finding_buckets.insert(finding.bucket.to_string()) {
+                        if !display_progress {
+                            write_message(finding.summary(), Some(path));
+                        }
+                        return;
+                    }
+
+                    write_message(finding.summary(), Some(path));
+
+                    let mut rendered_reports = vec![];
+
+                    if report_output_formats.contains(&ReportOutputFormat::text) {
+                        let child_output = if report_options.show_child_output {
+                            Some((triage.child.stdout.as_str(), triage.child.stderr.as_str()))
+                        } else {
+                            None
+                        };
+
+                        rendered_reports.push(("TEXT", "txt", oracle::format_text_report(
+                            &finding, &binary_args_owned, path, child_output)));
+                    }
+                    if report_output_formats.contains(&ReportOutputFormat::json) {
+                        let report = serde_json::json!({
+                            "command_line": binary_args_owned,
+                            "testcase": path,
+                            "debugger": gdb.gdb_path,
+                            "triaged_at": util::format_iso8601(triaged_at, local_time),
+                            "finding": finding,
+                        });
+
+                        rendered_reports.push(("JSON", "json", serde_json::to_string_pretty(&report).unwrap()));
+                    }
+
+                    write_finding_reports(&output_dir, &format!("oracle_{}", finding.bucket), rendered_reports,
+                        path, &write_message);
+                } else if let Some(leak) = leak {
+                    state.leaks += 1;
+
+                    if !state.leak_buckets.insert(leak.bucket.to_string()) {
+                        if !display_progress {
+                            write_message(leak.summary(), Some(path));
+                        }
+                        return;
+                    }
+
+                    write_message(leak.summary(), Some(path));
+
+                    let mut rendered_reports = vec![];
+
+                    if report_output_formats.contains(&ReportOutputFormat::text) {
+                        rendered_reports.push(("TEXT", "txt", leak::format_text_report(&leak, &binary_args_owned, path)));
+                    }
+                    if report_output_formats.contains(&ReportOutputFormat::json) {
+                        let report = serde_json::json!({
+                            "command_line": binary_args_owned,
+                            "testcase": path,
+                            "triaged_at": util::format_iso8601(triaged_at, local_time),
+                            "leak": leak,
+                        });
+
+                        rendered_reports.push(("JSON", "json", serde_json::to_string_pretty(&report).unwrap()));
+                    }
+
+                    write_finding_reports(&output_dir, &leak.bucket, rendered_reports, path, &write_message);
+                } else {
+                    let no_crash = nocrash::classify_no_crash(&triage.response);
+                    let count = state.no_crash_buckets.entry(no_crash.bucket.to_string()).or_insert(0);
+                    *count += 1;
+
+                    if report_no_crash && *count == 1 {
+                        write_message(no_crash.summary(), Some(path));
+
+                        let mut rendered_reports = vec![];
+
+                        if report_output_formats.contains(&ReportOutputFormat::text) {
+                            let child_output = if report_options.show_child_output {
+                                Some((triage.child.stdout.as_str(), triage.child.stderr.as_str()))
+                            } else {
+                                None
+                            };
+
+                            rendered_reports.push(("TEXT", "txt", nocrash::format_text_report(
+                                &no_crash, &binary_args_owned, path, child_output)));
+                        }
+                        if report_output_formats.contains(&ReportOutputFormat::json) {
+                            let report = serde_json::json!({
+                                "command_line": binary_args_owned,
+                                "testcase": path,
+                                "debugger": gdb.gdb_path,
+                                "triaged_at": util::format_iso8601(triaged_at, local_time),
+                                "no_crash": no_crash,
+                            });
+
+                            rendered_reports.push(("JSON", "json", serde_json::to_string_pretty(&report).unwrap()));
+                        }
+
+                        write_finding_reports(&output_dir, &no_crash.bucket, rendered_reports, path, &write_message);
+                    } else if !display_progress {
+                        write_message(no_crash.summary(), Some(path));
+                    }
                 }
             }
             TriageResult::Timedout => {
@@ -930,68 +3518,127 @@ fn main_wrapper() -> i32 {
                 let crash_id = state.crashed;
                 state.crashed += 1;
 
-                let etriage = report::enriched::enrich_triage_info(&report_options, &triage).unwrap();
-                let bucket_strategy = value_t!(args, "bucket_strategy", CrashBucketStrategy).unwrap();
-                let bucket_info = bucket::bucket_crash(bucket_strategy, &etriage);
+                state.unsymbolized_modules.extend(symbolizer::unsymbolized_modules(&triage));
+
+                let CrashAnalysis {
+                    enriched: etriage,
+                    bucket: bucket_info,
+                    severity,
+                } = analysis.unwrap();
+
+                if etriage.hang == Some(true) {
+                    state.hangs += 1;
+                }
 
                 // Bucket info can be empty if bucketing failed or strategy is "none"
-                let bucket = if bucket_info.strategy_result.is_empty() {
-                    format!("CID_{}", crash_id)
-                } else {
-                    bucket_info.strategy_result.to_string()
+                let bucket = bucket::bucket_id(&bucket_info, crash_id);
+
+                let envelope = ReportEnvelope {
+                    schema_version: report::schema::SCHEMA_VERSION,
+                    command_line: binary_args.iter().map(|x| x.to_string()).collect(),
+                    testcase: path.to_string(),
+                    preprocessed_testcase: preprocessed.unwrap_or_default(),
+                    decoded_testcase,
+                    debugger: match (&gdb.options.valgrind, &gdb.options.cdb, &gdb.options.lldb) {
+                        (Some(valgrind), _, _) => valgrind.to_string_lossy().to_string(),
+                        (_, Some(cdb), _) => cdb.to_string_lossy().to_string(),
+                        (_, _, Some(lldb)) => lldb.to_string_lossy().to_string(),
+                        _ => gdb.gdb_path.to_string(),
+                    },
+                    env: gdb.options.target_env.iter().map(|(k, v)| format!("{}={}", k, v)).collect(),
+                    bucket: bucket_info,
+                    report_options: report_options.clone(),
+                    triaged_at: Some(util::format_iso8601(triaged_at, local_time)),
+                    triage_duration_ms: Some(triage_duration.as_millis() as u64),
+                    reproducibility,
+                    aslr: Some(gdb.options.aslr),
+                    warnings: kernel_settings.crash_warnings(&etriage, gdb.options.aslr),
+                    kernel_settings: Some(kernel_settings.clone()),
+                    privileges: privileges.clone(),
+                    afl_testcase: Path::new(path).file_name()
+                        .and_then(|name| report::provenance::AflTestcaseName::parse(&name.to_string_lossy())),
+                    coverage,
                 };
 
-                if !state.crash_signature.contains(&bucket) {
-                    state.crash_signature.insert(bucket.to_string());
+                let mut reports = vec![];
 
-                    write_message(format!("{}", etriage.summary), Some(path));
+                if etriage.sanitizer_reports.iter().flatten().any(|r| !r.is_symbolized()) {
+                    state.unsymbolized_sanitizer_reports += 1;
+                }
+
+                if afl::is_queue_input(Path::new(path)) && etriage.sanitizer_reports.iter().flatten().next().is_some() {
+                    state.queue_sanitizer_findings += 1;
+                }
 
-                    let envelope = ReportEnvelope {
-                        command_line: binary_args.iter().map(|x| x.to_string()).collect(),
-                        testcase: path.to_string(),
-                        debugger: gdb.gdb_path.to_string(),
-                        bucket: bucket_info,
-                        report_options: report_options.clone(),
+                if csv_summary || sql_results {
+                    state.summaries.push(TestcaseSummary::new(&envelope, &bucket, severity, &etriage));
+                }
+
+                state.breakdown.add_crash(&bucket, &etriage, severity);
+
+                if elasticsearch.is_some() {
+                    document = Some(TriageDocument::new(&target_name, &bucket, severity, &etriage, &envelope, provenance.as_ref()));
+                }
+
+                if let Some(provenance) = provenance {
+                    let provenance = TestcaseProvenance {
+                        bucket: bucket.to_string(),
+                        triaged_at: envelope.triaged_at.clone(),
+                        triage_duration_ms: envelope.triage_duration_ms,
+                        reproducibility,
+                        ..provenance
                     };
 
-                    let mut rendered_reports = vec![];
+                    state.provenance.insert(path.to_string(), provenance);
+                }
 
-                    if report_output_formats.contains(&ReportOutputFormat::text) {
-                        let text_report = report::text::format_text_report(&etriage, &envelope);
-                        rendered_reports.push(RenderedReport {
-                            data: text_report,
-                            format: ReportOutputFormat::text,
-                            extension: "txt"
-                        });
+                if let Some(entry) = state.buckets.get_mut(&bucket) {
+                    entry.testcases.push(path.to_string());
+
+                    if !display_progress {
+                        write_message(format!("{}", etriage.summary), Some(path));
                     }
-                    if report_output_formats.contains(&ReportOutputFormat::json) {
-                        let report_val = serde_json::to_value(&etriage).unwrap();
-                        let mut wrapper_val = serde_json::to_value(&envelope).unwrap();
-                        wrapper_val.as_object_mut().unwrap().insert("report".into(), report_val);
-                        let rendered = serde_json::to_string_pretty(&wrapper_val).unwrap();
-
-                        rendered_reports.push(RenderedReport {
-                            data: rendered,
-                            format: ReportOutputFormat::json,
-                            extension: "json",
-                        });
+                } else if two_phase && !representative {
+                    // The bucket's representative is still being triaged in full
+                    state.waiting_for_representative.entry(bucket.to_string()).or_default().push(path.to_string());
+
+                    if !display_progress {
+                        write_message(format!("{}", etriage.summary), Some(path));
                     }
-                    if report_output_formats.contains(&ReportOutputFormat::rawjson) {
-                        let report_val = serde_json::to_value(&triage).unwrap();
-                        let mut wrapper_val = serde_json::to_value(&envelope).unwrap();
-                        wrapper_val.as_object_mut().unwrap().insert("report".into(), report_val);
-                        let rendered = serde_json::to_string_pretty(&wrapper_val).unwrap();
-
-                        rendered_reports.push(RenderedReport {
-                            data: rendered,
-                            format: ReportOutputFormat::rawjson,
-                            extension: "rawjson",
-                        });
+                } else {
+                    let mut testcases = vec![path.to_string()];
+                    testcases.extend(state.waiting_for_representative.remove(&bucket).unwrap_or_default());
+
+                    let mut entry = BucketEntry {
+                        bucket: bucket.to_string(),
+                        summary: etriage.summary.to_string(),
+                        severity,
+                        testcases,
+                        reports: vec![],
+                        reproducibility,
+                    };
+
+                    write_message(format!("{}", etriage.summary), Some(path));
+
+                    // Known buckets are only listed in the index, like with --retain SEVERITY=none
+                    let known = ignore_known && known_buckets.contains(&bucket);
+
+                    if known {
+                        write_message(format!("Bucket {} is already known, skipping its reports", bucket), Some(path));
                     }
 
-                    let filename = format!("afltriage_{}_{}",
-                            util::sanitize(&etriage.terse_summary),
-                            util::sanitize(&bucket));
+                    if let Some(preprocessed) = &envelope.preprocessed_testcase {
+                        state.preprocessed.insert(path.to_string(), preprocessed.to_string());
+                    }
+
+                    let filename = report::report_filename_stem(&etriage, &bucket);
+                    let policy = if known { &suppressed_policy } else { retention.policy(entry.severity) };
+                    let rendered_reports = match redactor.as_ref().map(|r| r.redact_crash(&etriage, &triage, &envelope)) {
+                        Some((etriage, triage, envelope)) => report::render_reports(
+                            policy, entry.severity, etriage, &triage, &envelope, text_template.as_ref()),
+                        None => report::render_reports(
+                            policy, entry.severity, etriage, &triage, &envelope, text_template.as_ref()),
+                    };
 
                     for report in rendered_reports {
                         let report_name = report.format.to_string().to_uppercase();
@@ -1003,23 +3650,58 @@ fn main_wrapper() -> i32 {
                             ), None);
                         } else {
                             let output_dir = output_dir.as_ref().unwrap();
-                            let report_filename = format!("{}.{}", filename, report.extension);
+                            let report_filename = output_layout.report_path(&filename, report.extension);
+                            let report_path = output_dir.join(&report_filename);
 
-                            if let Err(e) =
-                                std::fs::write(output_dir.join(report_filename), report.data)
+                            if let Err(e) = std::fs::create_dir_all(report_path.parent().unwrap())
+                                .and_then(|_| std::fs::write(&report_path, report.data))
                             {
                                 // TODO: notify / exit early
                                 let failed_to_write = format!("Failed to write report: {}", e);
                                 write_message(failed_to_write, Some(path));
+                            } else {
+                                entry.reports.push(report_filename);
                             }
                         }
                     }
-                } else {
-                    if !display_progress {
-                        write_message(format!("{}", etriage.summary), Some(path));
+
+                    reports = entry.reports.to_vec();
+
+                    if !known && webhook.as_ref().map(|w| w.wants(severity)).unwrap_or(false) {
+                        notification = Some(notify::NewBucketNotification::new(&entry, path));
+                    }
+
+                    state.buckets.insert(bucket.to_string(), entry);
+                }
+
+                if let Some(saved_results) = state.saved_results.as_mut() {
+                    let mut saved = SavedResult {
+                        bucket,
+                        reports,
+                        envelope,
+                        triage: *triage,
+                    };
+
+                    if let Err(e) = report::write_saved_result(saved_results, &saved) {
+                        write_message(format!("Failed to save result: {}", e), Some(path));
+                    }
+
+                    // The copies that were not triaged share the result, without its reports
+                    saved.reports.clear();
+
+                    for copy in duplicates.get(path).into_iter().flatten() {
+                        saved.envelope.testcase = copy.to_string();
+
+                        if let Err(e) = report::write_saved_result(saved_results, &saved) {
+                            write_message(format!("Failed to save result: {}", e), Some(copy));
+                        }
                     }
                 }
             }
+            TriageResult::EnvironmentError(e) => {
+                write_message(e.to_string(), Some(path));
+                *state.environment_errors.entry(e).or_insert(0) += 1;
+            }
             TriageResult::Error(gdb_error) => {
                 state.errored += 1;
 
@@ -1033,31 +3715,178 @@ fn main_wrapper() -> i32 {
             }
         };
 
-        if display_progress {
-            pb.inc(1);
+        let unique_buckets = state.buckets.len();
+        drop(state);
+
+        if let Some(metrics) = &metrics {
+            metrics.observe(outcome, triage_duration, unique_buckets);
+        }
+
+        if let (Some(webhook), Some(notification)) = (&webhook, notification) {
+            if let Err(e) = webhook.send(&notification) {
+                log::warn!("Failed to notify the webhook of bucket {}: {}", notification.bucket, e);
+            }
+        }
+
+        if let (Some(elasticsearch), Some(document)) = (&elasticsearch, document) {
+            if let Some(batch) = elasticsearch.add(document) {
+                if let Err(e) = elasticsearch.send(&batch) {
+                    log::warn!("Failed to index {} documents in Elasticsearch: {}", batch.len(), e);
+                }
+            }
         }
+
+        progress.inc();
     });
 
-    if display_progress {
-        pb.finish();
-    } else {
-        pb.finish_and_clear();
+    progress.finish();
+
+    if let Some(elasticsearch) = &elasticsearch {
+        let batch = elasticsearch.take_pending();
+
+        if let Err(e) = elasticsearch.send(&batch) {
+            log::warn!("Failed to index {} documents in Elasticsearch: {}", batch.len(), e);
+        }
     }
 
-    let state = state.lock().unwrap();
+    let mut state = state.lock().unwrap();
     let total = all_testcases.len();
 
+    if !duplicates.is_empty() {
+        let recorded = record_duplicates(&mut state, &duplicates, local_time);
+        log::info!("Added {} duplicate testcase(s) to the buckets of the testcases they duplicate", recorded);
+    }
+
     log::info!(
-        "Triage stats [Crashes: {} (unique {}), No crash: {}, Timeout: {}, Errored: {}]",
+        "Triage stats [Crashes: {} (unique {}), No crash: {}, Timeout: {}, Errored: {}, Environment errors: {}]",
         state.crashed,
-        state.crash_signature.len(),
+        state.buckets.len(),
         state.no_crash,
         state.timedout,
-        state.errored
+        state.errored,
+        state.environment_errors.values().sum::<usize>()
     );
 
+    if state.hangs > 0 {
+        log::info!("Hangs: {} of the crashes (unique {})", state.hangs,
+            state.buckets.values().filter(|e| e.summary.starts_with("HANG ")).count());
+    }
+
+    log::info!("Triage started at {} and finished at {} (took {:.1?})",
+        util::format_iso8601(run_started_at, local_time),
+        util::format_iso8601(SystemTime::now(), local_time),
+        run_start.elapsed());
+
+    if !oracles.is_empty() {
+        log::info!("Oracle findings: {} (unique {})", state.findings, state.finding_buckets.len());
+    }
+
+    if detect_leaks {
+        log::info!("Leaks: {} of the runs without a crash (unique {})", state.leaks, state.leak_buckets.len());
+    }
+
+    if !state.no_crash_buckets.is_empty() {
+        let mut buckets: Vec<(&String, &usize)> = state.no_crash_buckets.iter().collect();
+        buckets.sort();
+        log::info!("Runs without a crash: {}", buckets.iter()
+            .map(|(bucket, count)| format!("{} ({})", bucket, count))
+            .collect::<Vec<String>>().join(", "));
+    }
+
+    if reruns > 0 {
+        let flaky = state.buckets.values()
+            .filter(|e| e.reproducibility.map(|r| r.is_flaky()).unwrap_or(false))
+            .count();
+
+        if flaky > 0 {
+            log::warn!("{} of {} unique bucket(s) did not reproduce in every rerun and are flaky", flaky, state.buckets.len());
+        } else {
+            log::info!("Every unique bucket reproduced in all {} rerun(s)", reruns);
+        }
+    }
+
+    if state.forked > 0 {
+        log::warn!("{} testcase(s) did not crash, but the target forked before exiting. If the target daemonizes, \
+                   triage with --follow-forks to follow its child processes", state.forked);
+    }
+
+    if minimize_execs > 0 && !state.buckets.is_empty() {
+        match &output_dir {
+            Some(output_dir) => {
+                log::info!("Minimizing the testcases of {} bucket(s)", state.buckets.len());
+                let state = &mut *state;
+                minimize_buckets(&mut state.buckets, &state.preprocessed, &gdb, &binary_args, &pipeline, &report_options,
+                    input_mode, timeout_ms, minimize_execs, output_dir);
+            }
+            None => log::warn!("Skipping testcase minimization as there is no output directory"),
+        }
+    }
+
     let mut retval = 0;
 
+    if !state.buckets.is_empty() {
+        report::index::write_bucket_index(&state.buckets, &binary_args_owned, output_dir.as_deref());
+
+        if let Some(output_dir) = &output_dir {
+            report::provenance::write_bucket_metadata(&state.buckets, &state.provenance, output_dir);
+
+            if output_layout == OutputLayout::buckets {
+                for entry in state.buckets.values() {
+                    if let Err(e) = report::layout::write_bucket_directory(output_dir, entry, &state.provenance,
+                        args.is_present("copy_testcases")) {
+                        log::error!("Failed to write the directory of bucket {}: {}", entry.bucket, e);
+                    }
+                }
+            }
+        }
+
+        if csv_summary {
+            report::csv::write_csv_summaries(&state.buckets, &state.summaries, output_dir.as_deref());
+        }
+
+        if sql_results {
+            report::sql::write_results_sql(&state.buckets, &state.summaries, output_dir.as_deref());
+        }
+    }
+
+    let failures = state.unique_errors.iter().map(|(e, times)| (e.to_string(), *times))
+        .chain(state.environment_errors.iter().map(|(e, times)| (e.to_string(), *times)))
+        .collect();
+
+    let campaign_summary = CampaignSummary {
+        testcases: total,
+        crashes: state.crashed,
+        unique_buckets: state.buckets.len(),
+        hangs: state.hangs,
+        no_crash: state.no_crash,
+        timeouts: state.timedout,
+        errors: state.errored,
+        environment_errors: state.environment_errors.values().sum(),
+        ..CampaignSummary::new(&binary_args_owned, &state.breakdown, failures)
+    };
+
+    report::campaign::write_campaign_summary(&campaign_summary, &state.buckets, output_dir.as_deref(),
+        report_output_formats.contains(&ReportOutputFormat::html));
+
+    if let Some(path) = &known_buckets_path {
+        let now = util::format_iso8601(run_started_at, local_time);
+        let new_buckets = state.buckets.values()
+            .filter(|e| known_buckets.record(&e.bucket, &e.summary, e.severity, e.testcases.len(), &now))
+            .count();
+
+        log::info!("{} of {} unique bucket(s) are new", new_buckets, state.buckets.len());
+
+        if let Err(e) = known_buckets.save(path) {
+            log::error!("Failed to write the known buckets {}: {}", shlex::quote(&path.to_string_lossy()), e);
+        }
+    }
+
+    if let Some(saved_results) = state.saved_results.as_mut() {
+        if let Err(e) = saved_results.flush() {
+            log::error!("Failed to save results: {}", e);
+        }
+    }
+
     if state.errored == total {
         log::error!("Something seems to be wrong during triage as all testcases errored.");
         retval = 1; // this is a particually bad case. let parent processes know
@@ -1078,6 +3907,52 @@ fn main_wrapper() -> i32 {
         // even with errors, still return 0 as *some* testcases may have succeeded
     }
 
+    let environment_errored: usize = state.environment_errors.values().sum();
+
+    if environment_errored > 0 {
+        log::error!("The target could not start for {} testcase(s) because of its environment", environment_errored);
+
+        for (e, times) in &state.environment_errors {
+            log::error!("{} (seen {} time(s))", e, times);
+        }
+
+        if environment_errored == total {
+            retval = 1;
+        }
+    }
+
+    if state.unsymbolized_sanitizer_reports > 0 {
+        log::warn!("{} crash(es) had sanitizer reports without symbols, so their buckets and summaries may be unreliable. \
+            Set ASAN_SYMBOLIZER_PATH to llvm-symbolizer or addr2line and triage them again", state.unsymbolized_sanitizer_reports);
+    }
+
+    // Only GDB searches the debug file directories
+    let uses_gdb = gdb.options.valgrind.is_none() && gdb.options.cdb.is_none() && gdb.options.lldb.is_none();
+
+    if uses_gdb && !state.unsymbolized_modules.is_empty() {
+        log::warn!("Crashes had frames without function names in {} module(s), so their buckets and summaries may be unreliable. \
+            If these are stripped, give the directories of their separate debug files with --debug-file-dir:",
+            state.unsymbolized_modules.len());
+
+        let debug_dirs: Vec<&Path> = gdb.options.debug_file_dirs.iter().map(PathBuf::as_path)
+            .chain(std::iter::once(Path::new(DEFAULT_DEBUG_FILE_DIRECTORY)))
+            .collect();
+
+        for (module, build_id) in state.unsymbolized_modules.iter().take(10) {
+            match build_id {
+                Some(build_id) => log::warn!("  {} (build ID {}, not found at {})", module, build_id,
+                    debug_dirs.iter().map(|dir| symbolizer::build_id_debug_path(dir, build_id).display().to_string())
+                        .collect::<Vec<String>>().join(" or ")),
+                None => log::warn!("  {}", module),
+            }
+        }
+    }
+
+    if state.queue_sanitizer_findings > 0 {
+        log::info!("{} AFL queue input(s) triggered sanitizer findings without crashing the fuzzed binary",
+            state.queue_sanitizer_findings);
+    }
+
     if state.no_crash == total {
         log::warn!("None of the testcases crashed! Make sure that you are using the correct target command line and the right set of testcases");
     }
@@ -0,0 +1,178 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Prometheus metrics for long triage runs.
+//!
+//! With `--metrics-listen`, AFLTriage serves its progress in the Prometheus text format at
+//! `/metrics` while it triages, so the health of a long campaign can be monitored and alerted on
+//! like any other service:
+//!
+//! * `afltriage_testcases` - how many testcases the run will triage
+//! * `afltriage_testcases_triaged_total` - testcases triaged, by `result` (`crash`, `no_crash`,
+//!   `timeout`, `error`, or `environment_error`)
+//! * `afltriage_unique_buckets` - unique crash buckets found so far
+//! * `afltriage_triage_duration_seconds` - a histogram of how long each testcase took to triage
+//!
+//! The endpoint is a minimal HTTP server on its own thread. It only answers `GET` requests and is
+//! meant to be scraped from a trusted network.
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The upper bounds of the triage duration histogram buckets, in seconds
+const DURATION_BUCKETS: [f64; 10] = [0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+#[derive(Debug, Default)]
+struct MetricValues {
+    testcases: u64,
+    triaged: BTreeMap<&'static str, u64>,
+    unique_buckets: u64,
+    /// Observations at or below each of [DURATION_BUCKETS]
+    duration_buckets: [u64; DURATION_BUCKETS.len()],
+    duration_count: u64,
+    duration_sum: f64,
+}
+
+/// The metrics of a triage run, shared by its worker threads
+#[derive(Debug, Default)]
+pub struct TriageMetrics {
+    values: Mutex<MetricValues>,
+}
+
+impl TriageMetrics {
+    pub fn new(testcases: u64) -> Self {
+        TriageMetrics {
+            values: Mutex::new(MetricValues { testcases, ..Default::default() }),
+        }
+    }
+
+    /// Count a triaged testcase with its `result`, how long it took, and the unique buckets found
+    /// so far
+    pub fn observe(&self, result: &'static str, duration: Duration, unique_buckets: usize) {
+        let mut values = self.values.lock().unwrap();
+        let secs = duration.as_secs_f64();
+
+        *values.triaged.entry(result).or_insert(0) += 1;
+        values.unique_buckets = unique_buckets as u64;
+        values.duration_count += 1;
+        values.duration_sum += secs;
+
+        for (i, bound) in DURATION_BUCKETS.iter().enumerate() {
+            if secs <= *bound {
+                values.duration_buckets[i] += 1;
+            }
+        }
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let values = self.values.lock().unwrap();
+        let mut out = String::new();
+
+        out += "# HELP afltriage_testcases Testcases to triage in this run.\n";
+        out += "# TYPE afltriage_testcases gauge\n";
+        out += &format!("afltriage_testcases {}\n", values.testcases);
+
+        out += "# HELP afltriage_testcases_triaged_total Testcases triaged, by result.\n";
+        out += "# TYPE afltriage_testcases_triaged_total counter\n";
+        for result in ["crash", "no_crash", "timeout", "error", "environment_error"] {
+            out += &format!("afltriage_testcases_triaged_total{{result=\"{}\"}} {}\n",
+                result, values.triaged.get(result).unwrap_or(&0));
+        }
+
+        out += "# HELP afltriage_unique_buckets Unique crash buckets found.\n";
+        out += "# TYPE afltriage_unique_buckets gauge\n";
+        out += &format!("afltriage_unique_buckets {}\n", values.unique_buckets);
+
+        out += "# HELP afltriage_triage_duration_seconds How long triaging a testcase took.\n";
+        out += "# TYPE afltriage_triage_duration_seconds histogram\n";
+        for (bound, count) in DURATION_BUCKETS.iter().zip(values.duration_buckets.iter()) {
+            out += &format!("afltriage_triage_duration_seconds_bucket{{le=\"{}\"}} {}\n", bound, count);
+        }
+        out += &format!("afltriage_triage_duration_seconds_bucket{{le=\"+Inf\"}} {}\n", values.duration_count);
+        out += &format!("afltriage_triage_duration_seconds_sum {}\n", values.duration_sum);
+        out += &format!("afltriage_triage_duration_seconds_count {}\n", values.duration_count);
+
+        out
+    }
+}
+
+fn handle_request(stream: TcpStream, metrics: &TriageMetrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+
+    let (status, body) = if !request_line.starts_with("GET ") {
+        ("405 Method Not Allowed", String::new())
+    } else if path == "/metrics" || path.starts_with("/metrics?") {
+        ("200 OK", metrics.render())
+    } else {
+        ("404 Not Found", String::new())
+    };
+
+    let mut stream = &stream;
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body)?;
+    stream.flush()
+}
+
+/// Serve `metrics` at `/metrics` on `address` (e.g. `127.0.0.1:9464`), from a background thread
+pub fn serve_metrics(address: &str, metrics: Arc<TriageMetrics>) -> std::io::Result<()> {
+    serve_metrics_on(TcpListener::bind(address)?, metrics);
+
+    Ok(())
+}
+
+/// Serve `metrics` at `/metrics` on a bound `listener`, from a background thread
+pub fn serve_metrics_on(listener: TcpListener, metrics: Arc<TriageMetrics>) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = handle_request(stream, &metrics) {
+                log::debug!("Failed to serve metrics: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_triage_metrics() {
+        let metrics = Arc::new(TriageMetrics::new(10));
+        metrics.observe("crash", Duration::from_millis(700), 1);
+        metrics.observe("crash", Duration::from_secs(20), 1);
+        metrics.observe("timeout", Duration::from_secs(400), 1);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("afltriage_testcases 10\n"));
+        assert!(rendered.contains("afltriage_testcases_triaged_total{result=\"crash\"} 2\n"));
+        assert!(rendered.contains("afltriage_testcases_triaged_total{result=\"error\"} 0\n"));
+        assert!(rendered.contains("afltriage_unique_buckets 1\n"));
+        assert!(rendered.contains("afltriage_triage_duration_seconds_bucket{le=\"0.5\"} 0\n"));
+        assert!(rendered.contains("afltriage_triage_duration_seconds_bucket{le=\"1\"} 1\n"));
+        assert!(rendered.contains("afltriage_triage_duration_seconds_bucket{le=\"300\"} 2\n"));
+        assert!(rendered.contains("afltriage_triage_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(rendered.contains("afltriage_triage_duration_seconds_count 3\n"));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        serve_metrics_on(listener, Arc::clone(&metrics));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&rendered));
+    }
+}
@@ -0,0 +1,508 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Triage of Breakpad minidumps.
+//!
+//! Crash collection systems often keep a minidump (`.dmp`) of each crash instead of the input that
+//! caused it. A minidump is triaged without running the target: the crashing thread's stack is
+//! walked from the registers and stack memory in the dump and turned into the same
+//! [GdbTriageResult] GDB produces, so minidumps are bucketed and reported like any other crash.
+//!
+//! Minidumps carry no unwind information, so the stack is walked with frame pointers and, if that
+//! finds no callers, by scanning the stack for addresses inside a loaded module. Scanned frames can
+//! include stale return addresses. Frames are named by the
+//! [ExternalSymbolizer](crate::platform::symbolizer::ExternalSymbolizer), when the modules in the
+//! dump exist at the same paths on this system.
+use crate::debugger::gdb::*;
+use crate::platform::linux::signal_to_string;
+use std::convert::TryInto;
+use std::path::Path;
+
+/// "MDMP"
+const MINIDUMP_SIGNATURE: u32 = 0x504d_444d;
+
+const THREAD_LIST_STREAM: u32 = 3;
+const MODULE_LIST_STREAM: u32 = 4;
+const EXCEPTION_STREAM: u32 = 6;
+const SYSTEM_INFO_STREAM: u32 = 7;
+
+const THREAD_SIZE: usize = 48;
+const MODULE_SIZE: usize = 108;
+
+/// The most frames walked, the same as GDBTriage
+const FRAME_LIMIT: usize = 100;
+/// The most frames found by scanning the stack
+const SCAN_FRAME_LIMIT: usize = 32;
+/// How many stack words are scanned
+const SCAN_WORD_LIMIT: usize = 2048;
+
+/// The CPU of a minidump, from the `ProcessorArchitecture` of its system info
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cpu {
+    X86,
+    Amd64,
+    Arm,
+    Arm64,
+}
+
+/// A register of the crashing thread's context: its name and offset
+type ContextRegister = (&'static str, usize);
+
+impl Cpu {
+    fn from_processor_architecture(arch: u16) -> Option<Cpu> {
+        match arch {
+            0 => Some(Cpu::X86),
+            5 => Some(Cpu::Arm),
+            9 => Some(Cpu::Amd64),
+            // 0x8003 is the value older versions of Breakpad used
+            12 | 0x8003 => Some(Cpu::Arm64),
+            _ => None,
+        }
+    }
+
+    fn pointer_size(&self) -> usize {
+        match self {
+            Cpu::X86 | Cpu::Arm => 4,
+            Cpu::Amd64 | Cpu::Arm64 => 8,
+        }
+    }
+
+    /// GDB's name for the architecture
+    fn gdb_architecture(&self) -> &'static str {
+        match self {
+            Cpu::X86 => "i386",
+            Cpu::Amd64 => "i386:x86-64",
+            Cpu::Arm => "arm",
+            Cpu::Arm64 => "aarch64",
+        }
+    }
+
+    /// The program counter, stack pointer, and frame pointer in the thread context
+    fn context_registers(&self) -> [ContextRegister; 3] {
+        match self {
+            Cpu::X86 => [("eip", 0xb8), ("esp", 0xc4), ("ebp", 0xb4)],
+            Cpu::Amd64 => [("rip", 0xf8), ("rsp", 0x98), ("rbp", 0xa0)],
+            Cpu::Arm => [("pc", 0x40), ("sp", 0x38), ("r11", 0x30)],
+            Cpu::Arm64 => [("pc", 0x108), ("sp", 0x100), ("x29", 0xf0)],
+        }
+    }
+
+    /// Whether the frame pointer chain is `[saved frame pointer, return address]`
+    fn has_frame_records(&self) -> bool {
+        *self != Cpu::Arm
+    }
+}
+
+/// Little-endian reads from a minidump that fail instead of panicking on a truncated dump
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: usize, size: usize) -> Result<&'a [u8], String> {
+        offset.checked_add(size)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| format!("truncated minidump (reading {} bytes at 0x{:x})", size, offset))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(offset, 2)?.try_into().unwrap()))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(offset, 8)?.try_into().unwrap()))
+    }
+
+    fn pointer(&self, offset: usize, cpu: Cpu) -> Result<u64, String> {
+        match cpu.pointer_size() {
+            4 => self.u32(offset).map(u64::from),
+            _ => self.u64(offset),
+        }
+    }
+
+    /// A `MINIDUMP_STRING`: a byte length followed by UTF-16
+    fn string(&self, offset: usize) -> Result<String, String> {
+        let length = self.u32(offset)? as usize;
+        let units: Vec<u16> = self.bytes(offset + 4, length)?
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+
+        Ok(String::from_utf16_lossy(&units))
+    }
+}
+
+#[derive(Debug)]
+struct Module {
+    base: u64,
+    size: u64,
+    name: String,
+}
+
+/// Memory captured in the dump
+struct Memory<'a> {
+    start: u64,
+    data: &'a [u8],
+}
+
+impl Memory<'_> {
+    fn pointer(&self, address: u64, cpu: Cpu) -> Option<u64> {
+        let offset = address.checked_sub(self.start)? as usize;
+        Reader { data: self.data }.pointer(offset, cpu).ok()
+    }
+}
+
+struct Thread<'a> {
+    id: u32,
+    stack: Memory<'a>,
+    context: &'a [u8],
+}
+
+fn find_module(modules: &[Module], address: u64) -> Option<&Module> {
+    modules.iter().find(|m| address >= m.base && address - m.base < m.size)
+}
+
+/// Walk a stack from its program counter, stack pointer, and frame pointer
+fn walk_stack(cpu: Cpu, pc: u64, sp: u64, fp: u64, stack: &Memory, modules: &[Module]) -> Vec<u64> {
+    let mut frames = vec![pc];
+    let ptr = cpu.pointer_size() as u64;
+
+    if cpu.has_frame_records() {
+        let mut fp = fp;
+
+        while frames.len() < FRAME_LIMIT {
+            let (next_fp, ret) = match (stack.pointer(fp, cpu), stack.pointer(fp.wrapping_add(ptr), cpu)) {
+                (Some(next_fp), Some(ret)) => (next_fp, ret),
+                _ => break,
+            };
+
+            if find_module(modules, ret).is_none() {
+                break;
+            }
+
+            frames.push(ret);
+
+            // Frames are pushed to lower addresses, so callers have higher frame pointers
+            if next_fp <= fp {
+                break;
+            }
+
+            fp = next_fp;
+        }
+    }
+
+    if frames.len() == 1 {
+        let mut address = sp;
+
+        for _ in 0..SCAN_WORD_LIMIT {
+            if frames.len() > SCAN_FRAME_LIMIT {
+                break;
+            }
+
+            match stack.pointer(address, cpu) {
+                Some(word) if find_module(modules, word).is_some() => frames.push(word),
+                Some(_) => (),
+                None => break,
+            }
+
+            address += ptr;
+        }
+    }
+
+    frames
+}
+
+fn frame_info(address: u64, modules: &[Module]) -> GdbFrameInfo {
+    match find_module(modules, address) {
+        Some(module) => GdbFrameInfo {
+            address,
+            relative_address: address - module.base,
+            module: module.name.to_string(),
+            module_address: format!("{}+0x{:x}", module.name, address - module.base),
+            symbol: None,
+        },
+        None => GdbFrameInfo {
+            address,
+            relative_address: address,
+            module: "??".into(),
+            module_address: format!("0x{:x}", address),
+            symbol: None,
+        },
+    }
+}
+
+/// Whether `path` is a minidump: a `.dmp` file starting with the minidump signature
+pub fn is_minidump(path: &Path) -> bool {
+    if path.extension().map(|e| e != "dmp").unwrap_or(true) {
+        return false;
+    }
+
+    let mut signature = [0u8; 4];
+
+    std::fs::File::open(path)
+        .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut signature))
+        .map(|_| u32::from_le_bytes(signature) == MINIDUMP_SIGNATURE)
+        .unwrap_or(false)
+}
+
+/// Triage a minidump, producing the result GDB would for the crash it captured
+pub fn triage_minidump(data: &[u8]) -> Result<GdbTriageResult, String> {
+    let r = Reader { data };
+
+    if r.u32(0)? != MINIDUMP_SIGNATURE {
+        return Err("not a minidump".into());
+    }
+
+    let stream_count = r.u32(8)? as usize;
+    let directory = r.u32(12)? as usize;
+
+    let mut streams = std::collections::HashMap::new();
+
+    for i in 0..stream_count {
+        let entry = directory + i * 12;
+        streams.insert(r.u32(entry)?, (r.u32(entry + 8)? as usize, r.u32(entry + 4)? as usize));
+    }
+
+    let stream = |kind: u32, name: &str| streams.get(&kind).copied()
+        .ok_or_else(|| format!("the minidump has no {} stream", name));
+
+    let (system_info, _) = stream(SYSTEM_INFO_STREAM, "system info")?;
+    let processor_architecture = r.u16(system_info)?;
+    let cpu = Cpu::from_processor_architecture(processor_architecture)
+        .ok_or_else(|| format!("unsupported processor architecture {}", processor_architecture))?;
+
+    let mut modules = vec![];
+
+    if let Ok((module_list, _)) = stream(MODULE_LIST_STREAM, "module list") {
+        for i in 0..r.u32(module_list)? as usize {
+            let module = module_list + 4 + i * MODULE_SIZE;
+
+            modules.push(Module {
+                base: r.u64(module)?,
+                size: r.u32(module + 8)? as u64,
+                name: r.string(r.u32(module + 20)? as usize)?,
+            });
+        }
+    }
+
+    let (exception, _) = stream(EXCEPTION_STREAM, "exception")?;
+    let thread_id = r.u32(exception)?;
+    // Breakpad stores the signal, its code, and the faulting address in the exception record
+    let signal_number = r.u32(exception + 8)? as i32;
+    let signal_code = r.u32(exception + 12)? as i32;
+    let faulting_address = r.u64(exception + 24)?;
+    let context_size = r.u32(exception + 160)? as usize;
+    let context_rva = r.u32(exception + 164)? as usize;
+
+    let (thread_list, _) = stream(THREAD_LIST_STREAM, "thread list")?;
+    let mut crashed = None;
+
+    for i in 0..r.u32(thread_list)? as usize {
+        let thread = thread_list + 4 + i * THREAD_SIZE;
+
+        if r.u32(thread)? != thread_id {
+            continue;
+        }
+
+        crashed = Some(Thread {
+            id: thread_id,
+            stack: Memory {
+                start: r.u64(thread + 24)?,
+                data: r.bytes(r.u32(thread + 36)? as usize, r.u32(thread + 32)? as usize)?,
+            },
+            // The exception's context is from the crash, the thread's from the dump handler
+            context: r.bytes(context_rva, context_size)?,
+        });
+    }
+
+    let thread = crashed.ok_or_else(|| format!("the crashing thread {} is not in the minidump", thread_id))?;
+
+    let context = Reader { data: thread.context };
+    let [pc, sp, fp] = cpu.context_registers();
+    let register_values = [pc, sp, fp].iter()
+        .map(|(_, offset)| context.pointer(*offset, cpu))
+        .collect::<Result<Vec<u64>, String>>()?;
+
+    let backtrace = walk_stack(cpu, register_values[0], register_values[1], register_values[2], &thread.stack, &modules)
+        .into_iter()
+        .map(|address| frame_info(address, &modules))
+        .collect();
+
+    let registers = [pc, sp, fp].iter()
+        .zip(&register_values)
+        .map(|((name, _), value)| std::rc::Rc::new(GdbRegister {
+            name: name.to_string(),
+            value: *value,
+            pretty_value: format!("0x{:x}", value),
+            r#type: "int".into(),
+            size: cpu.pointer_size() as u64,
+        }))
+        .collect();
+
+    let memory_map = modules.iter()
+        .map(|m| GdbMemoryMapping {
+            start: m.base,
+            end: m.base + m.size,
+            offset: 0,
+            permissions: None,
+            name: m.name.to_string(),
+        })
+        .collect();
+
+    let signal_name = signal_to_string(signal_number).to_string();
+    let has_faulting_address = ["SIGSEGV", "SIGILL", "SIGBUS", "SIGFPE", "SIGTRAP"].contains(&signal_name.as_str());
+
+    Ok(GdbTriageResult {
+        response: GdbJsonResult {
+            result: GdbResultCode::SUCCESS,
+            context: Some(GdbContextInfo {
+                stop_info: GdbStopInfo {
+                    signal_name,
+                    signal_number,
+                    signal_code,
+                    faulting_address: Some(faulting_address).filter(|_| has_faulting_address),
+                },
+                arch_info: GdbArchInfo {
+                    address_bits: cpu.pointer_size() * 8,
+                    architecture: cpu.gdb_architecture().into(),
+                },
+                primary_thread: GdbThread {
+                    tid: thread.id as i32,
+                    name: None,
+                    backtrace,
+                    current_instruction: None,
+                    registers: Some(registers),
+                    unwind_error: None,
+                },
+                other_threads: None,
+                memory_map: Some(memory_map),
+                modules: Some(modules.iter()
+                    .map(|m| GdbModule {
+                        path: m.name.to_string(),
+                        load_address: Some(m.base),
+                        end_address: Some(m.base + m.size),
+                        build_id: None,
+                    })
+                    .collect()),
+                extra: None,
+                forked_child: None,
+                hang: None,
+                exception: None,
+                fault_handler: None,
+            }),
+            forks: None,
+            exit_code: None,
+            oracle_backtrace: None,
+            warnings: vec![],
+            exit_signal: None,
+        },
+        child: GdbChildOutput::default(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Write an x86_64 minidump of a SIGSEGV with a stack of `main` called by `__libc_start_main`
+    fn write_minidump() -> Vec<u8> {
+        let mut d = vec![0u8; 0x800];
+        let put32 = |d: &mut Vec<u8>, off: usize, v: u32| d[off..off + 4].copy_from_slice(&v.to_le_bytes());
+        let put64 = |d: &mut Vec<u8>, off: usize, v: u64| d[off..off + 8].copy_from_slice(&v.to_le_bytes());
+
+        // Header and stream directory
+        put32(&mut d, 0, MINIDUMP_SIGNATURE);
+        put32(&mut d, 8, 4);
+        put32(&mut d, 12, 0x20);
+        for (i, (kind, rva)) in [(SYSTEM_INFO_STREAM, 0x80), (MODULE_LIST_STREAM, 0x100),
+                (EXCEPTION_STREAM, 0x200), (THREAD_LIST_STREAM, 0x300)].iter().enumerate() {
+            put32(&mut d, 0x20 + i * 12, *kind);
+            put32(&mut d, 0x20 + i * 12 + 8, *rva);
+        }
+
+        // AMD64
+        d[0x80] = 9;
+
+        // Modules
+        put32(&mut d, 0x100, 2);
+        for (i, (base, name_rva)) in [(0x5555_0000u64, 0x400), (0x7fff_0000u64, 0x440)].iter().enumerate() {
+            let module = 0x104 + i * MODULE_SIZE;
+            put64(&mut d, module, *base);
+            put32(&mut d, module + 8, 0x10000);
+            put32(&mut d, module + 20, *name_rva);
+        }
+        for (rva, name) in [(0x400, "/tmp/target"), (0x440, "/lib/libc.so.6")].iter() {
+            let utf16: Vec<u8> = name.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+            put32(&mut d, *rva, utf16.len() as u32);
+            d[rva + 4..rva + 4 + utf16.len()].copy_from_slice(&utf16);
+        }
+
+        // Exception: thread 42, SIGSEGV at 0x10, context at 0x500
+        put32(&mut d, 0x200, 42);
+        put32(&mut d, 0x208, 11);
+        put32(&mut d, 0x20c, 1);
+        put64(&mut d, 0x218, 0x10);
+        put32(&mut d, 0x200 + 160, 0x100);
+        put32(&mut d, 0x200 + 164, 0x500);
+        put64(&mut d, 0x500 + 0xf8, 0x5555_1234);
+        put64(&mut d, 0x500 + 0x98, 0x7ffd_0000);
+        put64(&mut d, 0x500 + 0xa0, 0x7ffd_0010);
+
+        // Thread 42 with 0x100 bytes of stack at 0x7ffd0000, stored at 0x700
+        put32(&mut d, 0x300, 1);
+        put32(&mut d, 0x304, 42);
+        put64(&mut d, 0x304 + 24, 0x7ffd_0000);
+        put32(&mut d, 0x304 + 32, 0x100);
+        put32(&mut d, 0x304 + 36, 0x700);
+        // The frame record of main: saved rbp and the return address into libc
+        put64(&mut d, 0x710, 0x7ffd_0040);
+        put64(&mut d, 0x718, 0x7fff_5678);
+        // The frame record of __libc_start_main ends the chain
+        put64(&mut d, 0x740, 0);
+        put64(&mut d, 0x748, 0x5555_0100);
+
+        d
+    }
+
+    #[test]
+    fn test_triage_minidump() {
+        let dump = write_minidump();
+        let triage = triage_minidump(&dump).unwrap();
+
+        // Reports are made as for crashes triaged by GDB
+        let report_options = crate::report::ReportOptions { child_output_lines: 0, ..Default::default() };
+        let einfo = crate::report::enriched::enrich_triage_info(&report_options, &triage).unwrap();
+        assert!(einfo.summary.contains("SIGSEGV"));
+
+        // The module list is kept for symbolizing offline
+        let modules = einfo.modules.as_ref().unwrap();
+        assert_eq!(modules.len(), 2);
+        assert_eq!((modules[1].path.as_str(), modules[1].in_backtrace), ("/lib/libc.so.6", true));
+        assert_eq!(modules[1].load_address.as_ref().unwrap().f, "0x000000007fff0000");
+        assert_eq!(modules[1].end_address.as_ref().unwrap().r, 0x7fff_0000 + 0x10000);
+
+        let ctx = triage.response.context.unwrap();
+
+        assert_eq!(ctx.stop_info.signal_name, "SIGSEGV");
+        assert_eq!(ctx.stop_info.faulting_address, Some(0x10));
+        assert_eq!(ctx.arch_info.architecture, "i386:x86-64");
+        assert_eq!(ctx.primary_thread.tid, 42);
+
+        let frames: Vec<&str> = ctx.primary_thread.backtrace.iter().map(|f| f.module_address.as_str()).collect();
+        assert_eq!(frames, vec!["/tmp/target+0x1234", "/lib/libc.so.6+0x5678", "/tmp/target+0x100"]);
+
+        // Without frame pointers, the stack is scanned
+        let mut no_fp = dump.clone();
+        no_fp[0x500 + 0xa0..0x500 + 0xa8].copy_from_slice(&0u64.to_le_bytes());
+        let ctx = triage_minidump(&no_fp).unwrap().response.context.unwrap();
+        assert_eq!(ctx.primary_thread.backtrace.len(), 3);
+        assert_eq!(ctx.primary_thread.backtrace[1].address, 0x7fff_5678);
+
+        assert!(triage_minidump(&dump[..0x300]).is_err());
+        assert!(triage_minidump(b"MDMX").is_err());
+    }
+}
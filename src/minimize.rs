@@ -0,0 +1,86 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Testcase minimization.
+//!
+//! A simple trim loop in the style of `afl-tmin`'s block deletion. Blocks of the input are removed
+//! one at a time and kept removed if the crash still reproduces. The block size starts at 1/16th of
+//! the input and is halved after every pass until single bytes are removed. What "reproduces"
+//! means is up to the caller. AFLTriage re-triages the candidate and checks that it lands in the
+//! same crash bucket.
+
+/// The initial block size is the input length divided by this (rounded up to a power of two)
+const TRIM_START_STEPS: usize = 16;
+
+/// The outcome of [minimize]
+#[derive(Debug, PartialEq)]
+pub struct Minimized {
+    pub data: Vec<u8>,
+    /// How many times the target was executed
+    pub execs: usize,
+}
+
+/// Minimize `input` by removing blocks while `reproduces` returns true for the smaller input.
+/// Stops after `max_execs` calls to `reproduces`
+pub fn minimize<F>(input: &[u8], max_execs: usize, mut reproduces: F) -> Minimized
+where
+    F: FnMut(&[u8]) -> bool,
+{
+    let mut data = input.to_vec();
+    let mut execs = 0;
+    let mut block = std::cmp::max(data.len() / TRIM_START_STEPS, 1).next_power_of_two();
+
+    'passes: while !data.is_empty() {
+        let mut pos = 0;
+        let mut changed = false;
+
+        while pos < data.len() {
+            if execs >= max_execs {
+                break 'passes;
+            }
+
+            let end = std::cmp::min(pos + block, data.len());
+            let candidate = [&data[..pos], &data[end..]].concat();
+
+            execs += 1;
+
+            if reproduces(&candidate) {
+                data = candidate;
+                changed = true;
+            } else {
+                pos += block;
+            }
+        }
+
+        if block > 1 {
+            block /= 2;
+        } else if !changed {
+            break;
+        }
+    }
+
+    Minimized { data, execs }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_minimize() {
+        // "Crashes" whenever the input has the letters of "BUG" in order
+        let input = b"aaaaaaaaaaaaaaaaaaaaaaaaBaaaaaaaaaaaaUaaaaaaaaaaaaaaGaaaaaaaaa";
+        let crashes = |d: &[u8]| String::from_utf8_lossy(d).replace('a', "").contains("BUG");
+
+        let min = minimize(input, 10000, crashes);
+        assert_eq!(min.data, b"BUG");
+
+        let min = minimize(input, 5, crashes);
+        assert_eq!(min.execs, 5);
+        assert!(crashes(&min.data));
+        assert!(min.data.len() < input.len());
+
+        assert_eq!(minimize(b"", 10, |_| true).data, b"");
+        assert_eq!(minimize(b"x", 10, |d| !d.is_empty()).data, b"x");
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Classifying runs that did not crash.
+//!
+//! A testcase that does not crash still ends some way: the target exits with a code, or is
+//! terminated by a signal GDB does not stop on, like a SIGKILL from the OOM killer. Runs are
+//! bucketed by how they ended, e.g. `NO_CRASH_exit_0`, `NO_CRASH_exit_1`, or
+//! `NO_CRASH_signal_SIGKILL`. They are only reported with `--report-no-crash`, for instance to find
+//! the testcases of a crashing corpus that no longer reproduce.
+use crate::debugger::gdb::GdbJsonResult;
+use crate::util::shell_join;
+use serde::Serialize;
+
+/// How a target that did not crash ended
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetExit {
+    /// The target exited with this code
+    Code(i32),
+    /// The target was terminated by this signal
+    Signal(String),
+    /// The target ended, but how is not known, e.g. when it forked and the parent was not followed
+    Unknown,
+}
+
+/// A run that did not crash
+#[derive(Debug, PartialEq, Serialize)]
+pub struct NoCrash {
+    /// The bucket identifier, from how the target ended
+    pub bucket: String,
+    pub exit: TargetExit,
+    /// How many times the target forked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forks: Option<u64>,
+}
+
+impl NoCrash {
+    pub fn summary(&self) -> String {
+        let mut details = vec![];
+
+        match &self.exit {
+            TargetExit::Code(0) => details.push("exited normally".to_string()),
+            TargetExit::Code(code) => details.push(format!("exited with code {}", code)),
+            TargetExit::Signal(signal) => details.push(format!("terminated by {}", signal)),
+            TargetExit::Unknown => (),
+        }
+
+        if let Some(forks) = self.forks {
+            details.push(format!("the target forked {} time(s) and may have daemonized", forks));
+        }
+
+        if details.is_empty() {
+            "No crash".into()
+        } else {
+            format!("No crash ({})", details.join("; "))
+        }
+    }
+}
+
+/// Classify a run that did not crash from its triage `response`
+pub fn classify_no_crash(response: &GdbJsonResult) -> NoCrash {
+    let exit = match (response.exit_code, &response.exit_signal) {
+        (Some(code), _) => TargetExit::Code(code),
+        (None, Some(signal)) => TargetExit::Signal(signal.to_string()),
+        (None, None) => TargetExit::Unknown,
+    };
+
+    let bucket = match &exit {
+        TargetExit::Code(code) => format!("NO_CRASH_exit_{}", code),
+        TargetExit::Signal(signal) => format!("NO_CRASH_signal_{}", signal),
+        TargetExit::Unknown => "NO_CRASH_unknown".into(),
+    };
+
+    NoCrash {
+        bucket,
+        exit,
+        forks: response.forks.filter(|forks| *forks > 0),
+    }
+}
+
+/// Render a text report of a run that did not crash
+pub fn format_text_report(no_crash: &NoCrash, command_line: &[String], testcase: &str, child_output: Option<(&str, &str)>) -> String {
+    let mut report = format!("Summary: {}\n", no_crash.summary());
+    report += &format!("Command line: {}\n", shell_join(command_line));
+    report += &format!("Testcase: {}\n", shlex::quote(testcase));
+    report += &format!("Bucket: {}\n", no_crash.bucket);
+
+    if let Some((stdout, stderr)) = child_output {
+        report += &format!("\nChild STDOUT:\n{}\nChild STDERR:\n{}\n", stdout.trim_end(), stderr.trim_end());
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_classify_no_crash() {
+        let response = |json| serde_json::from_value::<GdbJsonResult>(json).unwrap();
+
+        let exited = classify_no_crash(&response(serde_json::json!({"result": "ERROR_TARGET_NOT_RUNNING", "exit_code": 0})));
+        assert_eq!(exited.bucket, "NO_CRASH_exit_0");
+        assert_eq!(exited.summary(), "No crash (exited normally)");
+
+        let failed = classify_no_crash(&response(serde_json::json!({"result": "ERROR_TARGET_NOT_RUNNING", "exit_code": 2})));
+        assert_eq!((failed.bucket.as_str(), &failed.exit), ("NO_CRASH_exit_2", &TargetExit::Code(2)));
+        assert_eq!(failed.summary(), "No crash (exited with code 2)");
+
+        let killed = classify_no_crash(&response(serde_json::json!({"result": "ERROR_TARGET_NOT_RUNNING", "exit_signal": "SIGKILL"})));
+        assert_eq!(killed.bucket, "NO_CRASH_signal_SIGKILL");
+        assert_eq!(killed.summary(), "No crash (terminated by SIGKILL)");
+
+        let forked = classify_no_crash(&response(serde_json::json!({"result": "ERROR_TARGET_NOT_RUNNING", "forks": 2})));
+        assert_eq!(forked.bucket, "NO_CRASH_unknown");
+        assert_eq!(forked.summary(), "No crash (the target forked 2 time(s) and may have daemonized)");
+
+        let report = format_text_report(&failed, &["./test".into(), "@@".into()], "id:0", None);
+        assert_eq!(report, "Summary: No crash (exited with code 2)\nCommand line: ./test @@\nTestcase: id:0\nBucket: NO_CRASH_exit_2\n");
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Webhook notifications for new crash buckets.
+//!
+//! With `--webhook`, a JSON payload is POSTed whenever triage finds a new unique bucket, so the
+//! owners of a fuzzing campaign hear about new bugs without watching the output directory. The
+//! payload has a `text` field, which is all a Slack incoming webhook needs, next to structured
+//! fields for other receivers. A minimum [CrashSeverity] keeps low-value crashes from paging
+//! anyone. Payloads are sent with `curl`, and a failure to send one is logged but does not stop
+//! triage.
+use crate::report::index::BucketEntry;
+use crate::report::severity::CrashSeverity;
+use serde::Serialize;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// How long to wait for a webhook to respond
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+
+/// Where and when to send notifications
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub url: String,
+    /// Only notify for buckets at least this severe
+    pub min_severity: CrashSeverity,
+}
+
+/// The payload POSTed for a new bucket
+#[derive(Debug, PartialEq, Serialize)]
+pub struct NewBucketNotification {
+    /// A one line message, for Slack and compatible receivers
+    pub text: String,
+    pub bucket: String,
+    pub severity: CrashSeverity,
+    pub summary: String,
+    pub testcase: String,
+    /// The reports written for the bucket
+    pub reports: Vec<String>,
+}
+
+impl NewBucketNotification {
+    /// A notification for the first crash of a bucket, from its `testcase`
+    pub fn new(entry: &BucketEntry, testcase: &str) -> Self {
+        NewBucketNotification {
+            text: format!("New {} severity crash: {} (bucket {}, testcase {})",
+                entry.severity.as_str(), entry.summary, entry.bucket, testcase),
+            bucket: entry.bucket.to_string(),
+            severity: entry.severity,
+            summary: entry.summary.to_string(),
+            testcase: testcase.to_string(),
+            reports: entry.reports.to_vec(),
+        }
+    }
+}
+
+impl Webhook {
+    /// Whether a bucket of this severity should be notified
+    pub fn wants(&self, severity: CrashSeverity) -> bool {
+        severity >= self.min_severity
+    }
+
+    /// POST a notification to the webhook
+    pub fn send(&self, notification: &NewBucketNotification) -> Result<(), String> {
+        let payload = serde_json::to_string(notification).unwrap();
+
+        let mut child = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--max-time", &WEBHOOK_TIMEOUT_SECS.to_string(),
+                    "--header", "Content-Type: application/json", "--data-binary", "@-", &self.url])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run curl: {}", e))?;
+
+        child.stdin.take().unwrap().write_all(payload.as_bytes())
+            .map_err(|e| format!("Failed to write the payload to curl: {}", e))?;
+
+        let output = child.wait_with_output().map_err(|e| format!("Failed to run curl: {}", e))?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_new_bucket_notification() {
+        let webhook = Webhook { url: "http://localhost/hook".into(), min_severity: CrashSeverity::medium };
+        assert!(webhook.wants(CrashSeverity::high));
+        assert!(webhook.wants(CrashSeverity::medium));
+        assert!(!webhook.wants(CrashSeverity::low));
+
+        let entry = BucketEntry {
+            bucket: "abc123".into(),
+            summary: "CRASH detected in bad_read due to a fault at or near 0x0 leading to SIGSEGV".into(),
+            severity: CrashSeverity::high,
+            testcases: vec!["id:000000".into()],
+            reports: vec!["afltriage_abc123.txt".into()],
+            reproducibility: None,
+        };
+        let payload = serde_json::to_value(NewBucketNotification::new(&entry, "id:000000")).unwrap();
+
+        assert_eq!(payload["text"], "New high severity crash: CRASH detected in bad_read due to a fault at or near 0x0 \
+            leading to SIGSEGV (bucket abc123, testcase id:000000)");
+        assert_eq!(payload["severity"], "high");
+        assert_eq!(payload["reports"][0], "afltriage_abc123.txt");
+    }
+}
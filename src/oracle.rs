@@ -0,0 +1,283 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Test oracles that turn runs without a crash into findings.
+//!
+//! Not every bug ends in a signal. An [Oracle] inspects a run that did not crash and decides if it
+//! is still a finding, for example when the output contains "SQL error" or a differential check
+//! fails. Oracles are given as `ID=KIND:ARGUMENT`:
+//!
+//! * `ID=regex:PATTERN` - The target's stdout or stderr matches a regular expression
+//! * `ID=exit:CODE,...` - The target exited with one of the listed exit codes
+//! * `ID=command:COMMAND` - A shell command receives a JSON object on STDIN with the `testcase`,
+//!   `stdout`, `stderr`, and `exit_code` of the run. It reports a finding by exiting with status 1
+//!   and may print a description of it
+//!
+//! Findings are bucketed by the oracle ID and the top frame of a backtrace captured, without
+//! stopping the target, at the first hit of the oracle breakpoint (`--oracle-breakpoint`). As the
+//! breakpoint is usually placed on an error reporting function, the top frame is the breakpoint's
+//! caller.
+use crate::debugger::gdb::{GdbOracleFrame, GdbTriageResult};
+use crate::process;
+use crate::util::shell_join;
+use regex::Regex;
+use serde::Serialize;
+
+/// How long an oracle command may run
+const ORACLE_COMMAND_TIMEOUT_MS: u64 = 30000;
+
+/// How an [Oracle] decides if a run is a finding
+#[derive(Debug)]
+pub enum OracleCheck {
+    OutputRegex(Regex),
+    ExitCodes(Vec<i32>),
+    Command(String),
+}
+
+/// A user-defined test oracle
+#[derive(Debug)]
+pub struct Oracle {
+    pub id: String,
+    pub check: OracleCheck,
+}
+
+impl Oracle {
+    /// Parse an `ID=KIND:ARGUMENT` oracle definition
+    pub fn parse(spec: &str) -> Result<Oracle, String> {
+        let invalid = || format!("Invalid oracle '{}'. Expected ID=regex:PATTERN, ID=exit:CODE,..., or ID=command:COMMAND", spec);
+
+        let (id, definition) = spec.split_once('=').ok_or_else(invalid)?;
+        let (kind, argument) = definition.split_once(':').ok_or_else(invalid)?;
+
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("Invalid oracle ID '{}'. Use letters, numbers, '_', and '-'", id));
+        }
+
+        let check = match kind {
+            "regex" => OracleCheck::OutputRegex(
+                Regex::new(argument).map_err(|e| format!("Invalid oracle regex: {}", e))?),
+            "exit" => OracleCheck::ExitCodes(argument.split(',')
+                .map(|c| c.trim().parse::<i32>().map_err(|_| format!("Invalid oracle exit code '{}'", c)))
+                .collect::<Result<Vec<i32>, String>>()?),
+            "command" => OracleCheck::Command(argument.to_string()),
+            _ => return Err(invalid()),
+        };
+
+        Ok(Oracle {
+            id: id.to_string(),
+            check,
+        })
+    }
+
+    /// Check a run that did not crash. Returns a description of the finding, if any
+    pub fn check(&self, testcase: &str, triage: &GdbTriageResult) -> Result<Option<String>, String> {
+        let exit_code = triage.response.exit_code;
+
+        match &self.check {
+            OracleCheck::OutputRegex(re) => {
+                let matched = re.find(&triage.child.stdout).or_else(|| re.find(&triage.child.stderr));
+
+                Ok(matched.map(|m| format!("output matched /{}/: {}", re.as_str(), m.as_str().trim())))
+            }
+            OracleCheck::ExitCodes(codes) => Ok(exit_code
+                .filter(|c| codes.contains(c))
+                .map(|c| format!("exited with code {}", c))),
+            OracleCheck::Command(command) => {
+                let request = serde_json::json!({
+                    "testcase": testcase,
+                    "stdout": triage.child.stdout,
+                    "stderr": triage.child.stderr,
+                    "exit_code": exit_code,
+                });
+
+                let output = process::execute_capture_output_timeout(
+                    "sh", &["-c", command], ORACLE_COMMAND_TIMEOUT_MS, Some(request.to_string().into_bytes()))
+                    .map_err(|e| format!("Failed to execute oracle {} command: {}", self.id, e))?;
+
+                match output.status.code() {
+                    Some(0) => Ok(None),
+                    Some(1) => {
+                        let description = output.stdout.lines()
+                            .map(str::trim)
+                            .find(|l| !l.is_empty())
+                            .unwrap_or("the checker command reported a finding");
+                        Ok(Some(description.to_string()))
+                    }
+                    _ => Err(format!("Oracle {} command failed ({}): {}", self.id, output.status, output.stderr.trim())),
+                }
+            }
+        }
+    }
+}
+
+/// A run that did not crash, but was marked as a finding by an [Oracle]
+#[derive(Debug, PartialEq, Serialize)]
+pub struct OracleFinding {
+    /// The ID of the oracle that matched
+    pub oracle: String,
+    pub description: String,
+    /// The bucket identifier, from the oracle ID and the top frame of the backtrace
+    pub bucket: String,
+    /// The frame signature used for bucketing, if a backtrace was captured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_frame: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// The backtrace captured at the oracle breakpoint
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub backtrace: Vec<GdbOracleFrame>,
+}
+
+impl OracleFinding {
+    pub fn summary(&self) -> String {
+        format!("Oracle {} finding: {}", self.oracle, self.description)
+    }
+}
+
+/// A frame signature for bucketing: file:line, a function name, or an address
+fn oracle_frame_signature(frame: &GdbOracleFrame) -> String {
+    match (&frame.file, frame.line, &frame.function_name) {
+        (Some(file), Some(line), _) => format!("{}:{}", file, line),
+        (_, _, Some(function)) => function.to_string(),
+        _ => format!("0x{:x}", frame.address),
+    }
+}
+
+/// Run the `oracles` in order on a run that did not crash. The first oracle to match creates the
+/// finding
+pub fn check_oracles(oracles: &[Oracle], testcase: &str, triage: &GdbTriageResult) -> Option<OracleFinding> {
+    for oracle in oracles {
+        let description = match oracle.check(testcase, triage) {
+            Ok(Some(description)) => description,
+            Ok(None) => continue,
+            Err(e) => {
+                log::warn!("{}: {}", shlex::quote(testcase), e);
+                continue;
+            }
+        };
+
+        let backtrace = triage.response.oracle_backtrace.clone().unwrap_or_default();
+        // The breakpoint's caller, or the breakpoint itself if it has none
+        let top_frame = backtrace.get(1).or_else(|| backtrace.first()).map(oracle_frame_signature);
+
+        let mut hash = md5::Context::new();
+        hash.consume(oracle.id.as_bytes());
+
+        if let Some(frame) = &top_frame {
+            hash.consume(frame.as_bytes());
+        }
+
+        return Some(OracleFinding {
+            oracle: oracle.id.to_string(),
+            description,
+            bucket: format!("{}_{:x}", oracle.id, hash.compute()),
+            top_frame,
+            exit_code: triage.response.exit_code,
+            backtrace,
+        });
+    }
+
+    None
+}
+
+/// Render a text report of a finding
+pub fn format_text_report(finding: &OracleFinding, command_line: &[String], testcase: &str, child_output: Option<(&str, &str)>) -> String {
+    let mut report = format!("Summary: {}\n", finding.summary());
+    report += &format!("Command line: {}\n", shell_join(command_line));
+    report += &format!("Testcase: {}\n", shlex::quote(testcase));
+    report += &format!("Oracle bucket: {}\n", finding.bucket);
+
+    if let Some(exit_code) = finding.exit_code {
+        report += &format!("Exit code: {}\n", exit_code);
+    }
+
+    if !finding.backtrace.is_empty() {
+        report += "\nOracle breakpoint backtrace\n";
+
+        for (i, frame) in finding.backtrace.iter().enumerate() {
+            report += &format!("  #{:<2} 0x{:016x} in {}", i, frame.address,
+                frame.function_name.as_deref().unwrap_or("??"));
+
+            if let (Some(file), Some(line)) = (&frame.file, frame.line) {
+                report += &format!(" ({}:{})", file, line);
+            }
+
+            report += "\n";
+        }
+    }
+
+    if let Some((stdout, stderr)) = child_output {
+        report += &format!("\nChild STDOUT:\n{}\nChild STDERR:\n{}\n", stdout.trim_end(), stderr.trim_end());
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debugger::gdb::{GdbChildOutput, GdbJsonResult, GdbResultCode};
+
+    fn no_crash(stdout: &str, exit_code: Option<i32>, oracle_backtrace: Option<Vec<GdbOracleFrame>>) -> GdbTriageResult {
+        GdbTriageResult {
+            response: GdbJsonResult {
+                result: GdbResultCode::ERROR_TARGET_NOT_RUNNING,
+                context: None,
+                forks: None,
+                exit_code,
+                oracle_backtrace,
+                exit_signal: None,
+                warnings: vec![],
+            },
+            child: GdbChildOutput { stdout: stdout.into(), ..Default::default() },
+        }
+    }
+
+    #[test]
+    fn test_oracle_parse() {
+        assert!(matches!(Oracle::parse("sql=regex:SQL error").unwrap().check, OracleCheck::OutputRegex(_)));
+        assert!(matches!(Oracle::parse("bad_exit=exit:2, 3").unwrap().check, OracleCheck::ExitCodes(ref c) if c == &[2, 3]));
+        assert!(matches!(Oracle::parse("diff=command:./check.sh").unwrap().check, OracleCheck::Command(_)));
+
+        assert!(Oracle::parse("sql").is_err());
+        assert!(Oracle::parse("sql=grep:x").is_err());
+        assert!(Oracle::parse("a b=exit:1").is_err());
+        assert!(Oracle::parse("x=exit:one").is_err());
+        assert!(Oracle::parse("x=regex:(").is_err());
+    }
+
+    #[test]
+    fn test_check_oracles() {
+        let oracles = vec![
+            Oracle::parse("sql=regex:SQL error[^\\n]*").unwrap(),
+            Oracle::parse("exit=exit:3").unwrap(),
+            Oracle::parse("diff=command:if grep -q mismatch; then echo outputs differ; exit 1; fi").unwrap(),
+        ];
+
+        assert_eq!(check_oracles(&oracles, "tc", &no_crash("all good", Some(0), None)), None);
+
+        let finding = check_oracles(&oracles, "tc", &no_crash("ERROR: SQL error near x\n", Some(0), None)).unwrap();
+        assert_eq!(finding.oracle, "sql");
+        assert_eq!(finding.description, "output matched /SQL error[^\\n]*/: SQL error near x");
+        assert_eq!(finding.top_frame, None);
+
+        let finding = check_oracles(&oracles, "tc", &no_crash("", Some(3), None)).unwrap();
+        assert_eq!(finding.oracle, "exit");
+
+        let finding = check_oracles(&oracles, "tc", &no_crash("mismatch", Some(0), None)).unwrap();
+        assert_eq!(finding.oracle, "diff");
+        assert_eq!(finding.description, "outputs differ");
+
+        let frame = |function: &str, line| GdbOracleFrame {
+            address: 0x401000,
+            function_name: Some(function.into()),
+            file: Some("db.c".into()),
+            line: Some(line),
+        };
+
+        let a = check_oracles(&oracles, "tc", &no_crash("SQL error", None, Some(vec![frame("report_error", 10), frame("query", 20)]))).unwrap();
+        let b = check_oracles(&oracles, "tc", &no_crash("SQL error", None, Some(vec![frame("report_error", 10), frame("insert", 30)]))).unwrap();
+        assert_eq!(a.top_frame.as_deref(), Some("db.c:20"));
+        assert_ne!(a.bucket, b.bucket);
+    }
+}
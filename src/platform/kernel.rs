@@ -0,0 +1,197 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Linux kernel settings that change crash behavior.
+//!
+//! A crash that AFL found may not reproduce during triage because the system is configured
+//! differently from a typical fuzzing setup. The settings are read from `/proc/sys` once per run,
+//! recorded in every report, and checked for differences that plausibly change crash behavior:
+//!
+//! * `kernel.randomize_va_space` - GDB disables ASLR for the target (unless triaging with `--aslr`),
+//!   unlike most fuzzing setups
+//! * `kernel.core_pattern` - AFL requires core dumps to not be piped to a helper program
+//! * `kernel.yama.ptrace_scope` - GDB needs to be allowed to ptrace the target
+//! * `vm.overcommit_memory` - Sanitizers reserve far more memory than they use
+use crate::report::enriched::EnrichedTriageInfo;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Faults below this address are NULL pointer dereferences and do not depend on the memory layout
+const NULL_PAGE_END: u64 = 0x10000;
+
+/// Kernel settings read from `/proc/sys`. Settings that could not be read are `None`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KernelSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub randomize_va_space: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub core_pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ptrace_scope: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overcommit_memory: Option<u32>,
+}
+
+/// A kernel setting that may change crash behavior
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingWarning {
+    /// The sysctl name of the setting
+    pub setting: String,
+    pub value: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for SettingWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}: {}", self.setting, self.value, self.message)
+    }
+}
+
+fn read_setting(proc_sys: &Path, name: &str) -> Option<String> {
+    std::fs::read_to_string(proc_sys.join(name)).ok().map(|s| s.trim().to_string())
+}
+
+/// Read the kernel settings from a `/proc/sys` directory
+pub fn read_kernel_settings_from(proc_sys: &Path) -> KernelSettings {
+    KernelSettings {
+        randomize_va_space: read_setting(proc_sys, "kernel/randomize_va_space").and_then(|s| s.parse().ok()),
+        core_pattern: read_setting(proc_sys, "kernel/core_pattern"),
+        ptrace_scope: read_setting(proc_sys, "kernel/yama/ptrace_scope").and_then(|s| s.parse().ok()),
+        overcommit_memory: read_setting(proc_sys, "vm/overcommit_memory").and_then(|s| s.parse().ok()),
+    }
+}
+
+/// Read the kernel settings of this system
+pub fn read_kernel_settings() -> KernelSettings {
+    read_kernel_settings_from(Path::new("/proc/sys"))
+}
+
+fn warning(setting: &str, value: impl ToString, message: &str) -> SettingWarning {
+    SettingWarning {
+        setting: setting.to_string(),
+        value: value.to_string(),
+        message: message.to_string(),
+    }
+}
+
+impl KernelSettings {
+    /// A one line summary of the settings that could be read
+    pub fn summary(&self) -> String {
+        let mut settings = vec![];
+
+        if let Some(v) = self.randomize_va_space {
+            settings.push(format!("randomize_va_space={}", v));
+        }
+        if let Some(v) = &self.core_pattern {
+            settings.push(format!("core_pattern={}", v));
+        }
+        if let Some(v) = self.ptrace_scope {
+            settings.push(format!("ptrace_scope={}", v));
+        }
+        if let Some(v) = self.overcommit_memory {
+            settings.push(format!("overcommit_memory={}", v));
+        }
+
+        settings.join(", ")
+    }
+
+    fn aslr_warning(&self) -> Option<SettingWarning> {
+        self.randomize_va_space.filter(|&v| v != 0).map(|v| warning("kernel.randomize_va_space", v,
+            "Fuzzing likely ran with ASLR, but GDB disables it for the target. Crashes that depend on the memory layout may not reproduce"))
+    }
+
+    fn core_pattern_warning(&self) -> Option<SettingWarning> {
+        self.core_pattern.as_ref().filter(|v| v.starts_with('|')).map(|v| warning("kernel.core_pattern", v,
+            "Core dumps are piped to a helper program, which AFL does not allow while fuzzing. Crashes outside of the debugger, such as in forked children, can be slow and time out"))
+    }
+
+    fn overcommit_warning(&self) -> Option<SettingWarning> {
+        self.overcommit_memory.filter(|&v| v == 2).map(|v| warning("vm.overcommit_memory", v,
+            "Memory overcommit is disabled. Sanitizers reserve large amounts of memory and may abort, and allocation failures can differ from fuzzing"))
+    }
+
+    /// Warnings about settings that affect the whole run. `privileged` is whether AFLTriage can
+    /// ptrace any process and `aslr` is whether the target is triaged with ASLR
+    pub fn run_warnings(&self, privileged: bool, aslr: bool) -> Vec<SettingWarning> {
+        let mut warnings = vec![];
+
+        if aslr && self.randomize_va_space == Some(0) {
+            warnings.push(warning("kernel.randomize_va_space", 0,
+                "ASLR was requested, but the kernel disables it for every process"));
+        }
+
+        match self.ptrace_scope {
+            Some(3) => warnings.push(warning("kernel.yama.ptrace_scope", 3,
+                "ptrace is disabled until reboot. GDB cannot debug the target and every testcase will error")),
+            Some(2) if !privileged => warnings.push(warning("kernel.yama.ptrace_scope", 2,
+                "Only processes with CAP_SYS_PTRACE may use ptrace. GDB cannot debug the target unless AFLTriage runs as root")),
+            _ => (),
+        }
+
+        warnings.extend(self.core_pattern_warning());
+        warnings.extend(self.overcommit_warning());
+        warnings
+    }
+
+    /// Warnings about settings that plausibly changed the behavior of a specific crash. `aslr` is
+    /// whether the target was triaged with ASLR
+    pub fn crash_warnings(&self, einfo: &EnrichedTriageInfo, aslr: bool) -> Vec<SettingWarning> {
+        let mut warnings = vec![];
+
+        let layout_dependent = einfo.stop_info.faulting_address.as_ref()
+            .map(|a| a.r >= NULL_PAGE_END)
+            .unwrap_or(false);
+
+        if layout_dependent && !aslr {
+            warnings.extend(self.aslr_warning());
+        }
+
+        if einfo.forked_child == Some(true) {
+            warnings.extend(self.core_pattern_warning());
+        }
+
+        if einfo.sanitizer_reports.is_some() {
+            warnings.extend(self.overcommit_warning());
+        }
+
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::load_test_report;
+
+    #[test]
+    fn test_kernel_settings() {
+        let proc_sys = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(proc_sys.path().join("kernel/yama")).unwrap();
+        std::fs::create_dir_all(proc_sys.path().join("vm")).unwrap();
+        std::fs::write(proc_sys.path().join("kernel/randomize_va_space"), "2\n").unwrap();
+        std::fs::write(proc_sys.path().join("kernel/core_pattern"), "|/usr/share/apport/apport %p\n").unwrap();
+        std::fs::write(proc_sys.path().join("kernel/yama/ptrace_scope"), "2\n").unwrap();
+        std::fs::write(proc_sys.path().join("vm/overcommit_memory"), "2\n").unwrap();
+
+        let settings = read_kernel_settings_from(proc_sys.path());
+        assert_eq!(settings.summary(),
+            "randomize_va_space=2, core_pattern=|/usr/share/apport/apport %p, ptrace_scope=2, overcommit_memory=2");
+
+        let names = |w: Vec<SettingWarning>| w.into_iter().map(|w| w.setting).collect::<Vec<_>>();
+        assert_eq!(names(settings.run_warnings(false, false)),
+            vec!["kernel.yama.ptrace_scope", "kernel.core_pattern", "vm.overcommit_memory"]);
+        assert_eq!(names(settings.run_warnings(true, true)), vec!["kernel.core_pattern", "vm.overcommit_memory"]);
+
+        // An ASAN report without a faulting address
+        let einfo: EnrichedTriageInfo = load_test_report();
+        assert_eq!(names(settings.crash_warnings(&einfo, false)), vec!["vm.overcommit_memory"]);
+        assert!(KernelSettings::default().crash_warnings(&einfo, false).is_empty());
+
+        let missing = read_kernel_settings_from(&proc_sys.path().join("missing"));
+        assert_eq!(missing, KernelSettings::default());
+        assert!(missing.run_warnings(false, false).is_empty());
+
+        let no_aslr = KernelSettings { randomize_va_space: Some(0), ..KernelSettings::default() };
+        assert_eq!(names(no_aslr.run_warnings(true, true)), vec!["kernel.randomize_va_space"]);
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Targets that fail to start because of the environment.
+//!
+//! A target that cannot be loaded did not run the testcase at all, so its "result" says nothing
+//! about the testcase. These runs are reported as environment errors, naming the missing
+//! dependency, instead of as crashes, runs without a crash, or triage errors. They are detected
+//! from the messages of the dynamic loader (`ld.so`) and the shell that GDB starts the target
+//! with, and from `SIGILL` while the dynamic loader runs, which happens when the CPU lacks an
+//! instruction set extension that a library's IFUNC resolver or the loader itself assumed. On
+//! macOS, the dynamic loader (`dyld`) aborts the target instead of exiting.
+use crate::debugger::gdb::{GdbFrameInfo, GdbResultCode, GdbTriageResult};
+use regex::Regex;
+
+/// How many of the innermost frames are checked for the dynamic loader
+const LOADER_FRAMES: usize = 8;
+
+/// What kept the target from starting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EnvironmentErrorKind {
+    MissingLibrary,
+    MissingLibraryVersion,
+    MissingSymbol,
+    MissingInterpreter,
+    /// The target is for another architecture or is not an executable
+    ExecFormat,
+    UnsupportedCpu,
+}
+
+impl EnvironmentErrorKind {
+    fn describe(&self) -> &'static str {
+        match self {
+            EnvironmentErrorKind::MissingLibrary => "missing shared library",
+            EnvironmentErrorKind::MissingLibraryVersion => "missing shared library version",
+            EnvironmentErrorKind::MissingSymbol => "missing symbol",
+            EnvironmentErrorKind::MissingInterpreter => "missing interpreter",
+            EnvironmentErrorKind::ExecFormat => "unsupported executable format",
+            EnvironmentErrorKind::UnsupportedCpu => "unsupported CPU feature (illegal instruction while loading)",
+        }
+    }
+}
+
+/// A target that failed to start, with the dependency that is missing or unsupported
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnvironmentError {
+    pub kind: EnvironmentErrorKind,
+    pub dependency: String,
+}
+
+impl std::fmt::Display for EnvironmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ENVIRONMENT_ERROR: {} {}", self.kind.describe(), self.dependency)
+    }
+}
+
+lazy_static! {
+    static ref LOADER_ERRORS: Vec<(EnvironmentErrorKind, Regex)> = vec![
+        (EnvironmentErrorKind::MissingLibrary,
+            Regex::new(r"error while loading shared libraries: (?P<dep>[^:]+): cannot open shared object file").unwrap()),
+        (EnvironmentErrorKind::MissingLibraryVersion,
+            Regex::new(r"version `(?P<dep>[^']+)' not found \(required by [^)]+\)").unwrap()),
+        (EnvironmentErrorKind::MissingSymbol,
+            Regex::new(r"symbol lookup error: [^:]+: undefined symbol: (?P<dep>\S+)").unwrap()),
+        (EnvironmentErrorKind::MissingLibrary,
+            Regex::new(r"dyld(\[[0-9]+\])?: Library not loaded: (?P<dep>\S+)").unwrap()),
+        (EnvironmentErrorKind::MissingSymbol,
+            Regex::new(r"dyld(\[[0-9]+\])?: Symbol not found: (?P<dep>\S+)").unwrap()),
+        (EnvironmentErrorKind::MissingInterpreter,
+            Regex::new(r": (?P<dep>[^:]+): bad interpreter: No such file or directory").unwrap()),
+        (EnvironmentErrorKind::MissingInterpreter,
+            Regex::new(r"(?P<dep>[^:\s]+): cannot execute: required file not found").unwrap()),
+        (EnvironmentErrorKind::ExecFormat,
+            Regex::new(r"(?P<dep>[^:\s]+): cannot execute binary file: Exec format error").unwrap()),
+    ];
+}
+
+/// An environment error in the messages of the loader or shell
+fn find_loader_error(output: &str) -> Option<EnvironmentError> {
+    LOADER_ERRORS.iter().find_map(|(kind, re)| {
+        re.captures(output).map(|c| EnvironmentError {
+            kind: *kind,
+            dependency: c["dep"].trim().to_string(),
+        })
+    })
+}
+
+fn is_loader_frame(frame: &GdbFrameInfo) -> bool {
+    let module = frame.module.rsplit('/').next().unwrap_or("");
+    let function = frame.symbol.as_ref()
+        .and_then(|s| s.function_name.as_deref())
+        .unwrap_or("");
+
+    module.starts_with("ld-linux") || module.starts_with("ld64.so") ||
+        (module.starts_with("ld-") && module.contains(".so")) ||
+        function.starts_with("_dl_") || function.contains("ifunc")
+}
+
+/// Whether dyld is aborting the target, which every program starts in, so it is on every stack
+fn is_dyld_abort(frame: &GdbFrameInfo) -> bool {
+    let function = frame.symbol.as_ref()
+        .and_then(|s| s.function_name.as_deref())
+        .unwrap_or("");
+
+    frame.module.rsplit('/').next() == Some("dyld") && (function.ends_with("halt") || function.contains("abort_with_payload"))
+}
+
+/// Whether the target failed to start because of its environment, rather than the testcase
+pub fn detect_environment_error(triage: &GdbTriageResult) -> Option<EnvironmentError> {
+    match triage.response.result {
+        GdbResultCode::ERROR_TARGET_NOT_RUNNING => {
+            // A target that ran and failed normally can print anything, including these messages
+            match triage.response.exit_code {
+                Some(0) => None,
+                _ => find_loader_error(&triage.child.stderr),
+            }
+        }
+        GdbResultCode::SUCCESS => {
+            let ctx = triage.response.context.as_ref()?;
+            let frames = &ctx.primary_thread.backtrace;
+
+            match ctx.stop_info.signal_name.as_str() {
+                "SIGABRT" if frames.iter().take(LOADER_FRAMES).any(is_dyld_abort) => {
+                    return find_loader_error(&triage.child.stderr);
+                }
+                "SIGILL" => (),
+                _ => return None,
+            }
+
+            // The loader calls IFUNC resolvers, so the faulting frame can be in any library
+            if !frames.iter().take(LOADER_FRAMES).any(is_loader_frame) {
+                return None;
+            }
+
+            let faulting = frames.first()?;
+            let dependency = match faulting.symbol.as_ref().and_then(|s| s.function_name.as_ref()) {
+                Some(function) => format!("{} in {}", function, faulting.module),
+                None => faulting.module.to_string(),
+            };
+
+            Some(EnvironmentError { kind: EnvironmentErrorKind::UnsupportedCpu, dependency })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debugger::gdb::{GdbChildOutput, GdbJsonResult};
+    use crate::report::fixtures::load_test_triage;
+
+    fn not_running(exit_code: i32, stderr: &str) -> GdbTriageResult {
+        GdbTriageResult {
+            response: GdbJsonResult {
+                result: GdbResultCode::ERROR_TARGET_NOT_RUNNING,
+                context: None,
+                forks: None,
+                exit_code: Some(exit_code),
+                oracle_backtrace: None,
+                warnings: vec![],
+                exit_signal: None,
+            },
+            child: GdbChildOutput { stderr: stderr.into(), ..Default::default() },
+        }
+    }
+
+    #[test]
+    fn test_detect_environment_error() {
+        let error = |exit_code, stderr| detect_environment_error(&not_running(exit_code, stderr))
+            .map(|e| (e.kind, e.dependency));
+
+        assert_eq!(error(127, "./target: error while loading shared libraries: libfoo.so.1: cannot open shared object file: No such file or directory\n"),
+            Some((EnvironmentErrorKind::MissingLibrary, "libfoo.so.1".into())));
+        assert_eq!(error(1, "./target: /lib/x86_64-linux-gnu/libc.so.6: version `GLIBC_2.34' not found (required by ./target)\n"),
+            Some((EnvironmentErrorKind::MissingLibraryVersion, "GLIBC_2.34".into())));
+        assert_eq!(error(127, "./target: symbol lookup error: ./target: undefined symbol: foo_init\n"),
+            Some((EnvironmentErrorKind::MissingSymbol, "foo_init".into())));
+        assert_eq!(error(126, "/bin/bash: ./run.py: /usr/bin/python9: bad interpreter: No such file or directory\n"),
+            Some((EnvironmentErrorKind::MissingInterpreter, "/usr/bin/python9".into())));
+        assert_eq!(error(127, "bash: line 1: ./target: cannot execute: required file not found\n"),
+            Some((EnvironmentErrorKind::MissingInterpreter, "./target".into())));
+        assert_eq!(error(126, "bash: line 1: ./target: cannot execute binary file: Exec format error\n"),
+            Some((EnvironmentErrorKind::ExecFormat, "./target".into())));
+
+        assert_eq!(error(1, "dyld[4242]: Library not loaded: @rpath/libfoo.dylib\n  Referenced from: /Users/fuzz/target\n"),
+            Some((EnvironmentErrorKind::MissingLibrary, "@rpath/libfoo.dylib".into())));
+
+        assert_eq!(error(1, "usage: target FILE\n"), None);
+        assert_eq!(error(0, "error while loading shared libraries: libfoo.so: cannot open shared object file"), None);
+
+        let err = detect_environment_error(&not_running(127,
+            "error while loading shared libraries: libfoo.so.1: cannot open shared object file")).unwrap();
+        assert_eq!(err.to_string(), "ENVIRONMENT_ERROR: missing shared library libfoo.so.1");
+    }
+
+    #[test]
+    fn test_detect_unsupported_cpu() {
+        let mut triage: serde_json::Value = load_test_triage();
+        triage["response"]["context"]["stop_info"]["signal_name"] = "SIGILL".into();
+
+        // An illegal instruction in the program is a crash
+        let crash: GdbTriageResult = serde_json::from_value(triage.clone()).unwrap();
+        assert_eq!(detect_environment_error(&crash), None);
+
+        triage["response"]["context"]["primary_thread"]["backtrace"][1]["module"] = "/lib64/ld-linux-x86-64.so.2".into();
+        let loading: GdbTriageResult = serde_json::from_value(triage.clone()).unwrap();
+        assert_eq!(detect_environment_error(&loading), Some(EnvironmentError {
+            kind: EnvironmentErrorKind::UnsupportedCpu,
+            dependency: "raise in /lib/x86_64-linux-gnu/libc-2.27.so".into(),
+        }));
+
+        // dyld aborts a target it cannot load on macOS
+        triage["response"]["context"]["stop_info"]["signal_name"] = "SIGABRT".into();
+        triage["response"]["context"]["primary_thread"]["backtrace"][1]["module"] = "/usr/lib/dyld".into();
+        triage["response"]["context"]["primary_thread"]["backtrace"][1]["symbol"]["function_name"] = "dyld4::halt".into();
+        triage["child"]["stderr"] = "dyld[4242]: Symbol not found: _foo_init\n".into();
+        let dyld: GdbTriageResult = serde_json::from_value(triage).unwrap();
+        assert_eq!(detect_environment_error(&dyld), Some(EnvironmentError {
+            kind: EnvironmentErrorKind::MissingSymbol,
+            dependency: "_foo_init".into(),
+        }));
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! macOS-specific handling.
+//!
+//! On macOS, a crash first arrives as a Mach exception, which LLDB stops on and describes as its
+//! stop reason, like `EXC_BAD_ACCESS (code=1, address=0x10)`. Exceptions are converted to the
+//! signal the kernel would have sent for them (see [mach_exception_stop_info]), with the signal
+//! code of the Linux signal that means the same, so classification and bucketing work like on
+//! Linux.
+//!
+//! Not every program can be debugged. System Integrity Protection (SIP) keeps debuggers away from
+//! the programs in system directories, and a program signed with the hardened runtime can only be
+//! debugged if it has the `com.apple.security.get-task-allow` entitlement. Both are checked before
+//! triage (see [debugging_problem]), since LLDB only reports that it was denied.
+use crate::debugger::gdb::GdbStopInfo;
+use crate::platform::linux::signal_to_string;
+use regex::Regex;
+use std::path::Path;
+use std::process::Command;
+
+lazy_static! {
+    static ref R_MACH_EXCEPTION: Regex = Regex::new(
+        r"^(?P<exc>EXC_[A-Z_]+)(?: \((?P<args>[^)]*)\))?").unwrap();
+    static ref R_SIGNAL: Regex = Regex::new(r"^signal (?P<sig>SIG[A-Z0-9]+)").unwrap();
+    static ref R_CODESIGN_FLAGS: Regex = Regex::new(r"(?m)\bflags=0x[0-9a-f]+\((?P<flags>[^)]*)\)").unwrap();
+}
+
+/// The directories SIP protects. `/usr/local` is not protected
+const SIP_DIRECTORIES: [&str; 4] = ["/System/", "/bin/", "/sbin/", "/usr/"];
+
+/// The value of `name=` in the arguments of a stop reason, like `code=1, address=0x10`
+fn stop_argument(args: &str, name: &str) -> Option<u64> {
+    args.split(", ")
+        .find_map(|arg| arg.strip_prefix(name)?.strip_prefix('='))
+        .and_then(|value| match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        })
+}
+
+fn signal_number(name: &str) -> i32 {
+    (1..32).find(|n| signal_to_string(*n) == name).unwrap_or(0)
+}
+
+/// The signal for an LLDB stop reason: a Mach exception, or a signal like `signal SIGABRT`
+pub fn mach_exception_stop_info(stop_reason: &str) -> Option<GdbStopInfo> {
+    if let Some(signal) = R_SIGNAL.captures(stop_reason) {
+        return Some(GdbStopInfo {
+            signal_name: signal["sig"].to_string(),
+            signal_number: signal_number(&signal["sig"]),
+            // SI_USER
+            signal_code: 0,
+            faulting_address: None,
+        });
+    }
+
+    let exception = R_MACH_EXCEPTION.captures(stop_reason)?;
+    let args = exception.name("args").map(|a| a.as_str()).unwrap_or("");
+    let code = stop_argument(args, "code");
+
+    let (signal_name, signal_code, faulting_address) = match &exception["exc"] {
+        // KERN_INVALID_ADDRESS and KERN_PROTECTION_FAILURE are SEGV_MAPERR and SEGV_ACCERR. Other
+        // codes are processor faults, like a general protection fault on x86_64 for a
+        // non-canonical address, which Linux reports as SI_KERNEL without an address
+        "EXC_BAD_ACCESS" => match code {
+            Some(1) => ("SIGSEGV", 1, stop_argument(args, "address")),
+            Some(2) => ("SIGSEGV", 2, stop_argument(args, "address")),
+            _ => ("SIGSEGV", 0x80, Some(0)),
+        },
+        // ILL_ILLOPC, FPE_INTDIV, and TRAP_BRKPT
+        "EXC_BAD_INSTRUCTION" => ("SIGILL", 1, None),
+        "EXC_ARITHMETIC" => ("SIGFPE", 1, None),
+        // Traps, like __builtin_trap() on arm64 or a Swift runtime failure
+        "EXC_BREAKPOINT" => ("SIGTRAP", 1, None),
+        // Guard and resource violations kill the process, and EXC_CRASH is the abnormal exit
+        // of a process that was not stopped by an earlier exception
+        _ => ("SIGABRT", 0, None),
+    };
+
+    Some(GdbStopInfo {
+        signal_name: signal_name.into(),
+        signal_number: signal_number(signal_name),
+        signal_code,
+        faulting_address,
+    })
+}
+
+/// Whether `program` is in a directory protected by SIP
+pub fn is_sip_protected(program: &Path) -> bool {
+    let path = program.to_string_lossy();
+
+    SIP_DIRECTORIES.iter().any(|dir| path.starts_with(dir)) && !path.starts_with("/usr/local/")
+}
+
+/// Whether `codesign -dv` and `codesign -d --entitlements -` output is for a program with the
+/// hardened runtime that does not allow debuggers
+fn denies_debugger(signature: &str, entitlements: &str) -> bool {
+    let hardened = R_CODESIGN_FLAGS.captures(signature)
+        .map(|c| c["flags"].split(',').any(|f| f == "runtime"))
+        .unwrap_or(false);
+
+    hardened && !entitlements.contains("com.apple.security.get-task-allow")
+}
+
+/// Why LLDB cannot debug `program`, if it cannot
+pub fn debugging_problem(program: &Path) -> Option<String> {
+    if is_sip_protected(program) {
+        return Some(format!("{} is protected by System Integrity Protection, so it cannot be debugged and DYLD_* variables \
+            are removed from its environment. Copy it out of the system directories and sign it again with 'codesign -f -s -'",
+            program.display()));
+    }
+
+    // codesign prints the signature to stderr
+    let signature = Command::new("codesign").arg("-dv").arg(program).output().ok()?;
+    let entitlements = Command::new("codesign").args(["-d", "--entitlements", "-"]).arg(program).output().ok()?;
+
+    if denies_debugger(&String::from_utf8_lossy(&signature.stderr), &String::from_utf8_lossy(&entitlements.stdout)) {
+        return Some(format!("{} is signed with the hardened runtime without the com.apple.security.get-task-allow \
+            entitlement, so it cannot be debugged. Sign it again without the hardened runtime with 'codesign -f -s -'",
+            program.display()));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mach_exception_stop_info() {
+        let stop = |reason| mach_exception_stop_info(reason)
+            .map(|s| (s.signal_name, s.signal_code, s.faulting_address));
+
+        assert_eq!(stop("EXC_BAD_ACCESS (code=1, address=0x10)"), Some(("SIGSEGV".into(), 1, Some(0x10))));
+        assert_eq!(stop("EXC_BAD_ACCESS (code=2, address=0x16f603ff8)"), Some(("SIGSEGV".into(), 2, Some(0x16f603ff8))));
+        assert_eq!(stop("EXC_BAD_ACCESS (code=EXC_I386_GPFLT)"), Some(("SIGSEGV".into(), 0x80, Some(0))));
+        assert_eq!(stop("EXC_BAD_INSTRUCTION (code=EXC_I386_INVOP, subcode=0x0)"), Some(("SIGILL".into(), 1, None)));
+        assert_eq!(stop("EXC_BREAKPOINT (code=1, subcode=0x100003f60)"), Some(("SIGTRAP".into(), 1, None)));
+        assert_eq!(stop("EXC_GUARD (code=4611686018427387908, subcode=0x0)"), Some(("SIGABRT".into(), 0, None)));
+        assert_eq!(stop("signal SIGABRT"), Some(("SIGABRT".into(), 0, None)));
+        assert_eq!(mach_exception_stop_info("signal SIGSEGV").unwrap().signal_number, 11);
+        assert_eq!(stop("breakpoint 1.1"), None);
+
+        assert!(is_sip_protected(Path::new("/usr/bin/plutil")));
+        assert!(!is_sip_protected(Path::new("/usr/local/bin/target")));
+
+        let entitlements = "<plist><dict><key>com.apple.security.get-task-allow</key><true/></dict></plist>";
+        assert!(denies_debugger("CodeDirectory v=20500 size=1234 flags=0x10000(runtime) hashes=27+7", ""));
+        assert!(!denies_debugger("CodeDirectory v=20500 size=1234 flags=0x10000(runtime) hashes=27+7", entitlements));
+        assert!(!denies_debugger("CodeDirectory v=20400 size=1234 flags=0x2(adhoc) hashes=27+0", ""));
+    }
+}
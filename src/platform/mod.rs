@@ -3,3 +3,9 @@
 // SPDX-License-Identifier: BSD-3-Clause
 //! Platform-specific handling
 pub mod linux;
+pub mod kernel;
+pub mod loader;
+pub mod macos;
+pub mod privileges;
+pub mod scheduling;
+pub mod symbolizer;
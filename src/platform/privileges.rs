@@ -0,0 +1,187 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Running with the least privileges triage needs.
+//!
+//! Testcases from external reporters are untrusted, and so is anything the target does with them.
+//! With `--harden`, AFLTriage gives up what it does not need before running anything:
+//!
+//! * Every capability except `CAP_SYS_PTRACE` is dropped, including from the bounding set, so GDB
+//!   and the target cannot regain them when executed as root
+//! * `no_new_privs` is set, so setuid binaries and file capabilities grant nothing to GDB, the
+//!   target, or anything they execute
+//! * Running as root is refused unless `--allow-root` is given
+//!
+//! Capabilities are per-thread, so hardening must happen before the triage threads are started.
+//! The privileges in effect are read back from `/proc/self/status` and recorded in every report.
+use serde::{Deserialize, Serialize};
+
+/// GDB may need to ptrace the target when `kernel.yama.ptrace_scope` is 2
+pub const CAP_SYS_PTRACE: u32 = 19;
+
+/// Required to drop capabilities from the bounding set
+const CAP_SETPCAP: u32 = 8;
+
+/// `_LINUX_CAPABILITY_VERSION_3`, which has 64-bit capability sets
+const CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// Capability names, indexed by their number
+const CAPABILITY_NAMES: &[&str] = &[
+    "chown", "dac_override", "dac_read_search", "fowner", "fsetid", "kill", "setgid", "setuid",
+    "setpcap", "linux_immutable", "net_bind_service", "net_broadcast", "net_admin", "net_raw",
+    "ipc_lock", "ipc_owner", "sys_module", "sys_rawio", "sys_chroot", "sys_ptrace", "sys_pacct",
+    "sys_admin", "sys_boot", "sys_nice", "sys_resource", "sys_time", "sys_tty_config", "mknod",
+    "lease", "audit_write", "audit_control", "setfcap", "mac_override", "mac_admin", "syslog",
+    "wake_alarm", "block_suspend", "audit_read", "perfmon", "bpf", "checkpoint_restore",
+];
+
+/// The privileges of AFLTriage, which GDB and the target run with too
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Privileges {
+    pub uid: u32,
+    pub euid: u32,
+    /// Whether the privileges were reduced with --harden
+    pub hardened: bool,
+    pub no_new_privs: bool,
+    /// The effective capability set, in hexadecimal as in `/proc/self/status`
+    pub capabilities_effective: String,
+    /// The bounding capability set, which limits what executed programs can gain
+    pub capabilities_bounding: String,
+}
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+fn capability_name(cap: u32) -> String {
+    match CAPABILITY_NAMES.get(cap as usize) {
+        Some(name) => format!("cap_{}", name),
+        None => format!("cap_{}", cap),
+    }
+}
+
+fn status_field<'a>(status: &'a str, field: &str) -> Option<&'a str> {
+    status.lines()
+        .find_map(|l| l.strip_prefix(field).and_then(|rest| rest.strip_prefix(':')))
+        .map(str::trim)
+}
+
+fn capability_set(status: &str, field: &str) -> u64 {
+    status_field(status, field)
+        .and_then(|v| u64::from_str_radix(v, 16).ok())
+        .unwrap_or(0)
+}
+
+/// Parse the privileges from the contents of `/proc/<pid>/status`
+pub fn parse_privileges(status: &str) -> Privileges {
+    let uids: Vec<u32> = status_field(status, "Uid")
+        .map(|v| v.split_whitespace().filter_map(|u| u.parse().ok()).collect())
+        .unwrap_or_default();
+
+    Privileges {
+        uid: uids.first().copied().unwrap_or(u32::MAX),
+        euid: uids.get(1).copied().unwrap_or(u32::MAX),
+        hardened: false,
+        no_new_privs: status_field(status, "NoNewPrivs") == Some("1"),
+        capabilities_effective: format!("{:016x}", capability_set(status, "CapEff")),
+        capabilities_bounding: format!("{:016x}", capability_set(status, "CapBnd")),
+    }
+}
+
+/// Read the privileges of this process
+pub fn read_privileges() -> std::io::Result<Privileges> {
+    Ok(parse_privileges(&std::fs::read_to_string("/proc/self/status")?))
+}
+
+impl Privileges {
+    /// The names of the effective capabilities
+    pub fn effective_capabilities(&self) -> Vec<String> {
+        let set = u64::from_str_radix(&self.capabilities_effective, 16).unwrap_or(0);
+
+        (0..64).filter(|cap| set & (1 << cap) != 0)
+            .map(capability_name)
+            .collect()
+    }
+
+    /// A one line summary of the privileges
+    pub fn summary(&self) -> String {
+        let caps = self.effective_capabilities();
+
+        format!("uid={}, euid={}, no_new_privs={}, capabilities={}", self.uid, self.euid,
+            self.no_new_privs as u8, if caps.is_empty() { "none".to_string() } else { caps.join(",") })
+    }
+}
+
+/// Drop every capability not in `keep` from this thread, and from threads and programs it starts,
+/// then set `no_new_privs`
+pub fn harden(keep: &[u32]) -> std::io::Result<()> {
+    let status = std::fs::read_to_string("/proc/self/status")?;
+    let keep_mask = keep.iter().fold(0u64, |mask, cap| mask | (1 << cap));
+    let permitted = capability_set(&status, "CapPrm") & keep_mask;
+    let check = |ret: libc::c_long| if ret < 0 { Err(std::io::Error::last_os_error()) } else { Ok(()) };
+
+    // SAFETY: prctl and capset only change the credentials of this thread
+    unsafe {
+        check(libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_CLEAR_ALL, 0, 0, 0) as libc::c_long)?;
+
+        // Without CAP_SETPCAP, executed programs cannot gain anything outside of the permitted set
+        // once no_new_privs is set, so the bounding set does not matter
+        if capability_set(&status, "CapEff") & (1 << CAP_SETPCAP) != 0 {
+            let bounding = capability_set(&status, "CapBnd");
+
+            for cap in (0..64).filter(|cap| bounding & (1 << cap) != 0 && keep_mask & (1 << cap) == 0) {
+                check(libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) as libc::c_long)?;
+            }
+        }
+
+        let mut header = CapHeader { version: CAPABILITY_VERSION_3, pid: 0 };
+        let mut data = [CapData::default(); 2];
+
+        for (i, d) in data.iter_mut().enumerate() {
+            d.permitted = (permitted >> (32 * i)) as u32;
+            d.effective = d.permitted;
+        }
+
+        check(libc::syscall(libc::SYS_capset, &mut header as *mut CapHeader, data.as_mut_ptr()))?;
+        check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) as libc::c_long)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_privileges() {
+        let status = "Name:\tafltriage\nUid:\t0\t0\t0\t0\nGid:\t0\t0\t0\t0\n\
+            CapInh:\t0000000000000000\nCapPrm:\t0000000000080000\nCapEff:\t0000000000080001\n\
+            CapBnd:\t0000000000080000\nCapAmb:\t0000000000000000\nNoNewPrivs:\t1\n";
+
+        let privileges = parse_privileges(status);
+        assert_eq!(privileges, Privileges {
+            uid: 0,
+            euid: 0,
+            hardened: false,
+            no_new_privs: true,
+            capabilities_effective: "0000000000080001".into(),
+            capabilities_bounding: "0000000000080000".into(),
+        });
+        assert_eq!(privileges.effective_capabilities(), vec!["cap_chown", "cap_sys_ptrace"]);
+        assert_eq!(privileges.summary(), "uid=0, euid=0, no_new_privs=1, capabilities=cap_chown,cap_sys_ptrace");
+
+        let privileges = parse_privileges("Uid:\t1000\t1000\t1000\t1000\nCapEff:\t0000000000000000\nNoNewPrivs:\t0\n");
+        assert_eq!(privileges.summary(), "uid=1000, euid=1000, no_new_privs=0, capabilities=none");
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Sharing the machine with a running fuzzing campaign.
+//!
+//! `--cpu-set` pins AFLTriage to some of the cores, so the ones AFL instances are bound to stay free,
+//! and `--nice` lowers its priority so fuzzers win any core it shares with them. Both are inherited
+//! by threads and programs when they are started, so they must be applied before the triage
+//! threads, GDB, or the target start. The default number of triage threads is the number of cores
+//! in the set.
+use std::io;
+
+/// The highest (least favorable) niceness
+pub const MAX_NICENESS: i32 = 19;
+
+/// Parse a list of cores like `taskset -c` takes, e.g. `0-3,8,10-11`
+pub fn parse_cpu_set(spec: &str) -> Result<Vec<usize>, String> {
+    let mut cpus = vec![];
+    let max_cpus = 8 * std::mem::size_of::<libc::cpu_set_t>();
+    let parse = |cpu: &str| cpu.trim().parse::<usize>()
+        .map_err(|_| format!("Invalid CPU '{}' in CPU set '{}'", cpu, spec));
+
+    for range in spec.split(',') {
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (parse(first)?, parse(last)?),
+            None => (parse(range)?, parse(range)?),
+        };
+
+        if first > last {
+            return Err(format!("Invalid CPU range '{}' in CPU set '{}'", range, spec));
+        }
+
+        if last >= max_cpus {
+            return Err(format!("CPU {} is out of range. At most {} CPUs are supported", last, max_cpus));
+        }
+
+        cpus.extend(first..=last);
+    }
+
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+/// Restrict this thread, and threads and programs it starts, to `cpus`
+pub fn set_cpu_affinity(cpus: &[usize]) -> io::Result<()> {
+    // SAFETY: cpu_set_t is a plain bitmask, and sched_setaffinity only changes this thread
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+
+        for cpu in cpus {
+            libc::CPU_SET(*cpu, &mut set);
+        }
+
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Set the niceness of this thread, and threads and programs it starts. Raising it back requires
+/// `CAP_SYS_NICE`
+pub fn set_niceness(niceness: i32) -> io::Result<()> {
+    // SAFETY: only changes the priority of this thread
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, niceness) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_set() {
+        assert_eq!(parse_cpu_set("0-3,8,10-11"), Ok(vec![0, 1, 2, 3, 8, 10, 11]));
+        assert_eq!(parse_cpu_set("4,2,2-3"), Ok(vec![2, 3, 4]));
+        assert!(parse_cpu_set("3-1").is_err());
+        assert!(parse_cpu_set("0,,1").is_err());
+        assert!(parse_cpu_set("a-b").is_err());
+        assert!(parse_cpu_set("100000").is_err());
+    }
+}
@@ -0,0 +1,365 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Symbolization of sanitizer reports and GDB frames.
+//!
+//! Sanitizers symbolize their own stack traces by running an external symbolizer. When none can
+//! be found, every frame of a report is a bare address (`#0 0x4f5d1c  (/target+0x4f5d1c)`). The
+//! addresses change with every build and, with ASLR, every run, so buckets and summaries built from
+//! them are close to useless. Nothing fails when this happens, so AFLTriage checks for it before
+//! triage starts.
+//!
+//! GDB can also fail to name frames, for instance when a stripped binary's debug info is in a
+//! separate file GDB does not look for. An [ExternalSymbolizer] (`llvm-symbolizer` or `addr2line`)
+//! is then run over the addresses of those frames after triage and what it finds is merged into
+//! their [GdbSymbol], before buckets and reports are made.
+//!
+//! Separate debug files are found by GDB in its debug file directories (`--debug-file-dir`), by
+//! the build ID of a module in `DIR/.build-id/xx/yyyy.debug` or by the module's path and
+//! `.gnu_debuglink` section. Modules whose frames are still unnamed after triage are listed with
+//! [unsymbolized_modules], so the run can warn about the debug files it did not find.
+use crate::debugger::gdb::{GdbFrameInfo, GdbSymbol, GdbTriageResult};
+use crate::process;
+use regex::bytes::Regex as BytesRegex;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Symbolizers the sanitizer runtime finds in `PATH` by itself
+const PATH_SYMBOLIZERS: &[&str] = &["llvm-symbolizer"];
+
+lazy_static! {
+    /// Symbols only present in binaries instrumented with AddressSanitizer
+    static ref R_ASAN_RUNTIME: BytesRegex = BytesRegex::new(r"__asan_init|__asan_report_(load|store)").unwrap();
+    static ref R_SYMBOLIZE_OFF: Regex = Regex::new(r"(^|[:,])symbolize=(0|false)($|[:,])").unwrap();
+}
+
+/// How long a symbolizer may take for the frames of one module
+const SYMBOLIZER_TIMEOUT_MS: u64 = 30000;
+
+/// The ELF `e_type` of executables loaded at a fixed address
+const ET_EXEC: u16 = 2;
+
+/// Which command line an [ExternalSymbolizer] takes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolizerKind {
+    LlvmSymbolizer,
+    Addr2line,
+}
+
+/// A symbolizer run over the frames GDB could not name
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalSymbolizer {
+    pub kind: SymbolizerKind,
+    pub path: PathBuf,
+}
+
+/// What a symbolizer found for an address
+#[derive(Debug, Default, PartialEq)]
+struct SymbolizedAddress {
+    function_name: Option<String>,
+    file: Option<String>,
+    line: Option<i64>,
+}
+
+/// Parse symbolizer output in the GNU style of `addr2line -f`: a function line then a `FILE:LINE`
+/// line for each address, with `??` for anything unknown
+fn parse_gnu_output(output: &str, count: usize) -> Vec<SymbolizedAddress> {
+    let lines: Vec<&str> = output.lines().collect();
+
+    lines.chunks(2)
+        .take(count)
+        .map(|pair| {
+            let function_name = Some(pair[0].trim())
+                .filter(|f| !f.is_empty() && *f != "??")
+                .map(str::to_string);
+
+            // e.g. /src/target.c:42 (discriminator 3)
+            let location = pair.get(1).and_then(|l| l.split(" (").next()).unwrap_or("");
+            let (file, line) = match location.rsplit_once(':') {
+                Some((file, line)) => (file, line.parse::<i64>().ok().filter(|l| *l > 0)),
+                None => (location, None),
+            };
+
+            SymbolizedAddress {
+                function_name,
+                file: Some(file).filter(|f| !f.is_empty() && *f != "??").map(str::to_string),
+                line,
+            }
+        })
+        .collect()
+}
+
+/// Whether the ELF file at `path` is an executable loaded at a fixed address, whose symbolizer
+/// addresses are absolute instead of relative to where it is loaded
+fn is_fixed_address_elf(path: &Path) -> bool {
+    let mut header = [0u8; 18];
+
+    let read = std::fs::File::open(path)
+        .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut header));
+
+    if read.is_err() || &header[..4] != b"\x7fELF" {
+        return false;
+    }
+
+    let e_type = match header[5] {
+        2 => u16::from_be_bytes([header[16], header[17]]),
+        _ => u16::from_le_bytes([header[16], header[17]]),
+    };
+
+    e_type == ET_EXEC
+}
+
+/// Merge what a symbolizer found into a frame, without replacing anything GDB found
+fn merge_symbol(frame: &mut GdbFrameInfo, found: SymbolizedAddress) {
+    if found.function_name.is_none() {
+        return;
+    }
+
+    match frame.symbol.as_mut() {
+        None => {
+            frame.symbol = Some(Rc::new(GdbSymbol {
+                function_name: found.function_name,
+                file: found.file,
+                line: found.line,
+                ..Default::default()
+            }));
+        }
+        Some(symbol) => {
+            // Shared symbols are left alone rather than copied
+            if let Some(symbol) = Rc::get_mut(symbol) {
+                symbol.function_name = found.function_name;
+                symbol.file = symbol.file.take().or(found.file);
+                symbol.line = symbol.line.or(found.line);
+            }
+        }
+    }
+}
+
+impl ExternalSymbolizer {
+    /// Find a symbolizer. `spec` is `auto`, to search `PATH` for `llvm-symbolizer` then
+    /// `addr2line`, or the path of a symbolizer
+    pub fn find(spec: &str) -> Option<ExternalSymbolizer> {
+        let with_kind = |path: PathBuf| {
+            let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            let kind = if name.contains("addr2line") { SymbolizerKind::Addr2line } else { SymbolizerKind::LlvmSymbolizer };
+            ExternalSymbolizer { kind, path }
+        };
+
+        if spec != "auto" {
+            return which::which(spec).ok().map(with_kind);
+        }
+
+        ["llvm-symbolizer", "addr2line"].iter()
+            .find_map(|name| which::which(name).ok())
+            .map(with_kind)
+    }
+
+    fn args(&self, module: &str, addresses: &[u64]) -> Vec<String> {
+        let mut args: Vec<String> = match self.kind {
+            SymbolizerKind::LlvmSymbolizer => vec![
+                "--output-style=GNU".into(), "--functions=linkage".into(), "--demangle".into(),
+                "--no-inlines".into(), format!("--obj={}", module)],
+            SymbolizerKind::Addr2line => vec!["-f".into(), "-C".into(), "-e".into(), module.into()],
+        };
+
+        args.extend(addresses.iter().map(|a| format!("0x{:x}", a)));
+        args
+    }
+
+    fn symbolize_module(&self, module: &str, addresses: &[u64]) -> std::io::Result<Vec<SymbolizedAddress>> {
+        let output = process::execute_capture_output_timeout(
+            &self.path.to_string_lossy(), &self.args(module, addresses), SYMBOLIZER_TIMEOUT_MS, None)?;
+
+        Ok(parse_gnu_output(&output.stdout, addresses.len()))
+    }
+
+    /// The names of the functions at `addresses` in `module`, addresses that are relative to where
+    /// it is loaded, or absolute for an executable loaded at a fixed address
+    pub fn function_names(&self, module: &Path, addresses: &[u64]) -> std::io::Result<Vec<Option<String>>> {
+        let mut names = vec![];
+
+        // Keep the command line well below the system's limit
+        for chunk in addresses.chunks(4096) {
+            let found = self.symbolize_module(&module.to_string_lossy(), chunk)?;
+            names.extend(found.into_iter().map(|f| f.function_name));
+        }
+
+        Ok(names)
+    }
+
+    /// Name the frames of `triage` that GDB could not, using their module's file. Returns how many
+    /// frames were symbolized
+    pub fn symbolize(&self, triage: &mut GdbTriageResult) -> usize {
+        let ctx = match triage.response.context.as_mut() {
+            Some(ctx) => ctx,
+            None => return 0,
+        };
+
+        let mut frames: Vec<&mut GdbFrameInfo> = ctx.primary_thread.backtrace.iter_mut()
+            .chain(ctx.other_threads.iter_mut().flatten().flat_map(|t| t.backtrace.iter_mut()))
+            .filter(|f| f.symbol.as_ref().map(|s| s.function_name.is_none()).unwrap_or(true))
+            // Pseudo modules like [vdso] or ?? have no file to read
+            .filter(|f| f.module.starts_with('/') && Path::new(&f.module).is_file())
+            .collect();
+
+        let mut by_module: HashMap<String, Vec<usize>> = HashMap::new();
+
+        for (i, frame) in frames.iter().enumerate() {
+            by_module.entry(frame.module.to_string()).or_default().push(i);
+        }
+
+        let mut symbolized = 0;
+
+        for (module, indices) in by_module {
+            let fixed = is_fixed_address_elf(Path::new(&module));
+            let addresses: Vec<u64> = indices.iter()
+                .map(|&i| if fixed { frames[i].address } else { frames[i].relative_address })
+                .collect();
+
+            let found = match self.symbolize_module(&module, &addresses) {
+                Ok(found) => found,
+                Err(e) => {
+                    log::warn!("Failed to symbolize frames in {} with {}: {}", module, self.path.display(), e);
+                    continue;
+                }
+            };
+
+            for (i, found) in indices.into_iter().zip(found) {
+                if found.function_name.is_some() {
+                    symbolized += 1;
+                }
+
+                merge_symbol(frames[i], found);
+            }
+        }
+
+        symbolized
+    }
+}
+
+/// Where GDB looks for the separate debug file of a module with `build_id` in the debug file
+/// directory `dir`
+pub fn build_id_debug_path(dir: &Path, build_id: &str) -> PathBuf {
+    let (prefix, rest) = build_id.split_at(std::cmp::min(2, build_id.len()));
+    dir.join(".build-id").join(prefix).join(format!("{}.debug", rest))
+}
+
+/// The modules, with their build IDs if known, of the frames in the backtrace of the primary
+/// thread that have no function name. Pseudo modules like `[vdso]` are left out
+pub fn unsymbolized_modules(triage: &GdbTriageResult) -> Vec<(String, Option<String>)> {
+    let ctx = match triage.response.context.as_ref() {
+        Some(ctx) => ctx,
+        None => return vec![],
+    };
+
+    let mut modules: Vec<(String, Option<String>)> = vec![];
+
+    for frame in &ctx.primary_thread.backtrace {
+        let named = frame.symbol.as_ref().map(|s| s.function_name.is_some()).unwrap_or(false);
+
+        if named || !frame.module.starts_with('/') || modules.iter().any(|(m, _)| *m == frame.module) {
+            continue;
+        }
+
+        let build_id = ctx.modules.iter().flatten()
+            .find(|m| m.path == frame.module)
+            .and_then(|m| m.build_id.clone());
+
+        modules.push((frame.module.to_string(), build_id));
+    }
+
+    modules
+}
+
+/// Whether the binary at `path` is instrumented with AddressSanitizer
+pub fn is_asan_instrumented(path: &Path) -> std::io::Result<bool> {
+    let data = std::fs::read(path)?;
+    Ok(R_ASAN_RUNTIME.is_match(&data))
+}
+
+/// Why the sanitizer runtime will not be able to symbolize its reports, if it cannot. Takes the
+/// `ASAN_OPTIONS` and `ASAN_SYMBOLIZER_PATH` the target runs with
+pub fn asan_symbolizer_problem(asan_options: Option<&str>, symbolizer_path: Option<&str>) -> Option<String> {
+    if asan_options.map(|o| R_SYMBOLIZE_OFF.is_match(o)).unwrap_or(false) {
+        return Some("ASAN_OPTIONS disables symbolization with symbolize=0".into());
+    }
+
+    match symbolizer_path {
+        Some(path) if which::which(path).is_err() =>
+            Some(format!("ASAN_SYMBOLIZER_PATH=\"{}\" does not exist or is not executable", path)),
+        Some(_) => None,
+        None if PATH_SYMBOLIZERS.iter().any(|s| which::which(s).is_ok()) => None,
+        None => Some("no ASAN_SYMBOLIZER_PATH is set and llvm-symbolizer is not in PATH".into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::load_test_triage;
+
+    #[test]
+    fn test_asan_symbolizer_problem() {
+        assert!(asan_symbolizer_problem(Some("abort_on_error=1:symbolize=0"), Some("/bin/sh")).unwrap()
+            .contains("symbolize=0"));
+        assert!(asan_symbolizer_problem(Some("symbolize=false,detect_leaks=0"), None).is_some());
+        assert_eq!(asan_symbolizer_problem(Some("abort_on_error=1:symbolize=1"), Some("/bin/sh")), None);
+        assert!(asan_symbolizer_problem(None, Some("/nonexistent/llvm-symbolizer")).unwrap()
+            .contains("/nonexistent/llvm-symbolizer"));
+
+        let binary = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(binary.path(), b"\x7fELF\0\0main\0__asan_report_load8\0").unwrap();
+        assert!(is_asan_instrumented(binary.path()).unwrap());
+        std::fs::write(binary.path(), b"\x7fELF\0\0main\0").unwrap();
+        assert!(!is_asan_instrumented(binary.path()).unwrap());
+    }
+
+    #[test]
+    fn test_parse_symbolizer_output() {
+        let output = "bad_read\n/tmp/test.c:20\n\
+            ??\n??:0\n\
+            main\n/tmp/test.c:27 (discriminator 2)\n\
+            _start\n??:?\n";
+
+        let found = parse_gnu_output(output, 4);
+        assert_eq!(found.len(), 4);
+        assert_eq!(found[0], SymbolizedAddress {
+            function_name: Some("bad_read".into()),
+            file: Some("/tmp/test.c".into()),
+            line: Some(20),
+        });
+        assert_eq!(found[1], SymbolizedAddress::default());
+        assert_eq!(found[2].line, Some(27));
+        assert_eq!(found[3], SymbolizedAddress { function_name: Some("_start".into()), ..Default::default() });
+
+        let mut frame = GdbFrameInfo {
+            address: 0x5561e001bba8,
+            relative_address: 0xbba8,
+            module: "/tmp/target".into(),
+            module_address: "/tmp/target+0xbba8".into(),
+            symbol: None,
+        };
+        merge_symbol(&mut frame, parse_gnu_output(output, 1).remove(0));
+        assert_eq!(frame.symbol.unwrap().format_short(), "bad_read");
+    }
+
+    #[test]
+    fn test_unsymbolized_modules() {
+        let mut triage: GdbTriageResult = load_test_triage();
+        assert!(unsymbolized_modules(&triage).is_empty());
+
+        let backtrace = &mut triage.response.context.as_mut().unwrap().primary_thread.backtrace;
+        for i in [0, 1, 6] {
+            backtrace[i].symbol = None;
+        }
+
+        assert_eq!(unsymbolized_modules(&triage), vec![
+            ("/lib/x86_64-linux-gnu/libc-2.27.so".to_string(), None),
+            ("/tmp/test".to_string(), None),
+        ]);
+        assert_eq!(build_id_debug_path(Path::new("/usr/lib/debug"), "0f1e2d3c4b5a"),
+            PathBuf::from("/usr/lib/debug/.build-id/0f/1e2d3c4b5a.debug"));
+    }
+}
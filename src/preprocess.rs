@@ -0,0 +1,95 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Testcase pre-processing.
+//!
+//! Some corpora are not stored in the form the target reads, such as compressed files or entries
+//! wrapped in a fuzzer-specific container. A [Preprocessor] runs a shell command for each testcase
+//! before it is triaged and the file the command writes becomes the target's input. In the
+//! command, `{in}` is replaced with the original testcase and `{out}` with where to write the
+//! processed input, for example `zstd -d {in} -o {out}`.
+use crate::process;
+use std::path::{Path, PathBuf};
+
+/// How long a pre-processing command may run
+const PREPROCESS_TIMEOUT_MS: u64 = 30000;
+
+/// Runs a pre-processing command for testcases
+#[derive(Debug)]
+pub struct Preprocessor {
+    command: String,
+    /// Where processed inputs are written
+    output_dir: PathBuf,
+}
+
+/// Replace `{in}` and `{out}` in a pre-processing `command` with shell quoted paths
+pub fn expand_command(command: &str, input: &str, output: &str) -> String {
+    command
+        .replace("{in}", &shlex::quote(input))
+        .replace("{out}", &shlex::quote(output))
+}
+
+impl Preprocessor {
+    /// Create a [Preprocessor] that writes processed inputs to `output_dir`. The `command` must
+    /// contain `{out}`
+    pub fn new(command: &str, output_dir: &Path) -> Result<Preprocessor, String> {
+        if !command.contains("{out}") {
+            return Err(format!("Pre-processing command '{}' must contain {{out}}", command));
+        }
+
+        Ok(Preprocessor {
+            command: command.to_string(),
+            output_dir: output_dir.to_path_buf(),
+        })
+    }
+
+    /// Pre-process a `testcase`. `id` must be unique per testcase, as testcases from different
+    /// directories can have the same name. Returns the path of the processed input
+    pub fn process(&self, testcase: &str, id: usize) -> Result<PathBuf, String> {
+        let name = Path::new(testcase).file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let output = self.output_dir.join(format!("{}_{}", id, crate::util::sanitize(&name)));
+        let command = expand_command(&self.command, testcase, &output.to_string_lossy());
+
+        let result = process::execute_capture_output_timeout("sh", &["-c", &command], PREPROCESS_TIMEOUT_MS, None)
+            .map_err(|e| format!("Failed to execute the pre-processing command: {}", e))?;
+
+        if !result.status.success() {
+            return Err(format!("Pre-processing command failed ({}): {}", result.status, result.stderr.trim()));
+        }
+
+        if !output.is_file() {
+            return Err("Pre-processing command did not write {out}".into());
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_preprocess() {
+        assert_eq!(expand_command("zstd -d {in} -o {out}", "a b.zst", "out"), "zstd -d \"a b.zst\" -o out");
+        assert!(Preprocessor::new("zstd -d {in}", Path::new(".")).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let testcase = dir.path().join("input.txt");
+        std::fs::write(&testcase, "crash").unwrap();
+
+        let upper = Preprocessor::new("tr a-z A-Z < {in} > {out}", dir.path()).unwrap();
+        let processed = upper.process(testcase.to_str().unwrap(), 3).unwrap();
+        assert_eq!(processed, dir.path().join("3_input_txt"));
+        assert_eq!(std::fs::read_to_string(processed).unwrap(), "CRASH");
+
+        let failing = Preprocessor::new("echo bad container >&2; exit 1 # {out}", dir.path()).unwrap();
+        assert_eq!(failing.process(testcase.to_str().unwrap(), 4).unwrap_err(),
+            "Pre-processing command failed (exit status: 1): bad container");
+
+        let lazy = Preprocessor::new("true {in} {out}", dir.path()).unwrap();
+        assert!(lazy.process(testcase.to_str().unwrap(), 5).is_err());
+    }
+}
@@ -3,11 +3,11 @@
 // SPDX-License-Identifier: BSD-3-Clause
 //! Process spawning utilities
 use async_io::block_on;
-use futures_lite::io::AsyncWriteExt;
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use smol_timeout::TimeoutExt;
 use std::ffi::OsStr;
 use std::io::{Result, Error, ErrorKind};
-use std::process::{Command, ExitStatus, Output};
+use std::process::{Command, ExitStatus};
 use async_process::unix::CommandExt;
 use std::time::Duration;
 
@@ -16,17 +16,207 @@ pub struct ChildResult {
     pub stdout: String,
     pub stderr: String,
     pub status: ExitStatus,
+    /// The exact bytes of stdout, if they are not UTF-8 and some were replaced in `stdout`
+    pub stdout_raw: Option<Vec<u8>>,
+    /// The exact bytes of stderr, if they are not UTF-8 and some were replaced in `stderr`
+    pub stderr_raw: Option<Vec<u8>>,
+}
+
+impl ChildResult {
+    fn from_bytes(stdout: Vec<u8>, stderr: Vec<u8>, status: ExitStatus) -> ChildResult {
+        let (stdout, stdout_raw) = crate::util::decode_output(stdout);
+        let (stderr, stderr_raw) = crate::util::decode_output(stderr);
+
+        ChildResult { stdout, stderr, status, stdout_raw, stderr_raw }
+    }
+}
+
+/// The smallest output limit. Anything smaller could cut the markers delimiting a section
+pub const MIN_OUTPUT_LIMIT: usize = 4096;
+
+/// How much of a stream of output is kept. The first `head` and the last `tail` bytes are kept, and
+/// a note of how many bytes were dropped is put in between. With a `section`, only the output
+/// between its start and end markers is limited
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputLimit {
+    pub head: usize,
+    pub tail: usize,
+    pub section: Option<(&'static str, &'static str)>,
+}
+
+impl OutputLimit {
+    /// Keep at most `max_bytes` of a stream, most of them from its end, where errors and sanitizer
+    /// reports are
+    pub fn new(max_bytes: usize) -> Self {
+        let max_bytes = max_bytes.max(MIN_OUTPUT_LIMIT);
+
+        OutputLimit {
+            head: max_bytes / 4,
+            tail: max_bytes - max_bytes / 4,
+            section: None,
+        }
+    }
+
+    /// Only limit the output between the `start` and `end` markers
+    pub fn within(self, start: &'static str, end: &'static str) -> Self {
+        OutputLimit { section: Some((start, end)), ..self }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LimitStage {
+    /// Before the start marker of the section
+    Before,
+    Limited,
+    /// After the end marker of the section, or without a limit
+    After,
+}
+
+/// Output collected under an [OutputLimit]
+pub struct LimitedOutput {
+    limit: Option<OutputLimit>,
+    stage: LimitStage,
+    kept: Vec<u8>,
+    head_len: usize,
+    tail: Vec<u8>,
+    truncated: usize,
+    /// The end of the output so far, to find a marker split between two pushes
+    carry: Vec<u8>,
+}
+
+impl LimitedOutput {
+    /// Collect output, keeping all of it if there is no `limit`
+    pub fn new(limit: Option<OutputLimit>) -> Self {
+        let stage = match &limit {
+            Some(OutputLimit { section: Some(_), .. }) => LimitStage::Before,
+            Some(_) => LimitStage::Limited,
+            None => LimitStage::After,
+        };
+
+        LimitedOutput { limit, stage, kept: vec![], head_len: 0, tail: vec![], truncated: 0, carry: vec![] }
+    }
+
+    fn marker(&self) -> Option<&'static str> {
+        let (start, end) = self.limit.as_ref()?.section?;
+
+        match self.stage {
+            LimitStage::Before => Some(start),
+            LimitStage::Limited => Some(end),
+            LimitStage::After => None,
+        }
+    }
+
+    /// Add `data` to the output
+    pub fn push(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let marker = match self.marker() {
+                Some(marker) => marker.as_bytes(),
+                None => {
+                    self.keep(data);
+                    return;
+                }
+            };
+
+            let joined = [&self.carry[..], data].concat();
+            let found = joined.windows(marker.len()).position(|w| w == marker)
+                .map(|pos| pos + marker.len() - self.carry.len());
+
+            let (now, rest) = data.split_at(found.unwrap_or(data.len()));
+            self.keep(now);
+
+            if found.is_some() {
+                self.carry.clear();
+
+                if self.stage == LimitStage::Limited {
+                    self.flush_tail();
+                }
+
+                self.stage = match self.stage {
+                    LimitStage::Before => LimitStage::Limited,
+                    _ => LimitStage::After,
+                };
+            } else {
+                self.carry = joined[joined.len().saturating_sub(marker.len() - 1)..].to_vec();
+            }
+
+            data = rest;
+        }
+    }
+
+    fn keep(&mut self, data: &[u8]) {
+        let limit = match (&self.limit, self.stage) {
+            (Some(limit), LimitStage::Limited) => limit,
+            _ => {
+                self.kept.extend_from_slice(data);
+                return;
+            }
+        };
+
+        let to_head = data.len().min(limit.head - self.head_len);
+        self.kept.extend_from_slice(&data[..to_head]);
+        self.head_len += to_head;
+        self.tail.extend_from_slice(&data[to_head..]);
+
+        // Drop from the tail in bulk so that it is not shifted on every push
+        if self.tail.len() >= limit.tail * 2 {
+            let excess = self.tail.len() - limit.tail;
+            self.tail.drain(..excess);
+            self.truncated += excess;
+        }
+    }
+
+    /// Move the tail after what is kept, noting how much was dropped before it
+    fn flush_tail(&mut self) {
+        let tail_limit = self.limit.as_ref().map(|l| l.tail).unwrap_or(usize::MAX);
+
+        if self.tail.len() > tail_limit {
+            let excess = self.tail.len() - tail_limit;
+            self.tail.drain(..excess);
+            self.truncated += excess;
+        }
+
+        if self.truncated > 0 {
+            self.kept.extend_from_slice(
+                format!("\n[... {} bytes of output truncated by AFLTriage ...]\n", self.truncated).as_bytes());
+        }
+
+        self.kept.append(&mut self.tail);
+    }
+
+    /// The output that was kept
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.stage == LimitStage::Limited {
+            self.flush_tail();
+        }
+
+        self.kept
+    }
+}
+
+/// Read all of `reader` into `output`
+async fn read_limited<R: AsyncRead + Unpin>(reader: Option<R>, mut output: LimitedOutput) -> Result<LimitedOutput> {
+    if let Some(mut reader) = reader {
+        let mut buf = vec![0u8; 64 * 1024];
+
+        loop {
+            let n = reader.read(&mut buf).await?;
+
+            if n == 0 {
+                break;
+            }
+
+            output.push(&buf[..n]);
+        }
+    }
+
+    Ok(output)
 }
 
 /// Execute a `command` with `args` and capture the output as a String
 pub fn execute_capture_output<S: AsRef<OsStr>>(command: &str, args: &[S]) -> Result<ChildResult> {
     let output = Command::new(command).args(args).output()?;
 
-    Ok(ChildResult {
-        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-        status: output.status,
-    })
+    Ok(ChildResult::from_bytes(output.stdout, output.stderr, output.status))
 }
 
 /// Send SIGTERM to a process
@@ -45,7 +235,7 @@ fn kill_forcefully(pid: i32) {
 
 /// Mask certainy signals when executing subprocesses
 // SAFETY: simple signal handling
-unsafe fn pre_execute() {
+pub(crate) unsafe fn pre_execute() {
     let mut set: libc::sigset_t = core::mem::MaybeUninit::uninit().assume_init();
 
     // GDB spawned under a controlling TTY will inherit it. This means it will also receive
@@ -59,6 +249,13 @@ unsafe fn pre_execute() {
     libc::sigprocmask(libc::SIG_BLOCK, &mut set, core::ptr::null_mut());
 }
 
+/// What a process started with [execute_capture_output_timeout] reads from STDIN
+pub enum ProcessStdin {
+    Null,
+    Data(Vec<u8>),
+    Inherit,
+}
+
 /// Execute a `command` with `args` while enforcing a timeout of `timeout_ms`, after which the
 /// target process is killed. `input` can be passed if input is to be given to the process via
 /// STDIN
@@ -68,40 +265,76 @@ pub fn execute_capture_output_timeout<S: AsRef<OsStr>>(
     timeout_ms: u64,
     input: Option<Vec<u8>>
 ) -> Result<ChildResult> {
-    let output: Output = block_on(async {
+    let stdin = match input {
+        Some(data) => ProcessStdin::Data(data),
+        None => ProcessStdin::Null,
+    };
+
+    execute_with_stdin(command, args, timeout_ms, stdin, None)
+}
+
+/// Like [execute_capture_output_timeout], but the process inherits STDIN from AFLTriage
+pub fn execute_capture_output_timeout_inherit_stdin<S: AsRef<OsStr>>(
+    command: &str,
+    args: &[S],
+    timeout_ms: u64,
+) -> Result<ChildResult> {
+    execute_with_stdin(command, args, timeout_ms, ProcessStdin::Inherit, None)
+}
+
+/// Like [execute_capture_output_timeout], but only as much of the output as `limit` allows is kept,
+/// so that a process flooding its output does not exhaust memory
+pub fn execute_capture_limited_output_timeout<S: AsRef<OsStr>>(
+    command: &str,
+    args: &[S],
+    timeout_ms: u64,
+    input: ProcessStdin,
+    limit: Option<&OutputLimit>,
+) -> Result<ChildResult> {
+    execute_with_stdin(command, args, timeout_ms, input, limit)
+}
+
+fn execute_with_stdin<S: AsRef<OsStr>>(
+    command: &str,
+    args: &[S],
+    timeout_ms: u64,
+    input: ProcessStdin,
+    limit: Option<&OutputLimit>,
+) -> Result<ChildResult> {
+    let (status, stdout, stderr) = block_on(async {
+        let stdin = match &input {
+            ProcessStdin::Null => async_process::Stdio::null(),
+            ProcessStdin::Data(_) => async_process::Stdio::piped(),
+            ProcessStdin::Inherit => async_process::Stdio::inherit(),
+        };
+
         // SAFETY: only pre_exec call back is unsafe
-        let mut cmd = if input.is_none() {
-            unsafe {
-                async_process::Command::new(command)
-                    .stdin(async_process::Stdio::null())
-                    .stdout(async_process::Stdio::piped())
-                    .stderr(async_process::Stdio::piped())
-                    .pre_exec(|| Ok(pre_execute()) )
-                    .args(args)
-                    .spawn()
-            }
-        } else {
-            unsafe {
-                async_process::Command::new(command)
-                    .stdin(async_process::Stdio::piped())
-                    .stdout(async_process::Stdio::piped())
-                    .stderr(async_process::Stdio::piped())
-                    .pre_exec(|| Ok(pre_execute()) )
-                    .args(args)
-                    .spawn()
-            }
+        let mut cmd = unsafe {
+            async_process::Command::new(command)
+                .stdin(stdin)
+                .stdout(async_process::Stdio::piped())
+                .stderr(async_process::Stdio::piped())
+                .pre_exec(|| Ok(pre_execute()) )
+                .args(args)
+                .spawn()
         }?;
 
         let pid = cmd.id();
 
-        if let Some(data) = input {
+        if let ProcessStdin::Data(data) = input {
             let mut stdin: async_process::ChildStdin = cmd.stdin.take().unwrap();
 
             // XXX: this can deadlock
             stdin.write_all(data.as_ref()).await?;
         }
 
-        let output = cmd.output();
+        let stdout = read_limited(cmd.stdout.take(), LimitedOutput::new(limit.cloned()));
+        let stderr = read_limited(cmd.stderr.take(), LimitedOutput::new(limit.cloned()));
+
+        let output = async {
+            let (stdout, stderr) = futures_lite::future::zip(stdout, stderr).await;
+            Ok((cmd.status().await?, stdout?, stderr?))
+        };
 
         let result = output
             .timeout(Duration::from_millis(timeout_ms))
@@ -130,9 +363,56 @@ pub fn execute_capture_output_timeout<S: AsRef<OsStr>>(
         result
     })?;
 
-    Ok(ChildResult {
-        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-        status: output.status,
-    })
+    Ok(ChildResult::from_bytes(stdout.finish(), stderr.finish(), status))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_limited_output() {
+        let mut output = LimitedOutput::new(None);
+        output.push(b"all of it");
+        assert_eq!(output.finish(), b"all of it");
+
+        let limit = OutputLimit::new(8192);
+        assert_eq!((limit.head, limit.tail), (2048, 6144));
+        assert_eq!(OutputLimit::new(10).head + OutputLimit::new(10).tail, MIN_OUTPUT_LIMIT);
+
+        let flood = |output: &mut LimitedOutput| {
+            for _ in 0..100 {
+                output.push(&[b'A'; 1000]);
+            }
+        };
+
+        let mut output = LimitedOutput::new(Some(limit.clone()));
+        flood(&mut output);
+        output.push(b"the end");
+        let text = String::from_utf8(output.finish()).unwrap();
+        assert!(text.starts_with(&"A".repeat(2048)));
+        assert!(text.contains(&format!("A\n[... {} bytes of output truncated by AFLTriage ...]\nA", 100007 - 8192)));
+        assert!(text.ends_with(&format!("{}the end", "A".repeat(6137))));
+
+        // Only the section between the markers is limited, even if a marker is split between pushes
+        let mut output = LimitedOutput::new(Some(limit.within("----START----", "----END----")));
+        output.push(&[b'G'; 10000]);
+        output.push(b"\n----STA");
+        output.push(b"RT----\n");
+        flood(&mut output);
+        output.push(b"----END");
+        output.push(b"----\n");
+        output.push(&[b'J'; 10000]);
+        let text = String::from_utf8(output.finish()).unwrap();
+        assert!(text.starts_with(&format!("{}\n----START----\n{}", "G".repeat(10000), "A".repeat(2047))));
+        assert!(text.contains(" bytes of output truncated by AFLTriage ...]\n"));
+        assert!(text.ends_with(&format!("A----END----\n{}", "J".repeat(10000))));
+
+        let result = execute_capture_limited_output_timeout("sh",
+            &["-c", "head -c 1000000 /dev/zero | tr '\\0' A; echo done >&2"], 10000, ProcessStdin::Null,
+            Some(&OutputLimit::new(8192))).unwrap();
+        assert!(result.stdout.len() < 8192 + 100);
+        assert!(result.stdout.ends_with("AAAA"));
+        assert_eq!(result.stderr, "done\n");
+    }
 }
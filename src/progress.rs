@@ -0,0 +1,193 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Triage progress.
+//!
+//! On a terminal, a progress bar shows the throughput and ETA of the run, and below it a line per
+//! worker thread with the testcase it is triaging and for how long, so a stuck target stands out.
+//! When the output is not a terminal (e.g. under CI or `nohup`), a progress line is logged every
+//! [LOG_INTERVAL] instead.
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often progress is logged when there is no progress bar
+pub const LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Format a duration tersely, e.g. 1h02m, 5m12s, or 42s
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+/// Testcases per second, and the estimated time left, after `done` of `total` in `elapsed`
+pub fn throughput_and_eta(done: u64, total: u64, elapsed: Duration) -> (f64, Option<Duration>) {
+    let secs = elapsed.as_secs_f64();
+
+    if done == 0 || secs <= 0.0 {
+        return (0.0, None);
+    }
+
+    let per_sec = done as f64 / secs;
+    let left = total.saturating_sub(done) as f64 / per_sec;
+
+    (per_sec, Some(Duration::from_secs_f64(left)))
+}
+
+/// A progress line, like `1200/5000 (24%), 12.3 testcases/s, ETA 5m12s`
+pub fn format_progress_line(done: u64, total: u64, elapsed: Duration) -> String {
+    let (per_sec, eta) = throughput_and_eta(done, total, elapsed);
+    let percent = (done * 100).checked_div(total).unwrap_or(100);
+
+    format!("{}/{} ({}%), {:.1} testcases/s, ETA {}", done, total, percent, per_sec,
+        eta.map(format_duration).unwrap_or_else(|| "unknown".into()))
+}
+
+struct ProgressDisplay {
+    bar: ProgressBar,
+    /// A status line per worker thread
+    workers: Vec<ProgressBar>,
+    /// Draws the bars until they are all finished
+    drawer: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// The progress of a triage run, shared by its worker threads
+pub struct TriageProgress {
+    display: Option<ProgressDisplay>,
+    total: u64,
+    done: AtomicU64,
+    started: Instant,
+    last_log: Mutex<Instant>,
+}
+
+impl TriageProgress {
+    /// Track the progress of `total` testcases. With `display`, progress bars are drawn for the
+    /// run and each of its `workers`. Otherwise, progress is logged periodically
+    pub fn new(total: u64, display: bool, workers: usize) -> Self {
+        let display = if display {
+            let multi = MultiProgress::with_draw_target(ProgressDrawTarget::stderr());
+            let bar = multi.add(ProgressBar::new(total));
+            bar.set_style(ProgressStyle::default_bar()
+                .template("[+] Triaging {spinner:.green} [{pos}/{len} {elapsed_precise}] [{bar:.cyan/blue}] {prefix} {wide_msg}")
+                .progress_chars("#>-"));
+            bar.enable_steady_tick(200);
+
+            let workers = (0..workers).map(|i| {
+                let worker = multi.add(ProgressBar::new_spinner());
+                worker.set_style(ProgressStyle::default_spinner()
+                    .template("    {prefix:>3} {spinner} {elapsed:>4} {wide_msg}"));
+                worker.set_prefix(&format!("#{}", i + 1));
+                worker.set_message("idle");
+                worker.enable_steady_tick(500);
+                worker
+            }).collect();
+
+            let drawer = std::thread::spawn(move || {
+                let _ = multi.join();
+            });
+
+            Some(ProgressDisplay { bar, workers, drawer: Mutex::new(Some(drawer)) })
+        } else {
+            None
+        };
+
+        TriageProgress {
+            display,
+            total,
+            done: AtomicU64::new(0),
+            started: Instant::now(),
+            last_log: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Show a message next to the progress bar
+    pub fn set_message(&self, msg: &str) {
+        if let Some(display) = &self.display {
+            display.bar.set_message(msg);
+        }
+    }
+
+    fn worker(&self) -> Option<&ProgressBar> {
+        let display = self.display.as_ref()?;
+        display.workers.get(rayon::current_thread_index()?)
+    }
+
+    /// Show that the current worker thread started triaging `testcase`
+    pub fn start(&self, testcase: &str) {
+        if let Some(worker) = self.worker() {
+            let name = Path::new(testcase).file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| testcase.to_string());
+
+            worker.reset_elapsed();
+            worker.set_message(&name);
+        }
+    }
+
+    /// Count a testcase as done by the current worker thread
+    pub fn inc(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        let elapsed = self.started.elapsed();
+
+        match &self.display {
+            Some(display) => {
+                let (per_sec, eta) = throughput_and_eta(done, self.total, elapsed);
+                display.bar.set_prefix(&format!("{:.1}/s ETA {}", per_sec, format_duration(eta.unwrap_or_default())));
+                display.bar.inc(1);
+
+                if let Some(worker) = self.worker() {
+                    worker.set_message("idle");
+                }
+            }
+            None => {
+                let mut last_log = self.last_log.lock().unwrap();
+
+                if last_log.elapsed() >= LOG_INTERVAL && done < self.total {
+                    *last_log = Instant::now();
+                    log::info!("Progress: {}", format_progress_line(done, self.total, elapsed));
+                }
+            }
+        }
+    }
+
+    /// Stop drawing progress. The progress bar is left on the terminal
+    pub fn finish(&self) {
+        if let Some(display) = &self.display {
+            display.bar.finish();
+
+            for worker in &display.workers {
+                worker.finish_and_clear();
+            }
+
+            if let Some(drawer) = display.drawer.lock().unwrap().take() {
+                let _ = drawer.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_progress_line() {
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(312)), "5m12s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h02m");
+
+        assert_eq!(format_progress_line(1200, 5000, Duration::from_secs(100)),
+            "1200/5000 (24%), 12.0 testcases/s, ETA 5m16s");
+        assert_eq!(format_progress_line(0, 5000, Duration::from_secs(3)),
+            "0/5000 (0%), 0.0 testcases/s, ETA unknown");
+        assert_eq!(throughput_and_eta(10, 10, Duration::from_secs(4)), (2.5, Some(Duration::from_secs(0))));
+    }
+}
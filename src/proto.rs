@@ -0,0 +1,86 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Decoding of protobuf testcases.
+//!
+//! Targets fuzzed with libprotobuf-mutator using a binary fuzzer (`DEFINE_BINARY_PROTO_FUZZER`)
+//! read serialized protobuf messages, which are opaque bytes to an analyst. A [ProtoDecoder]
+//! decodes a testcase into the text format with `protoc --decode`, given the `.proto` file and
+//! the fully qualified message type of the fuzzer's input. `protoc` must be in the `PATH`.
+use crate::process;
+use std::path::{Path, PathBuf};
+
+/// How long decoding a testcase may take
+const PROTOC_TIMEOUT_MS: u64 = 10000;
+
+/// Decodes serialized protobuf messages into the text format
+#[derive(Debug, PartialEq)]
+pub struct ProtoDecoder {
+    pub proto_file: PathBuf,
+    /// The fully qualified message type, such as `fuzzing.Input`
+    pub message_type: String,
+}
+
+impl ProtoDecoder {
+    /// Parse a `PROTO_FILE:MESSAGE_TYPE` decoder definition
+    pub fn parse(spec: &str) -> Result<ProtoDecoder, String> {
+        let (proto_file, message_type) = spec.rsplit_once(':')
+            .filter(|(file, message)| !file.is_empty() && !message.is_empty())
+            .ok_or_else(|| format!("Invalid protobuf decoder '{}'. Expected PROTO_FILE:MESSAGE_TYPE", spec))?;
+
+        Ok(ProtoDecoder {
+            proto_file: PathBuf::from(proto_file),
+            message_type: message_type.to_string(),
+        })
+    }
+
+    /// The arguments to `protoc` to decode a message from STDIN. Imports are resolved relative to
+    /// the directory of the `.proto` file
+    pub fn protoc_args(&self) -> Vec<String> {
+        let include_dir = self.proto_file.parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let file_name = self.proto_file.file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        vec![
+            format!("--decode={}", self.message_type),
+            format!("--proto_path={}", include_dir.to_string_lossy()),
+            file_name,
+        ]
+    }
+
+    /// Decode the message in the file `testcase` into the protobuf text format
+    pub fn decode(&self, testcase: &str) -> Result<String, String> {
+        let data = crate::util::read_file_to_bytes(testcase)
+            .map_err(|e| format!("Failed to read the testcase: {}", e))?;
+
+        let output = process::execute_capture_output_timeout("protoc", &self.protoc_args(), PROTOC_TIMEOUT_MS, Some(data))
+            .map_err(|e| format!("Failed to execute protoc: {}", e))?;
+
+        if !output.status.success() {
+            return Err(format!("protoc failed to decode a {}: {}", self.message_type, output.stderr.trim()));
+        }
+
+        Ok(output.stdout.trim_end().to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_proto_decoder() {
+        let decoder = ProtoDecoder::parse("protos/fuzz.proto:fuzzing.Input").unwrap();
+        assert_eq!(decoder.protoc_args(), vec!["--decode=fuzzing.Input", "--proto_path=protos", "fuzz.proto"]);
+
+        let decoder = ProtoDecoder::parse("fuzz.proto:Input").unwrap();
+        assert_eq!(decoder.protoc_args(), vec!["--decode=Input", "--proto_path=.", "fuzz.proto"]);
+
+        assert!(ProtoDecoder::parse("fuzz.proto").is_err());
+        assert!(ProtoDecoder::parse("fuzz.proto:").is_err());
+        assert!(ProtoDecoder::parse(":Input").is_err());
+    }
+}
@@ -0,0 +1,375 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Re-bucket the saved results of a previous triage run.
+//!
+//! `afltriage rebucket <output>` reads the crashes saved with `--save-results`, assigns them to
+//! buckets using a (possibly different) strategy, and replaces the reports, saved results, and
+//! bucket index in the output directory. GDB is not run again. A mapping from the old to the new
+//! bucket identifiers is written to [REBUCKET_MAP_FILE] so that external references to buckets
+//! can be migrated.
+use clap::{App, AppSettings, Arg, ArgMatches};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use afltriage::{bucket, report};
+use afltriage::analysis::{AnalysisPipeline, BucketPass, ClassifyPass, CrashAnalysis};
+use afltriage::bucket::CrashBucketStrategy;
+use afltriage::report::summary::TestcaseSummary;
+use afltriage::report::index::BucketEntry;
+use afltriage::report::severity::CrashSeverity;
+use afltriage::report::retention::{RetentionPolicy, RetentionRules};
+use afltriage::report::template::TextTemplate;
+use afltriage::report::{ReportOutputFormat, SavedResult};
+
+/// The file in an output directory mapping old bucket identifiers to new ones
+pub const REBUCKET_MAP_FILE: &str = "afltriage_rebucket_map.json";
+
+/// A mapping from each old bucket identifier to the new buckets its crashes landed in. A bucket
+/// can be split or merged with others
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RebucketMap {
+    pub strategy: CrashBucketStrategy,
+    pub buckets: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// The outcome of re-bucketing saved results, before anything is written
+struct Rebucketed {
+    results: Vec<SavedResult>,
+    buckets: HashMap<String, BucketEntry>,
+    summaries: Vec<TestcaseSummary>,
+    /// Report file names and their contents
+    reports: Vec<(String, String)>,
+    map: RebucketMap,
+}
+
+pub fn build_app() -> App<'static, 'static> {
+    App::new("afltriage rebucket")
+        .about("Re-bucket the crashes saved with --save-results without triaging them again")
+        .usage("afltriage rebucket [OPTIONS] <output>")
+        .setting(AppSettings::UnifiedHelpMessage)
+        .setting(AppSettings::DeriveDisplayOrder)
+        .arg(Arg::with_name("output")
+             .required(true)
+             .help("An output directory from a triage run using --save-results."))
+        .arg(Arg::with_name("report_formats")
+             .long("--report-formats")
+             .takes_value(true)
+             .multiple(true)
+             .use_delimiter(true)
+             .possible_values(&ReportOutputFormat::variants())
+             .default_value("text")
+             .case_insensitive(true)
+             .help("The triage report output formats. Multiple values allowed: e.g. text,json."))
+        .arg(Arg::with_name("text_template")
+             .long("--text-template")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Render text reports with this template instead of the built-in layout. See afltriage --help."))
+        .arg(Arg::with_name("retain")
+             .long("--retain")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("SEVERITY=ARTIFACTS")
+             .help("Override which artifacts are kept for crashes of a severity. See afltriage --help."))
+        .arg(crate::severity_rule_arg())
+        .arg(Arg::with_name("bucket_strategy")
+             .long("--bucket-strategy")
+             .takes_value(true)
+             .possible_values(&CrashBucketStrategy::variants())
+             .default_value("afltriage")
+             .case_insensitive(true)
+             .help("The crash deduplication strategy to use. The external strategy requires --bucket-command."))
+        .arg(Arg::with_name("bucket_command")
+             .long("--bucket-command")
+             .takes_value(true)
+             .help("A shell command that decides the bucket of each crash for the external bucket strategy."))
+        .args(&crate::bucket_policy_args())
+        .arg(Arg::with_name("child_output")
+             .long("--child-output")
+             .help("Include child output in triage reports, if it was saved."))
+}
+
+/// Run `afltriage rebucket`. Returns the process exit code
+pub fn rebucket_main(args: &ArgMatches) -> i32 {
+    let output_dir = Path::new(args.value_of("output").unwrap());
+    let results_path = output_dir.join(report::SAVED_RESULTS_FILE);
+
+    let saved = match read_saved_results(&results_path) {
+        Ok(saved) => saved,
+        Err(e) => {
+            log::error!("Failed to read saved results from {}: {}", shlex::quote(&results_path.to_string_lossy()), e);
+            log::error!("Triage with --save-results to be able to re-bucket crashes");
+            return 1;
+        }
+    };
+
+    if saved.is_empty() {
+        log::warn!("There are no saved crashes to re-bucket");
+        return 0;
+    }
+
+    let report_formats = values_t!(args, "report_formats", ReportOutputFormat).unwrap_or_else(|e| e.exit());
+    let bucket_pass = match crate::bucket_pass_from_args(args) {
+        Ok(pass) => pass,
+        Err(e) => {
+            log::error!("{}", e);
+            return 1;
+        }
+    };
+    let bucket_strategy = bucket_pass.strategy;
+    let classify_pass = match crate::classify_pass_from_args(args) {
+        Ok(pass) => pass,
+        Err(e) => {
+            log::error!("{}", e);
+            return 1;
+        }
+    };
+
+    let mut retention = RetentionRules::new(RetentionPolicy {
+        report_formats,
+        child_output: args.is_present("child_output"),
+    });
+
+    for rule in args.values_of("retain").unwrap_or_default() {
+        if let Err(e) = retention.add_rule(rule) {
+            log::error!("{}", e);
+            return 1;
+        }
+    }
+
+    let text_template = match args.value_of("text_template") {
+        Some(path) => match TextTemplate::load(Path::new(path)) {
+            Ok(template) => Some(template),
+            Err(e) => {
+                log::error!("Failed to load the text report template {}: {}", shlex::quote(path), e);
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let old_reports: BTreeSet<String> = saved.iter()
+        .flat_map(|s| s.reports.iter().cloned())
+        .collect();
+    let total = saved.len();
+
+    let rebucketed = match rebucket_results(saved, bucket_pass, classify_pass, &retention, text_template.as_ref()) {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("{}", e);
+            return 1;
+        }
+    };
+
+    // Replace the previous reports
+    for name in old_reports {
+        if let Err(e) = std::fs::remove_file(output_dir.join(&name)) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Failed to remove old report {}: {}", shlex::quote(&name), e);
+            }
+        }
+    }
+
+    for (name, data) in &rebucketed.reports {
+        if let Err(e) = std::fs::write(output_dir.join(name), data) {
+            log::error!("Failed to write report {}: {}", shlex::quote(name), e);
+            return 1;
+        }
+    }
+
+    if let Err(e) = write_saved_results(&results_path, &rebucketed.results) {
+        log::error!("Failed to save results: {}", e);
+        return 1;
+    }
+
+    let command_line = &rebucketed.results[0].envelope.command_line;
+    report::index::write_bucket_index(&rebucketed.buckets, command_line, Some(output_dir));
+
+    let report_formats = &retention.policy(CrashSeverity::unknown).report_formats;
+
+    if report_formats.contains(&ReportOutputFormat::csv) {
+        report::csv::write_csv_summaries(&rebucketed.buckets, &rebucketed.summaries, Some(output_dir));
+    }
+
+    if report_formats.contains(&ReportOutputFormat::sql) {
+        report::sql::write_results_sql(&rebucketed.buckets, &rebucketed.summaries, Some(output_dir));
+    }
+
+    let map_path = output_dir.join(REBUCKET_MAP_FILE);
+
+    if let Err(e) = std::fs::write(&map_path, serde_json::to_string_pretty(&rebucketed.map).unwrap()) {
+        log::error!("Failed to write the bucket mapping: {}", e);
+        return 1;
+    }
+
+    log::info!("Re-bucketed {} crashes from {} into {} buckets using the {} strategy",
+        total, rebucketed.map.buckets.len(), rebucketed.buckets.len(), bucket_strategy);
+    log::info!("Wrote old to new bucket mapping to {}", shlex::quote(&map_path.to_string_lossy()));
+
+    0
+}
+
+pub fn read_saved_results(path: &Path) -> Result<Vec<SavedResult>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut saved = vec![];
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        saved.push(report::schema::read_saved_result(&line).map_err(|e| format!("line {}: {}", i + 1, e))?);
+    }
+
+    Ok(saved)
+}
+
+fn write_saved_results(path: &Path, results: &[SavedResult]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    for saved in results {
+        report::write_saved_result(&mut writer, saved)?;
+    }
+
+    writer.flush()
+}
+
+/// Assign saved crashes to new buckets, in their original order, and render the reports of each
+/// new bucket
+fn rebucket_results(
+    saved: Vec<SavedResult>,
+    bucket_pass: BucketPass,
+    classify_pass: ClassifyPass,
+    retention: &RetentionRules,
+    text_template: Option<&TextTemplate>,
+) -> Result<Rebucketed, String> {
+    let bucket_strategy = bucket_pass.strategy;
+    let pipeline = AnalysisPipeline::new(bucket_pass, classify_pass, &[]);
+
+    let mut rebucketed = Rebucketed {
+        results: vec![],
+        buckets: HashMap::new(),
+        summaries: vec![],
+        reports: vec![],
+        map: RebucketMap {
+            strategy: bucket_strategy,
+            buckets: BTreeMap::new(),
+        },
+    };
+
+    for (crash_id, mut result) in saved.into_iter().enumerate() {
+        let mut report_options = result.envelope.report_options.clone();
+        report_options.show_child_output = retention.any_child_output();
+
+        let CrashAnalysis {
+            enriched: etriage,
+            bucket: bucket_info,
+            severity,
+        } = pipeline.run(&result.envelope.testcase, &result.triage, &report_options)
+            .map_err(|e| format!("{}: {}", shlex::quote(&result.envelope.testcase), e))?;
+
+        let bucket = bucket::bucket_id(&bucket_info, crash_id);
+
+        rebucketed.map.buckets.entry(result.bucket.to_string())
+            .or_default()
+            .insert(bucket.to_string());
+
+        rebucketed.summaries.push(TestcaseSummary::new(&result.envelope, &bucket, severity, &etriage));
+
+        result.bucket = bucket.to_string();
+        result.envelope.bucket = bucket_info;
+        result.reports = vec![];
+
+        if let Some(entry) = rebucketed.buckets.get_mut(&bucket) {
+            entry.testcases.push(result.envelope.testcase.to_string());
+        } else {
+            let mut entry = BucketEntry {
+                bucket: bucket.to_string(),
+                summary: etriage.summary.to_string(),
+                severity,
+                testcases: vec![result.envelope.testcase.to_string()],
+                reports: vec![],
+                reproducibility: result.envelope.reproducibility,
+            };
+
+            let filename = report::report_filename_stem(&etriage, &bucket);
+
+            for report in report::render_reports(retention.policy(entry.severity), entry.severity, etriage, &result.triage, &result.envelope, text_template) {
+                let report_filename = format!("{}.{}", filename, report.extension);
+                entry.reports.push(report_filename.to_string());
+                rebucketed.reports.push((report_filename, report.data));
+            }
+
+            result.reports = entry.reports.to_vec();
+            rebucketed.buckets.insert(bucket, entry);
+        }
+
+        rebucketed.results.push(result);
+    }
+
+    Ok(rebucketed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use afltriage::ReportEnvelope;
+    use crate::fixtures::{load_fixture, load_test_triage};
+
+    fn load_saved_result(bucket: &str, testcase: &str) -> SavedResult {
+        let mut envelope: ReportEnvelope = load_fixture("asan_stack_bof.json");
+        envelope.testcase = testcase.into();
+
+        SavedResult {
+            bucket: bucket.into(),
+            reports: vec![format!("afltriage_{}.txt", bucket)],
+            envelope,
+            triage: load_test_triage(),
+        }
+    }
+
+    #[test]
+    fn test_rebucket_results() {
+        let saved = vec![
+            load_saved_result("old_a", "id:000000"),
+            load_saved_result("old_b", "id:000001"),
+            load_saved_result("old_b", "id:000002"),
+        ];
+
+        let retention = RetentionRules::new(RetentionPolicy {
+            report_formats: vec![ReportOutputFormat::text, ReportOutputFormat::json],
+            child_output: false,
+        });
+
+        // Two buckets merge into one
+        let rebucketed = rebucket_results(saved, BucketPass::new(CrashBucketStrategy::first_function_name), ClassifyPass::default(), &retention, None).unwrap();
+
+        assert_eq!(rebucketed.buckets.len(), 1);
+        let bucket = rebucketed.buckets.keys().next().unwrap().to_string();
+        let new_buckets: BTreeSet<String> = vec![bucket.to_string()].into_iter().collect();
+
+        assert_eq!(rebucketed.map.buckets.len(), 2);
+        assert_eq!(rebucketed.map.buckets["old_a"], new_buckets);
+        assert_eq!(rebucketed.map.buckets["old_b"], new_buckets);
+
+        assert_eq!(rebucketed.reports.len(), 2);
+        assert_eq!(rebucketed.results[0].reports.len(), 2);
+        assert!(rebucketed.results[1].reports.is_empty());
+        assert!(rebucketed.results.iter().all(|r| r.bucket == bucket));
+        assert_eq!(rebucketed.buckets[&bucket].testcases, vec!["id:000000", "id:000001", "id:000002"]);
+
+        // And split apart again
+        let rebucketed = rebucket_results(rebucketed.results, BucketPass::new(CrashBucketStrategy::none), ClassifyPass::default(), &retention, None).unwrap();
+
+        assert_eq!(rebucketed.buckets.len(), 3);
+        assert_eq!(rebucketed.map.buckets[&bucket].len(), 3);
+        assert!(rebucketed.map.buckets[&bucket].contains("CID_2"));
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Deciding whether a memory fault was a read, a write, or an instruction fetch.
+//!
+//! Linux does not say which kind of access faulted in the signal information, so the access is
+//! taken from the sanitizer report when there is one, and otherwise decoded from the faulting
+//! instruction as disassembled by GDB (in Intel syntax on x86). A fault on the address the program
+//! counter points to is an instruction fetch. Instructions that both read and write memory through
+//! different operands, like `movs`, are not decoded, as the faulting operand cannot be told from
+//! the instruction alone.
+use serde::{Deserialize, Serialize};
+use strum::IntoStaticStr;
+
+/// The kind of memory access that faulted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoStaticStr, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum FaultAccess {
+    read,
+    write,
+    execute,
+}
+
+impl FaultAccess {
+    pub fn as_str(&self) -> &'static str {
+        self.into()
+    }
+}
+
+/// Instruction prefixes GDB prints before the mnemonic
+const PREFIXES: [&str; 10] = ["rep", "repe", "repz", "repne", "repnz", "lock", "notrack", "bnd", "data16", "addr32"];
+
+/// x86 instructions that only read a memory destination operand
+const READ_ONLY_DESTINATION: [&str; 14] = ["cmp", "test", "bt", "ucomiss", "ucomisd", "comiss", "comisd", "ptest",
+    "mul", "imul", "div", "idiv", "call", "jmp"];
+
+/// x87 instructions that write their memory operand
+const X87_STORES: [&str; 7] = ["fst", "fist", "fnst", "fbstp", "fsave", "fnsave", "fxsave"];
+
+/// Split operands at commas outside of brackets
+fn split_operands(operands: &str) -> Vec<&str> {
+    let mut split = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in operands.char_indices() {
+        match c {
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(operands[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+
+    let last = operands[start..].trim();
+
+    if !last.is_empty() {
+        split.push(last);
+    }
+
+    split
+}
+
+/// Decode the memory access of a disassembled instruction, like `mov DWORD PTR [rax],0x1`
+pub fn decode_instruction_access(insn: &str) -> Option<FaultAccess> {
+    // Drop GDB's comments, like "# 0x4010 <global>"
+    let insn = insn.split('#').next().unwrap_or_default();
+    let mut words = insn.split_whitespace().skip_while(|w| PREFIXES.contains(w));
+    let mnemonic = words.next()?.to_lowercase();
+    let operands: String = words.collect::<Vec<&str>>().join(" ");
+    let operands = split_operands(&operands);
+
+    let memory: Vec<usize> = operands.iter().enumerate()
+        .filter(|(_, op)| op.contains('['))
+        .map(|(i, _)| i)
+        .collect();
+
+    // String instructions address memory implicitly in some disassemblers
+    if mnemonic.starts_with("stos") {
+        return Some(FaultAccess::write);
+    } else if ["lods", "scas", "cmps"].iter().any(|m| mnemonic.starts_with(m)) {
+        return Some(FaultAccess::read);
+    }
+
+    // Implicit stack accesses
+    if memory.is_empty() {
+        return match mnemonic.as_str() {
+            "push" | "call" => Some(FaultAccess::write),
+            "pop" | "ret" | "leave" => Some(FaultAccess::read),
+            _ => None,
+        };
+    }
+
+    if memory.len() > 1 || mnemonic == "lea" || mnemonic.starts_with("nop") || mnemonic.starts_with("prefetch") {
+        return None;
+    }
+
+    // ARM and AArch64 loads and stores, like "ldr x0, [x1]" and "stp x29, x30, [sp, #-16]!"
+    if !operands.iter().any(|op| op.contains("PTR")) {
+        if mnemonic.starts_with("ld") {
+            return Some(FaultAccess::read);
+        } else if mnemonic.starts_with("st") {
+            return Some(FaultAccess::write);
+        }
+    }
+
+    if mnemonic.starts_with('f') {
+        return if X87_STORES.iter().any(|m| mnemonic.starts_with(m)) {
+            Some(FaultAccess::write)
+        } else {
+            Some(FaultAccess::read)
+        };
+    }
+
+    // Intel syntax puts the destination first
+    if memory[0] == 0 && !READ_ONLY_DESTINATION.contains(&mnemonic.as_str()) && mnemonic != "push" {
+        Some(FaultAccess::write)
+    } else {
+        Some(FaultAccess::read)
+    }
+}
+
+/// The access that caused a fault. `operation` is the access reported by a sanitizer, if any, and
+/// `pc` the address of the faulting instruction `insn`
+pub fn detect_fault_access(
+    signal_name: &str,
+    faulting_address: Option<u64>,
+    pc: Option<u64>,
+    insn: Option<&str>,
+    operation: Option<&str>,
+) -> Option<FaultAccess> {
+    match operation {
+        Some("READ") => return Some(FaultAccess::read),
+        Some("WRITE") => return Some(FaultAccess::write),
+        _ => (),
+    }
+
+    if signal_name != "SIGSEGV" && signal_name != "SIGBUS" {
+        return None;
+    }
+
+    if faulting_address.is_some() && faulting_address == pc {
+        return Some(FaultAccess::execute);
+    }
+
+    insn.and_then(decode_instruction_access)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fault_access() {
+        let decode = |insn| decode_instruction_access(insn).map(|a| a.as_str());
+
+        assert_eq!(decode("mov    rcx,QWORD PTR [rsp+0x108]"), Some("read"));
+        assert_eq!(decode("mov    DWORD PTR [rax],0x1"), Some("write"));
+        assert_eq!(decode("add    DWORD PTR [rax+rbx*4],edx"), Some("write"));
+        assert_eq!(decode("cmp    BYTE PTR [rdi],0x0"), Some("read"));
+        assert_eq!(decode("movzx  eax,BYTE PTR [rax]    # 0x4010 <g>"), Some("read"));
+        assert_eq!(decode("rep stos QWORD PTR es:[rdi],rax"), Some("write"));
+        assert_eq!(decode("movs   BYTE PTR es:[rdi],BYTE PTR ds:[rsi]"), None);
+        assert_eq!(decode("vmovdqu YMMWORD PTR [rdi+0x20],ymm1"), Some("write"));
+        assert_eq!(decode("fld    QWORD PTR [rax]"), Some("read"));
+        assert_eq!(decode("fstp   QWORD PTR [rax]"), Some("write"));
+        assert_eq!(decode("push   rbp"), Some("write"));
+        assert_eq!(decode("call   QWORD PTR [rax+0x8]"), Some("read"));
+        assert_eq!(decode("lea    rax,[rip+0x2ee5]"), None);
+        assert_eq!(decode("ldr x0, [x1, #8]"), Some("read"));
+        assert_eq!(decode("stp x29, x30, [sp, #-16]!"), Some("write"));
+        assert_eq!(decode("xor    eax,eax"), None);
+
+        assert_eq!(detect_fault_access("SIGSEGV", Some(0x41414141), Some(0x41414141), None, None), Some(FaultAccess::execute));
+        assert_eq!(detect_fault_access("SIGSEGV", Some(0), Some(0x401000), Some("mov DWORD PTR [rax],0x1"), None),
+            Some(FaultAccess::write));
+        assert_eq!(detect_fault_access("SIGABRT", None, Some(0x401000), Some("mov DWORD PTR [rax],0x1"), None), None);
+        assert_eq!(detect_fault_access("SIGABRT", None, None, None, Some("READ")), Some(FaultAccess::read));
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Extracting failed assertions.
+//!
+//! A failed `assert()` ends in `abort()`, and every failed assertion has the same stack through
+//! the C library down to `raise()`. The assertion itself is more useful: its expression, file,
+//! and line are taken from the message the C library prints, e.g. glibc's
+//! `prog: test.c:20: main: Assertion `x > 0' failed.` or musl's
+//! `Assertion failed: x > 0 (test.c: main: 20)`. When the target output was lost, they are taken
+//! from the arguments of the C library's assertion handler in the backtrace instead, which needs
+//! the C library's debugging information. Bucketing hashes the [AssertionFailure] in place of
+//! the stack, so one assertion reached through different paths is one bucket.
+use crate::debugger::gdb::GdbVariable;
+use crate::report::enriched::EnrichedFrameInfo;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    static ref R_GLIBC_ASSERTION: Regex = Regex::new(
+        r#"(?m)^[^\r\n]*?: (?P<file>[^\r\n:]+):(?P<line>[0-9]+): (?P<function>[^\r\n]+?): Assertion [`'](?P<expr>[^\r\n]*)' failed\.\r?$"#
+    ).unwrap();
+    static ref R_MUSL_ASSERTION: Regex = Regex::new(
+        r#"(?m)^Assertion failed: (?P<expr>[^\r\n]*) \((?P<file>[^\r\n:]+): (?P<function>[^\r\n]+?): (?P<line>[0-9]+)\)\r?$"#
+    ).unwrap();
+    /// The string of a `char *` argument, as GDB prints it
+    static ref R_GDB_STRING: Regex = Regex::new(r#""(?P<s>(\\.|[^"\\])*)""#).unwrap();
+}
+
+/// The C library functions that report a failed assertion: glibc, Android's bionic, and macOS
+const ASSERTION_HANDLERS: [&str; 4] = ["__assert_fail", "__assert_fail_base", "__assert2", "__assert_rtn"];
+
+/// A failed assertion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssertionFailure {
+    /// The asserted expression, like `x > 0`
+    pub expression: String,
+    pub file: String,
+    pub line: u64,
+    /// The function containing the assertion
+    pub function: String,
+}
+
+impl AssertionFailure {
+    /// The signature hashed by bucketing
+    pub fn signature(&self) -> String {
+        format!("assert:{}:{}:{}", self.file, self.line, self.expression)
+    }
+}
+
+/// Whether `function` is a C library function that reports a failed assertion. The frame with the
+/// assertion is the one calling it
+pub fn is_assertion_handler(function: &str) -> bool {
+    ASSERTION_HANDLERS.contains(&function.trim_start_matches("__GI_"))
+}
+
+/// Extract the last failed assertion from the target's output
+pub fn assertion_extract(output: &str) -> Option<AssertionFailure> {
+    R_GLIBC_ASSERTION.captures_iter(output)
+        .chain(R_MUSL_ASSERTION.captures_iter(output))
+        .max_by_key(|c| c.get(0).unwrap().start())
+        .and_then(|c| Some(AssertionFailure {
+            expression: c["expr"].to_string(),
+            file: c["file"].to_string(),
+            line: c["line"].parse().ok()?,
+            function: c["function"].to_string(),
+        }))
+}
+
+fn string_arg(args: &[std::rc::Rc<GdbVariable>], name: &str) -> Option<String> {
+    let value = &args.iter().find(|a| a.name == name)?.value;
+    let s = &R_GDB_STRING.captures(value)?["s"];
+    Some(s.replace("\\\"", "\"").replace("\\\\", "\\"))
+}
+
+/// Extract a failed assertion from the arguments of the assertion handler in `frames`
+pub fn assertion_from_frames(frames: &[EnrichedFrameInfo]) -> Option<AssertionFailure> {
+    let symbol = frames.iter()
+        .filter_map(|fr| fr.symbol.as_ref())
+        .find(|s| s.function_name.as_deref().map(is_assertion_handler).unwrap_or(false))?;
+    let args = symbol.args.as_ref()?;
+
+    // glibc names them assertion, file, line, and function. bionic names the expression failed_expression
+    let expression = string_arg(args, "assertion").or_else(|| string_arg(args, "failed_expression"))?;
+    let line = args.iter().find(|a| a.name == "line")?.value.parse().ok()?;
+
+    Some(AssertionFailure {
+        expression,
+        file: string_arg(args, "file").unwrap_or_default(),
+        line,
+        function: string_arg(args, "function").unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debugger::gdb::GdbSymbol;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_assertion_extract() {
+        let expected = AssertionFailure {
+            expression: "len < sizeof(buf)".into(),
+            file: "src/parse.c".into(),
+            line: 120,
+            function: "parse_header".into(),
+        };
+
+        let glibc = "reading input\n./target: src/parse.c:120: parse_header: Assertion `len < sizeof(buf)' failed.\n";
+        assert_eq!(assertion_extract(glibc), Some(expected.clone()));
+        assert_eq!(expected.signature(), "assert:src/parse.c:120:len < sizeof(buf)");
+
+        let musl = "Assertion failed: len < sizeof(buf) (src/parse.c: parse_header: 120)\n";
+        assert_eq!(assertion_extract(musl), Some(expected.clone()));
+        assert_eq!(assertion_extract("Aborted\n"), None);
+
+        let arg = |name: &str, value: &str| Rc::new(GdbVariable {
+            r#type: "const char *".into(),
+            name: name.into(),
+            value: value.into(),
+        });
+        let symbol = GdbSymbol {
+            function_name: Some("__GI___assert_fail".into()),
+            args: Some(vec![
+                arg("assertion", "0x402010 \"len < sizeof(buf)\""),
+                arg("file", "0x402004 \"src/parse.c\""),
+                arg("line", "120"),
+                arg("function", "0x402030 <__PRETTY_FUNCTION__.0> \"parse_header\""),
+            ]),
+            ..Default::default()
+        };
+        let frame: EnrichedFrameInfo = serde_json::from_value(serde_json::json!({
+            "summary": "", "address": {"r": 0x401000, "f": "0x401000", "s": 64},
+            "relative_address": {"r": 0x1000, "f": "0x1000", "s": 64},
+            "module": "libc.so.6", "module_address": "libc.so.6+0x1000",
+        })).unwrap();
+        let frame = EnrichedFrameInfo { symbol: Some(Rc::new(symbol)), ..frame };
+
+        assert_eq!(assertion_from_frames(&[frame]), Some(expected));
+    }
+}
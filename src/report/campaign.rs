@@ -0,0 +1,437 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! A top-level summary of a triage campaign.
+//!
+//! Where the index lists every bucket, the summary describes the run as a whole: how many inputs
+//! were triaged and how they ended, how the crashes break down by signal, classification, and
+//! [CrashSeverity], which functions crash most often, and why triage failed. It is written as text
+//! and JSON at the end of every run, and is the index page of the `html` report, which links each
+//! bucket to its reports.
+use crate::report::access::FaultAccess;
+use crate::report::enriched::{EnrichedTriageInfo, FaultAddressKind, MemoryRegionKind};
+use crate::report::index::{sort_bucket_entries, BucketEntry};
+use crate::report::severity::CrashSeverity;
+use crate::util::shell_join;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+/// The text summary file in an output directory
+pub const SUMMARY_TEXT_FILE: &str = "afltriage_summary.txt";
+/// The JSON summary file in an output directory
+pub const SUMMARY_JSON_FILE: &str = "afltriage_summary.json";
+/// The index page of the HTML report in an output directory
+pub const HTML_INDEX_FILE: &str = "afltriage_index.html";
+
+/// How many of the most crashing functions are listed
+const TOP_FUNCTIONS: usize = 10;
+
+/// The crashes and unique buckets with something in common
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Category {
+    pub name: String,
+    pub crashes: usize,
+    pub buckets: usize,
+}
+
+/// An error triage failed with, and how often
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Failure {
+    pub error: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    crashes: usize,
+    buckets: HashSet<String>,
+}
+
+impl Counter {
+    fn add(&mut self, bucket: &str) {
+        self.crashes += 1;
+        self.buckets.insert(bucket.to_string());
+    }
+
+    fn category(&self, name: &str) -> Category {
+        Category { name: name.to_string(), crashes: self.crashes, buckets: self.buckets.len() }
+    }
+}
+
+/// Crash counts collected during triage
+#[derive(Debug, Default)]
+pub struct CrashBreakdown {
+    signals: HashMap<String, Counter>,
+    classifications: HashMap<String, Counter>,
+    severities: BTreeMap<CrashSeverity, Counter>,
+    functions: HashMap<String, Counter>,
+}
+
+/// What kind of crash this is: the sanitizer's reason, a hang, or the kind of fault
+pub fn crash_classification(einfo: &EnrichedTriageInfo) -> String {
+    if einfo.hang == Some(true) {
+        return "hang".into();
+    }
+
+    if let Some(san) = einfo.sanitizer_reports.iter().flatten().next() {
+        // Sanitizers report signals too, which are better classified by the stop information
+        if !["SEGV", "FPE", "ILL", "BUS", "ABRT"].contains(&san.stop_reason.as_str()) {
+            return format!("{} {}", san.name_prefer_short(), san.stop_reason);
+        }
+    }
+
+    if einfo.fault_handler.is_some() {
+        return "fault-handler".into();
+    }
+
+    if einfo.assertion.is_some() {
+        return "assertion-failure".into();
+    }
+
+    if let Some(error) = &einfo.go_fatal_error {
+        return if error.kind == "panic" { "go-panic" } else { "go-fatal-error" }.into();
+    }
+
+    if einfo.panicked == Some(true) {
+        return "panic".into();
+    }
+
+    if let Some(exception) = &einfo.uncaught_exception {
+        return if exception.thrown() { "uncaught-exception" } else { "terminate" }.into();
+    }
+
+    if einfo.stack_exhausted == Some(true) {
+        return "stack-overflow".into();
+    }
+
+    let stop_info = &einfo.stop_info;
+
+    match stop_info.signal_name.as_str() {
+        "SIGSEGV" | "SIGBUS" => {
+            let pc = einfo.faulting_thread.frames.first().map(|f| f.address.r);
+
+            let kind = match (&stop_info.faulting_address, &stop_info.faulting_address_region) {
+                (Some(addr), _) if Some(addr.r) == pc => return "bad-pc".into(),
+                (Some(_), _) if stop_info.is_null_dereference() => return "null-dereference".into(),
+                (Some(_), _) if stop_info.fault_address_kind == Some(FaultAddressKind::non_canonical) => "wild",
+                (Some(_), Some(region)) if region.kind == MemoryRegionKind::unmapped => "wild",
+                (Some(_), Some(_)) => "permission-violation",
+                _ => "invalid",
+            };
+
+            // A wild write is a very different bug than a wild read
+            match (kind, stop_info.fault_access.filter(|a| *a != FaultAccess::execute)) {
+                ("permission-violation", Some(access)) => format!("{}-permission-violation", access.as_str()),
+                ("permission-violation", None) => kind.into(),
+                (_, Some(access)) => format!("{}-{}", kind, access.as_str()),
+                (_, None) => format!("{}-access", kind),
+            }
+        }
+        "SIGABRT" => "abort".into(),
+        "SIGILL" => "illegal-instruction".into(),
+        "SIGFPE" => "arithmetic-error".into(),
+        "SIGTRAP" => "trap".into(),
+        signal => signal.to_string(),
+    }
+}
+
+impl CrashBreakdown {
+    /// Count a crash that landed in `bucket`
+    pub fn add_crash(&mut self, bucket: &str, einfo: &EnrichedTriageInfo, severity: CrashSeverity) {
+        self.signals.entry(einfo.stop_info.signal_name.to_string()).or_default().add(bucket);
+        self.classifications.entry(crash_classification(einfo)).or_default().add(bucket);
+        self.severities.entry(severity).or_default().add(bucket);
+        self.functions.entry(einfo.faulting_function.to_string()).or_default().add(bucket);
+    }
+}
+
+/// Categories with the most crashes first
+fn sorted_categories(counters: &HashMap<String, Counter>) -> Vec<Category> {
+    let mut categories: Vec<Category> = counters.iter().map(|(name, c)| c.category(name)).collect();
+    categories.sort_by(|a, b| b.crashes.cmp(&a.crashes).then(a.name.cmp(&b.name)));
+    categories
+}
+
+/// The summary of a triage run
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct CampaignSummary {
+    pub command_line: Vec<String>,
+    /// Every input given to triage
+    pub testcases: usize,
+    pub crashes: usize,
+    pub unique_buckets: usize,
+    pub hangs: usize,
+    pub no_crash: usize,
+    pub timeouts: usize,
+    pub errors: usize,
+    pub environment_errors: usize,
+    pub by_signal: Vec<Category>,
+    pub by_classification: Vec<Category>,
+    /// Most severe first
+    pub by_severity: Vec<Category>,
+    pub top_functions: Vec<Category>,
+    /// Unique errors, most frequent first
+    pub failures: Vec<Failure>,
+}
+
+impl CampaignSummary {
+    /// A summary with the crash breakdown of `breakdown`. Counts are filled in by the caller
+    pub fn new(command_line: &[String], breakdown: &CrashBreakdown, failures: Vec<(String, usize)>) -> Self {
+        let mut failures: Vec<Failure> = failures.into_iter()
+            .map(|(error, count)| Failure { error, count })
+            .collect();
+        failures.sort_by(|a, b| b.count.cmp(&a.count).then(a.error.cmp(&b.error)));
+
+        let mut top_functions = sorted_categories(&breakdown.functions);
+        top_functions.truncate(TOP_FUNCTIONS);
+
+        CampaignSummary {
+            command_line: command_line.to_vec(),
+            by_signal: sorted_categories(&breakdown.signals),
+            by_classification: sorted_categories(&breakdown.classifications),
+            by_severity: breakdown.severities.iter().rev().map(|(s, c)| c.category(s.as_str())).collect(),
+            top_functions,
+            failures,
+            ..Default::default()
+        }
+    }
+}
+
+fn plural(count: usize, singular: &str, plural: &str) -> String {
+    format!("{} {}", count, if count == 1 { singular } else { plural })
+}
+
+fn format_text_categories(title: &str, categories: &[Category]) -> String {
+    if categories.is_empty() {
+        return String::new();
+    }
+
+    let width = categories.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    let mut text = format!("\n{}:\n", title);
+
+    for c in categories {
+        text += &format!("  {:<width$}  {} in {}\n", c.name, plural(c.crashes, "crash", "crashes"),
+            plural(c.buckets, "bucket", "buckets"), width = width);
+    }
+
+    text
+}
+
+/// Render a text summary
+pub fn format_text_summary(summary: &CampaignSummary) -> String {
+    let mut text = format!("Command line: {}\n", shell_join(&summary.command_line));
+    text += &format!("Testcases: {} (crashes: {}, no crash: {}, timeouts: {}, errors: {})\n",
+        summary.testcases, summary.crashes, summary.no_crash, summary.timeouts, summary.errors);
+    text += &format!("Unique crash buckets: {}\n", summary.unique_buckets);
+
+    if summary.hangs > 0 {
+        text += &format!("Hangs: {}\n", summary.hangs);
+    }
+
+    if summary.environment_errors > 0 {
+        text += &format!("Environment errors: {}\n", summary.environment_errors);
+    }
+
+    text += &format_text_categories("Crashes by signal", &summary.by_signal);
+    text += &format_text_categories("Crashes by classification", &summary.by_classification);
+    text += &format_text_categories("Crashes by severity", &summary.by_severity);
+    text += &format_text_categories("Top crashing functions", &summary.top_functions);
+
+    if !summary.failures.is_empty() {
+        text += "\nFailures:\n";
+
+        for f in &summary.failures {
+            text += &format!("  {} (seen {} time(s))\n", f.error, f.count);
+        }
+    }
+
+    text
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn format_html_categories(title: &str, categories: &[Category]) -> String {
+    if categories.is_empty() {
+        return String::new();
+    }
+
+    let mut html = format!("<h2>{}</h2>\n<table>\n<tr><th></th><th>Crashes</th><th>Buckets</th></tr>\n", title);
+
+    for c in categories {
+        html += &format!("<tr><td><code>{}</code></td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&c.name), c.crashes, c.buckets);
+    }
+
+    html + "</table>\n"
+}
+
+/// Render the index page of the HTML report: the summary, then every bucket in `entries` (which
+/// should already be sorted) with links to its reports
+pub fn format_html_index(summary: &CampaignSummary, entries: &[&BucketEntry]) -> String {
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+        <title>AFLTriage summary</title>\n<style>\n\
+        body { font-family: sans-serif; margin: 2em; }\n\
+        table { border-collapse: collapse; margin-bottom: 1em; }\n\
+        th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }\n\
+        </style>\n</head>\n<body>\n<h1>AFLTriage summary</h1>\n");
+
+    html += &format!("<p>Command line: <code>{}</code></p>\n", escape_html(&shell_join(&summary.command_line)));
+    html += "<table>\n";
+
+    for (name, count) in [
+        ("Testcases", summary.testcases),
+        ("Crashes", summary.crashes),
+        ("Unique crash buckets", summary.unique_buckets),
+        ("Hangs", summary.hangs),
+        ("No crash", summary.no_crash),
+        ("Timeouts", summary.timeouts),
+        ("Errors", summary.errors),
+        ("Environment errors", summary.environment_errors),
+    ].iter() {
+        html += &format!("<tr><th>{}</th><td>{}</td></tr>\n", name, count);
+    }
+
+    html += "</table>\n";
+    html += &format_html_categories("Crashes by signal", &summary.by_signal);
+    html += &format_html_categories("Crashes by classification", &summary.by_classification);
+    html += &format_html_categories("Crashes by severity", &summary.by_severity);
+    html += &format_html_categories("Top crashing functions", &summary.top_functions);
+
+    if !entries.is_empty() {
+        html += "<h2>Buckets</h2>\n<table>\n<tr><th>Severity</th><th>Crashes</th><th>Summary</th><th>Reports</th></tr>\n";
+
+        for entry in entries {
+            let reports: Vec<String> = entry.reports.iter()
+                .map(|r| format!("<a href=\"{}\">{}</a>", escape_html(r), escape_html(r)))
+                .collect();
+
+            html += &format!("<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                entry.severity.as_str(), entry.testcases.len(), escape_html(&entry.summary), reports.join("<br>"));
+        }
+
+        html += "</table>\n";
+    }
+
+    if !summary.failures.is_empty() {
+        html += "<h2>Failures</h2>\n<table>\n<tr><th>Error</th><th>Count</th></tr>\n";
+
+        for f in &summary.failures {
+            html += &format!("<tr><td><code>{}</code></td><td>{}</td></tr>\n", escape_html(&f.error), f.count);
+        }
+
+        html += "</table>\n";
+    }
+
+    html + "</body>\n</html>\n"
+}
+
+/// Write the summary to `output_dir` as text and JSON, and the HTML index page if `html` is set.
+/// Without an output directory, the text summary is written to the terminal
+pub fn write_campaign_summary(summary: &CampaignSummary, buckets: &HashMap<String, BucketEntry>,
+    output_dir: Option<&Path>, html: bool) {
+    let text = format_text_summary(summary);
+
+    let output_dir = match output_dir {
+        Some(output_dir) => output_dir,
+        None => {
+            println!("--- SUMMARY REPORT BEGIN ---\n{}--- SUMMARY REPORT END ---", text);
+
+            if html {
+                log::warn!("Skipping the HTML report as there is no output directory");
+            }
+
+            return;
+        }
+    };
+
+    let mut files = vec![
+        (SUMMARY_TEXT_FILE, text),
+        (SUMMARY_JSON_FILE, serde_json::to_string_pretty(summary).unwrap()),
+    ];
+
+    if html {
+        let mut entries: Vec<&BucketEntry> = buckets.values().collect();
+        sort_bucket_entries(&mut entries);
+        files.push((HTML_INDEX_FILE, format_html_index(summary, &entries)));
+    }
+
+    for (filename, data) in files.iter() {
+        let path = output_dir.join(filename);
+
+        if let Err(e) = std::fs::write(&path, data) {
+            log::error!("Failed to write the campaign summary: {}", e);
+        } else {
+            log::info!("Wrote campaign summary to {}", shlex::quote(&path.to_string_lossy()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::enriched::enrich_triage_info;
+    use crate::report::severity::rate_crash_severity;
+    use crate::report::ReportOptions;
+    use crate::debugger::gdb::GdbTriageResult;
+    use crate::report::fixtures::load_test_triage;
+
+    #[test]
+    fn test_campaign_summary() {
+        let triage: GdbTriageResult = load_test_triage();
+        let report_options = ReportOptions { child_output_lines: 0, ..Default::default() };
+        let einfo = enrich_triage_info(&report_options, &triage).unwrap();
+        let severity = rate_crash_severity(&einfo);
+
+        let mut breakdown = CrashBreakdown::default();
+        breakdown.add_crash("a", &einfo, severity);
+        breakdown.add_crash("a", &einfo, severity);
+        breakdown.add_crash("b", &einfo, CrashSeverity::high);
+
+        let mut summary = CampaignSummary {
+            testcases: 5,
+            crashes: 3,
+            unique_buckets: 2,
+            no_crash: 1,
+            errors: 1,
+            ..CampaignSummary::new(&["./target".into(), "@@".into()], &breakdown,
+                vec![("ERROR_TIMEOUT <b>".into(), 1)])
+        };
+
+        let signal = &einfo.stop_info.signal_name;
+        assert_eq!(summary.by_signal, vec![Category { name: signal.to_string(), crashes: 3, buckets: 2 }]);
+        assert_eq!(summary.by_severity[0], Category { name: "high".into(), crashes: 1, buckets: 1 });
+        assert_eq!(summary.by_severity[1], Category { name: severity.as_str().into(), crashes: 2, buckets: 1 });
+        assert_eq!(summary.top_functions[0].name, einfo.faulting_function);
+        assert_eq!(summary.by_classification[0].name, "ASAN stack-buffer-overflow");
+
+        let text = format_text_summary(&summary);
+        assert!(text.starts_with("Command line: ./target @@\nTestcases: 5 (crashes: 3, no crash: 1, timeouts: 0, errors: 1)\n"));
+        assert!(text.contains(&format!("Crashes by signal:\n  {}  3 crashes in 2 buckets\n", signal)));
+        assert!(text.ends_with("Failures:\n  ERROR_TIMEOUT <b> (seen 1 time(s))\n"));
+
+        let entry = BucketEntry {
+            bucket: "a".into(),
+            summary: "CRASH detected in <main>".into(),
+            severity,
+            testcases: vec!["tc1".into(), "tc2".into()],
+            reports: vec!["afltriage_a.txt".into()],
+            reproducibility: None,
+        };
+        let html = format_html_index(&summary, &[&entry]);
+        assert!(html.contains("<a href=\"afltriage_a.txt\">afltriage_a.txt</a>"));
+        assert!(html.contains("CRASH detected in &lt;main&gt;"));
+        assert!(html.contains("ERROR_TIMEOUT &lt;b&gt;"));
+
+        summary.failures.clear();
+        let json: serde_json::Value = serde_json::from_str(&serde_json::to_string(&summary).unwrap()).unwrap();
+        assert_eq!(json["by_signal"][0]["buckets"], 2);
+        assert_eq!(json["testcases"], 5);
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Crash reports in the format of CASR.
+//!
+//! [CASR](https://github.com/ispras/casr) deduplicates and clusters crashes from its `.casrep`
+//! JSON reports, with `casr-cluster`. Exporting to its format lets teams that already run CASR
+//! tooling feed AFLTriage results into it. Only the fields AFLTriage knows are filled in, the rest
+//! are left empty.
+//!
+//! CASR parses the `Stacktrace` according to the report it came with. When the crash has an
+//! AddressSanitizer or MemorySanitizer report, the frames are written the way those sanitizers
+//! print them, and the report goes in `AsanReport`. Otherwise they are written the way GDB prints
+//! them. The `CrashSeverity` is derived from AFLTriage's own [CrashSeverity] rating, which is NOT
+//! an exploitability classification.
+use crate::report::enriched::{EnrichedFrameInfo, EnrichedTriageInfo};
+use crate::report::severity::CrashSeverity;
+use crate::report::ReportEnvelope;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The `CrashSeverity` of a CASR report
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CasrExecutionClass {
+    r#type: &'static str,
+    short_description: String,
+    description: String,
+    explanation: String,
+}
+
+/// A CASR crash report. Field names are those of CASR's `CrashReport`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CasrReport {
+    date: String,
+    executable_path: String,
+    proc_environ: Vec<String>,
+    proc_cmdline: String,
+    stdin: String,
+    crash_severity: CasrExecutionClass,
+    stacktrace: Vec<String>,
+    registers: BTreeMap<String, u64>,
+    disassembly: Vec<String>,
+    asan_report: Vec<String>,
+    crash_line: String,
+    source: Vec<String>,
+}
+
+/// Whether CASR expects the report of this sanitizer in `AsanReport`
+fn is_asan_like(sanitizer_short: &str) -> bool {
+    sanitizer_short == "ASAN" || sanitizer_short == "MSAN"
+}
+
+/// The CASR severity type for a [CrashSeverity]
+fn casr_severity_type(severity: CrashSeverity) -> &'static str {
+    match severity {
+        CrashSeverity::high => "EXPLOITABLE",
+        CrashSeverity::medium => "PROBABLY_EXPLOITABLE",
+        CrashSeverity::low | CrashSeverity::unknown => "NOT_EXPLOITABLE",
+    }
+}
+
+/// A frame the way a sanitizer prints it, like `#3 0x5122d8 in bad_read test.c:20`
+fn format_sanitizer_frame(i: usize, frame: &EnrichedFrameInfo) -> String {
+    let symbol = frame.symbol.as_ref();
+    let function = symbol.and_then(|s| s.function_name.as_deref());
+    let location = symbol.and_then(|s| match (&s.file, s.line) {
+        (Some(file), Some(line)) => Some(format!("{}:{}", file, line)),
+        _ => None,
+    });
+
+    match (function, location) {
+        (Some(function), Some(location)) => format!("#{} 0x{:x} in {} {}", i, frame.address.r, function, location),
+        (Some(function), None) => format!("#{} 0x{:x} in {} ({}+0x{:x})", i, frame.address.r, function, frame.module, frame.relative_address.r),
+        (None, _) => format!("#{} 0x{:x} ({}+0x{:x})", i, frame.address.r, frame.module, frame.relative_address.r),
+    }
+}
+
+/// A frame the way GDB prints it, like `#3  0x00000000005122d8 in bad_read () at test.c:20`
+fn format_gdb_frame(i: usize, frame: &EnrichedFrameInfo) -> String {
+    let symbol = frame.symbol.as_ref();
+    let function = symbol.and_then(|s| s.function_name.as_deref()).unwrap_or("??");
+    let location = match symbol.map(|s| (&s.file, s.line)) {
+        Some((Some(file), Some(line))) => format!("at {}:{}", file, line),
+        _ => format!("from {}", frame.module),
+    };
+
+    format!("#{}  {} in {} () {}", i, frame.address.f, function, location)
+}
+
+/// Format a crash as a CASR report, rated as `severity`
+pub fn format_casr_report(einfo: &EnrichedTriageInfo, envelope: &ReportEnvelope, severity: CrashSeverity) -> String {
+    let testcase = envelope.preprocessed_testcase.as_ref().unwrap_or(&envelope.testcase);
+    let args: Vec<&str> = envelope.command_line.iter().map(|s| s.as_str()).collect();
+    let from_stdin = !args.iter().any(|a| a.contains("@@"));
+
+    let sanitizer = einfo.sanitizer_reports.as_ref()
+        .and_then(|reports| reports.first())
+        .filter(|san| is_asan_like(&san.sanitizer_short));
+
+    let thread = &einfo.faulting_thread;
+    let stacktrace = thread.frames.iter().enumerate()
+        .map(|(i, frame)| match sanitizer {
+            Some(_) => format_sanitizer_frame(i, frame),
+            None => format_gdb_frame(i, frame),
+        })
+        .collect();
+
+    let culprit = thread.frames.get(einfo.faulting_frame_idx).and_then(|fr| fr.symbol.as_ref());
+    let crash_line = match culprit.map(|s| (&s.file, s.line)) {
+        Some((Some(file), Some(line))) => format!("{}:{}", file, line),
+        _ => String::new(),
+    };
+
+    let short_description = match sanitizer {
+        Some(san) => san.stop_reason.clone(),
+        None => einfo.stop_info.signal_name.clone(),
+    };
+
+    let report = CasrReport {
+        date: envelope.triaged_at.clone().unwrap_or_default(),
+        executable_path: args.first().map(|s| s.to_string()).unwrap_or_default(),
+        proc_environ: envelope.env.clone(),
+        proc_cmdline: crate::util::shell_join(&crate::util::expand_filepath_templates(&args, testcase)),
+        stdin: if from_stdin { testcase.to_string() } else { String::new() },
+        crash_severity: CasrExecutionClass {
+            r#type: casr_severity_type(severity),
+            short_description,
+            description: einfo.summary.clone(),
+            explanation: format!("AFLTriage rated this crash {} severity", severity.as_str()),
+        },
+        stacktrace,
+        registers: thread.registers.iter().flatten()
+            .map(|r| (r.name.clone(), r.value))
+            .collect(),
+        disassembly: thread.instruction_context.iter().flatten()
+            .map(|insn| format!("{}: {}", insn.address.f, insn.insn))
+            .collect(),
+        asan_report: sanitizer
+            .map(|san| san.body.lines().map(|l| l.to_string()).collect())
+            .unwrap_or_default(),
+        crash_line,
+        source: culprit.and_then(|s| s.callsite.clone()).unwrap_or_default(),
+    };
+
+    serde_json::to_string_pretty(&report).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::Value;
+    use crate::report::fixtures::load_test_envelope;
+
+    #[test]
+    fn test_casr_report() {
+        let (mut einfo, envelope): (EnrichedTriageInfo, ReportEnvelope) = load_test_envelope();
+
+        let report: Value = serde_json::from_str(&format_casr_report(&einfo, &envelope, CrashSeverity::high)).unwrap();
+
+        assert_eq!(report["ExecutablePath"], "./test");
+        assert_eq!(report["ProcCmdline"], "./test test.c");
+        assert_eq!(report["Stdin"], "");
+        assert_eq!(report["CrashSeverity"]["Type"], "EXPLOITABLE");
+        assert_eq!(report["CrashSeverity"]["ShortDescription"], "stack-buffer-overflow");
+        assert_eq!(report["CrashLine"], "test.c:20");
+        assert_eq!(report["Stacktrace"][0], "#0 0x7ffff6e3ffb7 in raise (/lib/x86_64-linux-gnu/libc-2.27.so+0x3efb7)");
+        assert_eq!(report["Stacktrace"][6], "#6 0x5122d8 in bad_read test.c:20");
+        assert!(report["AsanReport"][0].as_str().unwrap().starts_with("=="));
+        assert!(report["Registers"]["rip"].is_u64());
+
+        einfo.sanitizer_reports = None;
+        let report: Value = serde_json::from_str(&format_casr_report(&einfo, &envelope, CrashSeverity::unknown)).unwrap();
+
+        assert_eq!(report["CrashSeverity"]["Type"], "NOT_EXPLOITABLE");
+        assert_eq!(report["CrashSeverity"]["ShortDescription"], "SIGABRT");
+        assert_eq!(report["Stacktrace"][0], "#0  0x00007ffff6e3ffb7 in raise () from /lib/x86_64-linux-gnu/libc-2.27.so");
+        assert_eq!(report["Stacktrace"][6], "#6  0x00000000005122d8 in bad_read () at test.c:20");
+        assert_eq!(report["AsanReport"], serde_json::json!([]));
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! CSV summaries of a triage run for spreadsheet review.
+//!
+//! Two files are produced: [TESTCASES_CSV_FILE] with one row per crashing testcase and
+//! [BUCKETS_CSV_FILE] with one row per unique crash bucket.
+use crate::report::index::BucketEntry;
+use crate::report::summary::TestcaseSummary;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The per-testcase CSV file in an output directory
+pub const TESTCASES_CSV_FILE: &str = "afltriage_testcases.csv";
+/// The per-bucket CSV file in an output directory
+pub const BUCKETS_CSV_FILE: &str = "afltriage_buckets.csv";
+
+/// Quote a CSV field if needed (RFC 4180)
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub(crate) fn csv_line(fields: &[&str]) -> String {
+    let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+    fields.join(",") + "\n"
+}
+
+/// Render the testcase CSV
+pub fn format_testcases_csv(rows: &[TestcaseSummary]) -> String {
+    let mut csv = csv_line(&["testcase", "bucket", "severity", "signal", "faulting_address",
+        "faulting_function", "location", "summary", "triaged_at", "triage_duration_ms", "afl_op", "afl_time_ms"]);
+
+    for row in rows {
+        let duration = row.triage_duration_ms.map(|ms| ms.to_string()).unwrap_or_default();
+        let afl_time = row.afl_time_ms.map(|ms| ms.to_string()).unwrap_or_default();
+
+        csv += &csv_line(&[&row.testcase, &row.bucket, row.severity.as_str(), &row.signal,
+            &row.faulting_address, &row.faulting_function, &row.location, &row.summary,
+            &row.triaged_at, &duration, &row.afl_op, &afl_time]);
+    }
+
+    csv
+}
+
+/// Render the bucket CSV for `entries` (which should already be sorted). Crash details are taken
+/// from the row of each bucket's first testcase
+pub fn format_buckets_csv(entries: &[&BucketEntry], rows: &[TestcaseSummary]) -> String {
+    let rows_by_testcase: HashMap<&str, &TestcaseSummary> = rows.iter()
+        .map(|r| (r.testcase.as_str(), r))
+        .collect();
+
+    let mut csv = csv_line(&["bucket", "severity", "crashes", "signal", "faulting_function",
+        "location", "summary", "first_testcase", "reports"]);
+
+    for entry in entries {
+        let first = entry.testcases.first().map(String::as_str).unwrap_or("");
+        let row = rows_by_testcase.get(first);
+
+        csv += &csv_line(&[
+            &entry.bucket,
+            entry.severity.as_str(),
+            &entry.testcases.len().to_string(),
+            row.map(|r| r.signal.as_str()).unwrap_or(""),
+            row.map(|r| r.faulting_function.as_str()).unwrap_or(""),
+            row.map(|r| r.location.as_str()).unwrap_or(""),
+            &entry.summary,
+            first,
+            &entry.reports.join(" "),
+        ]);
+    }
+
+    csv
+}
+
+/// Write both CSV summaries to `output_dir`, or to the terminal if there is none
+pub fn write_csv_summaries(buckets: &HashMap<String, BucketEntry>, rows: &[TestcaseSummary], output_dir: Option<&Path>) {
+    let mut entries: Vec<&BucketEntry> = buckets.values().collect();
+    crate::report::index::sort_bucket_entries(&mut entries);
+
+    let summaries = [
+        (TESTCASES_CSV_FILE, "TESTCASES CSV", format_testcases_csv(rows)),
+        (BUCKETS_CSV_FILE, "BUCKETS CSV", format_buckets_csv(&entries, rows)),
+    ];
+
+    for (filename, name, csv) in summaries.iter() {
+        match output_dir {
+            Some(output_dir) => {
+                let path = output_dir.join(filename);
+
+                if let Err(e) = std::fs::write(&path, csv) {
+                    log::error!("Failed to write CSV summary: {}", e);
+                } else {
+                    log::info!("Wrote CSV summary to {}", shlex::quote(&path.to_string_lossy()));
+                }
+            }
+            None => println!("--- {} BEGIN ---\n{}--- {} END ---", name, csv, name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::severity::CrashSeverity;
+
+    #[test]
+    fn test_csv_format() {
+        let row = TestcaseSummary {
+            testcase: "crashes/id:000000,sig:11".into(),
+            bucket: "abc".into(),
+            severity: CrashSeverity::high,
+            signal: "SIGSEGV".into(),
+            faulting_address: "0x0000000000000000".into(),
+            faulting_function: "main".into(),
+            location: "test.c:20".into(),
+            summary: "A \"quoted\" summary".into(),
+            triaged_at: "2021-06-01T12:00:00Z".into(),
+            triage_duration_ms: Some(1500),
+            afl_op: "havoc".into(),
+            afl_time_ms: None,
+            frames: vec![],
+        };
+
+        let entry = BucketEntry {
+            bucket: "abc".into(),
+            summary: "A summary".into(),
+            severity: CrashSeverity::high,
+            testcases: vec![row.testcase.to_string(), "other".into()],
+            reports: vec!["a.txt".into(), "a.json".into()],
+            reproducibility: None,
+        };
+
+        let testcases = format_testcases_csv(std::slice::from_ref(&row));
+        assert_eq!(testcases.lines().nth(1).unwrap(),
+            "\"crashes/id:000000,sig:11\",abc,high,SIGSEGV,0x0000000000000000,main,test.c:20,\"A \"\"quoted\"\" summary\",2021-06-01T12:00:00Z,1500,havoc,");
+
+        let buckets = format_buckets_csv(&[&entry], &[row]);
+        assert_eq!(buckets.lines().nth(1).unwrap(),
+            "abc,high,2,SIGSEGV,main,test.c:20,A summary,\"crashes/id:000000,sig:11\",a.txt a.json");
+    }
+}
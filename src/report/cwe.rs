@@ -0,0 +1,108 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Mapping crash classifications to CWE identifiers.
+//!
+//! Vulnerability management tools key findings off the Common Weakness Enumeration, so JSON reports
+//! carry the [Cwe] that best describes a crash in `report.cwe`. The mapping is from the
+//! classification of [crash_classification] and, for out-of-bounds accesses, whether the sanitizer
+//! reported a read or a write. It describes the symptom AFLTriage observed, which may not be the
+//! root cause: a NULL pointer dereference can be the result of an earlier use after free.
+//! Crashes with no fitting CWE, like a plain `abort()`, have none.
+use crate::report::campaign::crash_classification;
+use crate::report::enriched::EnrichedTriageInfo;
+use serde::Serialize;
+
+/// A CWE entry
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Cwe {
+    /// The identifier, like `CWE-122`
+    pub id: &'static str,
+    pub name: &'static str,
+}
+
+const fn cwe(id: &'static str, name: &'static str) -> Option<Cwe> {
+    Some(Cwe { id, name })
+}
+
+/// The CWE of a crash, if one fits
+pub fn classify_cwe(einfo: &EnrichedTriageInfo) -> Option<Cwe> {
+    let classification = crash_classification(einfo);
+    // Sanitizer classifications are prefixed with the sanitizer, like "ASAN heap-buffer-overflow"
+    let kind = classification.rsplit(' ').next().unwrap_or_default();
+    let write = einfo.sanitizer_reports.iter().flatten().next()
+        .map(|s| s.operation == "WRITE")
+        .unwrap_or(false);
+
+    let out_of_bounds_read = cwe("CWE-125", "Out-of-bounds Read");
+
+    match kind {
+        "heap-buffer-overflow" | "heap-corruption" if write => cwe("CWE-122", "Heap-based Buffer Overflow"),
+        "stack-buffer-overflow" | "dynamic-stack-buffer-overflow" if write => cwe("CWE-121", "Stack-based Buffer Overflow"),
+        "global-buffer-overflow" | "container-overflow" | "use-after-poison" if write => cwe("CWE-787", "Out-of-bounds Write"),
+        "stack-buffer-underflow" if write => cwe("CWE-124", "Buffer Underwrite ('Buffer Underflow')"),
+        "stack-buffer-underflow" => cwe("CWE-127", "Buffer Under-read"),
+        "heap-buffer-overflow" | "stack-buffer-overflow" | "dynamic-stack-buffer-overflow" |
+            "global-buffer-overflow" | "container-overflow" | "use-after-poison" => out_of_bounds_read,
+        // glibc only notices overwritten memory
+        "stack-smashing" => cwe("CWE-121", "Stack-based Buffer Overflow"),
+        "fortify-buffer-overflow" => cwe("CWE-787", "Out-of-bounds Write"),
+        "heap-corruption" => cwe("CWE-122", "Heap-based Buffer Overflow"),
+        "heap-use-after-free" => cwe("CWE-416", "Use After Free"),
+        "stack-use-after-return" => cwe("CWE-562", "Return of Stack Variable Address"),
+        "stack-use-after-scope" => cwe("CWE-825", "Expired Pointer Dereference"),
+        "double-free" => cwe("CWE-415", "Double Free"),
+        "bad-free" => cwe("CWE-590", "Free of Memory not on the Heap"),
+        "alloc-dealloc-mismatch" => cwe("CWE-762", "Mismatched Memory Management Routines"),
+        "memory-leak" | "leak" => cwe("CWE-401", "Missing Release of Memory after Effective Lifetime"),
+        "stack-overflow" => cwe("CWE-674", "Uncontrolled Recursion"),
+        "calloc-overflow" => cwe("CWE-190", "Integer Overflow or Wraparound"),
+        "allocation-size-too-big" | "requested" | "out-of-memory" =>
+            cwe("CWE-789", "Memory Allocation with Excessive Size Value"),
+        "use-of-uninitialized-value" => cwe("CWE-457", "Use of Uninitialized Variable"),
+        "data-race" => cwe("CWE-362", "Concurrent Execution using Shared Resource with Improper Synchronization ('Race Condition')"),
+        "lock-order-inversion" => cwe("CWE-833", "Deadlock"),
+        "memcpy-param-overlap" => cwe("CWE-628", "Function Call with Incorrectly Specified Arguments"),
+        "null-dereference" => cwe("CWE-476", "NULL Pointer Dereference"),
+        "bad-pc" | "invalid-jump" => cwe("CWE-822", "Untrusted Pointer Dereference"),
+        "wild-write" | "invalid-write" | "write-permission-violation" => cwe("CWE-787", "Out-of-bounds Write"),
+        "wild-read" | "invalid-read" | "read-permission-violation" => out_of_bounds_read,
+        "wild-access" | "permission-violation" | "invalid-access" =>
+            cwe("CWE-119", "Improper Restriction of Operations within the Bounds of a Memory Buffer"),
+        "uncaught-exception" => cwe("CWE-248", "Uncaught Exception"),
+        "assertion-failure" => cwe("CWE-617", "Reachable Assertion"),
+        "arithmetic-error" => cwe("CWE-369", "Divide By Zero"),
+        "hang" => cwe("CWE-835", "Loop with Unreachable Exit Condition ('Infinite Loop')"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::load_test_report;
+
+    #[test]
+    fn test_classify_cwe() {
+        let mut einfo: EnrichedTriageInfo = load_test_report();
+
+        assert_eq!(classify_cwe(&einfo).unwrap().id, "CWE-125");
+
+        let san = &mut einfo.sanitizer_reports.as_mut().unwrap()[0];
+        san.operation = "WRITE".into();
+        assert_eq!(classify_cwe(&einfo).unwrap().id, "CWE-121");
+
+        let san = &mut einfo.sanitizer_reports.as_mut().unwrap()[0];
+        san.stop_reason = "heap-use-after-free".into();
+        assert_eq!(classify_cwe(&einfo), cwe("CWE-416", "Use After Free"));
+
+        einfo.hang = Some(true);
+        assert_eq!(classify_cwe(&einfo).unwrap().id, "CWE-835");
+
+        // A plain SIGABRT
+        einfo.hang = None;
+        einfo.sanitizer_reports = None;
+        einfo.stop_info.signal_name = "SIGABRT".into();
+        assert_eq!(classify_cwe(&einfo), None);
+    }
+}
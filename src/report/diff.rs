@@ -0,0 +1,169 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Comparing the crash buckets of two triage runs.
+//!
+//! `afltriage diff OLD NEW` reads the bucket metadata ([BUCKETS_JSON_FILE]) of two output
+//! directories and lists the buckets that are new, the buckets that were fixed (they disappeared),
+//! and the buckets whose crash count changed. Triaging the same crashes against two versions of a
+//! target this way shows what a patch fixed and what it broke. Both runs must use the same
+//! bucketing strategy for their buckets to be comparable.
+use crate::report::provenance::BUCKETS_JSON_FILE;
+use crate::report::severity::CrashSeverity;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A bucket of a triage run, as compared
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BucketSummary {
+    pub bucket: String,
+    pub summary: String,
+    pub severity: CrashSeverity,
+    pub crashes: usize,
+}
+
+/// A bucket found by both runs with a different number of crashes
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CountChange {
+    #[serde(flatten)]
+    pub bucket: BucketSummary,
+    pub old_crashes: usize,
+}
+
+/// The differences between two triage runs
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ResultsDiff {
+    /// Buckets only found by the new run
+    pub new: Vec<BucketSummary>,
+    /// Buckets only found by the old run
+    pub fixed: Vec<BucketSummary>,
+    pub changed: Vec<CountChange>,
+    /// How many buckets both runs found the same number of crashes in
+    pub unchanged: usize,
+}
+
+#[derive(Deserialize)]
+struct SavedBucket {
+    bucket: String,
+    summary: String,
+    severity: CrashSeverity,
+    testcases: Vec<serde_json::Value>,
+}
+
+/// Read the buckets of a triage run from its output directory, or from its bucket metadata file
+pub fn read_buckets(path: &Path) -> Result<Vec<BucketSummary>, String> {
+    let path = if path.is_dir() { path.join(BUCKETS_JSON_FILE) } else { path.to_path_buf() };
+
+    let data = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let saved: Vec<SavedBucket> = serde_json::from_str(&data)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+    Ok(saved.into_iter()
+        .map(|b| BucketSummary { bucket: b.bucket, summary: b.summary, severity: b.severity, crashes: b.testcases.len() })
+        .collect())
+}
+
+/// Most severe first, then by crash count
+fn sort_buckets(buckets: &mut [BucketSummary]) {
+    buckets.sort_by(|a, b| b.severity.cmp(&a.severity)
+        .then(b.crashes.cmp(&a.crashes))
+        .then(a.bucket.cmp(&b.bucket)));
+}
+
+/// Compare the buckets of an `old` and a `new` triage run
+pub fn diff_results(old: Vec<BucketSummary>, new: Vec<BucketSummary>) -> ResultsDiff {
+    let mut old: BTreeMap<String, BucketSummary> = old.into_iter().map(|b| (b.bucket.to_string(), b)).collect();
+    let mut diff = ResultsDiff::default();
+
+    for bucket in new {
+        match old.remove(&bucket.bucket) {
+            None => diff.new.push(bucket),
+            Some(prev) if prev.crashes != bucket.crashes => {
+                diff.changed.push(CountChange { old_crashes: prev.crashes, bucket })
+            }
+            Some(_) => diff.unchanged += 1,
+        }
+    }
+
+    diff.fixed = old.into_values().collect();
+
+    sort_buckets(&mut diff.new);
+    sort_buckets(&mut diff.fixed);
+    diff.changed.sort_by(|a, b| b.bucket.severity.cmp(&a.bucket.severity)
+        .then(a.bucket.bucket.cmp(&b.bucket.bucket)));
+
+    diff
+}
+
+fn format_bucket(bucket: &BucketSummary, crashes: &str) -> String {
+    format!("  {:<7} {} ({}) {}\n", bucket.severity.as_str().to_uppercase(), bucket.bucket, crashes, bucket.summary)
+}
+
+fn crash_count(crashes: usize) -> String {
+    format!("{} crash{}", crashes, if crashes == 1 { "" } else { "es" })
+}
+
+/// Render a text report of `diff`
+pub fn format_text_diff(diff: &ResultsDiff) -> String {
+    let mut text = format!("New buckets: {}, fixed buckets: {}, changed: {}, unchanged: {}\n",
+        diff.new.len(), diff.fixed.len(), diff.changed.len(), diff.unchanged);
+
+    if !diff.new.is_empty() {
+        text += "\nNew:\n";
+        for bucket in &diff.new {
+            text += &format_bucket(bucket, &crash_count(bucket.crashes));
+        }
+    }
+
+    if !diff.fixed.is_empty() {
+        text += "\nFixed:\n";
+        for bucket in &diff.fixed {
+            text += &format_bucket(bucket, &crash_count(bucket.crashes));
+        }
+    }
+
+    if !diff.changed.is_empty() {
+        text += "\nChanged:\n";
+        for change in &diff.changed {
+            let crashes = format!("{} -> {}", change.old_crashes, crash_count(change.bucket.crashes));
+            text += &format_bucket(&change.bucket, &crashes);
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bucket(bucket: &str, severity: CrashSeverity, crashes: usize) -> BucketSummary {
+        BucketSummary { bucket: bucket.into(), summary: format!("Summary {}", bucket), severity, crashes }
+    }
+
+    #[test]
+    fn test_diff_results() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(BUCKETS_JSON_FILE), r#"[
+            {"bucket": "a", "summary": "Summary a", "severity": "high", "reports": [], "testcases": [{}, {}]}
+        ]"#).unwrap();
+        assert_eq!(read_buckets(dir.path()).unwrap(), vec![bucket("a", CrashSeverity::high, 2)]);
+
+        let old = vec![bucket("a", CrashSeverity::high, 2), bucket("b", CrashSeverity::low, 1), bucket("c", CrashSeverity::medium, 4)];
+        let new = vec![bucket("a", CrashSeverity::high, 2), bucket("c", CrashSeverity::medium, 1),
+            bucket("d", CrashSeverity::low, 3), bucket("e", CrashSeverity::high, 1)];
+        let diff = diff_results(old, new);
+
+        assert_eq!(diff.new.iter().map(|b| b.bucket.as_str()).collect::<Vec<_>>(), vec!["e", "d"]);
+        assert_eq!(diff.fixed, vec![bucket("b", CrashSeverity::low, 1)]);
+        assert_eq!(diff.changed, vec![CountChange { bucket: bucket("c", CrashSeverity::medium, 1), old_crashes: 4 }]);
+        assert_eq!(diff.unchanged, 1);
+
+        assert_eq!(format_text_diff(&diff), "New buckets: 2, fixed buckets: 1, changed: 1, unchanged: 1\n\
+            \nNew:\n  HIGH    e (1 crash) Summary e\n  LOW     d (3 crashes) Summary d\n\
+            \nFixed:\n  LOW     b (1 crash) Summary b\n\
+            \nChanged:\n  MEDIUM  c (4 -> 1 crash) Summary c\n");
+    }
+}
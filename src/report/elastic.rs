@@ -0,0 +1,216 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Streaming triage documents to Elasticsearch or OpenSearch.
+//!
+//! With `--elasticsearch`, every crashing testcase becomes a [TriageDocument] in an index, so large
+//! campaigns over many targets can be dashboarded in Kibana or OpenSearch Dashboards. Documents
+//! are sent while triage runs, in batches of [BATCH_SIZE] using the `_bulk` API, with `curl`.
+//! Each document's ID is derived from the target and testcase, so triaging a testcase again
+//! replaces its document instead of duplicating it.
+//!
+//! The fields are flat so they can be indexed without a mapping: `@timestamp` (when the testcase
+//! was triaged), `target`, `testcase`, `bucket`, `bucket_strategy`, `classification`, `severity`,
+//! `signal`, `faulting_function`, `location`, and `summary`, and when known `cwe`, `discovered_at`,
+//! `fuzzer_instance`, `sha256`, and `triage_duration_ms`.
+use crate::report::campaign::crash_classification;
+use crate::report::cwe::classify_cwe;
+use crate::report::enriched::EnrichedTriageInfo;
+use crate::report::provenance::TestcaseProvenance;
+use crate::report::severity::CrashSeverity;
+use crate::report::ReportEnvelope;
+use serde::Serialize;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+/// How many documents are sent at once
+pub const BATCH_SIZE: usize = 100;
+
+/// How long to wait for a bulk request to complete
+const BULK_TIMEOUT_SECS: u64 = 60;
+
+/// A crashing testcase, as indexed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TriageDocument {
+    #[serde(skip)]
+    pub id: String,
+    #[serde(rename = "@timestamp", skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    pub target: String,
+    pub testcase: String,
+    pub bucket: String,
+    pub bucket_strategy: String,
+    pub classification: String,
+    pub severity: CrashSeverity,
+    pub signal: String,
+    pub faulting_function: String,
+    pub location: String,
+    pub summary: String,
+    /// The CWE identifier, like `CWE-122`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwe: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discovered_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fuzzer_instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub triage_duration_ms: Option<u64>,
+}
+
+impl TriageDocument {
+    pub fn new(
+        target: &str,
+        bucket: &str,
+        severity: CrashSeverity,
+        einfo: &EnrichedTriageInfo,
+        envelope: &ReportEnvelope,
+        provenance: Option<&TestcaseProvenance>,
+    ) -> Self {
+        let location = einfo.faulting_thread.frames.get(einfo.faulting_frame_idx)
+            .and_then(|f| f.symbol.as_ref())
+            .map(|s| s.format_file())
+            .unwrap_or_default();
+
+        TriageDocument {
+            id: format!("{:x}", md5::compute(format!("{}\0{}", target, envelope.testcase))),
+            timestamp: envelope.triaged_at.clone(),
+            target: target.to_string(),
+            testcase: envelope.testcase.to_string(),
+            bucket: bucket.to_string(),
+            bucket_strategy: envelope.bucket.strategy.to_string(),
+            classification: crash_classification(einfo),
+            severity,
+            signal: einfo.stop_info.signal_name.to_string(),
+            faulting_function: einfo.faulting_function.to_string(),
+            location,
+            summary: einfo.summary.to_string(),
+            cwe: classify_cwe(einfo).map(|c| c.id.to_string()),
+            discovered_at: provenance.and_then(|p| p.discovered_at.clone()),
+            fuzzer_instance: provenance.and_then(|p| p.fuzzer_instance.clone()),
+            sha256: provenance.map(|p| p.sha256.to_string()),
+            triage_duration_ms: envelope.triage_duration_ms,
+        }
+    }
+}
+
+/// The body of a `_bulk` request indexing `documents`
+pub fn bulk_body(documents: &[TriageDocument]) -> String {
+    let mut body = String::new();
+
+    for doc in documents {
+        body += &serde_json::json!({"index": {"_id": doc.id}}).to_string();
+        body += "\n";
+        body += &serde_json::to_string(doc).unwrap();
+        body += "\n";
+    }
+
+    body
+}
+
+/// Documents waiting to be sent to an index
+pub struct ElasticsearchSink {
+    /// The URL of the index, like `http://localhost:9200/afltriage`
+    index_url: String,
+    pending: Mutex<Vec<TriageDocument>>,
+}
+
+impl ElasticsearchSink {
+    pub fn new(index_url: &str) -> Self {
+        ElasticsearchSink {
+            index_url: index_url.trim_end_matches('/').to_string(),
+            pending: Mutex::new(vec![]),
+        }
+    }
+
+    /// Queue a document. Returns a batch to [send](ElasticsearchSink::send) once enough are queued
+    pub fn add(&self, doc: TriageDocument) -> Option<Vec<TriageDocument>> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.push(doc);
+
+        if pending.len() >= BATCH_SIZE {
+            Some(std::mem::take(&mut *pending))
+        } else {
+            None
+        }
+    }
+
+    /// The documents still queued
+    pub fn take_pending(&self) -> Vec<TriageDocument> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// Index a batch of documents
+    pub fn send(&self, documents: &[TriageDocument]) -> Result<(), String> {
+        if documents.is_empty() {
+            return Ok(());
+        }
+
+        let mut child = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--max-time", &BULK_TIMEOUT_SECS.to_string(),
+                   "--header", "Content-Type: application/x-ndjson", "--data-binary", "@-",
+                   &format!("{}/_bulk", self.index_url)])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run curl: {}", e))?;
+
+        child.stdin.take().unwrap().write_all(bulk_body(documents).as_bytes())
+            .map_err(|e| format!("Failed to write the documents to curl: {}", e))?;
+
+        let output = child.wait_with_output().map_err(|e| format!("Failed to run curl: {}", e))?;
+
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+
+        // A bulk request succeeds even when some of its documents were rejected
+        let response: serde_json::Value = serde_json::from_slice(&output.stdout)
+            .map_err(|e| format!("Unexpected response: {}", e))?;
+
+        if response["errors"] == true {
+            let first_error = response["items"].as_array().into_iter().flatten()
+                .find_map(|item| item["index"].get("error"))
+                .map(|e| e.to_string())
+                .unwrap_or_default();
+
+            return Err(format!("Some documents were rejected: {}", first_error));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::load_test_envelope;
+
+    #[test]
+    fn test_triage_document() {
+        let (einfo, mut envelope): (EnrichedTriageInfo, ReportEnvelope) = load_test_envelope();
+        envelope.triaged_at = Some("2021-06-01T12:00:00Z".into());
+
+        let doc = TriageDocument::new("test", "abc123", CrashSeverity::medium, &einfo, &envelope, None);
+        let body = bulk_body(&[doc.clone(), doc.clone()]);
+        let lines: Vec<serde_json::Value> = body.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["index"]["_id"], doc.id);
+        assert_eq!(lines[1]["@timestamp"], "2021-06-01T12:00:00Z");
+        assert_eq!(lines[1]["classification"], "ASAN stack-buffer-overflow");
+        assert_eq!(lines[1]["severity"], "medium");
+        assert_eq!(lines[1]["location"], "test.c:20");
+        assert_eq!(lines[1]["cwe"], "CWE-125");
+        assert!(lines[1].get("sha256").is_none());
+
+        let sink = ElasticsearchSink::new("http://localhost:9200/afltriage/");
+        assert_eq!(sink.index_url, "http://localhost:9200/afltriage");
+        assert!((1..BATCH_SIZE).all(|_| sink.add(doc.clone()).is_none()));
+        assert_eq!(sink.add(doc).unwrap().len(), BATCH_SIZE);
+        assert!(sink.take_pending().is_empty());
+    }
+}
@@ -10,6 +10,15 @@
 use std::collections::HashSet;
 use std::rc::Rc;
 use super::sanitizer::*;
+use super::access::{detect_fault_access, FaultAccess};
+use super::severity::NULL_PAGE_LIMIT;
+use super::unwind::{assess_backtrace, BacktraceQuality};
+use super::assertion::{assertion_extract, assertion_from_frames, is_assertion_handler, AssertionFailure};
+use super::exception::{detect_uncaught_exception, find_exception_frame, UncaughtException};
+use crate::debugger::remote::find_fault_handler_frame;
+use super::panic::{find_panic_frame, is_panicking, panic_extract, RustPanic};
+use super::golang::{go_fatal_error_extract, GoFatalError};
+use super::stackoverflow::{detect_stack_exhaustion, find_recursion, find_stack_pointer, RecursionCycle};
 use serde::{Deserialize, Serialize};
 use regex::Regex;
 use std::collections::HashMap;
@@ -17,6 +26,7 @@ use crate::debugger::gdb::*;
 use crate::ReportOptions;
 use crate::util;
 use crate::platform::linux::si_code_to_string;
+use strum::IntoStaticStr;
 
 lazy_static! {
     static ref R_CIDENT: Regex = Regex::new(r#"[_a-zA-Z][_a-zA-Z0-9]{0,30}"#).unwrap();
@@ -43,6 +53,107 @@ impl AddressView {
     }
 }
 
+/// The kind of memory region an address falls in
+#[derive(Debug, Clone, Copy, PartialEq, IntoStaticStr, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum MemoryRegionKind {
+    /// The process heap (\[heap\])
+    heap,
+    /// A thread stack (\[stack\])
+    stack,
+    /// A file-backed mapping, such as the executable or a shared library
+    module,
+    /// An anonymous mapping (e.g. mmap'd memory or a secondary thread's stack)
+    anonymous,
+    /// A kernel provided mapping, such as \[vdso\] or \[vvar\]
+    special,
+    /// The address is not mapped in the process
+    unmapped,
+}
+
+/// The memory region an address falls in, according to the process memory map
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct EnrichedMemoryRegion {
+    /// The kind of region
+    pub kind: MemoryRegionKind,
+    /// The name of the mapping. Empty for anonymous and unmapped regions
+    pub name: String,
+}
+
+impl EnrichedMemoryRegion {
+    /// A short description of the region, suitable for a sentence
+    pub fn format(&self) -> String {
+        match self.kind {
+            MemoryRegionKind::heap => "in the heap".to_string(),
+            MemoryRegionKind::stack => "in the stack".to_string(),
+            MemoryRegionKind::anonymous => "in an anonymous mapping".to_string(),
+            MemoryRegionKind::unmapped => "unmapped".to_string(),
+            MemoryRegionKind::module | MemoryRegionKind::special => format!("in {}", self.name),
+        }
+    }
+}
+
+/// What a faulting address looks like, to tell NULL pointer dereferences from wild pointers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum FaultAddressKind {
+    /// Address 0
+    null,
+    /// Within [NULL_PAGE_LIMIT] of 0, in either direction. Usually a field accessed through a NULL
+    /// struct pointer
+    near_null,
+    stack,
+    heap,
+    /// Another mapping, such as a module or an anonymous mapping
+    mapped,
+    /// An unmapped address that is not near NULL
+    wild,
+    /// An x86-64 address outside of the canonical address space, which the kernel reports as 0.
+    /// Usually a garbage pointer
+    non_canonical,
+}
+
+impl FaultAddressKind {
+    /// A short description for a sentence
+    pub fn describe(&self) -> &'static str {
+        match self {
+            FaultAddressKind::null => "a NULL pointer",
+            FaultAddressKind::near_null => "near NULL, likely a field of a NULL pointer",
+            FaultAddressKind::stack => "in the stack",
+            FaultAddressKind::heap => "in the heap",
+            FaultAddressKind::mapped => "in mapped memory",
+            FaultAddressKind::wild => "unmapped, likely a wild pointer",
+            FaultAddressKind::non_canonical => "a non-canonical address, likely a garbage pointer",
+        }
+    }
+}
+
+/// Classify a faulting `address` from its memory `region` and the signal it raised
+fn classify_fault_address(arch: &GdbArchInfo, stop_info: &GdbStopInfo, region: Option<&EnrichedMemoryRegion>) -> Option<FaultAddressKind> {
+    let address = stop_info.faulting_address?;
+    // General protection faults, like dereferencing a non-canonical address, are SI_KERNEL
+    let si_kernel = 0x80;
+
+    if address == 0 && stop_info.signal_code == si_kernel && arch.architecture.contains("x86-64") {
+        return Some(FaultAddressKind::non_canonical);
+    }
+
+    let top = if arch.address_bits >= 64 { u64::MAX } else { (1u64 << arch.address_bits) - 1 };
+
+    if address == 0 {
+        return Some(FaultAddressKind::null);
+    } else if address < NULL_PAGE_LIMIT || top - address < NULL_PAGE_LIMIT {
+        return Some(FaultAddressKind::near_null);
+    }
+
+    Some(match region?.kind {
+        MemoryRegionKind::stack => FaultAddressKind::stack,
+        MemoryRegionKind::heap => FaultAddressKind::heap,
+        MemoryRegionKind::unmapped => FaultAddressKind::wild,
+        _ => FaultAddressKind::mapped,
+    })
+}
+
 /// Context information for a single target instruction
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct EnrichedInstructionContext {
@@ -74,6 +185,12 @@ pub struct EnrichedSourceContext {
 /// Triage information for a single target thread
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct EnrichedThreadInfo {
+    /// The thread's OS ID, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tid: Option<i32>,
+    /// The thread's name, if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     /// Frames extracted from a thread's backtrace
     pub frames: Vec<EnrichedFrameInfo>,
     /// Registers may be collected during debugger backtracing
@@ -83,6 +200,39 @@ pub struct EnrichedThreadInfo {
     /// One or more instructions that were collected for this thread
     #[serde(skip_serializing_if = "Option::is_none")]
     pub instruction_context: Option<Vec<EnrichedInstructionContext>>,
+    /// Frames of the backtrace that repeat, if the thread is recursing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recursion: Option<RecursionCycle>,
+}
+
+/// Threads other than the faulting thread with the same backtrace, collapsed into one entry
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct EnrichedThreadGroup {
+    /// The OS IDs of the threads
+    pub tids: Vec<i32>,
+    /// The name of the first thread, if it has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The function, or module and address, of each frame of the backtrace
+    pub frames: Vec<String>,
+}
+
+/// A module (the executable or a shared library) loaded in the target when it stopped
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct EnrichedModule {
+    /// The module's file path
+    pub path: String,
+    /// The lowest address the module is mapped at, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_address: Option<AddressView>,
+    /// The end of the module's highest mapping (exclusive), if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_address: Option<AddressView>,
+    /// The module's GNU build ID in hex, if it has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_id: Option<String>,
+    /// Whether the faulting thread has frames in this module
+    pub in_backtrace: bool,
 }
 
 /// Output from the target
@@ -90,6 +240,13 @@ pub struct EnrichedThreadInfo {
 pub struct EnrichedTargetOutput {
     pub stdout: String,
     pub stderr: String,
+    /// The exact bytes of the last lines of stdout, if they are not UTF-8. Serialized as hex
+    #[serde(default, rename = "stdout_hex", with = "crate::util::hex_bytes", skip_serializing_if = "Option::is_none")]
+    pub stdout_raw: Option<Vec<u8>>,
+    /// The exact bytes of the last lines of stderr, including any sanitizer report, if they are not
+    /// UTF-8. Serialized as hex
+    #[serde(default, rename = "stderr_hex", with = "crate::util::hex_bytes", skip_serializing_if = "Option::is_none")]
+    pub stderr_raw: Option<Vec<u8>>,
 }
 
 /// Information for a single target thread's stack frame
@@ -106,6 +263,9 @@ pub struct EnrichedFrameInfo {
     pub module: String,
     /// An opinionated, uniquely identifiable (within a process) formatting of module and address
     pub module_address: String,
+    /// The memory region of the frame address, if the process memory map was collected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<EnrichedMemoryRegion>,
     /// Symbol information for the frame's function, if available
     #[serde(skip_serializing_if = "Option::is_none")]
     pub symbol: Option<Rc<GdbSymbol>>,
@@ -130,6 +290,27 @@ pub struct EnrichedLinuxStopInfo {
     /// An optional faulting address (sigfault.si_addr)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub faulting_address: Option<AddressView>,
+    /// The memory region of the faulting address, if the process memory map was collected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub faulting_address_region: Option<EnrichedMemoryRegion>,
+    /// Whether the fault was a read, a write, or an instruction fetch, if it could be decided
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fault_access: Option<FaultAccess>,
+    /// What the faulting address looks like, if there is one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fault_address_kind: Option<FaultAddressKind>,
+}
+
+impl EnrichedLinuxStopInfo {
+    /// Whether the fault was a NULL pointer dereference. Reports from before the faulting address
+    /// was classified fall back to the address alone
+    pub fn is_null_dereference(&self) -> bool {
+        match (self.fault_address_kind, &self.faulting_address) {
+            (Some(kind), _) => kind == FaultAddressKind::null || kind == FaultAddressKind::near_null,
+            (None, Some(addr)) => addr.r < NULL_PAGE_LIMIT,
+            (None, None) => false,
+        }
+    }
 }
 
 /// The top-level container for crash triage information
@@ -152,6 +333,12 @@ pub struct EnrichedTriageInfo {
     pub faulting_function: String,
     /// The thread that caused a fault leading to the target being stopped
     pub faulting_thread: EnrichedThreadInfo,
+    /// The other threads of the target, grouped by their backtrace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub other_threads: Option<Vec<EnrichedThreadGroup>>,
+    /// The modules loaded in the target, for symbolizing the report offline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modules: Option<Vec<EnrichedModule>>,
     /// Sanitizer reports extracted from the target output in reverse order (most recent first)
     /// Currently only the last report is extracted.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -159,6 +346,43 @@ pub struct EnrichedTriageInfo {
     /// Raw output from the target, if enabled
     #[serde(skip_serializing_if = "Option::is_none")]
     pub target_output: Option<EnrichedTargetOutput>,
+    /// Fields added by triage script extensions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Set when the crash happened in a forked child process of the target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forked_child: Option<bool>,
+    /// Set when the target did not crash, but was stopped after running longer than the timeout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hang: Option<bool>,
+    /// Set when the crash was caused by exhausting the stack, e.g. by runaway recursion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack_exhausted: Option<bool>,
+    /// The assertion that failed, if the target aborted due to one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assertion: Option<AssertionFailure>,
+    /// The uncaught C++ exception, if the target was terminated due to one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uncaught_exception: Option<UncaughtException>,
+    /// Set when a Rust target aborted due to a panic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub panicked: Option<bool>,
+    /// The panic message and location, if the target printed them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub panic: Option<RustPanic>,
+    /// The panic or fatal error of a Go target, with the backtraces the Go runtime printed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub go_fatal_error: Option<GoFatalError>,
+    /// The fault handler bare-metal firmware entered, when triaged on a remote target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fault_handler: Option<String>,
+    /// Set when the backtrace of the faulting thread looks unreliable, e.g. due to a corrupted stack
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backtrace_quality: Option<BacktraceQuality>,
+    /// Problems the debugger had collecting triage information, e.g. a variable that could not be
+    /// read. The report may be missing what they name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triage_warnings: Vec<String>,
 }
 
 /// With [ReportOptions] `opt` translate raw debugger triage information ([GdbTriageResult]) into
@@ -173,18 +397,81 @@ pub fn enrich_triage_info(opt: &ReportOptions, triage_result: &GdbTriageResult)
         return Err("Backtrace is empty!")
     }
 
-    let stop_info = build_stop_info(arch_info, &ctx_info.stop_info);
+    let memory_map = &ctx_info.memory_map;
+    let mut stop_info = build_stop_info(arch_info, &ctx_info.stop_info, memory_map);
 
-    let faulting_thread = build_thread_info(arch_info, primary_thread);
+    let faulting_thread = build_thread_info(arch_info, primary_thread, memory_map);
+    let other_threads = ctx_info.other_threads.as_ref()
+        .map(|threads| group_threads(arch_info, threads, memory_map))
+        .filter(|groups| !groups.is_empty());
+    let modules = ctx_info.modules.as_ref()
+        .map(|modules| build_modules(arch_info, modules, &faulting_thread));
+    let backtrace_quality = assess_backtrace(&primary_thread.backtrace, primary_thread.unwind_error.as_deref(), memory_map);
     let sanitizer_reports = sanitizer_report_extract(&triage_result.child.stderr).map(|r| vec![r]);
     let faulting_sanitizer_report = sanitizer_reports.as_ref()
         .map(|reports| reports.get(0)).flatten();
 
-    let faulting_frame_idx = sanitizer_reports.as_ref()
-        .map(|s| find_faulting_frame(&faulting_thread, s)).unwrap_or(0);
+    stop_info.fault_access = detect_fault_access(&stop_info.signal_name, ctx_info.stop_info.faulting_address,
+        Some(frames[0].address), primary_thread.current_instruction.as_deref(),
+        faulting_sanitizer_report.map(|s| s.operation.as_str()));
+
+    let hang = ctx_info.hang == Some(true);
+    let stack_exhausted = match faulting_sanitizer_report {
+        Some(san) => san.stop_reason == "stack-overflow",
+        None => detect_stack_exhaustion(ctx_info.stop_info.faulting_address, find_stack_pointer(&faulting_thread.registers),
+            memory_map, faulting_thread.recursion.is_some()),
+    };
+
+    let assertion = if faulting_sanitizer_report.is_none() && stop_info.signal_name == "SIGABRT" {
+        assertion_extract(&triage_result.child.stderr).or_else(|| assertion_from_frames(&faulting_thread.frames))
+    } else {
+        None
+    };
+
+    // Panics abort, or execute an illegal instruction when the panic runtime itself fails
+    let aborted = stop_info.signal_name == "SIGABRT" || stop_info.signal_name == "SIGILL";
+    let panic = if faulting_sanitizer_report.is_none() && aborted {
+        panic_extract(&triage_result.child.stderr)
+    } else {
+        None
+    };
+    let panicked = panic.is_some() || (faulting_sanitizer_report.is_none() && aborted && is_panicking(&faulting_thread.frames));
+
+    let uncaught_exception = if faulting_sanitizer_report.is_none() && assertion.is_none() && !panicked
+        && stop_info.signal_name == "SIGABRT" {
+        detect_uncaught_exception(ctx_info.exception.as_ref(), &triage_result.child.stderr, &faulting_thread.frames)
+    } else {
+        None
+    };
+
+    let faulting_frame_idx = if hang {
+        find_hang_frame(&faulting_thread)
+    } else if let Some(handler) = &ctx_info.fault_handler {
+        find_fault_handler_frame(handler, &faulting_thread.frames)
+    } else if assertion.is_some() {
+        find_assertion_frame(&faulting_thread)
+    } else if panicked {
+        find_panic_frame(&faulting_thread.frames)
+    } else if let Some(exception) = &uncaught_exception {
+        find_exception_frame(exception, &faulting_thread.frames)
+    } else {
+        sanitizer_reports.as_ref()
+            .map(|s| find_faulting_frame(&faulting_thread, s)).unwrap_or(0)
+    };
+    let go_fatal_error = if faulting_sanitizer_report.is_none() {
+        go_fatal_error_extract(&triage_result.child.stderr)
+    } else {
+        None
+    };
+    let go_frame = go_fatal_error.as_ref().and_then(|e| e.failing_frames().first().cloned().cloned());
+
     let faulting_frame = &faulting_thread.frames[faulting_frame_idx];
-    let faulting_function = faulting_frame.symbol.as_ref()
-        .map(|s| s.format()).unwrap_or(faulting_frame.address.f.to_string());
+    // GDB's backtrace of a Go program is of the runtime
+    let faulting_function = match &go_frame {
+        Some(go_frame) => go_frame.function.to_string(),
+        None => faulting_frame.symbol.as_ref()
+            .map(|s| s.format()).unwrap_or(faulting_frame.address.f.to_string()),
+    };
 
     let target_output = if opt.show_child_output {
         Some(build_target_output(opt, &triage_result.child, &sanitizer_reports))
@@ -197,6 +484,17 @@ pub fn enrich_triage_info(opt: &ReportOptions, triage_result: &GdbTriageResult)
 
     // Build sanitizer report
     match &faulting_sanitizer_report {
+        _ if hang => {
+            summary = format!("HANG detected in {} (the target ran longer than the timeout and was stopped)",
+                faulting_function);
+            terse_summary = format!("HANG_{}", faulting_function);
+        }
+        _ if ctx_info.fault_handler.is_some() => {
+            let handler = ctx_info.fault_handler.as_ref().unwrap();
+
+            summary = format!("FAULT detected in {} leading to {}", faulting_function, handler);
+            terse_summary = format!("{}_{}", handler, faulting_function);
+        }
         Some(san) => {
             let op = if san.operation.is_empty() {
                 terse_summary =
@@ -217,10 +515,87 @@ pub fn enrich_triage_info(opt: &ReportOptions, triage_result: &GdbTriageResult)
                 san.name_prefer_short(), san.stop_reason, faulting_function, op, stop_info.summary
             );
         }
+        None if assertion.is_some() => {
+            let assertion = assertion.as_ref().unwrap();
+
+            summary = format!(
+                "ASSERTION `{}` failed in {} at {}:{} leading to {}",
+                assertion.expression, faulting_function, assertion.file, assertion.line, stop_info.summary,
+            );
+
+            terse_summary = format!("ASSERT_{}", faulting_function);
+        }
+        None if go_fatal_error.is_some() => {
+            let error = go_fatal_error.as_ref().unwrap();
+            let location = go_frame.as_ref().map(|fr| format!(" at {}:{}", fr.file, fr.line)).unwrap_or_default();
+
+            summary = format!(
+                "GO {}: {} in {}{} leading to {}",
+                error.kind, error.message, faulting_function, location, stop_info.summary,
+            );
+
+            terse_summary = format!("GO_{}", faulting_function);
+        }
+        None if panicked => {
+            summary = match &panic {
+                Some(panic) => format!(
+                    "PANIC in {} at {}:{}:{}: {} leading to {}",
+                    faulting_function, panic.file, panic.line, panic.column, panic.message, stop_info.summary),
+                None => format!("PANIC in {} leading to {}", faulting_function, stop_info.summary),
+            };
+
+            terse_summary = format!("PANIC_{}", faulting_function);
+        }
+        None if uncaught_exception.is_some() => {
+            let exception = uncaught_exception.as_ref().unwrap();
+            let what = exception.what.as_ref().map(|w| format!(" (what(): {})", w)).unwrap_or_default();
+
+            summary = match &exception.exception_type {
+                _ if !exception.thrown() => format!(
+                    "TERMINATE called in {} without an active exception leading to {}",
+                    faulting_function, stop_info.summary),
+                Some(exception_type) => format!(
+                    "UNCAUGHT EXCEPTION {} thrown in {}{} leading to {}",
+                    exception_type, faulting_function, what, stop_info.summary),
+                None => format!(
+                    "UNCAUGHT EXCEPTION thrown in {}{} leading to {}",
+                    faulting_function, what, stop_info.summary),
+            };
+
+            terse_summary = format!("EXCEPTION_{}", faulting_function);
+        }
+        None if stack_exhausted => {
+            let recursion = faulting_thread.recursion.as_ref().map(|cycle| {
+                let functions: Vec<String> = faulting_thread.frames[cycle.first_frame..cycle.first_frame + cycle.length].iter()
+                    .map(|fr| fr.symbol.as_ref().map(|s| s.format()).unwrap_or(fr.address.f.to_string()))
+                    .collect();
+                format!(" from recursion through {}", functions.join(", "))
+            }).unwrap_or_default();
+
+            summary = format!(
+                "CRASH detected in {} due to stack exhaustion{} leading to {}",
+                faulting_function, recursion, stop_info.summary,
+            );
+
+            terse_summary = format!("{}_stack-overflow_{}", stop_info.signal_name, faulting_function);
+        }
         None => {
-            let fault_address = match &stop_info.faulting_address {
-                Some(addr) => format!(" due to a fault at or near {}", addr.f),
-                None => "".to_string(),
+            let fault = match stop_info.fault_access {
+                Some(FaultAccess::read) => "a read fault",
+                Some(FaultAccess::write) => "a write fault",
+                Some(FaultAccess::execute) => "an instruction fetch fault",
+                None => "a fault",
+            };
+
+            let fault_address = match (&stop_info.faulting_address, stop_info.fault_address_kind, &stop_info.faulting_address_region) {
+                // The kernel does not report the address
+                (Some(_), Some(FaultAddressKind::non_canonical), _) =>
+                    format!(" due to {} at {}", fault, FaultAddressKind::non_canonical.describe()),
+                (Some(addr), Some(FaultAddressKind::mapped), Some(region)) =>
+                    format!(" due to {} at or near {} ({})", fault, addr.f, region.format()),
+                (Some(addr), Some(kind), _) => format!(" due to {} at or near {} ({})", fault, addr.f, kind.describe()),
+                (Some(addr), None, _) => format!(" due to {} at or near {}", fault, addr.f),
+                _ => "".to_string(),
             };
 
             summary = format!(
@@ -239,8 +614,22 @@ pub fn enrich_triage_info(opt: &ReportOptions, triage_result: &GdbTriageResult)
         faulting_frame_idx,
         faulting_function,
         faulting_thread,
+        other_threads,
+        modules,
         sanitizer_reports,
         target_output,
+        extra: ctx_info.extra.clone(),
+        forked_child: ctx_info.forked_child,
+        hang: ctx_info.hang,
+        stack_exhausted: Some(true).filter(|_| stack_exhausted),
+        assertion,
+        uncaught_exception,
+        panicked: Some(true).filter(|_| panicked),
+        panic,
+        go_fatal_error,
+        fault_handler: ctx_info.fault_handler.clone(),
+        backtrace_quality,
+        triage_warnings: triage_result.response.warnings.clone(),
     })
 }
 
@@ -265,15 +654,31 @@ fn build_target_output(opt: &ReportOptions, child: &GdbChildOutput, sanitizer_re
     let stderr = if opt.child_output_lines > 0 {
         util::tail_string(&stderr, opt.child_output_lines).join("\n")
     } else {
-        child.stdout.to_string()
+        stderr
     };
 
+    let raw_tail = |raw: &Option<Vec<u8>>| raw.as_ref()
+        .map(|raw| if opt.child_output_lines > 0 { util::tail_bytes(raw, opt.child_output_lines) } else { raw })
+        .filter(|raw| std::str::from_utf8(raw).is_err())
+        .map(<[u8]>::to_vec);
+
     EnrichedTargetOutput {
         stdout,
         stderr,
+        stdout_raw: raw_tail(&child.stdout_raw),
+        stderr_raw: raw_tail(&child.stderr_raw),
     }
 }
 
+/// Where a hung target is stuck. A target is usually stopped in a library call (e.g. waiting in a
+/// system call), so this is the first frame with source information, which is likely the target's
+/// own code
+fn find_hang_frame(thread: &EnrichedThreadInfo) -> usize {
+    thread.frames.iter()
+        .position(|fr| fr.symbol.as_ref().map(|s| s.file.is_some() && s.line.is_some()).unwrap_or(false))
+        .unwrap_or(0)
+}
+
 fn find_faulting_frame(thread: &EnrichedThreadInfo, sanitizers: &Vec<SanitizerReport>) -> usize {
     for san in sanitizers.iter() {
         for san_frame in san.frames.iter() {
@@ -285,23 +690,103 @@ fn find_faulting_frame(thread: &EnrichedThreadInfo, sanitizers: &Vec<SanitizerRe
         }
     }
 
+    // ThreadSanitizer frames have no addresses, only function names
+    for san in sanitizers.iter() {
+        for func in san.functions.iter() {
+            let found = thread.frames.iter().position(|fr| fr.symbol.as_ref()
+                .and_then(|s| s.function_name.as_ref())
+                .map(|name| name == func)
+                .unwrap_or(false));
+
+            if let Some(fr_id) = found {
+                return fr_id;
+            }
+        }
+    }
+
+    // A failed glibc check has no stack, but the frame that failed it calls into glibc's check
+    // functions or allocator on the way to abort()
+    if sanitizers.iter().any(|san| san.sanitizer == "glibc") {
+        let last_check = thread.frames.iter().rposition(|fr| fr.symbol.as_ref()
+            .and_then(|s| s.function_name.as_ref())
+            .map(|name| is_glibc_check_function(name) || is_glibc_heap_function(name))
+            .unwrap_or(false));
+
+        if let Some(fr_id) = last_check.map(|i| i + 1).filter(|i| *i < thread.frames.len()) {
+            return fr_id;
+        }
+    }
+
     0
 }
 
-fn build_thread_info(arch_info: &GdbArchInfo, thread: &GdbThread) -> EnrichedThreadInfo {
-    let frames: Vec<EnrichedFrameInfo> = thread.backtrace.iter().map(|f| build_frame_info(arch_info, f)).collect();
+/// The frame that failed an assertion calls the C library's assertion handler
+fn find_assertion_frame(thread: &EnrichedThreadInfo) -> usize {
+    thread.frames.iter().rposition(|fr| fr.symbol.as_ref()
+            .and_then(|s| s.function_name.as_ref())
+            .map(|name| is_assertion_handler(name))
+            .unwrap_or(false))
+        .map(|i| i + 1)
+        .filter(|i| *i < thread.frames.len())
+        .unwrap_or(0)
+}
+
+fn build_thread_info(arch_info: &GdbArchInfo, thread: &GdbThread, memory_map: &Option<Vec<GdbMemoryMapping>>) -> EnrichedThreadInfo {
+    let frames: Vec<EnrichedFrameInfo> = thread.backtrace.iter().map(|f| build_frame_info(arch_info, f, memory_map)).collect();
     let registers = thread.registers.as_ref().map(|d| d.clone());
     let first_insn_ctx = thread.current_instruction.as_ref()
         .map(|i| build_instruction_context(arch_info, &registers, i.to_string(), frames[0].address.r));
     let insnctx = first_insn_ctx.map(|i| vec![i]);
+    let recursion = find_recursion(&thread.backtrace.iter().map(|f| f.address).collect::<Vec<u64>>());
 
     EnrichedThreadInfo {
+        tid: Some(thread.tid),
+        name: thread.name.clone(),
         frames,
         registers,
         instruction_context: insnctx,
+        recursion,
     }
 }
 
+fn build_modules(arch_info: &GdbArchInfo, modules: &[GdbModule], thread: &EnrichedThreadInfo) -> Vec<EnrichedModule> {
+    modules.iter().map(|m| EnrichedModule {
+        path: m.path.to_string(),
+        load_address: m.load_address.map(|a| AddressView::new(a, arch_info.address_bits)),
+        end_address: m.end_address.map(|a| AddressView::new(a, arch_info.address_bits)),
+        build_id: m.build_id.clone(),
+        in_backtrace: thread.frames.iter().any(|fr| fr.module == m.path),
+    }).collect()
+}
+
+/// Collapse threads with the same backtrace into one group each, in the order they were first seen.
+/// A process often has many worker threads waiting in the same place
+fn group_threads(arch_info: &GdbArchInfo, threads: &[GdbThread], memory_map: &Option<Vec<GdbMemoryMapping>>) -> Vec<EnrichedThreadGroup> {
+    let mut groups: Vec<EnrichedThreadGroup> = vec![];
+
+    for thread in threads {
+        let frames: Vec<String> = thread.backtrace.iter().map(|f| {
+            let frame = build_frame_info(arch_info, f, memory_map);
+            frame.symbol.as_ref()
+                .map(|s| s.format())
+                .filter(|name| !name.is_empty())
+                .map(|name| format!("{} ({})", name, frame.module))
+                .unwrap_or(frame.module_address)
+        }).collect();
+
+        match groups.iter_mut().find(|g| g.frames == frames) {
+            Some(group) => group.tids.push(thread.tid),
+            None => groups.push(EnrichedThreadGroup {
+                tids: vec![thread.tid],
+                name: thread.name.clone(),
+                frames,
+            }),
+        }
+    }
+
+    groups
+}
+
 fn build_reference_list<T>(needles: &HashMap<&str, Rc<T>>, haystack: Vec<&str>) -> Option<Vec<Rc<T>>> {
     let mut found: Vec<Rc<T>> = vec![];
     let mut seen: HashSet<&str> = HashSet::new();
@@ -375,11 +860,60 @@ fn build_source_context(symbol: &Rc<GdbSymbol>) -> Option<Vec<EnrichedSourceCont
     Some(ctx)
 }
 
-fn build_frame_info(arch_info: &GdbArchInfo, fr: &GdbFrameInfo) -> EnrichedFrameInfo {
+/// Find the memory region containing `address`. Returns None if no memory map was collected
+fn find_memory_region(memory_map: &Option<Vec<GdbMemoryMapping>>, address: u64) -> Option<EnrichedMemoryRegion> {
+    let mappings = memory_map.as_ref()?;
+
+    let mapping = match mappings.iter().find(|m| address >= m.start && address < m.end) {
+        Some(m) => m,
+        None => return Some(EnrichedMemoryRegion {
+            kind: MemoryRegionKind::unmapped,
+            name: "".to_string(),
+        }),
+    };
+
+    let kind = if mapping.name == "[heap]" {
+        MemoryRegionKind::heap
+    } else if mapping.name.starts_with("[stack") {
+        MemoryRegionKind::stack
+    } else if mapping.name.is_empty() {
+        MemoryRegionKind::anonymous
+    } else if mapping.name.starts_with('[') {
+        MemoryRegionKind::special
+    } else {
+        MemoryRegionKind::module
+    };
+
+    Some(EnrichedMemoryRegion {
+        kind,
+        name: mapping.name.to_string(),
+    })
+}
+
+/// The file-backed module `address` falls in, and the address relative to the module's lowest
+/// mapping, which does not change with ASLR
+fn find_module_address(memory_map: &Option<Vec<GdbMemoryMapping>>, address: u64) -> Option<(&str, u64)> {
+    let mappings = memory_map.as_ref()?;
+    let mapping = mappings.iter()
+        .find(|m| address >= m.start && address < m.end && !m.name.is_empty() && !m.name.starts_with('['))?;
+    let base = mappings.iter().filter(|m| m.name == mapping.name).map(|m| m.start).min()?;
+
+    Some((&mapping.name, address - base))
+}
+
+fn build_frame_info(arch_info: &GdbArchInfo, fr: &GdbFrameInfo, memory_map: &Option<Vec<GdbMemoryMapping>>) -> EnrichedFrameInfo {
     let address = AddressView::new(fr.address, arch_info.address_bits);
-    let relative_address = AddressView::new(fr.relative_address, arch_info.address_bits);
-    let module = fr.module.to_string();
-    let module_address = fr.module_address.to_string();
+
+    // GDB only knows the sections of modules with section headers, which stripped binaries can
+    // lack. The memory map still knows the module, so such frames are not left with an absolute
+    // address
+    let (module, relative_address, module_address) = match find_module_address(memory_map, fr.address) {
+        Some((name, offset)) if fr.module == "??" =>
+            (name.to_string(), offset, format!("{}+0x{:x}", name, offset)),
+        _ => (fr.module.to_string(), fr.relative_address, fr.module_address.to_string()),
+    };
+    let relative_address = AddressView::new(relative_address, arch_info.address_bits);
+    let region = find_memory_region(memory_map, fr.address);
     let symbol_reduced = fr.symbol.as_ref().map(|s| reduce_debugger_symbol(s));
     let srcctx = fr.symbol.as_ref().map(|s| build_source_context(s)).flatten();
 
@@ -393,6 +927,7 @@ fn build_frame_info(arch_info: &GdbArchInfo, fr: &GdbFrameInfo) -> EnrichedFrame
         relative_address,
         module,
         module_address,
+        region,
         symbol: symbol_reduced,
         source_context: srcctx,
     }
@@ -412,9 +947,12 @@ fn reduce_debugger_symbol(symbol: &Rc<GdbSymbol>) -> Rc<GdbSymbol> {
     })
 }
 
-fn build_stop_info(arch: &GdbArchInfo, stop_info: &GdbStopInfo) -> EnrichedLinuxStopInfo {
+fn build_stop_info(arch: &GdbArchInfo, stop_info: &GdbStopInfo, memory_map: &Option<Vec<GdbMemoryMapping>>) -> EnrichedLinuxStopInfo {
     let si_code_name = si_code_to_string(&stop_info.signal_name, stop_info.signal_code as i8).into();
     let faulting_address = stop_info.faulting_address.map(|a| AddressView::new(a, arch.address_bits));
+    let faulting_address_region = stop_info.faulting_address
+        .and_then(|a| find_memory_region(memory_map, a));
+    let fault_address_kind = classify_fault_address(arch, stop_info, faulting_address_region.as_ref());
 
     let summary = format!(
         "{} (si_signo={}) / {} (si_code={})",
@@ -431,6 +969,9 @@ fn build_stop_info(arch: &GdbArchInfo, stop_info: &GdbStopInfo) -> EnrichedLinux
         signal_code_name: si_code_name,
         signal_code: stop_info.signal_code,
         faulting_address,
+        faulting_address_region,
+        fault_access: None,
+        fault_address_kind,
     }
 }
 
@@ -467,15 +1008,19 @@ mod test {
 
         let report_options = ReportOptions {
             show_child_output: true,
-            child_output_lines: 25,
+            ..Default::default()
         };
 
         let envelope: ReportEnvelope = serde_json::from_value(envelope_value).unwrap();
 
         assert_eq!(ReportEnvelope {
+                        schema_version: 1,
                         command_line: vec!["./test".into(), "@@".into()],
                         testcase: "test.c".into(),
+                        preprocessed_testcase: None,
+                        decoded_testcase: None,
                         debugger: "gdb".into(),
+                        env: vec![],
                         bucket: CrashBucketInfo {
                             inputs: vec![
                               "/lib/x86_64-linux-gnu/libc-2.27.so+0x3efb7".into(),
@@ -489,12 +1034,170 @@ mod test {
                             ],
                             strategy: CrashBucketStrategy::afltriage,
                             strategy_result: "e7a73ec00e0f0d990e5a753f8f942622".into(),
+                            hash_algorithm: None,
                         },
                         report_options: report_options.clone(),
+                        triaged_at: None,
+                        triage_duration_ms: None,
+                        reproducibility: None,
+                        aslr: None,
+                        kernel_settings: None,
+                        warnings: vec![],
+                        privileges: None,
+                        afl_testcase: None,
+                        coverage: None,
                     }, envelope);
 
         let etriage = enrich_triage_info(&report_options, &triage).unwrap();
 
         assert_eq!(etriage_golden, etriage);
     }
+
+    #[test]
+    fn test_memory_region() {
+        let mapping = |start, end, name: &str| GdbMemoryMapping {
+            start,
+            end,
+            offset: 0,
+            permissions: None,
+            name: name.into(),
+        };
+
+        let memory_map = Some(vec![
+            mapping(0x1000, 0x2000, "/tmp/test"),
+            mapping(0x2000, 0x3000, "[heap]"),
+            mapping(0x5000, 0x6000, ""),
+            mapping(0x7000, 0x8000, "[stack]"),
+            mapping(0x9000, 0xa000, "[vdso]"),
+        ]);
+
+        let kind = |addr| find_memory_region(&memory_map, addr).unwrap().kind;
+
+        assert_eq!(kind(0x1000), MemoryRegionKind::module);
+        assert_eq!(kind(0x2000), MemoryRegionKind::heap);
+        assert_eq!(kind(0x3000), MemoryRegionKind::unmapped);
+        assert_eq!(kind(0x5fff), MemoryRegionKind::anonymous);
+        assert_eq!(kind(0x7800), MemoryRegionKind::stack);
+        assert_eq!(kind(0x9000), MemoryRegionKind::special);
+        assert_eq!(kind(0x0), MemoryRegionKind::unmapped);
+        assert_eq!(find_memory_region(&memory_map, 0x1000).unwrap().name, "/tmp/test");
+        assert!(find_memory_region(&None, 0x1000).is_none());
+    }
+
+    #[test]
+    fn test_fault_address_kind() {
+        let memory_map = Some(vec![
+            GdbMemoryMapping { start: 0x20000, end: 0x30000, offset: 0, permissions: None, name: "[heap]".into() },
+            GdbMemoryMapping { start: 0x40000, end: 0x50000, offset: 0, permissions: None, name: "/tmp/test".into() },
+        ]);
+        let arch: GdbArchInfo = serde_json::from_value(serde_json::json!({"address_bits": 64, "architecture": "i386:x86-64"})).unwrap();
+
+        let kind = |address: u64, signal_code| {
+            let stop_info = GdbStopInfo {
+                signal_name: "SIGSEGV".into(), signal_number: 11, signal_code, faulting_address: Some(address),
+            };
+            build_stop_info(&arch, &stop_info, &memory_map).fault_address_kind
+        };
+
+        assert_eq!(kind(0, 1), Some(FaultAddressKind::null));
+        assert_eq!(kind(0x18, 1), Some(FaultAddressKind::near_null));
+        assert_eq!(kind(0xfffffffffffffff8, 1), Some(FaultAddressKind::near_null));
+        assert_eq!(kind(0x20010, 2), Some(FaultAddressKind::heap));
+        assert_eq!(kind(0x40010, 2), Some(FaultAddressKind::mapped));
+        assert_eq!(kind(0x41414141, 1), Some(FaultAddressKind::wild));
+        assert_eq!(kind(0, 0x80), Some(FaultAddressKind::non_canonical));
+
+        let stop_info = build_stop_info(&arch, &GdbStopInfo {
+            signal_name: "SIGSEGV".into(), signal_number: 11, signal_code: 0x80, faulting_address: Some(0),
+        }, &memory_map);
+        assert!(!stop_info.is_null_dereference());
+        assert!(build_stop_info(&arch, &GdbStopInfo {
+            signal_name: "SIGSEGV".into(), signal_number: 11, signal_code: 1, faulting_address: Some(0x18),
+        }, &None).is_null_dereference());
+    }
+
+    #[test]
+    fn test_module_address() {
+        let mapping = |start, end, name: &str| GdbMemoryMapping {
+            start,
+            end,
+            offset: 0,
+            permissions: None,
+            name: name.into(),
+        };
+
+        let memory_map = Some(vec![
+            mapping(0x7f0000, 0x7f1000, "/usr/lib/libstripped.so"),
+            mapping(0x7f1000, 0x7f3000, "/usr/lib/libstripped.so"),
+            mapping(0x7f3000, 0x7f4000, ""),
+            mapping(0x7f5000, 0x7f6000, "[vdso]"),
+        ]);
+
+        assert_eq!(find_module_address(&memory_map, 0x7f2345), Some(("/usr/lib/libstripped.so", 0x2345)));
+        assert_eq!(find_module_address(&memory_map, 0x7f3010), None);
+        assert_eq!(find_module_address(&memory_map, 0x7f5010), None);
+
+        let arch_info: GdbArchInfo = serde_json::from_value(serde_json::json!({"address_bits": 64, "architecture": "x86-64"})).unwrap();
+        let frame: GdbFrameInfo = serde_json::from_value(serde_json::json!({
+            "address": 0x7f2345, "relative_address": 0x7f2345, "module": "??", "module_address": "0x7f2345",
+        })).unwrap();
+
+        let frame = build_frame_info(&arch_info, &frame, &memory_map);
+        assert_eq!(frame.module, "/usr/lib/libstripped.so");
+        assert_eq!(frame.module_address, "/usr/lib/libstripped.so+0x2345");
+        assert_eq!(frame.relative_address.r, 0x2345);
+    }
+
+    #[test]
+    fn test_glibc_check_failure() {
+        let mut triage: serde_json::Value = serde_json::from_str(&load_test("asan_stack_bof.rawjson")).unwrap();
+        triage["child"]["stderr"] = "*** stack smashing detected ***: terminated\n".into();
+        triage["response"]["context"]["primary_thread"]["backtrace"][5]["symbol"]["function_name"] = "__stack_chk_fail".into();
+        let triage: GdbTriageResult = serde_json::from_value(triage).unwrap();
+
+        let report_options = ReportOptions::default();
+
+        let etriage = enrich_triage_info(&report_options, &triage).unwrap();
+        assert_eq!(etriage.faulting_frame_idx, 6);
+        assert!(etriage.terse_summary.starts_with("GLIBC_stack-smashing_bad_read"));
+        assert!(etriage.summary.starts_with("GLIBC detected stack-smashing in bad_read"));
+        assert_eq!(crate::report::severity::rate_crash_severity(&etriage), crate::report::severity::CrashSeverity::high);
+    }
+
+    #[test]
+    fn test_other_threads() {
+        let mut triage: serde_json::Value = serde_json::from_str(&load_test("asan_stack_bof.rawjson")).unwrap();
+        let waiting = serde_json::json!([
+            {"address": 0x7ffff7a42fb7u64, "relative_address": 0x3efb7, "module": "/lib/x86_64-linux-gnu/libc-2.27.so",
+             "module_address": "/lib/x86_64-linux-gnu/libc-2.27.so+0x3efb7", "symbol": {"function_name": "pthread_cond_wait"}},
+            {"address": 0x4c8b9d, "relative_address": 0xc8b9d, "module": "/tmp/test", "module_address": "/tmp/test+0xc8b9d"},
+        ]);
+        triage["response"]["context"]["primary_thread"]["name"] = "test".into();
+        triage["response"]["context"]["other_threads"] = serde_json::json!([
+            {"tid": 1002, "name": "worker", "backtrace": waiting},
+            {"tid": 1003, "backtrace": []},
+            {"tid": 1004, "name": "worker", "backtrace": waiting},
+        ]);
+        let triage: GdbTriageResult = serde_json::from_value(triage).unwrap();
+
+        let report_options = ReportOptions::default();
+
+        let etriage = enrich_triage_info(&report_options, &triage).unwrap();
+        assert_eq!(etriage.faulting_thread.tid, Some(1));
+        assert_eq!(etriage.other_threads, Some(vec![
+            EnrichedThreadGroup {
+                tids: vec![1002, 1004],
+                name: Some("worker".into()),
+                frames: vec!["pthread_cond_wait (/lib/x86_64-linux-gnu/libc-2.27.so)".into(), "/tmp/test+0xc8b9d".into()],
+            },
+            EnrichedThreadGroup { tids: vec![1003], name: None, frames: vec![] },
+        ]));
+
+        let envelope: ReportEnvelope = serde_json::from_str(&load_test("asan_stack_bof.json")).unwrap();
+        let text = crate::report::text::format_text_report(&etriage, &envelope);
+        assert!(text.contains("Crashing thread backtrace (thread 1 \"test\"):\n#0 "));
+        assert!(text.contains("Other threads (3):\n\
+            Threads 1002, 1004 \"worker\" (2 with the same backtrace): in pthread_cond_wait (/lib/x86_64-linux-gnu/libc-2.27.so) <- /tmp/test+0xc8b9d\n\
+            Thread 1003: no backtrace\n"));
+    }
 }
@@ -0,0 +1,157 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Detecting aborts due to uncaught C++ exceptions.
+//!
+//! An exception without a handler makes `__cxa_throw()` call `std::terminate()`, which aborts.
+//! These crashes are not memory corruption, but would otherwise look like any other SIGABRT in the
+//! C++ runtime. The type of the exception is read by the triage script from its `std::type_info`,
+//! or taken from the message the C++ runtime prints along with the exception's `what()`:
+//!
+//! * libstdc++: `terminate called after throwing an instance of 'std::runtime_error'` followed by
+//!   `  what():  bad input`
+//! * libc++: `libc++abi: terminating due to uncaught exception of type std::runtime_error: bad input`
+//!
+//! The exception was never unwound, so the frame that threw it is still on the stack, calling
+//! `__cxa_throw()`.
+use crate::debugger::gdb::GdbException;
+use crate::report::enriched::EnrichedFrameInfo;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    static ref R_LIBSTDCXX_TERMINATE: Regex = Regex::new(
+        r#"(?m)^terminate called (after throwing an instance of '(?P<type>[^\r\n]+)'(\r?\n\s+what\(\):  (?P<what>[^\r\n]*))?|without an active exception)"#
+    ).unwrap();
+    static ref R_LIBCXX_TERMINATE: Regex = Regex::new(
+        r#"(?m)^libc\+\+abi: terminat(ing|e) (due to|with) uncaught exception of type (?P<type>[^\r\n:]+(::[^\r\n:]+)*)(: (?P<what>[^\r\n]*))?"#
+    ).unwrap();
+}
+
+/// `std::terminate()` and its libstdc++ and libc++ implementations
+const TERMINATE_FUNCTIONS: [&str; 3] = ["std::terminate", "__cxxabiv1::__terminate", "std::__terminate"];
+
+/// The functions that throw an exception for the frame calling them
+const THROW_FUNCTIONS: [&str; 3] = ["__cxa_throw", "__cxa_rethrow", "std::rethrow_exception"];
+
+/// An uncaught exception that terminated the target
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UncaughtException {
+    /// The type of the exception, like `std::runtime_error`. `None` when it is unknown, or when
+    /// `std::terminate()` was called without an active exception
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub exception_type: Option<String>,
+    /// The exception's `what()` message, if the C++ runtime printed it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub what: Option<String>,
+    /// The frame that threw the exception
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throw_frame: Option<usize>,
+}
+
+impl UncaughtException {
+    /// Whether an exception was thrown, as opposed to `std::terminate()` being called directly
+    pub fn thrown(&self) -> bool {
+        self.exception_type.is_some() || self.throw_frame.is_some()
+    }
+}
+
+fn frame_function(fr: &EnrichedFrameInfo) -> Option<&str> {
+    // Functions without debugging information are named with their arguments
+    fr.symbol.as_ref()
+        .and_then(|s| s.function_name.as_deref())
+        .map(|name| name.split('(').next().unwrap_or_default())
+}
+
+/// The frame calling the innermost of `functions`, if any
+fn find_caller_of(frames: &[EnrichedFrameInfo], functions: &[&str]) -> Option<usize> {
+    frames.iter()
+        .rposition(|fr| frame_function(fr).map(|f| functions.contains(&f)).unwrap_or(false))
+        .map(|i| i + 1)
+        .filter(|i| *i < frames.len())
+}
+
+/// Detect an uncaught exception from the exception the triage script found (`exception`), the
+/// target's `output`, and its backtrace
+pub fn detect_uncaught_exception(
+    exception: Option<&GdbException>,
+    output: &str,
+    frames: &[EnrichedFrameInfo],
+) -> Option<UncaughtException> {
+    let message = R_LIBSTDCXX_TERMINATE.captures_iter(output)
+        .chain(R_LIBCXX_TERMINATE.captures_iter(output))
+        .max_by_key(|c| c.get(0).unwrap().start());
+    let terminating = find_caller_of(frames, &TERMINATE_FUNCTIONS).is_some();
+
+    if exception.is_none() && message.is_none() && !terminating {
+        return None;
+    }
+
+    let exception_type = exception.and_then(|e| e.exception_type.clone())
+        .or_else(|| message.as_ref().and_then(|c| c.name("type")).map(|t| t.as_str().to_string()));
+
+    Some(UncaughtException {
+        exception_type,
+        what: message.as_ref().and_then(|c| c.name("what")).map(|w| w.as_str().to_string()),
+        throw_frame: find_caller_of(frames, &THROW_FUNCTIONS),
+    })
+}
+
+/// The frame that threw an uncaught exception, or else the frame that called `std::terminate()`
+pub fn find_exception_frame(exception: &UncaughtException, frames: &[EnrichedFrameInfo]) -> usize {
+    exception.throw_frame
+        .or_else(|| find_caller_of(frames, &TERMINATE_FUNCTIONS))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debugger::gdb::GdbSymbol;
+    use std::rc::Rc;
+
+    fn frame(function: &str) -> EnrichedFrameInfo {
+        let frame: EnrichedFrameInfo = serde_json::from_value(serde_json::json!({
+            "summary": "", "address": {"r": 0x401000, "f": "0x401000", "s": 64},
+            "relative_address": {"r": 0x1000, "f": "0x1000", "s": 64},
+            "module": "test", "module_address": "test+0x1000",
+        })).unwrap();
+        let symbol = GdbSymbol { function_name: Some(function.into()), ..Default::default() };
+        EnrichedFrameInfo { symbol: Some(Rc::new(symbol)), ..frame }
+    }
+
+    #[test]
+    fn test_uncaught_exception() {
+        let frames = vec![frame("raise"), frame("abort"), frame("__gnu_cxx::__verbose_terminate_handler()"),
+            frame("__cxxabiv1::__terminate(void (*)())"), frame("std::terminate()"), frame("__cxa_throw"),
+            frame("parse_config"), frame("main")];
+
+        let output = "terminate called after throwing an instance of 'std::runtime_error'\n  what():  bad input\n";
+        let exception = detect_uncaught_exception(None, output, &frames).unwrap();
+        assert_eq!(exception, UncaughtException {
+            exception_type: Some("std::runtime_error".into()),
+            what: Some("bad input".into()),
+            throw_frame: Some(6),
+        });
+        assert_eq!(find_exception_frame(&exception, &frames), 6);
+
+        let output = "libc++abi: terminating due to uncaught exception of type std::out_of_range: vector\n";
+        let exception = detect_uncaught_exception(None, output, &frames).unwrap();
+        assert_eq!(exception.exception_type.as_deref(), Some("std::out_of_range"));
+        assert_eq!(exception.what.as_deref(), Some("vector"));
+
+        // The triage script read the type, but the output was lost
+        let gdb = GdbException { exception_type: Some("ParseError".into()) };
+        let exception = detect_uncaught_exception(Some(&gdb), "", &frames).unwrap();
+        assert_eq!(exception.exception_type.as_deref(), Some("ParseError"));
+        assert_eq!(exception.what, None);
+
+        // std::terminate() called directly, e.g. by destroying a joinable std::thread
+        let frames = vec![frame("raise"), frame("abort"), frame("std::terminate()"), frame("std::thread::~thread()"), frame("main")];
+        let exception = detect_uncaught_exception(None, "terminate called without an active exception\n", &frames).unwrap();
+        assert!(!exception.thrown());
+        assert_eq!(find_exception_frame(&exception, &frames), 3);
+
+        assert_eq!(detect_uncaught_exception(None, "Aborted\n", &[frame("raise"), frame("abort"), frame("main")]), None);
+    }
+}
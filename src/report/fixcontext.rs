@@ -0,0 +1,239 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! A markdown "fix context" bundle for a crash bucket.
+//!
+//! Everything someone fixing a crash asks for first, in one document that can be pasted into a
+//! code review or an LLM-assisted fix workflow: the source of the culprit function, its callers,
+//! their arguments and locals, the sanitizer report, and the bytes of the input. The source of the
+//! culprit function is read from disk when available, so the bundle is best generated on the
+//! machine that built the target, or with `--substitute-path` pointing at a checkout of its source.
+use crate::debugger::gdb::{GdbSymbol, GdbVariable};
+use crate::report::enriched::{EnrichedFrameInfo, EnrichedTriageInfo};
+use crate::report::ReportEnvelope;
+use crate::sourcemap::SourcePathMap;
+use crate::util::shell_join;
+use std::path::Path;
+
+/// The most lines of the culprit function to include
+const MAX_FUNCTION_LINES: usize = 200;
+/// How many callers of the culprit frame to include
+const MAX_CALLERS: usize = 5;
+/// How many bytes of the input to include
+const MAX_INPUT_BYTES: usize = 512;
+
+/// A code fence language for a source file
+fn fence_language(file: &str) -> &'static str {
+    match Path::new(file).extension().and_then(|e| e.to_str()) {
+        Some("c") | Some("h") => "c",
+        Some("cc") | Some("cpp") | Some("cxx") | Some("hh") | Some("hpp") => "cpp",
+        Some("rs") => "rust",
+        Some("go") => "go",
+        _ => "",
+    }
+}
+
+/// Read the source of a function from disk, from its first line to the first closing brace in the
+/// first column after the frame's line. Returns the number of the first line and the lines
+fn read_function_source(symbol: &GdbSymbol, source_path_map: &SourcePathMap) -> Option<(usize, Vec<String>)> {
+    let file = symbol.file.as_ref()?;
+    let start = symbol.function_line.filter(|&l| l > 0)? as usize;
+    let source = std::fs::read_to_string(source_path_map.remap(file)).ok()?;
+    let lines: Vec<&str> = source.lines().collect();
+
+    if start > lines.len() {
+        return None;
+    }
+
+    let frame_line = symbol.line.map(|l| l as usize).unwrap_or(start).max(start);
+    let limit = std::cmp::min(start - 1 + MAX_FUNCTION_LINES, lines.len());
+    let end = (frame_line - 1..limit)
+        .find(|&i| lines[i].starts_with('}'))
+        .map(|i| i + 1)
+        .unwrap_or(limit);
+
+    Some((start, lines[start - 1..end].iter().map(|l| l.to_string()).collect()))
+}
+
+/// Source lines with line numbers, marking the line of the frame with `=>`
+fn format_source_lines<'a, I>(lines: I, frame_line: Option<i64>) -> String
+where
+    I: IntoIterator<Item = (usize, &'a str)>,
+{
+    let mut out = String::new();
+
+    for (line_no, line) in lines {
+        let marker = if Some(line_no as i64) == frame_line { "=>" } else { "  " };
+        out += &format!("{}{:>5}: {}\n", marker, line_no, line);
+    }
+
+    out
+}
+
+fn format_variables(title: &str, variables: &Option<Vec<std::rc::Rc<GdbVariable>>>) -> String {
+    match variables {
+        Some(vars) if !vars.is_empty() => {
+            let mut out = format!("{}:\n\n", title);
+
+            for var in vars {
+                out += &format!("- `{}`\n", var.format_decl());
+            }
+
+            out + "\n"
+        }
+        _ => String::new(),
+    }
+}
+
+fn frame_title(i: usize, frame: &EnrichedFrameInfo) -> String {
+    match &frame.symbol {
+        Some(symbol) if !symbol.format_file().is_empty() =>
+            format!("#{} `{}` at {}", i, symbol.format(), symbol.format_file()),
+        Some(symbol) if !symbol.format().is_empty() =>
+            format!("#{} `{}` in {}", i, symbol.format(), frame.module),
+        _ => format!("#{} {} in {}", i, frame.address.f, frame.module),
+    }
+}
+
+/// The source of a frame, either its whole function read from disk or the lines GDB captured
+fn format_frame_source(frame: &EnrichedFrameInfo, whole_function: bool, source_path_map: &SourcePathMap) -> String {
+    let symbol = match &frame.symbol {
+        Some(symbol) => symbol,
+        None => return String::new(),
+    };
+
+    let language = symbol.file.as_deref().map(fence_language).unwrap_or("");
+
+    let source = match read_function_source(symbol, source_path_map).filter(|_| whole_function) {
+        Some((start, lines)) => format_source_lines(
+            lines.iter().enumerate().map(|(i, l)| (start + i, l.as_str())), symbol.line),
+        None => match &frame.source_context {
+            Some(ctx) if !ctx.is_empty() => format_source_lines(
+                ctx.iter().map(|c| (c.line_no, c.source.as_str())), symbol.line),
+            _ => return String::new(),
+        },
+    };
+
+    format!("```{}\n{}```\n\n", language, source)
+}
+
+/// A hexdump in the style of `xxd`
+pub fn format_hexdump(data: &[u8]) -> String {
+    let mut out = String::new();
+
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk.iter()
+            .map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' })
+            .collect();
+
+        out += &format!("{:08x}: {:<47}  {}\n", i * 16, hex.join(" "), ascii);
+    }
+
+    out
+}
+
+fn format_input(envelope: &ReportEnvelope) -> String {
+    let path = envelope.preprocessed_testcase.as_ref().unwrap_or(&envelope.testcase);
+    let mut out = String::from("## Input\n\n");
+
+    if let Some(decoded) = &envelope.decoded_testcase {
+        out += &format!("Decoded as a protobuf message:\n\n```\n{}\n```\n\n", decoded);
+    }
+
+    match crate::util::read_file_to_bytes(path) {
+        Ok(data) => {
+            if data.len() > MAX_INPUT_BYTES {
+                out += &format!("{} bytes, the first {} are shown:\n\n", data.len(), MAX_INPUT_BYTES);
+            } else {
+                out += &format!("{} bytes:\n\n", data.len());
+            }
+
+            out += &format!("```\n{}```\n", format_hexdump(&data[..std::cmp::min(data.len(), MAX_INPUT_BYTES)]));
+        }
+        Err(e) => out += &format!("The input could not be read: {}\n", e),
+    }
+
+    out
+}
+
+/// Render the fix context bundle of a crash
+pub fn format_fix_context(einfo: &EnrichedTriageInfo, envelope: &ReportEnvelope) -> String {
+    let mut out = format!("# Fix context: {}\n\n", einfo.summary);
+
+    out += &format!("- Crash bucket: `{}`\n", envelope.bucket.strategy_result);
+    out += &format!("- Testcase: `{}`\n", shlex::quote(&envelope.testcase));
+    out += &format!("- Command line: `{}`\n\n", shell_join(&envelope.command_line));
+
+    let frames = &einfo.faulting_thread.frames;
+    let culprit_idx = einfo.faulting_frame_idx;
+
+    if let Some(culprit) = frames.get(culprit_idx) {
+        out += &format!("## Culprit: {}\n\n", frame_title(culprit_idx, culprit));
+
+        if let Some(symbol) = &culprit.symbol {
+            out += &format_frame_source(culprit, true, &envelope.report_options.source_path_map);
+            out += &format_variables("Arguments", &symbol.args);
+            out += &format_variables("Locals", &symbol.locals);
+        }
+    }
+
+    let callers: Vec<(usize, &EnrichedFrameInfo)> = frames.iter().enumerate()
+        .skip(culprit_idx + 1)
+        .filter(|(_, f)| f.symbol.is_some())
+        .take(MAX_CALLERS)
+        .collect();
+
+    if !callers.is_empty() {
+        out += "## Callers\n\n";
+
+        for (i, frame) in callers {
+            out += &format!("### {}\n\n", frame_title(i, frame));
+            out += &format_frame_source(frame, false, &envelope.report_options.source_path_map);
+
+            if let Some(symbol) = &frame.symbol {
+                out += &format_variables("Arguments", &symbol.args);
+                out += &format_variables("Locals", &symbol.locals);
+            }
+        }
+    }
+
+    if let Some(report) = einfo.sanitizer_reports.as_ref().and_then(|r| r.first()) {
+        out += &format!("## {} report\n\n```\n{}\n```\n\n", report.name_prefer_short(), report.body.trim_end());
+    }
+
+    out += &format_input(envelope);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::load_test_envelope;
+
+    #[test]
+    fn test_fix_context() {
+        let (einfo, mut envelope): (EnrichedTriageInfo, ReportEnvelope) = load_test_envelope();
+
+        let input = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(input.path(), b"AAAA\x00\x01").unwrap();
+        envelope.preprocessed_testcase = Some(input.path().to_string_lossy().to_string());
+
+        let bundle = format_fix_context(&einfo, &envelope);
+
+        assert!(bundle.starts_with(&format!("# Fix context: {}\n", einfo.summary)));
+        assert!(bundle.contains("## Culprit: #6 `bad_read` at test.c:20\n"));
+        assert!(bundle.contains("### #7 `main` at test.c:33\n"));
+        assert!(bundle.contains("## ASAN report\n"));
+        assert!(bundle.contains("6 bytes:\n\n```\n00000000: 41 41 41 41 00 01"));
+        assert!(bundle.ends_with("  AAAA..\n```\n"));
+    }
+
+    #[test]
+    fn test_hexdump() {
+        assert_eq!(format_hexdump(b""), "");
+        assert_eq!(format_hexdump(b"0123456789abcdefXY"),
+            "00000000: 30 31 32 33 34 35 36 37 38 39 61 62 63 64 65 66  0123456789abcdef\n\
+             00000010: 58 59                                            XY\n");
+    }
+}
@@ -0,0 +1,41 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Loading the test reports in `res/test_report_text`.
+//!
+//! The binary's tests include this file too, so it only names types through its callers.
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+
+/// The path of a test report file
+pub(crate) fn fixture_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::from(file!());
+    path.pop();
+    path.push("res");
+    path.push("test_report_text");
+    path.push(name);
+    path
+}
+
+/// Deserialize a JSON test report file
+pub(crate) fn load_fixture<T: DeserializeOwned>(name: &str) -> T {
+    serde_json::from_str(&std::fs::read_to_string(fixture_path(name)).unwrap()).unwrap()
+}
+
+/// The enriched report and the envelope around it of the ASAN stack buffer overflow
+pub(crate) fn load_test_envelope<R: DeserializeOwned, E: DeserializeOwned>() -> (R, E) {
+    let mut envelope: serde_json::Value = load_fixture("asan_stack_bof.json");
+    let report = serde_json::from_value(envelope.get_mut("report").unwrap().take()).unwrap();
+
+    (report, serde_json::from_value(envelope).unwrap())
+}
+
+/// The enriched report of the ASAN stack buffer overflow
+pub(crate) fn load_test_report<R: DeserializeOwned>() -> R {
+    load_test_envelope::<R, serde_json::Value>().0
+}
+
+/// The raw GDB triage result of the ASAN stack buffer overflow
+pub(crate) fn load_test_triage<T: DeserializeOwned>() -> T {
+    load_fixture("asan_stack_bof.rawjson")
+}
@@ -0,0 +1,202 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Parsing fatal errors of the Go runtime.
+//!
+//! GDB unwinds Go programs poorly: goroutines run on their own stacks, and a crash is usually a
+//! SIGABRT raised by the runtime on a system stack. The runtime prints a much better report of its
+//! own when a program panics or hits a fatal error, with a backtrace for each goroutine:
+//!
+//! ```text
+//! panic: runtime error: index out of range [5] with length 3
+//!
+//! goroutine 1 [running]:
+//! main.parse(...)
+//!         /src/main.go:10
+//! main.main()
+//!         /src/main.go:20 +0x1d
+//! ```
+//!
+//! The goroutine printed first is the one that failed. Its frames outside of the Go runtime are
+//! used for bucketing in place of GDB's backtrace. AFLTriage runs targets with `GOTRACEBACK=crash`
+//! unless it is set, so that Go programs abort instead of exiting with status 2, which GDB would
+//! not see as a crash.
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    static ref R_GO_ERROR: Regex = Regex::new(
+        r#"(?m)^(?P<kind>panic|fatal error): (?P<msg>[^\r\n]*)\r?$"#).unwrap();
+    /// Go 1.23 and later print the goroutine and thread addresses before the state
+    static ref R_GOROUTINE: Regex = Regex::new(
+        r#"^goroutine (?P<id>[0-9]+)( [^\[]*)? \[(?P<state>[^\]]*)\]:$"#).unwrap();
+    static ref R_GO_FUNCTION: Regex = Regex::new(
+        r#"^(?P<created>created by )?(?P<func>[^\s(]\S*?)(\([^()]*\))?( in goroutine [0-9]+)?$"#).unwrap();
+    static ref R_GO_LOCATION: Regex = Regex::new(r#"^\t(?P<file>\S+):(?P<line>[0-9]+)"#).unwrap();
+}
+
+/// A frame of a goroutine backtrace
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GoFrame {
+    pub function: String,
+    pub file: String,
+    pub line: u64,
+}
+
+impl GoFrame {
+    /// Whether the frame is in the Go runtime, which every panic or fatal error goes through
+    pub fn is_runtime(&self) -> bool {
+        self.function.starts_with("runtime.") || self.function == "panic"
+    }
+}
+
+/// A goroutine of a Go runtime backtrace
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Goroutine {
+    pub id: u64,
+    /// The state of the goroutine, like `running` or `chan receive`
+    pub state: String,
+    pub frames: Vec<GoFrame>,
+    /// Where the goroutine was started, if it is not the main goroutine
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<GoFrame>,
+}
+
+/// A panic or fatal error of the Go runtime
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GoFatalError {
+    /// `panic` or `fatal error`
+    pub kind: String,
+    /// The error, like `runtime error: index out of range [5] with length 3`
+    pub message: String,
+    /// Every goroutine the runtime printed, the failing one first
+    pub goroutines: Vec<Goroutine>,
+}
+
+impl GoFatalError {
+    /// The frames of the failing goroutine outside of the Go runtime
+    pub fn failing_frames(&self) -> Vec<&GoFrame> {
+        self.goroutines.first().iter()
+            .flat_map(|g| g.frames.iter())
+            .filter(|fr| !fr.is_runtime())
+            .collect()
+    }
+
+    /// The frame signatures hashed by bucketing
+    pub fn frame_signatures(&self) -> Vec<String> {
+        self.failing_frames().iter().map(|fr| format!("{}:{}", fr.file, fr.line)).collect()
+    }
+}
+
+/// Parse the backtrace of a goroutine from `lines`, after its header
+fn parse_goroutine<'a>(id: u64, state: &str, lines: &mut impl Iterator<Item = &'a str>) -> Goroutine {
+    let mut goroutine = Goroutine { id, state: state.to_string(), frames: vec![], created_by: None };
+
+    while let Some(line) = lines.next() {
+        let line = line.trim_end_matches('\r');
+
+        let function = match R_GO_FUNCTION.captures(line) {
+            Some(function) => function,
+            // The goroutine ends with an empty line
+            None => break,
+        };
+
+        let location = match lines.next().and_then(|l| R_GO_LOCATION.captures(l)) {
+            Some(location) => location,
+            None => break,
+        };
+
+        let frame = GoFrame {
+            function: function["func"].to_string(),
+            file: location["file"].to_string(),
+            line: location["line"].parse().unwrap_or(0),
+        };
+
+        if function.name("created").is_some() {
+            goroutine.created_by = Some(frame);
+        } else {
+            goroutine.frames.push(frame);
+        }
+    }
+
+    goroutine
+}
+
+/// Extract the first panic or fatal error of the Go runtime and its goroutines from the target's
+/// output
+pub fn go_fatal_error_extract(output: &str) -> Option<GoFatalError> {
+    let error = R_GO_ERROR.captures(output)?;
+    let mut lines = output[error.get(0).unwrap().end()..].lines();
+    let mut goroutines = vec![];
+
+    while let Some(line) = lines.next() {
+        if let Some(header) = R_GOROUTINE.captures(line.trim_end_matches('\r')) {
+            let id = header["id"].parse().unwrap_or(0);
+            goroutines.push(parse_goroutine(id, &header["state"], &mut lines));
+        }
+    }
+
+    // A line starting with "panic: " alone could be anything
+    if goroutines.is_empty() {
+        return None;
+    }
+
+    Some(GoFatalError {
+        kind: error["kind"].to_string(),
+        message: error["msg"].to_string(),
+        goroutines,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_go_fatal_error() {
+        let output = "fatal error: concurrent map writes\n\n\
+            goroutine 18 gp=0xc000102380 m=3 mp=0xc000100008 [running]:\n\
+            runtime.throw({0x4b2c5a?, 0x0?})\n\
+            \t/usr/local/go/src/runtime/panic.go:1047 +0x5d fp=0xc000047f28 sp=0xc000047ef8 pc=0x432b1d\n\
+            runtime.mapassign_faststr(0x0?, 0x0?, {0x4b1b0e, 0x3})\n\
+            \t/usr/local/go/src/runtime/map_faststr.go:205 +0x3be\n\
+            main.worker(...)\n\
+            \t/src/main.go:12\n\
+            created by main.main in goroutine 1\n\
+            \t/src/main.go:20 +0x3e\n\n\
+            goroutine 1 [sleep]:\n\
+            time.Sleep(0x3b9aca00)\n\
+            \t/usr/local/go/src/runtime/time.go:195 +0x135\n\
+            main.main()\n\
+            \t/src/main.go:22 +0x4a\n";
+
+        let error = go_fatal_error_extract(output).unwrap();
+        assert_eq!(error.kind, "fatal error");
+        assert_eq!(error.message, "concurrent map writes");
+        assert_eq!(error.goroutines.len(), 2);
+        assert_eq!(error.goroutines[0].id, 18);
+        assert_eq!(error.goroutines[0].state, "running");
+        assert_eq!(error.goroutines[0].frames.len(), 3);
+        assert_eq!(error.goroutines[0].created_by,
+            Some(GoFrame { function: "main.main".into(), file: "/src/main.go".into(), line: 20 }));
+        assert_eq!(error.goroutines[1].state, "sleep");
+        assert_eq!(error.frame_signatures(), vec!["/src/main.go:12"]);
+
+        let output = "panic: runtime error: index out of range [5] with length 3\n\n\
+            goroutine 1 [running]:\n\
+            main.parse(...)\n\
+            \t/src/main.go:10\n\
+            main.(*Parser).Run(0xc000010000)\n\
+            \t/src/main.go:15 +0x25\n\
+            main.main()\n\
+            \t/src/main.go:20 +0x1d\n\
+            exit status 2\n";
+        let error = go_fatal_error_extract(output).unwrap();
+        assert_eq!(error.kind, "panic");
+        assert_eq!(error.failing_frames()[0].function, "main.parse");
+        assert_eq!(error.failing_frames()[1].function, "main.(*Parser).Run");
+        assert_eq!(error.frame_signatures(), vec!["/src/main.go:10", "/src/main.go:15", "/src/main.go:20"]);
+
+        assert_eq!(go_fatal_error_extract("panic: something\n"), None);
+    }
+}
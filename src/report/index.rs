@@ -0,0 +1,187 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! An aggregate report indexing every unique crash bucket found during triage.
+//!
+//! Buckets are ordered by [CrashSeverity], then with flaky buckets after reproducible ones, and
+//! then by how many crashes landed in them, so the most interesting findings come first. The text index starts with a table of contents that
+//! refers to each bucket's section by number.
+use crate::report::severity::CrashSeverity;
+use crate::report::Reproducibility;
+use crate::util::shell_join;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The bucket index file in an output directory
+pub const INDEX_FILE: &str = "afltriage_index.txt";
+
+/// A unique crash bucket and the testcases that landed in it
+#[derive(Debug, PartialEq)]
+pub struct BucketEntry {
+    /// The bucket identifier (the bucketing strategy result or a crash ID)
+    pub bucket: String,
+    /// The crash summary of the first testcase in the bucket
+    pub summary: String,
+    /// The severity of the first testcase in the bucket
+    pub severity: CrashSeverity,
+    /// All testcases in this bucket, in the order they were triaged
+    pub testcases: Vec<String>,
+    /// The file names of the reports written for this bucket, if any
+    pub reports: Vec<String>,
+    /// How often the first testcase reproduced the crash, if it was re-run
+    pub reproducibility: Option<Reproducibility>,
+}
+
+impl BucketEntry {
+    fn is_flaky(&self) -> bool {
+        self.reproducibility.map(|r| r.is_flaky()).unwrap_or(false)
+    }
+}
+
+/// Order bucket entries by severity (highest first), then flaky buckets last, then by crash count
+/// (largest first). Ties are broken by the bucket identifier to keep the order stable between runs.
+pub fn sort_bucket_entries(entries: &mut [&BucketEntry]) {
+    entries.sort_by(|a, b| {
+        b.severity.cmp(&a.severity)
+            .then(a.is_flaky().cmp(&b.is_flaky()))
+            .then(b.testcases.len().cmp(&a.testcases.len()))
+            .then(a.bucket.cmp(&b.bucket))
+    });
+}
+
+/// Render a text index of all `entries` (which should already be sorted with
+/// [sort_bucket_entries]) for a triage run of `command_line`.
+pub fn format_text_index(entries: &[&BucketEntry], command_line: &[String]) -> String {
+    let total_crashes: usize = entries.iter().map(|e| e.testcases.len()).sum();
+    let index_width = format!("{}", entries.len()).len() + 2;
+
+    let mut toc = String::new();
+    let mut sections = String::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        let anchor = format!("[{}]", i + 1);
+
+        toc += &format!("{:<index_width$} {:<7} ({} crash{}{}) {}\n",
+            anchor, entry.severity.as_str().to_uppercase(),
+            entry.testcases.len(), if entry.testcases.len() == 1 { "" } else { "es" },
+            if entry.is_flaky() { ", flaky" } else { "" },
+            entry.summary, index_width = index_width);
+
+        sections += &format!("{} {}\n", anchor, entry.summary);
+        sections += &format!("Severity: {}\n", entry.severity.as_str());
+        sections += &format!("Crash bucket: {}\n", entry.bucket);
+        sections += &format!("Crashes: {}\n", entry.testcases.len());
+
+        if let Some(reproducibility) = &entry.reproducibility {
+            sections += &format!("Reproducibility: {}\n", reproducibility.summary());
+        }
+
+        if !entry.reports.is_empty() {
+            sections += &format!("Reports: {}\n", entry.reports.join(", "));
+        }
+
+        sections += "Testcases:\n";
+
+        for tc in &entry.testcases {
+            sections += &format!("  {}\n", shlex::quote(tc));
+        }
+
+        sections += "\n";
+    }
+
+    let mut report = format!(
+        "Command line: {}\nUnique crash buckets: {} ({} crashes)\n\n",
+        shell_join(command_line), entries.len(), total_crashes
+    );
+
+    if !entries.is_empty() {
+        report += &format!("Table of contents:\n{}\n{}", toc, sections);
+    }
+
+    report = report.trim_end().to_string();
+    report += "\n";
+    report
+}
+
+/// Write the bucket index to `output_dir`, or to the terminal if there is none
+pub fn write_bucket_index(buckets: &HashMap<String, BucketEntry>, command_line: &[String], output_dir: Option<&Path>) {
+    let mut entries: Vec<&BucketEntry> = buckets.values().collect();
+    sort_bucket_entries(&mut entries);
+
+    let index = format_text_index(&entries, command_line);
+
+    match output_dir {
+        Some(output_dir) => {
+            let index_path = output_dir.join(INDEX_FILE);
+
+            if let Err(e) = std::fs::write(&index_path, index) {
+                log::error!("Failed to write the bucket index: {}", e);
+            } else {
+                log::info!("Wrote bucket index to {}", shlex::quote(&index_path.to_string_lossy()));
+            }
+        }
+        None => println!("--- INDEX REPORT BEGIN ---\n{}\n--- INDEX REPORT END ---", index),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(bucket: &str, severity: CrashSeverity, count: usize) -> BucketEntry {
+        BucketEntry {
+            bucket: bucket.into(),
+            summary: format!("Summary {}", bucket),
+            severity,
+            testcases: (0..count).map(|i| format!("tc_{}_{}", bucket, i)).collect(),
+            reports: vec![format!("afltriage_{}.txt", bucket)],
+            reproducibility: None,
+        }
+    }
+
+    #[test]
+    fn test_bucket_ordering() {
+        let a = entry("a", CrashSeverity::low, 10);
+        let b = entry("b", CrashSeverity::high, 1);
+        let c = entry("c", CrashSeverity::high, 3);
+        let d = entry("d", CrashSeverity::unknown, 50);
+        let e = entry("e", CrashSeverity::low, 10);
+
+        let mut entries = vec![&a, &b, &c, &d, &e];
+        sort_bucket_entries(&mut entries);
+
+        let order: Vec<&str> = entries.iter().map(|e| e.bucket.as_str()).collect();
+        assert_eq!(order, vec!["c", "b", "a", "e", "d"]);
+
+        let index = format_text_index(&entries, &["./test".into(), "@@".into()]);
+        let lines: Vec<&str> = index.lines().collect();
+
+        assert_eq!(lines[0], "Command line: ./test @@");
+        assert_eq!(lines[1], "Unique crash buckets: 5 (74 crashes)");
+        assert_eq!(lines[3], "Table of contents:");
+        assert_eq!(lines[4], "[1] HIGH    (3 crashes) Summary c");
+        assert_eq!(lines[5], "[2] HIGH    (1 crash) Summary b");
+        assert_eq!(lines[10], "[1] Summary c");
+        assert!(index.ends_with("  tc_d_49\n"));
+    }
+
+    #[test]
+    fn test_flaky_bucket_ordering() {
+        let mut a = entry("a", CrashSeverity::high, 10);
+        a.reproducibility = Some(Reproducibility { reruns: 4, reproduced: 1 });
+        let mut b = entry("b", CrashSeverity::high, 1);
+        b.reproducibility = Some(Reproducibility { reruns: 4, reproduced: 4 });
+        let c = entry("c", CrashSeverity::low, 1);
+
+        let mut entries = vec![&a, &b, &c];
+        sort_bucket_entries(&mut entries);
+
+        let order: Vec<&str> = entries.iter().map(|e| e.bucket.as_str()).collect();
+        assert_eq!(order, vec!["b", "a", "c"]);
+
+        let index = format_text_index(&entries, &["./test".into()]);
+        assert!(index.contains("[2] HIGH    (10 crashes, flaky) Summary a\n"));
+        assert!(index.contains("Reproducibility: 1/4 reruns crashed in the same bucket (25%), FLAKY\n"));
+        assert!(index.contains("Reproducibility: 4/4 reruns crashed in the same bucket (100%)\n"));
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! GitHub issues for crash buckets.
+//!
+//! `afltriage issues` files an issue for each new bucket of a run, or renders it ready to paste.
+//! The title names the crash's classification and the function to blame, and the body has the
+//! backtrace, the sanitizer report, and a command to reproduce the crash. Buckets that already
+//! have an issue are recorded in an [IssueState] file, which can be shared between runs so a bucket
+//! is only filed once.
+use crate::report::campaign::crash_classification;
+use crate::report::enriched::EnrichedTriageInfo;
+use crate::report::severity::CrashSeverity;
+use crate::report::ReportEnvelope;
+use crate::util::{elide_size, shell_join};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The default issue state file in an output directory
+pub const ISSUE_STATE_FILE: &str = "afltriage_issues.json";
+
+/// The most frames of the backtrace to include
+const MAX_ISSUE_FRAMES: usize = 24;
+/// The most characters of the sanitizer report to include. GitHub limits issue bodies to 65536
+const MAX_SANITIZER_REPORT: usize = 16384;
+
+/// A rendered issue
+#[derive(Debug, PartialEq)]
+pub struct Issue {
+    pub title: String,
+    /// The body, in GitHub flavored markdown
+    pub body: String,
+}
+
+/// The issue of a bucket that was filed or rendered
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IssueRecord {
+    pub title: String,
+    /// The URL of the filed issue
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// The file the issue was rendered to, if it was not filed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// When the issue was filed or rendered, as an ISO-8601 timestamp
+    pub created_at: String,
+}
+
+/// The buckets that already have an issue
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct IssueState {
+    pub issues: BTreeMap<String, IssueRecord>,
+}
+
+impl IssueState {
+    /// Read the state from `path`. A missing file is an empty state
+    pub fn load(path: &Path) -> Result<IssueState, String> {
+        match std::fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).map_err(|e| e.to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(IssueState::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, serde_json::to_string_pretty(self).unwrap()).map_err(|e| e.to_string())
+    }
+
+    /// Whether `bucket` already has an issue. With `filed`, only issues filed on GitHub count, so
+    /// rendering a bucket's issue does not keep it from being filed later
+    pub fn contains(&self, bucket: &str, filed: bool) -> bool {
+        self.issues.get(bucket).is_some_and(|record| !filed || record.url.is_some())
+    }
+}
+
+/// A shell command reproducing the crash, from the directory the target was triaged in
+pub fn repro_command(envelope: &ReportEnvelope) -> String {
+    let testcase = envelope.preprocessed_testcase.as_ref().unwrap_or(&envelope.testcase);
+    let args: Vec<&str> = envelope.command_line.iter().map(|s| s.as_str()).collect();
+
+    // Variables are quoted after the = so the shell still treats them as assignments
+    let mut command: Vec<String> = envelope.env.iter()
+        .map(|var| match var.split_once('=') {
+            Some((name, value)) => format!("{}={}", name, shlex::quote(value)),
+            None => var.to_string(),
+        })
+        .collect();
+    command.push(shell_join(&crate::util::expand_filepath_templates(&args, testcase)));
+    let mut command = command.join(" ");
+
+    if !args.iter().any(|a| a.contains("@@")) {
+        command += &format!(" < {}", shlex::quote(testcase));
+    }
+
+    command
+}
+
+/// Render the issue of a crash bucket, rated as `severity`
+pub fn format_issue(einfo: &EnrichedTriageInfo, envelope: &ReportEnvelope, severity: CrashSeverity) -> Issue {
+    let classification = crash_classification(einfo);
+    let title = format!("{} in {}", classification, einfo.faulting_function);
+
+    let mut body = format!("{}\n\n", einfo.summary);
+    body += &format!("- Classification: {} (severity {})\n", classification, severity.as_str());
+    body += &format!("- Crash bucket: `{}`\n", envelope.bucket.strategy_result);
+
+    if let Some(reproducibility) = &envelope.reproducibility {
+        body += &format!("- Reproducibility: {}\n", reproducibility.summary());
+    }
+
+    let frames = &einfo.faulting_thread.frames;
+
+    if !frames.is_empty() {
+        body += "\n### Backtrace\n\n```\n";
+
+        for (i, frame) in frames.iter().enumerate().take(MAX_ISSUE_FRAMES) {
+            let culprit = if i == einfo.faulting_frame_idx { "  <- blamed" } else { "" };
+            body += &format!("#{} {}{}\n", i, frame.summary, culprit);
+        }
+
+        if frames.len() > MAX_ISSUE_FRAMES {
+            body += &format!("... {} more frames\n", frames.len() - MAX_ISSUE_FRAMES);
+        }
+
+        body += "```\n";
+    }
+
+    if let Some(report) = einfo.sanitizer_reports.as_ref().and_then(|r| r.first()) {
+        body += &format!("\n<details>\n<summary>{} report</summary>\n\n```\n{}\n```\n\n</details>\n",
+            report.name_prefer_short(), elide_size(report.body.trim_end(), MAX_SANITIZER_REPORT));
+    }
+
+    body += &format!("\n### Reproduce\n\n```\n{}\n```\n", repro_command(envelope));
+
+    Issue { title, body }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::load_test_envelope;
+
+    #[test]
+    fn test_format_issue() {
+        let (einfo, mut envelope): (EnrichedTriageInfo, ReportEnvelope) = load_test_envelope();
+        envelope.env = vec!["ASAN_OPTIONS=abort_on_error=1".into()];
+
+        let issue = format_issue(&einfo, &envelope, CrashSeverity::high);
+
+        assert_eq!(issue.title, "ASAN stack-buffer-overflow in bad_read");
+        assert!(issue.body.starts_with(&format!("{}\n\n- Classification: ASAN stack-buffer-overflow (severity high)\n", einfo.summary)));
+        assert!(issue.body.contains("#6 0x00000000005122d8 in bad_read (/tmp/test)  <- blamed\n"));
+        assert!(issue.body.contains("<summary>ASAN report</summary>"));
+        assert!(issue.body.ends_with("### Reproduce\n\n```\nASAN_OPTIONS=\"abort_on_error=1\" ./test test.c\n```\n"));
+
+        envelope.command_line = vec!["./test".into()];
+        assert_eq!(repro_command(&envelope), "ASAN_OPTIONS=\"abort_on_error=1\" ./test < test.c");
+    }
+
+    #[test]
+    fn test_issue_state_render_then_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(ISSUE_STATE_FILE);
+
+        // A run without --repo renders the issue
+        let mut state = IssueState::load(&path).unwrap();
+        assert!(!state.contains("abc123", false));
+        state.issues.insert("abc123".into(), IssueRecord {
+            title: "ASAN stack-buffer-overflow in bad_read".into(),
+            url: None,
+            file: Some("afltriage_bad_read_abc123.issue.md".into()),
+            created_at: "2021-06-01T12:00:00Z".into(),
+        });
+        state.save(&path).unwrap();
+
+        // A later run with --repo still files it, and only once
+        let mut state = IssueState::load(&path).unwrap();
+        assert!(state.contains("abc123", false));
+        assert!(!state.contains("abc123", true));
+        state.issues.insert("abc123".into(), IssueRecord {
+            title: "ASAN stack-buffer-overflow in bad_read".into(),
+            url: Some("https://github.com/example/project/issues/1".into()),
+            file: None,
+            created_at: "2021-06-02T12:00:00Z".into(),
+        });
+        state.save(&path).unwrap();
+
+        assert!(IssueState::load(&path).unwrap().contains("abc123", true));
+    }
+}
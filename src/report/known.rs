@@ -0,0 +1,189 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! A database of crash buckets seen across triage runs.
+//!
+//! Fuzzing campaigns are re-run, and each new cycle finds most of the same crashes again. With
+//! `--known-buckets FILE`, every bucket a run finds is recorded in a JSON database, and with
+//! `--ignore-known` the buckets already in it are not reported, so only genuinely new crashes
+//! surface. Point every campaign at the same file (e.g. from a configuration file) to share it.
+//!
+//! Known buckets can be shared between machines with `afltriage known`, which exports them as a
+//! plain list of bucket identifiers, one per line, and imports such lists or other databases.
+//! Bucket identifiers are only comparable between runs that use the same bucketing strategy and
+//! hash algorithm.
+use crate::report::severity::CrashSeverity;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A bucket seen in a previous run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KnownBucket {
+    /// The crash summary of the first testcase seen in the bucket
+    #[serde(default)]
+    pub summary: String,
+    #[serde(default = "unknown_severity")]
+    pub severity: CrashSeverity,
+    /// When the bucket was first and last seen, as ISO-8601 timestamps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<String>,
+    /// How many runs found the bucket
+    #[serde(default)]
+    pub runs: usize,
+    /// How many crashes landed in the bucket, over all runs
+    #[serde(default)]
+    pub crashes: usize,
+}
+
+fn unknown_severity() -> CrashSeverity {
+    CrashSeverity::unknown
+}
+
+/// The known bucket database, keyed by bucket identifier
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KnownBuckets {
+    pub buckets: BTreeMap<String, KnownBucket>,
+}
+
+impl KnownBuckets {
+    /// Read a database. A missing file is an empty database
+    pub fn load(path: &Path) -> Result<KnownBuckets, String> {
+        match std::fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).map_err(|e| e.to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(KnownBuckets::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Write the database, replacing the previous one at once so a concurrent reader never sees
+    /// half of it
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let tmp_path = path.with_extension("tmp");
+
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(self).unwrap())
+            .and_then(|_| std::fs::rename(&tmp_path, path))
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn contains(&self, bucket: &str) -> bool {
+        self.buckets.contains_key(bucket)
+    }
+
+    /// Record that a run at `now` found `crashes` crashes in a bucket. Returns whether the bucket
+    /// was new
+    pub fn record(&mut self, bucket: &str, summary: &str, severity: CrashSeverity, crashes: usize, now: &str) -> bool {
+        let mut new = false;
+
+        let known = self.buckets.entry(bucket.to_string()).or_insert_with(|| {
+            new = true;
+            KnownBucket {
+                summary: summary.to_string(),
+                severity,
+                first_seen: Some(now.to_string()),
+                last_seen: None,
+                runs: 0,
+                crashes: 0,
+            }
+        });
+
+        known.last_seen = Some(now.to_string());
+        known.runs += 1;
+        known.crashes += crashes;
+        new
+    }
+
+    /// Merge another database, or a list of bucket identifiers one per line. Returns how many
+    /// buckets were new
+    pub fn import(&mut self, data: &str) -> Result<usize, String> {
+        let other = if data.trim_start().starts_with('{') {
+            serde_json::from_str(data).map_err(|e| e.to_string())?
+        } else {
+            KnownBuckets {
+                buckets: data.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|bucket| (bucket.to_string(), KnownBucket {
+                        summary: String::new(),
+                        severity: CrashSeverity::unknown,
+                        first_seen: None,
+                        last_seen: None,
+                        runs: 0,
+                        crashes: 0,
+                    }))
+                    .collect(),
+            }
+        };
+
+        let mut imported = 0;
+
+        for (bucket, known) in other.buckets {
+            if let Some(existing) = self.buckets.get_mut(&bucket) {
+                existing.runs += known.runs;
+                existing.crashes += known.crashes;
+
+                if existing.summary.is_empty() {
+                    existing.summary = known.summary;
+                    existing.severity = known.severity;
+                }
+
+                // ISO-8601 timestamps in the same timezone sort chronologically
+                if known.first_seen.is_some() && (existing.first_seen.is_none() || known.first_seen < existing.first_seen) {
+                    existing.first_seen = known.first_seen;
+                }
+
+                if known.last_seen > existing.last_seen {
+                    existing.last_seen = known.last_seen;
+                }
+            } else {
+                self.buckets.insert(bucket, known);
+                imported += 1;
+            }
+        }
+
+        Ok(imported)
+    }
+
+    /// The bucket identifiers, one per line
+    pub fn export_list(&self) -> String {
+        self.buckets.keys().map(|bucket| format!("{}\n", bucket)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_known_buckets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known.json");
+
+        let mut known = KnownBuckets::load(&path).unwrap();
+        assert!(known.buckets.is_empty());
+
+        assert!(known.record("abc", "CRASH in a", CrashSeverity::high, 3, "2021-06-01T00:00:00Z"));
+        assert!(!known.record("abc", "CRASH in a", CrashSeverity::high, 2, "2021-06-02T00:00:00Z"));
+        known.save(&path).unwrap();
+
+        let mut known = KnownBuckets::load(&path).unwrap();
+        let abc = &known.buckets["abc"];
+        assert_eq!((abc.runs, abc.crashes), (2, 5));
+        assert_eq!(abc.first_seen.as_deref(), Some("2021-06-01T00:00:00Z"));
+        assert_eq!(abc.last_seen.as_deref(), Some("2021-06-02T00:00:00Z"));
+
+        assert_eq!(known.import("# exported\nabc\ndef\n\n").unwrap(), 1);
+        assert!(known.contains("def"));
+        assert_eq!(known.export_list(), "abc\ndef\n");
+
+        let mut other = KnownBuckets::default();
+        other.record("abc", "CRASH in a", CrashSeverity::high, 1, "2021-05-01T00:00:00Z");
+        other.record("ghi", "CRASH in g", CrashSeverity::low, 1, "2021-05-01T00:00:00Z");
+        assert_eq!(known.import(&serde_json::to_string(&other).unwrap()).unwrap(), 1);
+        assert_eq!(known.buckets["abc"].first_seen.as_deref(), Some("2021-05-01T00:00:00Z"));
+        assert_eq!(known.buckets["abc"].runs, 3);
+        assert_eq!(known.buckets["ghi"].summary, "CRASH in g");
+    }
+}
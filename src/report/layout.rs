@@ -0,0 +1,149 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! How reports are laid out in an output directory.
+//!
+//! By default every report is written directly to the output directory. With the `buckets` layout,
+//! each unique bucket gets its own directory instead, named like its reports, which can be handed
+//! to a developer on its own:
+//!
+//! ```text
+//! afltriage_SIGSEGV_bad_read_0123abcd/
+//!   afltriage_SIGSEGV_bad_read_0123abcd.txt   reports of the bucket's first testcase
+//!   afltriage_SIGSEGV_bad_read_0123abcd.min   the minimized testcase, with --minimize
+//!   bucket.json                               the bucket's metadata and testcase provenance
+//!   testcases/                                every member testcase, linked or copied
+//! ```
+//!
+//! Run-level files, like the index and CSV summaries, stay in the output directory.
+use crate::report::index::BucketEntry;
+use crate::report::provenance::{format_bucket_json, TestcaseProvenance};
+use clap::arg_enum;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// The metadata file in a bucket directory
+pub const BUCKET_METADATA_FILE: &str = "bucket.json";
+/// The directory of member testcases in a bucket directory
+pub const BUCKET_TESTCASES_DIR: &str = "testcases";
+
+arg_enum! {
+    /// Where reports are written in the output directory
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[allow(non_camel_case_types)]
+    pub enum OutputLayout {
+        flat,
+        buckets,
+    }
+}
+
+impl OutputLayout {
+    /// The path of a report, relative to the output directory, for a report file name `stem`
+    pub fn report_path(&self, stem: &str, extension: &str) -> String {
+        match self {
+            OutputLayout::flat => format!("{}.{}", stem, extension),
+            OutputLayout::buckets => format!("{}/{}.{}", stem, stem, extension),
+        }
+    }
+}
+
+/// The directory of a bucket, relative to the output directory: where its reports are
+fn bucket_directory(entry: &BucketEntry) -> String {
+    entry.reports.first()
+        .and_then(|r| Path::new(r).parent())
+        .map(|p| p.to_string_lossy().to_string())
+        .filter(|p| !p.is_empty())
+        .unwrap_or_else(|| format!("afltriage_{}", crate::util::sanitize(&entry.bucket)))
+}
+
+/// A unique file name for a member testcase
+fn testcase_filename(testcase: &str, index: usize, used: &mut HashSet<String>) -> String {
+    let name = Path::new(testcase).file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "testcase".into());
+
+    // Testcases of different AFL instances often have the same name
+    let name = if used.contains(&name) { format!("{}_{}", index, name) } else { name };
+    used.insert(name.to_string());
+    name
+}
+
+/// Fill the directory of a bucket with its metadata and its member testcases. Testcases are
+/// symlinked unless `copy` is set
+pub fn write_bucket_directory(
+    output_dir: &Path,
+    entry: &BucketEntry,
+    provenance: &HashMap<String, TestcaseProvenance>,
+    copy: bool,
+) -> std::io::Result<()> {
+    let dir = output_dir.join(bucket_directory(entry));
+    let testcases_dir = dir.join(BUCKET_TESTCASES_DIR);
+    std::fs::create_dir_all(&testcases_dir)?;
+
+    std::fs::write(dir.join(BUCKET_METADATA_FILE), format_bucket_json(entry, provenance))?;
+
+    let mut used = HashSet::new();
+
+    for (i, testcase) in entry.testcases.iter().enumerate() {
+        let target = testcases_dir.join(testcase_filename(testcase, i, &mut used));
+
+        if target.symlink_metadata().is_ok() {
+            std::fs::remove_file(&target)?;
+        }
+
+        if copy {
+            std::fs::copy(testcase, &target)?;
+        } else {
+            std::os::unix::fs::symlink(std::fs::canonicalize(testcase)?, &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::severity::CrashSeverity;
+
+    #[test]
+    fn test_bucket_directory() {
+        let inputs = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let testcases: Vec<String> = ["a", "b"].iter().map(|d| {
+            let path = inputs.path().join(d).join("id:000000,sig:11");
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, d).unwrap();
+            path.to_string_lossy().to_string()
+        }).collect();
+
+        let layout = OutputLayout::buckets;
+        let entry = BucketEntry {
+            bucket: "abc".into(),
+            summary: "A summary".into(),
+            severity: CrashSeverity::high,
+            testcases: testcases.clone(),
+            reports: vec![layout.report_path("afltriage_SIGSEGV_abc", "txt")],
+            reproducibility: None,
+        };
+        assert_eq!(entry.reports[0], "afltriage_SIGSEGV_abc/afltriage_SIGSEGV_abc.txt");
+        assert_eq!(OutputLayout::flat.report_path("afltriage_SIGSEGV_abc", "txt"), "afltriage_SIGSEGV_abc.txt");
+
+        write_bucket_directory(output.path(), &entry, &HashMap::new(), false).unwrap();
+        // Written again, e.g. into an existing output directory
+        write_bucket_directory(output.path(), &entry, &HashMap::new(), false).unwrap();
+
+        let dir = output.path().join("afltriage_SIGSEGV_abc");
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.join(BUCKET_METADATA_FILE)).unwrap()).unwrap();
+        assert_eq!(json["bucket"], "abc");
+
+        let first = dir.join(BUCKET_TESTCASES_DIR).join("id:000000,sig:11");
+        assert!(first.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read_to_string(first).unwrap(), "a");
+        assert_eq!(std::fs::read_to_string(dir.join(BUCKET_TESTCASES_DIR).join("1_id:000000,sig:11")).unwrap(), "b");
+
+        let unreported = BucketEntry { reports: vec![], ..entry };
+        write_bucket_directory(output.path(), &unreported, &HashMap::new(), true).unwrap();
+        assert!(output.path().join("afltriage_abc").join(BUCKET_TESTCASES_DIR).join("id:000000,sig:11").is_file());
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Size limits for JSON reports.
+//!
+//! Some crashes produce huge reports, such as deep recursion with many locals or a target that
+//! floods its output. Stores with row or message size limits reject these. When a report is larger
+//! than its limit, details are dropped in order of least importance until it fits:
+//!
+//! 1. Other threads
+//! 1. The memory map
+//! 1. The loaded modules
+//! 1. Locals, arguments, and source context of frames, starting from the outermost frame of the
+//!    faulting thread
+//! 1. The target's output
+//! 1. Frames after the faulting frame, starting from the outermost
+//!
+//! A truncated report has `"truncated": true` and a `truncation` list describing what was dropped.
+use serde_json::Value;
+
+/// Where the droppable parts of a report live, as JSON pointers
+pub struct ReportLayout {
+    pub other_threads: Option<&'static str>,
+    pub memory_map: Option<&'static str>,
+    pub modules: Option<&'static str>,
+    /// The frames of the faulting thread
+    pub frames: &'static str,
+    /// How many leading frames to always keep
+    pub keep_frames: usize,
+    pub child_output: &'static str,
+}
+
+/// The layout of a `json` report
+pub fn enriched_layout(faulting_frame_idx: usize) -> ReportLayout {
+    ReportLayout {
+        other_threads: Some("/report/other_threads"),
+        memory_map: None,
+        modules: Some("/report/modules"),
+        frames: "/report/faulting_thread/frames",
+        keep_frames: faulting_frame_idx + 1,
+        child_output: "/report/target_output",
+    }
+}
+
+/// The layout of a `rawjson` report
+pub fn raw_layout() -> ReportLayout {
+    ReportLayout {
+        other_threads: Some("/report/response/context/other_threads"),
+        memory_map: Some("/report/response/context/memory_map"),
+        modules: Some("/report/response/context/modules"),
+        frames: "/report/response/context/primary_thread/backtrace",
+        keep_frames: 1,
+        child_output: "/report/child",
+    }
+}
+
+fn json_size(value: &Value) -> usize {
+    serde_json::to_string_pretty(value).map(|s| s.len()).unwrap_or(0)
+}
+
+/// Remove the value at a JSON `pointer`. Returns the size of what was removed, which is a lower
+/// bound of how much smaller the report is (indentation is not counted)
+fn remove_pointer(value: &mut Value, pointer: &str) -> Option<usize> {
+    let (parent, key) = pointer.rsplit_once('/')?;
+
+    value.pointer_mut(parent)
+        .and_then(Value::as_object_mut)
+        .and_then(|obj| obj.remove(key))
+        .map(|removed| json_size(&removed))
+}
+
+/// Drop parts of a report `value` as described by `layout` until its pretty printed size is at
+/// most `max_size` bytes. Returns what was dropped, if anything. The report may still be too large
+/// if everything droppable was dropped
+pub fn limit_report_size(value: &mut Value, layout: &ReportLayout, max_size: usize) -> Vec<String> {
+    let mut dropped = vec![];
+    let mut size = json_size(value);
+
+    if size <= max_size {
+        return dropped;
+    }
+
+    for (pointer, name) in [(layout.other_threads, "other threads"), (layout.memory_map, "memory map"),
+            (layout.modules, "modules")].iter() {
+        if pointer.and_then(|p| remove_pointer(value, p)).is_some() {
+            dropped.push(name.to_string());
+            size = json_size(value);
+
+            if size <= max_size {
+                return dropped;
+            }
+        }
+    }
+
+    // Estimate the savings from what was removed and only measure the whole report again once
+    // enough should have been removed. Measuring after every frame is too slow for deep stacks
+    let frame_count = value.pointer(layout.frames).and_then(Value::as_array).map(Vec::len).unwrap_or(0);
+    let mut stripped = 0;
+    let mut removed = 0;
+
+    for i in (0..frame_count).rev() {
+        let mut frame_removed = 0;
+
+        for key in &["symbol/locals", "symbol/args", "symbol/callsite", "source_context"] {
+            frame_removed += remove_pointer(value, &format!("{}/{}/{}", layout.frames, i, key)).unwrap_or(0);
+        }
+
+        if frame_removed > 0 {
+            stripped += 1;
+            removed += frame_removed;
+
+            if size - removed.min(size) <= max_size {
+                size = json_size(value);
+                removed = 0;
+
+                if size <= max_size {
+                    break;
+                }
+            }
+        }
+    }
+
+    if stripped > 0 {
+        dropped.push(format!("locals and source context of {} frame(s)", stripped));
+        size = json_size(value);
+
+        if size <= max_size {
+            return dropped;
+        }
+    }
+
+    if remove_pointer(value, layout.child_output).is_some() {
+        dropped.push("target output".to_string());
+        size = json_size(value);
+
+        if size <= max_size {
+            return dropped;
+        }
+    }
+
+    let total = value.pointer(layout.frames).and_then(Value::as_array).map(Vec::len).unwrap_or(0);
+    let mut kept = total;
+
+    while size > max_size && kept > layout.keep_frames {
+        let frames = value.pointer_mut(layout.frames).and_then(Value::as_array_mut).unwrap();
+        let mut removed = 0;
+
+        while kept > layout.keep_frames && size - removed.min(size) > max_size {
+            removed += frames.pop().map(|f| json_size(&f)).unwrap_or(0);
+            kept -= 1;
+        }
+
+        size = json_size(value);
+    }
+
+    if kept < total {
+        dropped.push(format!("{} frame(s)", total - kept));
+    }
+
+    dropped
+}
+
+/// Limit a report and mark it as truncated if anything was dropped
+pub fn limit_and_mark(value: &mut Value, layout: &ReportLayout, max_size: usize) {
+    let dropped = limit_report_size(value, layout, max_size);
+
+    if dropped.is_empty() {
+        return;
+    }
+
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert("truncated".into(), Value::Bool(true));
+        obj.insert("truncation".into(), Value::from(dropped));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn big_report(frames: usize) -> Value {
+        let frames: Vec<Value> = (0..frames).map(|i| json!({
+            "summary": format!("#{} frame", i),
+            "symbol": {
+                "function_name": format!("f{}", i),
+                "locals": [{"name": "buf", "type": "char[64]", "value": "A".repeat(200)}],
+            },
+        })).collect();
+
+        json!({
+            "testcase": "tc",
+            "report": {
+                "summary": "A crash",
+                "faulting_thread": {"frames": frames},
+                "target_output": {"stdout": "x".repeat(2000), "stderr": ""},
+            },
+        })
+    }
+
+    #[test]
+    fn test_limit_report_size() {
+        let layout = enriched_layout(1);
+
+        let mut report = big_report(20);
+        let original = report.clone();
+        limit_and_mark(&mut report, &layout, 1_000_000);
+        assert_eq!(report, original);
+
+        // Only the locals of the outermost frames need to go
+        let mut report = big_report(20);
+        let target = json_size(&report) - 1000;
+        limit_and_mark(&mut report, &layout, target);
+        assert!(json_size(&report) <= target);
+        assert_eq!(report["truncated"], true);
+        assert!(report.pointer("/report/faulting_thread/frames/0/symbol/locals").is_some());
+        assert!(report.pointer("/report/faulting_thread/frames/19/symbol/locals").is_none());
+        assert!(report.pointer("/report/target_output").is_some());
+
+        // Everything droppable goes, but the faulting frame stays
+        let mut report = big_report(20);
+        limit_and_mark(&mut report, &layout, 10);
+        assert_eq!(report["truncation"], json!([
+            "locals and source context of 20 frame(s)", "target output", "18 frame(s)"]));
+        assert_eq!(report.pointer("/report/faulting_thread/frames").unwrap().as_array().unwrap().len(), 2);
+    }
+}
@@ -2,6 +2,321 @@
 //
 // SPDX-License-Identifier: BSD-3-Clause
 //! Report generation
+use clap::arg_enum;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use crate::bucket::CrashBucketInfo;
+use crate::coverage::Coverage;
+use crate::sourcemap::SourcePathMap;
+use crate::debugger::gdb::{GdbChildOutput, GdbTriageResult};
+use crate::platform::kernel::{KernelSettings, SettingWarning};
+use crate::platform::privileges::Privileges;
+use enriched::EnrichedTriageInfo;
+use retention::RetentionPolicy;
+
 pub mod sanitizer;
 pub mod text;
 pub mod enriched;
+pub mod severity;
+pub mod index;
+pub mod layout;
+pub mod retention;
+pub mod csv;
+pub mod sql;
+pub mod summary;
+pub mod limit;
+pub mod fixcontext;
+pub mod nextsteps;
+pub mod provenance;
+pub mod campaign;
+pub mod unwind;
+pub mod stackoverflow;
+pub mod assertion;
+pub mod exception;
+pub mod panic;
+pub mod golang;
+pub mod schema;
+pub mod template;
+pub mod casr;
+pub mod issue;
+pub mod elastic;
+pub mod known;
+pub mod diff;
+pub mod cwe;
+pub mod access;
+pub mod redact;
+#[cfg(test)]
+pub(crate) mod fixtures;
+
+// arg_enum! doesn't support docstrings...
+arg_enum! {
+    /// The output formats supported by AFLTriage
+    #[derive(PartialEq, Debug, Clone, Copy)]
+    // these are user controlable options so follow normal cmdline conventions
+    #[allow(non_camel_case_types)]
+    pub enum ReportOutputFormat {
+        // A simple, but opinionated text report. Don't parse this directly, choose JSON or raw instead, as the output format is unversioned
+        text,
+        // Opinionated fields derived from the raw triage output
+        json,
+        // Unfiltered JSON output from the triage script and child process
+        // Heavily dependent on the debugging backend
+        rawjson,
+        // Spreadsheet friendly summaries of every crash and bucket. Written once per run
+        csv,
+        // An SQL script of every crash, frame, and bucket for loading into SQLite. Written once per run
+        sql,
+        // A markdown bundle of the culprit's source, callers, variables, and input for fixing a crash
+        fixcontext,
+        // A GDB script that breaks at the culprit frame and prints what it references, for investigating a crash
+        nextsteps,
+        // An HTML index page of the campaign summary and every bucket, linking to its reports. Written once per run
+        html,
+        // A CASR crash report, for deduplicating and clustering with casr-cluster
+        casr,
+    }
+}
+
+/// Metadata for a report that can act as a wrapper around data from a debugger
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportEnvelope {
+    /// The version of the report format. See [schema]
+    #[serde(default = "schema::legacy_schema_version")]
+    pub schema_version: u32,
+    pub command_line: Vec<String>,
+    pub testcase: String,
+    /// The input given to the target, if the testcase was pre-processed with --preprocess
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preprocessed_testcase: Option<String>,
+    /// The testcase decoded as a protobuf message in the text format, with --proto
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded_testcase: Option<String>,
+    pub debugger: String,
+    /// Environment variables explicitly set for the target
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    pub bucket: CrashBucketInfo,
+    pub report_options: ReportOptions,
+    /// When the testcase was triaged, as an ISO-8601 timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub triaged_at: Option<String>,
+    /// How long triaging the testcase took, measured with a monotonic clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub triage_duration_ms: Option<u64>,
+    /// How often the crash reproduced when re-run with --rerun
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reproducibility: Option<Reproducibility>,
+    /// Whether the target ran with address space layout randomization
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aslr: Option<bool>,
+    /// The kernel settings during triage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel_settings: Option<KernelSettings>,
+    /// Kernel settings that plausibly changed the behavior of this crash
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<SettingWarning>,
+    /// The privileges GDB and the target were started with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privileges: Option<Privileges>,
+    /// The fields of the testcase's AFL file name, like its mutation operator and discovery time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub afl_testcase: Option<provenance::AflTestcaseName>,
+    /// The code of the target binary the testcase covered, with --coverage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<Coverage>,
+}
+
+/// How often a crash reproduced in the same bucket when its testcase was re-run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Reproducibility {
+    pub reruns: usize,
+    /// Reruns that crashed in the same bucket as the first run
+    pub reproduced: usize,
+}
+
+impl Reproducibility {
+    /// The fraction of reruns that reproduced the crash, from 0.0 to 1.0
+    pub fn score(&self) -> f64 {
+        if self.reruns == 0 {
+            1.0
+        } else {
+            self.reproduced as f64 / self.reruns as f64
+        }
+    }
+
+    /// A crash is flaky if any rerun did not reproduce it, for example due to a race condition or
+    /// uninitialized memory
+    pub fn is_flaky(&self) -> bool {
+        self.reproduced < self.reruns
+    }
+
+    pub fn summary(&self) -> String {
+        format!("{}/{} reruns crashed in the same bucket ({:.0}%){}",
+            self.reproduced, self.reruns, self.score() * 100.0,
+            if self.is_flaky() { ", FLAKY" } else { "" })
+    }
+}
+
+/// A fully stringified report ready to be written to an output
+pub struct RenderedReport {
+    pub data: String,
+    pub format: ReportOutputFormat,
+    pub extension: &'static str,
+}
+
+/// Options controlling the output of reports. Start from [ReportOptions::default], as options may
+/// be added
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ReportOptions {
+    pub show_child_output: bool,
+    pub child_output_lines: usize,
+    /// The largest a JSON report may be, in bytes. See [limit]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_json_size: Option<usize>,
+    /// Where reports read the source files of a build done elsewhere from, with --substitute-path
+    #[serde(default, skip_serializing_if = "SourcePathMap::is_empty")]
+    pub source_path_map: SourcePathMap,
+}
+
+impl Default for ReportOptions {
+    /// The defaults of the command line
+    fn default() -> ReportOptions {
+        ReportOptions {
+            show_child_output: false,
+            child_output_lines: 25,
+            max_json_size: None,
+            source_path_map: SourcePathMap::default(),
+        }
+    }
+}
+
+/// The file in an output directory that --save-results writes to
+pub const SAVED_RESULTS_FILE: &str = "afltriage_results.jsonl";
+
+/// A crash saved with --save-results, one per line of [SAVED_RESULTS_FILE]. This keeps the raw
+/// triage result so crashes can be re-bucketed without running GDB again
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedResult {
+    /// The bucket identifier the crash was assigned
+    pub bucket: String,
+    /// The reports written for this crash, if it was the first in its bucket
+    pub reports: Vec<String>,
+    pub envelope: ReportEnvelope,
+    pub triage: GdbTriageResult,
+}
+
+/// Append a [SavedResult] as a line of JSON
+pub fn write_saved_result<W: Write>(writer: &mut W, saved: &SavedResult) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *writer, saved)?;
+    writeln!(writer)
+}
+
+/// The report file name for a bucket, without the format extension
+pub fn report_filename_stem(etriage: &EnrichedTriageInfo, bucket: &str) -> String {
+    format!("afltriage_{}_{}",
+            crate::util::sanitize(&etriage.terse_summary),
+            crate::util::sanitize(bucket))
+}
+
+/// Render the reports for a crash, keeping only the artifacts selected by its retention `policy`.
+/// Text reports use `text_template`, or the built-in layout if `None`
+pub fn render_reports(
+    policy: &RetentionPolicy,
+    severity: severity::CrashSeverity,
+    mut etriage: EnrichedTriageInfo,
+    triage: &GdbTriageResult,
+    envelope: &ReportEnvelope,
+    text_template: Option<&template::TextTemplate>,
+) -> Vec<RenderedReport> {
+    let mut envelope = envelope.clone();
+
+    if !policy.child_output {
+        etriage.target_output = None;
+        envelope.report_options.show_child_output = false;
+    }
+
+    let mut rendered_reports = vec![];
+
+    if policy.report_formats.contains(&ReportOutputFormat::text) {
+        let text_report = match text_template {
+            Some(template) => text::format_text_report_with(template, &etriage, &envelope),
+            None => text::format_text_report(&etriage, &envelope),
+        };
+        rendered_reports.push(RenderedReport {
+            data: text_report,
+            format: ReportOutputFormat::text,
+            extension: "txt"
+        });
+    }
+    if policy.report_formats.contains(&ReportOutputFormat::json) {
+        let mut report_val = serde_json::to_value(&etriage).unwrap();
+
+        if let Some(cwe) = cwe::classify_cwe(&etriage) {
+            report_val.as_object_mut().unwrap().insert("cwe".into(), serde_json::to_value(cwe).unwrap());
+        }
+
+        let mut wrapper_val = serde_json::to_value(&envelope).unwrap();
+        wrapper_val.as_object_mut().unwrap().insert("report".into(), report_val);
+
+        if let Some(max_size) = envelope.report_options.max_json_size {
+            limit::limit_and_mark(&mut wrapper_val, &limit::enriched_layout(etriage.faulting_frame_idx), max_size);
+        }
+
+        let rendered = serde_json::to_string_pretty(&wrapper_val).unwrap();
+
+        rendered_reports.push(RenderedReport {
+            data: rendered,
+            format: ReportOutputFormat::json,
+            extension: "json",
+        });
+    }
+    if policy.report_formats.contains(&ReportOutputFormat::rawjson) {
+        let mut report_val = serde_json::to_value(triage).unwrap();
+
+        if !policy.child_output {
+            report_val["child"] = serde_json::to_value(GdbChildOutput::default()).unwrap();
+        }
+
+        let mut wrapper_val = serde_json::to_value(&envelope).unwrap();
+        wrapper_val.as_object_mut().unwrap().insert("report".into(), report_val);
+
+        if let Some(max_size) = envelope.report_options.max_json_size {
+            limit::limit_and_mark(&mut wrapper_val, &limit::raw_layout(), max_size);
+        }
+
+        let rendered = serde_json::to_string_pretty(&wrapper_val).unwrap();
+
+        rendered_reports.push(RenderedReport {
+            data: rendered,
+            format: ReportOutputFormat::rawjson,
+            extension: "rawjson",
+        });
+    }
+
+    if policy.report_formats.contains(&ReportOutputFormat::fixcontext) {
+        rendered_reports.push(RenderedReport {
+            data: fixcontext::format_fix_context(&etriage, &envelope),
+            format: ReportOutputFormat::fixcontext,
+            extension: "md",
+        });
+    }
+
+    if policy.report_formats.contains(&ReportOutputFormat::nextsteps) {
+        rendered_reports.push(RenderedReport {
+            data: nextsteps::format_next_steps(&etriage, &envelope),
+            format: ReportOutputFormat::nextsteps,
+            extension: "gdb",
+        });
+    }
+
+    if policy.report_formats.contains(&ReportOutputFormat::casr) {
+        rendered_reports.push(RenderedReport {
+            data: casr::format_casr_report(&etriage, &envelope, severity),
+            format: ReportOutputFormat::casr,
+            extension: "casrep",
+        });
+    }
+
+    rendered_reports
+}
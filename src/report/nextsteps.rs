@@ -0,0 +1,184 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! A GDB script of next steps for investigating a crash bucket.
+//!
+//! Triage ends where an analyst's manual deep-dive begins: breaking in the culprit function,
+//! printing the variables on the culprit line, and watching the memory that went wrong. The script
+//! is generated from the structured result, so it can be run with `gdb -x` from the directory the
+//! target was triaged in. Steps that stop the target more than once, such as watchpoints, are left
+//! commented out for the analyst to enable.
+use crate::debugger::gdb::GdbVariable;
+use crate::report::enriched::{EnrichedFrameInfo, EnrichedTriageInfo};
+use crate::report::ReportEnvelope;
+use crate::util::shell_join;
+use std::rc::Rc;
+
+/// The most variables of the culprit line to print or watch
+const MAX_VARIABLES: usize = 8;
+
+/// Whether a GDB type is a pointer, which is worth watching instead of the variable itself
+fn is_pointer_type(ty: &str) -> bool {
+    ty.trim_end().ends_with('*')
+}
+
+/// The variables referenced on the culprit frame's line, without duplicates
+fn culprit_references(frame: &EnrichedFrameInfo) -> Vec<Rc<GdbVariable>> {
+    let line = frame.symbol.as_ref().and_then(|s| s.line);
+    let mut vars: Vec<Rc<GdbVariable>> = vec![];
+
+    let referenced = frame.source_context.iter().flatten()
+        .filter(|c| Some(c.line_no as i64) == line)
+        .flat_map(|c| c.references.iter().flatten());
+
+    for var in referenced {
+        if !vars.iter().any(|v| v.name == var.name) {
+            vars.push(Rc::clone(var));
+        }
+    }
+
+    vars.truncate(MAX_VARIABLES);
+    vars
+}
+
+/// Where to break for a frame, if GDB can name it
+fn breakpoint_location(frame: &EnrichedFrameInfo) -> Option<String> {
+    let symbol = frame.symbol.as_ref()?;
+
+    match (&symbol.file, symbol.line, &symbol.function_name) {
+        (Some(file), Some(line), _) if line > 0 => Some(format!("{}:{}", file, line)),
+        (_, _, Some(function)) => Some(function.to_string()),
+        _ => None,
+    }
+}
+
+fn format_run(envelope: &ReportEnvelope) -> String {
+    let testcase = envelope.preprocessed_testcase.as_ref().unwrap_or(&envelope.testcase);
+    let mut out = String::new();
+
+    let program = match envelope.command_line.first() {
+        Some(program) => program,
+        None => return out,
+    };
+
+    let args: Vec<&str> = envelope.command_line[1..].iter().map(|s| s.as_str()).collect();
+    let mut args = shell_join(&crate::util::expand_filepath_templates(&args, testcase));
+
+    if !envelope.command_line.iter().any(|a| a.contains("@@")) {
+        args += &format!(" < {}", shlex::quote(testcase));
+    }
+
+    out += &format!("file {}\n", program);
+    out += &format!("set args {}\n", args.trim_start());
+
+    for var in &envelope.env {
+        out += &format!("set environment {}\n", var);
+    }
+
+    for command in envelope.report_options.source_path_map.gdb_commands() {
+        out += &format!("{}\n", command);
+    }
+
+    match envelope.aslr {
+        Some(true) => out += "set disable-randomization off\n",
+        _ => out += "set disable-randomization on\n",
+    }
+
+    out
+}
+
+/// Render the next steps of a crash as a GDB script
+pub fn format_next_steps(einfo: &EnrichedTriageInfo, envelope: &ReportEnvelope) -> String {
+    let mut out = format!("# Next steps: {}\n", einfo.summary);
+
+    out += &format!("# Crash bucket: {}\n", envelope.bucket.strategy_result);
+    out += &format!("# Testcase: {}\n", shlex::quote(&envelope.testcase));
+    out += "# Run with: gdb -x <this file>\n\n";
+    out += &format_run(envelope);
+
+    let culprit_idx = einfo.faulting_frame_idx;
+    let culprit = einfo.faulting_thread.frames.get(culprit_idx);
+    let references = culprit.map(culprit_references).unwrap_or_default();
+
+    match culprit.and_then(breakpoint_location) {
+        Some(location) => {
+            out += &format!("\n# Stop at the culprit frame #{} before it crashes\n", culprit_idx);
+            out += "# If it is reached many times before the crash, use 'ignore 1 COUNT' or 'condition 1 EXPR'\n";
+            out += &format!("break {}\n", location);
+            out += "run\n";
+
+            if !references.is_empty() {
+                out += "\n# Variables referenced on the culprit line\n";
+
+                for var in &references {
+                    out += &format!("print {}\n", var.name);
+                }
+            }
+
+            out += "info args\ninfo locals\n";
+
+            let pointers: Vec<&Rc<GdbVariable>> = references.iter()
+                .filter(|v| is_pointer_type(&v.r#type))
+                .collect();
+
+            if !pointers.is_empty() {
+                out += "\n# Find out who last wrote the memory the culprit line uses\n";
+
+                for var in pointers {
+                    out += &format!("# watch -l *{}\n", var.name);
+                }
+            }
+
+            out += "\n# Let the target crash\ncontinue\n";
+        }
+        None => out += "\n# The culprit frame has no symbol to break at, so run until the crash\nrun\n",
+    }
+
+    out += "\n# Inspect the crash\n";
+    out += &format!("frame {}\n", culprit_idx);
+    out += "x/i $pc\n";
+
+    let regs: Vec<&str> = einfo.faulting_thread.instruction_context.iter()
+        .flat_map(|insns| insns.first())
+        .flat_map(|insn| insn.referenced_regs.iter().flatten())
+        .map(|r| r.name.as_str())
+        .collect();
+
+    if !regs.is_empty() {
+        out += &format!("info registers {}\n", regs.join(" "));
+    }
+
+    if let Some(address) = &einfo.stop_info.faulting_address {
+        out += &format!("\n# The faulting address\ninfo symbol {}\nx/16xb {}\n", address.f, address.f);
+    }
+
+    out += "bt\n";
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::load_test_envelope;
+
+    #[test]
+    fn test_next_steps() {
+        let (einfo, mut envelope): (EnrichedTriageInfo, ReportEnvelope) = load_test_envelope();
+        envelope.env = vec!["ASAN_OPTIONS=abort_on_error=1".into()];
+
+        let script = format_next_steps(&einfo, &envelope);
+
+        assert!(script.starts_with(&format!("# Next steps: {}\n", einfo.summary)));
+        assert!(script.contains(&format!("file ./test\nset args {}\n", shlex::quote(&envelope.testcase))));
+        assert!(script.contains("set environment ASAN_OPTIONS=abort_on_error=1\nset disable-randomization on\n"));
+        assert!(script.contains("break test.c:20\nrun\n"));
+        assert!(script.contains("print c\nprint arr\ninfo args\ninfo locals\n"));
+        assert!(!script.contains("watch"));
+        assert!(script.contains("frame 6\nx/i $pc\n"));
+        assert!(script.ends_with("bt\n"));
+
+        envelope.command_line = vec!["./test".into(), "-".into()];
+        assert!(format_next_steps(&einfo, &envelope)
+            .contains(&format!("set args - < {}\n", shlex::quote(&envelope.testcase))));
+    }
+}
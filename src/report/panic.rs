@@ -0,0 +1,133 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Recognizing Rust panics.
+//!
+//! Fuzzing harnesses for Rust, like cargo-fuzz, abort on a panic. The crash is a SIGABRT deep in
+//! the panic runtime, where every panic has the same frames. The panic message and location are
+//! taken from what the default panic hook prints, either
+//! `thread 'main' panicked at src/main.rs:10:5:` followed by the message (Rust 1.73 and later), or
+//! `thread 'main' panicked at 'the message', src/main.rs:10:5` before that. The faulting frame is
+//! the first one after the panic runtime, which is where the target panicked, so bucketing skips
+//! the panic machinery.
+use crate::report::enriched::EnrichedFrameInfo;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    static ref R_PANIC: Regex = Regex::new(
+        r#"(?m)^thread '(?P<thread>[^'\r\n]*)' panicked at (?P<file>[^\r\n']+?):(?P<line>[0-9]+):(?P<column>[0-9]+):\r?\n(?P<msg>[^\r\n]*)"#
+    ).unwrap();
+    static ref R_OLD_PANIC: Regex = Regex::new(
+        r#"(?m)^thread '(?P<thread>[^'\r\n]*)' panicked at '(?P<msg>[^\r\n]*)', (?P<file>[^\r\n]+?):(?P<line>[0-9]+):(?P<column>[0-9]+)\r?$"#
+    ).unwrap();
+    /// Frames of the panic runtime. The `std::rt` and `std::panicking::try` frames every Rust
+    /// program starts in are not included
+    static ref R_PANIC_FRAME: Regex = Regex::new(
+        r#"^((std|core)::panicking::(begin_panic|panic|rust_panic|default_hook|assert_failed)|std::panicking::rust_panic|(std|core)::panic::panic_|rust_begin_unwind|rust_panic|__rustc::|__rust_(start_panic|end_short_backtrace|abort)|std::sys(_common)?::backtrace::__rust_end_short_backtrace|panic_abort::|std::process::abort|std::sys::.*abort_internal|core::(result|option)::(unwrap|expect)_failed|core::(result|option)::(Result|Option)<.*>::(unwrap|expect)|core::slice::index::slice_|core::str::slice_error_fail|core::intrinsics::abort)"#
+    ).unwrap();
+    /// Frames of the C library aborting for the panic runtime
+    static ref R_ABORT_FRAME: Regex = Regex::new(
+        r#"^(__GI_)*(raise|abort|pthread_kill|__pthread_kill|__pthread_kill_implementation|__pthread_kill_internal)$"#
+    ).unwrap();
+}
+
+/// A panic of a Rust target
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RustPanic {
+    /// The panic message, or its first line
+    pub message: String,
+    pub file: String,
+    pub line: u64,
+    pub column: u64,
+    /// The name of the thread that panicked
+    pub thread: String,
+}
+
+/// Extract the last panic from the target's output
+pub fn panic_extract(output: &str) -> Option<RustPanic> {
+    R_PANIC.captures_iter(output)
+        .chain(R_OLD_PANIC.captures_iter(output))
+        .max_by_key(|c| c.get(0).unwrap().start())
+        .and_then(|c| Some(RustPanic {
+            message: c["msg"].to_string(),
+            file: c["file"].to_string(),
+            line: c["line"].parse().ok()?,
+            column: c["column"].parse().ok()?,
+            thread: c["thread"].to_string(),
+        }))
+}
+
+fn function_matches(fr: &EnrichedFrameInfo, regex: &Regex) -> bool {
+    fr.symbol.as_ref()
+        .and_then(|s| s.function_name.as_ref())
+        .map(|name| regex.is_match(name))
+        .unwrap_or(false)
+}
+
+fn is_panic_frame(fr: &EnrichedFrameInfo) -> bool {
+    function_matches(fr, &R_PANIC_FRAME) || function_matches(fr, &R_ABORT_FRAME)
+}
+
+/// Whether the backtrace goes through the panic runtime
+pub fn is_panicking(frames: &[EnrichedFrameInfo]) -> bool {
+    frames.iter().any(|fr| function_matches(fr, &R_PANIC_FRAME))
+}
+
+/// The frame that panicked: the first one after the panic runtime frames at the top of the stack
+pub fn find_panic_frame(frames: &[EnrichedFrameInfo]) -> usize {
+    let first = frames.iter().position(is_panic_frame).unwrap_or(0);
+
+    frames.iter().skip(first).position(|fr| !is_panic_frame(fr))
+        .map(|i| first + i)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debugger::gdb::GdbSymbol;
+    use std::rc::Rc;
+
+    fn frame(function: &str) -> EnrichedFrameInfo {
+        let frame: EnrichedFrameInfo = serde_json::from_value(serde_json::json!({
+            "summary": "", "address": {"r": 0x401000, "f": "0x401000", "s": 64},
+            "relative_address": {"r": 0x1000, "f": "0x1000", "s": 64},
+            "module": "test", "module_address": "test+0x1000",
+        })).unwrap();
+        let symbol = GdbSymbol { function_name: Some(function.into()), ..Default::default() };
+        EnrichedFrameInfo { symbol: Some(Rc::new(symbol)), ..frame }
+    }
+
+    #[test]
+    fn test_rust_panic() {
+        let expected = RustPanic {
+            message: "index out of bounds: the len is 3 but the index is 5".into(),
+            file: "src/parse.rs".into(),
+            line: 42,
+            column: 9,
+            thread: "main".into(),
+        };
+
+        let output = "thread 'main' panicked at src/parse.rs:42:9:\nindex out of bounds: the len is 3 but the index is 5\n\
+            note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace\n";
+        assert_eq!(panic_extract(output), Some(expected.clone()));
+
+        let output = "thread 'main' panicked at 'index out of bounds: the len is 3 but the index is 5', src/parse.rs:42:9\n";
+        assert_eq!(panic_extract(output), Some(expected));
+        assert_eq!(panic_extract("Aborted\n"), None);
+
+        let frames = vec![frame("__pthread_kill_implementation"), frame("raise"), frame("abort"),
+            frame("std::sys::pal::unix::abort_internal"), frame("std::process::abort"), frame("__rustc::__rust_start_panic"),
+            frame("rust_panic"), frame("std::panicking::rust_panic_with_hook"), frame("rust_begin_unwind"),
+            frame("core::panicking::panic_fmt"), frame("core::panicking::panic_bounds_check"),
+            frame("target::parse::parse_header"), frame("target::main"),
+            frame("std::rt::lang_start_internal"), frame("main")];
+        assert!(is_panicking(&frames));
+        assert_eq!(find_panic_frame(&frames), 11);
+
+        // An abort outside of the panic runtime
+        let frames = vec![frame("raise"), frame("abort"), frame("target::main"), frame("std::rt::lang_start_internal")];
+        assert!(!is_panicking(&frames));
+    }
+}
@@ -0,0 +1,343 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Where every crashing testcase came from and which bucket it landed in.
+//!
+//! Reports are only written for the first testcase of a bucket, so the rest of a bucket's members
+//! would otherwise only be known by path. The provenance of each one is recorded instead: the AFL
+//! instance that found it, the fields of its AFL file name (`id:000003,sig:11,src:000001,...`),
+//! a hash of its contents, when it was discovered and triaged, and how often it reproduced. At the
+//! end of a run it is written, grouped by bucket, to [BUCKETS_JSON_FILE] and as one row per
+//! testcase to [PROVENANCE_CSV_FILE], so any crash file can be traced to its bucket and back
+//! without triaging it again.
+//!
+//! The discovery time comes from AFL++'s `time` field, which is relative to the instance's
+//! `start_time` in `fuzzer_stats`. Without both, the modification time of the file is used.
+use crate::afl;
+use crate::report::csv::csv_line;
+use crate::report::index::{sort_bucket_entries, BucketEntry};
+use crate::report::Reproducibility;
+use crate::util;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The bucket metadata file in an output directory
+pub const BUCKETS_JSON_FILE: &str = "afltriage_buckets.json";
+/// The per-testcase provenance CSV file in an output directory
+pub const PROVENANCE_CSV_FILE: &str = "afltriage_provenance.csv";
+
+/// The directories of an AFL instance that testcases are found in
+const AFL_TESTCASE_DIRS: &[&str] = &["crashes", "hangs", "queue"];
+
+/// Where a crashing testcase came from
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TestcaseProvenance {
+    pub testcase: String,
+    pub bucket: String,
+    /// The name of the AFL instance directory the testcase was found in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fuzzer_instance: Option<String>,
+    /// The `key:value` fields of an AFL file name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub afl_fields: BTreeMap<String, String>,
+    /// The SHA-256 of the testcase's contents
+    pub sha256: String,
+    /// When the testcase was discovered, as an ISO-8601 timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discovered_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub triaged_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub triage_duration_ms: Option<u64>,
+    /// How often the testcase reproduced its crash, with --rerun
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reproducibility: Option<Reproducibility>,
+}
+
+/// A bucket and the provenance of its members, in the order they were triaged
+#[derive(Debug, Serialize)]
+struct BucketMetadata<'a> {
+    bucket: &'a str,
+    summary: &'a str,
+    severity: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reproducibility: Option<Reproducibility>,
+    reports: &'a [String],
+    testcases: Vec<&'a TestcaseProvenance>,
+}
+
+/// The fields of an AFL or AFL++ testcase file name, like
+/// `id:000003,sig:11,src:000001,time:1500,execs:99,op:havoc,rep:4`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AflTestcaseName {
+    pub id: u64,
+    /// The signal that crashed the target, for testcases in `crashes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sig: Option<i32>,
+    /// The queue entries the testcase was mutated from, like `000001` or `000001+000002` for a splice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub src: Option<String>,
+    /// The fuzzer instance a testcase synced from another instance was found by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync: Option<String>,
+    /// The mutation stage that produced the testcase, like `havoc` or `flip1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op: Option<String>,
+    /// Milliseconds from the start of the fuzzer until the testcase was found (AFL++)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+    /// Target executions until the testcase was found (AFL++)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execs: Option<u64>,
+    /// Any other fields, like `rep`, `pos`, or `val`
+    #[serde(flatten)]
+    pub other: BTreeMap<String, String>,
+}
+
+impl AflTestcaseName {
+    /// Parse an AFL file name. Other file names, or ones without a numeric `id`, give None
+    pub fn parse(name: &str) -> Option<AflTestcaseName> {
+        let mut fields = parse_afl_filename(name);
+        let id = fields.remove("id")?.parse().ok()?;
+        let mut number = |key: &str| fields.remove(key).and_then(|v| v.parse::<u64>().ok());
+        let time = number("time");
+        let execs = number("execs");
+
+        Some(AflTestcaseName {
+            id,
+            sig: fields.remove("sig").and_then(|v| v.parse().ok()),
+            src: fields.remove("src"),
+            sync: fields.remove("sync"),
+            op: fields.remove("op"),
+            time,
+            execs,
+            other: fields,
+        })
+    }
+
+    /// The fields as a single line, like `id 3, sig 11, src 000001, op havoc, found after 1500 ms`
+    pub fn summary(&self) -> String {
+        let mut parts = vec![format!("id {}", self.id)];
+
+        if let Some(sig) = self.sig {
+            parts.push(format!("sig {}", sig));
+        }
+
+        if let Some(sync) = &self.sync {
+            parts.push(format!("synced from {}", sync));
+        }
+
+        if let Some(src) = &self.src {
+            parts.push(format!("src {}", src));
+        }
+
+        if let Some(op) = &self.op {
+            parts.push(format!("op {}", op));
+        }
+
+        match (self.time, self.execs) {
+            (Some(ms), Some(execs)) => parts.push(format!("found after {} ms and {} execs", ms, execs)),
+            (Some(ms), None) => parts.push(format!("found after {} ms", ms)),
+            (None, Some(execs)) => parts.push(format!("found after {} execs", execs)),
+            (None, None) => (),
+        }
+
+        parts.join(", ")
+    }
+}
+
+/// Parse the fields of an AFL file name, like `id:000003,sig:11,src:000001,time:1234,op:havoc`.
+/// Other file names have none
+pub fn parse_afl_filename(name: &str) -> BTreeMap<String, String> {
+    if !name.starts_with("id:") {
+        return BTreeMap::new();
+    }
+
+    name.split(',')
+        .filter_map(|field| field.split_once(':'))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// The AFL instance directory containing `path`, if it is in one of its testcase directories
+fn afl_instance_dir(path: &Path) -> Option<&Path> {
+    let parent = path.parent()?;
+    let dir_name = parent.file_name()?.to_str()?;
+
+    if AFL_TESTCASE_DIRS.contains(&dir_name) {
+        parent.parent().filter(|p| p.file_name().is_some())
+    } else {
+        None
+    }
+}
+
+/// When a testcase was discovered: from the AFL `time` field if the instance start time is known,
+/// or when the file was last modified
+fn discovery_time(path: &Path, afl_fields: &BTreeMap<String, String>, start_time: Option<u64>) -> Option<SystemTime> {
+    let afl_time = afl_fields.get("time").and_then(|t| t.parse::<u64>().ok());
+
+    match (start_time, afl_time) {
+        (Some(start), Some(ms)) => Some(UNIX_EPOCH + Duration::from_secs(start) + Duration::from_millis(ms)),
+        _ => std::fs::metadata(path).and_then(|m| m.modified()).ok(),
+    }
+}
+
+/// Read the provenance of a testcase from its path, contents, and AFL instance. Triage results are
+/// filled in by the caller
+pub fn read_provenance(testcase: &str, local_time: bool) -> TestcaseProvenance {
+    let path = Path::new(testcase);
+    let afl_fields = path.file_name()
+        .map(|n| parse_afl_filename(&n.to_string_lossy()))
+        .unwrap_or_default();
+
+    let instance = afl_instance_dir(path);
+    let start_time = instance
+        .and_then(|dir| afl::parse_afl_fuzzer_stats(&dir.join("fuzzer_stats")).ok())
+        .and_then(|stats| stats.get("start_time").and_then(|t| t.parse::<u64>().ok()));
+
+    TestcaseProvenance {
+        testcase: testcase.to_string(),
+        fuzzer_instance: instance.map(|dir| dir.file_name().unwrap().to_string_lossy().to_string()),
+        sha256: util::read_file_to_bytes(testcase).map(|data| format!("{:x}", Sha256::digest(data))).unwrap_or_default(),
+        discovered_at: discovery_time(path, &afl_fields, start_time).map(|t| util::format_iso8601(t, local_time)),
+        afl_fields,
+        ..Default::default()
+    }
+}
+
+fn bucket_metadata<'a>(entry: &'a BucketEntry, provenance: &'a HashMap<String, TestcaseProvenance>) -> BucketMetadata<'a> {
+    BucketMetadata {
+        bucket: &entry.bucket,
+        summary: &entry.summary,
+        severity: entry.severity.as_str(),
+        reproducibility: entry.reproducibility,
+        reports: &entry.reports,
+        testcases: entry.testcases.iter().filter_map(|tc| provenance.get(tc)).collect(),
+    }
+}
+
+/// Render the metadata of a single bucket as JSON
+pub fn format_bucket_json(entry: &BucketEntry, provenance: &HashMap<String, TestcaseProvenance>) -> String {
+    serde_json::to_string_pretty(&bucket_metadata(entry, provenance)).unwrap()
+}
+
+/// Render the bucket metadata for `entries` (which should already be sorted) as JSON
+fn format_buckets_json(entries: &[&BucketEntry], provenance: &HashMap<String, TestcaseProvenance>) -> String {
+    let buckets: Vec<BucketMetadata> = entries.iter()
+        .map(|entry| bucket_metadata(entry, provenance))
+        .collect();
+
+    serde_json::to_string_pretty(&buckets).unwrap()
+}
+
+/// Render the provenance of every member of `entries` as CSV, one row per testcase
+fn format_provenance_csv(entries: &[&BucketEntry], provenance: &HashMap<String, TestcaseProvenance>) -> String {
+    let mut csv = csv_line(&["testcase", "bucket", "fuzzer_instance", "afl_id", "afl_sig", "afl_src",
+        "afl_op", "sha256", "discovered_at", "triaged_at", "triage_duration_ms", "reruns", "reproduced"]);
+
+    for p in entries.iter().flat_map(|e| e.testcases.iter()).filter_map(|tc| provenance.get(tc)) {
+        let field = |key: &str| p.afl_fields.get(key).map(String::as_str).unwrap_or("");
+        let duration = p.triage_duration_ms.map(|ms| ms.to_string()).unwrap_or_default();
+        let reruns = p.reproducibility.map(|r| r.reruns.to_string()).unwrap_or_default();
+        let reproduced = p.reproducibility.map(|r| r.reproduced.to_string()).unwrap_or_default();
+
+        csv += &csv_line(&[&p.testcase, &p.bucket, p.fuzzer_instance.as_deref().unwrap_or(""),
+            field("id"), field("sig"), field("src"), field("op"), &p.sha256,
+            p.discovered_at.as_deref().unwrap_or(""), p.triaged_at.as_deref().unwrap_or(""),
+            &duration, &reruns, &reproduced]);
+    }
+
+    csv
+}
+
+/// Write the bucket metadata and testcase provenance to `output_dir`
+pub fn write_bucket_metadata(buckets: &HashMap<String, BucketEntry>, provenance: &HashMap<String, TestcaseProvenance>, output_dir: &Path) {
+    let mut entries: Vec<&BucketEntry> = buckets.values().collect();
+    sort_bucket_entries(&mut entries);
+
+    let files = [
+        (BUCKETS_JSON_FILE, format_buckets_json(&entries, provenance)),
+        (PROVENANCE_CSV_FILE, format_provenance_csv(&entries, provenance)),
+    ];
+
+    for (filename, data) in files.iter() {
+        let path = output_dir.join(filename);
+
+        if let Err(e) = std::fs::write(&path, data) {
+            log::error!("Failed to write the bucket metadata: {}", e);
+        } else {
+            log::info!("Wrote bucket metadata to {}", shlex::quote(&path.to_string_lossy()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::severity::CrashSeverity;
+
+    #[test]
+    fn test_testcase_provenance() {
+        let fields = parse_afl_filename("id:000003,sig:11,src:000001+000002,time:1500,execs:99,op:splice,rep:4");
+        assert_eq!(fields["id"], "000003");
+        assert_eq!(fields["src"], "000001+000002");
+        assert_eq!(fields.len(), 7);
+        assert!(parse_afl_filename("crash.bin").is_empty());
+
+        let name = AflTestcaseName::parse("id:000003,sig:11,src:000001+000002,time:1500,execs:99,op:splice,rep:4").unwrap();
+        assert_eq!((name.id, name.sig, name.op.as_deref(), name.time, name.execs), (3, Some(11), Some("splice"), Some(1500), Some(99)));
+        assert_eq!(name.other.get("rep").map(String::as_str), Some("4"));
+        assert_eq!(name.summary(), "id 3, sig 11, src 000001+000002, op splice, found after 1500 ms and 99 execs");
+        let json = serde_json::to_value(&name).unwrap();
+        assert_eq!(json["time"], 1500);
+        assert_eq!(json["rep"], "4");
+        assert_eq!(serde_json::from_value::<AflTestcaseName>(json).unwrap(), name);
+        assert_eq!(AflTestcaseName::parse("id:000000,sync:fuzzer01,src:000042").unwrap().summary(),
+            "id 0, synced from fuzzer01, src 000042");
+        assert_eq!(AflTestcaseName::parse("crash.bin"), None);
+
+        let sync_dir = tempfile::tempdir().unwrap();
+        let crashes = sync_dir.path().join("fuzzer02").join("crashes");
+        std::fs::create_dir_all(&crashes).unwrap();
+        std::fs::write(sync_dir.path().join("fuzzer02").join("fuzzer_stats"), "start_time        : 1622548800\n").unwrap();
+
+        let testcase = crashes.join("id:000003,sig:11,src:000001,time:1500,op:havoc,rep:4");
+        std::fs::write(&testcase, "abc").unwrap();
+
+        let mut p = read_provenance(testcase.to_str().unwrap(), false);
+        assert_eq!(p.fuzzer_instance.as_deref(), Some("fuzzer02"));
+        assert_eq!(p.sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(p.discovered_at.as_deref(), Some("2021-06-01T12:00:01Z"));
+
+        let other = sync_dir.path().join("crash.bin");
+        std::fs::write(&other, "abc").unwrap();
+        let other = read_provenance(other.to_str().unwrap(), false);
+        assert_eq!(other.fuzzer_instance, None);
+        assert!(other.discovered_at.is_some());
+
+        p.bucket = "abc".into();
+        p.reproducibility = Some(Reproducibility { reruns: 2, reproduced: 1 });
+
+        let entry = BucketEntry {
+            bucket: "abc".into(),
+            summary: "A summary".into(),
+            severity: CrashSeverity::high,
+            testcases: vec![p.testcase.to_string(), "untracked".into()],
+            reports: vec!["a.txt".into()],
+            reproducibility: None,
+        };
+        let provenance: HashMap<String, TestcaseProvenance> = vec![(p.testcase.to_string(), p.clone())].into_iter().collect();
+
+        let json: serde_json::Value = serde_json::from_str(&format_buckets_json(&[&entry], &provenance)).unwrap();
+        assert_eq!(json[0]["bucket"], "abc");
+        assert_eq!(json[0]["testcases"][0]["afl_fields"]["op"], "havoc");
+        assert_eq!(json[0]["testcases"].as_array().unwrap().len(), 1);
+
+        let csv = format_provenance_csv(&[&entry], &provenance);
+        assert!(csv.lines().nth(1).unwrap().ends_with(
+            ",abc,fuzzer02,000003,11,000001,havoc,ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad,2021-06-01T12:00:01Z,,,2,1"));
+    }
+}
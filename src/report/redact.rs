@@ -0,0 +1,198 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Redaction of reports that are shared outside of the machine they were made on.
+//!
+//! Reports are full of details about where triage ran: source and build paths in backtraces, the
+//! user's home directory in the command line and testcase paths, and the hostname in sanitizer
+//! output. With `--redact`, a [Redactor] replaces them in every string of a crash report before it
+//! is rendered:
+//!
+//! * The home directory becomes `~`, and any other `/home/NAME` or `/Users/NAME` becomes
+//!   `/home/<user>` or `/Users/<user>`
+//! * The current directory, where the target is usually built, becomes `.`
+//! * The user name and the hostname become `<user>` and `<host>`
+//! * Each `--redact-pattern` regular expression becomes `<redacted>`
+//!
+//! The target's stdout and stderr are only redacted with `--redact-child-output`, as rewriting
+//! them can hide what the target printed about the crash.
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::debugger::gdb::GdbTriageResult;
+use crate::report::enriched::EnrichedTriageInfo;
+use crate::report::ReportEnvelope;
+
+/// A replacement of text matching a regular expression
+#[derive(Debug)]
+struct RedactionRule {
+    pattern: Regex,
+    replacement: String,
+}
+
+/// What is known about the machine to redact
+#[derive(Debug, Default)]
+pub struct MachineIdentity {
+    pub home_dir: Option<String>,
+    pub current_dir: Option<String>,
+    pub user: Option<String>,
+    pub hostname: Option<String>,
+}
+
+impl MachineIdentity {
+    /// The identity of this machine and user
+    pub fn current() -> MachineIdentity {
+        MachineIdentity {
+            home_dir: std::env::var("HOME").ok(),
+            current_dir: std::env::current_dir().ok().map(|dir| dir.to_string_lossy().to_string()),
+            user: std::env::var("USER").or_else(|_| std::env::var("LOGNAME")).ok(),
+            hostname: read_hostname(),
+        }
+    }
+}
+
+fn read_hostname() -> Option<String> {
+    let mut name = [0u8; 256];
+
+    // SAFETY: gethostname writes at most name.len() bytes
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return None;
+    }
+
+    let end = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    Some(String::from_utf8_lossy(&name[..end]).to_string())
+}
+
+/// Redacts identifying paths and names from reports
+#[derive(Debug)]
+pub struct Redactor {
+    rules: Vec<RedactionRule>,
+    /// Also redact the target's stdout and stderr
+    pub redact_child_output: bool,
+}
+
+impl Redactor {
+    /// A redactor for the `identity` of a machine and the regular expressions in `patterns`
+    pub fn new(identity: &MachineIdentity, patterns: &[&str], redact_child_output: bool) -> Result<Redactor, String> {
+        let mut rules = vec![];
+        let mut add = |pattern: &str, replacement: &str| rules.push(RedactionRule {
+            pattern: Regex::new(pattern).unwrap(),
+            replacement: replacement.to_string(),
+        });
+
+        // Longer paths first, so the current directory inside the home directory is `.`. The root
+        // directory is too short to redact
+        let mut dirs: Vec<(&str, &str)> = [(&identity.current_dir, "."), (&identity.home_dir, "~")].iter()
+            .filter_map(|(dir, replacement)| dir.as_deref().map(|d| (d.trim_end_matches('/'), *replacement)))
+            .filter(|(dir, _)| !dir.is_empty())
+            .collect();
+        dirs.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.len()));
+
+        for (dir, replacement) in dirs {
+            add(&format!(r"{}\b", regex::escape(dir)), replacement);
+        }
+
+        add(r"/home/[^/\s:'\x22]+", "/home/<user>");
+        add(r"/Users/[^/\s:'\x22]+", "/Users/<user>");
+
+        for (name, replacement) in [(&identity.user, "<user>"), (&identity.hostname, "<host>")] {
+            if let Some(name) = name.as_deref().filter(|n| !n.is_empty()) {
+                add(&format!(r"\b{}\b", regex::escape(name)), replacement);
+            }
+        }
+
+        for pattern in patterns {
+            rules.push(RedactionRule {
+                pattern: Regex::new(pattern).map_err(|e| format!("Invalid redaction pattern: {}", e))?,
+                replacement: "<redacted>".into(),
+            });
+        }
+
+        Ok(Redactor { rules, redact_child_output })
+    }
+
+    /// Redact a string
+    pub fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+
+        for rule in &self.rules {
+            if let std::borrow::Cow::Owned(replaced) = rule.pattern.replace_all(&text, rule.replacement.as_str()) {
+                text = replaced;
+            }
+        }
+
+        text
+    }
+
+    /// Redact every string in a JSON value
+    pub fn redact_value(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(s) => *s = self.redact(s),
+            serde_json::Value::Array(items) => items.iter_mut().for_each(|v| self.redact_value(v)),
+            serde_json::Value::Object(fields) => fields.values_mut().for_each(|v| self.redact_value(v)),
+            _ => (),
+        }
+    }
+
+    /// A redacted copy of `value`. The field named `child_output` holds the target's output
+    fn redacted<T: Serialize + DeserializeOwned>(&self, value: &T, child_output: Option<&str>) -> T {
+        let mut value = serde_json::to_value(value).unwrap();
+
+        match value.as_object_mut() {
+            Some(fields) => {
+                for (name, field) in fields.iter_mut() {
+                    if self.redact_child_output || Some(name.as_str()) != child_output {
+                        self.redact_value(field);
+                    }
+                }
+            }
+            None => self.redact_value(&mut value),
+        }
+
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Redacted copies of everything the reports of a crash are rendered from
+    pub fn redact_crash(&self, etriage: &EnrichedTriageInfo, triage: &GdbTriageResult, envelope: &ReportEnvelope)
+        -> (EnrichedTriageInfo, GdbTriageResult, ReportEnvelope) {
+        (self.redacted(etriage, Some("target_output")), self.redacted(triage, Some("child")), self.redacted(envelope, None))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::{load_test_envelope, load_test_triage};
+
+    #[test]
+    fn test_redactor() {
+        let identity = MachineIdentity {
+            home_dir: Some("/home/alice".into()),
+            current_dir: Some("/home/alice/src/project".into()),
+            user: Some("alice".into()),
+            hostname: Some("fuzzbox".into()),
+        };
+        let redactor = Redactor::new(&identity, &["ACME-[0-9]+"], false).unwrap();
+
+        assert_eq!(redactor.redact("/home/alice/src/project/parse.c:12"), "./parse.c:12");
+        assert_eq!(redactor.redact("/home/alice/fuzz/out/crashes/id:000000"), "~/fuzz/out/crashes/id:000000");
+        assert_eq!(redactor.redact("/home/alicex/a.c /home/bob/b.c /Users/carol/c.c"),
+            "/home/<user>/a.c /home/<user>/b.c /Users/<user>/c.c");
+        assert_eq!(redactor.redact("alice@fuzzbox: ACME-1234 malice"), "<user>@<host>: <redacted> malice");
+        assert!(Redactor::new(&identity, &["("], false).is_err());
+
+        let (etriage, mut envelope): (EnrichedTriageInfo, ReportEnvelope) = load_test_envelope();
+        envelope.testcase = "/tmp/crashes/id:000000".into();
+        let triage: GdbTriageResult = load_test_triage();
+
+        let identity = MachineIdentity { current_dir: Some("/tmp".into()), ..Default::default() };
+        let (etriage, triage, envelope) = Redactor::new(&identity, &[], false).unwrap()
+            .redact_crash(&etriage, &triage, &envelope);
+
+        assert!(!serde_json::to_string(&etriage.faulting_thread).unwrap().contains("/tmp/"));
+        assert_eq!(triage.response.context.unwrap().primary_thread.backtrace[7].module, "./test");
+        assert_eq!(envelope.testcase, "./crashes/id:000000");
+        assert!(triage.child.stderr.contains("/tmp/test"));
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Per-severity artifact retention.
+//!
+//! By default every unique crash bucket keeps the same artifacts: the reports selected with
+//! `--report-formats` and, with `--child-output`, the target's output. Retention rules of the form
+//! `SEVERITY=ARTIFACT,...` override this for a single [CrashSeverity], trading forensic detail for
+//! disk usage on less interesting crashes. Valid artifacts are the report formats and
+//! `child_output`, or `none` to only keep the bucket's entry in the index.
+use std::collections::HashMap;
+use std::str::FromStr;
+use crate::ReportOutputFormat;
+use crate::report::severity::CrashSeverity;
+
+/// The artifacts kept for a crash bucket
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetentionPolicy {
+    pub report_formats: Vec<ReportOutputFormat>,
+    pub child_output: bool,
+}
+
+/// Retention policies for each [CrashSeverity]
+#[derive(Debug)]
+pub struct RetentionRules {
+    default: RetentionPolicy,
+    rules: HashMap<CrashSeverity, RetentionPolicy>,
+}
+
+impl RetentionRules {
+    pub fn new(default: RetentionPolicy) -> Self {
+        RetentionRules {
+            default,
+            rules: HashMap::new(),
+        }
+    }
+
+    /// Parse and add a `SEVERITY=ARTIFACT,...` rule, replacing any previous rule for the severity
+    pub fn add_rule(&mut self, rule: &str) -> Result<(), String> {
+        let (severity, artifacts) = match rule.split_once('=') {
+            Some(x) => x,
+            None => return Err(format!("Invalid retention rule '{}'. Expected SEVERITY=ARTIFACT,...", rule)),
+        };
+
+        let severity = CrashSeverity::from_str(&severity.to_lowercase())
+            .map_err(|_| format!("Unknown crash severity '{}'", severity))?;
+
+        let mut policy = RetentionPolicy {
+            report_formats: vec![],
+            child_output: false,
+        };
+
+        for artifact in artifacts.split(',').map(str::trim) {
+            match artifact.to_lowercase().as_str() {
+                "none" => (),
+                "child_output" => policy.child_output = true,
+                _ => policy.report_formats.push(ReportOutputFormat::from_str(artifact)?),
+            }
+        }
+
+        self.rules.insert(severity, policy);
+
+        Ok(())
+    }
+
+    /// The policy for crashes of `severity`
+    pub fn policy(&self, severity: CrashSeverity) -> &RetentionPolicy {
+        self.rules.get(&severity).unwrap_or(&self.default)
+    }
+
+    /// Whether any policy keeps the target's output
+    pub fn any_child_output(&self) -> bool {
+        self.default.child_output || self.rules.values().any(|p| p.child_output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debugger::gdb::GdbTriageResult;
+    use crate::report::enriched::EnrichedTriageInfo;
+    use crate::report::fixtures::{load_test_envelope, load_test_triage};
+    use crate::report::{render_reports, ReportEnvelope};
+
+    #[test]
+    fn test_retention_rules() {
+        let mut rules = RetentionRules::new(RetentionPolicy {
+            report_formats: vec![ReportOutputFormat::text],
+            child_output: false,
+        });
+
+        assert!(rules.add_rule("high=text,JSON,child_output").is_ok());
+        assert!(rules.add_rule("low=none").is_ok());
+        assert!(rules.add_rule("bogus=text").is_err());
+        assert!(rules.add_rule("medium=pdf").is_err());
+        assert!(rules.add_rule("medium").is_err());
+
+        assert_eq!(rules.policy(CrashSeverity::high), &RetentionPolicy {
+            report_formats: vec![ReportOutputFormat::text, ReportOutputFormat::json],
+            child_output: true,
+        });
+        assert!(rules.policy(CrashSeverity::low).report_formats.is_empty());
+        assert_eq!(rules.policy(CrashSeverity::medium).report_formats, vec![ReportOutputFormat::text]);
+        assert!(rules.any_child_output());
+    }
+
+    #[test]
+    fn test_rawjson_child_output() {
+        let triage: GdbTriageResult = load_test_triage();
+        assert!(!triage.child.stderr.is_empty());
+
+        let render = |child_output| {
+            let (etriage, envelope): (EnrichedTriageInfo, ReportEnvelope) = load_test_envelope();
+            let policy = RetentionPolicy { report_formats: vec![ReportOutputFormat::rawjson], child_output };
+            let reports = render_reports(&policy, CrashSeverity::low, etriage, &triage, &envelope, None);
+            let rendered: serde_json::Value = serde_json::from_str(&reports[0].data).unwrap();
+            rendered["report"]["child"]["stderr"].as_str().unwrap().to_string()
+        };
+
+        assert_eq!(render(false), "");
+        assert_eq!(render(true), triage.child.stderr);
+    }
+}
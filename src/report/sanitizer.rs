@@ -12,16 +12,59 @@ lazy_static! {
     static ref R_SANITIZER_REPORT: Regex = Regex::new(
         r#"(?x)
         ([=]+[\r\n]+)?
-        (?P<pid>=+[0-9]+=+)\s*ERROR:\s*(?P<san>AddressSanitizer):\s*
+        (?P<pid>=+[0-9]+=+)\s*(ERROR|WARNING):\s*(?P<san>AddressSanitizer|MemorySanitizer):\s*
         (attempting\s)?(?P<reason>[-_A-Za-z0-9]+)[^\r\n]*[\r\n]+
         (?P<operation>[-_A-Za-z0-9]+)?
         "#
     )
     .unwrap();
     static ref R_SANITIZER_REPORT_FRAME: Regex = Regex::new(r#"#(?P<num>[0-9]+)\s+(?P<addr>0x[a-fA-F0-9]+)"#).unwrap();
+    static ref R_SANITIZER_REPORT_FRAME_SYMBOL: Regex = Regex::new(
+        r#"(?m)^(\s*#[0-9]+\s+0x[a-fA-F0-9]+\s+in\s|==[0-9]+==\s+(at|by) 0x[a-fA-F0-9]+: [^?\s])"#).unwrap();
+
+    /// The headline of a Valgrind Memcheck error
+    static ref R_MEMCHECK_ERROR: Regex = Regex::new(
+        r#"(?m)^(?P<pid>==[0-9]+==) (?P<what>Invalid (?P<op>read|write) of size [0-9]+|Invalid free\(\).*|Mismatched free\(\).*|Conditional jump or move depends on uninitialised value.*|Use of uninitialised value.*|Jump to the invalid address.*|Syscall param .*|Source and destination overlap.*)$"#
+    ).unwrap();
+    /// A frame of any stack in a report, which is enough to find where the stack starts and the
+    /// location of its first frame. ThreadSanitizer frames have no address
+    static ref R_SANITIZER_STACK_FRAME: Regex = Regex::new(
+        r#"^\s*#(?P<num>[0-9]+)\s+(0x[a-fA-F0-9]+\s+(in\s+)?)?(?P<func>\S+)(.*?\s(?P<file>[^\s()]+?):(?P<line>[0-9]+)(:[0-9]+)?(\s|$))?"#
+    ).unwrap();
+
+    /// The headline of a ThreadSanitizer report, which has no PID marker on every line
+    static ref R_TSAN_REPORT: Regex = Regex::new(
+        r#"(?m)^WARNING: ThreadSanitizer: (?P<reason>[^(\r\n]+?)\s*(\(.*)?$"#).unwrap();
+    static ref R_TSAN_OPERATION: Regex = Regex::new(r#"(?m)^\s+(Atomic\s)?(?P<op>Read|Write) of size"#).unwrap();
+
+    static ref R_MEMCHECK_FRAME: Regex = Regex::new(r#"^==[0-9]+==\s+(at|by) 0x(?P<addr>[a-fA-F0-9]+):"#).unwrap();
+
+    /// The message of a failed glibc stack protector or FORTIFY_SOURCE check. Older releases name
+    /// the program before "terminated"
+    static ref R_GLIBC_CHECK_FAILURE: Regex = Regex::new(
+        r#"(?m)^\*\*\* (?P<msg>[^*\r\n]+?) \*\*\*: [^\r\n]*terminated\s*$"#).unwrap();
+    /// The glibc functions between the frame that failed a stack protector or FORTIFY_SOURCE check
+    /// and abort(), e.g. __stack_chk_fail, __fortify_fail, __chk_fail, or __strcpy_chk
+    static ref R_GLIBC_CHECK_FUNCTION: Regex = Regex::new(r#"^(__GI_)*__\w*(_chk|fortify_fail)"#).unwrap();
+    /// The message of a malloc consistency check that failed, printed on its own line
+    static ref R_GLIBC_HEAP_ERROR: Regex = Regex::new(
+        r#"(?m)^(?P<msg>(free|malloc|realloc|calloc|munmap_chunk|malloc_consolidate|_int_malloc|_int_free|_int_realloc|tcache_thread_shutdown|memalign|posix_memalign|aligned_alloc)\(\): [^\r\n]+|double free or corruption[^\r\n]*|corrupted size vs\. prev_size[^\r\n]*|corrupted double-linked list[^\r\n]*)\r?$"#
+    ).unwrap();
+    /// The same message from glibc before 2.26, which names the program and the chunk
+    static ref R_GLIBC_OLD_HEAP_ERROR: Regex = Regex::new(
+        r#"(?m)^\*\*\* Error in `[^'\r\n]*': (?P<msg>[^\r\n]+?): 0x[a-fA-F0-9]+ \*\*\*"#).unwrap();
+    /// The allocator functions between the frame that passed a bad or corrupted chunk and abort(),
+    /// e.g. malloc_printerr, _int_free, free, or operator delete
+    static ref R_GLIBC_HEAP_FUNCTION: Regex = Regex::new(
+        r#"^((__GI_)*(__libc_)?(malloc|free|cfree|realloc|calloc|memalign|posix_memalign|aligned_alloc|reallocarray)$|(__GI_)*(_int_|malloc_|munmap_chunk|tcache_|unlink_chunk|__libc_message)|operator (delete|new))"#
+    ).unwrap();
 
     static ref SANITIZER_SHORT: HashMap<&'static str, &'static str> = HashMap::<_, _>::from_iter(IntoIter::new([
             ("AddressSanitizer", "ASAN"),
+            ("MemorySanitizer", "MSAN"),
+            ("ThreadSanitizer", "TSAN"),
+            ("Memcheck", "MEMCHECK"),
+            ("glibc", "GLIBC"),
     ]));
 }
 
@@ -33,6 +76,15 @@ pub struct SanitizerReport {
     pub operation: String,
     pub frames: Vec<u64>,
     pub body: String,
+    /// The function names of the first stack, for reports whose frames have no addresses
+    /// (ThreadSanitizer)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub functions: Vec<String>,
+    /// Where each further stack of a MemorySanitizer or ThreadSanitizer report starts, as
+    /// `file:line` or a function name. For instance, the previous access of a data race, or where
+    /// an uninitialized value was created
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub related_locations: Vec<String>,
 }
 
 impl SanitizerReport {
@@ -43,16 +95,75 @@ impl SanitizerReport {
             &self.sanitizer_short
         }
     }
+
+    /// Whether the sanitizer could name the functions of its stack trace. A report without frames
+    /// counts as symbolized
+    pub fn is_symbolized(&self) -> bool {
+        self.frames.is_empty() || R_SANITIZER_REPORT_FRAME_SYMBOL.is_match(&self.body)
+    }
 }
 
 /// Extract key fields and body of Sanitizer reports from an `input` string.
 ///
 /// Currently only supports AddressSanitizer reports and only a single report (specifically the
-/// last report) within a string.
+/// last report) within a string. MemorySanitizer and ThreadSanitizer reports are also
+/// recognized. Without any, the first Valgrind Memcheck error is extracted instead, and then the
+/// message of a failed glibc stack protector, FORTIFY_SOURCE, or malloc consistency check.
 // TODO: support multiple sanitizer reports in successsion
 // TODO: support more sanitizers
 // FIXME: string slicing of unicode can lead to panics
 pub fn sanitizer_report_extract(input: &str) -> Option<SanitizerReport> {
+    pid_report_extract(input)
+        .or_else(|| tsan_report_extract(input))
+        .or_else(|| memcheck_report_extract(input))
+        .or_else(|| glibc_report_extract(input))
+}
+
+/// Whether `function` is part of glibc's handling of a failed stack protector or FORTIFY_SOURCE
+/// check. The frame that failed the check is the one calling it
+pub fn is_glibc_check_function(function: &str) -> bool {
+    R_GLIBC_CHECK_FUNCTION.is_match(function)
+}
+
+/// Whether `function` is part of glibc's allocator, which aborts when it finds a bad or corrupted
+/// chunk. The frame that passed the chunk is the first one outside of it
+pub fn is_glibc_heap_function(function: &str) -> bool {
+    R_GLIBC_HEAP_FUNCTION.is_match(function)
+}
+
+/// The stacks of a report body, each as its frame lines
+fn report_stacks(body: &str) -> Vec<Vec<regex::Captures<'_>>> {
+    let mut stacks: Vec<Vec<regex::Captures>> = vec![];
+    let mut in_stack = false;
+
+    for line in body.lines() {
+        match R_SANITIZER_STACK_FRAME.captures(line) {
+            Some(frame) if &frame["num"] == "0" => {
+                stacks.push(vec![frame]);
+                in_stack = true;
+            }
+            Some(frame) if in_stack => stacks.last_mut().unwrap().push(frame),
+            _ => in_stack = false,
+        }
+    }
+
+    stacks
+}
+
+/// The location of the first frame of each stack after the first
+fn related_locations(stacks: &[Vec<regex::Captures>]) -> Vec<String> {
+    stacks.iter()
+        .skip(1)
+        .filter_map(|stack| stack.first())
+        .map(|frame| match (frame.name("file"), frame.name("line")) {
+            (Some(file), Some(line)) => format!("{}:{}", file.as_str(), line.as_str()),
+            _ => frame["func"].to_string(),
+        })
+        .collect()
+}
+
+/// Extract an AddressSanitizer or MemorySanitizer report, whose lines start with a PID marker
+fn pid_report_extract(input: &str) -> Option<SanitizerReport> {
     // find the NEWEST sanitizer headline
     // regex doesn't support finding in reverse so we go at it forward
     let report_match = R_SANITIZER_REPORT.captures_iter(input).last();
@@ -117,6 +228,13 @@ pub fn sanitizer_report_extract(input: &str) -> Option<SanitizerReport> {
         _ => "",
     };
 
+    // Only MemorySanitizer's further stacks are needed for now
+    let related_locations = if report_sanitizer == "MemorySanitizer" {
+        related_locations(&report_stacks(report_body))
+    } else {
+        vec![]
+    };
+
     Some(SanitizerReport {
         sanitizer: report_sanitizer.into(),
         sanitizer_short: report_sanitizer_short.to_string(),
@@ -124,9 +242,158 @@ pub fn sanitizer_report_extract(input: &str) -> Option<SanitizerReport> {
         operation: operation.to_string(),
         frames: report_frames,
         body: report_body.trim_end().to_string(),
+        functions: vec![],
+        related_locations,
+    })
+}
+
+/// Extract the last ThreadSanitizer report. Its stacks are symbolized, but have no addresses, so
+/// the report has `functions` instead of `frames`
+fn tsan_report_extract(input: &str) -> Option<SanitizerReport> {
+    let headline = R_TSAN_REPORT.captures_iter(input).last()?;
+    let start = headline.get(0).unwrap().start();
+    let rest = &input[start..];
+
+    // Reports end with a summary line, then a line of =
+    let end = rest.find("\nSUMMARY: ThreadSanitizer")
+        .map(|pos| pos + 1 + rest[pos + 1..].find('\n').unwrap_or(rest.len() - pos - 1))
+        .or_else(|| rest.find("\n=================="))
+        .unwrap_or(rest.len());
+
+    let body = &rest[..end];
+    let stacks = report_stacks(body);
+
+    let functions = stacks.first()
+        .map(|stack| stack.iter()
+            .map(|frame| frame["func"].to_string())
+            .filter(|f| f != "<null>")
+            .collect())
+        .unwrap_or_default();
+
+    Some(SanitizerReport {
+        sanitizer: "ThreadSanitizer".into(),
+        sanitizer_short: SANITIZER_SHORT["ThreadSanitizer"].into(),
+        stop_reason: headline["reason"].trim().replace(' ', "-"),
+        operation: R_TSAN_OPERATION.captures(body)
+            .map(|c| c["op"].to_uppercase())
+            .unwrap_or_default(),
+        frames: vec![],
+        body: body.trim_end().to_string(),
+        functions,
+        related_locations: related_locations(&stacks),
+    })
+}
+
+
+/// Classify a Memcheck error like the AddressSanitizer report of the same bug, from its headline
+/// and the description of the address that follows it
+fn memcheck_stop_reason(what: &str, body: &str) -> &'static str {
+    let freed = body.contains(" free'd");
+    let near_block = body.contains(" alloc'd");
+
+    if what.starts_with("Invalid read") || what.starts_with("Invalid write") {
+        if freed {
+            "heap-use-after-free"
+        } else if near_block {
+            "heap-buffer-overflow"
+        } else if body.contains("on thread ") && body.contains("'s stack") {
+            "stack-buffer-overflow"
+        } else {
+            "invalid-access"
+        }
+    } else if what.starts_with("Invalid free") {
+        if freed { "double-free" } else { "bad-free" }
+    } else if what.starts_with("Mismatched free") {
+        "alloc-dealloc-mismatch"
+    } else if what.starts_with("Conditional jump") || what.starts_with("Use of uninitialised") {
+        "use-of-uninitialized-value"
+    } else if what.starts_with("Jump to the invalid address") {
+        "invalid-jump"
+    } else if what.starts_with("Syscall param") {
+        "syscall-param"
+    } else {
+        "memcpy-param-overlap"
+    }
+}
+
+/// Extract the first Valgrind Memcheck error, which is usually the cause of those after it
+fn memcheck_report_extract(input: &str) -> Option<SanitizerReport> {
+    let headline = R_MEMCHECK_ERROR.captures(input)?;
+    let marker = headline.name("pid").unwrap().as_str();
+    let start = headline.get(0).unwrap().start();
+
+    // The error ends at the first line with only the marker
+    let body: String = input[start..].lines()
+        .take_while(|l| l.starts_with(marker) && l.trim_end() != marker)
+        .map(|l| format!("{}\n", l))
+        .collect();
+
+    let what = headline.name("what").unwrap().as_str();
+    let operation = match headline.name("op").map(|op| op.as_str()) {
+        Some("read") => "READ",
+        Some("write") => "WRITE",
+        _ => "",
+    };
+
+    // Only the stack of the error, not those of the allocation and free
+    let frames = body.lines()
+        .skip(1)
+        .map_while(|l| R_MEMCHECK_FRAME.captures(l))
+        .filter_map(|c| u64::from_str_radix(&c["addr"], 16).ok())
+        .collect();
+
+    Some(SanitizerReport {
+        sanitizer: "Memcheck".into(),
+        sanitizer_short: SANITIZER_SHORT["Memcheck"].into(),
+        stop_reason: memcheck_stop_reason(what, &body).into(),
+        operation: operation.into(),
+        frames,
+        body: body.trim_end().to_string(),
+        functions: vec![],
+        related_locations: vec![],
     })
 }
 
+/// Classify the message of a failed malloc consistency check
+fn glibc_heap_reason(msg: &str) -> &'static str {
+    if msg.contains("double free") {
+        "double-free"
+    } else if msg.contains("invalid pointer") || msg.contains("invalid size") || msg.starts_with("munmap_chunk") {
+        "bad-free"
+    } else {
+        // e.g. "malloc(): corrupted top size" or "free(): invalid next size (fast)"
+        "heap-corruption"
+    }
+}
+
+/// Extract the last failed glibc stack protector, FORTIFY_SOURCE, or malloc consistency check,
+/// which would otherwise be an ordinary SIGABRT. The message has no stack, so the report has no
+/// frames
+fn glibc_report_extract(input: &str) -> Option<SanitizerReport> {
+    let checks = R_GLIBC_CHECK_FAILURE.captures_iter(input)
+        .map(|c| (c.get(0).unwrap(), match &c["msg"] {
+            "stack smashing detected" => "stack-smashing",
+            "buffer overflow detected" => "fortify-buffer-overflow",
+            // e.g. "longjmp causes uninitialized stack frame"
+            _ => "fortify-failure",
+        }));
+    let heap_errors = R_GLIBC_HEAP_ERROR.captures_iter(input)
+        .chain(R_GLIBC_OLD_HEAP_ERROR.captures_iter(input))
+        .map(|c| (c.get(0).unwrap(), glibc_heap_reason(&c["msg"])));
+
+    let (headline, stop_reason) = checks.chain(heap_errors).max_by_key(|(m, _)| m.start())?;
+
+    Some(SanitizerReport {
+        sanitizer: "glibc".into(),
+        sanitizer_short: SANITIZER_SHORT["glibc"].into(),
+        stop_reason: stop_reason.into(),
+        operation: "".into(),
+        frames: vec![],
+        body: headline.as_str().trim_end().to_string(),
+        functions: vec![],
+        related_locations: vec![],
+    })
+}
 
 #[cfg(test)]
 mod test {
@@ -174,6 +441,7 @@ mod test {
         assert_eq!(r.operation, "READ");
         assert_eq!(r.frames[0], 0x5561e001bba8);
         assert_eq!(r.body, a.trim());
+        assert!(r.is_symbolized());
 
         let a = load_test("asan_multi.txt");
         let r = sanitizer_report_extract(&a).unwrap();
@@ -229,6 +497,121 @@ mod test {
                 operation: "".into(),
                 frames: vec![],
                 body: m.trim().into(),
+                functions: vec![],
+                related_locations: vec![],
             });
+
+        let unsymbolized = "==1==ERROR: AddressSanitizer: SEGV on unknown address 0x000000000000\n\
+            ==1==The signal is caused by a READ memory access.\n    \
+            #0 0x4f5d1c  (/tmp/target+0x4f5d1c)\n    \
+            #1 0x7f2b1c3c0b96  (/lib/x86_64-linux-gnu/libc.so.6+0x21b96)\n\n\
+            SUMMARY: AddressSanitizer: SEGV (/tmp/target+0x4f5d1c)\n==1==ABORTING\n";
+        let r = sanitizer_report_extract(unsymbolized).unwrap();
+        assert_eq!(r.frames.len(), 2);
+        assert!(!r.is_symbolized());
+    }
+
+    #[test]
+    fn test_memcheck_report_parsing() {
+        let a = load_test("memcheck_uaf.txt");
+        let r = sanitizer_report_extract(&a).unwrap();
+
+        assert_eq!(r.name_prefer_short(), "MEMCHECK");
+        assert_eq!(r.stop_reason, "heap-use-after-free");
+        assert_eq!(r.operation, "READ");
+        assert_eq!(r.frames, vec![0x1091B4, 0x109207]);
+        assert!(r.body.starts_with("==24193== Invalid read of size 4\n"));
+        assert!(r.body.ends_with("by 0x109207: main (test.c:25)"));
+        assert!(r.is_symbolized());
+
+        let r = sanitizer_report_extract(&a.replace(" free'd", " alloc'd")).unwrap();
+        assert_eq!(r.stop_reason, "heap-buffer-overflow");
+
+        let r = sanitizer_report_extract("==7== Invalid free() / delete / delete[] / realloc()\n\
+            ==7==    at 0x483CA3F: free (vg_replace_malloc.c:540)\n\
+            ==7==  Address 0x4a4a040 is 0 bytes inside a block of size 16 free'd\n==7== \n").unwrap();
+        assert_eq!((r.stop_reason.as_str(), r.operation.as_str()), ("double-free", ""));
+        assert_eq!(r.frames, vec![0x483CA3F]);
+    }
+
+    #[test]
+    fn test_msan_tsan_report_parsing() {
+        let a = load_test("msan_uninit.txt");
+        let r = sanitizer_report_extract(&a).unwrap();
+
+        assert_eq!(r.name_prefer_short(), "MSAN");
+        assert_eq!(r.stop_reason, "use-of-uninitialized-value");
+        assert_eq!(r.operation, "");
+        assert_eq!(r.frames, vec![0x4a1c5e, 0x4a1e07, 0x7f5d2c1e4082, 0x41f36d]);
+        assert_eq!(r.related_locations, vec!["/tmp/parse.c:24"]);
+        assert!(r.body.ends_with("in parse_header"));
+        assert!(r.is_symbolized());
+
+        let a = load_test("tsan_race.txt");
+        let r = sanitizer_report_extract(&a).unwrap();
+
+        assert_eq!(r.name_prefer_short(), "TSAN");
+        assert_eq!(r.stop_reason, "data-race");
+        assert_eq!(r.operation, "WRITE");
+        assert!(r.frames.is_empty());
+        assert_eq!(r.functions, vec!["worker"]);
+        assert_eq!(r.related_locations, vec!["/tmp/race.c:17", "../../../../src/libsanitizer/tsan/tsan_interceptors.cc:915"]);
+        assert!(r.body.starts_with("WARNING: ThreadSanitizer: data race (pid=9043)\n"));
+        assert!(r.body.ends_with("SUMMARY: ThreadSanitizer: data race /tmp/race.c:8:13 in worker"));
+
+        let r = sanitizer_report_extract("WARNING: ThreadSanitizer: lock-order-inversion (potential deadlock) (pid=1)\n").unwrap();
+        assert_eq!(r.stop_reason, "lock-order-inversion");
+    }
+
+    #[test]
+    fn test_glibc_report_parsing() {
+        let a = load_test("glibc_stack_smashing.txt");
+        let r = sanitizer_report_extract(&a).unwrap();
+
+        assert_eq!(r.name_prefer_short(), "GLIBC");
+        assert_eq!(r.stop_reason, "stack-smashing");
+        assert_eq!(r.operation, "");
+        assert!(r.frames.is_empty());
+        assert_eq!(r.body, "*** stack smashing detected ***: terminated");
+
+        let r = sanitizer_report_extract("*** buffer overflow detected ***: ./test terminated\n").unwrap();
+        assert_eq!(r.stop_reason, "fortify-buffer-overflow");
+        let r = sanitizer_report_extract("*** longjmp causes uninitialized stack frame ***: terminated\n").unwrap();
+        assert_eq!(r.stop_reason, "fortify-failure");
+
+        assert!(is_glibc_check_function("__stack_chk_fail"));
+        assert!(is_glibc_check_function("__GI___fortify_fail"));
+        assert!(is_glibc_check_function("__strcpy_chk"));
+        assert!(!is_glibc_check_function("__GI_abort"));
+        assert!(!is_glibc_check_function("check_header"));
+    }
+
+    #[test]
+    fn test_glibc_heap_error_parsing() {
+        let a = load_test("glibc_double_free.txt");
+        let r = sanitizer_report_extract(&a).unwrap();
+
+        assert_eq!(r.name_prefer_short(), "GLIBC");
+        assert_eq!(r.stop_reason, "double-free");
+        assert_eq!(r.body, "*** Error in `./test': double free or corruption (fasttop): 0x0000000001c47010 ***");
+        assert!(r.frames.is_empty());
+
+        let reason = |s: &str| sanitizer_report_extract(s).map(|r| r.stop_reason);
+        assert_eq!(reason("free(): double free detected in tcache 2\n").unwrap(), "double-free");
+        assert_eq!(reason("double free or corruption (out)\n").unwrap(), "double-free");
+        assert_eq!(reason("free(): invalid pointer\n").unwrap(), "bad-free");
+        assert_eq!(reason("munmap_chunk(): invalid pointer\n").unwrap(), "bad-free");
+        assert_eq!(reason("malloc(): corrupted top size\r\n").unwrap(), "heap-corruption");
+        assert_eq!(reason("malloc(): unaligned tcache chunk detected\n").unwrap(), "heap-corruption");
+        assert_eq!(reason("corrupted size vs. prev_size\n").unwrap(), "heap-corruption");
+        assert_eq!(reason("parsing failed: free(): invalid pointer\n"), None);
+        // The last message is the one that aborted
+        assert_eq!(reason("*** stack smashing detected ***: terminated\nfree(): invalid pointer\n").unwrap(), "bad-free");
+
+        assert!(is_glibc_heap_function("malloc_printerr"));
+        assert!(is_glibc_heap_function("_int_free"));
+        assert!(is_glibc_heap_function("__GI___libc_free"));
+        assert!(is_glibc_heap_function("operator delete(void*)"));
+        assert!(!is_glibc_heap_function("free_list"));
     }
 }
@@ -0,0 +1,229 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Versioned readers for the JSON AFLTriage writes.
+//!
+//! JSON reports and saved results (`--save-results`) record the [SCHEMA_VERSION] they were
+//! written with in the envelope's `schema_version`. Output from before schema versioning has no
+//! version and is read as version 1. The readers upgrade older output one version at a time with
+//! [MIGRATIONS] before deserializing it, so results kept in long-lived crash databases can still be
+//! re-rendered and re-bucketed by newer versions of AFLTriage.
+//!
+//! A migration that cannot carry a field forward, because what the field means changed between
+//! versions, fails with [SchemaError::ChangedSemantics] naming the field instead of silently
+//! reinterpreting it. Output from a newer version than this one is rejected.
+use crate::debugger::gdb::GdbTriageResult;
+use crate::report::enriched::EnrichedTriageInfo;
+use crate::report::{ReportEnvelope, SavedResult};
+use serde_json::Value;
+
+/// The version of the JSON this version of AFLTriage writes
+pub const SCHEMA_VERSION: u32 = 2;
+/// The oldest version that can still be read. Output from before schema versioning is version 1
+pub const MIN_SCHEMA_VERSION: u32 = 1;
+
+/// The version of output without a `schema_version`
+pub fn legacy_schema_version() -> u32 {
+    1
+}
+
+/// Why stored AFLTriage output could not be read
+#[derive(Debug, PartialEq)]
+pub enum SchemaError {
+    /// The JSON is invalid or does not match the schema of its version
+    Malformed(String),
+    /// Written by a newer version of AFLTriage
+    TooNew(u32),
+    /// Written by a version of AFLTriage that is no longer supported
+    TooOld(u32),
+    /// A field's meaning changed in `version` and it cannot be converted
+    ChangedSemantics {
+        field: String,
+        version: u32,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::Malformed(e) => write!(f, "{}", e),
+            SchemaError::TooNew(v) => write!(f,
+                "written with schema version {}, but this version of AFLTriage only reads up to version {}. Upgrade AFLTriage",
+                v, SCHEMA_VERSION),
+            SchemaError::TooOld(v) => write!(f,
+                "written with schema version {}, but this version of AFLTriage only reads version {} and newer. Triage the testcases again",
+                v, MIN_SCHEMA_VERSION),
+            SchemaError::ChangedSemantics { field, version, reason } => write!(f,
+                "the meaning of {} changed in schema version {}: {}", field, version, reason),
+        }
+    }
+}
+
+/// Upgrades the envelope of output from `from` to `from + 1`. The envelope is the top-level
+/// object of a JSON report or the `envelope` of a saved result
+pub struct Migration {
+    pub from: u32,
+    pub migrate: fn(&mut serde_json::Map<String, Value>) -> Result<(), SchemaError>,
+}
+
+/// Version 2 added `schema_version` itself. Everything else is unchanged
+fn migrate_v1(_envelope: &mut serde_json::Map<String, Value>) -> Result<(), SchemaError> {
+    Ok(())
+}
+
+/// Every migration, from the oldest readable version to [SCHEMA_VERSION]
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { from: 1, migrate: migrate_v1 },
+];
+
+/// The schema version of an envelope
+pub fn schema_version(envelope: &Value) -> Result<u32, SchemaError> {
+    match envelope.get("schema_version") {
+        None => Ok(legacy_schema_version()),
+        Some(v) => v.as_u64()
+            .map(|v| v as u32)
+            .ok_or_else(|| SchemaError::Malformed(format!("invalid schema_version {}", v))),
+    }
+}
+
+/// Upgrade an envelope to [SCHEMA_VERSION] using `migrations`
+fn upgrade_with(envelope: &mut Value, migrations: &[Migration], current: u32) -> Result<(), SchemaError> {
+    let mut version = schema_version(envelope)?;
+
+    if version > current {
+        return Err(SchemaError::TooNew(version));
+    }
+
+    let object = envelope.as_object_mut()
+        .ok_or_else(|| SchemaError::Malformed("the report envelope is not an object".into()))?;
+
+    while version < current {
+        let migration = migrations.iter()
+            .find(|m| m.from == version)
+            .ok_or(SchemaError::TooOld(version))?;
+
+        (migration.migrate)(object)?;
+        version += 1;
+    }
+
+    object.insert("schema_version".into(), current.into());
+    Ok(())
+}
+
+/// Upgrade an envelope to [SCHEMA_VERSION]
+pub fn upgrade(envelope: &mut Value) -> Result<(), SchemaError> {
+    upgrade_with(envelope, MIGRATIONS, SCHEMA_VERSION)
+}
+
+fn malformed(e: serde_json::Error) -> SchemaError {
+    SchemaError::Malformed(e.to_string())
+}
+
+/// Read a line of [SAVED_RESULTS_FILE](crate::report::SAVED_RESULTS_FILE) written by this or an
+/// older version of AFLTriage
+pub fn read_saved_result(line: &str) -> Result<SavedResult, SchemaError> {
+    let mut saved: Value = serde_json::from_str(line).map_err(malformed)?;
+
+    let envelope = saved.get_mut("envelope")
+        .ok_or_else(|| SchemaError::Malformed("missing field `envelope`".into()))?;
+    upgrade(envelope)?;
+
+    serde_json::from_value(saved).map_err(malformed)
+}
+
+/// Read a JSON report (the `json` format) written by this or an older version of AFLTriage
+pub fn read_json_report(data: &[u8]) -> Result<(ReportEnvelope, EnrichedTriageInfo), SchemaError> {
+    let (envelope, report) = read_report_value(data)?;
+    Ok((envelope, serde_json::from_value(report).map_err(malformed)?))
+}
+
+/// Read a raw JSON report (the `rawjson` format) written by this or an older version of AFLTriage
+pub fn read_raw_json_report(data: &[u8]) -> Result<(ReportEnvelope, GdbTriageResult), SchemaError> {
+    let (envelope, report) = read_report_value(data)?;
+    Ok((envelope, serde_json::from_value(report).map_err(malformed)?))
+}
+
+fn read_report_value(data: &[u8]) -> Result<(ReportEnvelope, Value), SchemaError> {
+    let mut envelope: Value = serde_json::from_slice(data).map_err(malformed)?;
+    upgrade(&mut envelope)?;
+
+    if envelope.get("truncated") == Some(&Value::Bool(true)) {
+        return Err(SchemaError::Malformed("the report was truncated by --max-json-size and cannot be read back".into()));
+    }
+
+    let report = envelope.as_object_mut()
+        .and_then(|o| o.remove("report"))
+        .ok_or_else(|| SchemaError::Malformed("missing field `report`".into()))?;
+
+    Ok((serde_json::from_value(envelope).map_err(malformed)?, report))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::fixture_path;
+
+    #[test]
+    fn test_read_versioned_report() {
+        // The test report predates schema versioning
+        let data = std::fs::read(fixture_path("asan_stack_bof.json")).unwrap();
+        let (envelope, einfo) = read_json_report(&data).unwrap();
+        assert_eq!(envelope.schema_version, SCHEMA_VERSION);
+        assert_eq!(einfo.faulting_frame_idx, 6);
+
+        let mut newer: Value = serde_json::from_slice(&data).unwrap();
+        newer["schema_version"] = (SCHEMA_VERSION + 1).into();
+        let err = read_json_report(newer.to_string().as_bytes()).unwrap_err();
+        assert_eq!(err, SchemaError::TooNew(SCHEMA_VERSION + 1));
+        assert!(err.to_string().contains("Upgrade AFLTriage"));
+
+        let mut truncated: Value = serde_json::from_slice(&data).unwrap();
+        truncated["truncated"] = true.into();
+        assert!(read_json_report(truncated.to_string().as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_migrations() {
+        fn rename_count(envelope: &mut serde_json::Map<String, Value>) -> Result<(), SchemaError> {
+            if let Some(count) = envelope.remove("count") {
+                envelope.insert("crashes".into(), count);
+            }
+            Ok(())
+        }
+
+        fn reject_score(envelope: &mut serde_json::Map<String, Value>) -> Result<(), SchemaError> {
+            match envelope.contains_key("score") {
+                true => Err(SchemaError::ChangedSemantics {
+                    field: "score".into(),
+                    version: 3,
+                    reason: "scores are now out of 100".into(),
+                }),
+                false => Ok(()),
+            }
+        }
+
+        let migrations = [
+            Migration { from: 1, migrate: rename_count },
+            Migration { from: 2, migrate: reject_score },
+        ];
+
+        let mut envelope = serde_json::json!({"count": 3});
+        upgrade_with(&mut envelope, &migrations, 3).unwrap();
+        assert_eq!(envelope, serde_json::json!({"crashes": 3, "schema_version": 3}));
+
+        // Already current
+        upgrade_with(&mut envelope, &migrations, 3).unwrap();
+        assert_eq!(envelope["crashes"], 3);
+
+        let mut envelope = serde_json::json!({"schema_version": 2, "score": 5});
+        assert_eq!(upgrade_with(&mut envelope, &migrations, 3).unwrap_err().to_string(),
+            "the meaning of score changed in schema version 3: scores are now out of 100");
+
+        let mut envelope = serde_json::json!({"schema_version": 0});
+        assert_eq!(upgrade_with(&mut envelope, &migrations, 3).unwrap_err(), SchemaError::TooOld(0));
+
+        let mut envelope = serde_json::json!({"schema_version": "2"});
+        assert!(upgrade_with(&mut envelope, &migrations, 3).is_err());
+    }
+}
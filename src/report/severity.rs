@@ -0,0 +1,274 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! A coarse crash severity rating used to order findings.
+//!
+//! This is NOT an exploitability classification. The rating only exists to bring the crashes that
+//! most deserve an analyst's attention (e.g. memory writes out of bounds) to the top of aggregate
+//! reports. It is based on the sanitizer report, if any, and the stop information.
+//!
+//! The built-in rating can be overridden to match an organization's triage policy with a
+//! [SeverityModel] of rules given with `--severity-rule CONDITIONS=SEVERITY`. CONDITIONS is one or
+//! more of the following, joined with `+`, that must all hold:
+//!
+//! * `signal:NAME` - the stop signal, like `signal:SIGABRT`
+//! * `sanitizer:REASON` - the sanitizer's stop reason, like `sanitizer:heap-buffer-overflow`
+//! * `access:read`, `access:write`, or `access:execute` - the access that faulted, from the
+//!   sanitizer report or the faulting instruction
+//! * `pc_control` - the target jumped to the faulting address
+//! * `region:KIND` - where the faulting address is: `heap`, `stack`, `module`, `anonymous`,
+//!   `special`, `unmapped`, or `null`. Sanitizer reasons like `heap-use-after-free` also give a
+//!   region
+//! * `hang` - the target was stopped after the timeout
+//!
+//! When several rules match a crash, the one with the most conditions wins, and then the most
+//! severe. Crashes no rule matches keep the built-in rating.
+use serde::{Deserialize, Serialize};
+use strum::{EnumString, IntoStaticStr};
+use crate::report::access::FaultAccess;
+use crate::report::enriched::{EnrichedTriageInfo, FaultAddressKind, MemoryRegionKind};
+
+/// A condition of a [SeverityRule]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SeverityCondition {
+    Signal(String),
+    Sanitizer(String),
+    Access(String),
+    PcControl,
+    Region(String),
+    Hang,
+}
+
+const REGIONS: [&str; 7] = ["heap", "stack", "module", "anonymous", "special", "unmapped", "null"];
+
+impl SeverityCondition {
+    fn parse(spec: &str) -> Result<SeverityCondition, String> {
+        let (key, value) = match spec.split_once(':') {
+            Some((key, value)) => (key, value.to_string()),
+            None => (spec, String::new()),
+        };
+
+        let condition = match (key, value.as_str()) {
+            ("signal", v) if !v.is_empty() => {
+                let v = v.to_uppercase();
+                SeverityCondition::Signal(if v.starts_with("SIG") { v } else { format!("SIG{}", v) })
+            }
+            ("sanitizer", v) if !v.is_empty() => SeverityCondition::Sanitizer(v.to_lowercase()),
+            ("access", "read") | ("access", "write") | ("access", "execute") => SeverityCondition::Access(value),
+            ("pc_control", "") => SeverityCondition::PcControl,
+            ("region", v) if REGIONS.contains(&v) => SeverityCondition::Region(value),
+            ("hang", "") => SeverityCondition::Hang,
+            _ => return Err(format!("Unknown severity rule condition {}", spec)),
+        };
+
+        Ok(condition)
+    }
+
+    fn holds(&self, einfo: &EnrichedTriageInfo) -> bool {
+        let sanitizer = einfo.sanitizer_reports.as_ref().and_then(|r| r.first());
+        let stop_info = &einfo.stop_info;
+
+        match self {
+            SeverityCondition::Signal(name) => stop_info.signal_name == *name,
+            SeverityCondition::Sanitizer(reason) => sanitizer.map(|s| s.stop_reason == *reason).unwrap_or(false),
+            SeverityCondition::Access(access) => {
+                // Reports from before the access was decoded only have the sanitizer's
+                let decoded = stop_info.fault_access.map(|a| a.as_str().to_string())
+                    .or_else(|| sanitizer.map(|s| s.operation.to_lowercase()));
+
+                decoded.as_deref() == Some(access.as_str())
+            }
+            SeverityCondition::PcControl => {
+                let pc = einfo.faulting_thread.frames.first().map(|f| f.address.r);
+                stop_info.faulting_address.as_ref().map(|a| Some(a.r) == pc).unwrap_or(false)
+            }
+            SeverityCondition::Region(region) => {
+                let from_sanitizer = sanitizer.and_then(|s| match s.stop_reason.split('-').next() {
+                    Some("heap") | Some("double") | Some("bad") => Some("heap"),
+                    Some("stack") | Some("dynamic") => Some("stack"),
+                    Some("global") => Some("module"),
+                    _ => None,
+                });
+
+                let from_fault = stop_info.faulting_address.as_ref().and_then(|_| {
+                    if stop_info.is_null_dereference() {
+                        Some("null")
+                    } else if stop_info.fault_address_kind == Some(FaultAddressKind::non_canonical) {
+                        Some("unmapped")
+                    } else {
+                        stop_info.faulting_address_region.as_ref().map(|r| r.kind.into())
+                    }
+                });
+
+                from_sanitizer.or(from_fault) == Some(region.as_str())
+            }
+            SeverityCondition::Hang => einfo.hang == Some(true),
+        }
+    }
+}
+
+/// A rule giving crashes that meet all of its conditions a severity
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeverityRule {
+    pub conditions: Vec<SeverityCondition>,
+    pub severity: CrashSeverity,
+}
+
+impl SeverityRule {
+    /// Parse a rule like `sanitizer:heap-buffer-overflow+access:read=high`
+    pub fn parse(spec: &str) -> Result<SeverityRule, String> {
+        let (conditions, severity) = spec.rsplit_once('=')
+            .ok_or_else(|| format!("Severity rule {} must look like CONDITIONS=SEVERITY", spec))?;
+
+        let severity = severity.parse::<CrashSeverity>()
+            .map_err(|_| format!("Unknown severity {} in rule {}", severity, spec))?;
+
+        let conditions = conditions.split('+')
+            .map(SeverityCondition::parse)
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(SeverityRule { conditions, severity })
+    }
+
+    fn matches(&self, einfo: &EnrichedTriageInfo) -> bool {
+        self.conditions.iter().all(|c| c.holds(einfo))
+    }
+}
+
+/// Rules overriding the built-in severity rating
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SeverityModel {
+    pub rules: Vec<SeverityRule>,
+}
+
+impl SeverityModel {
+    /// Rate a crash with the most specific matching rule, or the built-in rating
+    pub fn rate(&self, einfo: &EnrichedTriageInfo) -> CrashSeverity {
+        self.rules.iter()
+            .filter(|rule| rule.matches(einfo))
+            .max_by_key(|rule| (rule.conditions.len(), rule.severity))
+            .map(|rule| rule.severity)
+            .unwrap_or_else(|| rate_crash_severity(einfo))
+    }
+}
+
+/// How severe a crash is believed to be. Variants are ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, EnumString, IntoStaticStr, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+pub enum CrashSeverity {
+    unknown,
+    low,
+    medium,
+    high,
+}
+
+impl CrashSeverity {
+    pub fn as_str(&self) -> &'static str {
+        self.into()
+    }
+}
+
+/// Addresses below this are treated as NULL pointer dereferences
+pub(crate) const NULL_PAGE_LIMIT: u64 = 0x10000;
+
+/// Rate the severity of a crash using its [EnrichedTriageInfo]
+pub fn rate_crash_severity(einfo: &EnrichedTriageInfo) -> CrashSeverity {
+    // At worst a denial of service
+    if einfo.hang == Some(true) || einfo.stack_exhausted == Some(true) || einfo.panicked == Some(true)
+        || einfo.go_fatal_error.is_some() {
+        return CrashSeverity::low;
+    }
+
+    if let Some(reports) = &einfo.sanitizer_reports {
+        if let Some(san) = reports.first() {
+            if let Some(severity) = rate_sanitizer_reason(&san.stop_reason, &san.operation) {
+                return severity;
+            }
+        }
+    }
+
+    // Firmware faults on bad memory accesses, but nothing is known of the access
+    if einfo.fault_handler.is_some() {
+        return CrashSeverity::medium;
+    }
+
+    let stop_info = &einfo.stop_info;
+
+    match stop_info.signal_name.as_str() {
+        "SIGSEGV" | "SIGBUS" => {
+            let pc = einfo.faulting_thread.frames.first().map(|f| f.address.r);
+
+            match &stop_info.faulting_address {
+                // Fetching an instruction from the faulting address
+                Some(addr) if Some(addr.r) == pc => CrashSeverity::high,
+                Some(_) if stop_info.is_null_dereference() => CrashSeverity::low,
+                // A wild write
+                Some(_) if stop_info.fault_access == Some(FaultAccess::write) => CrashSeverity::high,
+                Some(_) => match &stop_info.faulting_address_region {
+                    Some(region) if region.kind == MemoryRegionKind::unmapped => CrashSeverity::medium,
+                    // A fault on mapped memory is a permission violation (e.g. writing to .text)
+                    Some(_) => CrashSeverity::high,
+                    None => CrashSeverity::medium,
+                },
+                None => CrashSeverity::medium,
+            }
+        }
+        "SIGILL" => CrashSeverity::medium,
+        "SIGABRT" | "SIGFPE" | "SIGTRAP" => CrashSeverity::low,
+        _ => CrashSeverity::unknown,
+    }
+}
+
+/// Rate a sanitizer stop reason. Returns None for reasons that are better rated by the signal
+fn rate_sanitizer_reason(reason: &str, operation: &str) -> Option<CrashSeverity> {
+    match reason {
+        "SEGV" | "FPE" | "ILL" | "BUS" | "ABRT" => None,
+        "heap-use-after-free" | "double-free" | "bad-free" |
+            "alloc-dealloc-mismatch" => Some(CrashSeverity::high),
+        // A failed glibc stack protector, FORTIFY_SOURCE, or malloc check means memory was already
+        // overwritten
+        "stack-smashing" | "fortify-buffer-overflow" | "fortify-failure" |
+            "heap-corruption" => Some(CrashSeverity::high),
+        "heap-buffer-overflow" | "stack-buffer-overflow" | "global-buffer-overflow" |
+            "stack-buffer-underflow" | "stack-use-after-return" | "stack-use-after-scope" |
+            "container-overflow" | "dynamic-stack-buffer-overflow" | "use-after-poison" => {
+            if operation == "WRITE" {
+                Some(CrashSeverity::high)
+            } else {
+                Some(CrashSeverity::medium)
+            }
+        }
+        // "requested allocation size exceeds maximum supported size"
+        "stack-overflow" | "allocation-size-too-big" | "out-of-memory" | "calloc-overflow" |
+            "requested" => Some(CrashSeverity::low),
+        _ => Some(CrashSeverity::medium),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::fixtures::load_test_report;
+
+    #[test]
+    fn test_severity_model() {
+        let einfo: EnrichedTriageInfo = load_test_report();
+
+        let model = |rules: &[&str]| SeverityModel {
+            rules: rules.iter().map(|r| SeverityRule::parse(r).unwrap()).collect(),
+        };
+
+        // A stack-buffer-overflow read
+        assert_eq!(model(&[]).rate(&einfo), CrashSeverity::medium);
+        assert_eq!(model(&["region:stack=high"]).rate(&einfo), CrashSeverity::high);
+        assert_eq!(model(&["region:heap=high", "access:write=high"]).rate(&einfo), CrashSeverity::medium);
+        assert_eq!(model(&["sanitizer:stack-buffer-overflow=high", "sanitizer:stack-buffer-overflow+access:read=low"])
+            .rate(&einfo), CrashSeverity::low);
+        assert_eq!(model(&["region:stack=low", "access:read=high"]).rate(&einfo), CrashSeverity::high);
+
+        assert!(SeverityRule::parse("signal:segv=low").unwrap().conditions == vec![SeverityCondition::Signal("SIGSEGV".into())]);
+        assert!(SeverityRule::parse("region:stack").is_err());
+        assert!(SeverityRule::parse("region:moon=high").is_err());
+        assert!(SeverityRule::parse("hang=critical").is_err());
+    }
+}
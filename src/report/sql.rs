@@ -0,0 +1,173 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! An SQL script of a triage run for loading into SQLite.
+//!
+//! [RESULTS_SQL_FILE] creates `testcases`, `frames`, and `buckets` tables if they do not exist and
+//! fills them in a single transaction. Loading the scripts of several runs into the same database
+//! merges them: testcases are keyed by their path and replace earlier results, while a bucket keeps
+//! the details of the first crash that created it.
+//!
+//! ```text
+//! $ sqlite3 triage.db < reports/afltriage_results.sql
+//! $ sqlite3 triage.db "SELECT bucket, crashes FROM bucket_crashes ORDER BY crashes DESC"
+//! ```
+use crate::report::index::BucketEntry;
+use crate::report::summary::TestcaseSummary;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The SQL script in an output directory
+pub const RESULTS_SQL_FILE: &str = "afltriage_results.sql";
+
+const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS testcases (
+    testcase TEXT PRIMARY KEY,
+    bucket TEXT NOT NULL,
+    severity TEXT NOT NULL,
+    signal TEXT,
+    faulting_address TEXT,
+    faulting_function TEXT,
+    location TEXT,
+    summary TEXT,
+    triaged_at TEXT,
+    triage_duration_ms INTEGER,
+    afl_op TEXT,
+    afl_time_ms INTEGER
+);
+CREATE TABLE IF NOT EXISTS frames (
+    testcase TEXT NOT NULL,
+    idx INTEGER NOT NULL,
+    address TEXT,
+    module TEXT,
+    function TEXT,
+    file TEXT,
+    line INTEGER,
+    PRIMARY KEY (testcase, idx)
+);
+CREATE TABLE IF NOT EXISTS buckets (
+    bucket TEXT PRIMARY KEY,
+    severity TEXT NOT NULL,
+    summary TEXT,
+    first_testcase TEXT
+);
+CREATE VIEW IF NOT EXISTS bucket_crashes AS
+    SELECT bucket, COUNT(*) AS crashes FROM testcases GROUP BY bucket;
+";
+
+/// Quote an SQL string literal. Empty strings become NULL
+fn sql_text(value: &str) -> String {
+    if value.is_empty() {
+        "NULL".to_string()
+    } else {
+        format!("'{}'", value.replace('\0', "").replace('\'', "''"))
+    }
+}
+
+fn sql_int<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_else(|| "NULL".to_string())
+}
+
+/// Render the SQL script for all crashing testcases and the bucket `entries`
+pub fn format_results_sql(entries: &[&BucketEntry], summaries: &[TestcaseSummary]) -> String {
+    let mut sql = String::from("BEGIN TRANSACTION;\n");
+    sql += SCHEMA;
+
+    for entry in entries {
+        sql += &format!("INSERT OR IGNORE INTO buckets VALUES ({}, {}, {}, {});\n",
+            sql_text(&entry.bucket), sql_text(entry.severity.as_str()), sql_text(&entry.summary),
+            sql_text(entry.testcases.first().map(String::as_str).unwrap_or("")));
+    }
+
+    for tc in summaries {
+        let testcase = sql_text(&tc.testcase);
+
+        sql += &format!("INSERT OR REPLACE INTO testcases VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {});\n",
+            testcase, sql_text(&tc.bucket), sql_text(tc.severity.as_str()), sql_text(&tc.signal),
+            sql_text(&tc.faulting_address), sql_text(&tc.faulting_function), sql_text(&tc.location),
+            sql_text(&tc.summary), sql_text(&tc.triaged_at), sql_int(tc.triage_duration_ms),
+            sql_text(&tc.afl_op), sql_int(tc.afl_time_ms));
+
+        sql += &format!("DELETE FROM frames WHERE testcase = {};\n", testcase);
+
+        for (i, frame) in tc.frames.iter().enumerate() {
+            sql += &format!("INSERT INTO frames VALUES ({}, {}, {}, {}, {}, {}, {});\n",
+                testcase, i, sql_text(&frame.address), sql_text(&frame.module),
+                sql_text(&frame.function), sql_text(&frame.file), sql_int(frame.line));
+        }
+    }
+
+    sql += "COMMIT;\n";
+    sql
+}
+
+/// Write the SQL script to `output_dir`, or to the terminal if there is none
+pub fn write_results_sql(buckets: &HashMap<String, BucketEntry>, summaries: &[TestcaseSummary], output_dir: Option<&Path>) {
+    let mut entries: Vec<&BucketEntry> = buckets.values().collect();
+    crate::report::index::sort_bucket_entries(&mut entries);
+
+    let sql = format_results_sql(&entries, summaries);
+
+    match output_dir {
+        Some(output_dir) => {
+            let path = output_dir.join(RESULTS_SQL_FILE);
+
+            if let Err(e) = std::fs::write(&path, sql) {
+                log::error!("Failed to write SQL results: {}", e);
+            } else {
+                log::info!("Wrote SQL results to {}", shlex::quote(&path.to_string_lossy()));
+            }
+        }
+        None => println!("--- SQL RESULTS BEGIN ---\n{}--- SQL RESULTS END ---", sql),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::severity::CrashSeverity;
+    use crate::report::summary::FrameSummary;
+
+    #[test]
+    fn test_results_sql() {
+        let tc = TestcaseSummary {
+            testcase: "crashes/id:000000,it's".into(),
+            bucket: "abc".into(),
+            severity: CrashSeverity::high,
+            signal: "SIGSEGV".into(),
+            faulting_address: "".into(),
+            faulting_function: "main".into(),
+            location: "test.c:20".into(),
+            summary: "A summary".into(),
+            triaged_at: "2021-06-01T12:00:00Z".into(),
+            triage_duration_ms: None,
+            afl_op: "".into(),
+            afl_time_ms: Some(1500),
+            frames: vec![FrameSummary {
+                address: "0x0000000000401000".into(),
+                module: "/tmp/test".into(),
+                function: "main".into(),
+                file: "test.c".into(),
+                line: Some(20),
+            }],
+        };
+
+        let entry = BucketEntry {
+            bucket: "abc".into(),
+            summary: "A summary".into(),
+            severity: CrashSeverity::high,
+            testcases: vec![tc.testcase.to_string()],
+            reports: vec![],
+            reproducibility: None,
+        };
+
+        let sql = format_results_sql(&[&entry], &[tc]);
+        let lines: Vec<&str> = sql.lines().collect();
+
+        assert_eq!(lines[0], "BEGIN TRANSACTION;");
+        assert_eq!(*lines.last().unwrap(), "COMMIT;");
+        assert!(lines.contains(&"INSERT OR IGNORE INTO buckets VALUES ('abc', 'high', 'A summary', 'crashes/id:000000,it''s');"));
+        assert!(lines.contains(&"INSERT OR REPLACE INTO testcases VALUES ('crashes/id:000000,it''s', 'abc', 'high', 'SIGSEGV', NULL, 'main', 'test.c:20', 'A summary', '2021-06-01T12:00:00Z', NULL, NULL, 1500);"));
+        assert!(lines.contains(&"INSERT INTO frames VALUES ('crashes/id:000000,it''s', 0, '0x0000000000401000', '/tmp/test', 'main', 'test.c', 20);"));
+    }
+}
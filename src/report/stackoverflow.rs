@@ -0,0 +1,168 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Detecting crashes caused by stack exhaustion.
+//!
+//! Without a sanitizer to say so, runaway recursion ends in a plain SIGSEGV in whichever function
+//! first touched the guard page below the stack, often at a `push` or `call`. A crash is treated as
+//! stack exhaustion when the faulting address is close to the stack pointer and either the
+//! backtrace recurses or the address is in the guard gap below a stack. Thread stacks have a
+//! guard mapping without permissions, and the main thread's stack has an unmapped gap below it.
+//!
+//! Recursion is found in any backtrace as a [RecursionCycle] of frames repeating at least
+//! [MIN_RECURSION_REPEATS] times in a row. Text reports print one repetition of it, as the
+//! backtrace is otherwise a wall of identical frames.
+use crate::debugger::gdb::{GdbMemoryMapping, GdbRegister};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// The longest cycle of frames looked for, for mutual recursion
+pub const MAX_RECURSION_CYCLE: usize = 8;
+
+/// How many times frames must repeat in a row to be considered recursion
+pub const MIN_RECURSION_REPEATS: usize = 8;
+
+/// How far below the stack pointer a fault is considered a stack access
+const STACK_FAULT_DISTANCE: u64 = 0x10000;
+
+/// How far below the main thread's stack the kernel keeps an unmapped guard gap (`stack_guard_gap`)
+const STACK_GUARD_GAP: u64 = 0x100000;
+
+/// Frames of a backtrace that repeat
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecursionCycle {
+    /// The innermost frame of the first repetition
+    pub first_frame: usize,
+    /// How many frames one repetition has
+    pub length: usize,
+    /// How many times the frames repeat in a row in the backtrace. As backtraces are cut off, the
+    /// recursion may go deeper
+    pub repeats: usize,
+}
+
+impl RecursionCycle {
+    /// The frames after the first repetition
+    pub fn repeated_frames(&self) -> std::ops::Range<usize> {
+        self.first_frame + self.length..self.first_frame + self.length * self.repeats
+    }
+}
+
+/// Find the innermost recursion in a backtrace of frame `addresses`, if any
+pub fn find_recursion(addresses: &[u64]) -> Option<RecursionCycle> {
+    for first_frame in 0..addresses.len() {
+        let mut best: Option<RecursionCycle> = None;
+
+        for length in 1..=MAX_RECURSION_CYCLE {
+            let cycle = match addresses.get(first_frame..first_frame + length) {
+                Some(cycle) => cycle,
+                None => break,
+            };
+
+            let repeats = addresses[first_frame..].chunks_exact(length)
+                .take_while(|chunk| *chunk == cycle)
+                .count();
+
+            // Prefer the shortest cycle covering the most frames
+            if repeats >= MIN_RECURSION_REPEATS
+                && best.as_ref().map(|b| b.length * b.repeats).unwrap_or(0) < length * repeats {
+                best = Some(RecursionCycle { first_frame, length, repeats });
+            }
+        }
+
+        if best.is_some() {
+            return best;
+        }
+    }
+
+    None
+}
+
+/// The stack pointer, by its name on common architectures
+pub fn find_stack_pointer(registers: &Option<Vec<Rc<GdbRegister>>>) -> Option<u64> {
+    registers.iter().flatten()
+        .find(|r| ["rsp", "esp", "sp"].contains(&r.name.as_str()))
+        .map(|r| r.value)
+}
+
+/// Whether `address` is in the guard gap below a stack
+fn in_stack_guard(memory_map: &[GdbMemoryMapping], address: u64) -> bool {
+    let writable = |m: &GdbMemoryMapping| matches!(m.permissions.as_deref(), Some(p) if p.contains('w'));
+
+    match memory_map.iter().find(|m| address >= m.start && address < m.end) {
+        // Thread stacks are preceded by a mapping without permissions
+        Some(guard) => matches!(guard.permissions.as_deref(), Some(p) if p.starts_with("---"))
+            && memory_map.iter().any(|m| m.start == guard.end && writable(m)),
+        // The main thread's stack grows down into an unmapped gap
+        None => memory_map.iter()
+            .filter(|m| m.start > address)
+            .min_by_key(|m| m.start)
+            .filter(|m| m.name.starts_with("[stack") && m.start - address <= STACK_GUARD_GAP)
+            .is_some(),
+    }
+}
+
+/// Whether a fault at `fault_address` was caused by exhausting the stack, given the stack pointer
+/// and whether the backtrace recurses
+pub fn detect_stack_exhaustion(
+    fault_address: Option<u64>,
+    stack_pointer: Option<u64>,
+    memory_map: &Option<Vec<GdbMemoryMapping>>,
+    recursion: bool,
+) -> bool {
+    let address = match fault_address {
+        Some(address) => address,
+        None => return false,
+    };
+
+    let in_guard = memory_map.as_ref().map(|m| in_stack_guard(m, address)).unwrap_or(false);
+
+    match stack_pointer {
+        // Pushes and calls write just below the stack pointer, and large frames further below it
+        Some(sp) => address <= sp && sp - address <= STACK_FAULT_DISTANCE && (recursion || in_guard),
+        None => recursion && in_guard,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stack_exhaustion() {
+        let mut frames = vec![0x401000];
+        frames.extend([0x401100, 0x401200].repeat(20));
+        frames.push(0x401300);
+
+        let cycle = find_recursion(&frames).unwrap();
+        assert_eq!(cycle, RecursionCycle { first_frame: 1, length: 2, repeats: 20 });
+        assert_eq!(cycle.repeated_frames(), 3..41);
+
+        // Not repeated enough
+        assert_eq!(find_recursion(&[0x401100, 0x401100, 0x401100, 0x401000]), None);
+        assert_eq!(find_recursion(&[0x401100; 100]).unwrap(), RecursionCycle { first_frame: 0, length: 1, repeats: 100 });
+
+        let mapping = |start, end, permissions: &str, name: &str| GdbMemoryMapping {
+            start,
+            end,
+            offset: 0,
+            permissions: Some(permissions.into()),
+            name: name.into(),
+        };
+        let memory_map = Some(vec![
+            mapping(0x7f0000000000, 0x7f0000001000, "---p", ""),
+            mapping(0x7f0000001000, 0x7f0000800000, "rw-p", ""),
+            mapping(0x7ffffffde000, 0x7ffffffff000, "rw-p", "[stack]"),
+        ]);
+
+        // Just below the main thread's stack
+        assert!(detect_stack_exhaustion(Some(0x7ffffffddff8), Some(0x7ffffffde000), &memory_map, false));
+        // In a thread's guard page
+        assert!(detect_stack_exhaustion(Some(0x7f0000000ff8), Some(0x7f0000001000), &memory_map, false));
+        assert!(detect_stack_exhaustion(Some(0x7f0000000ff8), None, &memory_map, true));
+        // Far from the stack pointer
+        assert!(!detect_stack_exhaustion(Some(0x7f0000000ff8), Some(0x7ffffffde000), &memory_map, true));
+        // An unrelated NULL dereference
+        assert!(!detect_stack_exhaustion(Some(0), Some(0x7ffffffde000), &memory_map, true));
+        assert!(!detect_stack_exhaustion(None, Some(0x7ffffffde000), &memory_map, true));
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Flat per-testcase crash summaries used by the run-level report formats (CSV and SQL).
+use crate::report::enriched::EnrichedTriageInfo;
+use crate::report::severity::CrashSeverity;
+use crate::report::ReportEnvelope;
+
+/// A frame of the faulting thread
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameSummary {
+    pub address: String,
+    pub module: String,
+    pub function: String,
+    pub file: String,
+    pub line: Option<i64>,
+}
+
+/// The key facts of a crashing testcase
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestcaseSummary {
+    pub testcase: String,
+    pub bucket: String,
+    pub severity: CrashSeverity,
+    pub signal: String,
+    pub faulting_address: String,
+    pub faulting_function: String,
+    /// The file:line of the faulting frame, if known
+    pub location: String,
+    pub summary: String,
+    /// The ISO-8601 time the testcase was triaged, if known
+    pub triaged_at: String,
+    pub triage_duration_ms: Option<u64>,
+    /// The mutation operator of an AFL testcase
+    pub afl_op: String,
+    /// When an AFL++ testcase was found, in milliseconds from the start of the fuzzer
+    pub afl_time_ms: Option<u64>,
+    /// The faulting thread's backtrace, innermost frame first
+    pub frames: Vec<FrameSummary>,
+}
+
+impl TestcaseSummary {
+    pub fn new(envelope: &ReportEnvelope, bucket: &str, severity: CrashSeverity, einfo: &EnrichedTriageInfo) -> Self {
+        let location = einfo.faulting_thread.frames.get(einfo.faulting_frame_idx)
+            .and_then(|f| f.symbol.as_ref())
+            .map(|s| s.format_file())
+            .unwrap_or_default();
+
+        let frames = einfo.faulting_thread.frames.iter()
+            .map(|f| FrameSummary {
+                address: f.address.f.to_string(),
+                module: f.module.to_string(),
+                function: f.symbol.as_ref().map(|s| s.format_short()).unwrap_or_default(),
+                file: f.symbol.as_ref().and_then(|s| s.file.clone()).unwrap_or_default(),
+                line: f.symbol.as_ref().and_then(|s| s.line),
+            })
+            .collect();
+
+        TestcaseSummary {
+            testcase: envelope.testcase.to_string(),
+            bucket: bucket.to_string(),
+            severity,
+            signal: einfo.stop_info.signal_name.to_string(),
+            faulting_address: einfo.stop_info.faulting_address.as_ref()
+                .map(|a| a.f.to_string())
+                .unwrap_or_default(),
+            faulting_function: einfo.faulting_function.to_string(),
+            location,
+            summary: einfo.summary.to_string(),
+            triaged_at: envelope.triaged_at.clone().unwrap_or_default(),
+            triage_duration_ms: envelope.triage_duration_ms,
+            afl_op: envelope.afl_testcase.as_ref().and_then(|n| n.op.clone()).unwrap_or_default(),
+            afl_time_ms: envelope.afl_testcase.as_ref().and_then(|n| n.time),
+            frames,
+        }
+    }
+}
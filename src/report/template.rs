@@ -0,0 +1,287 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Templates for text reports.
+//!
+//! Text reports are rendered from a template, so teams can change their layout, add links to
+//! internal systems, or leave out sections without patching AFLTriage. The default template,
+//! [DEFAULT_TEXT_TEMPLATE], is the built-in layout. Templates use a small subset of the Handlebars
+//! syntax:
+//!
+//! * `{{path}}` inserts a value. Paths are dotted, like `report.stop_info.signal_name` or
+//!   `report.faulting_thread.frames.0.summary`. Strings are inserted as is and other values as
+//!   JSON. Missing values insert nothing
+//! * `{{#if path}}...{{/if}}` keeps its contents if the value is present and not `false`, `0`,
+//!   empty, or `null`. `{{#unless path}}...{{/unless}}` is the opposite. Both can have an
+//!   `{{else}}`
+//! * `{{! comment }}` is left out
+//!
+//! Block tags alone on a line take the line with them, so they do not leave blank lines behind.
+//! Rendered reports are trimmed and end with a single newline.
+//!
+//! The values are those of a JSON report: `report` is the triage information and the rest is the
+//! report envelope (`testcase`, `command_line`, `bucket`, etc.). `sections` has every section of
+//! the built-in layout, already formatted with its title, or empty if it has nothing to show:
+//! `header`, `backtrace`, `other_threads`, `sanitizer_report`, `crash_context`, `register_info`,
+//! `extension_data`, `modules`, `decoded_testcase`, and `child_output`.
+use serde_json::Value;
+use std::path::Path;
+
+/// The built-in text report layout
+pub const DEFAULT_TEXT_TEMPLATE: &str = include_str!("res/report.txt.hbs");
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Text(String),
+    Value(String),
+    /// A `{{#if}}` (or negated `{{#unless}}`) block, with its `{{else}}` nodes
+    If {
+        path: String,
+        negated: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// A parsed text report template
+#[derive(Debug)]
+pub struct TextTemplate {
+    nodes: Vec<Node>,
+}
+
+/// A tag and the text before it
+enum Token<'a> {
+    Text(&'a str),
+    Tag(&'a str),
+}
+
+fn is_block_tag(tag: &str) -> bool {
+    tag.starts_with('#') || tag.starts_with('/') || tag.starts_with('!') || tag == "else"
+}
+
+/// Split a template into text and tags. Block tags alone on a line take the line's whitespace and
+/// newline with them
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = vec![];
+    let mut rest = source;
+    // Whether `rest` starts at the beginning of a line
+    let mut line_begins = true;
+
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..].find("}}")
+            .ok_or_else(|| format!("unclosed tag at line {}", line_of(source, rest, start)))?;
+        let tag = rest[start + 2..start + end].trim();
+
+        let mut text = &rest[..start];
+        let mut after = &rest[start + end + 2..];
+
+        let mut standalone = false;
+
+        if is_block_tag(tag) {
+            let line_start = text.rfind('\n').map(|i| i + 1).unwrap_or(0);
+            let line_end = after.find('\n').map(|i| i + 1).unwrap_or(after.len());
+
+            standalone = (line_start > 0 || line_begins)
+                && text[line_start..].trim().is_empty()
+                && after[..line_end].trim().is_empty();
+
+            if standalone {
+                text = &text[..line_start];
+                after = &after[line_end..];
+            }
+        }
+
+        if !text.is_empty() {
+            tokens.push(Token::Text(text));
+        }
+
+        tokens.push(Token::Tag(tag));
+        rest = after;
+        line_begins = standalone;
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+
+    Ok(tokens)
+}
+
+fn line_of(source: &str, rest: &str, offset: usize) -> usize {
+    let consumed = source.len() - rest.len() + offset;
+    source[..consumed].matches('\n').count() + 1
+}
+
+/// Parse tokens until the end of a block named `block`, or the end of the template
+fn parse_nodes<'a, I: Iterator<Item = Token<'a>>>(tokens: &mut I, block: Option<&str>) -> Result<(Vec<Node>, bool), String> {
+    let mut nodes = vec![];
+
+    while let Some(token) = tokens.next() {
+        let tag = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text.to_string()));
+                continue;
+            }
+            Token::Tag(tag) => tag,
+        };
+
+        if tag.starts_with('!') {
+            continue;
+        } else if tag == "else" {
+            return match block {
+                Some(_) => Ok((nodes, true)),
+                None => Err("{{else}} outside of a block".into()),
+            };
+        } else if let Some(name) = tag.strip_prefix('/') {
+            return match block {
+                Some(open) if open == name.trim() => Ok((nodes, false)),
+                Some(open) => Err(format!("{{{{/{}}}}} closes {{{{#{}}}}}", name.trim(), open)),
+                None => Err(format!("{{{{/{}}}}} without a block to close", name.trim())),
+            };
+        } else if let Some(open) = tag.strip_prefix('#') {
+            let mut words = open.split_whitespace();
+            let (name, path) = match (words.next(), words.next(), words.next()) {
+                (Some(name), Some(path), None) if name == "if" || name == "unless" => (name, path),
+                _ => return Err(format!("unsupported block {{{{#{}}}}}, only #if and #unless are", open)),
+            };
+
+            let (then, has_else) = parse_nodes(tokens, Some(name))?;
+            let otherwise = if has_else {
+                match parse_nodes(tokens, Some(name))? {
+                    (nodes, false) => nodes,
+                    (_, true) => return Err(format!("more than one {{{{else}}}} in {{{{#{}}}}}", name)),
+                }
+            } else {
+                vec![]
+            };
+
+            nodes.push(Node::If {
+                path: path.to_string(),
+                negated: name == "unless",
+                then,
+                otherwise,
+            });
+        } else if tag.is_empty() || tag.contains(char::is_whitespace) {
+            return Err(format!("invalid tag {{{{{}}}}}", tag));
+        } else {
+            nodes.push(Node::Value(tag.to_string()));
+        }
+    }
+
+    match block {
+        Some(open) => Err(format!("{{{{#{}}}}} is never closed", open)),
+        None => Ok((nodes, false)),
+    }
+}
+
+/// Look up a dotted `path` in `context`
+fn lookup<'a>(context: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(context, |value, key| match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    })
+}
+
+fn is_truthy(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) => false,
+        Some(Value::Bool(b)) => *b,
+        Some(Value::Number(n)) => n.as_f64() != Some(0.0),
+        Some(Value::String(s)) => !s.is_empty(),
+        Some(Value::Array(a)) => !a.is_empty(),
+        Some(Value::Object(o)) => !o.is_empty(),
+    }
+}
+
+fn render_nodes(nodes: &[Node], context: &Value, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Value(path) => match lookup(context, path) {
+                None | Some(Value::Null) => (),
+                Some(Value::String(s)) => out.push_str(s),
+                Some(value) => out.push_str(&value.to_string()),
+            },
+            Node::If { path, negated, then, otherwise } => {
+                if is_truthy(lookup(context, path)) != *negated {
+                    render_nodes(then, context, out);
+                } else {
+                    render_nodes(otherwise, context, out);
+                }
+            }
+        }
+    }
+}
+
+impl TextTemplate {
+    pub fn parse(source: &str) -> Result<TextTemplate, String> {
+        let tokens = tokenize(source)?;
+        let (nodes, _) = parse_nodes(&mut tokens.into_iter(), None)?;
+        Ok(TextTemplate { nodes })
+    }
+
+    pub fn load(path: &Path) -> Result<TextTemplate, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        TextTemplate::parse(&source)
+    }
+
+    /// The built-in text report layout
+    pub fn default_template() -> TextTemplate {
+        TextTemplate::parse(DEFAULT_TEXT_TEMPLATE).unwrap()
+    }
+
+    /// Render a report from its values in `context`
+    pub fn render(&self, context: &Value) -> String {
+        let mut out = String::new();
+        render_nodes(&self.nodes, context, &mut out);
+
+        let mut report = out.trim().to_string();
+        report += "\n";
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_template() {
+        let context = json!({
+            "testcase": "id:000000",
+            "report": {"summary": "CRASH detected", "frames": [{"pc": 4096}], "hang": null},
+            "sections": {"backtrace": "Crashing thread backtrace:\n#0 main", "modules": ""},
+        });
+
+        let template = TextTemplate::parse("\
+            {{! A custom layout }}\n\
+            Testcase: {{testcase}} ({{report.summary}}, first PC {{report.frames.0.pc}}){{missing.value}}\n\
+            {{#if sections.backtrace}}\n\
+            \n\
+            {{sections.backtrace}}\n\
+            {{/if}}\n\
+            {{#if sections.modules}}\n\
+            {{sections.modules}}\n\
+            {{else}}\n\
+            No modules\n\
+            {{/if}}\n\
+            {{#unless report.hang}}Not a hang{{/unless}}\n").unwrap();
+
+        assert_eq!(template.render(&context), "\
+            Testcase: id:000000 (CRASH detected, first PC 4096)\n\
+            \n\
+            Crashing thread backtrace:\n\
+            #0 main\n\
+            No modules\n\
+            Not a hang\n");
+
+        assert_eq!(TextTemplate::parse("{{#if a}}x").unwrap_err(), "{{#if}} is never closed");
+        assert_eq!(TextTemplate::parse("{{#if a}}x{{/unless}}").unwrap_err(), "{{/unless}} closes {{#if}}");
+        assert_eq!(TextTemplate::parse("{{#each a}}x{{/each}}").unwrap_err(), "unsupported block {{#each a}}, only #if and #unless are");
+        assert_eq!(TextTemplate::parse("x\n{{a").unwrap_err(), "unclosed tag at line 2");
+        assert!(TextTemplate::parse("{{else}}").is_err());
+        assert!(TextTemplate::default_template().nodes.len() > 1);
+    }
+}
@@ -5,10 +5,23 @@
 use crate::{ReportOptions, ReportEnvelope};
 use crate::debugger::gdb::*;
 use crate::report::enriched::*;
-use crate::util::{shell_join, elide_size};
+use crate::report::golang::GoFatalError;
+use crate::report::template::TextTemplate;
+use crate::util::{self, shell_join, elide_size};
 
 use std::collections::HashSet;
 
+lazy_static! {
+    static ref DEFAULT_TEMPLATE: TextTemplate = TextTemplate::default_template();
+}
+
+/// How many frames of each group of other threads are shown
+const OTHER_THREAD_FRAMES: usize = 3;
+/// How many thread IDs of each group of other threads are shown
+const OTHER_THREAD_TIDS: usize = 8;
+/// How many triage warnings are shown
+const TRIAGE_WARNINGS: usize = 5;
+
 enum TextReportSectionEntry {
     Line(String),
     Section(TextReportSection),
@@ -70,37 +83,46 @@ struct TextReportSections {
     register_info: TextReportSection,
     crash_context: TextReportSection,
     backtrace: TextReportSection,
+    other_threads: TextReportSection,
     sanitizer_report: TextReportSection,
+    extension_data: TextReportSection,
+    modules: TextReportSection,
+    decoded_testcase: TextReportSection,
     child_output: TextReportSection,
 }
 
 /// Render a text report using information from [EnrichedTriageInfo] and the corresponding
 /// [ReportEnvelope].
 pub fn format_text_report(einfo: &EnrichedTriageInfo, envelope: &ReportEnvelope) -> String {
+    format_text_report_with(&DEFAULT_TEMPLATE, einfo, envelope)
+}
+
+/// Render a text report with a custom `template` (see [template](crate::report::template))
+pub fn format_text_report_with(template: &TextTemplate, einfo: &EnrichedTriageInfo, envelope: &ReportEnvelope) -> String {
     let sections = build_text_report(einfo, envelope);
 
-    let mut report: String = String::new();
-
-    let sec_order = vec![
-        &sections.header,
-        &sections.backtrace,
-        &sections.sanitizer_report,
-        &sections.crash_context,
-        &sections.register_info,
-        &sections.child_output,
-    ];
-
-    for sec in sec_order {
-        if sec.len() > 0 {
-            report += &sec.format().trim();
-            report += "\n\n";
-        }
-    }
+    let format_section = |sec: &TextReportSection| if sec.len() > 0 {
+        sec.format().trim().to_string()
+    } else {
+        String::new()
+    };
 
-    // remove all but last newline
-    report = report.trim().to_string();
-    report += "\n";
-    report
+    let mut context = serde_json::to_value(envelope).unwrap();
+    context["report"] = serde_json::to_value(einfo).unwrap();
+    context["sections"] = serde_json::json!({
+        "header": format_section(&sections.header),
+        "backtrace": format_section(&sections.backtrace),
+        "sanitizer_report": format_section(&sections.sanitizer_report),
+        "crash_context": format_section(&sections.crash_context),
+        "register_info": format_section(&sections.register_info),
+        "other_threads": format_section(&sections.other_threads),
+        "extension_data": format_section(&sections.extension_data),
+        "modules": format_section(&sections.modules),
+        "decoded_testcase": format_section(&sections.decoded_testcase),
+        "child_output": format_section(&sections.child_output),
+    });
+
+    template.render(&context)
 }
 
 fn build_text_report(einfo: &EnrichedTriageInfo, envelope: &ReportEnvelope) -> TextReportSections {
@@ -109,17 +131,91 @@ fn build_text_report(einfo: &EnrichedTriageInfo, envelope: &ReportEnvelope) -> T
     let mut crash_context = TextReportSection::new("Crash context".into());
 
     let mut backtrace = TextReportSection::new("Crashing thread backtrace".into());
+    let mut other_threads = TextReportSection::new("Other threads".into());
     let mut sanitizer_report = TextReportSection::new("Sanitizer Report".into());
+    let mut extension_data = TextReportSection::new("Extension data".into());
+    let mut modules = TextReportSection::new("Loaded modules".into());
+    let mut decoded_testcase = TextReportSection::new("Decoded testcase".into());
     let mut child_output = TextReportSection::new("".into());
 
-    header.add_line(format!(
-        "Summary: {}\nCommand line: {}\nTestcase: {}\nCrash bucket: {}",
-        einfo.summary, shell_join(&envelope.command_line), shlex::quote(&envelope.testcase), envelope.bucket.strategy_result,
-    ));
+    header.add_line(format!("Summary: {}", einfo.summary));
+    header.add_line(format!("Command line: {}", shell_join(&envelope.command_line)));
+
+    if !envelope.env.is_empty() {
+        header.add_line(format!("Environment: {}", shell_join(&envelope.env)));
+    }
+
+    header.add_line(format!("Testcase: {}", shlex::quote(&envelope.testcase)));
+
+    if let Some(afl_testcase) = &envelope.afl_testcase {
+        header.add_line(format!("AFL testcase: {}", afl_testcase.summary()));
+    }
+
+    if let Some(preprocessed) = &envelope.preprocessed_testcase {
+        header.add_line(format!("Preprocessed testcase: {}", shlex::quote(preprocessed)));
+    }
+
+    if einfo.forked_child == Some(true) {
+        header.add_line("Process: the crash occurred in a forked child of the target (followed with --follow-forks)".into());
+    }
+
+    if let Some(triaged_at) = &envelope.triaged_at {
+        match envelope.triage_duration_ms {
+            Some(ms) => header.add_line(format!("Triaged at: {} (took {} ms)", triaged_at, ms)),
+            None => header.add_line(format!("Triaged at: {}", triaged_at)),
+        }
+    }
+
+    match envelope.bucket.hash_algorithm {
+        Some(hash) => header.add_line(format!("Crash bucket: {} ({} strategy, {} hash)",
+            envelope.bucket.strategy_result, envelope.bucket.strategy, hash)),
+        None => header.add_line(format!("Crash bucket: {}", envelope.bucket.strategy_result)),
+    }
+
+    if let Some(reproducibility) = &envelope.reproducibility {
+        header.add_line(format!("Reproducibility: {}", reproducibility.summary()));
+    }
+
+    if let Some(coverage) = &envelope.coverage {
+        header.add_line(format!("Coverage: {}", coverage.summary()));
+    }
+
+    if let Some(aslr) = envelope.aslr {
+        header.add_line(format!("ASLR: {}", if aslr { "enabled (addresses differ between runs)" } else { "disabled" }));
+    }
+
+    for warning in &envelope.warnings {
+        header.add_line(format!("Warning: {}", warning));
+    }
+
+    if let Some(quality) = &einfo.backtrace_quality {
+        header.add_line(format!("Backtrace: {}", quality.summary()));
+    }
+
+    for warning in einfo.triage_warnings.iter().take(TRIAGE_WARNINGS) {
+        header.add_line(format!("Triage warning: {}", warning));
+    }
+
+    if einfo.triage_warnings.len() > TRIAGE_WARNINGS {
+        header.add_line(format!("Triage warning: {} more in the JSON report", einfo.triage_warnings.len() - TRIAGE_WARNINGS));
+    }
 
     build_register_info(einfo, &mut register_info);
     build_instruction_context(einfo, &mut crash_context);
-    build_backtrace(einfo, &mut backtrace);
+    match &einfo.go_fatal_error {
+        Some(error) if !error.goroutines.is_empty() => build_goroutine_backtrace(error, &mut backtrace),
+        _ => build_backtrace(einfo, &mut backtrace),
+    }
+
+    if let Some(groups) = &einfo.other_threads {
+        build_other_threads(groups, &mut other_threads);
+
+        // Only worth telling which thread crashed if there are others
+        if let Some(tid) = einfo.faulting_thread.tid {
+            let name = einfo.faulting_thread.name.as_ref().map(|n| format!(" \"{}\"", n)).unwrap_or_default();
+            backtrace.section_name = format!("Crashing thread backtrace (thread {}{})", tid, name);
+        }
+    }
 
     if let Some(reports) = &einfo.sanitizer_reports {
         // TODO: multiple reports
@@ -134,6 +230,23 @@ fn build_text_report(einfo: &EnrichedTriageInfo, envelope: &ReportEnvelope) -> T
         }
     }
 
+    if let Some(extra) = &einfo.extra {
+        for (name, value) in extra {
+            match value {
+                serde_json::Value::String(s) => extension_data.add_line(format!("{}: {}", name, s)),
+                v => extension_data.add_line(format!("{}: {}", name, v)),
+            }
+        }
+    }
+
+    if let Some(loaded) = &einfo.modules {
+        build_modules(loaded, &mut modules);
+    }
+
+    if let Some(decoded) = &envelope.decoded_testcase {
+        decoded_testcase.add_line(decoded.to_string());
+    }
+
     if let Some(toutput) = &einfo.target_output {
         build_target_output(toutput, &envelope.report_options, &mut child_output);
     }
@@ -143,13 +256,35 @@ fn build_text_report(einfo: &EnrichedTriageInfo, envelope: &ReportEnvelope) -> T
         register_info,
         crash_context,
         backtrace,
+        other_threads,
         sanitizer_report,
+        extension_data,
+        modules,
+        decoded_testcase,
         child_output,
     }
 }
 
 fn build_backtrace(einfo: &EnrichedTriageInfo, backtrace: &mut TextReportSection) {
+    let trusted_frames = einfo.backtrace_quality.as_ref().map(|q| q.trusted_frames);
+    let repeated_frames = einfo.faulting_thread.recursion.as_ref().map(|r| r.repeated_frames());
+
     for (i, fr) in einfo.faulting_thread.frames.iter().enumerate() {
+        if trusted_frames == Some(i) {
+            backtrace.add_line("--- The frames below may be bogus and are not used for bucketing ---".into());
+        }
+
+        // Print recursion once
+        if let (Some(cycle), Some(repeated)) = (&einfo.faulting_thread.recursion, &repeated_frames) {
+            if i == repeated.start {
+                backtrace.add_line(format!("--- Frames #{}-#{} repeat {} more times (recursion) ---",
+                    cycle.first_frame, repeated.start - 1, cycle.repeats - 1));
+            }
+
+            if repeated.contains(&i) {
+                continue;
+            }
+        }
 
         let mut ctx = vec![];
         let frame_header_p1 = format!("#{:<2} {}", i, fr.address.f);
@@ -185,6 +320,64 @@ fn build_backtrace(einfo: &EnrichedTriageInfo, backtrace: &mut TextReportSection
     }
 }
 
+/// GDB's backtrace of a Go program is of the runtime, so the failing goroutine is shown instead
+fn build_goroutine_backtrace(error: &GoFatalError, backtrace: &mut TextReportSection) {
+    let goroutine = &error.goroutines[0];
+    backtrace.section_name = format!("Failing goroutine {} [{}]", goroutine.id, goroutine.state);
+
+    for (i, fr) in goroutine.frames.iter().chain(goroutine.created_by.iter()).enumerate() {
+        let created = if i == goroutine.frames.len() { "created by " } else { "" };
+        backtrace.add_line(format!("#{:<2} {}{}", i, created, fr.function));
+        backtrace.add_line(format!("    at {}:{}", fr.file, fr.line));
+    }
+}
+
+fn build_other_threads(groups: &[EnrichedThreadGroup], other_threads: &mut TextReportSection) {
+    let count: usize = groups.iter().map(|g| g.tids.len()).sum();
+    other_threads.section_name = format!("Other threads ({})", count);
+
+    for group in groups {
+        let name = group.name.as_ref().map(|n| format!(" \"{}\"", n)).unwrap_or_default();
+        let mut tids: Vec<String> = group.tids.iter().take(OTHER_THREAD_TIDS).map(|t| t.to_string()).collect();
+
+        if group.tids.len() > OTHER_THREAD_TIDS {
+            tids.push("...".into());
+        }
+
+        let threads = match group.tids.len() {
+            1 => format!("Thread {}{}", tids[0], name),
+            n => format!("Threads {}{} ({} with the same backtrace)", tids.join(", "), name, n),
+        };
+
+        if group.frames.is_empty() {
+            other_threads.add_line(format!("{}: no backtrace", threads));
+            continue;
+        }
+
+        let mut frames: Vec<&str> = group.frames.iter().take(OTHER_THREAD_FRAMES).map(|f| f.as_str()).collect();
+
+        if group.frames.len() > OTHER_THREAD_FRAMES {
+            frames.push("...");
+        }
+
+        other_threads.add_line(format!("{}: in {}", threads, frames.join(" <- ")));
+    }
+}
+
+fn build_modules(loaded: &[EnrichedModule], modules: &mut TextReportSection) {
+    for module in loaded {
+        let range = match (&module.load_address, &module.end_address) {
+            (Some(start), Some(end)) => format!("{}-{} ", start.f, end.f),
+            (Some(start), None) => format!("{} ", start.f),
+            _ => "".to_string(),
+        };
+        let build_id = module.build_id.as_ref().map(|id| format!(" (build ID {})", id)).unwrap_or_default();
+        let marker = if module.in_backtrace { " [in backtrace]" } else { "" };
+
+        modules.add_line(format!("{}{}{}{}", range, module.path, build_id, marker));
+    }
+}
+
 fn build_source_context(symbol: &GdbSymbol, source_ctx: &Vec<EnrichedSourceContext>) -> Vec<String> {
     /* NNN: <FUNCTION_PROTOTYPE> {
      * |||: <REF 1>
@@ -263,24 +456,28 @@ fn build_source_context(symbol: &GdbSymbol, source_ctx: &Vec<EnrichedSourceConte
 }
 
 fn build_target_output(toutput: &EnrichedTargetOutput, opt: &ReportOptions, child_output: &mut TextReportSection) {
-    let section_title = |name: &str, output: &str| -> String {
+    let section_title = |name: &str, output: &str, raw: &Option<Vec<u8>>| -> String {
+        // The exact bytes are only in JSON reports
+        let binary = if raw.is_some() { ", not UTF-8" } else { "" };
+
         if output.is_empty() {
             format!("Child {} (no output)", name)
         } else if opt.child_output_lines == 0 {
-            format!("Child {} (everything)", name)
+            format!("Child {} (everything{})", name, binary)
         } else {
-            format!("Child {} (last {} lines)", name, opt.child_output_lines)
+            format!("Child {} (last {} lines{})", name, opt.child_output_lines, binary)
         }
     };
 
-    let mut stdout = TextReportSection::new(section_title("STDOUT", &toutput.stdout));
-    let mut stderr = TextReportSection::new(section_title("STDERR", &toutput.stderr));
+    let mut stdout = TextReportSection::new(section_title("STDOUT", &toutput.stdout, &toutput.stdout_raw));
+    let mut stderr = TextReportSection::new(section_title("STDERR", &toutput.stderr, &toutput.stderr_raw));
 
+    // Escape sequences in the output would otherwise reach the terminal of whoever reads the report
     if !toutput.stdout.is_empty() {
-        stdout.add_line(toutput.stdout.to_string());
+        stdout.add_line(util::escape_control_chars(&toutput.stdout));
     }
     if !toutput.stderr.is_empty() {
-        stderr.add_line(toutput.stderr.to_string());
+        stderr.add_line(util::escape_control_chars(&toutput.stderr));
     }
 
     child_output.add_section(stdout);
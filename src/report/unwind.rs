@@ -0,0 +1,138 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Detecting backtraces that cannot be trusted.
+//!
+//! When the stack is corrupted, for instance by a stack buffer overflow that overwrote return
+//! addresses, GDB unwinds through garbage. Those frames differ between crashes with the same
+//! cause, and hashing them would split one bug into many buckets. A backtrace is unreliable from
+//! the first frame where:
+//!
+//! * A caller's PC is unmapped or not executable
+//! * GDB stopped unwinding because the previous frame was identical to this one (unwinding would
+//!   recurse into the same frame), inner to it, or could not be read
+//!
+//! A backtrace with more than [MAX_SANE_FRAMES] frames is cut off by the triage script and is
+//! likely a runaway unwind or deep recursion, so only its first [RUNAWAY_TRUSTED_FRAMES] frames are
+//! trusted. Bucketing only hashes the trusted frames.
+use crate::debugger::gdb::{GdbFrameInfo, GdbMemoryMapping};
+use serde::{Deserialize, Serialize};
+
+/// Backtraces are cut off at this many frames by the triage script
+pub const MAX_SANE_FRAMES: usize = 100;
+
+/// How many frames of a cut off backtrace are trusted
+pub const RUNAWAY_TRUSTED_FRAMES: usize = 16;
+
+/// The quality of a backtrace that looks unreliable
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BacktraceQuality {
+    /// How many frames, starting from the innermost, can be trusted
+    pub trusted_frames: usize,
+    /// Why the frames after those cannot be
+    pub problems: Vec<String>,
+}
+
+impl BacktraceQuality {
+    pub fn summary(&self) -> String {
+        format!("unreliable after frame #{} ({})", self.trusted_frames.saturating_sub(1), self.problems.join("; "))
+    }
+}
+
+/// Why a caller's PC cannot be real, if the memory map says so
+fn bad_caller_pc(memory_map: &[GdbMemoryMapping], address: u64) -> Option<&'static str> {
+    match memory_map.iter().find(|m| address >= m.start && address < m.end) {
+        None => Some("unmapped"),
+        Some(m) => match &m.permissions {
+            Some(perms) if !perms.contains('x') => Some("not executable"),
+            _ => None,
+        },
+    }
+}
+
+/// Assess a backtrace of `frames`, where GDB stopped unwinding due to `unwind_error` if any.
+/// Returns `None` if nothing about it looks unreliable
+pub fn assess_backtrace(
+    frames: &[GdbFrameInfo],
+    unwind_error: Option<&str>,
+    memory_map: &Option<Vec<GdbMemoryMapping>>,
+) -> Option<BacktraceQuality> {
+    let mut trusted_frames = frames.len();
+    let mut problems = vec![];
+
+    // The PC of the first frame is where the target stopped, which is often a bad address on
+    // purpose. Only callers are checked
+    if let Some(memory_map) = memory_map.as_ref().filter(|m| !m.is_empty()) {
+        let bad_caller = frames.iter().enumerate().skip(1)
+            .find_map(|(i, fr)| bad_caller_pc(memory_map, fr.address).map(|why| (i, fr.address, why)));
+
+        if let Some((i, address, why)) = bad_caller {
+            trusted_frames = i;
+            problems.push(format!("frame #{} has a PC of 0x{:x}, which is {}", i, address, why));
+        }
+    }
+
+    if let Some(error) = unwind_error {
+        // The last frame is the one GDB could not unwind past correctly
+        trusted_frames = trusted_frames.min(frames.len().saturating_sub(1).max(1));
+        problems.push(format!("GDB stopped unwinding: {}", error));
+    }
+
+    if frames.len() >= MAX_SANE_FRAMES {
+        trusted_frames = trusted_frames.min(RUNAWAY_TRUSTED_FRAMES);
+        problems.push(format!("the backtrace has {} or more frames", MAX_SANE_FRAMES));
+    }
+
+    if problems.is_empty() {
+        None
+    } else {
+        Some(BacktraceQuality { trusted_frames, problems })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(address: u64) -> GdbFrameInfo {
+        GdbFrameInfo {
+            address,
+            relative_address: address,
+            module: "??".into(),
+            module_address: format!("0x{:x}", address),
+            symbol: None,
+        }
+    }
+
+    #[test]
+    fn test_assess_backtrace() {
+        let mapping = |start, end, permissions: &str| GdbMemoryMapping {
+            start,
+            end,
+            offset: 0,
+            permissions: Some(permissions.into()),
+            name: "/tmp/test".into(),
+        };
+        let memory_map = Some(vec![mapping(0x1000, 0x2000, "r-xp"), mapping(0x2000, 0x3000, "rw-p")]);
+
+        let good = vec![frame(0x1100), frame(0x1200), frame(0x1300)];
+        assert_eq!(assess_backtrace(&good, None, &memory_map), None);
+        assert_eq!(assess_backtrace(&good, None, &None), None);
+
+        // A bad PC in the first frame is the crash itself
+        let bad_pc = vec![frame(0x4141414141414141), frame(0x1200)];
+        assert_eq!(assess_backtrace(&bad_pc, None, &memory_map), None);
+
+        let smashed = vec![frame(0x1100), frame(0x1200), frame(0x2100), frame(0x4141414141414141)];
+        let quality = assess_backtrace(&smashed, None, &memory_map).unwrap();
+        assert_eq!(quality.trusted_frames, 2);
+        assert_eq!(quality.summary(), "unreliable after frame #1 (frame #2 has a PC of 0x2100, which is not executable)");
+
+        let quality = assess_backtrace(&good, Some("previous frame identical to this frame (corrupt stack?)"), &None).unwrap();
+        assert_eq!(quality.trusted_frames, 2);
+        assert_eq!(quality.problems, vec!["GDB stopped unwinding: previous frame identical to this frame (corrupt stack?)"]);
+
+        let runaway: Vec<GdbFrameInfo> = (0..MAX_SANE_FRAMES as u64).map(|i| frame(0x1000 + i)).collect();
+        assert_eq!(assess_backtrace(&runaway, None, &memory_map).unwrap().trusted_frames, RUNAWAY_TRUSTED_FRAMES);
+    }
+}
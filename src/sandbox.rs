@@ -0,0 +1,144 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Sandboxed target execution.
+//!
+//! Crash inputs are hostile by nature and a target that misbehaves can do anything the user
+//! running AFLTriage can. A [Sandbox] runs GDB, and with it the target, under bubblewrap (`bwrap`)
+//! with an isolation [SandboxLevel]:
+//!
+//! * `net` - a new network namespace with only a loopback interface
+//! * `full` - `net`, plus a read-only view of the filesystem, private `/tmp` and `/var/tmp`, and a
+//!   new IPC namespace. Files the run needs under `/tmp` (triage scripts, testcases) are bound
+//!   read-only into the private `/tmp`
+//!
+//! An optional seccomp filter, compiled to BPF (e.g. with libseccomp's `seccomp_export_bpf`), is
+//! applied to GDB and the target. The filter must allow `ptrace` for GDB to work.
+use clap::arg_enum;
+use std::path::{Path, PathBuf};
+
+arg_enum! {
+    /// How isolated the target is from the system
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[allow(non_camel_case_types)]
+    pub enum SandboxLevel {
+        none,
+        net,
+        full,
+    }
+}
+
+/// The file descriptor the seccomp filter is passed to `bwrap` on
+const SECCOMP_FD: &str = "9";
+
+/// Directories that are replaced with an empty tmpfs at the `full` level
+const PRIVATE_DIRS: &[&str] = &["/tmp", "/var/tmp"];
+
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+/// Runs commands isolated from the system using bubblewrap
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sandbox {
+    pub level: SandboxLevel,
+    /// A compiled BPF seccomp filter
+    pub seccomp_filter: Option<PathBuf>,
+}
+
+impl Sandbox {
+    /// The `bwrap` arguments for the isolation level. `exposed` are files the sandboxed command
+    /// needs, which are bound read-only if they would be hidden by a private directory
+    fn bwrap_args(&self, exposed: &[&Path]) -> Vec<String> {
+        let mut args = strings(&["--die-with-parent", "--unshare-net"]);
+
+        if self.level != SandboxLevel::full {
+            return [strings(&["--dev-bind", "/", "/"]), args].concat();
+        }
+
+        args = [strings(&["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"]), args].concat();
+        args.push("--unshare-ipc".into());
+
+        for dir in PRIVATE_DIRS {
+            args.extend(strings(&["--tmpfs", dir]));
+        }
+
+        let mut bound: Vec<PathBuf> = vec![];
+
+        for path in exposed {
+            let path = match path.canonicalize() {
+                Ok(p) => p,
+                Err(_) => continue,
+            };
+
+            if PRIVATE_DIRS.iter().any(|d| path.starts_with(d)) && !bound.contains(&path) {
+                args.extend(strings(&["--ro-bind", &path.to_string_lossy(), &path.to_string_lossy()]));
+                bound.push(path);
+            }
+        }
+
+        if let Ok(cwd) = std::env::current_dir() {
+            args.extend(strings(&["--chdir", &cwd.to_string_lossy()]));
+        }
+
+        args
+    }
+
+    /// Wrap `command` and its `args` so they run in the sandbox. Returns the program to execute
+    /// and its arguments
+    pub fn wrap(&self, command: &str, args: &[String], exposed: &[&Path]) -> (String, Vec<String>) {
+        if self.level == SandboxLevel::none {
+            return (command.to_string(), args.to_vec());
+        }
+
+        let mut bwrap_args = self.bwrap_args(exposed);
+        bwrap_args.push("--".into());
+        bwrap_args.push(command.to_string());
+        bwrap_args.extend(args.iter().cloned());
+
+        match &self.seccomp_filter {
+            // bwrap reads the filter from a file descriptor, which the shell opens for it
+            Some(filter) => {
+                let script = format!("f=$1; shift; exec bwrap --seccomp {} \"$@\" {}<\"$f\"", SECCOMP_FD, SECCOMP_FD);
+                let mut sh_args = strings(&["-c", &script, "sh", &filter.to_string_lossy()]);
+                sh_args.extend(bwrap_args);
+                ("sh".into(), sh_args)
+            }
+            None => ("bwrap".into(), bwrap_args),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sandbox_wrap() {
+        let args = strings(&["--batch", "--args", "./target"]);
+
+        let none = Sandbox { level: SandboxLevel::none, seccomp_filter: None };
+        assert_eq!(none.wrap("gdb", &args, &[]), ("gdb".to_string(), args.clone()));
+
+        let net = Sandbox { level: SandboxLevel::net, seccomp_filter: None };
+        assert_eq!(net.wrap("gdb", &args, &[]), ("bwrap".to_string(),
+            strings(&["--dev-bind", "/", "/", "--die-with-parent", "--unshare-net",
+                "--", "gdb", "--batch", "--args", "./target"])));
+
+        let script = tempfile::Builder::new().tempfile_in("/tmp").unwrap();
+        let script_path = script.path().to_string_lossy().to_string();
+        let full = Sandbox { level: SandboxLevel::full, seccomp_filter: None };
+        let (program, wrapped) = full.wrap("gdb", &args, &[script.path(), script.path(), Path::new("/missing")]);
+
+        assert_eq!(program, "bwrap");
+        assert_eq!(wrapped[..12], strings(&["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc",
+            "--die-with-parent", "--unshare-net", "--unshare-ipc", "--tmpfs", "/tmp"])[..]);
+        assert_eq!(wrapped.iter().filter(|a| **a == script_path).count(), 2);
+        assert!(wrapped.ends_with(&strings(&["--", "gdb", "--batch", "--args", "./target"])));
+
+        let filtered = Sandbox { level: SandboxLevel::net, seccomp_filter: Some(PathBuf::from("filter.bpf")) };
+        let (program, wrapped) = filtered.wrap("gdb", &args, &[]);
+        assert_eq!(program, "sh");
+        assert_eq!(wrapped[2..5], strings(&["sh", "filter.bpf", "--dev-bind"])[..]);
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright (c) 2021, Qualcomm Innovation Center, Inc. All rights reserved.
+//
+// SPDX-License-Identifier: BSD-3-Clause
+//! Source path remapping.
+//!
+//! Targets built in a container or on a CI machine have debug info pointing at source paths that
+//! do not exist locally, like `/src/project/parse.c`. `--substitute-path OLD=NEW` maps them to a
+//! local checkout: GDB is given the rules with `set substitute-path`, so the source context it
+//! captures is read from `NEW`, and reports that read source themselves (see
+//! [crate::report::fixcontext]) apply the same rules. The paths recorded in reports are not changed.
+//!
+//! Like GDB, a rule only replaces whole path components, so `/src` maps `/src/a.c` but not
+//! `/srcs/a.c`, and the first rule that matches is used.
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// The source path substitution rules, as `(OLD, NEW)` prefixes
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourcePathMap {
+    pub rules: Vec<(String, String)>,
+}
+
+/// Quote an argument of a GDB command that GDB splits like a shell would
+fn gdb_quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl SourcePathMap {
+    /// Parse `OLD=NEW` substitution rules
+    pub fn parse(specs: &[&str]) -> Result<SourcePathMap, String> {
+        let rules = specs.iter()
+            .map(|spec| match spec.split_once('=') {
+                Some((old, new)) if !old.is_empty() && !new.is_empty() => {
+                    // A trailing slash would keep the rule from matching the directory itself
+                    let trim = |p: &str| if p.len() > 1 { p.trim_end_matches('/').to_string() } else { p.to_string() };
+                    Ok((trim(old), trim(new)))
+                }
+                _ => Err(format!("Invalid source path substitution '{}'. Expected OLD=NEW", spec)),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(SourcePathMap { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Where to read the source file at `path` from
+    pub fn remap(&self, path: &str) -> PathBuf {
+        for (old, new) in &self.rules {
+            match path.strip_prefix(old.as_str()) {
+                // Only a rule for the root directory ends with a slash
+                Some(rest) if old.ends_with('/') => return PathBuf::from(format!("{}/{}", new.trim_end_matches('/'), rest)),
+                Some(rest) if rest.is_empty() || rest.starts_with('/') => return PathBuf::from(format!("{}{}", new, rest)),
+                _ => (),
+            }
+        }
+
+        PathBuf::from(path)
+    }
+
+    /// The GDB commands that apply the rules
+    pub fn gdb_commands(&self) -> Vec<String> {
+        self.rules.iter()
+            .map(|(old, new)| format!("set substitute-path {} {}", gdb_quote(old), gdb_quote(new)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_source_path_map() {
+        let map = SourcePathMap::parse(&["/src/project/=/home/me/project", "/src=/mnt/src", "/=/sysroot"]).unwrap();
+        assert_eq!(map.rules[0], ("/src/project".into(), "/home/me/project".into()));
+
+        assert_eq!(map.remap("/src/project/parse.c"), PathBuf::from("/home/me/project/parse.c"));
+        assert_eq!(map.remap("/src/other/main.c"), PathBuf::from("/mnt/src/other/main.c"));
+        assert_eq!(map.remap("/srcs/main.c"), PathBuf::from("/sysroot/srcs/main.c"));
+        assert_eq!(SourcePathMap::default().remap("/src/a.c"), PathBuf::from("/src/a.c"));
+
+        assert_eq!(SourcePathMap::parse(&["/build dir=/home/me/it's"]).unwrap().gdb_commands(),
+            vec!["set substitute-path \"/build dir\" \"/home/me/it's\""]);
+        assert!(SourcePathMap::parse(&["/src"]).is_err());
+        assert!(SourcePathMap::parse(&["=/src"]).is_err());
+    }
+}
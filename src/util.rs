@@ -6,6 +6,7 @@ use regex::Regex;
 use std::io::{self, Read, BufRead};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 lazy_static! {
     static ref ALLOWED_CHARS: Regex = Regex::new(r#"[^A-Za-z0-9_-]"#).unwrap();
@@ -80,6 +81,44 @@ pub fn get_peak_rss() -> usize {
     }
 }
 
+/// Format a wall-clock time as an ISO-8601 timestamp with second precision. UTC times end in `Z`
+/// while local times carry their UTC offset (e.g. `+02:00`)
+pub fn format_iso8601(time: SystemTime, local_time: bool) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as libc::time_t,
+        Err(_) => 0,
+    };
+
+    let tm = unsafe {
+        let mut tm: libc::tm = core::mem::MaybeUninit::zeroed().assume_init();
+
+        let ok = if local_time {
+            !libc::localtime_r(&secs, &mut tm).is_null()
+        } else {
+            !libc::gmtime_r(&secs, &mut tm).is_null()
+        };
+
+        if !ok {
+            tm = core::mem::MaybeUninit::zeroed().assume_init();
+            tm.tm_year = 70;
+            tm.tm_mday = 1;
+        }
+
+        tm
+    };
+
+    let offset = if local_time && tm.tm_gmtoff != 0 {
+        let off = tm.tm_gmtoff.abs();
+        format!("{}{:02}:{:02}", if tm.tm_gmtoff < 0 { '-' } else { '+' }, off / 3600, (off % 3600) / 60)
+    } else {
+        "Z".to_string()
+    };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+        tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday,
+        tm.tm_hour, tm.tm_min, tm.tm_sec, offset)
+}
+
 pub fn list_sorted_files_at(path: &Path) -> io::Result<Vec<PathBuf>> {
     let mut files = fs::read_dir(path)?
         .map(|res| res.map(|e| e.path()))
@@ -89,6 +128,57 @@ pub fn list_sorted_files_at(path: &Path) -> io::Result<Vec<PathBuf>> {
     Ok(files)
 }
 
+/// List all files under `path` and its subdirectories, sorted. Symlinks to directories are not
+/// followed to avoid loops
+pub fn list_sorted_files_recursive(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![path.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let entry_path = entry.path();
+
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry_path);
+            } else if entry_path.is_file() {
+                files.push(entry_path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Translate a shell-like glob into an anchored [Regex]. `*` and `?` match within a path
+/// component and `**` matches across components (e.g. `**/*.bin`)
+pub fn glob_to_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re += "(?:.*/)?";
+                } else {
+                    re += ".*";
+                }
+            }
+            '*' => re += "[^/]*",
+            '?' => re += "[^/]",
+            c => re += &regex::escape(&c.to_string()),
+        }
+    }
+
+    re += "$";
+    Regex::new(&re)
+}
+
 pub fn expand_filepath_templates(args: &[&str], value: &str) -> Vec<String> {
     let mut expanded_args: Vec<String> = Vec::new();
 
@@ -117,13 +207,171 @@ where
     )
 }
 
+/// The shell GDB starts the target with. GDB uses `$SHELL`, so it is given this one instead of the
+/// user's, which may not be POSIX
+pub const TARGET_SHELL: &str = "/bin/sh";
+
+/// Quote `arg` as a single word for [TARGET_SHELL]. Anything but a plain word is put in single
+/// quotes, which it takes literally, with each `'` in it as `'\''`
+pub fn sh_quote(arg: &str) -> std::borrow::Cow<'_, str> {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "@+=:,./_-".contains(c);
+
+    // zsh expands a leading = to the path of a command
+    if !arg.is_empty() && arg.chars().all(plain) && !arg.starts_with('=') {
+        arg.into()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''")).into()
+    }
+}
+
+/// Join `args` into a command line with [sh_quote], so the shell passes them to the target
+/// verbatim
+pub fn sh_join<'a, I, S>(args: I) -> String
+where
+    I: IntoIterator<Item = &'a S>,
+    S: AsRef<str> + 'a, {
+
+    args.into_iter()
+        .map(|x| sh_quote(x.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Decode the output of a process. Bytes that are not UTF-8 are replaced, so the exact output is
+/// returned as well if there were any
+pub fn decode_output(bytes: Vec<u8>) -> (String, Option<Vec<u8>>) {
+    match String::from_utf8(bytes) {
+        Ok(text) => (text, None),
+        Err(e) => {
+            let bytes = e.into_bytes();
+            (String::from_utf8_lossy(&bytes).to_string(), Some(bytes))
+        }
+    }
+}
+
+/// The last `limit` lines of `bytes`, the same as joining [tail_string] with newlines
+pub fn tail_bytes(bytes: &[u8], limit: usize) -> &[u8] {
+    match bytes.iter().enumerate().rev().filter(|(_, b)| **b == b'\n').nth(limit.saturating_sub(1)) {
+        Some((pos, _)) if limit > 0 => &bytes[pos + 1..],
+        _ => bytes,
+    }
+}
+
+/// Escape control characters other than newlines and tabs, so that output that is not text cannot
+/// mangle a terminal or report
+pub fn escape_control_chars(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\n' | '\t' => c.to_string(),
+            c if c.is_control() => c.escape_default().to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Serialize optional bytes as a lowercase hex string, with `#[serde(with = "crate::util::hex_bytes")]`
+pub mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_str(&bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        let hex = match Option::<String>::deserialize(deserializer)? {
+            Some(hex) => hex,
+            None => return Ok(None),
+        };
+
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(D::Error::custom("invalid hex string"));
+        }
+
+        (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(D::Error::custom))
+            .collect::<Result<Vec<u8>, D::Error>>()
+            .map(Some)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_sh_join() {
+        let args = ["plain", "", "two words", "it's", "\"quoted\"", "$HOME", "`id`", "a;b|c&d", "*.c", "{a,b}",
+            "~", "=ls", "back\\slash", "a\\\\b", "trailing\\", "\\'", "tab\there", "new\nline", "ünïcode", "!!", "%self", "'"];
+
+        assert_eq!(sh_quote("id:000000,sig:11"), "id:000000,sig:11");
+        assert_eq!(sh_quote("it's"), "'it'\\''s'");
+
+        // The shell GDB runs the target with must give back every argument as it was
+        let output = std::process::Command::new(TARGET_SHELL)
+            .arg("-c")
+            .arg(format!("printf '%s\\0' {}", sh_join(&args)))
+            .output()
+            .unwrap();
+        let parsed: Vec<String> = String::from_utf8(output.stdout).unwrap()
+            .split_terminator('\0')
+            .map(str::to_string)
+            .collect();
+
+        assert_eq!(parsed, args);
+    }
+
     #[test]
     fn test_elide_size() {
         assert_eq!(elide_size("привет", 1), "п...");
         assert_eq!(elide_size("привет", 6), "привет");
     }
+
+    #[test]
+    fn test_format_iso8601() {
+        let t = UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        assert_eq!(format_iso8601(t, false), "2001-09-09T01:46:40Z");
+        assert_eq!(format_iso8601(UNIX_EPOCH, false), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        let re = glob_to_regex("**/*.bin").unwrap();
+        assert!(re.is_match("a.bin"));
+        assert!(re.is_match("x/y/a.bin"));
+        assert!(!re.is_match("a.binx"));
+
+        let re = glob_to_regex("crash-?.*").unwrap();
+        assert!(re.is_match("crash-1.txt"));
+        assert!(!re.is_match("dir/crash-1.txt"));
+        assert!(!re.is_match("crash-12.txt"));
+    }
+
+    #[test]
+    fn test_output_bytes() {
+        assert_eq!(decode_output(b"text\n".to_vec()), ("text\n".to_string(), None));
+        assert_eq!(decode_output(b"\xffA".to_vec()), ("\u{FFFD}A".to_string(), Some(b"\xffA".to_vec())));
+
+        let output = b"one\ntwo\nthree\n";
+        assert_eq!(tail_bytes(output, 2), b"three\n");
+        assert_eq!(tail_bytes(output, 2), tail_string("one\ntwo\nthree\n", 2).join("\n").as_bytes());
+        assert_eq!(tail_bytes(output, 10), output);
+        assert_eq!(tail_bytes(output, 0), output);
+
+        assert_eq!(escape_control_chars("a\x1b[31mb\r\n\tc"), "a\\u{1b}[31mb\\r\n\tc");
+
+        #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Output {
+            #[serde(default, with = "hex_bytes", skip_serializing_if = "Option::is_none")]
+            bytes: Option<Vec<u8>>,
+        }
+
+        let json = serde_json::to_string(&Output { bytes: Some(vec![0x00, 0xff, 0x41]) }).unwrap();
+        assert_eq!(json, r#"{"bytes":"00ff41"}"#);
+        assert_eq!(serde_json::from_str::<Output>(&json).unwrap().bytes, Some(vec![0x00, 0xff, 0x41]));
+        assert_eq!(serde_json::from_str::<Output>("{}").unwrap().bytes, None);
+        assert!(serde_json::from_str::<Output>(r#"{"bytes":"0g"}"#).is_err());
+    }
 }